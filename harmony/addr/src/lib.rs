@@ -0,0 +1,340 @@
+//! x86-64 physical and virtual addresses shared by the kernel and userspace.
+//!
+//! Constructors check the invariants of each type (physical addresses fit in
+//! 52 bits, virtual addresses are canonical, frames and pages are aligned) so
+//! values can be passed across the syscall boundary as raw integers and turned
+//! back into the same type on the other side.
+#![cfg_attr(not(test), no_std)]
+
+use core::fmt;
+
+/// Size of a page and of a frame.
+pub const PAGE_SIZE: usize = 4096;
+
+/// Bits in the index of a page table entry.
+const TABLE_INDEX_BITS: u32 = 9;
+/// Bits in the offset within a page.
+const PAGE_OFFSET_BITS: u32 = PAGE_SIZE.trailing_zeros();
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BadPhysAddr;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BadVirtAddr;
+
+/// The address isn't aligned to what the type requires.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Unaligned;
+
+/// A physical address of up to 52 bits.
+#[repr(transparent)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PhysAddr(u64);
+
+impl fmt::Debug for PhysAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PhysAddr({:#X})", self.0)
+    }
+}
+
+impl PhysAddr {
+    pub const fn new(addr: u64) -> Self {
+        match Self::try_new(addr) {
+            Ok(addr) => addr,
+            Err(_) => panic!("Invalid Physical Address: Must be up to 52 bits"),
+        }
+    }
+
+    pub const fn try_new(addr: u64) -> Result<Self, BadPhysAddr> {
+        if Self::new_truncate(addr).0 == addr {
+            Ok(Self(addr))
+        } else {
+            Err(BadPhysAddr)
+        }
+    }
+
+    pub const fn new_truncate(addr: u64) -> Self {
+        Self(addr % (1 << 52))
+    }
+
+    pub const fn zero() -> Self {
+        Self(0)
+    }
+
+    pub const fn as_u64(&self) -> u64 {
+        self.0
+    }
+
+    /// Whether the address is a multiple of `align`, which must be a power of two.
+    pub const fn is_aligned(&self, align: u64) -> bool {
+        self.0 & (align - 1) == 0
+    }
+
+    /// Rounds the address down to a multiple of `align`, which must be a power of two.
+    pub const fn align_down(self, align: u64) -> Self {
+        Self(self.0 & !(align - 1))
+    }
+
+    /// Rounds the address up to a multiple of `align`, which must be a power of two.
+    pub const fn checked_align_up(self, align: u64) -> Option<Self> {
+        match self.0.checked_add(align - 1) {
+            Some(addr) => match Self::try_new(addr & !(align - 1)) {
+                Ok(addr) => Some(addr),
+                Err(_) => None,
+            },
+            None => None,
+        }
+    }
+
+    pub const fn checked_add(self, offset: u64) -> Option<Self> {
+        match self.0.checked_add(offset) {
+            Some(addr) => match Self::try_new(addr) {
+                Ok(addr) => Some(addr),
+                Err(_) => None,
+            },
+            None => None,
+        }
+    }
+}
+
+impl From<PhysAddr> for u64 {
+    fn from(addr: PhysAddr) -> Self {
+        addr.0
+    }
+}
+
+impl TryFrom<u64> for PhysAddr {
+    type Error = BadPhysAddr;
+
+    fn try_from(addr: u64) -> Result<Self, Self::Error> {
+        Self::try_new(addr)
+    }
+}
+
+/// A canonical virtual address, i.e. 48 bits sign-extended to 64.
+#[repr(transparent)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VirtAddr(usize);
+
+impl fmt::Debug for VirtAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "VirtAddr({:#X?})", self.0)
+    }
+}
+
+impl VirtAddr {
+    pub const fn new(addr: usize) -> Self {
+        match Self::try_new(addr) {
+            Ok(addr) => addr,
+            Err(_) => panic!("Invalid address: Must be 48-bit sign-extended"),
+        }
+    }
+
+    pub fn from_ptr<T>(ptr: *const T) -> Self {
+        Self::new(ptr as usize)
+    }
+
+    pub const fn try_new(addr: usize) -> Result<Self, BadVirtAddr> {
+        if Self::new_truncate(addr).0 == addr {
+            Ok(Self::new_truncate(addr))
+        } else {
+            Err(BadVirtAddr)
+        }
+    }
+
+    pub const fn new_truncate(addr: usize) -> Self {
+        Self(((addr << 16) as i64 >> 16) as usize)
+    }
+
+    pub const fn zero() -> Self {
+        Self(0)
+    }
+
+    pub const fn as_ptr<T>(&self) -> *const T {
+        self.0 as *const T
+    }
+
+    pub const fn as_mut_ptr<T>(&self) -> *mut T {
+        self.0 as *mut T
+    }
+
+    pub const fn as_usize(&self) -> usize {
+        self.0
+    }
+
+    pub const fn as_u64(&self) -> u64 {
+        self.0 as u64
+    }
+
+    /// Whether the address is a multiple of `align`, which must be a power of two.
+    pub const fn is_aligned(&self, align: usize) -> bool {
+        self.0 & (align - 1) == 0
+    }
+
+    /// Rounds the address down to a multiple of `align`, which must be a power of two.
+    pub const fn align_down(self, align: usize) -> Self {
+        Self(self.0 & !(align - 1))
+    }
+
+    pub const fn checked_add(self, offset: usize) -> Option<Self> {
+        match self.0.checked_add(offset) {
+            Some(addr) => match Self::try_new(addr) {
+                Ok(addr) => Some(addr),
+                Err(_) => None,
+            },
+            None => None,
+        }
+    }
+
+    /// Returns the 9-bit index into the page table at `level` (1 through 4).
+    pub const fn table_index(self, level: u8) -> u16 {
+        let shift = PAGE_OFFSET_BITS + (level as u32 - 1) * TABLE_INDEX_BITS;
+        ((self.0 >> shift) & ((1 << TABLE_INDEX_BITS) - 1)) as u16
+    }
+}
+
+impl From<VirtAddr> for u64 {
+    fn from(addr: VirtAddr) -> Self {
+        addr.as_u64()
+    }
+}
+
+impl TryFrom<u64> for VirtAddr {
+    type Error = BadVirtAddr;
+
+    fn try_from(addr: u64) -> Result<Self, Self::Error> {
+        Self::try_new(usize::try_from(addr).map_err(|_| BadVirtAddr)?)
+    }
+}
+
+/// A frame of physical memory.
+#[repr(transparent)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Frame {
+    base: PhysAddr,
+}
+
+impl Frame {
+    pub fn from_start_address(base: PhysAddr) -> Self {
+        Self::try_from_start_address(base).unwrap()
+    }
+
+    pub const fn try_from_start_address(base: PhysAddr) -> Result<Self, Unaligned> {
+        if !base.is_aligned(PAGE_SIZE as u64) {
+            return Err(Unaligned);
+        }
+        Ok(Self { base })
+    }
+
+    /// The frame that `addr` falls in.
+    pub const fn containing_address(addr: PhysAddr) -> Self {
+        Self {
+            base: addr.align_down(PAGE_SIZE as u64),
+        }
+    }
+
+    pub const fn base(&self) -> PhysAddr {
+        self.base
+    }
+
+    /// Same as [`Frame::base`].
+    pub const fn addr(&self) -> PhysAddr {
+        self.base
+    }
+}
+
+/// A page of virtual memory.
+#[repr(transparent)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Page {
+    start_address: VirtAddr,
+}
+
+impl Page {
+    pub fn from_start_address(addr: VirtAddr) -> Self {
+        Self::try_from_start_address(addr).unwrap()
+    }
+
+    pub const fn try_from_start_address(addr: VirtAddr) -> Result<Self, Unaligned> {
+        if !addr.is_aligned(PAGE_SIZE) {
+            return Err(Unaligned);
+        }
+        Ok(Self {
+            start_address: addr,
+        })
+    }
+
+    /// The page that `addr` falls in.
+    pub const fn containing_address(addr: VirtAddr) -> Self {
+        Self {
+            start_address: addr.align_down(PAGE_SIZE),
+        }
+    }
+
+    pub const fn base(&self) -> VirtAddr {
+        self.start_address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_physical_addresses() {
+        assert_eq!(PhysAddr::try_new(1 << 52), Err(BadPhysAddr));
+        assert_eq!(PhysAddr::new_truncate((1 << 52) + 5), PhysAddr::new(5));
+        assert_eq!(u64::from(PhysAddr::new(0x1234)), 0x1234);
+        assert_eq!(
+            PhysAddr::new(0x1001).checked_align_up(0x1000),
+            Some(PhysAddr::new(0x2000))
+        );
+        assert_eq!(PhysAddr::new((1 << 52) - 1).checked_align_up(0x1000), None);
+        assert_eq!(PhysAddr::new((1 << 52) - 1).checked_add(1), None);
+    }
+
+    #[test]
+    fn checks_virtual_addresses() {
+        assert_eq!(VirtAddr::try_new(0x0000_8000_0000_0000), Err(BadVirtAddr));
+        assert!(VirtAddr::try_new(0xFFFF_8000_0000_0000).is_ok());
+        assert_eq!(
+            VirtAddr::new_truncate(0x0000_8000_0000_0000),
+            VirtAddr::new(0xFFFF_8000_0000_0000)
+        );
+        assert_eq!(
+            VirtAddr::try_from(0xFFFF_FFFF_8000_0000u64).map(u64::from),
+            Ok(0xFFFF_FFFF_8000_0000)
+        );
+        assert_eq!(VirtAddr::new(0x0000_7FFF_FFFF_FFFF).checked_add(1), None);
+    }
+
+    #[test]
+    fn indexes_page_tables() {
+        let addr = VirtAddr::new((3 << 39) | (5 << 30) | (7 << 21) | (9 << 12) | 0xABC);
+        assert_eq!(
+            [1, 2, 3, 4].map(|level| addr.table_index(level)),
+            [9, 7, 5, 3]
+        );
+        assert_eq!(VirtAddr::new(0xFFFF_FF80_0000_0000).table_index(4), 511);
+    }
+
+    #[test]
+    fn aligns_frames_and_pages() {
+        assert_eq!(
+            Frame::try_from_start_address(PhysAddr::new(0x1001)),
+            Err(Unaligned)
+        );
+        assert_eq!(
+            Frame::containing_address(PhysAddr::new(0x1FFF)).base(),
+            PhysAddr::new(0x1000)
+        );
+        assert_eq!(
+            Page::try_from_start_address(VirtAddr::new(0x10)),
+            Err(Unaligned)
+        );
+        assert_eq!(
+            Page::containing_address(VirtAddr::new(0x2345)).base(),
+            VirtAddr::new(0x2000)
+        );
+    }
+}
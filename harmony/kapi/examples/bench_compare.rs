@@ -0,0 +1,71 @@
+//! Compares a run of the kernel micro-benchmarks against a baseline.
+//!
+//! ```text
+//! cargo run -p kapi --example bench_compare -- <baseline> <run> [threshold-percent]
+//! ```
+//!
+//! Both files may hold anything else the test kernel printed. Exits with an
+//! error if any benchmark regressed by more than the threshold, 10% by
+//! default.
+
+use std::process::ExitCode;
+
+use kapi::bench::Measurement;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (baseline, run, threshold) = match args.as_slice() {
+        [baseline, run] => (baseline, run, Ok(10)),
+        [baseline, run, threshold] => (baseline, run, threshold.parse::<u64>()),
+        _ => {
+            eprintln!("usage: bench_compare <baseline> <run> [threshold-percent]");
+            return ExitCode::FAILURE;
+        }
+    };
+    let Ok(threshold) = threshold else {
+        eprintln!("The threshold must be a whole percentage");
+        return ExitCode::FAILURE;
+    };
+    let read = |path: &String| {
+        std::fs::read_to_string(path).unwrap_or_else(|e| panic!("Can't read {path}: {e}"))
+    };
+    let (baseline, run) = (read(baseline), read(run));
+    let baseline: Vec<_> = baseline.lines().filter_map(Measurement::parse).collect();
+
+    let mut regressions = 0;
+    let mut compared = 0;
+    for current in run.lines().filter_map(Measurement::parse) {
+        let Some(old) = baseline.iter().find(|old| old.name == current.name) else {
+            println!("{:<24} {:>10} (new)", current.name, current.median);
+            continue;
+        };
+        compared += 1;
+        let change = (current.median as f64 / old.median.max(1) as f64 - 1.0) * 100.0;
+        let verdict = if current.regressed_from(old, threshold) {
+            regressions += 1;
+            "REGRESSED"
+        } else {
+            "ok"
+        };
+        println!(
+            "{:<24} {:>10} -> {:>10} {change:+7.1}% {verdict}",
+            current.name, old.median, current.median
+        );
+    }
+    for missing in baseline.iter().filter(|old| {
+        !run.lines()
+            .filter_map(Measurement::parse)
+            .any(|m| m.name == old.name)
+    }) {
+        println!("{:<24} missing from the run", missing.name);
+    }
+    if compared == 0 {
+        eprintln!("No benchmarks to compare");
+        return ExitCode::FAILURE;
+    }
+    if regressions > 0 {
+        eprintln!("{regressions} benchmark(s) regressed by more than {threshold}%");
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,134 @@
+//! Audit trail of capability operations.
+//!
+//! The kernel appends an [`AuditRecord`] for every operation that builds,
+//! links, moves or releases a capability, whether it succeeded or not. Each
+//! core keeps its own log. A full log refuses new records instead of
+//! overwriting old ones, and every core may only log a burst of records
+//! followed by a steady rate, so a component can neither flush earlier
+//! records out nor keep the kernel busy logging. Records that didn't make it
+//! are counted, and the next drain starts with a [`LOST`] record holding how
+//! many. A security monitor holding the diagnostics capability drains the
+//! logs with `DiagnosticsOp::DrainAudit`.
+
+use core::fmt;
+
+use crate::raw::{CapError, RawOperation};
+
+/// The `op` of records that count the records lost before them.
+pub const LOST: u32 = u32::MAX;
+
+/// An operation recorded by the kernel.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// Nanoseconds since boot.
+    pub nanos: u64,
+    /// The [`RawOperation`], or [`LOST`].
+    pub op: u32,
+    /// Core the operation ran on.
+    pub core: u32,
+    /// Identifies the thread that made the syscall, or 0.
+    pub thread: u64,
+    /// The capability that was exercised.
+    pub cap: u32,
+    /// 0 if the operation succeeded, or the [`CapError`] it failed with.
+    pub error: u32,
+    /// The arguments of the operation, or the number of lost records in the
+    /// first one.
+    pub args: [u64; 4],
+}
+
+impl AuditRecord {
+    pub fn op(&self) -> Option<RawOperation> {
+        RawOperation::try_from(self.op as usize).ok()
+    }
+
+    pub fn error(&self) -> Option<CapError> {
+        CapError::try_from(u8::try_from(self.error).ok()?).ok()
+    }
+}
+
+/// Whether the kernel records `op`.
+pub fn is_audited(op: RawOperation) -> bool {
+    matches!(
+        op,
+        RawOperation::CapTableLink
+            | RawOperation::CapTableUnlink
+            | RawOperation::CapTableConstruct
+            | RawOperation::CapTableDrop
+            | RawOperation::CapTableCopy
+            | RawOperation::CapTableExtend
+            | RawOperation::MemoryRegionRetype
+            | RawOperation::MemoryRegionSplit
+            | RawOperation::MemoryRegionTransfer
+            | RawOperation::MemoryRegionMap
+    )
+}
+
+/// Writes the record as a line of text without the trailing newline.
+impl fmt::Display for AuditRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let nanos = self.nanos;
+        write!(
+            f,
+            "{}.{:09} core {} ",
+            nanos / 1_000_000_000,
+            nanos % 1_000_000_000,
+            self.core
+        )?;
+        if self.op == LOST {
+            return write!(f, "lost {} records", self.args[0]);
+        }
+        write!(f, "thread {:#x} cap {} ", self.thread, self.cap)?;
+        match self.op() {
+            Some(op) => write!(f, "{op:?}")?,
+            None => write!(f, "op {}", self.op)?,
+        }
+        let [a, b, c, d] = self.args;
+        write!(f, "({a:#x}, {b:#x}, {c:#x}, {d:#x}) ")?;
+        match (self.error, self.error()) {
+            (0, _) => f.write_str("ok"),
+            (_, Some(error)) => write!(f, "{error:?}"),
+            (error, None) => write!(f, "error {error}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn formats_records() {
+        let record = AuditRecord {
+            nanos: 1_500_000_000,
+            op: RawOperation::CapTableDrop as u32,
+            core: 1,
+            thread: 0x5000,
+            cap: 3,
+            error: 0,
+            args: [12, 0, 0, 0],
+        };
+        assert_eq!(
+            record.to_string(),
+            "1.500000000 core 1 thread 0x5000 cap 3 CapTableDrop(0xc, 0x0, 0x0, 0x0) ok"
+        );
+        let refused = AuditRecord {
+            error: CapError::ResourceInUse as u32,
+            ..record
+        };
+        assert!(refused.to_string().ends_with(" ResourceInUse"));
+        let lost = AuditRecord {
+            op: LOST,
+            args: [7, 0, 0, 0],
+            ..Default::default()
+        };
+        assert_eq!(lost.to_string(), "0.000000000 core 0 lost 7 records");
+        assert!(!is_audited(RawOperation::ClockGetTimeNs));
+        assert!(is_audited(RawOperation::MemoryRegionTransfer));
+    }
+}
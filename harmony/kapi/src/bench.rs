@@ -0,0 +1,128 @@
+//! Results of the kernel micro-benchmarks.
+//!
+//! Test kernels built with the `bench` feature run the benchmarks after the
+//! tests and print a line per benchmark to the serial port, in cycles:
+//!
+//! ```text
+//! bench dispatch_getclock iterations=1000 min=210 median=224 max=3080
+//! ```
+//!
+//! `make kbench` saves the lines of a run and `make bench-compare` compares
+//! them against a baseline, failing if any median got slower than the
+//! threshold allows.
+
+use core::fmt;
+
+/// First word of every result line.
+pub const PREFIX: &str = "bench";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Measurement<'a> {
+    pub name: &'a str,
+    pub iterations: usize,
+    pub min: u64,
+    pub median: u64,
+    pub max: u64,
+}
+
+impl<'a> Measurement<'a> {
+    /// Summarizes the cycles each iteration took, sorting `samples`.
+    pub fn new(name: &'a str, samples: &mut [u64]) -> Self {
+        samples.sort_unstable();
+        Self {
+            name,
+            iterations: samples.len(),
+            min: samples.first().copied().unwrap_or(0),
+            median: samples.get(samples.len() / 2).copied().unwrap_or(0),
+            max: samples.last().copied().unwrap_or(0),
+        }
+    }
+
+    /// Reads a result line, or returns `None` if the line is anything else.
+    pub fn parse(line: &'a str) -> Option<Self> {
+        let mut words = line.split_ascii_whitespace();
+        if words.next()? != PREFIX {
+            return None;
+        }
+        let name = words.next()?;
+        let mut field = |key: &str| {
+            words
+                .next()?
+                .strip_prefix(key)?
+                .strip_prefix('=')?
+                .parse::<u64>()
+                .ok()
+        };
+        let measurement = Self {
+            name,
+            iterations: field("iterations")?.try_into().ok()?,
+            min: field("min")?,
+            median: field("median")?,
+            max: field("max")?,
+        };
+        words.next().is_none().then_some(measurement)
+    }
+
+    /// Whether the median is more than `threshold_percent` slower than the
+    /// one in `baseline`.
+    pub fn regressed_from(&self, baseline: &Self, threshold_percent: u64) -> bool {
+        u128::from(self.median) * 100
+            > u128::from(baseline.median) * u128::from(100 + threshold_percent)
+    }
+}
+
+impl fmt::Display for Measurement<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{PREFIX} {} iterations={} min={} median={} max={}",
+            self.name, self.iterations, self.min, self.median, self.max
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn round_trips_through_lines() {
+        let mut samples = [5, 1, 9, 3, 7];
+        let measurement = Measurement::new("retype", &mut samples);
+        assert_eq!(
+            (measurement.min, measurement.median, measurement.max),
+            (1, 5, 9)
+        );
+        let line = measurement.to_string();
+        assert_eq!(line, "bench retype iterations=5 min=1 median=5 max=9");
+        assert_eq!(Measurement::parse(&line), Some(measurement));
+    }
+
+    #[test]
+    fn ignores_other_lines() {
+        assert_eq!(
+            Measurement::parse("1/3 - kernel::tests::trivial...\t[ok]"),
+            None
+        );
+        assert_eq!(Measurement::parse("bench retype iterations=5"), None);
+        assert_eq!(
+            Measurement::parse("bench retype iterations=5 min=1 median=5 max=9 extra"),
+            None
+        );
+    }
+
+    #[test]
+    fn regressions_go_past_the_threshold() {
+        let mut samples = [100];
+        let baseline = Measurement::new("map", &mut samples);
+        let mut samples = [110];
+        let current = Measurement::new("map", &mut samples);
+        assert!(!current.regressed_from(&baseline, 10));
+        assert!(current.regressed_from(&baseline, 9));
+        assert!(!baseline.regressed_from(&current, 0));
+    }
+}
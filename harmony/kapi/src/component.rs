@@ -0,0 +1,345 @@
+//! Metadata that components declare in their own binaries.
+//!
+//! Components place ELF notes owned by [`NOTE_NAME`] in a [`NOTE_SECTION`]
+//! section to tell the loader what they need: the interfaces they expect to
+//! be given, the size of their initial stack, how much memory to reserve for
+//! their heap and how they'd like to be scheduled. The linker puts note
+//! sections in a `PT_NOTE` segment, where the loader finds them.
+//!
+//! ```ignore
+//! use kapi::component::{stack_size, Note};
+//!
+//! #[used]
+//! #[link_section = ".note.harmony"]
+//! static STACK: Note<8> = stack_size(64 * 1024);
+//! ```
+//!
+//! Every note is a standard ELF note with 4 byte alignment. All integers are
+//! little endian.
+//!
+//! ```text
+//! note:       name size u32, desc size u32, type u32, name [u8; 8], desc
+//! stack size: bytes u64
+//! heap size:  bytes u64
+//! interface:  uuid [u8; 16]
+//! sched hint: priority u8, flags u8, core u8, reserved u8
+//! ```
+
+/// Section holding the component notes.
+pub const NOTE_SECTION: &str = ".note.harmony";
+
+/// Owner of the component notes, NUL terminated as ELF requires.
+pub const NOTE_NAME: [u8; 8] = *b"Harmony\0";
+
+/// Bytes of the initial stack.
+pub const NT_STACK_SIZE: u32 = 1;
+/// Bytes of memory to reserve for the heap.
+pub const NT_HEAP_SIZE: u32 = 2;
+/// An interface the component needs, once per interface.
+pub const NT_INTERFACE: u32 = 3;
+/// A [`SchedHint`].
+pub const NT_SCHED_HINT: u32 = 4;
+
+/// Name of the boot module holding the shared library components can link
+/// against dynamically.
+pub const LIBRARY_MODULE: &[u8] = b"libkapi.so";
+/// Where the loader maps the shared library in every component that needs it.
+pub const LIBRARY_ADDRESS: usize = 0x0000_6000_0000_0000;
+/// Largest span of virtual memory the shared library may take.
+pub const LIBRARY_SIZE: usize = 1 << 30;
+
+const HEADER_SIZE: usize = 12;
+const SCHED_PINNED: u8 = 1 << 0;
+
+/// Identifies an interface independently of who implements it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Uuid(pub [u8; 16]);
+
+/// How a component would like its threads to be scheduled.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct SchedHint {
+    /// Higher runs first.
+    pub priority: u8,
+    /// The core to run on, if the component should stay on one.
+    pub core: Option<u8>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NoteError {
+    Truncated,
+    /// A note has a description of the wrong size for its type.
+    BadNote,
+    /// A note that may only appear once appears again.
+    Duplicate,
+}
+
+/// A note ready to be placed in [`NOTE_SECTION`].
+///
+/// `N` must be a multiple of 4 so that the next note stays aligned.
+#[repr(C, align(4))]
+#[derive(Debug, Copy, Clone)]
+pub struct Note<const N: usize> {
+    name_size: u32,
+    desc_size: u32,
+    kind: u32,
+    name: [u8; 8],
+    desc: [u8; N],
+}
+
+impl<const N: usize> Note<N> {
+    pub const fn new(kind: u32, desc: [u8; N]) -> Self {
+        assert!(N % 4 == 0, "Note descriptions must keep 4 byte alignment");
+        Self {
+            name_size: NOTE_NAME.len() as u32,
+            desc_size: N as u32,
+            kind,
+            name: NOTE_NAME,
+            desc,
+        }
+    }
+}
+
+pub const fn stack_size(bytes: u64) -> Note<8> {
+    Note::new(NT_STACK_SIZE, bytes.to_le_bytes())
+}
+
+pub const fn heap_size(bytes: u64) -> Note<8> {
+    Note::new(NT_HEAP_SIZE, bytes.to_le_bytes())
+}
+
+pub const fn interface(uuid: Uuid) -> Note<16> {
+    Note::new(NT_INTERFACE, uuid.0)
+}
+
+pub const fn sched_hint(hint: SchedHint) -> Note<4> {
+    let (flags, core) = match hint.core {
+        Some(core) => (SCHED_PINNED, core),
+        None => (0, 0),
+    };
+    Note::new(NT_SCHED_HINT, [hint.priority, flags, core, 0])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn align4(size: usize) -> usize {
+    (size + 3) & !3
+}
+
+/// A single note of any owner.
+struct RawNote<'a> {
+    name: &'a [u8],
+    kind: u32,
+    desc: &'a [u8],
+}
+
+/// Splits a note segment into notes.
+struct RawNotes<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> RawNote<'a> {
+    /// Reads the note at the start of `bytes` and returns it with the rest.
+    fn split(bytes: &'a [u8]) -> Result<(Self, &'a [u8]), NoteError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(NoteError::Truncated);
+        }
+        let name_size = read_u32(bytes, 0) as usize;
+        let desc_size = read_u32(bytes, 4) as usize;
+        let kind = read_u32(bytes, 8);
+        let desc_start = HEADER_SIZE
+            .checked_add(align4(name_size))
+            .ok_or(NoteError::Truncated)?;
+        let end = desc_start
+            .checked_add(align4(desc_size))
+            .ok_or(NoteError::Truncated)?;
+        if end > bytes.len() {
+            return Err(NoteError::Truncated);
+        }
+        let note = Self {
+            name: &bytes[HEADER_SIZE..HEADER_SIZE + name_size],
+            kind,
+            desc: &bytes[desc_start..desc_start + desc_size],
+        };
+        Ok((note, &bytes[end..]))
+    }
+}
+
+impl<'a> Iterator for RawNotes<'a> {
+    type Item = Result<RawNote<'a>, NoteError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        match RawNote::split(self.bytes) {
+            Ok((note, rest)) => {
+                self.bytes = rest;
+                Some(Ok(note))
+            }
+            Err(e) => {
+                self.bytes = &[];
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Requirements a component declared, parsed from its note segments.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Metadata<'a> {
+    pub stack_size: Option<u64>,
+    pub heap_size: Option<u64>,
+    pub sched_hint: Option<SchedHint>,
+    notes: &'a [u8],
+}
+
+impl<'a> Metadata<'a> {
+    /// Parses the contents of a note segment.
+    ///
+    /// Notes from other owners and of unknown types are skipped.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, NoteError> {
+        let mut metadata = Self {
+            notes: bytes,
+            ..Default::default()
+        };
+        for note in (RawNotes { bytes }) {
+            let note = note?;
+            if note.name != NOTE_NAME {
+                continue;
+            }
+            match note.kind {
+                NT_STACK_SIZE => set_once(&mut metadata.stack_size, read_size(note.desc)?)?,
+                NT_HEAP_SIZE => set_once(&mut metadata.heap_size, read_size(note.desc)?)?,
+                NT_INTERFACE => {
+                    read_uuid(note.desc)?;
+                }
+                NT_SCHED_HINT => set_once(&mut metadata.sched_hint, read_sched_hint(note.desc)?)?,
+                _ => {}
+            }
+        }
+        Ok(metadata)
+    }
+
+    /// Whether the segment had no notes for [`NOTE_NAME`].
+    pub fn is_empty(&self) -> bool {
+        self.stack_size.is_none()
+            && self.heap_size.is_none()
+            && self.sched_hint.is_none()
+            && self.interfaces().next().is_none()
+    }
+
+    /// The interfaces the component needs, in declaration order.
+    pub fn interfaces(&self) -> impl Iterator<Item = Uuid> + 'a {
+        // Parsing validated every note.
+        RawNotes { bytes: self.notes }
+            .flatten()
+            .filter(|note| note.name == NOTE_NAME && note.kind == NT_INTERFACE)
+            .filter_map(|note| read_uuid(note.desc).ok())
+    }
+}
+
+fn set_once<T>(slot: &mut Option<T>, value: T) -> Result<(), NoteError> {
+    if slot.is_some() {
+        return Err(NoteError::Duplicate);
+    }
+    *slot = Some(value);
+    Ok(())
+}
+
+fn read_size(desc: &[u8]) -> Result<u64, NoteError> {
+    Ok(u64::from_le_bytes(
+        desc.try_into().map_err(|_| NoteError::BadNote)?,
+    ))
+}
+
+fn read_uuid(desc: &[u8]) -> Result<Uuid, NoteError> {
+    Ok(Uuid(desc.try_into().map_err(|_| NoteError::BadNote)?))
+}
+
+fn read_sched_hint(desc: &[u8]) -> Result<SchedHint, NoteError> {
+    let [priority, flags, core, _]: [u8; 4] = desc.try_into().map_err(|_| NoteError::BadNote)?;
+    Ok(SchedHint {
+        priority,
+        core: (flags & SCHED_PINNED != 0).then_some(core),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOGGER: Uuid = Uuid(*b"harmony::logger\0");
+    const BLOCK: Uuid = Uuid(*b"harmony::block\0\0");
+
+    /// The notes as the linker would lay them out in the segment.
+    #[repr(C)]
+    struct Segment {
+        stack: Note<8>,
+        foreign: Note<4>,
+        logger: Note<16>,
+        sched: Note<4>,
+        block: Note<16>,
+    }
+
+    fn bytes<T>(value: &T) -> &[u8] {
+        // SAFETY: Notes are plain integers and bytes without padding.
+        unsafe {
+            core::slice::from_raw_parts((value as *const T).cast(), core::mem::size_of::<T>())
+        }
+    }
+
+    #[test]
+    fn parses_notes() {
+        let mut foreign = Note::new(NT_STACK_SIZE, [1; 4]);
+        foreign.name = *b"Foreign\0";
+        let segment = Segment {
+            stack: stack_size(0x8000),
+            foreign,
+            logger: interface(LOGGER),
+            sched: sched_hint(SchedHint {
+                priority: 3,
+                core: Some(1),
+            }),
+            block: interface(BLOCK),
+        };
+        let metadata = Metadata::parse(bytes(&segment)).unwrap();
+        assert!(!metadata.is_empty());
+        assert_eq!(metadata.stack_size, Some(0x8000));
+        assert_eq!(metadata.heap_size, None);
+        assert_eq!(
+            metadata.sched_hint,
+            Some(SchedHint {
+                priority: 3,
+                core: Some(1)
+            })
+        );
+        assert!(metadata.interfaces().eq([LOGGER, BLOCK]));
+    }
+
+    #[test]
+    fn rejects_bad_notes() {
+        let notes = [heap_size(1), heap_size(2)];
+        assert_eq!(
+            Metadata::parse(bytes(&notes)).unwrap_err(),
+            NoteError::Duplicate
+        );
+
+        let note = Note::new(NT_INTERFACE, [0; 8]);
+        assert_eq!(
+            Metadata::parse(bytes(&note)).unwrap_err(),
+            NoteError::BadNote
+        );
+
+        let note = stack_size(1);
+        let note = bytes(&note);
+        assert_eq!(
+            Metadata::parse(&note[..note.len() - 4]).unwrap_err(),
+            NoteError::Truncated
+        );
+
+        let metadata = Metadata::parse(&[]).unwrap();
+        assert!(metadata.is_empty());
+    }
+}
@@ -0,0 +1,285 @@
+//! Control protocol for host-driven integration tests.
+//!
+//! Kernels built with the `control` feature listen on the second serial port
+//! for commands from a test runner on the host, one per line:
+//!
+//! ```text
+//! ping
+//! run-test <name>
+//! inject-fault <site> <n>
+//! dump-caps
+//! ```
+//!
+//! Every command gets a single line back holding a flat JSON object. The first
+//! field is always `status`, one of `ok`, `failed`, `unsupported` or
+//! `bad-command`, and the rest depend on the command:
+//!
+//! ```text
+//! {"status":"ok","command":"run-test","test":"stack","used":1234}
+//! ```
+//!
+//! Values are unsigned integers or strings. Strings never hold quotes,
+//! backslashes or control characters (they're replaced by `?`), so they can be
+//! read back without unescaping.
+
+use core::fmt::{self, Write as _};
+
+/// Longest command line the agent accepts.
+pub const MAX_COMMAND_LEN: usize = 128;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Command<'a> {
+    Ping,
+    /// Runs the named self-check.
+    RunTest {
+        name: &'a str,
+    },
+    /// Makes the `n`th call through a fault injection site fail.
+    InjectFault {
+        site: &'a str,
+        n: usize,
+    },
+    /// Lists the capabilities of the thread running on the core.
+    DumpCaps,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    Empty,
+    UnknownCommand,
+    MissingArgument,
+    BadArgument,
+    TooManyArguments,
+}
+
+impl<'a> Command<'a> {
+    pub fn parse(line: &'a str) -> Result<Self, ParseError> {
+        let mut words = line.split_ascii_whitespace();
+        let mut argument = || words.next().ok_or(ParseError::MissingArgument);
+        let command = match argument().map_err(|_| ParseError::Empty)? {
+            "ping" => Command::Ping,
+            "run-test" => Command::RunTest { name: argument()? },
+            "inject-fault" => Command::InjectFault {
+                site: argument()?,
+                n: argument()?.parse().map_err(|_| ParseError::BadArgument)?,
+            },
+            "dump-caps" => Command::DumpCaps,
+            _ => return Err(ParseError::UnknownCommand),
+        };
+        match words.next() {
+            Some(_) => Err(ParseError::TooManyArguments),
+            None => Ok(command),
+        }
+    }
+
+    /// Name of the command, as sent on the wire.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Ping => "ping",
+            Command::RunTest { .. } => "run-test",
+            Command::InjectFault { .. } => "inject-fault",
+            Command::DumpCaps => "dump-caps",
+        }
+    }
+}
+
+impl fmt::Display for Command<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Ping | Command::DumpCaps => write!(f, "{}", self.name()),
+            Command::RunTest { name } => write!(f, "{} {name}", self.name()),
+            Command::InjectFault { site, n } => write!(f, "{} {site} {n}", self.name()),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// The command ran and found a problem.
+    Failed,
+    /// The command isn't available in this kernel.
+    Unsupported,
+    BadCommand,
+}
+
+impl Status {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Failed => "failed",
+            Status::Unsupported => "unsupported",
+            Status::BadCommand => "bad-command",
+        }
+    }
+}
+
+/// Writes a response line field by field.
+pub struct Reply<W> {
+    out: W,
+}
+
+impl<W: fmt::Write> Reply<W> {
+    /// Starts the response with its status.
+    pub fn new(mut out: W, status: Status) -> Result<Self, fmt::Error> {
+        write!(out, "{{\"status\":\"{}\"", status.name())?;
+        Ok(Self { out })
+    }
+
+    pub fn string(&mut self, key: &str, value: impl fmt::Display) -> fmt::Result {
+        write!(self.out, ",\"{key}\":\"")?;
+        write!(Sanitize(&mut self.out), "{value}")?;
+        self.out.write_char('"')
+    }
+
+    pub fn number(&mut self, key: &str, value: u64) -> fmt::Result {
+        write!(self.out, ",\"{key}\":{value}")
+    }
+
+    /// Ends the response line.
+    pub fn finish(mut self) -> fmt::Result {
+        self.out.write_str("}\n")
+    }
+}
+
+/// Replaces the characters strings may not hold.
+struct Sanitize<W>(W);
+
+impl<W: fmt::Write> fmt::Write for Sanitize<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '"' | '\\' => self.0.write_char('?')?,
+                c if c.is_control() => self.0.write_char('?')?,
+                c => self.0.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Value<'a> {
+    String(&'a str),
+    Number(u64),
+}
+
+/// A response line read back by the host.
+#[derive(Debug, Copy, Clone)]
+pub struct Response<'a> {
+    pub status: &'a str,
+    /// The fields after the status, each starting with a comma.
+    rest: &'a str,
+}
+
+impl<'a> Response<'a> {
+    /// Parses a line written with [`Reply`]. Returns `None` if it isn't one.
+    pub fn parse(line: &'a str) -> Option<Self> {
+        let body = line.trim().strip_prefix('{')?.strip_suffix('}')?;
+        let (status, rest) = match field(body)? {
+            (("status", Value::String(status)), rest) => (status, rest),
+            _ => return None,
+        };
+        // Check that every field is well formed.
+        let mut remaining = rest;
+        while !remaining.is_empty() {
+            (_, remaining) = field(remaining.strip_prefix(',')?)?;
+        }
+        Some(Self { status, rest })
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == Status::Ok.name()
+    }
+
+    /// Every field after the status, in order.
+    pub fn fields(&self) -> impl Iterator<Item = (&'a str, Value<'a>)> + 'a {
+        let mut remaining = self.rest;
+        core::iter::from_fn(move || {
+            let (field, rest) = field(remaining.strip_prefix(',')?)?;
+            remaining = rest;
+            Some(field)
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<Value<'a>> {
+        self.fields()
+            .find(|(field, _)| *field == key)
+            .map(|(_, value)| value)
+    }
+}
+
+/// Splits the field at the start of `s` from the rest.
+fn field(s: &str) -> Option<((&str, Value<'_>), &str)> {
+    let (key, rest) = s.strip_prefix('"')?.split_once("\":")?;
+    // Strings can't hold quotes, so the next one ends the value.
+    if let Some(string) = rest.strip_prefix('"') {
+        let (value, rest) = string.split_once('"')?;
+        return Some(((key, Value::String(value)), rest));
+    }
+    let end = rest.find(',').unwrap_or(rest.len());
+    let value = rest[..end].parse().ok()?;
+    Some(((key, Value::Number(value)), &rest[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(Command::parse(" ping "), Ok(Command::Ping));
+        assert_eq!(
+            Command::parse("inject-fault retype 3"),
+            Ok(Command::InjectFault {
+                site: "retype",
+                n: 3
+            })
+        );
+        assert_eq!(
+            Command::parse("run-test stack"),
+            Ok(Command::RunTest { name: "stack" })
+        );
+        for (line, error) in [
+            ("", ParseError::Empty),
+            ("reboot", ParseError::UnknownCommand),
+            ("run-test", ParseError::MissingArgument),
+            ("inject-fault retype x", ParseError::BadArgument),
+            ("ping now", ParseError::TooManyArguments),
+        ] {
+            assert_eq!(Command::parse(line), Err(error), "{line:?}");
+        }
+        let command = Command::InjectFault { site: "kptr", n: 1 };
+        assert_eq!(Command::parse(&std::format!("{command}")), Ok(command));
+    }
+
+    #[test]
+    fn responses_round_trip() {
+        let mut line = String::new();
+        let mut reply = Reply::new(&mut line, Status::Failed).unwrap();
+        reply.string("command", "run-test").unwrap();
+        reply.string("error", "bad \"stack\",\n").unwrap();
+        reply.number("used", 1234).unwrap();
+        reply.finish().unwrap();
+        assert_eq!(
+            line,
+            "{\"status\":\"failed\",\"command\":\"run-test\",\"error\":\"bad ?stack?,?\",\
+             \"used\":1234}\n"
+        );
+
+        let response = Response::parse(&line).unwrap();
+        assert_eq!(response.status, "failed");
+        assert!(!response.is_ok());
+        assert_eq!(response.get("used"), Some(Value::Number(1234)));
+        assert_eq!(response.get("command"), Some(Value::String("run-test")));
+        assert_eq!(response.get("error"), Some(Value::String("bad ?stack?,?")));
+        assert_eq!(response.get("missing"), None);
+
+        assert!(Response::parse("{\"status\":\"ok\",\"x\"}").is_none());
+        assert!(Response::parse("Booting...").is_none());
+    }
+}
@@ -0,0 +1,688 @@
+//! Inventory of the hardware discovered by the kernel.
+//!
+//! The kernel serializes the devices it finds at boot into a compact blob
+//! mapped read-only at [`DEVICES_ADDRESS`] in the boot component. The blob
+//! starts with a header followed by one record per device, each followed by
+//! its resources. All integers are little endian.
+//!
+//! ```text
+//! header:   magic u32, version u16, device count u16, length u32, reserved u32
+//! device:   bus u8, resource count u8, reserved u16, path u32,
+//!           vendor u16, device u16, class u32
+//! resource: kind u8, flags u8, reserved [u8; 6], start u64, length u64
+//! ```
+//!
+//! NUMA nodes are listed like devices, after the platform devices. Their
+//! path is the node's proximity domain and their resources are the memory
+//! and processors local to them and their distance to every node. Machines
+//! without an ACPI SRAT have no node records, and every core should be
+//! considered as close to all memory as any other.
+
+use core::ops::Range;
+
+use crate::info::INFO_PAGE_ADDRESS;
+
+/// Virtual address where the kernel maps the device inventory.
+pub const DEVICES_ADDRESS: usize = INFO_PAGE_ADDRESS - INVENTORY_SIZE;
+
+/// Maximum size of the inventory in bytes.
+pub const INVENTORY_SIZE: usize = 4096;
+
+/// "HDEV"
+pub const INVENTORY_MAGIC: u32 = u32::from_le_bytes(*b"HDEV");
+pub const INVENTORY_VERSION: u16 = 2;
+
+const HEADER_SIZE: usize = 16;
+const DEVICE_SIZE: usize = 16;
+const RESOURCE_SIZE: usize = 24;
+
+const BUS_PLATFORM: u8 = 1;
+const BUS_PCI: u8 = 2;
+const BUS_NODE: u8 = 3;
+
+const RESOURCE_MMIO: u8 = 1;
+const RESOURCE_PORTS: u8 = 2;
+const RESOURCE_IRQ: u8 = 3;
+const RESOURCE_MEMORY: u8 = 4;
+const RESOURCE_CPU: u8 = 5;
+const RESOURCE_DISTANCE: u8 = 6;
+
+const FLAG_PREFETCHABLE: u8 = 1 << 0;
+
+/// Where a device sits in the system.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BusPath {
+    /// Legacy devices at fixed locations, numbered in discovery order.
+    Platform {
+        index: u32,
+    },
+    Pci {
+        bus: u8,
+        device: u8,
+        function: u8,
+    },
+    /// A NUMA node, named by its proximity domain.
+    Node {
+        id: u32,
+    },
+}
+
+/// What a device is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeviceId {
+    /// Compressed plug-and-play id, see [`pnp_id`].
+    Pnp(u32),
+    Pci {
+        vendor: u16,
+        device: u16,
+        /// Class code, subclass and programming interface as `0xCCSSPP`.
+        class: u32,
+    },
+    /// A NUMA node rather than a device.
+    Node,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeviceResource {
+    Mmio {
+        start: u64,
+        length: u64,
+        prefetchable: bool,
+    },
+    Ports {
+        start: u16,
+        length: u16,
+    },
+    Irq(u8),
+    /// RAM local to a node.
+    Memory {
+        start: u64,
+        length: u64,
+    },
+    /// A processor local to a node, by the id of its local APIC.
+    Cpu {
+        apic_id: u32,
+    },
+    /// How far a node is from another one, as in the ACPI SLIT: 10 is the
+    /// distance of a node to itself and 20 means memory takes twice as long
+    /// to reach.
+    Distance {
+        node: u32,
+        distance: u8,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InventoryError {
+    NoSpace,
+    TooManyResources,
+    TooManyDevices,
+    BadMagic,
+    UnsupportedVersion,
+    Truncated,
+    BadRecord,
+}
+
+/// Compresses a plug-and-play id such as `PNP0501` into 32 bits.
+///
+/// The three letters take 5 bits each (`A` is 1) followed by the four hex digits.
+pub const fn pnp_id(name: &[u8; 7]) -> u32 {
+    const fn hex(digit: u8) -> u32 {
+        match digit {
+            b'0'..=b'9' => (digit - b'0') as u32,
+            b'A'..=b'F' => (digit - b'A' + 10) as u32,
+            _ => panic!("Invalid hex digit in PNP id"),
+        }
+    }
+    let mut letters = 0;
+    let mut i = 0;
+    while i < 3 {
+        assert!(name[i].is_ascii_uppercase(), "Invalid letter in PNP id");
+        letters = (letters << 5) | (name[i] - b'@') as u32;
+        i += 1;
+    }
+    let mut digits = 0;
+    while i < 7 {
+        digits = (digits << 4) | hex(name[i]);
+        i += 1;
+    }
+    (letters << 16) | digits
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Serializes devices into an inventory blob.
+pub struct InventoryWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    count: u16,
+}
+
+impl<'a> InventoryWriter<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Result<Self, InventoryError> {
+        if buf.len() < HEADER_SIZE {
+            return Err(InventoryError::NoSpace);
+        }
+        let mut writer = Self {
+            buf,
+            len: HEADER_SIZE,
+            count: 0,
+        };
+        writer.write_header();
+        Ok(writer)
+    }
+
+    fn write_header(&mut self) {
+        self.buf[0..4].copy_from_slice(&INVENTORY_MAGIC.to_le_bytes());
+        self.buf[4..6].copy_from_slice(&INVENTORY_VERSION.to_le_bytes());
+        self.buf[6..8].copy_from_slice(&self.count.to_le_bytes());
+        self.buf[8..12].copy_from_slice(&(self.len as u32).to_le_bytes());
+        self.buf[12..16].fill(0);
+    }
+
+    /// Number of bytes used so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn add_device(
+        &mut self,
+        path: BusPath,
+        id: DeviceId,
+        resources: &[DeviceResource],
+    ) -> Result<(), InventoryError> {
+        let resource_count =
+            u8::try_from(resources.len()).map_err(|_| InventoryError::TooManyResources)?;
+        let count = self
+            .count
+            .checked_add(1)
+            .ok_or(InventoryError::TooManyDevices)?;
+        let size = DEVICE_SIZE + RESOURCE_SIZE * resources.len();
+        let record = self
+            .buf
+            .get_mut(self.len..self.len + size)
+            .ok_or(InventoryError::NoSpace)?;
+
+        let (bus, path) = match path {
+            BusPath::Platform { index } => (BUS_PLATFORM, index),
+            BusPath::Pci {
+                bus,
+                device,
+                function,
+            } => (
+                BUS_PCI,
+                u32::from(bus) << 16 | u32::from(device) << 8 | u32::from(function),
+            ),
+            BusPath::Node { id } => (BUS_NODE, id),
+        };
+        let (vendor, device, class) = match id {
+            DeviceId::Pnp(id) => (0, 0, id),
+            DeviceId::Node => (0, 0, 0),
+            DeviceId::Pci {
+                vendor,
+                device,
+                class,
+            } => (vendor, device, class),
+        };
+        record[0] = bus;
+        record[1] = resource_count;
+        record[2..4].fill(0);
+        record[4..8].copy_from_slice(&path.to_le_bytes());
+        record[8..10].copy_from_slice(&vendor.to_le_bytes());
+        record[10..12].copy_from_slice(&device.to_le_bytes());
+        record[12..16].copy_from_slice(&class.to_le_bytes());
+
+        for (resource, out) in resources
+            .iter()
+            .zip(record[DEVICE_SIZE..].chunks_exact_mut(RESOURCE_SIZE))
+        {
+            let (kind, flags, start, length) = match *resource {
+                DeviceResource::Mmio {
+                    start,
+                    length,
+                    prefetchable,
+                } => (
+                    RESOURCE_MMIO,
+                    if prefetchable { FLAG_PREFETCHABLE } else { 0 },
+                    start,
+                    length,
+                ),
+                DeviceResource::Ports { start, length } => {
+                    (RESOURCE_PORTS, 0, start.into(), length.into())
+                }
+                DeviceResource::Irq(line) => (RESOURCE_IRQ, 0, line.into(), 1),
+                DeviceResource::Memory { start, length } => (RESOURCE_MEMORY, 0, start, length),
+                DeviceResource::Cpu { apic_id } => (RESOURCE_CPU, 0, apic_id.into(), 1),
+                DeviceResource::Distance { node, distance } => {
+                    (RESOURCE_DISTANCE, 0, node.into(), distance.into())
+                }
+            };
+            out[0] = kind;
+            out[1] = flags;
+            out[2..8].fill(0);
+            out[8..16].copy_from_slice(&start.to_le_bytes());
+            out[16..24].copy_from_slice(&length.to_le_bytes());
+        }
+
+        self.len += size;
+        self.count = count;
+        self.write_header();
+        Ok(())
+    }
+}
+
+/// A parsed, validated inventory blob.
+#[derive(Debug, Copy, Clone)]
+pub struct DeviceInventory<'a> {
+    devices: &'a [u8],
+    count: u16,
+}
+
+impl<'a> DeviceInventory<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, InventoryError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(InventoryError::Truncated);
+        }
+        if read_u32(bytes, 0) != INVENTORY_MAGIC {
+            return Err(InventoryError::BadMagic);
+        }
+        if read_u16(bytes, 4) != INVENTORY_VERSION {
+            return Err(InventoryError::UnsupportedVersion);
+        }
+        let count = read_u16(bytes, 6);
+        let len = read_u32(bytes, 8) as usize;
+        let devices = bytes
+            .get(HEADER_SIZE..len)
+            .ok_or(InventoryError::Truncated)?;
+
+        // Validate every record so iterating never fails.
+        let mut offset = 0;
+        for _ in 0..count {
+            let record = devices
+                .get(offset..offset + DEVICE_SIZE)
+                .ok_or(InventoryError::Truncated)?;
+            if !matches!(record[0], BUS_PLATFORM | BUS_PCI | BUS_NODE) {
+                return Err(InventoryError::BadRecord);
+            }
+            let resources = usize::from(record[1]);
+            let end = offset + DEVICE_SIZE + resources * RESOURCE_SIZE;
+            let resources = devices
+                .get(offset + DEVICE_SIZE..end)
+                .ok_or(InventoryError::Truncated)?;
+            if resources
+                .chunks_exact(RESOURCE_SIZE)
+                .any(|resource| decode_resource(resource).is_none())
+            {
+                return Err(InventoryError::BadRecord);
+            }
+            offset = end;
+        }
+        if offset != devices.len() {
+            return Err(InventoryError::BadRecord);
+        }
+        Ok(Self { devices, count })
+    }
+
+    /// Parses the inventory the kernel mapped into this address space.
+    ///
+    /// # Safety
+    ///
+    /// The kernel must have mapped the inventory at [`DEVICES_ADDRESS`].
+    pub unsafe fn mapped() -> Result<Self, InventoryError> {
+        // SAFETY: Precondition.
+        let bytes =
+            unsafe { core::slice::from_raw_parts(DEVICES_ADDRESS as *const u8, INVENTORY_SIZE) };
+        Self::parse(bytes)
+    }
+
+    pub fn len(&self) -> usize {
+        self.count.into()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn devices(&self) -> Devices<'a> {
+        Devices {
+            bytes: self.devices,
+            remaining: self.count,
+        }
+    }
+
+    /// Finds the PCI functions matching `vendor` and `device`.
+    pub fn find_pci(&self, vendor: u16, device: u16) -> impl Iterator<Item = Device<'a>> {
+        self.devices().filter(move |dev| {
+            matches!(dev.id, DeviceId::Pci { vendor: v, device: d, .. } if v == vendor && d == device)
+        })
+    }
+
+    /// Finds the PCI functions with the given class and subclass.
+    pub fn find_class(&self, class: u8, subclass: u8) -> impl Iterator<Item = Device<'a>> {
+        self.devices().filter(move |dev| {
+            matches!(dev.id, DeviceId::Pci { class: c, .. } if c >> 8 == u32::from(class) << 8 | u32::from(subclass))
+        })
+    }
+
+    /// Finds the platform devices with the plug-and-play id `id`.
+    pub fn find_pnp(&self, id: u32) -> impl Iterator<Item = Device<'a>> {
+        self.devices()
+            .filter(move |dev| dev.id == DeviceId::Pnp(id))
+    }
+
+    /// The NUMA nodes, which are empty if the firmware didn't describe any.
+    pub fn nodes(&self) -> impl Iterator<Item = Device<'a>> {
+        self.devices().filter(|dev| dev.id == DeviceId::Node)
+    }
+
+    /// The node of the processor whose local APIC has id `apic_id`.
+    pub fn node_of_cpu(&self, apic_id: u32) -> Option<u32> {
+        self.nodes()
+            .find(|node| {
+                node.resources()
+                    .any(|resource| resource == DeviceResource::Cpu { apic_id })
+            })
+            .and_then(|node| node.node_id())
+    }
+
+    /// The node whose memory holds the physical address `address`.
+    pub fn node_of_address(&self, address: u64) -> Option<u32> {
+        self.nodes()
+            .find(|node| node.memory().any(|range| range.contains(&address)))
+            .and_then(|node| node.node_id())
+    }
+
+    /// Distance from node `from` to node `to`, see
+    /// [`DeviceResource::Distance`].
+    pub fn distance(&self, from: u32, to: u32) -> Option<u8> {
+        let from = self.nodes().find(|node| node.node_id() == Some(from))?;
+        from.resources().find_map(|resource| match resource {
+            DeviceResource::Distance { node, distance } if node == to => Some(distance),
+            _ => None,
+        })
+    }
+}
+
+fn decode_resource(bytes: &[u8]) -> Option<DeviceResource> {
+    let start = read_u64(bytes, 8);
+    let length = read_u64(bytes, 16);
+    match bytes[0] {
+        RESOURCE_MMIO => Some(DeviceResource::Mmio {
+            start,
+            length,
+            prefetchable: bytes[1] & FLAG_PREFETCHABLE != 0,
+        }),
+        RESOURCE_PORTS => Some(DeviceResource::Ports {
+            start: start.try_into().ok()?,
+            length: length.try_into().ok()?,
+        }),
+        RESOURCE_IRQ => Some(DeviceResource::Irq(start.try_into().ok()?)),
+        RESOURCE_MEMORY => Some(DeviceResource::Memory { start, length }),
+        RESOURCE_CPU => Some(DeviceResource::Cpu {
+            apic_id: start.try_into().ok()?,
+        }),
+        RESOURCE_DISTANCE => Some(DeviceResource::Distance {
+            node: start.try_into().ok()?,
+            distance: length.try_into().ok()?,
+        }),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Device<'a> {
+    pub path: BusPath,
+    pub id: DeviceId,
+    resources: &'a [u8],
+}
+
+impl<'a> Device<'a> {
+    pub fn resources(&self) -> impl Iterator<Item = DeviceResource> + 'a {
+        self.resources
+            .chunks_exact(RESOURCE_SIZE)
+            .filter_map(decode_resource)
+    }
+
+    /// The proximity domain of a NUMA node.
+    pub fn node_id(&self) -> Option<u32> {
+        match self.path {
+            BusPath::Node { id } => Some(id),
+            _ => None,
+        }
+    }
+
+    /// The physical memory ranges of a NUMA node.
+    pub fn memory(&self) -> impl Iterator<Item = Range<u64>> + 'a {
+        self.resources().filter_map(|resource| match resource {
+            DeviceResource::Memory { start, length } => Some(start..start.saturating_add(length)),
+            _ => None,
+        })
+    }
+}
+
+pub struct Devices<'a> {
+    bytes: &'a [u8],
+    remaining: u16,
+}
+
+impl<'a> Iterator for Devices<'a> {
+    type Item = Device<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let record = &self.bytes[..DEVICE_SIZE];
+        let path = read_u32(record, 4);
+        let path = match record[0] {
+            BUS_PLATFORM => BusPath::Platform { index: path },
+            BUS_NODE => BusPath::Node { id: path },
+            _ => BusPath::Pci {
+                bus: (path >> 16) as u8,
+                device: (path >> 8) as u8,
+                function: path as u8,
+            },
+        };
+        let class = read_u32(record, 12);
+        let id = match record[0] {
+            BUS_PLATFORM => DeviceId::Pnp(class),
+            BUS_NODE => DeviceId::Node,
+            _ => DeviceId::Pci {
+                vendor: read_u16(record, 8),
+                device: read_u16(record, 10),
+                class,
+            },
+        };
+        let end = DEVICE_SIZE + usize::from(record[1]) * RESOURCE_SIZE;
+        let resources = &self.bytes[DEVICE_SIZE..end];
+        self.bytes = &self.bytes[end..];
+        Some(Device {
+            path,
+            id,
+            resources,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UART: u32 = pnp_id(b"PNP0501");
+
+    fn sample(buf: &mut [u8]) -> usize {
+        let mut writer = InventoryWriter::new(buf).unwrap();
+        writer
+            .add_device(
+                BusPath::Platform { index: 0 },
+                DeviceId::Pnp(UART),
+                &[
+                    DeviceResource::Ports {
+                        start: 0x2F8,
+                        length: 8,
+                    },
+                    DeviceResource::Irq(3),
+                ],
+            )
+            .unwrap();
+        writer
+            .add_device(
+                BusPath::Pci {
+                    bus: 0,
+                    device: 3,
+                    function: 0,
+                },
+                DeviceId::Pci {
+                    vendor: 0x8086,
+                    device: 0x100E,
+                    class: 0x02_00_00,
+                },
+                &[DeviceResource::Mmio {
+                    start: 0xFEB8_0000,
+                    length: 0x2_0000,
+                    prefetchable: false,
+                }],
+            )
+            .unwrap();
+        writer.len()
+    }
+
+    #[test]
+    fn pnp_ids() {
+        assert_eq!(UART, (16 << 10 | 14 << 5 | 16) << 16 | 0x0501);
+        assert_ne!(pnp_id(b"PNP0303"), UART);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buf = [0; 256];
+        let len = sample(&mut buf);
+        assert_eq!(len, HEADER_SIZE + 2 * DEVICE_SIZE + 3 * RESOURCE_SIZE);
+
+        let inventory = DeviceInventory::parse(&buf).unwrap();
+        assert_eq!(inventory.len(), 2);
+        let uart = inventory.find_pnp(UART).next().unwrap();
+        assert_eq!(uart.path, BusPath::Platform { index: 0 });
+        let mut resources = uart.resources();
+        assert_eq!(
+            resources.next(),
+            Some(DeviceResource::Ports {
+                start: 0x2F8,
+                length: 8
+            })
+        );
+        assert_eq!(resources.next(), Some(DeviceResource::Irq(3)));
+        assert_eq!(resources.next(), None);
+
+        let nic = inventory.find_class(0x02, 0x00).next().unwrap();
+        assert_eq!(
+            nic.path,
+            BusPath::Pci {
+                bus: 0,
+                device: 3,
+                function: 0
+            }
+        );
+        assert_eq!(inventory.find_pci(0x8086, 0x100E).count(), 1);
+        assert_eq!(inventory.find_pci(0x8086, 0x1234).count(), 0);
+    }
+
+    #[test]
+    fn describes_numa_nodes() {
+        let mut buf = [0; 512];
+        let mut writer = InventoryWriter::new(&mut buf).unwrap();
+        for (id, start, apic_id, far) in [(0, 0, 0, 1), (1, 0x8000_0000, 1, 0)] {
+            writer
+                .add_device(
+                    BusPath::Node { id },
+                    DeviceId::Node,
+                    &[
+                        DeviceResource::Memory {
+                            start,
+                            length: 0x8000_0000,
+                        },
+                        DeviceResource::Cpu { apic_id },
+                        DeviceResource::Distance {
+                            node: id,
+                            distance: 10,
+                        },
+                        DeviceResource::Distance {
+                            node: far,
+                            distance: 21,
+                        },
+                    ],
+                )
+                .unwrap();
+        }
+        let len = writer.len();
+        let inventory = DeviceInventory::parse(&buf[..len]).unwrap();
+        assert_eq!(inventory.nodes().count(), 2);
+        assert_eq!(inventory.node_of_cpu(1), Some(1));
+        assert_eq!(inventory.node_of_cpu(2), None);
+        assert_eq!(inventory.node_of_address(0x7FFF_FFFF), Some(0));
+        assert_eq!(inventory.node_of_address(0x8000_0000), Some(1));
+        assert_eq!(inventory.node_of_address(0x1_0000_0000), None);
+        assert_eq!(inventory.distance(0, 0), Some(10));
+        assert_eq!(inventory.distance(1, 0), Some(21));
+        assert_eq!(inventory.distance(2, 0), None);
+
+        let mut buf = [0; 256];
+        let len = sample(&mut buf);
+        assert_eq!(
+            DeviceInventory::parse(&buf[..len]).unwrap().nodes().count(),
+            0
+        );
+    }
+
+    #[test]
+    fn rejects_bad_blobs() {
+        let mut buf = [0; 256];
+        let len = sample(&mut buf);
+        assert_eq!(
+            DeviceInventory::parse(&buf[..len - 1]).err(),
+            Some(InventoryError::Truncated)
+        );
+
+        let mut bad = buf;
+        bad[0] = 0;
+        assert_eq!(
+            DeviceInventory::parse(&bad).err(),
+            Some(InventoryError::BadMagic)
+        );
+
+        let mut bad = buf;
+        // Unknown resource kind in the first resource of the first device.
+        bad[HEADER_SIZE + DEVICE_SIZE] = 9;
+        assert_eq!(
+            DeviceInventory::parse(&bad).err(),
+            Some(InventoryError::BadRecord)
+        );
+
+        let mut small = [0; 64];
+        let mut writer = InventoryWriter::new(&mut small).unwrap();
+        assert_eq!(
+            writer.add_device(
+                BusPath::Platform { index: 0 },
+                DeviceId::Pnp(UART),
+                &[DeviceResource::Irq(1); 3],
+            ),
+            Err(InventoryError::NoSpace)
+        );
+        assert!(writer.is_empty());
+    }
+}
@@ -0,0 +1,335 @@
+//! Snapshot of kernel state for monitoring components.
+//!
+//! The kernel keeps a single [`DiagnosticsPage`] that holders of a diagnostics
+//! capability can map read-only into their address space (see
+//! [`crate::ops::diagnostics`]). The boot component finds it already mapped at
+//! [`DIAGNOSTICS_ADDRESS`]. The page is only written when someone asks
+//! for a refresh, so readers should check [`DiagnosticsPage::generation`]
+//! before and after reading to know they saw a consistent snapshot.
+
+use crate::devices::DEVICES_ADDRESS;
+use crate::stack::StackUsage;
+
+/// Virtual address where the kernel maps the diagnostics page in the boot
+/// component.
+pub const DIAGNOSTICS_ADDRESS: usize = DEVICES_ADDRESS - 4096;
+
+/// Layout version of [`DiagnosticsPage`].
+pub const DIAGNOSTICS_VERSION: u64 = 4;
+
+/// Maximum number of boot phases recorded.
+pub const MAX_BOOT_PHASES: usize = 16;
+
+/// Maximum length of a boot phase name.
+pub const PHASE_NAME_LEN: usize = 24;
+
+/// Bytes of the kernel log kept in the page.
+pub const LOG_TAIL_SIZE: usize = 2048;
+
+/// Maximum length of a symbol name returned by a symbol lookup.
+pub const SYMBOL_NAME_LEN: usize = 128;
+
+/// Number of buckets in a [`LatencyStats`] histogram.
+pub const LATENCY_BUCKETS: usize = 32;
+
+/// A step of the kernel's initialization.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct BootPhase {
+    /// NUL padded name of the phase.
+    pub name: [u8; PHASE_NAME_LEN],
+    /// Nanoseconds since boot when the phase finished.
+    pub finished_ns: u64,
+}
+
+impl BootPhase {
+    pub fn name(&self) -> &str {
+        let len = self
+            .name
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(PHASE_NAME_LEN);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("?")
+    }
+}
+
+/// Number of physical frames in each state of the retype table.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct RetypeStats {
+    pub untyped: u64,
+    /// Untyped frames known to be zeroed.
+    pub clean: u64,
+    pub user: u64,
+    pub kernel: u64,
+    pub unavailable: u64,
+}
+
+/// Number of page faults the kernel handled, by what caused them.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct PageFaultStats {
+    /// Faults raised by the kernel itself, which panics on them.
+    pub kernel: u64,
+    /// Faults raised by userspace on memory it can't access.
+    pub user: u64,
+    /// Faults raised by userspace on pages that were swapped out.
+    pub swapped: u64,
+}
+
+impl RetypeStats {
+    pub fn total(&self) -> u64 {
+        self.untyped + self.user + self.kernel + self.unavailable
+    }
+}
+
+impl core::ops::AddAssign for RetypeStats {
+    fn add_assign(&mut self, other: Self) {
+        self.untyped += other.untyped;
+        self.clean += other.clean;
+        self.user += other.user;
+        self.kernel += other.kernel;
+        self.unavailable += other.unavailable;
+    }
+}
+
+#[repr(C, align(4096))]
+#[derive(Debug)]
+pub struct DiagnosticsPage {
+    /// Layout version of this page. See [`DIAGNOSTICS_VERSION`].
+    pub version: u64,
+    /// Odd while the kernel is refreshing the page.
+    pub generation: u64,
+    /// Nanoseconds since boot at the last refresh.
+    pub refreshed_ns: u64,
+    pub retype: RetypeStats,
+    /// Number of valid entries in `phases`.
+    pub phase_count: u64,
+    pub phases: [BootPhase; MAX_BOOT_PHASES],
+    /// Total bytes ever written to the kernel log.
+    pub log_written: u64,
+    /// Number of valid bytes in `log`.
+    pub log_len: u64,
+    /// The most recent kernel log output, oldest first.
+    pub log: [u8; LOG_TAIL_SIZE],
+    /// Peak usage of the boot stack. Zero if the bootloader didn't say how
+    /// large it is.
+    pub boot_stack: StackUsage,
+    /// Length in frames of the longest run of physically contiguous untyped
+    /// memory, the most that can be handed to a device as a single buffer.
+    pub largest_untyped_run: u64,
+    pub page_faults: PageFaultStats,
+}
+
+impl Default for DiagnosticsPage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DiagnosticsPage {
+    pub const fn new() -> Self {
+        Self {
+            version: DIAGNOSTICS_VERSION,
+            generation: 0,
+            refreshed_ns: 0,
+            retype: RetypeStats {
+                untyped: 0,
+                clean: 0,
+                user: 0,
+                kernel: 0,
+                unavailable: 0,
+            },
+            phase_count: 0,
+            phases: [BootPhase {
+                name: [0; PHASE_NAME_LEN],
+                finished_ns: 0,
+            }; MAX_BOOT_PHASES],
+            log_written: 0,
+            log_len: 0,
+            log: [0; LOG_TAIL_SIZE],
+            boot_stack: StackUsage {
+                used: 0,
+                size: 0,
+                overflowed: 0,
+            },
+            largest_untyped_run: 0,
+            page_faults: PageFaultStats {
+                kernel: 0,
+                user: 0,
+                swapped: 0,
+            },
+        }
+    }
+
+    pub fn phases(&self) -> &[BootPhase] {
+        let count = usize::try_from(self.phase_count).unwrap_or(MAX_BOOT_PHASES);
+        &self.phases[..count.min(MAX_BOOT_PHASES)]
+    }
+
+    pub fn log(&self) -> &[u8] {
+        let len = usize::try_from(self.log_len).unwrap_or(LOG_TAIL_SIZE);
+        &self.log[..len.min(LOG_TAIL_SIZE)]
+    }
+}
+
+const _SIZE_OF_DIAGNOSTICS: () = {
+    assert!(core::mem::size_of::<DiagnosticsPage>() == 4096);
+};
+
+/// A kernel function or object that contains an address.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub address: u64,
+    pub size: u64,
+    /// Number of valid bytes in `name`. Longer names are truncated.
+    pub name_len: u64,
+    pub name: [u8; SYMBOL_NAME_LEN],
+}
+
+impl Default for Symbol {
+    fn default() -> Self {
+        Self {
+            address: 0,
+            size: 0,
+            name_len: 0,
+            name: [0; SYMBOL_NAME_LEN],
+        }
+    }
+}
+
+impl Symbol {
+    /// The mangled name of the symbol.
+    pub fn name(&self) -> &[u8] {
+        let len = usize::try_from(self.name_len).unwrap_or(SYMBOL_NAME_LEN);
+        &self.name[..len.min(SYMBOL_NAME_LEN)]
+    }
+}
+
+/// Distribution of the latencies measured at one stage of an interrupt.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub count: u64,
+    pub min_ns: u64,
+    pub max_ns: u64,
+    /// Sum of every sample, saturating.
+    pub total_ns: u64,
+    /// Bucket `i` counts the samples of at least `2^i` and less than
+    /// `2^(i + 1)` nanoseconds. The first bucket also counts zero and the last
+    /// everything above it.
+    pub buckets: [u64; LATENCY_BUCKETS],
+}
+
+impl Default for LatencyStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LatencyStats {
+    pub const fn new() -> Self {
+        Self {
+            count: 0,
+            min_ns: 0,
+            max_ns: 0,
+            total_ns: 0,
+            buckets: [0; LATENCY_BUCKETS],
+        }
+    }
+
+    pub fn record(&mut self, ns: u64) {
+        if self.count == 0 || ns < self.min_ns {
+            self.min_ns = ns;
+        }
+        self.max_ns = self.max_ns.max(ns);
+        self.count += 1;
+        self.total_ns = self.total_ns.saturating_add(ns);
+        let bucket = ns.checked_ilog2().unwrap_or(0) as usize;
+        self.buckets[bucket.min(LATENCY_BUCKETS - 1)] += 1;
+    }
+
+    pub fn average_ns(&self) -> Option<u64> {
+        self.total_ns.checked_div(self.count)
+    }
+
+    /// Bounds the latency that `percent` percent of the samples didn't
+    /// exceed.
+    ///
+    /// The histogram only keeps powers of two, so this is the end of the
+    /// bucket the percentile falls in, clamped to the samples seen.
+    pub fn percentile_ns(&self, percent: u8) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let wanted = (u128::from(self.count) * u128::from(percent.min(100))).div_ceil(100);
+        let mut seen = 0;
+        for (i, &count) in self.buckets.iter().enumerate() {
+            seen += u128::from(count);
+            if seen >= wanted.max(1) {
+                let end = if i == LATENCY_BUCKETS - 1 {
+                    u64::MAX
+                } else {
+                    (1 << (i + 1)) - 1
+                };
+                return Some(end.clamp(self.min_ns, self.max_ns));
+            }
+        }
+        Some(self.max_ns)
+    }
+}
+
+/// When an interrupt becomes visible to each part of the system, measured from
+/// the kernel's handler entry.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct InterruptLatency {
+    /// Until the kernel is done with the interrupt and returns or dispatches
+    /// a thread.
+    pub handoff: LatencyStats,
+    /// Until the handler of the userspace driver starts.
+    ///
+    /// Interrupts aren't delivered to userspace yet, so this stays empty.
+    pub userspace: LatencyStats,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounded_views() {
+        let mut page = DiagnosticsPage::new();
+        page.phase_count = 100;
+        page.log_len = u64::MAX;
+        assert_eq!(page.phases().len(), MAX_BOOT_PHASES);
+        assert_eq!(page.log().len(), LOG_TAIL_SIZE);
+
+        let mut phase = BootPhase::default();
+        phase.name[..4].copy_from_slice(b"boot");
+        assert_eq!(phase.name(), "boot");
+    }
+
+    #[test]
+    fn latency_percentiles() {
+        let mut stats = LatencyStats::new();
+        assert_eq!(stats.average_ns(), None);
+        assert_eq!(stats.percentile_ns(50), None);
+        for ns in [0, 100, 200, 300, 5_000] {
+            stats.record(ns);
+        }
+        assert_eq!((stats.min_ns, stats.max_ns, stats.count), (0, 5_000, 5));
+        assert_eq!(stats.average_ns(), Some(1_120));
+        assert_eq!(stats.percentile_ns(0), Some(1));
+        assert_eq!(stats.percentile_ns(40), Some(127));
+        assert_eq!(stats.percentile_ns(60), Some(255));
+        assert_eq!(stats.percentile_ns(80), Some(511));
+        assert_eq!(stats.percentile_ns(99), Some(5_000));
+
+        stats.record(u64::MAX);
+        assert_eq!(stats.buckets[LATENCY_BUCKETS - 1], 1);
+        assert_eq!(stats.total_ns, u64::MAX);
+        assert_eq!(stats.percentile_ns(100), Some(u64::MAX));
+    }
+}
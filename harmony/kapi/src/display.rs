@@ -0,0 +1,118 @@
+//! Read-only description of the display the bootloader set up.
+//!
+//! The display capability maps a [`DisplayInfo`] page followed by the pages
+//! of the console font, so that a display component draws the framebuffer
+//! the same way the kernel sees it without building its own idea of the mode
+//! or embedding a font. The font is the boot module named [`FONT_MODULE`],
+//! which the kernel hands over as is: it doesn't parse or draw with it, and
+//! it's up to the display component to understand the format, e.g. PC Screen
+//! Font. [`DisplayInfo::font_size`] is 0 if the bootloader loaded none.
+//!
+//! [`FONT_MODULE`]: crate::ops::display::FONT_MODULE
+
+/// Layout version of [`DisplayInfo`].
+pub const DISPLAY_INFO_VERSION: u64 = 1;
+
+/// The framebuffer is in text mode and its width and height are measured in
+/// characters instead of pixels.
+pub const FLAG_TEXT: u64 = 1 << 0;
+/// The channels describe how colors are encoded in the pixels. Without it,
+/// e.g. for palette-indexed modes, the display can't be drawn to directly.
+pub const FLAG_DIRECT_COLOR: u64 = 1 << 1;
+
+/// A color channel's bit field in a pixel.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ChannelInfo {
+    /// Position of the lowest bit.
+    pub shift: u8,
+    /// Number of bits.
+    pub size: u8,
+}
+
+#[repr(C, align(4096))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DisplayInfo {
+    /// Layout version of this page. See [`DISPLAY_INFO_VERSION`].
+    pub version: u64,
+    /// Bitflags of `FLAG_*` constants.
+    pub flags: u64,
+    /// Physical address of the first pixel.
+    pub address: u64,
+    pub width: u64,
+    pub height: u64,
+    /// Bytes per row, which may be more than the width of a row.
+    pub pitch: u64,
+    pub bpp: u64,
+    pub red: ChannelInfo,
+    pub green: ChannelInfo,
+    pub blue: ChannelInfo,
+    /// Size of the font in bytes, which starts at the page after this one.
+    pub font_size: u64,
+}
+
+impl Default for DisplayInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DisplayInfo {
+    pub const fn new() -> Self {
+        Self {
+            version: DISPLAY_INFO_VERSION,
+            flags: 0,
+            address: 0,
+            width: 0,
+            height: 0,
+            pitch: 0,
+            bpp: 0,
+            red: ChannelInfo { shift: 0, size: 0 },
+            green: ChannelInfo { shift: 0, size: 0 },
+            blue: ChannelInfo { shift: 0, size: 0 },
+            font_size: 0,
+        }
+    }
+
+    pub fn has_flag(&self, flag: u64) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Offset of the pixel at `x`, `y` from the start of the framebuffer.
+    ///
+    /// Returns `None` outside of the framebuffer or if pixels aren't whole
+    /// bytes.
+    pub fn offset(&self, x: u64, y: u64) -> Option<u64> {
+        if x >= self.width || y >= self.height || self.bpp % 8 != 0 {
+            return None;
+        }
+        y.checked_mul(self.pitch)?
+            .checked_add(x.checked_mul(self.bpp / 8)?)
+    }
+}
+
+const _SIZE_OF_INFO: () = {
+    assert!(core::mem::size_of::<DisplayInfo>() == 4096);
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_skip_the_padding_of_rows() {
+        let info = DisplayInfo {
+            width: 800,
+            height: 600,
+            pitch: 3328,
+            bpp: 32,
+            ..DisplayInfo::new()
+        };
+        assert_eq!(info.offset(0, 0), Some(0));
+        assert_eq!(info.offset(2, 1), Some(3336));
+        assert_eq!(info.offset(800, 0), None);
+        assert_eq!(info.offset(0, 600), None);
+        let packed = DisplayInfo { bpp: 15, ..info };
+        assert_eq!(packed.offset(0, 0), None);
+    }
+}
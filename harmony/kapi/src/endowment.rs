@@ -0,0 +1,173 @@
+//! The capabilities a component is started with.
+//!
+//! Components find the capabilities they were endowed with at fixed slots,
+//! and whoever starts them has to install the same resources at the same
+//! slots. Both sides use one [`Endowment`] list so that they can't drift
+//! apart: the kernel installs [`BOOT`] for the boot component, which can
+//! [`verify`] it through [`SystemOp::Identify`] before relying on it and fail
+//! with a message naming the slot that doesn't match.
+//!
+//! FIXME: The composer doesn't start components yet. Its configuration should
+//! produce an [`Endowment`] list per component the same way.
+
+use core::fmt;
+
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+
+use crate::ops::cap_table::BOOT_CAP_TABLE_CAP;
+use crate::ops::clock::BOOT_CLOCK_CAP;
+use crate::ops::diagnostics::BOOT_DIAGNOSTICS_CAP;
+use crate::ops::display::BOOT_DISPLAY_CAP;
+use crate::ops::initrd::BOOT_INITRD_CAP;
+use crate::ops::iommu::BOOT_IOMMU_CAP;
+use crate::ops::ipi::BOOT_IPI_CAP;
+use crate::ops::logger::BOOT_LOGGER_CAP;
+use crate::ops::perf::BOOT_PERF_CAP;
+use crate::ops::region::BOOT_REGION_CAP;
+use crate::ops::system::{SystemOp, BOOT_SYSTEM_CAP};
+use crate::ops::SyscallOp as _;
+use crate::raw::{CapError, CapId};
+
+/// What a capability holds, as returned by [`SystemOp::Identify`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum ResourceKind {
+    Empty = 0,
+    CapTable,
+    Thread,
+    PageTable,
+    Logger,
+    Ipi,
+    Region,
+    Clock,
+    Diagnostics,
+    PerfCounter,
+    Iommu,
+    DmaDomain,
+    Initrd,
+    Endpoint,
+    Hierarchy,
+    System,
+    Display,
+}
+
+/// A resource installed at a slot of a new component's table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Endowment {
+    pub cap: CapId,
+    pub kind: ResourceKind,
+}
+
+const fn endow(cap: CapId, kind: ResourceKind) -> Endowment {
+    Endowment { cap, kind }
+}
+
+/// What the kernel gives the boot component. The region covers all of
+/// physical memory.
+pub const BOOT: [Endowment; 11] = [
+    endow(BOOT_LOGGER_CAP, ResourceKind::Logger),
+    endow(BOOT_IPI_CAP, ResourceKind::Ipi),
+    endow(BOOT_REGION_CAP, ResourceKind::Region),
+    endow(BOOT_CLOCK_CAP, ResourceKind::Clock),
+    endow(BOOT_DIAGNOSTICS_CAP, ResourceKind::Diagnostics),
+    endow(BOOT_PERF_CAP, ResourceKind::PerfCounter),
+    endow(BOOT_IOMMU_CAP, ResourceKind::Iommu),
+    endow(BOOT_INITRD_CAP, ResourceKind::Initrd),
+    endow(BOOT_SYSTEM_CAP, ResourceKind::System),
+    endow(BOOT_DISPLAY_CAP, ResourceKind::Display),
+    endow(BOOT_CAP_TABLE_CAP, ResourceKind::CapTable),
+];
+
+/// Returns what `cap` holds, asking through the system capability `system`.
+pub fn identify(system: CapId, cap: CapId) -> Result<ResourceKind, CapError> {
+    // SAFETY: Identifying a capability doesn't touch any memory.
+    let kind = unsafe { SystemOp::Identify { cap }.syscall(system)? };
+    u8::try_from(kind)
+        .ok()
+        .and_then(|kind| ResourceKind::try_from(kind).ok())
+        .ok_or(CapError::Internal)
+}
+
+/// A slot that doesn't hold what it should.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub expected: Endowment,
+    /// What the slot holds, or why it couldn't be told.
+    pub found: Result<ResourceKind, CapError>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cap = u32::from(self.expected.cap);
+        let expected = self.expected.kind;
+        match self.found {
+            Ok(found) => write!(
+                f,
+                "Capability {cap} holds {found:?} instead of {expected:?}"
+            ),
+            Err(e) => write!(
+                f,
+                "Can't tell whether capability {cap} holds {expected:?}: {e:?}"
+            ),
+        }
+    }
+}
+
+/// Checks that every capability in `expected` holds what it should, through
+/// the system capability `system`.
+pub fn verify(system: CapId, expected: &[Endowment]) -> Result<(), Mismatch> {
+    for &endowment in expected {
+        let found = identify(system, endowment.cap);
+        if found != Ok(endowment.kind) {
+            return Err(Mismatch {
+                expected: endowment,
+                found,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    extern crate std;
+
+    use std::string::ToString;
+
+    use super::*;
+    use crate::testing::{MockKernel, MockResource};
+
+    #[test]
+    fn names_the_slot_that_differs() {
+        let mut kernel = MockKernel::new();
+        kernel
+            .insert(BOOT_SYSTEM_CAP, MockResource::System { seed: 0 })
+            .unwrap();
+        kernel
+            .insert(BOOT_LOGGER_CAP, MockResource::Logger)
+            .unwrap();
+        kernel.insert(BOOT_IPI_CAP, MockResource::Ipi).unwrap();
+        kernel
+            .insert(BOOT_REGION_CAP, MockResource::Clock { nanos: 0 })
+            .unwrap();
+        let _installed = kernel.install();
+
+        assert_eq!(
+            identify(BOOT_SYSTEM_CAP, BOOT_IPI_CAP),
+            Ok(ResourceKind::Ipi)
+        );
+        assert_eq!(
+            identify(BOOT_SYSTEM_CAP, CapId::new(100)),
+            Ok(ResourceKind::Empty)
+        );
+        assert_eq!(verify(BOOT_SYSTEM_CAP, &BOOT[..2]), Ok(()));
+        let mismatch = verify(BOOT_SYSTEM_CAP, &BOOT).unwrap_err();
+        assert_eq!(mismatch.found, Ok(ResourceKind::Clock));
+        assert_eq!(
+            mismatch.to_string(),
+            "Capability 2 holds Clock instead of Region"
+        );
+        let mismatch = verify(BOOT_LOGGER_CAP, &BOOT).unwrap_err();
+        assert_eq!(mismatch.found, Err(CapError::InvalidArgument));
+    }
+}
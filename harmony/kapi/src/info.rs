@@ -0,0 +1,103 @@
+//! Read-only kernel information page.
+//!
+//! The kernel maps a single page at [`INFO_PAGE_ADDRESS`] into every address
+//! space it builds. The page is updated by the kernel and allows userspace to
+//! implement time keeping and core identification without performing syscalls.
+
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// Virtual address where the kernel maps the info page.
+pub const INFO_PAGE_ADDRESS: usize = 0x0000_7FFF_FFFF_F000;
+
+/// Layout version of [`KernelInfo`].
+pub const INFO_PAGE_VERSION: u64 = 1;
+
+/// Maximum number of cores described by the info page.
+pub const MAX_CPUS: usize = 64;
+
+/// The `rdtscp` instruction is available and `IA32_TSC_AUX` holds the core index.
+pub const FEATURE_RDTSCP: u64 = 1 << 0;
+/// The TSC runs at a constant rate in every power state.
+pub const FEATURE_INVARIANT_TSC: u64 = 1 << 1;
+
+#[repr(C, align(4096))]
+#[derive(Debug)]
+pub struct KernelInfo {
+    /// Layout version of this page. See [`INFO_PAGE_VERSION`].
+    pub version: AtomicU64,
+    /// Bitflags of `FEATURE_*` constants.
+    pub features: AtomicU64,
+    /// Calibrated TSC frequency in Hz.
+    pub tsc_frequency: AtomicU64,
+    /// TSC value read when the kernel booted.
+    pub boot_tsc: AtomicU64,
+    /// Frequency of the scheduler tick in Hz.
+    pub tick_frequency: AtomicU64,
+    /// Number of scheduler ticks since boot.
+    pub ticks: AtomicU64,
+    /// Number of valid entries in `cpu_ids`.
+    pub cpu_count: AtomicU64,
+    /// Hardware (APIC) id for each core index.
+    pub cpu_ids: [AtomicU32; MAX_CPUS],
+}
+
+impl Default for KernelInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KernelInfo {
+    pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const NO_CPU: AtomicU32 = AtomicU32::new(0);
+        Self {
+            version: AtomicU64::new(INFO_PAGE_VERSION),
+            features: AtomicU64::new(0),
+            tsc_frequency: AtomicU64::new(0),
+            boot_tsc: AtomicU64::new(0),
+            tick_frequency: AtomicU64::new(0),
+            ticks: AtomicU64::new(0),
+            cpu_count: AtomicU64::new(0),
+            cpu_ids: [NO_CPU; MAX_CPUS],
+        }
+    }
+
+    pub fn has_feature(&self, feature: u64) -> bool {
+        self.features.load(Ordering::Relaxed) & feature != 0
+    }
+
+    /// Converts a TSC reading into nanoseconds since boot.
+    ///
+    /// Returns `None` if the kernel hasn't calibrated the TSC.
+    pub fn tsc_to_nanos(&self, tsc: u64) -> Option<u64> {
+        let frequency = self.tsc_frequency.load(Ordering::Relaxed);
+        if frequency == 0 {
+            return None;
+        }
+        let elapsed = tsc.saturating_sub(self.boot_tsc.load(Ordering::Relaxed));
+        let nanos = u128::from(elapsed) * 1_000_000_000 / u128::from(frequency);
+        Some(nanos.try_into().unwrap_or(u64::MAX))
+    }
+}
+
+const _SIZE_OF_INFO: () = {
+    assert!(core::mem::size_of::<KernelInfo>() == 4096);
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tsc_to_nanos() {
+        let info = KernelInfo::new();
+        assert_eq!(info.tsc_to_nanos(100), None);
+
+        info.tsc_frequency.store(2_000_000_000, Ordering::Relaxed);
+        info.boot_tsc.store(1_000, Ordering::Relaxed);
+        assert_eq!(info.tsc_to_nanos(1_000), Some(0));
+        assert_eq!(info.tsc_to_nanos(3_000), Some(1_000));
+        assert_eq!(info.tsc_to_nanos(500), Some(0));
+    }
+}
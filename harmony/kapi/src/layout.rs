@@ -0,0 +1,225 @@
+//! Standard layout of a component's address space.
+//!
+//! The lower half of every address space is split the same way so that the
+//! program, its heap, its mappings, its stack and the regions the kernel maps
+//! can't run into each other:
+//!
+//! ```text
+//! 0x0000_0000_0000  unmapped, catches null pointers
+//! 0x0000_0040_0000  code      the program's segments
+//! 0x1000_0000_0000  heap
+//! 0x2000_0000_0000  mmap      anonymous and file mappings
+//! 0x6000_0000_0000  library   the shared library (reserved)
+//! 0x6F00_0000_0000  stack     grows down from its end
+//! 0x7000_0000_0000  untyped   untyped memory windows (reserved)
+//! 0x7FFF_FFFF_D000  pages the kernel maps in every component (reserved)
+//! ```
+//!
+//! Neighbouring regions are at least [`Layout::guard`] bytes apart. The kernel
+//! refuses to load programs with segments outside of the code region.
+
+use crate::component::{LIBRARY_ADDRESS, LIBRARY_SIZE};
+use crate::diagnostics::DIAGNOSTICS_ADDRESS;
+use crate::info::INFO_PAGE_ADDRESS;
+
+/// A half-open range of virtual addresses.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub const fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub const fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+
+    /// Whether the `len` bytes at `start` are all inside the span.
+    pub fn contains(&self, start: usize, len: usize) -> bool {
+        start
+            .checked_add(len)
+            .is_some_and(|end| self.start <= start && end <= self.end)
+    }
+
+    /// Whether the `len` bytes at `start` share any address with the span.
+    pub fn overlaps(&self, start: usize, len: usize) -> bool {
+        let end = start.saturating_add(len);
+        start < self.end && self.start < end
+    }
+}
+
+/// The span the shared library may be mapped at.
+pub const LIBRARY: Span = Span::new(LIBRARY_ADDRESS, LIBRARY_ADDRESS + LIBRARY_SIZE);
+/// The pages the kernel maps into every component.
+pub const SHARED_PAGES: Span = Span::new(DIAGNOSTICS_ADDRESS, INFO_PAGE_ADDRESS + 4096);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LayoutError {
+    /// Two regions overlap or are closer than the guard gap.
+    Overlap,
+    /// A region is empty, unaligned or outside of the lower half.
+    BadRegion,
+    /// The range is in a region only the kernel places things in.
+    Reserved,
+    /// The range is outside of the region it has to be in.
+    OutsideRegion,
+}
+
+/// Where each kind of mapping goes in an address space.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Layout {
+    /// Where the program's segments may be linked.
+    pub code: Span,
+    pub heap: Span,
+    /// Where anonymous and file mappings are placed.
+    pub mmap: Span,
+    /// The initial stack, which grows down from the end.
+    pub stack: Span,
+    /// Where the kernel maps the untyped memory windows of regions.
+    pub untyped: Span,
+    /// Unmapped bytes kept between neighbouring regions.
+    pub guard: usize,
+}
+
+/// The layout every component gets unless its composer picks another one.
+pub const STANDARD: Layout = Layout {
+    code: Span::new(0x0000_0040_0000, 0x1000_0000_0000 - GUARD),
+    heap: Span::new(0x1000_0000_0000, 0x2000_0000_0000 - GUARD),
+    mmap: Span::new(0x2000_0000_0000, LIBRARY_ADDRESS - GUARD),
+    stack: Span::new(0x6F00_0000_0000, 0x7000_0000_0000 - GUARD),
+    untyped: Span::new(0x7000_0000_0000, DIAGNOSTICS_ADDRESS),
+    guard: GUARD,
+};
+
+const GUARD: usize = 1 << 20;
+/// First address past the lower half.
+pub const USER_END: usize = 0x0000_8000_0000_0000;
+const PAGE_SIZE: usize = 4096;
+
+impl Layout {
+    /// The regions in address order, along with the reserved ones.
+    fn regions(&self) -> [(Span, bool); 7] {
+        let mut regions = [
+            (self.code, false),
+            (self.heap, false),
+            (self.mmap, false),
+            (LIBRARY, true),
+            (self.stack, false),
+            (self.untyped, true),
+            (SHARED_PAGES, true),
+        ];
+        regions.sort_unstable_by_key(|(span, _)| span.start);
+        regions
+    }
+
+    /// Checks that every region is page aligned and in the lower half and
+    /// that no two of them are closer than the guard gap, including the ones
+    /// the kernel reserves.
+    pub fn validate(&self) -> Result<(), LayoutError> {
+        let regions = self.regions();
+        for (span, _) in regions {
+            let aligned = span.start % PAGE_SIZE == 0 && span.end % PAGE_SIZE == 0;
+            if span.is_empty() || !aligned || span.start == 0 || span.end > USER_END {
+                return Err(LayoutError::BadRegion);
+            }
+        }
+        for pair in regions.windows(2) {
+            let (first, second) = (pair[0].0, pair[1].0);
+            // The reserved regions are placed by the kernel and only need to
+            // be disjoint from each other.
+            let gap = if pair[0].1 && pair[1].1 {
+                0
+            } else {
+                self.guard
+            };
+            if first.end.saturating_add(gap) > second.start {
+                return Err(LayoutError::Overlap);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that a program segment of `len` bytes at `start` is inside the
+    /// code region.
+    pub fn check_code(&self, start: usize, len: usize) -> Result<(), LayoutError> {
+        if self.code.contains(start, len) {
+            return Ok(());
+        }
+        let reserved = self
+            .regions()
+            .iter()
+            .any(|&(span, reserved)| reserved && span.overlaps(start, len));
+        if reserved {
+            Err(LayoutError::Reserved)
+        } else {
+            Err(LayoutError::OutsideRegion)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_layout_is_valid() {
+        assert_eq!(STANDARD.validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_overlapping_regions() {
+        let layout = Layout {
+            stack: Span::new(0x6F00_0000_0000, 0x7000_0000_0000),
+            ..STANDARD
+        };
+        assert_eq!(layout.validate(), Err(LayoutError::Overlap));
+        let layout = Layout {
+            heap: Span::new(0x0FFF_0000_0000, 0x2000_0000_0000),
+            ..STANDARD
+        };
+        assert_eq!(layout.validate(), Err(LayoutError::Overlap));
+        let layout = Layout {
+            mmap: Span::new(0x2000_0000_0000, LIBRARY_ADDRESS + PAGE_SIZE),
+            ..STANDARD
+        };
+        assert_eq!(layout.validate(), Err(LayoutError::Overlap));
+        let layout = Layout {
+            code: Span::new(0, 0x1000),
+            ..STANDARD
+        };
+        assert_eq!(layout.validate(), Err(LayoutError::BadRegion));
+    }
+
+    #[test]
+    fn code_has_to_be_in_the_code_region() {
+        assert_eq!(STANDARD.check_code(0x40_0000, 0x3000), Ok(()));
+        assert_eq!(
+            STANDARD.check_code(0x1000, 0x1000),
+            Err(LayoutError::OutsideRegion)
+        );
+        assert_eq!(
+            STANDARD.check_code(STANDARD.code.end - 0x1000, 0x2000),
+            Err(LayoutError::OutsideRegion)
+        );
+        assert_eq!(
+            STANDARD.check_code(LIBRARY_ADDRESS, 0x1000),
+            Err(LayoutError::Reserved)
+        );
+        assert_eq!(
+            STANDARD.check_code(0x7000_0000_0000, 0x1000),
+            Err(LayoutError::Reserved)
+        );
+        assert_eq!(
+            STANDARD.check_code(usize::MAX, 2),
+            Err(LayoutError::OutsideRegion)
+        );
+    }
+}
@@ -2,5 +2,25 @@
 #![no_std]
 #![feature(naked_functions)]
 
+pub use addr;
+
+pub mod audit;
+pub mod bench;
+pub mod component;
+pub mod control;
+pub mod devices;
+pub mod diagnostics;
+pub mod display;
+pub mod endowment;
+pub mod info;
+pub mod layout;
+pub mod logfmt;
 pub mod ops;
+pub mod profile;
 pub mod raw;
+pub mod stack;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod trace;
+pub mod userspace;
+pub mod validate;
@@ -0,0 +1,335 @@
+//! Formatting of log records written to the serial port.
+//!
+//! The kernel and every component share the serial line, so each record
+//! carries the time since boot and the component and module that logged it:
+//!
+//! ```text
+//! [    1.204331] INFO  kernel/sched: Started core 1
+//! ```
+//!
+//! [`Style::Color`] additionally colors the level with ANSI escapes. For a
+//! host test runner, [`Style::Logfmt`] writes every record as one line of
+//! `key=value` pairs that [`parse`] reads back:
+//!
+//! ```text
+//! ns=1204331000 level=info component=kernel module=kernel::sched msg="Started core 1"
+//! ```
+//!
+//! The style is picked at compile time, see [`Style::parse`].
+
+use core::fmt::{self, Display, Write};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    const ALL: [Level; 5] = [
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN ",
+            Level::Info => "INFO ",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+
+    /// SGR parameters of the level's color.
+    fn color(self) -> &'static str {
+        match self {
+            Level::Error => "1;31",
+            Level::Warn => "33",
+            Level::Info => "32",
+            Level::Debug => "34",
+            Level::Trace => "35",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Style {
+    Plain,
+    /// Plain with the levels colored.
+    Color,
+    /// One `key=value` line per record, for machines.
+    Logfmt,
+}
+
+impl Style {
+    /// Parses a style name as given at compile time, e.g. through
+    /// `KERNEL_LOG_FORMAT`: `plain`, `color` or `logfmt`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "plain" => Some(Style::Plain),
+            "color" => Some(Style::Color),
+            "logfmt" => Some(Style::Logfmt),
+            _ => None,
+        }
+    }
+}
+
+/// A log record with where and when it was logged.
+#[derive(Debug, Copy, Clone)]
+pub struct Record<'a> {
+    /// Nanoseconds since boot, if the logger can tell the time.
+    pub nanos: Option<u64>,
+    pub level: Level,
+    /// The kernel or the name of the component.
+    pub component: &'a str,
+    pub module: &'a str,
+    pub args: fmt::Arguments<'a>,
+}
+
+/// Displays a record as one line, without the line break.
+pub struct Line<'a> {
+    pub style: Style,
+    pub record: &'a Record<'a>,
+}
+
+impl Display for Line<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let record = self.record;
+        if self.style == Style::Logfmt {
+            if let Some(nanos) = record.nanos {
+                write!(f, "ns={nanos} ")?;
+            }
+            write!(
+                f,
+                "level={} component={} module={} msg=\"",
+                record.level.name(),
+                Bare(record.component),
+                Bare(record.module)
+            )?;
+            write!(Escape(&mut *f), "{}", record.args)?;
+            return f.write_char('"');
+        }
+        if let Some(nanos) = record.nanos {
+            let micros = nanos / 1000;
+            write!(f, "[{:5}.{:06}] ", micros / 1_000_000, micros % 1_000_000)?;
+        }
+        match self.style {
+            Style::Color => write!(
+                f,
+                "\x1b[{}m{}\x1b[0m ",
+                record.level.color(),
+                record.level.label()
+            )?,
+            _ => write!(f, "{} ", record.level.label())?,
+        }
+        // Modules of the component's own crate are shown relative to it.
+        let module = record
+            .module
+            .strip_prefix(record.component)
+            .and_then(|module| module.strip_prefix("::"));
+        match module {
+            Some(module) => write!(f, "{}/{module}: ", record.component)?,
+            None if record.module == record.component => write!(f, "{}: ", record.component)?,
+            None => write!(f, "{}/{}: ", record.component, record.module)?,
+        }
+        write!(f, "{}", record.args)
+    }
+}
+
+/// Displays a name with anything that would end a logfmt value replaced.
+struct Bare<'a>(&'a str);
+
+impl Display for Bare<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            let bare = !(c.is_whitespace() || c.is_control() || c == '=' || c == '"');
+            f.write_char(if bare { c } else { '_' })?;
+        }
+        Ok(())
+    }
+}
+
+/// Escapes quotes, backslashes and control characters of a quoted value.
+struct Escape<W>(W);
+
+impl<W: Write> Write for Escape<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '"' => self.0.write_str("\\\"")?,
+                '\\' => self.0.write_str("\\\\")?,
+                '\n' => self.0.write_str("\\n")?,
+                '\r' => self.0.write_str("\\r")?,
+                '\t' => self.0.write_str("\\t")?,
+                c if c.is_ascii_control() => write!(self.0, "\\x{:02x}", c as u8)?,
+                c => self.0.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A record read back from a [`Style::Logfmt`] line.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Parsed<'a> {
+    pub nanos: Option<u64>,
+    pub level: Level,
+    pub component: &'a str,
+    pub module: &'a str,
+    /// The message as written, still escaped.
+    pub raw_message: &'a str,
+}
+
+impl Parsed<'_> {
+    /// Writes the message with its escapes undone.
+    pub fn write_message(&self, out: &mut impl Write) -> fmt::Result {
+        let mut chars = self.raw_message.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.write_char(c)?;
+                continue;
+            }
+            match chars.next() {
+                Some('n') => out.write_char('\n')?,
+                Some('r') => out.write_char('\r')?,
+                Some('t') => out.write_char('\t')?,
+                Some('x') => {
+                    let hex = chars.as_str().get(..2).ok_or(fmt::Error)?;
+                    let byte = u8::from_str_radix(hex, 16).map_err(|_| fmt::Error)?;
+                    out.write_char(byte.into())?;
+                    chars.nth(1);
+                }
+                Some(c) => out.write_char(c)?,
+                None => return Err(fmt::Error),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a line written in [`Style::Logfmt`].
+///
+/// Returns `None` for anything else, e.g. output that didn't go through a
+/// logger.
+pub fn parse(line: &str) -> Option<Parsed<'_>> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let (fields, message) = line.split_once(" msg=\"")?;
+    let raw_message = message.strip_suffix('"')?;
+    // The closing quote must not be escaped.
+    let escapes = raw_message.len() - raw_message.trim_end_matches('\\').len();
+    if escapes % 2 == 1 {
+        return None;
+    }
+    let (mut nanos, mut level, mut component, mut module) = (None, None, None, None);
+    for field in fields.split(' ') {
+        match field.split_once('=')? {
+            ("ns", value) => nanos = Some(value.parse().ok()?),
+            ("level", value) => {
+                level = Some(*Level::ALL.iter().find(|level| level.name() == value)?)
+            }
+            ("component", value) => component = Some(value),
+            ("module", value) => module = Some(value),
+            _ => {}
+        }
+    }
+    Some(Parsed {
+        nanos,
+        level: level?,
+        component: component?,
+        module: module?,
+        raw_message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::format;
+    use std::string::String;
+
+    use super::*;
+
+    fn line(style: Style, nanos: Option<u64>, module: &str, args: fmt::Arguments) -> String {
+        let record = Record {
+            nanos,
+            level: Level::Warn,
+            component: "kernel",
+            module,
+            args,
+        };
+        format!(
+            "{}",
+            Line {
+                style,
+                record: &record
+            }
+        )
+    }
+
+    #[test]
+    fn human_readable_lines() {
+        let plain = line(
+            Style::Plain,
+            Some(1_204_331_999),
+            "kernel::sched",
+            format_args!("core {}", 1),
+        );
+        assert_eq!(plain, "[    1.204331] WARN  kernel/sched: core 1");
+        let color = line(Style::Color, None, "kapi::ops", format_args!("hi"));
+        assert_eq!(color, "\x1b[33mWARN \x1b[0m kernel/kapi::ops: hi");
+        let root = line(Style::Plain, None, "kernel", format_args!("hi"));
+        assert_eq!(root, "WARN  kernel: hi");
+    }
+
+    #[test]
+    fn logfmt_round_trips() {
+        let message = "said \"hi\"\\\n\x1b[0m";
+        let text = line(
+            Style::Logfmt,
+            Some(42),
+            "kernel::caps",
+            format_args!("{message}"),
+        );
+        assert!(!text.contains('\n'));
+        let parsed = parse(&text).unwrap();
+        assert_eq!(parsed.nanos, Some(42));
+        assert_eq!(parsed.level, Level::Warn);
+        assert_eq!(parsed.component, "kernel");
+        assert_eq!(parsed.module, "kernel::caps");
+        let mut unescaped = String::new();
+        parsed.write_message(&mut unescaped).unwrap();
+        assert_eq!(unescaped, message);
+
+        // Messages that look like fields stay part of the message.
+        let tricky = line(Style::Logfmt, None, "m", format_args!("x msg=\"level=off"));
+        let parsed = parse(&tricky).unwrap();
+        assert_eq!(parsed.nanos, None);
+        assert_eq!(parsed.module, "m");
+    }
+
+    #[test]
+    fn rejects_other_lines() {
+        assert_eq!(parse("WARN - hello"), None);
+        assert_eq!(parse("level=loud component=a module=b msg=\"\""), None);
+        assert_eq!(parse("level=info component=a module=b msg=\"\\\""), None);
+        assert_eq!(Style::parse("json"), None);
+    }
+}
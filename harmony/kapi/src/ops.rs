@@ -23,25 +23,111 @@ pub trait SyscallOp: Sized + Copy {
 }
 
 pub mod thread {
+    use num_enum::{IntoPrimitive, TryFromPrimitive};
+
     use super::{InvalidOperation, SyscallOp};
-    use crate::raw::{RawOperation, SyscallArgs};
+    use crate::raw::{CapId, RawOperation, SyscallArgs};
+
+    /// What a thread is doing, as returned by [`ThreadOp::GetState`].
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+    #[repr(u8)]
+    pub enum ThreadState {
+        /// The thread is the active thread of some core.
+        Running = 0,
+        /// The thread can be activated.
+        Ready,
+        /// The thread is waiting in the kernel. Nothing blocks yet, so no
+        /// thread is ever reported as blocked.
+        Blocked,
+        /// The thread was suspended and gave up its core.
+        Suspended,
+        /// The thread faulted and waits for its supervisor to resume it.
+        Faulted,
+    }
 
     #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub enum ThreadOp {
         Activate,
-        ChangeAffinity,
+        /// Restricts the cores the thread may be dispatched on.
+        ///
+        /// Bit `n` of the mask allows the core with index `n` (see
+        /// [`crate::info::KernelInfo::cpu_ids`]).
+        SetAffinity {
+            mask: u64,
+        },
+        /// Returns the thread's affinity mask.
+        GetAffinity,
+        /// Adds the thread to the kernel's round-robin run queue.
+        ///
+        /// Queued threads take turns on the cores they can run on, switching
+        /// on timer ticks. Only available in kernels built with the
+        /// `round-robin` feature.
+        Schedule,
+        /// Stops the thread from being activated or scheduled until it's
+        /// resumed.
+        ///
+        /// A thread that's running keeps its core until it gives it up, or
+        /// until the next time slice with the `round-robin` scheduler, so it's
+        /// reported as running until then. Returns the [`ThreadState`] from
+        /// before the call.
+        Suspend,
+        /// Lets a suspended thread be activated and scheduled again.
+        ///
+        /// Returns the [`ThreadState`] from before the call.
+        Resume,
+        /// Returns the thread's [`ThreadState`].
+        GetState,
+        /// Gives up the core to the next thread in the round-robin run queue
+        /// that can run on it.
+        ///
+        /// Must be invoked on the calling thread. The caller is queued behind
+        /// the other threads and returns 0 once it's dispatched again. Returns
+        /// right away if no other thread is ready, or in kernels built without
+        /// the `round-robin` feature.
+        Yield,
+        /// Donates the rest of the calling thread's time slice to `thread`.
+        ///
+        /// Must be invoked on the calling thread. Unlike activating `thread`,
+        /// the time slice isn't restarted, and the caller is added to the run
+        /// queue so that it gets its core back. Fails like
+        /// [`ThreadOp::Activate`] if `thread` can't run here, and with
+        /// `ResourceInUse` if it's already running.
+        YieldTo {
+            thread: CapId,
+        },
     }
 
     impl SyscallOp for ThreadOp {
-        type R = ();
+        type R = u64;
 
         fn into_args(self) -> SyscallArgs {
             match self {
                 ThreadOp::Activate => {
                     SyscallArgs::new(RawOperation::ThreadActivate.into(), 0, 0, 0, 0)
                 }
-                ThreadOp::ChangeAffinity => {
-                    todo!();
+                ThreadOp::SetAffinity { mask } => SyscallArgs::new(
+                    RawOperation::ThreadSetAffinity.into(),
+                    mask as usize,
+                    0,
+                    0,
+                    0,
+                ),
+                ThreadOp::GetAffinity => {
+                    SyscallArgs::new(RawOperation::ThreadGetAffinity.into(), 0, 0, 0, 0)
+                }
+                ThreadOp::Schedule => {
+                    SyscallArgs::new(RawOperation::ThreadSchedule.into(), 0, 0, 0, 0)
+                }
+                ThreadOp::Suspend => {
+                    SyscallArgs::new(RawOperation::ThreadSuspend.into(), 0, 0, 0, 0)
+                }
+                ThreadOp::Resume => SyscallArgs::new(RawOperation::ThreadResume.into(), 0, 0, 0, 0),
+                ThreadOp::GetState => {
+                    SyscallArgs::new(RawOperation::ThreadGetState.into(), 0, 0, 0, 0)
+                }
+                ThreadOp::Yield => SyscallArgs::new(RawOperation::ThreadYield.into(), 0, 0, 0, 0),
+                ThreadOp::YieldTo { thread } => {
+                    SyscallArgs::new(RawOperation::ThreadYieldTo.into(), thread.into(), 0, 0, 0)
                 }
             }
         }
@@ -50,12 +136,27 @@ pub mod thread {
             let op = RawOperation::try_from(args.op()).map_err(|_| InvalidOperation::BadOp)?;
             match op {
                 RawOperation::ThreadActivate => Ok(Self::Activate),
-                RawOperation::ThreadChangeAffinity => Ok(Self::ChangeAffinity),
+                RawOperation::ThreadSetAffinity => Ok(Self::SetAffinity {
+                    mask: args.args().0 as u64,
+                }),
+                RawOperation::ThreadGetAffinity => Ok(Self::GetAffinity),
+                RawOperation::ThreadSchedule => Ok(Self::Schedule),
+                RawOperation::ThreadSuspend => Ok(Self::Suspend),
+                RawOperation::ThreadResume => Ok(Self::Resume),
+                RawOperation::ThreadGetState => Ok(Self::GetState),
+                RawOperation::ThreadYield => Ok(Self::Yield),
+                RawOperation::ThreadYieldTo => {
+                    let thread = CapId::try_from(args.args().0)
+                        .map_err(|_| InvalidOperation::InvalidArgument)?;
+                    Ok(Self::YieldTo { thread })
+                }
                 _ => Err(InvalidOperation::BadOp),
             }
         }
 
-        fn convert_success_code(&self, _code: usize) -> Self::R {}
+        fn convert_success_code(&self, code: usize) -> Self::R {
+            code as u64
+        }
     }
 }
 
@@ -65,19 +166,173 @@ pub mod cap_table {
     use super::{InvalidOperation, SyscallOp};
     use crate::raw::{CapId, RawOperation, SyscallArgs};
 
-    #[derive(Debug, Copy, Clone)]
-    #[repr(C)]
+    /// Number of slots in every node of a capability table.
+    pub const SLOT_COUNT: usize = 128;
+
+    /// Slot where the kernel places a capability to the boot component's own
+    /// table, which it constructs objects into.
+    pub const BOOT_CAP_TABLE_CAP: CapId = CapId::new(10);
+
+    /// What [`CapTableOp::Construct`] builds.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub enum ConstructArgs {
         CapTable,
+        /// A thread that starts at `entry` with its stack pointer at
+        /// `stack_pointer`.
+        ///
+        /// The page right below `stack_pointer` has to be mapped user
+        /// writable in `page_table`, or construction fails with
+        /// [`BadStack`](crate::raw::CapError::BadStack).
         Thread {
             entry: usize,
             stack_pointer: usize,
             cap_table: CapId,
             page_table: CapId,
+            /// Untyped page used as the thread's kernel execution stack.
+            kernel_stack: usize,
         },
         PageTable {
             level: u8,
         },
+        /// A DMA domain for the PCI function with the given
+        /// [requester id](super::iommu::requester_id), which needs the
+        /// [`IOMMU capability`](super::iommu::BOOT_IOMMU_CAP).
+        ///
+        /// Fails with `NotFound` if no IOMMU translates the function's DMA
+        /// and with `ResourceInUse` if it already has a domain.
+        DmaDomain {
+            iommu: CapId,
+            requester: u16,
+        },
+        /// An endpoint whose queue is kept in the untyped frames of the
+        /// `pages` pages starting at `buffer` in an untyped memory window.
+        ///
+        /// The queue holds [`MESSAGES_PER_PAGE`](super::endpoint::MESSAGES_PER_PAGE)
+        /// messages for every page, up to
+        /// [`MAX_ENDPOINT_PAGES`](super::endpoint::MAX_ENDPOINT_PAGES) pages,
+        /// so the receiver decides how much memory senders can use.
+        Endpoint {
+            buffer: usize,
+            pages: u8,
+        },
+        /// A [hierarchy](super::hierarchy) nested in the one held by `parent`,
+        /// or a new root hierarchy.
+        ///
+        /// Fails with `InvalidArgument` if the parent was killed or is
+        /// [`MAX_DEPTH`](super::hierarchy::MAX_DEPTH) levels deep, and with
+        /// `OutOfMemory` if it has no room for another child.
+        Hierarchy {
+            parent: Option<CapId>,
+        },
+    }
+
+    /// [`ConstructArgs`] as they're laid out in the caller's memory for
+    /// [`CapTableOp::Construct`].
+    ///
+    /// `kind` is the index of the variant, in the order they're declared,
+    /// and `args` holds its fields in order, with the words it doesn't use
+    /// left at 0. A hierarchy's parent takes two words: 1 if there is one,
+    /// then its id.
+    #[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+    #[repr(C)]
+    pub struct RawConstructArgs {
+        pub kind: usize,
+        pub args: [usize; 5],
+    }
+
+    impl From<ConstructArgs> for RawConstructArgs {
+        fn from(kind: ConstructArgs) -> Self {
+            let (kind, args) = match kind {
+                ConstructArgs::CapTable => (0, [0; 5]),
+                ConstructArgs::Thread {
+                    entry,
+                    stack_pointer,
+                    cap_table,
+                    page_table,
+                    kernel_stack,
+                } => (
+                    1,
+                    [
+                        entry,
+                        stack_pointer,
+                        cap_table.into(),
+                        page_table.into(),
+                        kernel_stack,
+                    ],
+                ),
+                ConstructArgs::PageTable { level } => (2, [level.into(), 0, 0, 0, 0]),
+                ConstructArgs::DmaDomain { iommu, requester } => {
+                    (3, [iommu.into(), requester.into(), 0, 0, 0])
+                }
+                ConstructArgs::Endpoint { buffer, pages } => (4, [buffer, pages.into(), 0, 0, 0]),
+                ConstructArgs::Hierarchy { parent } => (
+                    5,
+                    [
+                        parent.is_some().into(),
+                        parent.map_or(0, usize::from),
+                        0,
+                        0,
+                        0,
+                    ],
+                ),
+            };
+            Self { kind, args }
+        }
+    }
+
+    impl TryFrom<RawConstructArgs> for ConstructArgs {
+        type Error = InvalidOperation;
+
+        fn try_from(raw: RawConstructArgs) -> Result<Self, Self::Error> {
+            let cap = |value: usize| {
+                CapId::try_from(value).map_err(|_| InvalidOperation::InvalidArgument)
+            };
+            fn narrow<T: TryFrom<usize>>(value: usize) -> Result<T, InvalidOperation> {
+                T::try_from(value).map_err(|_| InvalidOperation::InvalidArgument)
+            }
+            let [a, b, c, d, e] = raw.args;
+            let (kind, used) = match raw.kind {
+                0 => (ConstructArgs::CapTable, 0),
+                1 => (
+                    ConstructArgs::Thread {
+                        entry: a,
+                        stack_pointer: b,
+                        cap_table: cap(c)?,
+                        page_table: cap(d)?,
+                        kernel_stack: e,
+                    },
+                    5,
+                ),
+                2 => (ConstructArgs::PageTable { level: narrow(a)? }, 1),
+                3 => (
+                    ConstructArgs::DmaDomain {
+                        iommu: cap(a)?,
+                        requester: narrow(b)?,
+                    },
+                    2,
+                ),
+                4 => (
+                    ConstructArgs::Endpoint {
+                        buffer: a,
+                        pages: narrow(b)?,
+                    },
+                    2,
+                ),
+                5 => {
+                    let parent = match a {
+                        0 if b == 0 => None,
+                        1 => Some(cap(b)?),
+                        _ => return Err(InvalidOperation::InvalidArgument),
+                    };
+                    (ConstructArgs::Hierarchy { parent }, 2)
+                }
+                _ => return Err(InvalidOperation::InvalidArgument),
+            };
+            if raw.args[used..].iter().any(|&word| word != 0) {
+                return Err(InvalidOperation::InvalidArgument);
+            }
+            Ok(kind)
+        }
     }
 
     #[derive(Debug, Copy, Clone)]
@@ -89,11 +344,26 @@ pub mod cap_table {
         Unlink {
             slot: SlotId<SLOT_COUNT>,
         },
+        /// Builds what `args` describes out of the untyped frame at `region`,
+        /// an address in one of the caller's
+        /// [untyped memory windows](super::region::RegionOp::Map), and puts
+        /// it in `slot`.
+        ///
+        /// The kernel reads `args` through the caller's address space when
+        /// the operation runs, so they only have to stay mapped for the call.
+        /// Fails with `InvalidArgument` if they aren't readable or don't
+        /// describe a [`ConstructArgs`].
         Construct {
-            kind: ConstructArgs,
+            args: *const RawConstructArgs,
             region: usize,
             slot: SlotId<SLOT_COUNT>,
         },
+        /// Empties `slot` and releases the resource in it.
+        ///
+        /// Tables and threads go back to untyped memory once nothing else
+        /// references them. Regions and page tables that still map something
+        /// are refused with [`crate::raw::CapError::ResourceInUse`]: transfer or clear
+        /// them first.
         Drop {
             slot: SlotId<SLOT_COUNT>,
         },
@@ -102,6 +372,17 @@ pub mod cap_table {
             other_table_cap: CapId,
             other_slot: SlotId<SLOT_COUNT>,
         },
+        /// Makes `cap` addressable from the table by linking any missing
+        /// child tables along its path.
+        ///
+        /// New tables are built in the untyped frames of the `frames` pages
+        /// starting at `region` in an untyped memory window. If they run out, the tables built so far stay
+        /// linked and the operation can be retried with more memory.
+        Extend {
+            cap: CapId,
+            region: usize,
+            frames: usize,
+        },
     }
 
     impl<const SLOT_COUNT: usize> SyscallOp for CapTableOp<SLOT_COUNT> {
@@ -122,19 +403,32 @@ pub mod cap_table {
                 CapTableOp::Unlink { slot } => {
                     SyscallArgs::new(RawOperation::CapTableUnlink.into(), slot.into(), 0, 0, 0)
                 }
-                CapTableOp::Construct {
-                    kind: _,
-                    slot: _,
-                    region: _,
-                } => {
-                    todo!()
+                CapTableOp::Construct { args, region, slot } => SyscallArgs::new(
+                    RawOperation::CapTableConstruct.into(),
+                    args as usize,
+                    region,
+                    slot.into(),
+                    0,
+                ),
+                CapTableOp::Drop { slot } => {
+                    SyscallArgs::new(RawOperation::CapTableDrop.into(), slot.into(), 0, 0, 0)
                 }
-                CapTableOp::Drop { slot: _ } => todo!(),
                 CapTableOp::Copy {
                     slot: _,
                     other_table_cap: _,
                     other_slot: _,
                 } => todo!(),
+                CapTableOp::Extend {
+                    cap,
+                    region,
+                    frames,
+                } => SyscallArgs::new(
+                    RawOperation::CapTableExtend.into(),
+                    cap.into(),
+                    region,
+                    frames,
+                    0,
+                ),
             }
         }
 
@@ -162,13 +456,2000 @@ pub mod cap_table {
                         .map_err(|_| InvalidOperation::InvalidArgument)?;
                     Ok(Self::Unlink { slot })
                 }
-                RawOperation::CapTableConstruct => todo!(),
-                RawOperation::CapTableDrop => todo!(),
-                RawOperation::CapTableCopy => todo!(),
+                RawOperation::CapTableConstruct => {
+                    let (args, region, slot, _) = args.args();
+                    let slot = slot
+                        .try_into()
+                        .map_err(|_| InvalidOperation::InvalidArgument)?;
+                    Ok(Self::Construct {
+                        args: args as *const RawConstructArgs,
+                        region,
+                        slot,
+                    })
+                }
+                // No encoding yet.
+                RawOperation::CapTableCopy => Err(InvalidOperation::BadOp),
+                RawOperation::CapTableDrop => {
+                    let slot = args
+                        .args()
+                        .0
+                        .try_into()
+                        .map_err(|_| InvalidOperation::InvalidArgument)?;
+                    Ok(Self::Drop { slot })
+                }
+                RawOperation::CapTableExtend => {
+                    let (cap, region, frames, _) = args.args();
+                    let cap =
+                        CapId::try_from(cap).map_err(|_| InvalidOperation::InvalidArgument)?;
+                    Ok(Self::Extend {
+                        cap,
+                        region,
+                        frames,
+                    })
+                }
+                _ => Err(InvalidOperation::BadOp),
+            }
+        }
+
+        fn convert_success_code(&self, _code: usize) -> Self::R {}
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn construct_args_round_trip() {
+            let cap = CapId::new(3);
+            let kinds = [
+                ConstructArgs::CapTable,
+                ConstructArgs::Thread {
+                    entry: 0x1000,
+                    stack_pointer: 0x8000,
+                    cap_table: cap,
+                    page_table: CapId::new(4),
+                    kernel_stack: 0x2000,
+                },
+                ConstructArgs::PageTable { level: 4 },
+                ConstructArgs::DmaDomain {
+                    iommu: cap,
+                    requester: 0x1219,
+                },
+                ConstructArgs::Endpoint {
+                    buffer: 0x3000,
+                    pages: 2,
+                },
+                ConstructArgs::Hierarchy { parent: None },
+                ConstructArgs::Hierarchy { parent: Some(cap) },
+            ];
+            for kind in kinds {
+                let raw = RawConstructArgs::from(kind);
+                assert_eq!(ConstructArgs::try_from(raw).ok(), Some(kind));
+            }
+
+            let bad = [
+                RawConstructArgs {
+                    kind: 6,
+                    args: [0; 5],
+                },
+                // Leftover words past the fields of the kind.
+                RawConstructArgs {
+                    kind: 0,
+                    args: [1, 0, 0, 0, 0],
+                },
+                RawConstructArgs {
+                    kind: 2,
+                    args: [0x100, 0, 0, 0, 0],
+                },
+                RawConstructArgs {
+                    kind: 5,
+                    args: [0, 3, 0, 0, 0],
+                },
+                RawConstructArgs {
+                    kind: 5,
+                    args: [2, 3, 0, 0, 0],
+                },
+            ];
+            for raw in bad {
+                assert!(ConstructArgs::try_from(raw).is_err(), "{raw:?}");
+            }
+
+            let construct = CapTableOp::<SLOT_COUNT>::Construct {
+                args: 0x5000 as *const RawConstructArgs,
+                region: 0x6000,
+                slot: 7.try_into().unwrap(),
+            };
+            let args = construct.into_args();
+            assert_eq!(args.args(), (0x5000, 0x6000, 7, 0));
+            assert!(matches!(
+                CapTableOp::<SLOT_COUNT>::from_args(args),
+                Ok(CapTableOp::Construct { args, region: 0x6000, slot })
+                    if args as usize == 0x5000 && usize::from(slot) == 7
+            ));
+        }
+    }
+}
+
+pub mod logger {
+    use num_enum::{IntoPrimitive, TryFromPrimitive};
+
+    use super::{InvalidOperation, SyscallOp};
+    use crate::raw::{CapId, RawOperation, SyscallArgs};
+
+    /// Slot where the kernel places the logger capability for the boot component.
+    pub const BOOT_LOGGER_CAP: CapId = CapId::new(0);
+
+    /// Largest number of bytes a single [`LoggerOp::Write`] takes.
+    pub const MAX_WRITE_LEN: usize = 4096;
+    /// Longest marker [`LoggerOp::InjectMarker`] takes, in bytes.
+    pub const MAX_MARKER_LEN: usize = 256;
+
+    /// The most verbose level a sink logs.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+    #[repr(u8)]
+    pub enum LevelFilter {
+        Off = 0,
+        Error,
+        Warn,
+        Info,
+        Debug,
+        Trace,
+    }
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum LoggerOp {
+        /// Changes the log filter of a kernel log sink.
+        ///
+        /// Both the sink name and the filter are UTF-8 strings in the caller's
+        /// address space.
+        SetFilter {
+            sink: *const u8,
+            sink_len: usize,
+            filter: *const u8,
+            filter_len: usize,
+        },
+        /// Writes up to [`MAX_WRITE_LEN`] bytes of UTF-8 text from the
+        /// caller's address space to the kernel's serial console as is.
+        ///
+        /// Components format their own records, e.g. with `kapi::logfmt`.
+        Write { buffer: *const u8, len: usize },
+        /// Sets the level of a kernel log sink for the modules none of its
+        /// directives match, keeping the directives.
+        SetLevel {
+            sink: *const u8,
+            sink_len: usize,
+            level: LevelFilter,
+        },
+        /// Sets the level of a kernel log sink for one module, replacing the
+        /// directive for the module if the sink has one.
+        ///
+        /// `names` holds the name of the sink followed by the module, without
+        /// a separator.
+        SetModuleLevel {
+            names: *const u8,
+            sink_len: usize,
+            module_len: usize,
+            level: LevelFilter,
+        },
+        /// Writes a line of up to [`MAX_MARKER_LEN`] bytes of UTF-8 text to
+        /// every kernel log sink, whatever their filters, to line up the
+        /// caller's events with the kernel's log and traces.
+        InjectMarker { text: *const u8, len: usize },
+    }
+
+    impl SyscallOp for LoggerOp {
+        type R = ();
+
+        fn into_args(self) -> SyscallArgs {
+            match self {
+                LoggerOp::SetFilter {
+                    sink,
+                    sink_len,
+                    filter,
+                    filter_len,
+                } => SyscallArgs::new(
+                    RawOperation::LoggerSetFilter.into(),
+                    sink as usize,
+                    sink_len,
+                    filter as usize,
+                    filter_len,
+                ),
+                LoggerOp::Write { buffer, len } => {
+                    SyscallArgs::new(RawOperation::LoggerWrite.into(), buffer as usize, len, 0, 0)
+                }
+                LoggerOp::SetLevel {
+                    sink,
+                    sink_len,
+                    level,
+                } => SyscallArgs::new(
+                    RawOperation::LoggerSetLevel.into(),
+                    sink as usize,
+                    sink_len,
+                    u8::from(level).into(),
+                    0,
+                ),
+                LoggerOp::SetModuleLevel {
+                    names,
+                    sink_len,
+                    module_len,
+                    level,
+                } => SyscallArgs::new(
+                    RawOperation::LoggerSetModuleLevel.into(),
+                    names as usize,
+                    sink_len,
+                    module_len,
+                    u8::from(level).into(),
+                ),
+                LoggerOp::InjectMarker { text, len } => SyscallArgs::new(
+                    RawOperation::LoggerInjectMarker.into(),
+                    text as usize,
+                    len,
+                    0,
+                    0,
+                ),
+            }
+        }
+
+        fn from_args(args: SyscallArgs) -> Result<Self, InvalidOperation> {
+            let op = RawOperation::try_from(args.op()).map_err(|_| InvalidOperation::BadOp)?;
+            match op {
+                RawOperation::LoggerSetFilter => {
+                    let (sink, sink_len, filter, filter_len) = args.args();
+                    Ok(Self::SetFilter {
+                        sink: sink as *const u8,
+                        sink_len,
+                        filter: filter as *const u8,
+                        filter_len,
+                    })
+                }
+                RawOperation::LoggerWrite => {
+                    let (buffer, len, ..) = args.args();
+                    Ok(Self::Write {
+                        buffer: buffer as *const u8,
+                        len,
+                    })
+                }
+                RawOperation::LoggerSetLevel => {
+                    let (sink, sink_len, level, _) = args.args();
+                    Ok(Self::SetLevel {
+                        sink: sink as *const u8,
+                        sink_len,
+                        level: level_filter(level)?,
+                    })
+                }
+                RawOperation::LoggerSetModuleLevel => {
+                    let (names, sink_len, module_len, level) = args.args();
+                    Ok(Self::SetModuleLevel {
+                        names: names as *const u8,
+                        sink_len,
+                        module_len,
+                        level: level_filter(level)?,
+                    })
+                }
+                RawOperation::LoggerInjectMarker => {
+                    let (text, len, ..) = args.args();
+                    Ok(Self::InjectMarker {
+                        text: text as *const u8,
+                        len,
+                    })
+                }
                 _ => Err(InvalidOperation::BadOp),
             }
         }
 
         fn convert_success_code(&self, _code: usize) -> Self::R {}
     }
+
+    fn level_filter(level: usize) -> Result<LevelFilter, InvalidOperation> {
+        u8::try_from(level)
+            .ok()
+            .and_then(|level| LevelFilter::try_from(level).ok())
+            .ok_or(InvalidOperation::InvalidArgument)
+    }
+}
+
+pub mod ipi {
+    use super::{InvalidOperation, SyscallOp};
+    use crate::raw::{CapId, RawOperation, SyscallArgs};
+
+    /// Slot where the kernel places the IPI capability for the boot component.
+    pub const BOOT_IPI_CAP: CapId = CapId::new(1);
+
+    /// Work item asking the target core to run its scheduler.
+    pub const WORK_RESCHEDULE: u8 = 0;
+    /// Number of distinct work items. Items other than [`WORK_RESCHEDULE`] are
+    /// free for the runtime to assign.
+    pub const MAX_WORK: u8 = 64;
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum IpiOp {
+        /// Queues `work` on `core`.
+        ///
+        /// Waits for room if the target's queue is full.
+        ///
+        /// The kernel doesn't raise inter-processor interrupts yet. It only
+        /// boots one core, so any other `core` is an invalid argument, and
+        /// work queued on a remote core would wait for that core's next
+        /// syscall instead of interrupting it.
+        Send { core: usize, work: u8 },
+        /// Returns and clears the mask of work queued on the current core.
+        TakePending,
+    }
+
+    impl SyscallOp for IpiOp {
+        type R = u64;
+
+        fn into_args(self) -> SyscallArgs {
+            match self {
+                IpiOp::Send { core, work } => {
+                    SyscallArgs::new(RawOperation::IpiSend.into(), core, work.into(), 0, 0)
+                }
+                IpiOp::TakePending => {
+                    SyscallArgs::new(RawOperation::IpiTakePending.into(), 0, 0, 0, 0)
+                }
+            }
+        }
+
+        fn from_args(args: SyscallArgs) -> Result<Self, InvalidOperation> {
+            let op = RawOperation::try_from(args.op()).map_err(|_| InvalidOperation::BadOp)?;
+            match op {
+                RawOperation::IpiSend => {
+                    let (core, work, _, _) = args.args();
+                    let work = u8::try_from(work)
+                        .ok()
+                        .filter(|work| *work < MAX_WORK)
+                        .ok_or(InvalidOperation::InvalidArgument)?;
+                    Ok(Self::Send { core, work })
+                }
+                RawOperation::IpiTakePending => Ok(Self::TakePending),
+                _ => Err(InvalidOperation::BadOp),
+            }
+        }
+
+        fn convert_success_code(&self, code: usize) -> Self::R {
+            code as u64
+        }
+    }
+}
+
+pub mod page_table {
+    use super::{InvalidOperation, SyscallOp};
+    use crate::raw::{RawOperation, SyscallArgs};
+
+    /// A leaf mapping in a page table tree.
+    #[repr(C)]
+    #[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+    pub struct MappingRecord {
+        /// Virtual address of the mapping, relative to the start of the table.
+        pub virt: u64,
+        pub phys: u64,
+        /// Raw x86_64 page table entry flags.
+        pub flags: u64,
+        /// Level of the table holding the entry. Anything above 1 is a huge page.
+        pub level: u8,
+    }
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum PageTableOp {
+        /// Writes up to `capacity` mapping records into `buffer` and returns
+        /// the total number of mappings in the tree.
+        ///
+        /// Only the lower (user) half of level 4 tables is walked. Only
+        /// available in kernels built with the `debug-ops` feature.
+        DumpMappings {
+            buffer: *mut MappingRecord,
+            capacity: usize,
+        },
+        /// Unmaps everything below the table and returns the number of pages
+        /// that were unmapped.
+        ///
+        /// Lower level tables are dropped along with their mappings. If
+        /// `release` is set, user frames and tables that are no longer
+        /// referenced anywhere are turned back into untyped memory.
+        ///
+        /// Pages pinned with [`PageTableOp::Pin`] stay mapped, along with the
+        /// tables leading to them, and the operation fails with
+        /// [`ResourceInUse`](crate::raw::CapError::ResourceInUse) once
+        /// everything else is unmapped.
+        Clear { release: bool },
+        /// Swaps out the page at `page` in the address space of a level 4
+        /// table.
+        ///
+        /// The contents of the page are copied into the page-sized `buffer`
+        /// in the caller's address space and the frame is released. The page
+        /// table entry keeps `token` so that the pager can tell which page to
+        /// bring back when it's accessed again. Tokens are limited to 40 bits.
+        ///
+        /// Fails with `InvalidArgument` if `buffer` is in the evicted frame,
+        /// e.g. inside `page` in the caller's own address space.
+        Evict {
+            page: usize,
+            token: u64,
+            buffer: *mut u8,
+        },
+        /// Pins the frame mapped at `page` in the address space of a level 4
+        /// table so that a device can access it, and returns its physical
+        /// address.
+        ///
+        /// The pin belongs to the mapping: a page can only be pinned once, and
+        /// it can't be unmapped until it's unpinned. A pinned frame can't be
+        /// retyped or evicted. Frames can be pinned a few times over, through
+        /// different pages or by DMA domains (see [`MAX_PINS`]). Only 4 KiB
+        /// user pages can be pinned.
+        Pin { page: usize },
+        /// Removes the pin added with [`PageTableOp::Pin`] through `page`.
+        ///
+        /// Fails if the page wasn't pinned, even if its frame was pinned some
+        /// other way.
+        Unpin { page: usize },
+    }
+
+    /// How many times a frame can be pinned at once.
+    pub const MAX_PINS: usize = 7;
+
+    impl SyscallOp for PageTableOp {
+        type R = usize;
+
+        fn into_args(self) -> SyscallArgs {
+            match self {
+                PageTableOp::DumpMappings { buffer, capacity } => SyscallArgs::new(
+                    RawOperation::PageTableDumpMappings.into(),
+                    buffer as usize,
+                    capacity,
+                    0,
+                    0,
+                ),
+                PageTableOp::Clear { release } => SyscallArgs::new(
+                    RawOperation::PageTableClear.into(),
+                    release as usize,
+                    0,
+                    0,
+                    0,
+                ),
+                PageTableOp::Evict {
+                    page,
+                    token,
+                    buffer,
+                } => SyscallArgs::new(
+                    RawOperation::PageTableEvict.into(),
+                    page,
+                    token as usize,
+                    buffer as usize,
+                    0,
+                ),
+                PageTableOp::Pin { page } => {
+                    SyscallArgs::new(RawOperation::PageTablePin.into(), page, 0, 0, 0)
+                }
+                PageTableOp::Unpin { page } => {
+                    SyscallArgs::new(RawOperation::PageTableUnpin.into(), page, 0, 0, 0)
+                }
+            }
+        }
+
+        fn from_args(args: SyscallArgs) -> Result<Self, InvalidOperation> {
+            let op = RawOperation::try_from(args.op()).map_err(|_| InvalidOperation::BadOp)?;
+            match op {
+                RawOperation::PageTableDumpMappings => {
+                    let (buffer, capacity, _, _) = args.args();
+                    Ok(Self::DumpMappings {
+                        buffer: buffer as *mut MappingRecord,
+                        capacity,
+                    })
+                }
+                RawOperation::PageTableClear => {
+                    let (release, _, _, _) = args.args();
+                    let release = match release {
+                        0 => false,
+                        1 => true,
+                        _ => return Err(InvalidOperation::InvalidArgument),
+                    };
+                    Ok(Self::Clear { release })
+                }
+                RawOperation::PageTableEvict => {
+                    let (page, token, buffer, _) = args.args();
+                    Ok(Self::Evict {
+                        page,
+                        token: token as u64,
+                        buffer: buffer as *mut u8,
+                    })
+                }
+                RawOperation::PageTablePin => Ok(Self::Pin {
+                    page: args.args().0,
+                }),
+                RawOperation::PageTableUnpin => Ok(Self::Unpin {
+                    page: args.args().0,
+                }),
+                _ => Err(InvalidOperation::BadOp),
+            }
+        }
+
+        fn convert_success_code(&self, code: usize) -> Self::R {
+            code
+        }
+    }
+}
+
+pub mod region {
+    use addr::{Frame, PhysAddr, PAGE_SIZE};
+
+    use super::{InvalidOperation, SyscallOp};
+    use crate::diagnostics::RetypeStats;
+    use crate::raw::{CapError, CapId, RawOperation, SyscallArgs};
+
+    /// Slot where the kernel places a region covering all physical memory for
+    /// the boot component.
+    pub const BOOT_REGION_CAP: CapId = CapId::new(2);
+
+    /// A contiguous range of physical frames owned by whoever holds the
+    /// capability.
+    ///
+    /// Frames are counted by index rather than address to keep capability
+    /// slots small, which limits regions to the first 16 TiB of physical
+    /// memory.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct Region {
+        first: u32,
+        frames: u32,
+    }
+
+    impl Region {
+        /// Returns `None` if the region is empty or extends past what can be
+        /// addressed.
+        pub fn new(base: Frame, frames: u32) -> Option<Self> {
+            let first = u32::try_from(base.addr().as_u64() / PAGE_SIZE as u64).ok()?;
+            if frames == 0 {
+                return None;
+            }
+            first.checked_add(frames)?;
+            Some(Self { first, frames })
+        }
+
+        fn frame(index: u32) -> Frame {
+            Frame::from_start_address(PhysAddr::new(u64::from(index) * PAGE_SIZE as u64))
+        }
+
+        pub fn base(&self) -> Frame {
+            Self::frame(self.first)
+        }
+
+        /// The last frame in the region.
+        pub fn last(&self) -> Frame {
+            Self::frame(self.first + (self.frames - 1))
+        }
+
+        pub fn frames(&self) -> u32 {
+            self.frames
+        }
+
+        /// Whether `frame` is between the first and the last frame of the
+        /// region, both included.
+        pub fn contains(&self, frame: Frame) -> bool {
+            let index = frame.addr().as_u64() / PAGE_SIZE as u64;
+            let last = u64::from(self.first) + u64::from(self.frames - 1);
+            u64::from(self.first) <= index && index <= last
+        }
+
+        /// Whether the regions have a frame in common.
+        pub fn overlaps(&self, other: &Region) -> bool {
+            self.contains(other.base()) || other.contains(self.base())
+        }
+
+        /// Splits off `frames` frames starting `offset` frames into the region.
+        ///
+        /// Returns what's left of the region, if anything, and the frames that
+        /// were split off. Only the start or the end of the region can be split
+        /// off so that what's left stays contiguous.
+        pub fn split_off(
+            self,
+            offset: usize,
+            frames: usize,
+        ) -> Result<(Option<Self>, Self), CapError> {
+            let total = self.frames as usize;
+            let end = offset
+                .checked_add(frames)
+                .filter(|&end| frames > 0 && end <= total)
+                .ok_or(CapError::FrameOutsideOfRegion)?;
+            // Everything fits in `u32` since it's bounded by `self.frames`.
+            let range = |start: usize, end: usize| Self {
+                first: self.first + start as u32,
+                frames: (end - start) as u32,
+            };
+            let kept = match (offset, end) {
+                (0, end) if end == total => None,
+                (0, end) => Some(range(end, total)),
+                (offset, end) if end == total => Some(range(0, offset)),
+                _ => return Err(CapError::InvalidArgument),
+            };
+            Ok((kept, range(offset, end)))
+        }
+    }
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum RegionOp {
+        /// Moves `frames` frames starting `offset` frames into the region to
+        /// a new region capability in `slot` of the table in `table`.
+        ///
+        /// The frames must be at the start or the end of the region so that
+        /// what's left stays contiguous. Transferring the whole region empties
+        /// the source capability. The destination slot must be empty and in
+        /// the first node of the table, i.e. below [`SLOT_COUNT`].
+        ///
+        /// [`SLOT_COUNT`]: super::cap_table::SLOT_COUNT
+        Transfer {
+            offset: usize,
+            frames: usize,
+            table: CapId,
+            slot: CapId,
+        },
+        /// Returns the physical address of the first frame in the region.
+        Base,
+        /// Returns the number of frames in the region.
+        Frames,
+        /// Maps the frames of the region as an untyped memory window starting
+        /// at `base`.
+        ///
+        /// Windows are only visible to the kernel. Operations that build
+        /// kernel objects out of untyped memory, like
+        /// [`CapTableOp::Construct`](super::cap_table::CapTableOp::Construct),
+        /// take addresses inside a window, and the frame behind an address is
+        /// only used while the caller still holds a region covering it.
+        ///
+        /// `base` must be page aligned and the window has to fit in the
+        /// untyped region of the [standard layout](crate::layout::STANDARD)
+        /// without overlapping another window.
+        Map { base: usize },
+        /// Writes how many of the region's frames are in each state of the
+        /// retype table to `buffer`.
+        ///
+        /// Frames past the end of the memory the kernel tracks are counted as
+        /// unavailable.
+        Stats { buffer: *mut RetypeStats },
+    }
+
+    impl SyscallOp for RegionOp {
+        type R = usize;
+
+        fn into_args(self) -> SyscallArgs {
+            match self {
+                RegionOp::Transfer {
+                    offset,
+                    frames,
+                    table,
+                    slot,
+                } => SyscallArgs::new(
+                    RawOperation::MemoryRegionTransfer.into(),
+                    offset,
+                    frames,
+                    table.into(),
+                    slot.into(),
+                ),
+                RegionOp::Base => {
+                    SyscallArgs::new(RawOperation::MemoryRegionBase.into(), 0, 0, 0, 0)
+                }
+                RegionOp::Frames => {
+                    SyscallArgs::new(RawOperation::MemoryRegionFrames.into(), 0, 0, 0, 0)
+                }
+                RegionOp::Map { base } => {
+                    SyscallArgs::new(RawOperation::MemoryRegionMap.into(), base, 0, 0, 0)
+                }
+                RegionOp::Stats { buffer } => SyscallArgs::new(
+                    RawOperation::MemoryRegionStats.into(),
+                    buffer as usize,
+                    0,
+                    0,
+                    0,
+                ),
+            }
+        }
+
+        fn from_args(args: SyscallArgs) -> Result<Self, InvalidOperation> {
+            let op = RawOperation::try_from(args.op()).map_err(|_| InvalidOperation::BadOp)?;
+            match op {
+                RawOperation::MemoryRegionTransfer => {
+                    let (offset, frames, table, slot) = args.args();
+                    let table =
+                        CapId::try_from(table).map_err(|_| InvalidOperation::InvalidArgument)?;
+                    let slot =
+                        CapId::try_from(slot).map_err(|_| InvalidOperation::InvalidArgument)?;
+                    Ok(Self::Transfer {
+                        offset,
+                        frames,
+                        table,
+                        slot,
+                    })
+                }
+                RawOperation::MemoryRegionBase => Ok(Self::Base),
+                RawOperation::MemoryRegionFrames => Ok(Self::Frames),
+                RawOperation::MemoryRegionMap => {
+                    let (base, _, _, _) = args.args();
+                    Ok(Self::Map { base })
+                }
+                RawOperation::MemoryRegionStats => Ok(Self::Stats {
+                    buffer: args.args().0 as *mut RetypeStats,
+                }),
+                _ => Err(InvalidOperation::BadOp),
+            }
+        }
+
+        fn convert_success_code(&self, code: usize) -> Self::R {
+            code
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        extern crate std;
+
+        use std::format;
+        use std::vec::Vec;
+
+        use proptest::prelude::*;
+
+        use super::*;
+
+        fn region(first: u32, frames: u32) -> Region {
+            Region::new(Region::frame(first), frames).unwrap()
+        }
+
+        #[test]
+        fn splits_regions_at_either_end() {
+            assert_eq!(
+                region(16, 8).split_off(0, 3),
+                Ok((Some(region(19, 5)), region(16, 3)))
+            );
+            assert_eq!(
+                region(16, 8).split_off(6, 2),
+                Ok((Some(region(16, 6)), region(22, 2)))
+            );
+            let region = region(16, 8);
+            assert_eq!(region.split_off(0, 8), Ok((None, region)));
+            assert_eq!(region.split_off(2, 2), Err(CapError::InvalidArgument));
+            assert_eq!(region.split_off(6, 3), Err(CapError::FrameOutsideOfRegion));
+            assert_eq!(region.split_off(0, 0), Err(CapError::FrameOutsideOfRegion));
+            assert!(Region::new(Region::frame(u32::MAX), 2).is_none());
+            assert!(Region::new(Region::frame(0), 0).is_none());
+        }
+
+        #[test]
+        fn bounds_are_inclusive() {
+            let bounds = region(16, 8);
+            assert_eq!(bounds.last(), Region::frame(23));
+            assert!(!bounds.contains(Region::frame(15)));
+            assert!(bounds.contains(Region::frame(16)));
+            assert!(bounds.contains(Region::frame(23)));
+            assert!(!bounds.contains(Region::frame(24)));
+            assert!(bounds.overlaps(&region(23, 1)));
+            assert!(!bounds.overlaps(&region(24, 4)));
+            assert!(!bounds.overlaps(&region(12, 4)));
+
+            let end = region(u32::MAX - 1, 1);
+            assert!(end.contains(Region::frame(u32::MAX - 1)));
+            assert!(!end.contains(Region::frame(u32::MAX)));
+        }
+
+        /// A split of a random leaf of the tree.
+        #[derive(Debug, Clone)]
+        struct Split {
+            leaf: usize,
+            from_end: bool,
+            frames: usize,
+        }
+
+        fn splits() -> impl Strategy<Value = Vec<Split>> {
+            prop::collection::vec(
+                (any::<usize>(), any::<bool>(), 1..64usize).prop_map(|(leaf, from_end, frames)| {
+                    Split {
+                        leaf,
+                        from_end,
+                        frames,
+                    }
+                }),
+                0..64,
+            )
+        }
+
+        proptest! {
+            /// Splits regions like a tree of components handing memory down
+            /// to their children and checks that the children of every split
+            /// partition their parent.
+            #[test]
+            fn sibling_regions_never_overlap(
+                first in 0..u32::MAX / 2,
+                frames in 1..4096u32,
+                splits in splits(),
+            ) {
+                let root = Region::new(Region::frame(first), frames).unwrap();
+                let mut leaves = Vec::from([root]);
+                for split in splits {
+                    let index = split.leaf % leaves.len();
+                    let parent = leaves[index];
+                    let total = parent.frames() as usize;
+                    let frames = split.frames.min(total);
+                    let offset = if split.from_end { total - frames } else { 0 };
+                    let (kept, taken) = parent.split_off(offset, frames).unwrap();
+
+                    prop_assert_eq!(taken.frames() as usize, frames);
+                    prop_assert!(parent.contains(taken.base()) && parent.contains(taken.last()));
+                    match kept {
+                        Some(kept) => {
+                            prop_assert!(!kept.overlaps(&taken));
+                            prop_assert!(parent.contains(kept.base()) && parent.contains(kept.last()));
+                            prop_assert_eq!(kept.frames() + taken.frames(), parent.frames());
+                            leaves[index] = kept;
+                            leaves.push(taken);
+                        }
+                        None => {
+                            prop_assert_eq!(taken, parent);
+                            leaves[index] = taken;
+                        }
+                    }
+                }
+                for (i, a) in leaves.iter().enumerate() {
+                    for b in &leaves[i + 1..] {
+                        prop_assert!(!a.overlaps(b), "{:?} overlaps {:?}", a, b);
+                    }
+                }
+                let covered: u32 = leaves.iter().map(Region::frames).sum();
+                prop_assert_eq!(covered, root.frames());
+            }
+        }
+    }
+}
+
+pub mod clock {
+    use super::{InvalidOperation, SyscallOp};
+    use crate::raw::{CapId, RawOperation, SyscallArgs};
+
+    /// Slot where the kernel places the clock capability for the boot component.
+    pub const BOOT_CLOCK_CAP: CapId = CapId::new(3);
+
+    /// What a component needs to turn TSC readings into time.
+    #[repr(C)]
+    #[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+    pub struct Calibration {
+        /// TSC frequency in Hz, or 0 if the TSC wasn't calibrated.
+        pub tsc_frequency: u64,
+        /// TSC value read when the kernel booted. Time is measured from here.
+        pub boot_tsc: u64,
+        /// Whether the TSC ticks at a constant rate, regardless of power
+        /// states, and can be used as a clock.
+        pub stable_tsc: bool,
+    }
+
+    impl Calibration {
+        /// Converts a TSC reading into nanoseconds since boot.
+        ///
+        /// Returns `None` if the TSC can't be used as a clock.
+        pub fn tsc_to_nanos(&self, tsc: u64) -> Option<u64> {
+            if !self.stable_tsc || self.tsc_frequency == 0 {
+                return None;
+            }
+            let elapsed = tsc.saturating_sub(self.boot_tsc);
+            let nanos = u128::from(elapsed) * 1_000_000_000 / u128::from(self.tsc_frequency);
+            Some(nanos.try_into().unwrap_or(u64::MAX))
+        }
+    }
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum ClockOp {
+        /// Writes the TSC calibration into `buffer`, so that time can be read
+        /// with `rdtsc` without performing syscalls.
+        GetCalibration { buffer: *mut Calibration },
+        /// Returns the nanoseconds elapsed since boot.
+        ///
+        /// Slower than reading the TSC but works on CPUs where the TSC isn't
+        /// stable.
+        GetTimeNs,
+    }
+
+    impl SyscallOp for ClockOp {
+        type R = u64;
+
+        fn into_args(self) -> SyscallArgs {
+            match self {
+                ClockOp::GetCalibration { buffer } => SyscallArgs::new(
+                    RawOperation::ClockGetCalibration.into(),
+                    buffer as usize,
+                    0,
+                    0,
+                    0,
+                ),
+                ClockOp::GetTimeNs => {
+                    SyscallArgs::new(RawOperation::ClockGetTimeNs.into(), 0, 0, 0, 0)
+                }
+            }
+        }
+
+        fn from_args(args: SyscallArgs) -> Result<Self, InvalidOperation> {
+            let op = RawOperation::try_from(args.op()).map_err(|_| InvalidOperation::BadOp)?;
+            match op {
+                RawOperation::ClockGetCalibration => Ok(Self::GetCalibration {
+                    buffer: args.args().0 as *mut Calibration,
+                }),
+                RawOperation::ClockGetTimeNs => Ok(Self::GetTimeNs),
+                _ => Err(InvalidOperation::BadOp),
+            }
+        }
+
+        fn convert_success_code(&self, code: usize) -> Self::R {
+            code as u64
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn tsc_to_nanos() {
+            let mut calibration = Calibration {
+                tsc_frequency: 2_000_000_000,
+                boot_tsc: 1_000,
+                stable_tsc: false,
+            };
+            assert_eq!(calibration.tsc_to_nanos(3_000), None);
+            calibration.stable_tsc = true;
+            assert_eq!(calibration.tsc_to_nanos(3_000), Some(1_000));
+            assert_eq!(calibration.tsc_to_nanos(500), Some(0));
+        }
+    }
+}
+
+pub mod diagnostics {
+    use super::{InvalidOperation, SyscallOp};
+    use crate::audit::AuditRecord;
+    use crate::diagnostics::{InterruptLatency, Symbol};
+    use crate::profile::StackSample;
+    use crate::raw::{CapId, RawOperation, SyscallArgs};
+    use crate::trace::TraceRecord;
+
+    /// Slot where the kernel places the diagnostics capability for the boot
+    /// component.
+    pub const BOOT_DIAGNOSTICS_CAP: CapId = CapId::new(4);
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum DiagnosticsOp {
+        /// Maps the [`crate::diagnostics::DiagnosticsPage`] read-only at
+        /// entry `index` of the level 1 page table in `table`.
+        ///
+        /// The entry must be empty.
+        Map { table: CapId, index: usize },
+        /// Updates the diagnostics page with the current kernel state and
+        /// returns its new generation.
+        Refresh,
+        /// Finds the kernel symbol containing `address` and writes it into
+        /// `buffer`.
+        ///
+        /// Fails with `NotFound` if the address isn't in any symbol or the
+        /// bootloader didn't provide the kernel image.
+        Symbolize { address: u64, buffer: *mut Symbol },
+        /// Moves up to `len` of the oldest events traced on `core` into
+        /// `buffer` and returns how many were moved.
+        ///
+        /// Nothing is traced unless the kernel was built with the
+        /// `trace-events` feature.
+        DrainTrace {
+            core: usize,
+            buffer: *mut TraceRecord,
+            len: usize,
+        },
+        /// Writes the latencies measured for interrupt `vector` into
+        /// `buffer`.
+        ///
+        /// Fails with `InvalidArgument` if `vector` isn't a hardware interrupt.
+        InterruptLatency {
+            vector: u8,
+            buffer: *mut InterruptLatency,
+        },
+        /// Samples the call stack of every core once every `every` timer
+        /// ticks, or stops sampling if it's 0.
+        ///
+        /// Stacks sampled before are kept until they're drained.
+        Profile { every: u32 },
+        /// Moves up to `len` of the stacks sampled on `core` into `buffer`
+        /// and returns how many were moved.
+        DrainProfile {
+            core: usize,
+            buffer: *mut StackSample,
+            len: usize,
+        },
+        /// Moves up to `len` of the oldest records in the audit log of `core`
+        /// into `buffer` and returns how many were moved.
+        DrainAudit {
+            core: usize,
+            buffer: *mut AuditRecord,
+            len: usize,
+        },
+    }
+
+    impl SyscallOp for DiagnosticsOp {
+        type R = u64;
+
+        fn into_args(self) -> SyscallArgs {
+            match self {
+                DiagnosticsOp::Map { table, index } => SyscallArgs::new(
+                    RawOperation::DiagnosticsMap.into(),
+                    table.into(),
+                    index,
+                    0,
+                    0,
+                ),
+                DiagnosticsOp::Refresh => {
+                    SyscallArgs::new(RawOperation::DiagnosticsRefresh.into(), 0, 0, 0, 0)
+                }
+                DiagnosticsOp::Symbolize { address, buffer } => SyscallArgs::new(
+                    RawOperation::DiagnosticsSymbolize.into(),
+                    address as usize,
+                    buffer as usize,
+                    0,
+                    0,
+                ),
+                DiagnosticsOp::DrainTrace { core, buffer, len } => SyscallArgs::new(
+                    RawOperation::DiagnosticsDrainTrace.into(),
+                    core,
+                    buffer as usize,
+                    len,
+                    0,
+                ),
+                DiagnosticsOp::InterruptLatency { vector, buffer } => SyscallArgs::new(
+                    RawOperation::DiagnosticsInterruptLatency.into(),
+                    vector.into(),
+                    buffer as usize,
+                    0,
+                    0,
+                ),
+                DiagnosticsOp::Profile { every } => SyscallArgs::new(
+                    RawOperation::DiagnosticsProfile.into(),
+                    every as usize,
+                    0,
+                    0,
+                    0,
+                ),
+                DiagnosticsOp::DrainProfile { core, buffer, len } => SyscallArgs::new(
+                    RawOperation::DiagnosticsDrainProfile.into(),
+                    core,
+                    buffer as usize,
+                    len,
+                    0,
+                ),
+                DiagnosticsOp::DrainAudit { core, buffer, len } => SyscallArgs::new(
+                    RawOperation::DiagnosticsDrainAudit.into(),
+                    core,
+                    buffer as usize,
+                    len,
+                    0,
+                ),
+            }
+        }
+
+        fn from_args(args: SyscallArgs) -> Result<Self, InvalidOperation> {
+            let op = RawOperation::try_from(args.op()).map_err(|_| InvalidOperation::BadOp)?;
+            match op {
+                RawOperation::DiagnosticsMap => {
+                    let (table, index, _, _) = args.args();
+                    let table =
+                        CapId::try_from(table).map_err(|_| InvalidOperation::InvalidArgument)?;
+                    Ok(Self::Map { table, index })
+                }
+                RawOperation::DiagnosticsRefresh => Ok(Self::Refresh),
+                RawOperation::DiagnosticsSymbolize => {
+                    let (address, buffer, _, _) = args.args();
+                    Ok(Self::Symbolize {
+                        address: address as u64,
+                        buffer: buffer as *mut Symbol,
+                    })
+                }
+                RawOperation::DiagnosticsDrainTrace => {
+                    let (core, buffer, len, _) = args.args();
+                    Ok(Self::DrainTrace {
+                        core,
+                        buffer: buffer as *mut TraceRecord,
+                        len,
+                    })
+                }
+                RawOperation::DiagnosticsInterruptLatency => {
+                    let (vector, buffer, _, _) = args.args();
+                    Ok(Self::InterruptLatency {
+                        vector: vector
+                            .try_into()
+                            .map_err(|_| InvalidOperation::InvalidArgument)?,
+                        buffer: buffer as *mut InterruptLatency,
+                    })
+                }
+                RawOperation::DiagnosticsProfile => {
+                    let (every, _, _, _) = args.args();
+                    Ok(Self::Profile {
+                        every: every
+                            .try_into()
+                            .map_err(|_| InvalidOperation::InvalidArgument)?,
+                    })
+                }
+                RawOperation::DiagnosticsDrainProfile => {
+                    let (core, buffer, len, _) = args.args();
+                    Ok(Self::DrainProfile {
+                        core,
+                        buffer: buffer as *mut StackSample,
+                        len,
+                    })
+                }
+                RawOperation::DiagnosticsDrainAudit => {
+                    let (core, buffer, len, _) = args.args();
+                    Ok(Self::DrainAudit {
+                        core,
+                        buffer: buffer as *mut AuditRecord,
+                        len,
+                    })
+                }
+                _ => Err(InvalidOperation::BadOp),
+            }
+        }
+
+        fn convert_success_code(&self, code: usize) -> Self::R {
+            code as u64
+        }
+    }
+}
+
+pub mod perf {
+    use num_enum::{IntoPrimitive, TryFromPrimitive};
+
+    use super::{InvalidOperation, SyscallOp};
+    use crate::raw::{CapId, RawOperation, SyscallArgs};
+
+    /// Slot where the kernel places the performance counter capability for the
+    /// boot component.
+    pub const BOOT_PERF_CAP: CapId = CapId::new(5);
+
+    /// Largest sampling period a counter can be programmed with.
+    pub const MAX_PERIOD: u32 = i32::MAX as u32;
+
+    /// Events a counter can count.
+    ///
+    /// These are the architectural events, so they count the same thing on
+    /// every CPU that supports them.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+    #[repr(u8)]
+    pub enum PerfEvent {
+        Cycles = 0,
+        Instructions,
+        /// References to the last level cache.
+        CacheReferences,
+        /// Misses in the last level cache.
+        CacheMisses,
+        /// Retired branch instructions.
+        Branches,
+        /// Mispredicted branch instructions.
+        BranchMisses,
+    }
+
+    /// Which privilege levels an event is counted at.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+    #[repr(u8)]
+    pub enum PerfMode {
+        User = 1,
+        Kernel,
+        All,
+    }
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum PerfOp {
+        /// Returns the number of counters the capability can program, or 0
+        /// if the CPU doesn't have any.
+        Counters,
+        /// Resets `counter` and starts counting `event` in `mode`.
+        ///
+        /// With a `period` the counter overflows after that many events
+        /// instead of starting from 0. Fails with `NotFound` if the CPU can't
+        /// count the event.
+        Program {
+            counter: u8,
+            event: PerfEvent,
+            mode: PerfMode,
+            period: Option<u32>,
+        },
+        /// Returns the value of `counter`.
+        Read { counter: u8 },
+        /// Stops `counter`, leaving its value.
+        Stop { counter: u8 },
+        /// Returns and clears the mask of the counters that overflowed.
+        TakeOverflows,
+    }
+
+    impl SyscallOp for PerfOp {
+        type R = u64;
+
+        fn into_args(self) -> SyscallArgs {
+            match self {
+                PerfOp::Counters => SyscallArgs::new(RawOperation::PerfCounters.into(), 0, 0, 0, 0),
+                PerfOp::Program {
+                    counter,
+                    event,
+                    mode,
+                    period,
+                } => SyscallArgs::new(
+                    RawOperation::PerfProgram.into(),
+                    counter.into(),
+                    u8::from(event).into(),
+                    u8::from(mode).into(),
+                    period.map_or(0, |period| period as usize),
+                ),
+                PerfOp::Read { counter } => {
+                    SyscallArgs::new(RawOperation::PerfRead.into(), counter.into(), 0, 0, 0)
+                }
+                PerfOp::Stop { counter } => {
+                    SyscallArgs::new(RawOperation::PerfStop.into(), counter.into(), 0, 0, 0)
+                }
+                PerfOp::TakeOverflows => {
+                    SyscallArgs::new(RawOperation::PerfTakeOverflows.into(), 0, 0, 0, 0)
+                }
+            }
+        }
+
+        fn from_args(args: SyscallArgs) -> Result<Self, InvalidOperation> {
+            let op = RawOperation::try_from(args.op()).map_err(|_| InvalidOperation::BadOp)?;
+            let (counter, event, mode, period) = args.args();
+            let counter = || u8::try_from(counter).map_err(|_| InvalidOperation::InvalidArgument);
+            match op {
+                RawOperation::PerfCounters => Ok(Self::Counters),
+                RawOperation::PerfProgram => {
+                    let event = u8::try_from(event)
+                        .ok()
+                        .and_then(|event| PerfEvent::try_from(event).ok())
+                        .ok_or(InvalidOperation::InvalidArgument)?;
+                    let mode = u8::try_from(mode)
+                        .ok()
+                        .and_then(|mode| PerfMode::try_from(mode).ok())
+                        .ok_or(InvalidOperation::InvalidArgument)?;
+                    let period = match u32::try_from(period) {
+                        Ok(0) => None,
+                        Ok(period) if period <= MAX_PERIOD => Some(period),
+                        _ => return Err(InvalidOperation::InvalidArgument),
+                    };
+                    Ok(Self::Program {
+                        counter: counter()?,
+                        event,
+                        mode,
+                        period,
+                    })
+                }
+                RawOperation::PerfRead => Ok(Self::Read {
+                    counter: counter()?,
+                }),
+                RawOperation::PerfStop => Ok(Self::Stop {
+                    counter: counter()?,
+                }),
+                RawOperation::PerfTakeOverflows => Ok(Self::TakeOverflows),
+                _ => Err(InvalidOperation::BadOp),
+            }
+        }
+
+        fn convert_success_code(&self, code: usize) -> Self::R {
+            code as u64
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn programs_round_trip() {
+            let op = PerfOp::Program {
+                counter: 1,
+                event: PerfEvent::CacheMisses,
+                mode: PerfMode::User,
+                period: Some(10_000),
+            };
+            assert_eq!(PerfOp::from_args(op.into_args()).ok(), Some(op));
+
+            let unsampled = PerfOp::Program {
+                counter: 0,
+                event: PerfEvent::Cycles,
+                mode: PerfMode::All,
+                period: None,
+            };
+            assert_eq!(
+                PerfOp::from_args(unsampled.into_args()).ok(),
+                Some(unsampled)
+            );
+
+            let too_long = SyscallArgs::new(
+                RawOperation::PerfProgram.into(),
+                1,
+                0,
+                1,
+                MAX_PERIOD as usize + 1,
+            );
+            assert!(PerfOp::from_args(too_long).is_err());
+            let no_mode = SyscallArgs::new(RawOperation::PerfProgram.into(), 1, 0, 0, 0);
+            assert!(PerfOp::from_args(no_mode).is_err());
+        }
+    }
+}
+
+pub mod iommu {
+    use super::{InvalidOperation, SyscallOp};
+    use crate::raw::{CapId, RawOperation, SyscallArgs};
+
+    /// Slot where the kernel places the IOMMU capability for the boot
+    /// component.
+    pub const BOOT_IOMMU_CAP: CapId = CapId::new(6);
+
+    /// The id a PCI function tags its DMA requests with.
+    pub const fn requester_id(bus: u8, device: u8, function: u8) -> u16 {
+        (bus as u16) << 8 | ((device & 0x1F) as u16) << 3 | (function & 0x7) as u16
+    }
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum IommuOp {
+        /// Returns the number of IOMMUs the kernel enabled, or 0 if DMA isn't
+        /// isolated.
+        Units,
+    }
+
+    impl SyscallOp for IommuOp {
+        type R = usize;
+
+        fn into_args(self) -> SyscallArgs {
+            match self {
+                IommuOp::Units => SyscallArgs::new(RawOperation::IommuUnits.into(), 0, 0, 0, 0),
+            }
+        }
+
+        fn from_args(args: SyscallArgs) -> Result<Self, InvalidOperation> {
+            match RawOperation::try_from(args.op()).map_err(|_| InvalidOperation::BadOp)? {
+                RawOperation::IommuUnits => Ok(Self::Units),
+                _ => Err(InvalidOperation::BadOp),
+            }
+        }
+
+        fn convert_success_code(&self, code: usize) -> Self::R {
+            code
+        }
+    }
+
+    /// Operations on the DMA domain of a device.
+    ///
+    /// A device can only reach the frames granted to its domain, at the I/O
+    /// virtual addresses (IOVAs) they were granted at. Everything else it
+    /// tries to access is blocked by the IOMMU.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum DmaDomainOp {
+        /// Builds the tables needed to grant a frame at `iova` and returns
+        /// how many were built.
+        ///
+        /// Tables are built in the untyped frames of the `frames` pages
+        /// starting at `region` in an untyped memory window. If they run out,
+        /// the tables built so far stay and the operation can be retried with
+        /// more memory.
+        Extend {
+            iova: usize,
+            region: usize,
+            frames: usize,
+        },
+        /// Lets the device access the user frame mapped at `page` in the
+        /// caller's address space at `iova`.
+        ///
+        /// The frame must be in one of the caller's memory regions. It stays
+        /// [pinned](super::page_table::PageTableOp::Pin) until it's revoked.
+        /// Fails with `OutOfMemory` if the domain needs more tables for
+        /// `iova`.
+        Grant {
+            page: usize,
+            iova: usize,
+            writable: bool,
+        },
+        /// Takes the frame granted at `iova` away from the device.
+        Revoke { iova: usize },
+    }
+
+    impl SyscallOp for DmaDomainOp {
+        type R = usize;
+
+        fn into_args(self) -> SyscallArgs {
+            match self {
+                DmaDomainOp::Extend {
+                    iova,
+                    region,
+                    frames,
+                } => SyscallArgs::new(
+                    RawOperation::DmaDomainExtend.into(),
+                    iova,
+                    region,
+                    frames,
+                    0,
+                ),
+                DmaDomainOp::Grant {
+                    page,
+                    iova,
+                    writable,
+                } => SyscallArgs::new(
+                    RawOperation::DmaDomainGrant.into(),
+                    page,
+                    iova,
+                    writable as usize,
+                    0,
+                ),
+                DmaDomainOp::Revoke { iova } => {
+                    SyscallArgs::new(RawOperation::DmaDomainRevoke.into(), iova, 0, 0, 0)
+                }
+            }
+        }
+
+        fn from_args(args: SyscallArgs) -> Result<Self, InvalidOperation> {
+            let op = RawOperation::try_from(args.op()).map_err(|_| InvalidOperation::BadOp)?;
+            let (a, b, c, _) = args.args();
+            match op {
+                RawOperation::DmaDomainExtend => Ok(Self::Extend {
+                    iova: a,
+                    region: b,
+                    frames: c,
+                }),
+                RawOperation::DmaDomainGrant => Ok(Self::Grant {
+                    page: a,
+                    iova: b,
+                    writable: match c {
+                        0 => false,
+                        1 => true,
+                        _ => return Err(InvalidOperation::InvalidArgument),
+                    },
+                }),
+                RawOperation::DmaDomainRevoke => Ok(Self::Revoke { iova: a }),
+                _ => Err(InvalidOperation::BadOp),
+            }
+        }
+
+        fn convert_success_code(&self, code: usize) -> Self::R {
+            code
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn grants_round_trip() {
+            assert_eq!(requester_id(0x12, 3, 1), 0x1219);
+            let grant = DmaDomainOp::Grant {
+                page: 0x1000,
+                iova: 0x20_0000,
+                writable: true,
+            };
+            assert_eq!(DmaDomainOp::from_args(grant.into_args()).ok(), Some(grant));
+            let bad = SyscallArgs::new(RawOperation::DmaDomainGrant.into(), 0x1000, 0, 2, 0);
+            assert!(DmaDomainOp::from_args(bad).is_err());
+        }
+    }
+}
+
+pub mod initrd {
+    use super::{InvalidOperation, SyscallOp};
+    use crate::raw::{CapId, RawOperation, SyscallArgs};
+
+    /// Slot where the kernel places the initrd capability for the boot
+    /// component.
+    pub const BOOT_INITRD_CAP: CapId = CapId::new(7);
+
+    /// Name of the boot module holding the initrd archive.
+    pub const INITRD_MODULE: &[u8] = b"initrd";
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum InitrdOp {
+        /// Returns the size of the archive in bytes.
+        ///
+        /// Fails with `NotFound` if the kernel wasn't booted with an initrd.
+        Size,
+        /// Maps the archive read-only into the level 1 page table `table`,
+        /// starting with page `first` of the archive at entry 0, and returns
+        /// the number of pages mapped.
+        ///
+        /// Pages past the end of the table or the archive aren't mapped, so
+        /// the archive is mapped with one table for every 2 MiB. Nothing is
+        /// mapped if one of the entries is in use. The rest of the last page
+        /// holds whatever the bootloader left after the archive.
+        Map { table: CapId, first: usize },
+    }
+
+    impl SyscallOp for InitrdOp {
+        type R = usize;
+
+        fn into_args(self) -> SyscallArgs {
+            match self {
+                InitrdOp::Size => SyscallArgs::new(RawOperation::InitrdSize.into(), 0, 0, 0, 0),
+                InitrdOp::Map { table, first } => {
+                    SyscallArgs::new(RawOperation::InitrdMap.into(), table.into(), first, 0, 0)
+                }
+            }
+        }
+
+        fn from_args(args: SyscallArgs) -> Result<Self, InvalidOperation> {
+            match RawOperation::try_from(args.op()).map_err(|_| InvalidOperation::BadOp)? {
+                RawOperation::InitrdSize => Ok(Self::Size),
+                RawOperation::InitrdMap => {
+                    let (table, first, ..) = args.args();
+                    Ok(Self::Map {
+                        table: CapId::try_from(table)
+                            .map_err(|_| InvalidOperation::InvalidArgument)?,
+                        first,
+                    })
+                }
+                _ => Err(InvalidOperation::BadOp),
+            }
+        }
+
+        fn convert_success_code(&self, code: usize) -> Self::R {
+            code
+        }
+    }
+}
+
+pub mod endpoint {
+    use num_enum::{IntoPrimitive, TryFromPrimitive};
+
+    use super::{InvalidOperation, SyscallOp};
+    use crate::raw::{RawOperation, SyscallArgs};
+
+    /// A message sent through an endpoint.
+    pub type Message = [usize; 4];
+
+    /// Number of messages held by every page of an endpoint's queue.
+    pub const MESSAGES_PER_PAGE: usize = 4096 / core::mem::size_of::<Message>();
+
+    /// Largest number of pages an endpoint's queue can be built with.
+    pub const MAX_ENDPOINT_PAGES: usize = 16;
+
+    /// Watermark crossed by a [`EndpointOp::Send`] or [`EndpointOp::Receive`].
+    ///
+    /// Nothing notifies producers yet, so the crossing is reported to the
+    /// thread whose operation crossed it. Each is only reported once until
+    /// the other one is crossed.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+    #[repr(u8)]
+    pub enum Watermark {
+        None = 0,
+        /// The queue filled up to the high watermark. Producers should slow
+        /// down.
+        High,
+        /// The queue drained down to the low watermark after having reached
+        /// the high one. Producers can speed up again.
+        Low,
+    }
+
+    /// Where an endpoint's watermarks are and which one was crossed last.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct Watermarks {
+        low: usize,
+        high: usize,
+        reached_high: bool,
+    }
+
+    impl Watermarks {
+        /// The watermarks a queue of `capacity` messages starts with.
+        pub fn new(capacity: usize) -> Self {
+            Self {
+                low: capacity / 4,
+                high: capacity * 3 / 4,
+                reached_high: false,
+            }
+        }
+
+        /// Watermarks at `low` and `high`, unless they don't fit in a queue of
+        /// `capacity` messages.
+        pub fn with_levels(low: usize, high: usize, capacity: usize) -> Option<Self> {
+            (low < high && high <= capacity).then_some(Self {
+                low,
+                high,
+                reached_high: false,
+            })
+        }
+
+        /// Records a send that left `len` messages queued.
+        pub fn sent(&mut self, len: usize) -> Watermark {
+            if self.reached_high || len < self.high {
+                return Watermark::None;
+            }
+            self.reached_high = true;
+            Watermark::High
+        }
+
+        /// Records a receive that left `len` messages queued.
+        pub fn received(&mut self, len: usize) -> Watermark {
+            if !self.reached_high || len > self.low {
+                return Watermark::None;
+            }
+            self.reached_high = false;
+            Watermark::Low
+        }
+    }
+
+    /// Operations on a bounded queue of messages.
+    ///
+    /// Anyone holding the capability can send and receive, so the receiver
+    /// is whoever builds the endpoint and hands out copies to its producers.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum EndpointOp {
+        /// Queues `message` and returns the [`Watermark`] it crossed.
+        ///
+        /// Fails with `WouldBlock` if the queue is full. Nothing blocks in the
+        /// kernel yet, so senders must retry later.
+        Send { message: Message },
+        /// Writes the oldest message into `buffer` and returns the
+        /// [`Watermark`] its removal crossed.
+        ///
+        /// Fails with `WouldBlock` if the queue is empty.
+        Receive { buffer: *mut Message },
+        /// Returns the number of queued messages.
+        Len,
+        /// Returns the number of messages the queue can hold.
+        Capacity,
+        /// Moves the watermarks, which start at a quarter and three quarters
+        /// of the capacity.
+        ///
+        /// Fails with `InvalidArgument` unless `low < high <= capacity`.
+        SetWatermarks { low: usize, high: usize },
+    }
+
+    impl SyscallOp for EndpointOp {
+        type R = usize;
+
+        fn into_args(self) -> SyscallArgs {
+            match self {
+                EndpointOp::Send {
+                    message: [a, b, c, d],
+                } => SyscallArgs::new(RawOperation::EndpointSend.into(), a, b, c, d),
+                EndpointOp::Receive { buffer } => SyscallArgs::new(
+                    RawOperation::EndpointReceive.into(),
+                    buffer as usize,
+                    0,
+                    0,
+                    0,
+                ),
+                EndpointOp::Len => SyscallArgs::new(RawOperation::EndpointLen.into(), 0, 0, 0, 0),
+                EndpointOp::Capacity => {
+                    SyscallArgs::new(RawOperation::EndpointCapacity.into(), 0, 0, 0, 0)
+                }
+                EndpointOp::SetWatermarks { low, high } => {
+                    SyscallArgs::new(RawOperation::EndpointSetWatermarks.into(), low, high, 0, 0)
+                }
+            }
+        }
+
+        fn from_args(args: SyscallArgs) -> Result<Self, InvalidOperation> {
+            let (a, b, c, d) = args.args();
+            match RawOperation::try_from(args.op()).map_err(|_| InvalidOperation::BadOp)? {
+                RawOperation::EndpointSend => Ok(Self::Send {
+                    message: [a, b, c, d],
+                }),
+                RawOperation::EndpointReceive => Ok(Self::Receive {
+                    buffer: a as *mut Message,
+                }),
+                RawOperation::EndpointLen => Ok(Self::Len),
+                RawOperation::EndpointCapacity => Ok(Self::Capacity),
+                RawOperation::EndpointSetWatermarks => Ok(Self::SetWatermarks { low: a, high: b }),
+                _ => Err(InvalidOperation::BadOp),
+            }
+        }
+
+        fn convert_success_code(&self, code: usize) -> Self::R {
+            code
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn watermarks_are_reported_once_per_crossing() {
+            let mut watermarks = Watermarks::new(8);
+            assert_eq!(watermarks.received(0), Watermark::None);
+            assert_eq!(watermarks.sent(5), Watermark::None);
+            assert_eq!(watermarks.sent(6), Watermark::High);
+            assert_eq!(watermarks.sent(7), Watermark::None);
+            assert_eq!(watermarks.received(3), Watermark::None);
+            assert_eq!(watermarks.received(2), Watermark::Low);
+            assert_eq!(watermarks.received(1), Watermark::None);
+            assert_eq!(watermarks.sent(6), Watermark::High);
+        }
+
+        #[test]
+        fn watermarks_must_fit_in_the_queue() {
+            assert!(Watermarks::with_levels(2, 8, 8).is_some());
+            assert!(Watermarks::with_levels(2, 9, 8).is_none());
+            assert!(Watermarks::with_levels(4, 4, 8).is_none());
+        }
+    }
+}
+
+/// Groups of threads that are torn down together.
+///
+/// A hierarchy holds the threads of a component and the hierarchies of its
+/// children, so a supervisor can treat a service along with its helpers as a
+/// single unit. Hierarchies are nested by naming the parent when they're
+/// constructed, and threads join them through [`HierarchyOp::Adopt`].
+pub mod hierarchy {
+    use super::{InvalidOperation, SyscallOp};
+    use crate::raw::{CapId, RawOperation, SyscallArgs};
+
+    /// Largest number of threads in a single hierarchy.
+    pub const MAX_MEMBERS: usize = 64;
+
+    /// Largest number of hierarchies nested directly in another one.
+    pub const MAX_CHILDREN: usize = 32;
+
+    /// Largest number of levels below a root hierarchy.
+    pub const MAX_DEPTH: usize = 8;
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum HierarchyOp {
+        /// Makes the thread held by `thread` a member of the hierarchy.
+        ///
+        /// A thread belongs to a single hierarchy for good. Fails with
+        /// `ResourceInUse` if it already joined one, with `OutOfMemory` if
+        /// the hierarchy is full and with `InvalidArgument` if it was killed.
+        Adopt { thread: CapId },
+        /// Suspends every thread in the hierarchy and those nested in it, and
+        /// clears their address spaces.
+        ///
+        /// Threads are suspended right away. Mappings are released the same
+        /// as [`PageTableOp::Clear`](super::page_table::PageTableOp::Clear)
+        /// with `release` set, except in address spaces shared with the
+        /// caller. Teardown waits for threads running on other cores to leave
+        /// them. Afterwards the hierarchies no longer hold their threads or
+        /// children, which go back to untyped memory once their capabilities
+        /// are dropped.
+        ///
+        /// Fails with `InvalidArgument` if the caller is in the hierarchy.
+        KillTree,
+        /// Returns the number of faults raised by threads in the hierarchy or
+        /// nested in it since the last call.
+        TakeFaults,
+    }
+
+    impl SyscallOp for HierarchyOp {
+        type R = usize;
+
+        fn into_args(self) -> SyscallArgs {
+            match self {
+                HierarchyOp::Adopt { thread } => {
+                    SyscallArgs::new(RawOperation::HierarchyAdopt.into(), thread.into(), 0, 0, 0)
+                }
+                HierarchyOp::KillTree => {
+                    SyscallArgs::new(RawOperation::HierarchyKillTree.into(), 0, 0, 0, 0)
+                }
+                HierarchyOp::TakeFaults => {
+                    SyscallArgs::new(RawOperation::HierarchyTakeFaults.into(), 0, 0, 0, 0)
+                }
+            }
+        }
+
+        fn from_args(args: SyscallArgs) -> Result<Self, InvalidOperation> {
+            let (thread, _, _, _) = args.args();
+            match RawOperation::try_from(args.op()).map_err(|_| InvalidOperation::BadOp)? {
+                RawOperation::HierarchyAdopt => Ok(Self::Adopt {
+                    thread: CapId::try_from(thread)
+                        .map_err(|_| InvalidOperation::InvalidArgument)?,
+                }),
+                RawOperation::HierarchyKillTree => Ok(Self::KillTree),
+                RawOperation::HierarchyTakeFaults => Ok(Self::TakeFaults),
+                _ => Err(InvalidOperation::BadOp),
+            }
+        }
+
+        fn convert_success_code(&self, code: usize) -> Self::R {
+            code
+        }
+    }
+}
+
+/// Services of the kernel as a whole.
+pub mod system {
+    use super::{InvalidOperation, SyscallOp};
+    use crate::raw::{CapId, RawOperation, SyscallArgs};
+
+    /// Slot where the kernel places the system capability for the boot
+    /// component.
+    pub const BOOT_SYSTEM_CAP: CapId = CapId::new(8);
+
+    /// Largest number of bytes filled by a single [`SystemOp::GetRandom`].
+    pub const MAX_RANDOM_LEN: usize = 4096;
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum SystemOp {
+        /// Fills `buffer` with up to [`MAX_RANDOM_LEN`] random bytes and
+        /// returns how many were written.
+        ///
+        /// The bytes come from a ChaCha20 generator the kernel seeds from
+        /// RDSEED, RDRAND or, without either, the jitter of the TSC. They're
+        /// fit for keys, canaries and address space randomization.
+        GetRandom { buffer: *mut u8, len: usize },
+        /// Returns the [`ResourceKind`](crate::endowment::ResourceKind) held
+        /// by `cap` in the caller's table, `Empty` if there's nothing there.
+        Identify { cap: CapId },
+    }
+
+    impl SyscallOp for SystemOp {
+        type R = usize;
+
+        fn into_args(self) -> SyscallArgs {
+            match self {
+                SystemOp::GetRandom { buffer, len } => SyscallArgs::new(
+                    RawOperation::SystemGetRandom.into(),
+                    buffer as usize,
+                    len,
+                    0,
+                    0,
+                ),
+                SystemOp::Identify { cap } => {
+                    SyscallArgs::new(RawOperation::SystemIdentify.into(), cap.into(), 0, 0, 0)
+                }
+            }
+        }
+
+        fn from_args(args: SyscallArgs) -> Result<Self, InvalidOperation> {
+            match RawOperation::try_from(args.op()).map_err(|_| InvalidOperation::BadOp)? {
+                RawOperation::SystemIdentify => {
+                    let cap = CapId::try_from(args.args().0)
+                        .map_err(|_| InvalidOperation::InvalidArgument)?;
+                    Ok(Self::Identify { cap })
+                }
+                RawOperation::SystemGetRandom => {
+                    let (buffer, len, ..) = args.args();
+                    Ok(Self::GetRandom {
+                        buffer: buffer as *mut u8,
+                        len,
+                    })
+                }
+                _ => Err(InvalidOperation::BadOp),
+            }
+        }
+
+        fn convert_success_code(&self, code: usize) -> Self::R {
+            code
+        }
+    }
+}
+
+/// The display the bootloader set up and the console font.
+pub mod display {
+    use super::{InvalidOperation, SyscallOp};
+    use crate::raw::{CapId, RawOperation, SyscallArgs};
+
+    /// Slot where the kernel places the display capability for the boot
+    /// component.
+    pub const BOOT_DISPLAY_CAP: CapId = CapId::new(9);
+
+    /// Name of the boot module holding the console font.
+    pub const FONT_MODULE: &[u8] = b"font";
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum DisplayOp {
+        /// Returns the number of pages [`Map`](Self::Map) can map: the
+        /// [`DisplayInfo`](crate::display::DisplayInfo) page and then the
+        /// font.
+        ///
+        /// Fails with `NotFound` if the bootloader didn't set up a display.
+        Pages,
+        /// Maps the pages read-only into the level 1 page table `table`,
+        /// starting with page `first` at entry 0, and returns the number of
+        /// pages mapped.
+        ///
+        /// Pages past the end of the table or the font aren't mapped. Nothing
+        /// is mapped if one of the entries is in use.
+        Map { table: CapId, first: usize },
+    }
+
+    impl SyscallOp for DisplayOp {
+        type R = usize;
+
+        fn into_args(self) -> SyscallArgs {
+            match self {
+                DisplayOp::Pages => SyscallArgs::new(RawOperation::DisplayPages.into(), 0, 0, 0, 0),
+                DisplayOp::Map { table, first } => {
+                    SyscallArgs::new(RawOperation::DisplayMap.into(), table.into(), first, 0, 0)
+                }
+            }
+        }
+
+        fn from_args(args: SyscallArgs) -> Result<Self, InvalidOperation> {
+            match RawOperation::try_from(args.op()).map_err(|_| InvalidOperation::BadOp)? {
+                RawOperation::DisplayPages => Ok(Self::Pages),
+                RawOperation::DisplayMap => {
+                    let (table, first, ..) = args.args();
+                    Ok(Self::Map {
+                        table: CapId::try_from(table)
+                            .map_err(|_| InvalidOperation::InvalidArgument)?,
+                        first,
+                    })
+                }
+                _ => Err(InvalidOperation::BadOp),
+            }
+        }
+
+        fn convert_success_code(&self, code: usize) -> Self::R {
+            code
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raw::{RawOperation, ResourceType, OPERATION_COUNT};
+
+    /// Decodes `args` as an operation on `resource` and encodes it again.
+    fn round_trip(
+        resource: ResourceType,
+        args: SyscallArgs,
+    ) -> Result<SyscallArgs, InvalidOperation> {
+        fn via<T: SyscallOp>(args: SyscallArgs) -> Result<SyscallArgs, InvalidOperation> {
+            T::from_args(args).map(T::into_args)
+        }
+        match resource {
+            ResourceType::CapabilityTable => {
+                via::<cap_table::CapTableOp<{ cap_table::SLOT_COUNT }>>(args)
+            }
+            ResourceType::ThreadControlBlock => via::<thread::ThreadOp>(args),
+            ResourceType::PageTable => via::<page_table::PageTableOp>(args),
+            ResourceType::Logger => via::<logger::LoggerOp>(args),
+            ResourceType::Ipi => via::<ipi::IpiOp>(args),
+            ResourceType::MemoryRegion => via::<region::RegionOp>(args),
+            ResourceType::Clock => via::<clock::ClockOp>(args),
+            ResourceType::Diagnostics => via::<diagnostics::DiagnosticsOp>(args),
+            ResourceType::PerfCounter => via::<perf::PerfOp>(args),
+            ResourceType::Iommu => via::<iommu::IommuOp>(args),
+            ResourceType::DmaDomain => via::<iommu::DmaDomainOp>(args),
+            ResourceType::Initrd => via::<initrd::InitrdOp>(args),
+            ResourceType::Endpoint => via::<endpoint::EndpointOp>(args),
+            ResourceType::Hierarchy => via::<hierarchy::HierarchyOp>(args),
+            ResourceType::System => via::<system::SystemOp>(args),
+            ResourceType::Display => via::<display::DisplayOp>(args),
+        }
+    }
+
+    #[test]
+    fn operations_only_decode_for_their_resource() {
+        let resources = || (0..).map_while(|resource| ResourceType::try_from(resource).ok());
+        for raw in 0..OPERATION_COUNT {
+            let op = RawOperation::try_from(raw).unwrap();
+            // None of these has an encoding yet.
+            let unencoded = matches!(
+                op,
+                RawOperation::CapTableCopy
+                    | RawOperation::PageTableLink
+                    | RawOperation::PageTableUnlink
+                    | RawOperation::MemoryRegionRetype
+                    | RawOperation::MemoryRegionSplit
+            );
+            for arg in [0, 1] {
+                let args = SyscallArgs::new(raw, arg, arg, arg, arg);
+                for resource in resources() {
+                    match round_trip(resource, args) {
+                        Err(InvalidOperation::BadOp) => assert!(
+                            resource != op.resource() || unencoded,
+                            "{op:?} doesn't decode as a {resource:?} operation"
+                        ),
+                        Err(InvalidOperation::InvalidArgument) => assert_eq!(
+                            resource,
+                            op.resource(),
+                            "{op:?} decodes as a {resource:?} operation"
+                        ),
+                        Ok(encoded) => {
+                            assert_eq!(
+                                resource,
+                                op.resource(),
+                                "{op:?} decodes as a {resource:?} operation"
+                            );
+                            assert_eq!(
+                                RawOperation::try_from(encoded.op()),
+                                Ok(op),
+                                "{op:?} is encoded as another operation"
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
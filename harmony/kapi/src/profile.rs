@@ -0,0 +1,95 @@
+//! Sampled call stacks.
+//!
+//! While sampling is enabled with `DiagnosticsOp::Profile`, the kernel records
+//! the call stack of whatever a core was running every few timer ticks,
+//! walking frame pointers through the kernel or the interrupted component.
+//! Identical stacks are counted together as [`StackSample`]s, which a
+//! component holding the diagnostics capability drains with
+//! `DiagnosticsOp::DrainProfile`. [`write_folded`] turns them into the folded
+//! stacks that flamegraph tools read.
+
+use core::fmt;
+
+/// Most frames recorded for a stack. Deeper stacks are cut off at the root.
+pub const MAX_DEPTH: usize = 16;
+
+/// How many times a call stack was sampled.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct StackSample {
+    pub count: u64,
+    /// Core the stack was sampled on.
+    pub core: u32,
+    /// Whether the core was running userspace.
+    pub user: bool,
+    /// Number of valid entries in `frames`.
+    pub depth: u8,
+    /// The interrupted instruction followed by the return addresses of its
+    /// callers.
+    pub frames: [u64; MAX_DEPTH],
+}
+
+impl StackSample {
+    pub fn frames(&self) -> &[u64] {
+        &self.frames[..usize::from(self.depth).min(MAX_DEPTH)]
+    }
+}
+
+/// Writes `samples` as folded stacks, one line per stack with the frames from
+/// the root down separated by `;` and followed by the count.
+///
+/// Every stack starts at `kernel` or `user`. Addresses `resolve` returns a
+/// name for are written with that name and any other in hex.
+pub fn write_folded<N: fmt::Display>(
+    samples: &[StackSample],
+    mut resolve: impl FnMut(&StackSample, u64) -> Option<N>,
+    mut out: impl fmt::Write,
+) -> fmt::Result {
+    for sample in samples {
+        out.write_str(if sample.user { "user" } else { "kernel" })?;
+        for &address in sample.frames().iter().rev() {
+            match resolve(sample, address) {
+                Some(name) => write!(out, ";{name}")?,
+                None => write!(out, ";{address:#x}")?,
+            }
+        }
+        writeln!(out, " {}", sample.count)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+
+    use super::*;
+
+    fn sample(count: u64, user: bool, frames: &[u64]) -> StackSample {
+        let mut sample = StackSample {
+            count,
+            user,
+            depth: frames.len() as u8,
+            ..Default::default()
+        };
+        sample.frames[..frames.len()].copy_from_slice(frames);
+        sample
+    }
+
+    #[test]
+    fn folds_stacks_from_the_root() {
+        let samples = [
+            sample(3, false, &[0x10, 0x20, 0x30]),
+            sample(1, true, &[0x1234]),
+        ];
+        let mut folded = String::new();
+        let resolve = |sample: &StackSample, address| match (sample.user, address) {
+            (false, 0x10) => Some("tick"),
+            (false, 0x30) => Some("kmain"),
+            _ => None,
+        };
+        write_folded(&samples, resolve, &mut folded).unwrap();
+        assert_eq!(folded, "kernel;kmain;0x20;tick 3\nuser;0x1234 1\n");
+    }
+}
@@ -9,6 +9,7 @@ use num_enum::{IntoPrimitive, TryFromPrimitive, TryFromPrimitiveError};
 ///
 /// Performing a syscall is inherently unsafe, follow the syscall
 /// documentation to guarantee proper usage and soundness.
+#[cfg(target_arch = "x86_64")]
 #[naked]
 pub unsafe extern "sysv64" fn raw_syscall(
     _a: usize,
@@ -38,12 +39,58 @@ pub unsafe extern "sysv64" fn raw_syscall(
     );
 }
 
+/// Performs a raw syscall
+///
+/// The arguments are already in `x0` to `x5` and the result comes back in
+/// `x0`, so the kernel must preserve every other register. The aarch64
+/// kernel doesn't take syscalls yet.
+///
+/// # Safety
+///
+/// Performing a syscall is inherently unsafe, follow the syscall
+/// documentation to guarantee proper usage and soundness.
+#[cfg(target_arch = "aarch64")]
+#[naked]
+pub unsafe extern "C" fn raw_syscall(
+    _a: usize,
+    _b: usize,
+    _c: usize,
+    _d: usize,
+    _e: usize,
+    _f: usize,
+) -> isize {
+    asm!("svc #0", "ret", options(noreturn));
+}
+
+/// Performs a raw syscall
+///
+/// The arguments are already in `a0` to `a5` and the result comes back in
+/// `a0`, so the kernel must preserve every other register.
+///
+/// # Safety
+///
+/// Performing a syscall is inherently unsafe, follow the syscall
+/// documentation to guarantee proper usage and soundness.
+#[cfg(target_arch = "riscv64")]
+#[naked]
+pub unsafe extern "C" fn raw_syscall(
+    _a: usize,
+    _b: usize,
+    _c: usize,
+    _d: usize,
+    _e: usize,
+    _f: usize,
+) -> isize {
+    asm!("ecall", "ret", options(noreturn));
+}
+
 /// Performs a syscall
 ///
 /// # Safety
 ///
 /// Performing a syscall is inherently unsafe, follow the syscall
 /// documentation to guarantee proper usage and soundness.
+#[cfg(not(feature = "testing"))]
 pub unsafe fn syscall(cap: CapId, args: SyscallArgs) -> Result<usize, CapError> {
     let result = unsafe {
         raw_syscall(
@@ -61,11 +108,21 @@ pub unsafe fn syscall(cap: CapId, args: SyscallArgs) -> Result<usize, CapError>
     }
 }
 
-#[derive(Debug, Copy, Clone, TryFromPrimitive, IntoPrimitive)]
+/// Performs a syscall against the installed [`crate::testing::MockKernel`].
+///
+/// # Safety
+///
+/// Always safe, the signature matches the real syscall.
+#[cfg(feature = "testing")]
+pub unsafe fn syscall(cap: CapId, args: SyscallArgs) -> Result<usize, CapError> {
+    crate::testing::syscall(cap, args)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
 #[repr(usize)]
 pub enum RawOperation {
     ThreadActivate = 0,
-    ThreadChangeAffinity,
+    ThreadSetAffinity,
     CapTableLink,
     CapTableUnlink,
     CapTableConstruct,
@@ -75,9 +132,129 @@ pub enum RawOperation {
     PageTableUnlink,
     MemoryRegionRetype,
     MemoryRegionSplit,
+    LoggerSetFilter,
+    ThreadGetAffinity,
+    IpiSend,
+    IpiTakePending,
+    PageTableDumpMappings,
+    PageTableClear,
+    CapTableExtend,
+    ThreadSchedule,
+    MemoryRegionTransfer,
+    MemoryRegionBase,
+    MemoryRegionFrames,
+    PageTableEvict,
+    ClockGetCalibration,
+    ClockGetTimeNs,
+    DiagnosticsMap,
+    DiagnosticsRefresh,
+    DiagnosticsSymbolize,
+    DiagnosticsDrainTrace,
+    DiagnosticsInterruptLatency,
+    PerfCounters,
+    PerfProgram,
+    PerfRead,
+    PerfStop,
+    PerfTakeOverflows,
+    DiagnosticsProfile,
+    DiagnosticsDrainProfile,
+    MemoryRegionMap,
+    DiagnosticsDrainAudit,
+    PageTablePin,
+    PageTableUnpin,
+    IommuUnits,
+    DmaDomainExtend,
+    DmaDomainGrant,
+    DmaDomainRevoke,
+    ThreadSuspend,
+    ThreadResume,
+    ThreadGetState,
+    InitrdSize,
+    InitrdMap,
+    EndpointSend,
+    EndpointReceive,
+    EndpointLen,
+    EndpointCapacity,
+    EndpointSetWatermarks,
+    HierarchyAdopt,
+    HierarchyKillTree,
+    HierarchyTakeFaults,
+    SystemGetRandom,
+    ThreadYield,
+    ThreadYieldTo,
+    MemoryRegionStats,
+    LoggerWrite,
+    LoggerSetLevel,
+    LoggerSetModuleLevel,
+    LoggerInjectMarker,
+    SystemIdentify,
+    DisplayPages,
+    DisplayMap,
+}
+
+/// Number of operations.
+///
+/// Operations are only ever appended, so programs built against an older kapi
+/// keep working with newer kernels.
+pub const OPERATION_COUNT: usize = RawOperation::DisplayMap as usize + 1;
+
+impl RawOperation {
+    /// The type of resource the operation is exercised on.
+    ///
+    /// Operations are numbered in the order they were added rather than in
+    /// ranges per resource, so this is what ties each number to the one
+    /// resource whose operations may decode it.
+    pub const fn resource(self) -> ResourceType {
+        use RawOperation::*;
+        match self {
+            ThreadActivate | ThreadSetAffinity | ThreadGetAffinity | ThreadSchedule
+            | ThreadSuspend | ThreadResume | ThreadGetState | ThreadYield | ThreadYieldTo => {
+                ResourceType::ThreadControlBlock
+            }
+            CapTableLink | CapTableUnlink | CapTableConstruct | CapTableDrop | CapTableCopy
+            | CapTableExtend => ResourceType::CapabilityTable,
+            PageTableLink
+            | PageTableUnlink
+            | PageTableDumpMappings
+            | PageTableClear
+            | PageTableEvict
+            | PageTablePin
+            | PageTableUnpin => ResourceType::PageTable,
+            MemoryRegionRetype | MemoryRegionSplit | MemoryRegionTransfer | MemoryRegionBase
+            | MemoryRegionFrames | MemoryRegionMap | MemoryRegionStats => {
+                ResourceType::MemoryRegion
+            }
+            LoggerSetFilter | LoggerWrite | LoggerSetLevel | LoggerSetModuleLevel
+            | LoggerInjectMarker => ResourceType::Logger,
+            IpiSend | IpiTakePending => ResourceType::Ipi,
+            ClockGetCalibration | ClockGetTimeNs => ResourceType::Clock,
+            DiagnosticsMap
+            | DiagnosticsRefresh
+            | DiagnosticsSymbolize
+            | DiagnosticsDrainTrace
+            | DiagnosticsInterruptLatency
+            | DiagnosticsProfile
+            | DiagnosticsDrainProfile
+            | DiagnosticsDrainAudit => ResourceType::Diagnostics,
+            PerfCounters | PerfProgram | PerfRead | PerfStop | PerfTakeOverflows => {
+                ResourceType::PerfCounter
+            }
+            IommuUnits => ResourceType::Iommu,
+            DmaDomainExtend | DmaDomainGrant | DmaDomainRevoke => ResourceType::DmaDomain,
+            InitrdSize | InitrdMap => ResourceType::Initrd,
+            EndpointSend
+            | EndpointReceive
+            | EndpointLen
+            | EndpointCapacity
+            | EndpointSetWatermarks => ResourceType::Endpoint,
+            HierarchyAdopt | HierarchyKillTree | HierarchyTakeFaults => ResourceType::Hierarchy,
+            SystemGetRandom | SystemIdentify => ResourceType::System,
+            DisplayPages | DisplayMap => ResourceType::Display,
+        }
+    }
 }
 
-#[derive(Debug, Copy, Clone, TryFromPrimitive, IntoPrimitive)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
 #[repr(u8)]
 pub enum CapError {
     ResourceInUse = 1,
@@ -88,14 +265,41 @@ pub enum CapError {
     FrameOutsideOfRegion,
     FrameNotUser,
     Internal,
+    /// The thread's affinity doesn't allow it to run on this core.
+    WrongCore,
+    /// The memory given for the operation ran out before it could complete.
+    OutOfMemory,
+    /// A synchronous invocation's stack pool has no free stacks.
+    NoSyncStacks,
+    /// The thread was suspended and can't be activated until it's resumed.
+    Suspended,
+    /// The operation can't make progress right now, e.g. because an
+    /// endpoint's queue is full, and should be retried later.
+    WouldBlock,
+    /// The stack given to a thread isn't mapped writable in its address
+    /// space.
+    BadStack,
 }
 
-#[derive(Debug, Copy, Clone, TryFromPrimitive, IntoPrimitive)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
 #[repr(u8)]
 pub enum ResourceType {
     CapabilityTable = 0,
     ThreadControlBlock,
     PageTable,
+    Logger,
+    Ipi,
+    MemoryRegion,
+    Clock,
+    Diagnostics,
+    PerfCounter,
+    Iommu,
+    DmaDomain,
+    Initrd,
+    Endpoint,
+    Hierarchy,
+    System,
+    Display,
 }
 
 impl<T: TryFromPrimitive> From<TryFromPrimitiveError<T>> for CapError {
@@ -163,8 +367,54 @@ impl SyscallArgs {
 #[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd, Ord)]
 pub struct CapId(u32);
 
+// This crate is the only definition of the syscall ABI that userspace and the
+// kernel share. Changing any of these breaks programs built against an older
+// kapi.
+const _: () = {
+    use core::mem::{align_of, size_of};
+
+    assert!(RawOperation::ThreadActivate as usize == 0);
+    assert!(RawOperation::MemoryRegionRetype as usize == 9);
+    assert!(RawOperation::PageTableEvict as usize == 22);
+    assert!(RawOperation::DiagnosticsInterruptLatency as usize == 29);
+    assert!(RawOperation::PerfTakeOverflows as usize == 34);
+    assert!(RawOperation::DiagnosticsDrainProfile as usize == 36);
+    assert!(RawOperation::MemoryRegionMap as usize == 37);
+    assert!(RawOperation::DiagnosticsDrainAudit as usize == 38);
+    assert!(RawOperation::PageTablePin as usize == 39);
+    assert!(RawOperation::PageTableUnpin as usize == 40);
+    assert!(RawOperation::DmaDomainRevoke as usize == 44);
+    assert!(RawOperation::ThreadGetState as usize == 47);
+    assert!(RawOperation::InitrdMap as usize == 49);
+    assert!(RawOperation::EndpointSetWatermarks as usize == 54);
+    assert!(RawOperation::HierarchyTakeFaults as usize == 57);
+    assert!(RawOperation::SystemGetRandom as usize == 58);
+    assert!(RawOperation::ThreadYieldTo as usize == 60);
+    assert!(RawOperation::MemoryRegionStats as usize == 61);
+    assert!(RawOperation::LoggerWrite as usize == 62);
+    assert!(RawOperation::LoggerInjectMarker as usize == 65);
+    assert!(RawOperation::SystemIdentify as usize == 66);
+    assert!(RawOperation::DisplayMap as usize == 68);
+
+    assert!(CapError::ResourceInUse as u8 == 1);
+    assert!(CapError::Suspended as u8 == 12);
+    assert!(CapError::WouldBlock as u8 == 13);
+    assert!(CapError::BadStack as u8 == 14);
+
+    // `raw_syscall` passes the capability and the arguments in six registers.
+    assert!(size_of::<SyscallArgs>() == 5 * size_of::<usize>());
+    assert!(align_of::<SyscallArgs>() == align_of::<usize>());
+    assert!(size_of::<CapId>() == size_of::<u32>());
+};
+
 pub struct OutOfBounds;
 
+impl CapId {
+    pub const fn new(id: u32) -> Self {
+        Self(id)
+    }
+}
+
 impl From<u32> for CapId {
     fn from(value: u32) -> Self {
         Self(value)
@@ -198,3 +448,24 @@ pub enum FrameType {
     User = 1,
     Kernel = 2,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operations_are_numbered_in_order() {
+        assert!((0..OPERATION_COUNT).all(|op| RawOperation::try_from(op).is_ok()));
+        assert!(RawOperation::try_from(OPERATION_COUNT).is_err());
+    }
+
+    #[test]
+    fn errors_round_trip_through_errnos() {
+        for errno in 1..=CapError::BadStack as u8 {
+            let error = CapError::try_from(errno).unwrap();
+            assert_eq!(error.to_errno(), -isize::from(errno));
+        }
+        assert!(CapError::try_from(0).is_err());
+        assert!(CapError::try_from(CapError::BadStack as u8 + 1).is_err());
+    }
+}
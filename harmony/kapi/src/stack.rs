@@ -0,0 +1,134 @@
+//! Stack usage watermarking.
+//!
+//! The unused part of a stack is painted with a known pattern when the stack
+//! is set up. Code running on the stack overwrites the pattern, so the lowest
+//! word that doesn't hold it marks the deepest the stack ever got. The bottom
+//! word holds a different canary: if that one changes, the stack overflowed.
+//!
+//! The kernel does this for its boot stack and reports it in the diagnostics
+//! page. Components get it through `librs::entry!`.
+
+/// Fills the painted part of a stack.
+pub const PAINT: u64 = 0x5AC4_5AC4_5AC4_5AC4;
+
+/// Placed in the lowest word of a stack.
+pub const CANARY: u64 = 0xDEAD_57AC_CA4A_12E5;
+
+/// Bytes below the stack pointer left alone when painting, since the function
+/// doing the painting needs them.
+pub const PAINT_MARGIN: usize = 4096;
+
+/// The peak usage of a stack.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct StackUsage {
+    /// Bytes used at the deepest point, including the part that wasn't
+    /// painted.
+    pub used: u64,
+    /// Size of the stack in bytes.
+    pub size: u64,
+    /// Whether the canary at the bottom of the stack was overwritten.
+    pub overflowed: u64,
+}
+
+impl StackUsage {
+    pub fn overflowed(&self) -> bool {
+        self.overflowed != 0
+    }
+
+    /// Used bytes in hundredths of the stack size.
+    pub fn percent(&self) -> u64 {
+        (self.used * 100).checked_div(self.size).unwrap_or(0)
+    }
+}
+
+/// Returns the current stack pointer.
+#[inline(always)]
+pub fn stack_pointer() -> usize {
+    let sp: usize;
+    // SAFETY: Reading the stack pointer has no side effects.
+    unsafe {
+        #[cfg(target_arch = "x86_64")]
+        core::arch::asm!("mov {}, rsp", out(reg) sp, options(nomem, nostack, preserves_flags));
+        #[cfg(target_arch = "aarch64")]
+        core::arch::asm!("mov {}, sp", out(reg) sp, options(nomem, nostack, preserves_flags));
+        #[cfg(target_arch = "riscv64")]
+        core::arch::asm!("mv {}, sp", out(reg) sp, options(nomem, nostack, preserves_flags));
+    }
+    sp
+}
+
+/// Puts the canary at `bottom` and paints up to `end`.
+///
+/// # Safety
+///
+/// `bottom..end` must be 8 byte aligned, writable and unused.
+pub unsafe fn paint(bottom: usize, end: usize) {
+    let words = bottom as *mut u64;
+    let count = end.saturating_sub(bottom) / 8;
+    if count == 0 {
+        return;
+    }
+    // SAFETY: Precondition. Volatile since the compiler doesn't know anyone
+    // reads the memory back.
+    unsafe {
+        words.write_volatile(CANARY);
+        for i in 1..count {
+            words.add(i).write_volatile(PAINT);
+        }
+    }
+}
+
+/// Measures the peak usage of the stack in `bottom..top`.
+///
+/// # Safety
+///
+/// `bottom..top` must be 8 byte aligned and readable.
+pub unsafe fn usage(bottom: usize, top: usize) -> StackUsage {
+    let words = bottom as *const u64;
+    let count = top.saturating_sub(bottom) / 8;
+    // SAFETY: Precondition.
+    let read = |i: usize| unsafe { words.add(i).read_volatile() };
+    let overflowed = count > 0 && read(0) != CANARY;
+    let unused = (1..count).take_while(|&i| read(i) == PAINT).count();
+    let size = (top - bottom) as u64;
+    StackUsage {
+        used: if overflowed {
+            size
+        } else {
+            size - (unused as u64 + 1) * 8
+        },
+        size,
+        overflowed: overflowed.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_the_deepest_write() {
+        let mut stack = [0u64; 64];
+        let bottom = stack.as_mut_ptr() as usize;
+        let top = bottom + 64 * 8;
+        // SAFETY: The stack is a local array.
+        unsafe { paint(bottom, top - 16) };
+        let measured = unsafe { usage(bottom, top) };
+        assert_eq!(measured.used, 16);
+        assert_eq!(measured.size, 512);
+        assert!(!measured.overflowed());
+
+        // SAFETY: In bounds of the array.
+        unsafe { (bottom as *mut u64).add(40).write(1) };
+        let measured = unsafe { usage(bottom, top) };
+        assert_eq!(measured.used, 24 * 8);
+        assert_eq!(measured.percent(), 37);
+
+        // SAFETY: In bounds of the array.
+        unsafe { (bottom as *mut u64).write(0) };
+        let measured = unsafe { usage(bottom, top) };
+        assert!(measured.overflowed());
+        assert_eq!(measured.used, measured.size);
+    }
+}
@@ -0,0 +1,1005 @@
+//! A mock kernel for testing userspace code on the host.
+//!
+//! With the `testing` feature, syscalls made through [`crate::raw::syscall`]
+//! (and thus [`crate::ops::SyscallOp::syscall`]) are handled by a
+//! [`MockKernel`] installed on the current thread instead of trapping into the
+//! kernel. The mock models capability tables, untyped memory and the simpler
+//! resources, and records every invocation so tests can assert on the sequence
+//! of operations a component performs.
+//!
+//! ```ignore
+//! let mut kernel = MockKernel::new();
+//! kernel.insert(CapId::new(0), MockResource::CapTable(kernel.root())).unwrap();
+//! let kernel = kernel.install();
+//! run_component_logic();
+//! assert_eq!(kernel.with(|kernel| kernel.ops()), [RawOperation::CapTableExtend]);
+//! ```
+
+extern crate std;
+
+use std::boxed::Box;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::vec::Vec;
+
+use crate::diagnostics::RetypeStats;
+use crate::endowment::ResourceKind;
+use crate::ops::cap_table::{CapTableOp, SLOT_COUNT};
+use crate::ops::clock::{Calibration, ClockOp};
+use crate::ops::diagnostics::DiagnosticsOp;
+use crate::ops::display::DisplayOp;
+use crate::ops::endpoint::{EndpointOp, Watermark, Watermarks};
+use crate::ops::hierarchy::HierarchyOp;
+use crate::ops::initrd::InitrdOp;
+use crate::ops::iommu::{DmaDomainOp, IommuOp};
+use crate::ops::ipi::IpiOp;
+use crate::ops::logger::LoggerOp;
+use crate::ops::page_table::PageTableOp;
+use crate::ops::perf::PerfOp;
+use crate::ops::region::RegionOp;
+use crate::ops::system::{SystemOp, MAX_RANDOM_LEN};
+use crate::ops::thread::{ThreadOp, ThreadState};
+use crate::ops::SyscallOp;
+use crate::raw::{CapError, CapId, RawOperation, SyscallArgs};
+use crate::userspace::vmm::PAGE_SIZE;
+
+std::thread_local! {
+    static KERNEL: RefCell<Option<MockKernel>> = const { RefCell::new(None) };
+}
+
+/// Identifies a capability table in a [`MockKernel`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TableId(usize);
+
+/// A resource held in a capability.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MockResource {
+    CapTable(TableId),
+    Thread {
+        affinity: u64,
+        suspended: bool,
+    },
+    PageTable {
+        level: u8,
+    },
+    Logger,
+    Ipi,
+    /// `frames` pages of physical memory starting at `base`.
+    Region {
+        base: usize,
+        frames: usize,
+    },
+    /// A clock reading `nanos` since boot, with an uncalibrated TSC.
+    Clock {
+        nanos: u64,
+    },
+    /// Diagnostics for a kernel without symbols or traces whose page is never
+    /// refreshed.
+    Diagnostics,
+    /// `counters` performance counters that never count anything.
+    PerfCounter {
+        counters: u8,
+    },
+    /// `units` IOMMUs.
+    Iommu {
+        units: u8,
+    },
+    /// The DMA domain of the function with the requester id `requester`,
+    /// which takes any grant.
+    DmaDomain {
+        requester: u16,
+    },
+    /// An initrd of `len` bytes.
+    Initrd {
+        len: usize,
+    },
+    /// An endpoint holding `len` of `capacity` messages. Messages aren't
+    /// kept, so the ones received are all zeros.
+    Endpoint {
+        capacity: usize,
+        len: usize,
+        watermarks: Watermarks,
+    },
+    /// A hierarchy whose threads raised `faults` faults. Members aren't
+    /// tracked, so killing it doesn't suspend any thread.
+    Hierarchy {
+        faults: usize,
+        killed: bool,
+    },
+    /// A system whose random bytes come from a generator seeded with `seed`,
+    /// so that tests are reproducible.
+    System {
+        seed: u64,
+    },
+    /// A display with a font of `font_len` bytes, which has no pages at all
+    /// without `present`.
+    Display {
+        present: bool,
+        font_len: usize,
+    },
+}
+
+impl MockResource {
+    pub fn kind(&self) -> ResourceKind {
+        match self {
+            MockResource::CapTable(_) => ResourceKind::CapTable,
+            MockResource::Thread { .. } => ResourceKind::Thread,
+            MockResource::PageTable { .. } => ResourceKind::PageTable,
+            MockResource::Logger => ResourceKind::Logger,
+            MockResource::Ipi => ResourceKind::Ipi,
+            MockResource::Region { .. } => ResourceKind::Region,
+            MockResource::Clock { .. } => ResourceKind::Clock,
+            MockResource::Diagnostics => ResourceKind::Diagnostics,
+            MockResource::PerfCounter { .. } => ResourceKind::PerfCounter,
+            MockResource::Iommu { .. } => ResourceKind::Iommu,
+            MockResource::DmaDomain { .. } => ResourceKind::DmaDomain,
+            MockResource::Initrd { .. } => ResourceKind::Initrd,
+            MockResource::Endpoint { .. } => ResourceKind::Endpoint,
+            MockResource::Hierarchy { .. } => ResourceKind::Hierarchy,
+            MockResource::System { .. } => ResourceKind::System,
+            MockResource::Display { .. } => ResourceKind::Display,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Slot {
+    resource: Option<MockResource>,
+    child: Option<TableId>,
+}
+
+/// A syscall made while the mock was installed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    pub capability: CapId,
+    /// The operation, if it was a valid one.
+    pub op: Option<RawOperation>,
+    pub args: SyscallArgs,
+    pub result: Result<usize, CapError>,
+}
+
+type Handler = Box<dyn FnMut(&mut MockKernel, CapId, SyscallArgs) -> Result<usize, CapError>>;
+
+/// An in-process model of the kernel's syscall interface.
+pub struct MockKernel {
+    tables: Vec<BTreeMap<usize, Slot>>,
+    /// Addresses of the untyped pages that can be turned into kernel objects.
+    untyped: BTreeSet<usize>,
+    invocations: Vec<Invocation>,
+    handlers: Vec<(RawOperation, Option<Handler>)>,
+}
+
+impl Default for MockKernel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockKernel {
+    /// Creates a kernel with an empty root table, the table capabilities are
+    /// looked up in.
+    pub fn new() -> Self {
+        Self {
+            tables: Vec::from([BTreeMap::new()]),
+            untyped: BTreeSet::new(),
+            invocations: Vec::new(),
+            handlers: Vec::new(),
+        }
+    }
+
+    pub fn root(&self) -> TableId {
+        TableId(0)
+    }
+
+    /// Creates an empty capability table that isn't linked anywhere.
+    pub fn new_table(&mut self) -> TableId {
+        self.tables.push(BTreeMap::new());
+        TableId(self.tables.len() - 1)
+    }
+
+    /// Number of capability tables, including the root.
+    pub fn table_count(&self) -> usize {
+        self.tables.len()
+    }
+
+    /// Places `resource` in `cap`, replacing whatever was there.
+    ///
+    /// Fails with [`CapError::NotFound`] if `cap` isn't addressable from the
+    /// root table.
+    pub fn insert(&mut self, cap: CapId, resource: MockResource) -> Result<(), CapError> {
+        self.slot_mut(cap)?.resource = Some(resource);
+        Ok(())
+    }
+
+    /// Returns the resource in `cap`, if any.
+    pub fn resource(&mut self, cap: CapId) -> Option<MockResource> {
+        self.slot_mut(cap).ok()?.resource
+    }
+
+    /// Makes `frames` pages starting at `region` available as untyped memory.
+    pub fn add_untyped(&mut self, region: usize, frames: usize) {
+        self.untyped
+            .extend((0..frames).map(|frame| region + frame * PAGE_SIZE));
+    }
+
+    /// Number of untyped pages that haven't been used.
+    pub fn untyped_left(&self) -> usize {
+        self.untyped.len()
+    }
+
+    /// Handles `op` with `handler` instead of the built-in model.
+    pub fn on<F>(&mut self, op: RawOperation, handler: F)
+    where
+        F: FnMut(&mut MockKernel, CapId, SyscallArgs) -> Result<usize, CapError> + 'static,
+    {
+        self.handlers.push((op, Some(Box::new(handler))));
+    }
+
+    /// Every syscall made so far, in order.
+    pub fn invocations(&self) -> &[Invocation] {
+        &self.invocations
+    }
+
+    /// The operations of every valid syscall made so far, in order.
+    pub fn ops(&self) -> Vec<RawOperation> {
+        self.invocations.iter().filter_map(|call| call.op).collect()
+    }
+
+    /// Installs the kernel on the current thread until the returned guard is dropped.
+    ///
+    /// # Panics
+    ///
+    /// If another kernel is already installed.
+    pub fn install(self) -> Installed {
+        KERNEL.with_borrow_mut(|kernel| {
+            assert!(kernel.is_none(), "A mock kernel is already installed");
+            *kernel = Some(self);
+        });
+        Installed { _private: () }
+    }
+
+    fn slot_mut(&mut self, cap: CapId) -> Result<&mut Slot, CapError> {
+        self.slot_in(self.root(), cap)
+    }
+
+    /// Walks the trie from `table` the same way the kernel does.
+    fn slot_in(&mut self, mut table: TableId, cap: CapId) -> Result<&mut Slot, CapError> {
+        let mut id = usize::from(cap);
+        loop {
+            let offset = id % SLOT_COUNT;
+            id /= SLOT_COUNT;
+            if id == 0 {
+                return Ok(self.tables[table.0].entry(offset).or_default());
+            }
+            table = self.tables[table.0]
+                .get(&offset)
+                .and_then(|slot| slot.child)
+                .ok_or(CapError::NotFound)?;
+        }
+    }
+
+    fn table_slot(&mut self, table: TableId, slot: usize) -> &mut Slot {
+        self.tables[table.0].entry(slot).or_default()
+    }
+
+    fn handle(&mut self, capability: CapId, args: SyscallArgs) -> Result<usize, CapError> {
+        let op = RawOperation::try_from(args.op())?;
+        if let Some(index) = self.handlers.iter().position(|(handled, _)| *handled == op) {
+            // Take the handler out while it runs so it can use the kernel.
+            let mut handler = self.handlers[index].1.take().unwrap();
+            let result = handler(self, capability, args);
+            self.handlers[index].1 = Some(handler);
+            return result;
+        }
+        let resource = self.resource(capability).ok_or(CapError::NotFound)?;
+        let invalid = |_| CapError::InvalidArgument;
+        match resource {
+            MockResource::CapTable(table) => {
+                match CapTableOp::<SLOT_COUNT>::from_args(args).map_err(invalid)? {
+                    CapTableOp::Link {
+                        slot,
+                        other_table_cap,
+                    } => {
+                        let Some(MockResource::CapTable(other)) = self.resource(other_table_cap)
+                        else {
+                            return Err(CapError::InvalidArgument);
+                        };
+                        self.table_slot(table, slot.into()).child = Some(other);
+                        Ok(0)
+                    }
+                    CapTableOp::Unlink { slot } => {
+                        self.table_slot(table, slot.into()).child = None;
+                        Ok(0)
+                    }
+                    CapTableOp::Extend {
+                        cap,
+                        region,
+                        frames,
+                    } => self.extend(table, cap, region, frames),
+                    CapTableOp::Drop { slot } => {
+                        let slot = self.table_slot(table, slot.into());
+                        match slot.resource {
+                            None => Err(CapError::NotFound),
+                            Some(MockResource::Region { .. }) => Err(CapError::ResourceInUse),
+                            Some(_) => {
+                                slot.resource = None;
+                                Ok(0)
+                            }
+                        }
+                    }
+                    _ => Err(CapError::InvalidOp),
+                }
+            }
+            MockResource::Thread {
+                affinity,
+                suspended,
+            } => {
+                let state = match suspended {
+                    true => ThreadState::Suspended,
+                    false => ThreadState::Ready,
+                };
+                let state = u8::from(state) as usize;
+                match ThreadOp::from_args(args).map_err(invalid)? {
+                    ThreadOp::SetAffinity { mask } => {
+                        if mask == 0 {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        let thread = MockResource::Thread {
+                            affinity: mask,
+                            suspended,
+                        };
+                        self.insert(capability, thread)?;
+                        Ok(0)
+                    }
+                    ThreadOp::GetAffinity => Ok(affinity as usize),
+                    ThreadOp::Activate if suspended => Err(CapError::Suspended),
+                    ThreadOp::Activate | ThreadOp::Schedule => Ok(0),
+                    ThreadOp::Suspend | ThreadOp::Resume => {
+                        let thread = MockResource::Thread {
+                            affinity,
+                            suspended: args.op() == RawOperation::ThreadSuspend.into(),
+                        };
+                        self.insert(capability, thread)?;
+                        Ok(state)
+                    }
+                    ThreadOp::GetState => Ok(state),
+                    // There's no other thread to run.
+                    ThreadOp::Yield => Ok(0),
+                    ThreadOp::YieldTo { thread } => match self.resource(thread) {
+                        Some(MockResource::Thread {
+                            suspended: true, ..
+                        }) => Err(CapError::Suspended),
+                        Some(MockResource::Thread { .. }) => Ok(0),
+                        _ => Err(CapError::InvalidArgument),
+                    },
+                }
+            }
+            MockResource::PageTable { level } => {
+                match PageTableOp::from_args(args).map_err(invalid)? {
+                    PageTableOp::Clear { .. } | PageTableOp::Evict { .. } => Ok(0),
+                    PageTableOp::Pin { .. } | PageTableOp::Unpin { .. } if level != 4 => {
+                        Err(CapError::InvalidArgument)
+                    }
+                    // Pages are identity mapped as far as devices can tell.
+                    PageTableOp::Pin { page } => Ok(page),
+                    PageTableOp::Unpin { .. } => Ok(0),
+                    PageTableOp::DumpMappings { .. } => Err(CapError::InvalidOp),
+                }
+            }
+            MockResource::Logger => match LoggerOp::from_args(args).map_err(invalid)? {
+                LoggerOp::SetFilter { .. }
+                | LoggerOp::Write { .. }
+                | LoggerOp::SetLevel { .. }
+                | LoggerOp::SetModuleLevel { .. }
+                | LoggerOp::InjectMarker { .. } => Ok(0),
+            },
+            MockResource::Ipi => match IpiOp::from_args(args).map_err(invalid)? {
+                IpiOp::Send { .. } | IpiOp::TakePending => Ok(0),
+            },
+            MockResource::Region { base, frames } => {
+                match RegionOp::from_args(args).map_err(invalid)? {
+                    RegionOp::Transfer {
+                        offset,
+                        frames: moved,
+                        table,
+                        slot,
+                    } => {
+                        let Some(MockResource::CapTable(table)) = self.resource(table) else {
+                            return Err(CapError::InvalidArgument);
+                        };
+                        if usize::from(slot) >= SLOT_COUNT {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        let end = offset
+                            .checked_add(moved)
+                            .filter(|&end| moved > 0 && end <= frames)
+                            .ok_or(CapError::FrameOutsideOfRegion)?;
+                        let kept = match (offset, end) {
+                            (0, end) if end == frames => None,
+                            (0, end) => Some(MockResource::Region {
+                                base: base + end * PAGE_SIZE,
+                                frames: frames - end,
+                            }),
+                            (offset, end) if end == frames => Some(MockResource::Region {
+                                base,
+                                frames: offset,
+                            }),
+                            _ => return Err(CapError::InvalidArgument),
+                        };
+                        let destination = self.slot_in(table, slot)?;
+                        if destination.resource.is_some() {
+                            return Err(CapError::ResourceInUse);
+                        }
+                        destination.resource = Some(MockResource::Region {
+                            base: base + offset * PAGE_SIZE,
+                            frames: moved,
+                        });
+                        self.slot_mut(capability)?.resource = kept;
+                        Ok(0)
+                    }
+                    RegionOp::Base => Ok(base),
+                    RegionOp::Frames => Ok(frames),
+                    RegionOp::Map { base: window } => {
+                        if window % PAGE_SIZE != 0 {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        self.add_untyped(window, frames);
+                        Ok(0)
+                    }
+                    RegionOp::Stats { buffer } => {
+                        if buffer.is_null() || !buffer.is_aligned() {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        let stats = RetypeStats {
+                            untyped: frames as u64,
+                            ..Default::default()
+                        };
+                        // SAFETY: The component passed a buffer it owns, the
+                        // same as it would to the kernel.
+                        unsafe { buffer.write(stats) };
+                        Ok(0)
+                    }
+                }
+            }
+            MockResource::Clock { nanos } => match ClockOp::from_args(args).map_err(invalid)? {
+                ClockOp::GetCalibration { buffer } => {
+                    if buffer.is_null() || !buffer.is_aligned() {
+                        return Err(CapError::InvalidArgument);
+                    }
+                    // SAFETY: The component passed a buffer it owns, the
+                    // same as it would to the kernel.
+                    unsafe { buffer.write(Calibration::default()) };
+                    Ok(0)
+                }
+                ClockOp::GetTimeNs => Ok(nanos as usize),
+            },
+            MockResource::Diagnostics => match DiagnosticsOp::from_args(args).map_err(invalid)? {
+                DiagnosticsOp::Map { table, index } => match self.resource(table) {
+                    Some(MockResource::PageTable { level: 1 }) if index < 512 => Ok(0),
+                    Some(_) => Err(CapError::InvalidArgument),
+                    None => Err(CapError::NotFound),
+                },
+                DiagnosticsOp::Refresh => Ok(0),
+                DiagnosticsOp::Symbolize { .. } => Err(CapError::NotFound),
+                DiagnosticsOp::DrainTrace { .. } => Ok(0),
+                DiagnosticsOp::InterruptLatency { .. } => Ok(0),
+                DiagnosticsOp::Profile { .. } => Ok(0),
+                DiagnosticsOp::DrainProfile { .. } => Ok(0),
+                DiagnosticsOp::DrainAudit { .. } => Ok(0),
+            },
+            MockResource::PerfCounter { counters } => {
+                let in_range = |counter: u8| {
+                    if counter < counters {
+                        Ok(0)
+                    } else {
+                        Err(CapError::InvalidArgument)
+                    }
+                };
+                match PerfOp::from_args(args).map_err(invalid)? {
+                    PerfOp::Counters => Ok(counters.into()),
+                    PerfOp::Program { counter, .. }
+                    | PerfOp::Read { counter }
+                    | PerfOp::Stop { counter } => in_range(counter),
+                    PerfOp::TakeOverflows => Ok(0),
+                }
+            }
+            MockResource::Iommu { units } => match IommuOp::from_args(args).map_err(invalid)? {
+                IommuOp::Units => Ok(units.into()),
+            },
+            MockResource::DmaDomain { .. } => {
+                match DmaDomainOp::from_args(args).map_err(invalid)? {
+                    DmaDomainOp::Extend { .. }
+                    | DmaDomainOp::Grant { .. }
+                    | DmaDomainOp::Revoke { .. } => Ok(0),
+                }
+            }
+            MockResource::Initrd { len } => match InitrdOp::from_args(args).map_err(invalid)? {
+                InitrdOp::Size => Ok(len),
+                InitrdOp::Map { table, first } => {
+                    let pages = len.div_ceil(PAGE_SIZE);
+                    match self.resource(table) {
+                        Some(MockResource::PageTable { level: 1 }) if first < pages => {
+                            Ok((pages - first).min(512))
+                        }
+                        Some(_) => Err(CapError::InvalidArgument),
+                        None => Err(CapError::NotFound),
+                    }
+                }
+            },
+            MockResource::Endpoint {
+                capacity,
+                len,
+                mut watermarks,
+            } => {
+                let (len, crossed) = match EndpointOp::from_args(args).map_err(invalid)? {
+                    EndpointOp::Len => return Ok(len),
+                    EndpointOp::Capacity => return Ok(capacity),
+                    EndpointOp::SetWatermarks { low, high } => {
+                        watermarks = Watermarks::with_levels(low, high, capacity)
+                            .ok_or(CapError::InvalidArgument)?;
+                        (len, Watermark::None)
+                    }
+                    EndpointOp::Send { .. } if len == capacity => return Err(CapError::WouldBlock),
+                    EndpointOp::Send { .. } => (len + 1, watermarks.sent(len + 1)),
+                    EndpointOp::Receive { .. } if len == 0 => return Err(CapError::WouldBlock),
+                    EndpointOp::Receive { buffer } => {
+                        if buffer.is_null() || !buffer.is_aligned() {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        // SAFETY: The component passed a buffer it owns, the
+                        // same as it would to the kernel.
+                        unsafe { buffer.write([0; 4]) };
+                        (len - 1, watermarks.received(len - 1))
+                    }
+                };
+                let endpoint = MockResource::Endpoint {
+                    capacity,
+                    len,
+                    watermarks,
+                };
+                self.insert(capability, endpoint)?;
+                Ok(usize::from(u8::from(crossed)))
+            }
+            MockResource::Hierarchy { faults, killed } => {
+                match HierarchyOp::from_args(args).map_err(invalid)? {
+                    HierarchyOp::Adopt { .. } if killed => Err(CapError::InvalidArgument),
+                    HierarchyOp::Adopt { thread } => match self.resource(thread) {
+                        Some(MockResource::Thread { .. }) => Ok(0),
+                        _ => Err(CapError::InvalidArgument),
+                    },
+                    HierarchyOp::KillTree => {
+                        let hierarchy = MockResource::Hierarchy {
+                            faults,
+                            killed: true,
+                        };
+                        self.insert(capability, hierarchy)?;
+                        Ok(0)
+                    }
+                    HierarchyOp::TakeFaults => {
+                        let hierarchy = MockResource::Hierarchy { faults: 0, killed };
+                        self.insert(capability, hierarchy)?;
+                        Ok(faults)
+                    }
+                }
+            }
+            MockResource::System { mut seed } => {
+                match SystemOp::from_args(args).map_err(invalid)? {
+                    SystemOp::GetRandom { buffer, len } => {
+                        if buffer.is_null() {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        let len = len.min(MAX_RANDOM_LEN);
+                        // SAFETY: The component passed a buffer it owns, the
+                        // same as it would to the kernel.
+                        let buffer = unsafe { std::slice::from_raw_parts_mut(buffer, len) };
+                        for chunk in buffer.chunks_mut(8) {
+                            // SplitMix64.
+                            seed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                            let mut z = seed;
+                            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                            z ^= z >> 31;
+                            chunk.copy_from_slice(&z.to_ne_bytes()[..chunk.len()]);
+                        }
+                        self.insert(capability, MockResource::System { seed })?;
+                        Ok(len)
+                    }
+                    SystemOp::Identify { cap } => {
+                        let kind = self.resource(cap).map_or(ResourceKind::Empty, |r| r.kind());
+                        Ok(u8::from(kind).into())
+                    }
+                }
+            }
+            MockResource::Display { present, font_len } => {
+                let operation = DisplayOp::from_args(args).map_err(invalid)?;
+                if !present {
+                    return Err(CapError::NotFound);
+                }
+                let pages = 1 + font_len.div_ceil(PAGE_SIZE);
+                match operation {
+                    DisplayOp::Pages => Ok(pages),
+                    DisplayOp::Map { table, first } => match self.resource(table) {
+                        Some(MockResource::PageTable { level: 1 }) if first < pages => {
+                            Ok((pages - first).min(512))
+                        }
+                        Some(_) => Err(CapError::InvalidArgument),
+                        None => Err(CapError::NotFound),
+                    },
+                }
+            }
+        }
+    }
+
+    /// Models `CapTableOp::Extend`.
+    fn extend(
+        &mut self,
+        root: TableId,
+        cap: CapId,
+        region: usize,
+        frames: usize,
+    ) -> Result<usize, CapError> {
+        let mut pages = (0..frames).map(|frame| region + frame * PAGE_SIZE);
+        let mut id = usize::from(cap);
+        let mut table = root;
+        let mut built = 0;
+        loop {
+            let offset = id % SLOT_COUNT;
+            id /= SLOT_COUNT;
+            if id == 0 {
+                return Ok(built);
+            }
+            table = match self.table_slot(table, offset).child {
+                Some(child) => child,
+                None => {
+                    let page = pages.next().ok_or(CapError::OutOfMemory)?;
+                    if !self.untyped.remove(&page) {
+                        return Err(CapError::InvalidArgument);
+                    }
+                    let child = self.new_table();
+                    self.table_slot(table, offset).child = Some(child);
+                    built += 1;
+                    child
+                }
+            };
+        }
+    }
+}
+
+/// Keeps a [`MockKernel`] installed on the current thread.
+#[must_use]
+pub struct Installed {
+    _private: (),
+}
+
+impl Installed {
+    /// Runs `f` with the installed kernel.
+    pub fn with<R>(&self, f: impl FnOnce(&mut MockKernel) -> R) -> R {
+        KERNEL.with_borrow_mut(|kernel| f(kernel.as_mut().unwrap()))
+    }
+
+    /// Uninstalls the kernel and returns it.
+    pub fn take(self) -> MockKernel {
+        let kernel = KERNEL.take().unwrap();
+        core::mem::forget(self);
+        kernel
+    }
+}
+
+impl Drop for Installed {
+    fn drop(&mut self) {
+        KERNEL.take();
+    }
+}
+
+/// Handles a syscall with the kernel installed on the current thread.
+///
+/// # Panics
+///
+/// If there's no kernel installed.
+pub(crate) fn syscall(capability: CapId, args: SyscallArgs) -> Result<usize, CapError> {
+    KERNEL.with_borrow_mut(|kernel| {
+        let kernel = kernel
+            .as_mut()
+            .expect("Syscall made without a mock kernel installed");
+        let result = kernel.handle(capability, args);
+        kernel.invocations.push(Invocation {
+            capability,
+            op: RawOperation::try_from(args.op()).ok(),
+            args,
+            result,
+        });
+        result
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use trie::SlotId;
+
+    use super::*;
+
+    fn kernel_with_table() -> MockKernel {
+        let mut kernel = MockKernel::new();
+        let root = kernel.root();
+        kernel
+            .insert(CapId::new(0), MockResource::CapTable(root))
+            .unwrap();
+        kernel
+    }
+
+    #[test]
+    fn records_invocations() {
+        let mut kernel = kernel_with_table();
+        kernel
+            .insert(
+                CapId::new(1),
+                MockResource::Thread {
+                    affinity: 1,
+                    suspended: false,
+                },
+            )
+            .unwrap();
+        let kernel = kernel.install();
+
+        // SAFETY: The mock doesn't touch memory.
+        unsafe {
+            ThreadOp::SetAffinity { mask: 0b110 }
+                .syscall(CapId::new(1))
+                .unwrap();
+            assert_eq!(ThreadOp::GetAffinity.syscall(CapId::new(1)), Ok(0b110));
+            assert_eq!(
+                ThreadOp::GetAffinity.syscall(CapId::new(2)),
+                Err(CapError::NotFound)
+            );
+        }
+        let kernel = kernel.take();
+        assert_eq!(
+            kernel.ops(),
+            [
+                RawOperation::ThreadSetAffinity,
+                RawOperation::ThreadGetAffinity,
+                RawOperation::ThreadGetAffinity
+            ]
+        );
+        assert_eq!(kernel.invocations()[2].result, Err(CapError::NotFound));
+    }
+
+    #[test]
+    fn suspended_threads_cant_be_activated() {
+        let mut kernel = kernel_with_table();
+        let thread = MockResource::Thread {
+            affinity: 1,
+            suspended: false,
+        };
+        kernel.insert(CapId::new(1), thread).unwrap();
+        kernel.insert(CapId::new(2), thread).unwrap();
+        let _kernel = kernel.install();
+        let ready = u8::from(ThreadState::Ready).into();
+        let suspended = u8::from(ThreadState::Suspended).into();
+        let yield_to = ThreadOp::YieldTo {
+            thread: CapId::new(1),
+        };
+
+        // SAFETY: The mock doesn't touch memory.
+        unsafe {
+            assert_eq!(ThreadOp::Suspend.syscall(CapId::new(1)), Ok(ready));
+            assert_eq!(ThreadOp::GetState.syscall(CapId::new(1)), Ok(suspended));
+            assert_eq!(
+                ThreadOp::Activate.syscall(CapId::new(1)),
+                Err(CapError::Suspended)
+            );
+            assert_eq!(yield_to.syscall(CapId::new(2)), Err(CapError::Suspended));
+            assert_eq!(ThreadOp::Resume.syscall(CapId::new(1)), Ok(suspended));
+            assert_eq!(ThreadOp::Activate.syscall(CapId::new(1)), Ok(0));
+            assert_eq!(yield_to.syscall(CapId::new(2)), Ok(0));
+        }
+    }
+
+    #[test]
+    fn full_endpoints_push_back_on_senders() {
+        let mut kernel = kernel_with_table();
+        let endpoint = MockResource::Endpoint {
+            capacity: 4,
+            len: 0,
+            watermarks: Watermarks::new(4),
+        };
+        kernel.insert(CapId::new(1), endpoint).unwrap();
+        let _kernel = kernel.install();
+        let send = EndpointOp::Send { message: [1; 4] };
+        let high = u8::from(Watermark::High).into();
+        let low = u8::from(Watermark::Low).into();
+        let mut message = [1; 4];
+        let receive = EndpointOp::Receive {
+            buffer: &mut message,
+        };
+
+        // SAFETY: The buffer outlives the syscalls.
+        unsafe {
+            assert_eq!(send.syscall(CapId::new(1)), Ok(0));
+            assert_eq!(send.syscall(CapId::new(1)), Ok(0));
+            assert_eq!(send.syscall(CapId::new(1)), Ok(high));
+            assert_eq!(send.syscall(CapId::new(1)), Ok(0));
+            assert_eq!(send.syscall(CapId::new(1)), Err(CapError::WouldBlock));
+            assert_eq!(receive.syscall(CapId::new(1)), Ok(0));
+            assert_eq!(receive.syscall(CapId::new(1)), Ok(0));
+            assert_eq!(receive.syscall(CapId::new(1)), Ok(low));
+            assert_eq!(EndpointOp::Len.syscall(CapId::new(1)), Ok(1));
+        }
+        assert_eq!(message, [0; 4]);
+    }
+
+    #[test]
+    fn killed_hierarchies_refuse_threads() {
+        let mut kernel = kernel_with_table();
+        let thread = MockResource::Thread {
+            affinity: 1,
+            suspended: false,
+        };
+        kernel.insert(CapId::new(1), thread).unwrap();
+        let hierarchy = MockResource::Hierarchy {
+            faults: 2,
+            killed: false,
+        };
+        kernel.insert(CapId::new(2), hierarchy).unwrap();
+        let _kernel = kernel.install();
+        let adopt = HierarchyOp::Adopt {
+            thread: CapId::new(1),
+        };
+
+        // SAFETY: The mock doesn't touch memory.
+        unsafe {
+            assert_eq!(adopt.syscall(CapId::new(2)), Ok(0));
+            assert_eq!(HierarchyOp::TakeFaults.syscall(CapId::new(2)), Ok(2));
+            assert_eq!(HierarchyOp::TakeFaults.syscall(CapId::new(2)), Ok(0));
+            assert_eq!(HierarchyOp::KillTree.syscall(CapId::new(2)), Ok(0));
+            assert_eq!(adopt.syscall(CapId::new(2)), Err(CapError::InvalidArgument));
+        }
+    }
+
+    #[test]
+    fn models_cap_table_tries() {
+        let mut kernel = kernel_with_table();
+        let far = CapId::new(SLOT_COUNT as u32 * 5 + 3);
+        assert_eq!(
+            kernel.insert(far, MockResource::Logger),
+            Err(CapError::NotFound)
+        );
+        kernel.add_untyped(0x1000, 1);
+        let table = kernel.new_table();
+        kernel
+            .insert(CapId::new(1), MockResource::CapTable(table))
+            .unwrap();
+        let kernel = kernel.install();
+
+        // SAFETY: The mock doesn't touch memory.
+        unsafe {
+            CapTableOp::<SLOT_COUNT>::Link {
+                slot: SlotId::try_from(3).ok().unwrap(),
+                other_table_cap: CapId::new(1),
+            }
+            .syscall(CapId::new(0))
+            .unwrap();
+        }
+        kernel.with(|kernel| {
+            kernel.insert(far, MockResource::Logger).unwrap();
+            assert_eq!(kernel.resource(far), Some(MockResource::Logger));
+        });
+
+        let further = CapId::new(SLOT_COUNT as u32 * 4);
+        let extend = |region, frames| CapTableOp::<SLOT_COUNT>::Extend {
+            cap: further,
+            region,
+            frames,
+        };
+        // SAFETY: The mock doesn't touch memory.
+        unsafe {
+            assert_eq!(
+                extend(0, 0).syscall(CapId::new(0)),
+                Err(CapError::OutOfMemory)
+            );
+            assert_eq!(
+                extend(0x2000, 1).syscall(CapId::new(0)),
+                Err(CapError::InvalidArgument)
+            );
+            extend(0x1000, 1).syscall(CapId::new(0)).unwrap();
+        }
+        kernel.with(|kernel| {
+            assert_eq!(kernel.untyped_left(), 0);
+            kernel.insert(further, MockResource::Ipi).unwrap();
+        });
+    }
+
+    #[test]
+    fn custom_handlers() {
+        let mut kernel = kernel_with_table();
+        kernel.on(RawOperation::IpiTakePending, |_, _, _| Ok(0b101));
+        let kernel = kernel.install();
+        // SAFETY: The mock doesn't touch memory.
+        assert_eq!(
+            unsafe { IpiOp::TakePending.syscall(CapId::new(0)) },
+            Ok(0b101)
+        );
+        kernel.with(|kernel| assert_eq!(kernel.invocations().len(), 1));
+    }
+
+    #[test]
+    fn models_region_transfers() {
+        let mut kernel = kernel_with_table();
+        let other = kernel.new_table();
+        let region = CapId::new(2);
+        kernel
+            .insert(CapId::new(1), MockResource::CapTable(other))
+            .unwrap();
+        kernel
+            .insert(
+                region,
+                MockResource::Region {
+                    base: 0x10_0000,
+                    frames: 8,
+                },
+            )
+            .unwrap();
+        let kernel = kernel.install();
+        let transfer = |offset, frames, slot| RegionOp::Transfer {
+            offset,
+            frames,
+            table: CapId::new(1),
+            slot: CapId::new(slot),
+        };
+
+        // SAFETY: The mock doesn't touch memory.
+        unsafe {
+            assert_eq!(
+                transfer(2, 2, 0).syscall(region),
+                Err(CapError::InvalidArgument)
+            );
+            assert_eq!(
+                transfer(6, 4, 0).syscall(region),
+                Err(CapError::FrameOutsideOfRegion)
+            );
+            assert_eq!(
+                transfer(6, 2, SLOT_COUNT as u32).syscall(region),
+                Err(CapError::InvalidArgument)
+            );
+            transfer(6, 2, 0).syscall(region).unwrap();
+            assert_eq!(
+                transfer(0, 2, 0).syscall(region),
+                Err(CapError::ResourceInUse)
+            );
+            transfer(0, 2, 1).syscall(region).unwrap();
+            assert_eq!(RegionOp::Base.syscall(region), Ok(0x10_2000));
+            assert_eq!(RegionOp::Frames.syscall(region), Ok(4));
+            transfer(0, 4, 2).syscall(region).unwrap();
+        }
+        let mut kernel = kernel.take();
+        assert_eq!(kernel.resource(region), None);
+        let moved = |kernel: &mut MockKernel, slot| {
+            kernel
+                .slot_in(other, CapId::new(slot))
+                .unwrap()
+                .resource
+                .unwrap()
+        };
+        assert_eq!(
+            moved(&mut kernel, 0),
+            MockResource::Region {
+                base: 0x10_6000,
+                frames: 2
+            }
+        );
+        assert_eq!(
+            moved(&mut kernel, 1),
+            MockResource::Region {
+                base: 0x10_0000,
+                frames: 2
+            }
+        );
+        assert_eq!(
+            moved(&mut kernel, 2),
+            MockResource::Region {
+                base: 0x10_2000,
+                frames: 4
+            }
+        );
+    }
+}
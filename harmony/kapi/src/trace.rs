@@ -0,0 +1,169 @@
+//! Kernel event traces.
+//!
+//! Kernels built with the `trace-events` feature record context switches,
+//! syscall entries and exits, IPIs and page faults as fixed-size
+//! [`TraceRecord`]s in a ring per core. A component holding the diagnostics
+//! capability drains them with `DiagnosticsOp::DrainTrace`, and
+//! [`write_chrome_trace`] turns them into the JSON that Chrome's trace viewer
+//! and Perfetto load, with a process per core and a track per thread.
+
+use core::fmt;
+
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+
+use crate::raw::RawOperation;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u32)]
+pub enum EventKind {
+    /// The core started running `thread`. `arg` is the thread it was running
+    /// before, or 0.
+    ContextSwitch = 1,
+    /// `thread` made a syscall. `arg` is the operation.
+    SyscallEnter,
+    /// The syscall of `thread` returned. `arg` is the result.
+    SyscallExit,
+    /// A request was queued for another core. `arg` is the core.
+    Ipi,
+    /// `thread` faulted. `arg` is the faulting address.
+    PageFault,
+    /// `thread` suspended the thread identified by `arg`.
+    ThreadSuspended,
+    /// `thread` resumed the thread identified by `arg`.
+    ThreadResumed,
+}
+
+/// An event recorded by the kernel.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Nanoseconds since boot.
+    pub nanos: u64,
+    /// An [`EventKind`].
+    pub kind: u32,
+    /// Core the event happened on.
+    pub core: u32,
+    /// Identifies the thread running when the event happened, or 0.
+    pub thread: u64,
+    /// Depends on the kind of event.
+    pub arg: u64,
+}
+
+impl TraceRecord {
+    pub fn kind(&self) -> Option<EventKind> {
+        EventKind::try_from(self.kind).ok()
+    }
+}
+
+/// Microseconds with a fractional part, as trace viewers expect.
+struct Micros(u64);
+
+impl fmt::Display for Micros {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:03}", self.0 / 1000, self.0 % 1000)
+    }
+}
+
+/// Writes `records` as a Chrome trace-event JSON object.
+///
+/// Syscalls become slices on the track of their thread, and everything else
+/// an instant event. Records of unknown kinds are skipped.
+pub fn write_chrome_trace(records: &[TraceRecord], mut out: impl fmt::Write) -> fmt::Result {
+    out.write_str("{\"traceEvents\":[")?;
+    let mut separator = "";
+    for record in records {
+        let Some(kind) = record.kind() else {
+            continue;
+        };
+        let (name, phase) = match kind {
+            EventKind::ContextSwitch => ("switch", "i"),
+            EventKind::SyscallEnter => ("syscall", "B"),
+            EventKind::SyscallExit => ("syscall", "E"),
+            EventKind::Ipi => ("ipi", "i"),
+            EventKind::PageFault => ("page fault", "i"),
+            EventKind::ThreadSuspended => ("suspend", "i"),
+            EventKind::ThreadResumed => ("resume", "i"),
+        };
+        write!(
+            out,
+            "{separator}{{\"name\":\"{name}\",\"ph\":\"{phase}\",\"ts\":{},\"pid\":{},\"tid\":{}",
+            Micros(record.nanos),
+            record.core,
+            record.thread
+        )?;
+        if phase == "i" {
+            out.write_str(",\"s\":\"t\"")?;
+        }
+        match kind {
+            EventKind::ContextSwitch => write!(out, ",\"args\":{{\"from\":{}}}", record.arg)?,
+            EventKind::SyscallEnter => match RawOperation::try_from(record.arg as usize) {
+                Ok(op) => write!(out, ",\"args\":{{\"op\":\"{op:?}\"}}")?,
+                Err(_) => write!(out, ",\"args\":{{\"op\":{}}}", record.arg)?,
+            },
+            EventKind::SyscallExit => {
+                write!(out, ",\"args\":{{\"result\":{}}}", record.arg as i64)?
+            }
+            EventKind::Ipi => write!(out, ",\"args\":{{\"core\":{}}}", record.arg)?,
+            EventKind::PageFault => write!(out, ",\"args\":{{\"address\":{}}}", record.arg)?,
+            EventKind::ThreadSuspended | EventKind::ThreadResumed => {
+                write!(out, ",\"args\":{{\"thread\":{}}}", record.arg)?
+            }
+        }
+        out.write_char('}')?;
+        separator = ",";
+    }
+    out.write_str("]}")
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+
+    use super::*;
+
+    fn record(nanos: u64, kind: EventKind, thread: u64, arg: u64) -> TraceRecord {
+        TraceRecord {
+            nanos,
+            kind: kind.into(),
+            core: 0,
+            thread,
+            arg,
+        }
+    }
+
+    #[test]
+    fn converts_to_chrome_traces() {
+        let records = [
+            record(1_500, EventKind::ContextSwitch, 7, 0),
+            record(
+                2_000,
+                EventKind::SyscallEnter,
+                7,
+                usize::from(RawOperation::ClockGetTimeNs) as u64,
+            ),
+            record(2_250, EventKind::SyscallExit, 7, -3i64 as u64),
+            TraceRecord {
+                kind: 99,
+                ..Default::default()
+            },
+            record(3_000, EventKind::PageFault, 7, 0x1000),
+        ];
+        let mut json = String::new();
+        write_chrome_trace(&records, &mut json).unwrap();
+        assert_eq!(
+            json,
+            "{\"traceEvents\":[\
+             {\"name\":\"switch\",\"ph\":\"i\",\"ts\":1.500,\"pid\":0,\"tid\":7,\"s\":\"t\",\
+             \"args\":{\"from\":0}},\
+             {\"name\":\"syscall\",\"ph\":\"B\",\"ts\":2.000,\"pid\":0,\"tid\":7,\
+             \"args\":{\"op\":\"ClockGetTimeNs\"}},\
+             {\"name\":\"syscall\",\"ph\":\"E\",\"ts\":2.250,\"pid\":0,\"tid\":7,\
+             \"args\":{\"result\":-3}},\
+             {\"name\":\"page fault\",\"ph\":\"i\",\"ts\":3.000,\"pid\":0,\"tid\":7,\"s\":\"t\",\
+             \"args\":{\"address\":4096}}\
+             ]}"
+        );
+    }
+}
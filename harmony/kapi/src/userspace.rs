@@ -0,0 +1,16 @@
+//! Helpers for components running in userspace.
+
+pub mod cap;
+pub mod dma;
+pub mod elf;
+pub mod lifecycle;
+pub mod numa;
+pub mod page_table;
+pub mod perf;
+pub mod random;
+pub mod registry;
+pub mod slab;
+pub mod thread;
+pub mod time;
+pub mod upgrade;
+pub mod vmm;
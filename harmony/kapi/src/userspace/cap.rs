@@ -0,0 +1,149 @@
+//! Capabilities owned by a value.
+//!
+//! A [`CapId`] is only a name: it can be copied freely and says nothing about
+//! who's responsible for the resource behind it, so capabilities are easily
+//! leaked or dropped twice. [`Owned`] holds a capability the way a `Box` holds
+//! memory. It can't be copied, and dropping it drops the capability from the
+//! table slot it's in. The wrappers of each kind of resource, e.g.
+//! [`Thread`](super::thread::Thread), are built on top of it.
+//!
+//! Capabilities handed out at boot aren't in a slot the component may drop,
+//! so owning one without a [`Slot`] only ties it to the value.
+//!
+//! FIXME: There are no typed constructors yet: wrappers are made with
+//! `from_raw` from capabilities the component was given or built itself with
+//! [`CapTableOp::Construct`].
+
+use trie::SlotId;
+
+use crate::ops::cap_table::{CapTableOp, SLOT_COUNT};
+use crate::ops::SyscallOp as _;
+use crate::raw::{CapError, CapId};
+
+/// Where a capability is held, as needed to drop it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Slot {
+    /// The capability table holding the slot.
+    pub table: CapId,
+    pub slot: SlotId<SLOT_COUNT>,
+}
+
+/// A capability dropped along with the value.
+#[derive(Debug, PartialEq, Eq)]
+#[must_use = "the capability is dropped right away"]
+pub struct Owned {
+    id: CapId,
+    slot: Option<Slot>,
+}
+
+impl Owned {
+    /// Takes ownership of the capability `id`, which is held in `slot` if it
+    /// should be dropped with the value.
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may own the capability, and `slot` must hold it.
+    pub unsafe fn from_raw(id: CapId, slot: Option<Slot>) -> Self {
+        Self { id, slot }
+    }
+
+    /// Gives up ownership without dropping the capability.
+    pub fn into_raw(self) -> (CapId, Option<Slot>) {
+        let raw = (self.id, self.slot);
+        core::mem::forget(self);
+        raw
+    }
+
+    pub fn id(&self) -> CapId {
+        self.id
+    }
+
+    pub fn slot(&self) -> Option<Slot> {
+        self.slot
+    }
+
+    /// Drops the capability, returning the error that dropping the value
+    /// would ignore, e.g. `ResourceInUse` for a page table that still maps
+    /// something.
+    pub fn release(mut self) -> Result<(), CapError> {
+        self.drop_slot()
+    }
+
+    fn drop_slot(&mut self) -> Result<(), CapError> {
+        let Some(Slot { table, slot }) = self.slot.take() else {
+            return Ok(());
+        };
+        // SAFETY: Nothing else owns the capability.
+        unsafe { CapTableOp::Drop { slot }.syscall(table) }
+    }
+}
+
+impl Drop for Owned {
+    fn drop(&mut self) {
+        let _ = self.drop_slot();
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::raw::RawOperation;
+    use crate::testing::{MockKernel, MockResource};
+
+    const TABLE: CapId = CapId::new(1);
+
+    fn owned(kernel: &mut MockKernel, id: u32) -> Owned {
+        kernel
+            .insert(CapId::new(id), MockResource::PageTable { level: 1 })
+            .unwrap();
+        let slot = Slot {
+            table: TABLE,
+            slot: SlotId::try_from(id as usize).ok().unwrap(),
+        };
+        // SAFETY: Nothing else uses the capability.
+        unsafe { Owned::from_raw(CapId::new(id), Some(slot)) }
+    }
+
+    #[test]
+    fn drops_the_capability_once() {
+        let mut kernel = MockKernel::new();
+        let root = MockResource::CapTable(kernel.root());
+        kernel.insert(TABLE, root).unwrap();
+        let dropped = owned(&mut kernel, 5);
+        let released = owned(&mut kernel, 6);
+        let kept = owned(&mut kernel, 7);
+        let installed = kernel.install();
+
+        drop(dropped);
+        assert_eq!(released.release(), Ok(()));
+        let (id, slot) = kept.into_raw();
+        assert_eq!(id, CapId::new(7));
+        assert_eq!(slot.map(|slot| slot.table), Some(TABLE));
+        // SAFETY: Nothing else uses the capability.
+        let unslotted = unsafe { Owned::from_raw(id, None) };
+        assert_eq!(unslotted.release(), Ok(()));
+        let slot = Slot {
+            table: TABLE,
+            slot: SlotId::try_from(5).ok().unwrap(),
+        };
+        // SAFETY: The slot was emptied, which the kernel reports.
+        let gone = unsafe { Owned::from_raw(CapId::new(5), Some(slot)) };
+        assert_eq!(gone.release(), Err(CapError::NotFound));
+
+        let mut kernel = installed.take();
+        assert_eq!(
+            kernel.ops(),
+            [
+                RawOperation::CapTableDrop,
+                RawOperation::CapTableDrop,
+                RawOperation::CapTableDrop
+            ]
+        );
+        assert_eq!(kernel.resource(CapId::new(5)), None);
+        assert_eq!(kernel.resource(CapId::new(6)), None);
+        assert_eq!(
+            kernel.resource(CapId::new(7)),
+            Some(MockResource::PageTable { level: 1 })
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! Pinning memory handed to devices.
+//!
+//! Devices read and write rings and buffers by physical address, behind the
+//! back of the retype table. A driver pins the pages of a buffer with [`pin`]
+//! before handing it to a device and keeps the returned [`Pinned`] until the
+//! transfer is over, so the frames can't be retyped or evicted while the
+//! device may still access them. Dropping it unpins the pages.
+//!
+//! Pins belong to the page they were taken through, so a page can't be
+//! pinned twice and the kernel refuses to unmap it until it's unpinned.
+
+use addr::PAGE_SIZE;
+
+use crate::ops::page_table::PageTableOp;
+use crate::ops::SyscallOp as _;
+use crate::raw::{CapError, CapId};
+
+/// Pages pinned with [`pin`].
+#[derive(Debug)]
+#[must_use = "the pages are unpinned when dropped"]
+pub struct Pinned {
+    table: CapId,
+    start: usize,
+    pages: usize,
+}
+
+/// Pins `pages` pages starting at `start` in the address space of the level 4
+/// page table `table`, and writes the physical address of each page into
+/// `addresses`.
+///
+/// Nothing stays pinned if any of the pages can't be pinned.
+pub fn pin(
+    table: CapId,
+    start: usize,
+    pages: usize,
+    addresses: &mut [u64],
+) -> Result<Pinned, CapError> {
+    if start % PAGE_SIZE != 0 || addresses.len() < pages {
+        return Err(CapError::InvalidArgument);
+    }
+    let mut pinned = Pinned {
+        table,
+        start,
+        pages: 0,
+    };
+    for address in &mut addresses[..pages] {
+        let page = pinned.page(pinned.pages);
+        // SAFETY: Pinning doesn't change any memory.
+        *address = unsafe { PageTableOp::Pin { page }.syscall(table)? } as u64;
+        pinned.pages += 1;
+    }
+    Ok(pinned)
+}
+
+impl Pinned {
+    fn page(&self, index: usize) -> usize {
+        self.start + index * PAGE_SIZE
+    }
+
+    /// Address of the first pinned page.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+
+    /// Unpins the pages, returning the first error but unpinning the rest
+    /// regardless.
+    pub fn unpin(mut self) -> Result<(), CapError> {
+        self.unpin_all()
+    }
+
+    fn unpin_all(&mut self) -> Result<(), CapError> {
+        let mut result = Ok(());
+        for index in 0..core::mem::take(&mut self.pages) {
+            let page = self.page(index);
+            // SAFETY: Unpinning doesn't change any memory.
+            if let Err(e) = unsafe { PageTableOp::Unpin { page }.syscall(self.table) } {
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+}
+
+impl Drop for Pinned {
+    fn drop(&mut self) {
+        let _ = self.unpin_all();
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::raw::RawOperation;
+    use crate::testing::{MockKernel, MockResource};
+
+    const ADDRESS_SPACE: CapId = CapId::new(2);
+    const LOWER_TABLE: CapId = CapId::new(3);
+    const BUFFER: usize = 0x10_0000;
+
+    fn kernel() -> MockKernel {
+        let mut kernel = MockKernel::new();
+        kernel
+            .insert(ADDRESS_SPACE, MockResource::PageTable { level: 4 })
+            .unwrap();
+        kernel
+            .insert(LOWER_TABLE, MockResource::PageTable { level: 3 })
+            .unwrap();
+        kernel
+    }
+
+    fn pages(kernel: &MockKernel, op: RawOperation) -> Vec<usize> {
+        kernel
+            .invocations()
+            .iter()
+            .filter(|call| call.op == Some(op))
+            .map(|call| call.args.args().0)
+            .collect()
+    }
+
+    #[test]
+    fn pins_for_the_duration_of_a_transfer() {
+        let installed = kernel().install();
+        let mut addresses = [0; 3];
+        let pinned = pin(ADDRESS_SPACE, BUFFER, 2, &mut addresses).unwrap();
+        assert_eq!(pinned.pages(), 2);
+        assert_eq!(addresses, [BUFFER as u64, (BUFFER + PAGE_SIZE) as u64, 0]);
+        installed.with(|kernel| assert!(pages(kernel, RawOperation::PageTableUnpin).is_empty()));
+        drop(pinned);
+        assert_eq!(
+            pin(LOWER_TABLE, BUFFER, 1, &mut addresses).unwrap_err(),
+            CapError::InvalidArgument
+        );
+
+        let kernel = installed.take();
+        assert_eq!(
+            pages(&kernel, RawOperation::PageTableUnpin),
+            [BUFFER, BUFFER + PAGE_SIZE]
+        );
+    }
+
+    #[test]
+    fn failed_pins_are_undone() {
+        let mut kernel = kernel();
+        let mut pinned = 0;
+        kernel.on(RawOperation::PageTablePin, move |_, _, args| {
+            pinned += 1;
+            match pinned {
+                3 => Err(CapError::ResourceInUse),
+                _ => Ok(args.args().0),
+            }
+        });
+        let installed = kernel.install();
+        let mut addresses = [0; 4];
+        assert_eq!(
+            pin(ADDRESS_SPACE, BUFFER, 4, &mut addresses).unwrap_err(),
+            CapError::ResourceInUse
+        );
+        assert_eq!(
+            pin(ADDRESS_SPACE, BUFFER + 1, 1, &mut addresses).unwrap_err(),
+            CapError::InvalidArgument
+        );
+        assert_eq!(
+            pin(ADDRESS_SPACE, BUFFER, 5, &mut addresses).unwrap_err(),
+            CapError::InvalidArgument
+        );
+
+        let kernel = installed.take();
+        assert_eq!(
+            pages(&kernel, RawOperation::PageTableUnpin),
+            [BUFFER, BUFFER + PAGE_SIZE]
+        );
+    }
+}
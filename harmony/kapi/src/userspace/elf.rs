@@ -0,0 +1,494 @@
+//! Loading ELF programs without reading them into memory first.
+//!
+//! The kernel loads the boot component from a module that's already in
+//! memory. Components started later come from a file system, and reading a
+//! whole binary into a buffer before mapping it takes twice the memory the
+//! program needs. [`Program`] instead reads the ELF header and the program
+//! headers from a [`Source`] when they're needed, and [`Program::load`]
+//! streams the loadable segments through a single page, handing every page to
+//! the caller to map into the new address space.
+//!
+//! The program gets the same checks the kernel makes when it loads the boot
+//! component: segments have to be readable, fit in the code region of the
+//! component's [`Layout`], lie within the file and can't be writable and
+//! executable unless the component may generate code. All of the segments are
+//! checked before the first page is handed out, so a bad program never maps
+//! anything.
+//!
+//! Component notes are parsed out of a buffer the caller provides, so that
+//! the amount of memory loading takes stays bounded.
+//!
+//! FIXME: There's no file system service to read from yet. Components with
+//! the file system in-process can read through [`ReadAt`], e.g. over
+//! `ext2::Ext2::read`. Shared libraries aren't linked, the caller has to find
+//! the dynamic segment with [`Program::segment`] if it wants to.
+
+use addr::PAGE_SIZE;
+
+use crate::component::{Metadata, NoteError};
+use crate::layout::{Layout, LayoutError};
+use crate::raw::CapError;
+
+const ELF_MAGIC: [u8; 4] = *b"\x7FELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+const EM_X86_64: u16 = 62;
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+pub const PT_LOAD: u32 = 1;
+pub const PT_DYNAMIC: u32 = 2;
+pub const PT_NOTE: u32 = 4;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+/// Where a [`Program`] is read from.
+pub trait Source {
+    type Error;
+
+    /// Size of the file in bytes.
+    fn len(&self) -> u64;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reads into `buf` from `offset`, returning the number of bytes read,
+    /// which is only short at the end of the file.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+impl Source for &[u8] {
+    type Error = core::convert::Infallible;
+
+    fn len(&self) -> u64 {
+        <[u8]>::len(self) as u64
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let start = usize::try_from(offset)
+            .map_or(<[u8]>::len(self), |offset| offset.min(<[u8]>::len(self)));
+        let bytes = &self[start..];
+        let len = buf.len().min(bytes.len());
+        buf[..len].copy_from_slice(&bytes[..len]);
+        Ok(len)
+    }
+}
+
+/// A [`Source`] of `len` bytes that reads with `read`, e.g. a closure over a
+/// file system's read call.
+pub struct ReadAt<F> {
+    pub len: u64,
+    pub read: F,
+}
+
+impl<F, E> Source for ReadAt<F>
+where
+    F: FnMut(u64, &mut [u8]) -> Result<usize, E>,
+{
+    type Error = E;
+
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, E> {
+        (self.read)(offset, buf)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ElfError<E> {
+    /// The source failed to read.
+    Read(E),
+    /// The file ends before something its headers point at.
+    Truncated,
+    /// The file isn't a 64-bit little-endian x86-64 executable.
+    NotSupported,
+    /// A segment isn't readable, is larger in the file than in memory or
+    /// wraps around the address space.
+    BadSegment,
+    /// A segment would be writable and executable.
+    WriteExecute,
+    /// A segment isn't in the code region.
+    Layout(LayoutError),
+    BadNotes(NoteError),
+    /// The note segment doesn't fit in the buffer given for it.
+    NotesTooLarge,
+    /// Mapping a page failed.
+    Map(CapError),
+}
+
+impl<E> From<LayoutError> for ElfError<E> {
+    fn from(value: LayoutError) -> Self {
+        ElfError::Layout(value)
+    }
+}
+
+impl<E> From<NoteError> for ElfError<E> {
+    fn from(value: NoteError) -> Self {
+        ElfError::BadNotes(value)
+    }
+}
+
+/// What a segment asks to do with its pages.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Permissions {
+    pub write: bool,
+    pub execute: bool,
+}
+
+/// A program header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+}
+
+impl Segment {
+    fn parse(bytes: &[u8; PROGRAM_HEADER_SIZE]) -> Self {
+        Self {
+            kind: u32_at(bytes, 0),
+            flags: u32_at(bytes, 4),
+            offset: u64_at(bytes, 8),
+            vaddr: u64_at(bytes, 16),
+            filesz: u64_at(bytes, 32),
+            memsz: u64_at(bytes, 40),
+        }
+    }
+
+    pub fn permissions(&self) -> Permissions {
+        Permissions {
+            write: self.flags & PF_W != 0,
+            execute: self.flags & PF_X != 0,
+        }
+    }
+
+    /// Checks that a loadable segment can be loaded from a file of `len`
+    /// bytes into `layout`.
+    fn check<E>(&self, len: u64, layout: &Layout, jit: bool) -> Result<(), ElfError<E>> {
+        if self.flags & PF_R == 0 || self.filesz > self.memsz {
+            return Err(ElfError::BadSegment);
+        }
+        let file_end = self.offset.checked_add(self.filesz);
+        if file_end.map_or(true, |end| end > len) {
+            return Err(ElfError::Truncated);
+        }
+        if self.vaddr.checked_add(self.memsz).is_none() {
+            return Err(ElfError::BadSegment);
+        }
+        let permissions = self.permissions();
+        if permissions.write && permissions.execute && !jit {
+            return Err(ElfError::WriteExecute);
+        }
+        layout.check_code(
+            usize::try_from(self.vaddr).map_err(|_| LayoutError::OutsideRegion)?,
+            usize::try_from(self.memsz).map_err(|_| LayoutError::OutsideRegion)?,
+        )?;
+        Ok(())
+    }
+}
+
+/// An ELF program read from a [`Source`] as it's needed.
+pub struct Program<S> {
+    source: S,
+    entry: u64,
+    phoff: u64,
+    phnum: u16,
+}
+
+impl<S: Source> Program<S> {
+    /// Reads and checks the ELF header.
+    pub fn open(mut source: S) -> Result<Self, ElfError<S::Error>> {
+        let mut header = [0; HEADER_SIZE];
+        read_exact(&mut source, 0, &mut header)?;
+        let supported = header[..4] == ELF_MAGIC
+            && header[4] == ELFCLASS64
+            && header[5] == ELFDATA2LSB
+            && matches!(u16_at(&header, 16), ET_EXEC | ET_DYN)
+            && u16_at(&header, 18) == EM_X86_64
+            && usize::from(u16_at(&header, 54)) == PROGRAM_HEADER_SIZE;
+        if !supported {
+            return Err(ElfError::NotSupported);
+        }
+        let phoff = u64_at(&header, 32);
+        let phnum = u16_at(&header, 56);
+        let headers_end = (PROGRAM_HEADER_SIZE as u64)
+            .checked_mul(phnum.into())
+            .and_then(|size| size.checked_add(phoff));
+        if headers_end.map_or(true, |end| end > source.len()) {
+            return Err(ElfError::Truncated);
+        }
+        Ok(Self {
+            source,
+            entry: u64_at(&header, 24),
+            phoff,
+            phnum,
+        })
+    }
+
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    /// Number of program headers.
+    pub fn segments(&self) -> u16 {
+        self.phnum
+    }
+
+    /// Reads the `index`-th program header.
+    pub fn segment(&mut self, index: u16) -> Result<Segment, ElfError<S::Error>> {
+        assert!(index < self.phnum);
+        let mut bytes = [0; PROGRAM_HEADER_SIZE];
+        let offset = self.phoff + u64::from(index) * PROGRAM_HEADER_SIZE as u64;
+        read_exact(&mut self.source, offset, &mut bytes)?;
+        Ok(Segment::parse(&bytes))
+    }
+
+    /// Reads the component notes into `buffer` and parses them.
+    ///
+    /// Fails with [`ElfError::NotesTooLarge`] if the note segment is larger
+    /// than `buffer`.
+    pub fn notes<'a>(&mut self, buffer: &'a mut [u8]) -> Result<Metadata<'a>, ElfError<S::Error>> {
+        let mut found = None;
+        for index in 0..self.phnum {
+            let segment = self.segment(index)?;
+            if segment.kind != PT_NOTE {
+                continue;
+            }
+            let len = usize::try_from(segment.filesz)
+                .ok()
+                .filter(|&len| len <= buffer.len())
+                .ok_or(ElfError::NotesTooLarge)?;
+            read_exact(&mut self.source, segment.offset, &mut buffer[..len])?;
+            if !Metadata::parse(&buffer[..len])?.is_empty() {
+                // The linker puts every `.note.harmony` section in the same
+                // segment.
+                if found.is_some() {
+                    return Err(NoteError::Duplicate.into());
+                }
+                found = Some(segment);
+            }
+        }
+        let Some(segment) = found else {
+            return Ok(Metadata::default());
+        };
+        // Other note segments may have been read into the buffer since.
+        let len = segment.filesz as usize;
+        read_exact(&mut self.source, segment.offset, &mut buffer[..len])?;
+        Ok(Metadata::parse(&buffer[..len])?)
+    }
+
+    /// Checks every loadable segment, then hands `map` the contents of each of
+    /// their pages in turn.
+    ///
+    /// `map` gets the address of the page, the permissions of the segment and
+    /// the page, which is zero past the bytes the segment has in the file. A
+    /// page shared by two segments is handed out for each of them. Segments
+    /// can only be writable and executable if `jit` is set.
+    pub fn load<F>(
+        &mut self,
+        layout: &Layout,
+        jit: bool,
+        mut map: F,
+    ) -> Result<(), ElfError<S::Error>>
+    where
+        F: FnMut(usize, Permissions, &[u8; PAGE_SIZE]) -> Result<(), CapError>,
+    {
+        layout.validate()?;
+        let len = self.source.len();
+        for index in 0..self.phnum {
+            let segment = self.segment(index)?;
+            if segment.kind == PT_LOAD {
+                segment.check(len, layout, jit)?;
+            }
+        }
+        let mut page = [0; PAGE_SIZE];
+        for index in 0..self.phnum {
+            let segment = self.segment(index)?;
+            if segment.kind != PT_LOAD {
+                continue;
+            }
+            // The checks above keep all of this in the code region.
+            let start = segment.vaddr as usize;
+            let end = start + segment.memsz as usize;
+            let file_end = start + segment.filesz as usize;
+            let mut address = start - start % PAGE_SIZE;
+            while address < end {
+                page.fill(0);
+                let from = address.max(start);
+                let to = (address + PAGE_SIZE).min(file_end);
+                if from < to {
+                    let offset = segment.offset + (from - start) as u64;
+                    let within = from - address;
+                    read_exact(
+                        &mut self.source,
+                        offset,
+                        &mut page[within..within + to - from],
+                    )?;
+                }
+                map(address, segment.permissions(), &page).map_err(ElfError::Map)?;
+                address += PAGE_SIZE;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fills `buf` from `offset`, failing if the file ends first.
+fn read_exact<S: Source>(
+    source: &mut S,
+    offset: u64,
+    buf: &mut [u8],
+) -> Result<(), ElfError<S::Error>> {
+    let mut done = 0;
+    while done < buf.len() {
+        match source.read_at(offset + done as u64, &mut buf[done..]) {
+            Ok(0) => return Err(ElfError::Truncated),
+            Ok(read) => done += read,
+            Err(e) => return Err(ElfError::Read(e)),
+        }
+    }
+    Ok(())
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::layout::STANDARD;
+
+    const CODE: usize = STANDARD.code.start;
+
+    fn put(image: &mut [u8], offset: usize, bytes: &[u8]) {
+        image[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// A program with a read-only segment of `data` that starts in the middle
+    /// of a page and runs two pages past the end of its file contents.
+    fn program(flags: u32, vaddr: usize, data: &[u8]) -> Vec<u8> {
+        let data_offset = HEADER_SIZE + PROGRAM_HEADER_SIZE;
+        let mut image = vec![0; data_offset + data.len()];
+        put(&mut image, 0, &ELF_MAGIC);
+        image[4] = ELFCLASS64;
+        image[5] = ELFDATA2LSB;
+        put(&mut image, 16, &ET_EXEC.to_le_bytes());
+        put(&mut image, 18, &EM_X86_64.to_le_bytes());
+        put(&mut image, 24, &(vaddr as u64).to_le_bytes());
+        put(&mut image, 32, &(HEADER_SIZE as u64).to_le_bytes());
+        put(&mut image, 54, &(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+        put(&mut image, 56, &1u16.to_le_bytes());
+        let header = HEADER_SIZE;
+        put(&mut image, header, &PT_LOAD.to_le_bytes());
+        put(&mut image, header + 4, &flags.to_le_bytes());
+        put(&mut image, header + 8, &(data_offset as u64).to_le_bytes());
+        put(&mut image, header + 16, &(vaddr as u64).to_le_bytes());
+        put(&mut image, header + 32, &(data.len() as u64).to_le_bytes());
+        let memsz = (data.len() + 2 * PAGE_SIZE) as u64;
+        put(&mut image, header + 40, &memsz.to_le_bytes());
+        put(&mut image, data_offset, data);
+        image
+    }
+
+    /// The address and contents of every page handed out.
+    type Pages = Vec<(usize, Vec<u8>)>;
+
+    fn load<S: Source>(program: S, jit: bool) -> Result<Pages, ElfError<S::Error>> {
+        let mut pages = Vec::new();
+        Program::open(program)?.load(&STANDARD, jit, |address, _, page| {
+            pages.push((address, page.to_vec()));
+            Ok(())
+        })?;
+        Ok(pages)
+    }
+
+    #[test]
+    fn streams_segments_a_page_at_a_time() {
+        let data: Vec<u8> = (0..PAGE_SIZE as u32 + 10).map(|i| i as u8 | 1).collect();
+        let image = program(PF_R | PF_W, CODE + 0x800, &data);
+        let mut largest_read = 0;
+        let source = ReadAt {
+            len: image.len() as u64,
+            read: |offset, buf: &mut [u8]| {
+                largest_read = largest_read.max(buf.len());
+                image.as_slice().read_at(offset, buf)
+            },
+        };
+        let pages = load(source, false).unwrap();
+        assert!(largest_read <= PAGE_SIZE);
+
+        let addresses: Vec<_> = pages.iter().map(|&(address, _)| address).collect();
+        let expected: Vec<_> = (0..4).map(|page| CODE + page * PAGE_SIZE).collect();
+        assert_eq!(addresses, expected);
+        let mut memory: Vec<u8> = pages.into_iter().flat_map(|(_, page)| page).collect();
+        assert!(memory[..0x800].iter().all(|&byte| byte == 0));
+        let rest = memory.split_off(0x800);
+        assert_eq!(rest[..data.len()], data[..]);
+        assert!(rest[data.len()..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn checks_segments_before_mapping() {
+        let data = [1; 16];
+        let writable_code = program(PF_R | PF_W | PF_X, CODE, &data);
+        assert_eq!(
+            load(writable_code.as_slice(), false),
+            Err(ElfError::WriteExecute)
+        );
+        assert!(load(writable_code.as_slice(), true).is_ok());
+
+        let outside = program(PF_R, STANDARD.heap.start, &data);
+        assert_eq!(
+            load(outside.as_slice(), false),
+            Err(ElfError::Layout(LayoutError::OutsideRegion))
+        );
+        let unreadable = program(PF_X, CODE, &data);
+        assert_eq!(
+            load(unreadable.as_slice(), false),
+            Err(ElfError::BadSegment)
+        );
+
+        let mut truncated = program(PF_R, CODE, &data);
+        truncated.truncate(truncated.len() - 1);
+        assert_eq!(load(truncated.as_slice(), false), Err(ElfError::Truncated));
+        let mut not_elf = program(PF_R, CODE, &data);
+        not_elf[0] = 0;
+        assert!(matches!(
+            Program::open(not_elf.as_slice()),
+            Err(ElfError::NotSupported)
+        ));
+    }
+
+    #[test]
+    fn reads_notes_into_the_buffer_given() {
+        let image = program(PF_R, CODE, &[1; 16]);
+        let mut program = Program::open(image.as_slice()).unwrap();
+        assert_eq!(program.entry(), CODE as u64);
+        assert_eq!(program.segment(0).unwrap().kind, PT_LOAD);
+        let mut buffer = [0; 64];
+        assert!(program.notes(&mut buffer).unwrap().is_empty());
+    }
+}
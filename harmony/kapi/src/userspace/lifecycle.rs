@@ -0,0 +1,407 @@
+//! Shutting components down and getting their resources back.
+//!
+//! The composer asks a component to stop through its management endpoint and
+//! waits for the component to acknowledge, after which the component must not
+//! run again (it parks its threads). If it doesn't answer before the timeout,
+//! the composer tears it down anyway. Either way, teardown then:
+//!
+//! 1. Kills the component's [hierarchy](crate::ops::hierarchy), which stops
+//!    its threads and those of its children.
+//! 2. Clears the component's address space, releasing the frames mapped in it.
+//! 3. Transfers the component's regions back to the composer.
+//! 4. Drops the component's threads, page tables and capability tables.
+//!
+//! FIXME: There are no management endpoints yet, so the management channel is
+//! a trait the composer implements over whatever it uses to talk to the
+//! component. A component started without a hierarchy can't be stopped: if it
+//! never acknowledges, it keeps running until the kernel drops its last thread
+//! reference.
+
+use core::time::Duration;
+
+use trie::SlotId;
+
+use super::time::Instant;
+use crate::ops::cap_table::{CapTableOp, SLOT_COUNT};
+use crate::ops::hierarchy::HierarchyOp;
+use crate::ops::page_table::PageTableOp;
+use crate::ops::region::RegionOp;
+use crate::ops::SyscallOp as _;
+use crate::raw::{CapError, CapId};
+
+/// The composer's end of a component's management endpoint.
+pub trait Management {
+    /// Asks the component to stop.
+    fn request_shutdown(&mut self) -> Result<(), CapError>;
+
+    /// Whether the component acknowledged the shutdown request and parked.
+    fn acknowledged(&mut self) -> bool;
+}
+
+/// How a component was stopped.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stopped {
+    /// The component acknowledged the request.
+    Graceful,
+    /// The component didn't acknowledge in time.
+    Forced,
+}
+
+/// A shutdown request waiting for the component to acknowledge it.
+#[derive(Debug, Copy, Clone)]
+pub struct Shutdown {
+    deadline: Instant,
+}
+
+impl Shutdown {
+    /// Sends the shutdown request, giving the component until `now + timeout`
+    /// to acknowledge it.
+    ///
+    /// A component that can't be reached is stopped by force.
+    pub fn start(management: &mut impl Management, now: Instant, timeout: Duration) -> Self {
+        let deadline = match management.request_shutdown() {
+            Ok(()) => now
+                .checked_add(timeout)
+                .unwrap_or(Instant::from_nanos(u64::MAX)),
+            Err(_) => now,
+        };
+        Self { deadline }
+    }
+
+    /// Returns how the component stopped, or `None` if it still has time to
+    /// acknowledge.
+    pub fn poll(&self, management: &mut impl Management, now: Instant) -> Option<Stopped> {
+        if management.acknowledged() {
+            Some(Stopped::Graceful)
+        } else if now >= self.deadline {
+            Some(Stopped::Forced)
+        } else {
+            None
+        }
+    }
+
+    /// Waits until the component acknowledges or the deadline passes.
+    pub fn wait(&self, management: &mut impl Management) -> Stopped {
+        loop {
+            if let Some(stopped) = self.poll(management, Instant::now()) {
+                return stopped;
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// The capabilities a composer kept for a component it started.
+#[derive(Debug, Copy, Clone)]
+pub struct Component<'a> {
+    /// The hierarchy holding the component's threads, if it was started in
+    /// one.
+    pub hierarchy: Option<CapId>,
+    /// The root page table of the component's address space.
+    pub address_space: CapId,
+    /// The regions the component was given.
+    pub regions: &'a [CapId],
+    /// The table node holding the component's kernel objects.
+    pub table: CapId,
+    /// The slots of `table` holding the component's objects, in the order
+    /// they should be dropped: threads, then page tables and capability
+    /// tables.
+    pub objects: &'a [SlotId<SLOT_COUNT>],
+}
+
+/// What teardown got back.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Reclaimed {
+    /// Pages that were mapped in the address space.
+    pub unmapped: usize,
+    /// Frames transferred back from the component's regions.
+    pub frames: usize,
+    /// Objects dropped.
+    pub objects: usize,
+}
+
+/// Reclaims everything the component was given.
+///
+/// Each non-empty region is transferred whole into the empty `(table, slot)`
+/// returned by `destination`. Regions and objects that are already gone are
+/// skipped, so teardown can be retried after an error. Killing the hierarchy
+/// unpins the component's pages, so its devices should be stopped first.
+/// Without a hierarchy, pinned pages make it fail with
+/// [`CapError::ResourceInUse`] until they're unpinned.
+pub fn teardown(
+    component: &Component<'_>,
+    mut destination: impl FnMut() -> Result<(CapId, CapId), CapError>,
+) -> Result<Reclaimed, CapError> {
+    if let Some(hierarchy) = component.hierarchy {
+        // SAFETY: The hierarchy belongs to the component, not to us.
+        match unsafe { HierarchyOp::KillTree.syscall(hierarchy) } {
+            Ok(_) | Err(CapError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    // SAFETY: Clearing a page table doesn't touch our memory.
+    let unmapped =
+        unsafe { PageTableOp::Clear { release: true }.syscall(component.address_space)? };
+    let mut reclaimed = Reclaimed {
+        unmapped,
+        ..Default::default()
+    };
+
+    for &region in component.regions {
+        // SAFETY: Reading the size of a region doesn't touch any memory.
+        let frames = match unsafe { RegionOp::Frames.syscall(region) } {
+            Ok(0) | Err(CapError::NotFound) => continue,
+            result => result?,
+        };
+        let (table, slot) = destination()?;
+        let transfer = RegionOp::Transfer {
+            offset: 0,
+            frames,
+            table,
+            slot,
+        };
+        // SAFETY: The region belongs to the component, not to us.
+        unsafe { transfer.syscall(region)? };
+        reclaimed.frames += frames;
+    }
+
+    for &slot in component.objects {
+        // SAFETY: The objects belong to the component, which is stopped.
+        match unsafe { CapTableOp::Drop { slot }.syscall(component.table) } {
+            Ok(_) => reclaimed.objects += 1,
+            Err(CapError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(reclaimed)
+}
+
+/// Stops the component and reclaims its resources.
+///
+/// Teardown happens whether or not the component acknowledged in time.
+pub fn shutdown(
+    management: &mut impl Management,
+    timeout: Duration,
+    component: &Component<'_>,
+    destination: impl FnMut() -> Result<(CapId, CapId), CapError>,
+) -> Result<(Stopped, Reclaimed), CapError> {
+    let stopped = Shutdown::start(management, Instant::now(), timeout).wait(management);
+    let reclaimed = teardown(component, destination)?;
+    Ok((stopped, reclaimed))
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::ops::clock::BOOT_CLOCK_CAP;
+    use crate::raw::RawOperation;
+    use crate::testing::{MockKernel, MockResource};
+    use crate::userspace::time;
+
+    const COMPOSER: CapId = CapId::new(0);
+    const COMPONENT_TABLE: CapId = CapId::new(1);
+    const ADDRESS_SPACE: CapId = CapId::new(2);
+    const REGION: CapId = CapId::new(5);
+    const EMPTY_REGION: CapId = CapId::new(6);
+    const HIERARCHY: CapId = CapId::new(7);
+    const RECLAIMED: CapId = CapId::new(10);
+
+    /// A component that acknowledges after being polled `after` times.
+    struct Endpoint {
+        reachable: bool,
+        requested: bool,
+        after: Option<usize>,
+    }
+
+    impl Management for Endpoint {
+        fn request_shutdown(&mut self) -> Result<(), CapError> {
+            if !self.reachable {
+                return Err(CapError::NotFound);
+            }
+            self.requested = true;
+            Ok(())
+        }
+
+        fn acknowledged(&mut self) -> bool {
+            match &mut self.after {
+                Some(0) => self.requested,
+                Some(after) => {
+                    *after -= 1;
+                    false
+                }
+                None => false,
+            }
+        }
+    }
+
+    fn slot(slot: usize) -> SlotId<SLOT_COUNT> {
+        slot.try_into().unwrap()
+    }
+
+    fn kernel() -> MockKernel {
+        let mut kernel = MockKernel::new();
+        let component = kernel.new_table();
+        let root = kernel.root();
+        let resources = [
+            (COMPOSER, MockResource::CapTable(root)),
+            (COMPONENT_TABLE, MockResource::CapTable(component)),
+            (ADDRESS_SPACE, MockResource::PageTable { level: 4 }),
+            (
+                REGION,
+                MockResource::Region {
+                    base: 0x10_0000,
+                    frames: 16,
+                },
+            ),
+            (
+                EMPTY_REGION,
+                MockResource::Region {
+                    base: 0x20_0000,
+                    frames: 0,
+                },
+            ),
+            (BOOT_CLOCK_CAP, MockResource::Clock { nanos: 0 }),
+            (
+                HIERARCHY,
+                MockResource::Hierarchy {
+                    faults: 0,
+                    killed: false,
+                },
+            ),
+        ];
+        for (cap, resource) in resources {
+            kernel.insert(cap, resource).unwrap();
+        }
+        // The component's objects, seen through its table.
+        kernel.on(RawOperation::CapTableDrop, {
+            let mut objects = [true, true, false];
+            move |_, cap, args| {
+                assert_eq!(cap, COMPONENT_TABLE);
+                let Ok(CapTableOp::Drop { slot }) = CapTableOp::<SLOT_COUNT>::from_args(args)
+                else {
+                    unreachable!();
+                };
+                match objects.get_mut(usize::from(slot)) {
+                    Some(present) if *present => {
+                        *present = false;
+                        Ok(0)
+                    }
+                    _ => Err(CapError::NotFound),
+                }
+            }
+        });
+        kernel
+    }
+
+    fn component(objects: &[SlotId<SLOT_COUNT>]) -> Component<'_> {
+        Component {
+            hierarchy: Some(HIERARCHY),
+            address_space: ADDRESS_SPACE,
+            regions: &[REGION, EMPTY_REGION],
+            table: COMPONENT_TABLE,
+            objects,
+        }
+    }
+
+    #[test]
+    fn times_out() {
+        let mut endpoint = Endpoint {
+            reachable: true,
+            requested: false,
+            after: Some(2),
+        };
+        let start = Instant::from_nanos(1_000);
+        let shutdown = Shutdown::start(&mut endpoint, start, Duration::from_nanos(500));
+        assert!(endpoint.requested);
+        assert_eq!(shutdown.poll(&mut endpoint, start), None);
+        assert_eq!(
+            shutdown.poll(&mut endpoint, Instant::from_nanos(1_500)),
+            Some(Stopped::Forced)
+        );
+        assert_eq!(
+            shutdown.poll(&mut endpoint, Instant::from_nanos(1_500)),
+            Some(Stopped::Graceful)
+        );
+
+        let mut unreachable = Endpoint {
+            reachable: false,
+            requested: false,
+            after: None,
+        };
+        let shutdown = Shutdown::start(&mut unreachable, start, Duration::MAX);
+        assert_eq!(
+            shutdown.poll(&mut unreachable, start),
+            Some(Stopped::Forced)
+        );
+    }
+
+    #[test]
+    fn reclaims_resources() {
+        let kernel = kernel().install();
+        time::init(BOOT_CLOCK_CAP).unwrap();
+        let mut endpoint = Endpoint {
+            reachable: true,
+            requested: false,
+            after: Some(3),
+        };
+        let objects = [slot(0), slot(1), slot(2)];
+        let mut next = RECLAIMED;
+        let (stopped, reclaimed) = shutdown(
+            &mut endpoint,
+            Duration::from_secs(1),
+            &component(&objects),
+            || {
+                let slot = next;
+                next = CapId::new(u32::from(next) + 1);
+                Ok((COMPOSER, slot))
+            },
+        )
+        .unwrap();
+        assert_eq!(stopped, Stopped::Graceful);
+        assert_eq!(
+            reclaimed,
+            Reclaimed {
+                unmapped: 0,
+                frames: 16,
+                objects: 2,
+            }
+        );
+        kernel.with(|kernel| {
+            assert_eq!(
+                kernel.resource(RECLAIMED),
+                Some(MockResource::Region {
+                    base: 0x10_0000,
+                    frames: 16
+                })
+            );
+            assert_eq!(kernel.resource(REGION), None);
+            assert_eq!(kernel.resource(CapId::new(11)), None);
+            let ops: Vec<_> = kernel
+                .ops()
+                .into_iter()
+                .filter(|op| *op != RawOperation::ClockGetTimeNs)
+                .collect();
+            assert_eq!(
+                ops[1..],
+                [
+                    RawOperation::HierarchyKillTree,
+                    RawOperation::PageTableClear,
+                    RawOperation::MemoryRegionFrames,
+                    RawOperation::MemoryRegionTransfer,
+                    RawOperation::MemoryRegionFrames,
+                    RawOperation::CapTableDrop,
+                    RawOperation::CapTableDrop,
+                    RawOperation::CapTableDrop,
+                ]
+            );
+        });
+
+        // Everything is already gone.
+        let reclaimed = teardown(&component(&objects), || Err(CapError::OutOfMemory)).unwrap();
+        assert_eq!(reclaimed.objects, 0);
+    }
+}
@@ -0,0 +1,206 @@
+//! Finding memory close to the running core.
+//!
+//! The device inventory lists the NUMA nodes with their processors and
+//! memory ranges. A region is a range of physical frames, so which node its
+//! frames belong to follows from its base. [`take_local`] moves the frames of
+//! a region that sit in a node's memory into a region of their own, which a
+//! component can then retype for the threads running on that node.
+//!
+//! Regions only give up frames at either end, so local frames in the middle
+//! of a region can't be taken this way.
+
+use addr::PAGE_SIZE;
+
+use crate::devices::DeviceInventory;
+use crate::ops::region::RegionOp;
+use crate::ops::SyscallOp as _;
+use crate::raw::{CapError, CapId};
+
+/// The id of the local APIC of the core this runs on.
+///
+/// The thread may be moved to another core right after, unless its affinity
+/// pins it to this one.
+#[cfg(target_arch = "x86_64")]
+pub fn current_apic_id() -> u32 {
+    use core::arch::x86_64::{__cpuid, __cpuid_count};
+
+    // SAFETY: cpuid has no side effects and every x86_64 processor has it.
+    unsafe {
+        if __cpuid(0).eax >= 0xB {
+            // The x2APIC topology leaf has the full 32-bit id.
+            let topology = __cpuid_count(0xB, 0);
+            if topology.ebx != 0 {
+                return topology.edx;
+            }
+        }
+        __cpuid(1).ebx >> 24
+    }
+}
+
+/// The node of the core this runs on, or `None` if the firmware didn't
+/// describe any nodes.
+#[cfg(target_arch = "x86_64")]
+pub fn current_node(inventory: &DeviceInventory<'_>) -> Option<u32> {
+    inventory.node_of_cpu(current_apic_id())
+}
+
+/// Moves up to `frames` frames of `region` that are in the memory of `node`
+/// into a new region in `slot` of `table`.
+///
+/// Takes frames from the start of the region if its first frame is local and
+/// from the end otherwise. Returns the number of frames moved, which is 0 if
+/// neither end of the region is in the node's memory.
+pub fn take_local(
+    inventory: &DeviceInventory<'_>,
+    node: u32,
+    region: CapId,
+    frames: usize,
+    table: CapId,
+    slot: CapId,
+) -> Result<usize, CapError> {
+    // SAFETY: Reading the bounds of a region doesn't touch any memory.
+    let (base, total) = unsafe {
+        (
+            RegionOp::Base.syscall(region)?,
+            RegionOp::Frames.syscall(region)?,
+        )
+    };
+    let local = |frame: usize| {
+        let address = (base + frame * PAGE_SIZE) as u64;
+        inventory.node_of_address(address) == Some(node)
+    };
+    let wanted = frames.min(total);
+    let head = (0..wanted).take_while(|&frame| local(frame)).count();
+    let (offset, moved) = if head > 0 {
+        (0, head)
+    } else {
+        let tail = (0..wanted)
+            .take_while(|&frame| local(total - 1 - frame))
+            .count();
+        (total - tail, tail)
+    };
+    if moved == 0 {
+        return Ok(0);
+    }
+    let transfer = RegionOp::Transfer {
+        offset,
+        frames: moved,
+        table,
+        slot,
+    };
+    // SAFETY: The frames aren't mapped by us, they're only moved.
+    unsafe { transfer.syscall(region)? };
+    Ok(moved)
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::devices::{BusPath, DeviceId, DeviceResource, InventoryWriter};
+    use crate::testing::{MockKernel, MockResource};
+
+    const TABLE: CapId = CapId::new(0);
+    const REGION: CapId = CapId::new(1);
+    const LOCAL: CapId = CapId::new(2);
+    /// Where node 1's memory starts.
+    const BOUNDARY: usize = 0x40_0000;
+
+    fn inventory(buf: &mut [u8]) -> DeviceInventory<'_> {
+        let mut writer = InventoryWriter::new(buf).unwrap();
+        for (id, start) in [(0, 0), (1, BOUNDARY as u64)] {
+            writer
+                .add_device(
+                    BusPath::Node { id },
+                    DeviceId::Node,
+                    &[
+                        DeviceResource::Memory {
+                            start,
+                            length: BOUNDARY as u64,
+                        },
+                        DeviceResource::Cpu { apic_id: id },
+                    ],
+                )
+                .unwrap();
+        }
+        let len = writer.len();
+        DeviceInventory::parse(&buf[..len]).unwrap()
+    }
+
+    /// A region of 8 frames with 2 on node 0 and the rest on node 1.
+    fn kernel() -> MockKernel {
+        let mut kernel = MockKernel::new();
+        let root = kernel.root();
+        kernel.insert(TABLE, MockResource::CapTable(root)).unwrap();
+        kernel
+            .insert(
+                REGION,
+                MockResource::Region {
+                    base: BOUNDARY - 2 * PAGE_SIZE,
+                    frames: 8,
+                },
+            )
+            .unwrap();
+        kernel
+    }
+
+    #[test]
+    fn takes_local_frames_from_either_end() {
+        let mut buf = [0; 256];
+        let inventory = inventory(&mut buf);
+        assert_eq!(inventory.node_of_cpu(1), Some(1));
+
+        let kernel = kernel().install();
+        assert_eq!(take_local(&inventory, 0, REGION, 4, TABLE, LOCAL), Ok(2));
+        kernel.with(|kernel| {
+            assert_eq!(
+                kernel.resource(LOCAL),
+                Some(MockResource::Region {
+                    base: BOUNDARY - 2 * PAGE_SIZE,
+                    frames: 2
+                })
+            );
+            assert_eq!(
+                kernel.resource(REGION),
+                Some(MockResource::Region {
+                    base: BOUNDARY,
+                    frames: 6
+                })
+            );
+        });
+        // Nothing left on node 0.
+        assert_eq!(
+            take_local(&inventory, 0, REGION, 4, TABLE, CapId::new(3)),
+            Ok(0)
+        );
+        assert_eq!(
+            take_local(&inventory, 1, REGION, 4, TABLE, CapId::new(3)),
+            Ok(4)
+        );
+        kernel.with(|kernel| {
+            assert_eq!(
+                kernel.resource(REGION),
+                Some(MockResource::Region {
+                    base: BOUNDARY + 4 * PAGE_SIZE,
+                    frames: 2
+                })
+            );
+        });
+    }
+
+    #[test]
+    fn takes_from_the_end_when_the_start_is_remote() {
+        let mut buf = [0; 256];
+        let inventory = inventory(&mut buf);
+        let kernel = kernel().install();
+        assert_eq!(take_local(&inventory, 1, REGION, 3, TABLE, LOCAL), Ok(3));
+        kernel.with(|kernel| {
+            assert_eq!(
+                kernel.resource(LOCAL),
+                Some(MockResource::Region {
+                    base: BOUNDARY + 3 * PAGE_SIZE,
+                    frames: 3
+                })
+            );
+        });
+    }
+}
@@ -0,0 +1,56 @@
+//! Page tables owned by a component.
+
+use super::cap::{Owned, Slot};
+use crate::ops::page_table::PageTableOp;
+use crate::ops::SyscallOp as _;
+use crate::raw::{CapError, CapId};
+
+/// A page table capability, dropped along with the value.
+///
+/// Tables that still map something can't be dropped, so [`clear`](Self::clear)
+/// them first or [`release`](Self::release) them to see the error.
+#[derive(Debug)]
+pub struct PageTable {
+    cap: Owned,
+}
+
+impl PageTable {
+    /// Takes ownership of the page table capability `id`.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Owned::from_raw`].
+    pub unsafe fn from_raw(id: CapId, slot: Option<Slot>) -> Self {
+        Self {
+            // SAFETY: Upheld by the caller.
+            cap: unsafe { Owned::from_raw(id, slot) },
+        }
+    }
+
+    /// Gives up ownership without dropping the table.
+    pub fn into_raw(self) -> (CapId, Option<Slot>) {
+        self.cap.into_raw()
+    }
+
+    pub fn id(&self) -> CapId {
+        self.cap.id()
+    }
+
+    /// Unmaps everything below the table, see [`PageTableOp::Clear`], and
+    /// returns the number of pages that were unmapped.
+    ///
+    /// # Safety
+    ///
+    /// Nothing still in use may be mapped through the table, e.g. the
+    /// caller's own code or stack.
+    pub unsafe fn clear(&self, release: bool) -> Result<usize, CapError> {
+        // SAFETY: Upheld by the caller.
+        unsafe { PageTableOp::Clear { release }.syscall(self.id()) }
+    }
+
+    /// Drops the table, returning the error that dropping the value would
+    /// ignore.
+    pub fn release(self) -> Result<(), CapError> {
+        self.cap.release()
+    }
+}
@@ -0,0 +1,137 @@
+//! Counting hardware events.
+//!
+//! A [`Counter`] programs one of the counters of a performance counter
+//! capability and reads it back through the kernel, which only ever writes
+//! the counter registers on the component's behalf. [`measure`] counts the
+//! events caused by a closure.
+
+use crate::ops::perf::{PerfEvent, PerfMode, PerfOp};
+use crate::ops::SyscallOp as _;
+use crate::raw::{CapError, CapId};
+
+/// A counter counting an event in userspace.
+#[derive(Debug)]
+pub struct Counter {
+    cap: CapId,
+    index: u8,
+    event: PerfEvent,
+}
+
+impl Counter {
+    /// Starts counter `index` of the capability in `cap` counting `event`
+    /// from 0.
+    pub fn start(cap: CapId, index: u8, event: PerfEvent) -> Result<Self, CapError> {
+        let op = PerfOp::Program {
+            counter: index,
+            event,
+            mode: PerfMode::User,
+            period: None,
+        };
+        // SAFETY: Programming a counter doesn't touch our memory.
+        unsafe { op.syscall(cap)? };
+        Ok(Self { cap, index, event })
+    }
+
+    pub fn event(&self) -> PerfEvent {
+        self.event
+    }
+
+    /// The events counted so far.
+    pub fn read(&self) -> Result<u64, CapError> {
+        // SAFETY: Reading a counter doesn't touch our memory.
+        unsafe {
+            PerfOp::Read {
+                counter: self.index,
+            }
+            .syscall(self.cap)
+        }
+    }
+
+    /// Stops the counter and returns the events it counted.
+    pub fn stop(self) -> Result<u64, CapError> {
+        let count = self.read()?;
+        // SAFETY: Stopping a counter doesn't touch our memory.
+        unsafe {
+            PerfOp::Stop {
+                counter: self.index,
+            }
+            .syscall(self.cap)?
+        };
+        Ok(count)
+    }
+}
+
+/// Runs `f` and returns its result with the number of `event`s it caused,
+/// counted on the first counter of the capability in `cap`.
+///
+/// The count includes the syscalls that read the counter, so it's only
+/// meaningful for closures that cause many more events than that.
+pub fn measure<R>(
+    cap: CapId,
+    event: PerfEvent,
+    f: impl FnOnce() -> R,
+) -> Result<(R, u64), CapError> {
+    let counter = Counter::start(cap, 0, event)?;
+    let result = f();
+    let count = counter.stop()?;
+    Ok((result, count))
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use core::cell::Cell;
+
+    use super::*;
+    use crate::ops::perf::BOOT_PERF_CAP;
+    use crate::raw::RawOperation;
+    use crate::testing::{MockKernel, MockResource};
+
+    #[test]
+    fn measures_closures() {
+        extern crate std;
+        use std::rc::Rc;
+
+        let mut kernel = MockKernel::new();
+        kernel
+            .insert(BOOT_PERF_CAP, MockResource::PerfCounter { counters: 2 })
+            .unwrap();
+        let reads = Rc::new(Cell::new(0));
+        let counted = reads.clone();
+        kernel.on(RawOperation::PerfRead, move |_, _, _| {
+            counted.set(counted.get() + 1);
+            Ok(1_000)
+        });
+        let kernel = kernel.install();
+
+        let (result, instructions) =
+            measure(BOOT_PERF_CAP, PerfEvent::Instructions, || 6 * 7).unwrap();
+        assert_eq!((result, instructions), (42, 1_000));
+        assert_eq!(reads.get(), 1);
+        kernel.with(|kernel| {
+            assert_eq!(
+                kernel.ops(),
+                [
+                    RawOperation::PerfProgram,
+                    RawOperation::PerfRead,
+                    RawOperation::PerfStop
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn refuses_missing_counters() {
+        let mut kernel = MockKernel::new();
+        kernel
+            .insert(BOOT_PERF_CAP, MockResource::PerfCounter { counters: 2 })
+            .unwrap();
+        let _kernel = kernel.install();
+        assert_eq!(
+            Counter::start(BOOT_PERF_CAP, 2, PerfEvent::Cycles).unwrap_err(),
+            CapError::InvalidArgument
+        );
+        let counter = Counter::start(BOOT_PERF_CAP, 1, PerfEvent::CacheMisses).unwrap();
+        assert_eq!(counter.event(), PerfEvent::CacheMisses);
+        assert_eq!(counter.stop(), Ok(0));
+    }
+}
@@ -0,0 +1,59 @@
+//! Random bytes for components.
+//!
+//! The kernel's generator hands out at most [`MAX_RANDOM_LEN`] bytes per call,
+//! so [`fill`] asks as many times as the buffer needs.
+
+use crate::ops::system::{SystemOp, MAX_RANDOM_LEN};
+use crate::ops::SyscallOp as _;
+use crate::raw::{CapError, CapId};
+
+/// Fills `buffer` with random bytes from the generator in `system`.
+pub fn fill(system: CapId, buffer: &mut [u8]) -> Result<(), CapError> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        let rest = &mut buffer[filled..];
+        let op = SystemOp::GetRandom {
+            buffer: rest.as_mut_ptr(),
+            len: rest.len().min(MAX_RANDOM_LEN),
+        };
+        // SAFETY: The buffer is valid for writes for the duration of the call.
+        match unsafe { op.syscall(system)? } {
+            0 => return Err(CapError::Internal),
+            written => filled += written,
+        }
+    }
+    Ok(())
+}
+
+/// Returns a random word from the generator in `system`, e.g. for a stack
+/// canary.
+pub fn u64(system: CapId) -> Result<u64, CapError> {
+    let mut bytes = [0; 8];
+    fill(system, &mut bytes)?;
+    Ok(u64::from_ne_bytes(bytes))
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::ops::system::BOOT_SYSTEM_CAP;
+    use crate::raw::RawOperation;
+    use crate::testing::{MockKernel, MockResource};
+
+    #[test]
+    fn fills_buffers_larger_than_a_call() {
+        let mut kernel = MockKernel::new();
+        kernel
+            .insert(BOOT_SYSTEM_CAP, MockResource::System { seed: 1 })
+            .unwrap();
+        let kernel = kernel.install();
+
+        let mut buffer = [0; MAX_RANDOM_LEN + 10];
+        fill(BOOT_SYSTEM_CAP, &mut buffer).unwrap();
+        assert!(buffer[MAX_RANDOM_LEN..].iter().any(|byte| *byte != 0));
+        assert_ne!(u64(BOOT_SYSTEM_CAP).unwrap(), u64(BOOT_SYSTEM_CAP).unwrap());
+        kernel.with(|kernel| {
+            assert_eq!(kernel.ops(), [RawOperation::SystemGetRandom; 4]);
+        });
+    }
+}
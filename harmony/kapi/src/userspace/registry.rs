@@ -0,0 +1,367 @@
+//! Finding services by name at runtime.
+//!
+//! The composer wires most connections up front, but components started
+//! later need a way to find the services already running. The registry keeps
+//! the interface instances providers registered under a `(uuid, name)` pair,
+//! and when a client asks for one it has the [`Broker`] hand the client a new
+//! capability to the provider, badged so that the provider can tell its
+//! clients apart.
+//!
+//! Who may provide or use what is decided by a [`Rule`] list the composer
+//! gives the registry. Anything the rules don't allow is denied.
+//!
+//! FIXME: There are no badged sync-call or endpoint capabilities yet, so the
+//! registry can't mint them itself and the capability handling is behind
+//! [`Broker`], like the other channels the composer implements until there
+//! are endpoints.
+
+use crate::component::Uuid;
+use crate::raw::CapError;
+
+/// Longest name an instance can be registered under.
+pub const NAME_LEN: usize = 32;
+
+/// Identifies a component to the registry. The composer assigns them and
+/// passes them along with every request, so components can't forge them.
+pub type ComponentId = u64;
+
+/// What a [`Rule`] lets a component do.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Access {
+    Provide,
+    Use,
+}
+
+/// Allows `component` to provide or use instances of `uuid`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rule<'a> {
+    pub component: ComponentId,
+    pub uuid: Uuid,
+    /// Only the instance with this name, or any of them if `None`.
+    pub name: Option<&'a str>,
+    pub access: Access,
+}
+
+/// Mints the capabilities that connect clients to providers.
+pub trait Broker {
+    /// Gives `client` a new capability to `provider` that carries `badge`.
+    fn connect(
+        &mut self,
+        provider: ComponentId,
+        client: ComponentId,
+        badge: u64,
+    ) -> Result<(), CapError>;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RegistryError {
+    /// The policy doesn't allow it.
+    Denied,
+    /// No instance is registered under the name. Clients started before
+    /// their provider can retry later.
+    NotFound,
+    /// Another instance is registered under the name.
+    Taken,
+    NameTooLong,
+    /// The registry holds as many instances as it can.
+    Full,
+    Cap(CapError),
+}
+
+impl From<CapError> for RegistryError {
+    fn from(value: CapError) -> Self {
+        RegistryError::Cap(value)
+    }
+}
+
+/// A connection the registry brokered.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Connection {
+    pub provider: ComponentId,
+    /// The badge the client's capability carries. Badges are never reused.
+    pub badge: u64,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Instance {
+    uuid: Uuid,
+    name: [u8; NAME_LEN],
+    name_len: usize,
+    provider: ComponentId,
+}
+
+impl Instance {
+    fn name(&self) -> &[u8] {
+        &self.name[..self.name_len]
+    }
+}
+
+/// Room for `ENTRIES` registered instances.
+#[derive(Debug)]
+pub struct Registry<'a, const ENTRIES: usize> {
+    policy: &'a [Rule<'a>],
+    instances: [Option<Instance>; ENTRIES],
+    next_badge: u64,
+}
+
+impl<'a, const ENTRIES: usize> Registry<'a, ENTRIES> {
+    pub const fn new(policy: &'a [Rule<'a>]) -> Self {
+        Self {
+            policy,
+            instances: [None; ENTRIES],
+            next_badge: 1,
+        }
+    }
+
+    fn allowed(&self, component: ComponentId, uuid: Uuid, name: &str, access: Access) -> bool {
+        self.policy.iter().any(|rule| {
+            rule.component == component
+                && rule.uuid == uuid
+                && rule.access == access
+                && rule.name.map_or(true, |rule| rule == name)
+        })
+    }
+
+    fn find(&self, uuid: Uuid, name: &str) -> Option<usize> {
+        self.instances.iter().position(|instance| {
+            instance
+                .is_some_and(|instance| instance.uuid == uuid && instance.name() == name.as_bytes())
+        })
+    }
+
+    /// Registers an instance of `uuid` provided by `provider` under `name`.
+    pub fn register(
+        &mut self,
+        provider: ComponentId,
+        uuid: Uuid,
+        name: &str,
+    ) -> Result<(), RegistryError> {
+        if !self.allowed(provider, uuid, name, Access::Provide) {
+            return Err(RegistryError::Denied);
+        }
+        if name.len() > NAME_LEN {
+            return Err(RegistryError::NameTooLong);
+        }
+        if self.find(uuid, name).is_some() {
+            return Err(RegistryError::Taken);
+        }
+        let slot = self
+            .instances
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(RegistryError::Full)?;
+        let mut instance = Instance {
+            uuid,
+            name: [0; NAME_LEN],
+            name_len: name.len(),
+            provider,
+        };
+        instance.name[..name.len()].copy_from_slice(name.as_bytes());
+        *slot = Some(instance);
+        Ok(())
+    }
+
+    /// Removes the instance `provider` registered under `name`.
+    ///
+    /// Clients keep the capabilities they were given. Revoking them is up to
+    /// the provider.
+    pub fn unregister(
+        &mut self,
+        provider: ComponentId,
+        uuid: Uuid,
+        name: &str,
+    ) -> Result<(), RegistryError> {
+        let index = self.find(uuid, name).ok_or(RegistryError::NotFound)?;
+        match &self.instances[index] {
+            Some(instance) if instance.provider == provider => {
+                self.instances[index] = None;
+                Ok(())
+            }
+            _ => Err(RegistryError::Denied),
+        }
+    }
+
+    /// Removes every instance `provider` registered, e.g. when it's shut
+    /// down. Returns how many were removed.
+    pub fn withdraw(&mut self, provider: ComponentId) -> usize {
+        let mut removed = 0;
+        for slot in &mut self.instances {
+            if slot.is_some_and(|instance| instance.provider == provider) {
+                *slot = None;
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// Connects `client` to the instance of `uuid` registered under `name`.
+    pub fn connect(
+        &mut self,
+        client: ComponentId,
+        uuid: Uuid,
+        name: &str,
+        broker: &mut impl Broker,
+    ) -> Result<Connection, RegistryError> {
+        if !self.allowed(client, uuid, name, Access::Use) {
+            return Err(RegistryError::Denied);
+        }
+        let index = self.find(uuid, name).ok_or(RegistryError::NotFound)?;
+        let provider = self.instances[index].unwrap().provider;
+        let badge = self.next_badge;
+        broker.connect(provider, client, badge)?;
+        self.next_badge += 1;
+        Ok(Connection { provider, badge })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    const BLOCK: Uuid = Uuid(*b"harmony::block\0\0");
+    const LOGGER: Uuid = Uuid(*b"harmony::logger\0");
+
+    const DISK: ComponentId = 1;
+    const FS: ComponentId = 2;
+    const SHELL: ComponentId = 3;
+
+    const POLICY: &[Rule] = &[
+        Rule {
+            component: DISK,
+            uuid: BLOCK,
+            name: None,
+            access: Access::Provide,
+        },
+        Rule {
+            component: FS,
+            uuid: BLOCK,
+            name: Some("disk0"),
+            access: Access::Use,
+        },
+        Rule {
+            component: SHELL,
+            uuid: BLOCK,
+            name: None,
+            access: Access::Use,
+        },
+    ];
+
+    #[derive(Default)]
+    struct Connections(Vec<(ComponentId, ComponentId, u64)>);
+
+    impl Broker for Connections {
+        fn connect(
+            &mut self,
+            provider: ComponentId,
+            client: ComponentId,
+            badge: u64,
+        ) -> Result<(), CapError> {
+            self.0.push((provider, client, badge));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn brokers_allowed_connections() {
+        let mut registry = Registry::<2>::new(POLICY);
+        let mut broker = Connections::default();
+        // Late clients find nothing until the provider registers.
+        assert_eq!(
+            registry.connect(FS, BLOCK, "disk0", &mut broker),
+            Err(RegistryError::NotFound)
+        );
+        registry.register(DISK, BLOCK, "disk0").unwrap();
+        registry.register(DISK, BLOCK, "disk1").unwrap();
+
+        assert_eq!(
+            registry.connect(FS, BLOCK, "disk0", &mut broker),
+            Ok(Connection {
+                provider: DISK,
+                badge: 1
+            })
+        );
+        assert_eq!(
+            registry.connect(SHELL, BLOCK, "disk0", &mut broker),
+            Ok(Connection {
+                provider: DISK,
+                badge: 2
+            })
+        );
+        assert_eq!(broker.0, [(DISK, FS, 1), (DISK, SHELL, 2)]);
+
+        // The filesystem may only use disk0, and nobody may use a logger.
+        assert_eq!(
+            registry.connect(FS, BLOCK, "disk1", &mut broker),
+            Err(RegistryError::Denied)
+        );
+        assert_eq!(
+            registry.connect(SHELL, LOGGER, "disk0", &mut broker),
+            Err(RegistryError::Denied)
+        );
+        assert_eq!(broker.0.len(), 2);
+    }
+
+    #[test]
+    fn registrations_follow_the_policy() {
+        let mut registry = Registry::<2>::new(POLICY);
+        assert_eq!(
+            registry.register(FS, BLOCK, "disk0"),
+            Err(RegistryError::Denied)
+        );
+        assert_eq!(
+            registry.register(DISK, LOGGER, "log"),
+            Err(RegistryError::Denied)
+        );
+        assert_eq!(
+            registry.register(DISK, BLOCK, &"d".repeat(NAME_LEN + 1)),
+            Err(RegistryError::NameTooLong)
+        );
+        registry.register(DISK, BLOCK, "disk0").unwrap();
+        assert_eq!(
+            registry.register(DISK, BLOCK, "disk0"),
+            Err(RegistryError::Taken)
+        );
+        registry.register(DISK, BLOCK, "disk1").unwrap();
+        assert_eq!(
+            registry.register(DISK, BLOCK, "disk2"),
+            Err(RegistryError::Full)
+        );
+
+        assert_eq!(
+            registry.unregister(FS, BLOCK, "disk0"),
+            Err(RegistryError::Denied)
+        );
+        registry.unregister(DISK, BLOCK, "disk0").unwrap();
+        registry.register(DISK, BLOCK, "disk2").unwrap();
+        assert_eq!(registry.withdraw(DISK), 2);
+        assert_eq!(
+            registry.unregister(DISK, BLOCK, "disk1"),
+            Err(RegistryError::NotFound)
+        );
+    }
+
+    #[test]
+    fn failed_connections_use_no_badge() {
+        struct Refuse;
+
+        impl Broker for Refuse {
+            fn connect(&mut self, _: ComponentId, _: ComponentId, _: u64) -> Result<(), CapError> {
+                Err(CapError::OutOfMemory)
+            }
+        }
+
+        let mut registry = Registry::<1>::new(POLICY);
+        registry.register(DISK, BLOCK, "disk0").unwrap();
+        assert_eq!(
+            registry.connect(FS, BLOCK, "disk0", &mut Refuse),
+            Err(RegistryError::Cap(CapError::OutOfMemory))
+        );
+        let mut broker = Connections::default();
+        let connection = registry.connect(FS, BLOCK, "disk0", &mut broker);
+        assert_eq!(connection.map(|connection| connection.badge), Ok(1));
+    }
+}
@@ -0,0 +1,391 @@
+//! Small objects carved out of shared frames.
+//!
+//! Sharing a whole frame for every small buffer wastes most of it. A memory
+//! manager can hand out small objects instead: it keeps a few frames, each
+//! behind a grant capability it shares with its clients, and a
+//! [`SlabAllocator`] splits them into objects. An [`Object`] is named by its
+//! grant and its offset in the frame, which is what the manager hands back
+//! to the client.
+//!
+//! Freeing objects leaves holes. [`SlabAllocator::compact`] moves the objects
+//! of the frames at the end into the others, reporting every move so that the
+//! manager can copy the data and tell the owner, until the frames are empty
+//! and can be given back with [`SlabAllocator::take_empty`].
+//!
+//! FIXME: There's no memory manager component yet to offer this as a service.
+
+use addr::PAGE_SIZE;
+
+use crate::raw::CapId;
+
+/// Objects are made of granules of this many bytes, which is also the
+/// smallest alignment.
+pub const GRANULE: usize = 16;
+const GRANULES: usize = PAGE_SIZE / GRANULE;
+const BITS: usize = u64::BITS as usize;
+const WORDS: usize = GRANULES / BITS;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SlabError {
+    /// No frame has a free range large enough for the request.
+    NoSpace,
+    /// The object is larger than a frame.
+    TooLarge,
+    /// The alignment isn't a power of two or is larger than a frame.
+    BadAlignment,
+    /// The object wasn't handed out by this allocator.
+    NotAllocated,
+    /// The allocator can't keep track of any more frames.
+    TooManyFrames,
+    /// The frame was already added.
+    Duplicate,
+}
+
+/// A range of a shared frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Object {
+    /// The grant capability of the frame.
+    pub grant: CapId,
+    /// Offset of the object in the frame.
+    pub offset: usize,
+    /// Length of the object, rounded up to a multiple of [`GRANULE`].
+    pub len: usize,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Frame {
+    grant: CapId,
+    used: [u64; WORDS],
+    /// Granules of the object starting at each granule, or 0.
+    lens: [u16; GRANULES],
+    /// Alignment of the object starting at each granule, in granules.
+    aligns: [u16; GRANULES],
+}
+
+impl Frame {
+    fn new(grant: CapId) -> Self {
+        Self {
+            grant,
+            used: [0; WORDS],
+            lens: [0; GRANULES],
+            aligns: [0; GRANULES],
+        }
+    }
+
+    fn is_used(&self, granule: usize) -> bool {
+        self.used[granule / BITS] & (1 << (granule % BITS)) != 0
+    }
+
+    fn is_empty(&self) -> bool {
+        self.used.iter().all(|&word| word == 0)
+    }
+
+    fn used_granules(&self) -> usize {
+        self.used
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    fn set(&mut self, first: usize, last: usize, used: bool) {
+        for granule in first..last {
+            let bit = 1 << (granule % BITS);
+            match used {
+                true => self.used[granule / BITS] |= bit,
+                false => self.used[granule / BITS] &= !bit,
+            }
+        }
+    }
+
+    /// Places an object of `count` granules aligned to `align` granules.
+    fn place(&mut self, count: usize, align: usize) -> Option<usize> {
+        let first = (0..=GRANULES - count)
+            .step_by(align)
+            .find(|&first| (first..first + count).all(|granule| !self.is_used(granule)))?;
+        self.set(first, first + count, true);
+        self.lens[first] = count as u16;
+        self.aligns[first] = align as u16;
+        Some(first)
+    }
+
+    fn remove(&mut self, first: usize) {
+        let count = usize::from(self.lens[first]);
+        self.set(first, first + count, false);
+        self.lens[first] = 0;
+        self.aligns[first] = 0;
+    }
+
+    /// The first granule, length and alignment of every object.
+    fn objects(&self) -> impl Iterator<Item = (usize, usize, usize)> + '_ {
+        (0..GRANULES)
+            .filter(|&first| self.lens[first] != 0)
+            .map(|first| {
+                let (len, align) = (self.lens[first], self.aligns[first]);
+                (first, usize::from(len), usize::from(align))
+            })
+    }
+
+    fn object(&self, first: usize) -> Object {
+        Object {
+            grant: self.grant,
+            offset: first * GRANULE,
+            len: usize::from(self.lens[first]) * GRANULE,
+        }
+    }
+}
+
+/// Splits up to `FRAMES` shared frames into small objects.
+#[derive(Debug, Clone)]
+pub struct SlabAllocator<const FRAMES: usize> {
+    frames: [Option<Frame>; FRAMES],
+}
+
+impl<const FRAMES: usize> Default for SlabAllocator<FRAMES> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const FRAMES: usize> SlabAllocator<FRAMES> {
+    pub const fn new() -> Self {
+        Self {
+            frames: [None; FRAMES],
+        }
+    }
+
+    /// Number of frames objects are allocated from.
+    pub fn frames(&self) -> usize {
+        self.frames.iter().flatten().count()
+    }
+
+    /// Bytes handed out in objects.
+    pub fn used(&self) -> usize {
+        let granules: usize = self.frames.iter().flatten().map(Frame::used_granules).sum();
+        granules * GRANULE
+    }
+
+    /// Allocates objects from the frame shared through `grant` from now on.
+    pub fn add_frame(&mut self, grant: CapId) -> Result<(), SlabError> {
+        if self
+            .frames
+            .iter()
+            .flatten()
+            .any(|frame| frame.grant == grant)
+        {
+            return Err(SlabError::Duplicate);
+        }
+        let slot = self
+            .frames
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(SlabError::TooManyFrames)?;
+        *slot = Some(Frame::new(grant));
+        Ok(())
+    }
+
+    /// Stops using a frame without objects and returns its grant.
+    pub fn take_empty(&mut self) -> Option<CapId> {
+        let slot = self
+            .frames
+            .iter_mut()
+            .rev()
+            .find(|slot| slot.is_some_and(|frame| frame.is_empty()))?;
+        slot.take().map(|frame| frame.grant)
+    }
+
+    /// Allocates `len` bytes aligned to `align` in the first frame with room
+    /// for them.
+    pub fn allocate(&mut self, len: usize, align: usize) -> Result<Object, SlabError> {
+        if !align.is_power_of_two() || align > PAGE_SIZE {
+            return Err(SlabError::BadAlignment);
+        }
+        if len > PAGE_SIZE {
+            return Err(SlabError::TooLarge);
+        }
+        let count = len.div_ceil(GRANULE).max(1);
+        let align = align.div_ceil(GRANULE);
+        self.frames
+            .iter_mut()
+            .flatten()
+            .find_map(|frame| frame.place(count, align).map(|first| frame.object(first)))
+            .ok_or(SlabError::NoSpace)
+    }
+
+    /// Returns an object to its frame.
+    pub fn free(&mut self, object: Object) -> Result<(), SlabError> {
+        let frame = self
+            .frames
+            .iter_mut()
+            .flatten()
+            .find(|frame| frame.grant == object.grant)
+            .ok_or(SlabError::NotAllocated)?;
+        let first = object.offset / GRANULE;
+        let allocated = object.offset % GRANULE == 0
+            && first < GRANULES
+            && frame.lens[first] != 0
+            && usize::from(frame.lens[first]) * GRANULE == object.len;
+        if !allocated {
+            return Err(SlabError::NotAllocated);
+        }
+        frame.remove(first);
+        Ok(())
+    }
+
+    /// Moves every object of a frame into the frames before it that are in
+    /// use, if they all fit, starting from the last frame. `relocate` is
+    /// called with the old and the new place of every object moved.
+    ///
+    /// Returns the number of frames emptied, which [`take_empty`](Self::take_empty)
+    /// hands back.
+    pub fn compact(&mut self, mut relocate: impl FnMut(Object, Object)) -> usize {
+        let mut emptied = 0;
+        for source in (0..FRAMES).rev() {
+            let Some(frame) = self.frames[source].filter(|frame| !frame.is_empty()) else {
+                continue;
+            };
+            // Frames are either emptied or left alone.
+            let mut trial = self.clone();
+            if !trial.move_out(source, &frame, |_, _| {}) {
+                continue;
+            }
+            self.move_out(source, &frame, &mut relocate);
+            emptied += 1;
+        }
+        emptied
+    }
+
+    /// Moves the objects of `frame`, at `source`, into the frames before it
+    /// that are in use. Returns whether they all fit.
+    fn move_out(
+        &mut self,
+        source: usize,
+        frame: &Frame,
+        mut relocate: impl FnMut(Object, Object),
+    ) -> bool {
+        for (first, count, align) in frame.objects() {
+            let moved = self.frames[..source]
+                .iter_mut()
+                .flatten()
+                .filter(|destination| !destination.is_empty())
+                .find_map(|destination| {
+                    let placed = destination.place(count, align)?;
+                    Some(destination.object(placed))
+                });
+            let Some(moved) = moved else {
+                return false;
+            };
+            relocate(frame.object(first), moved);
+            if let Some(frame) = &mut self.frames[source] {
+                frame.remove(first);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: CapId = CapId::new(10);
+    const B: CapId = CapId::new(11);
+    const C: CapId = CapId::new(12);
+
+    #[test]
+    fn allocates_aligned_objects() {
+        let mut slab = SlabAllocator::<2>::new();
+        assert_eq!(slab.allocate(8, 8), Err(SlabError::NoSpace));
+        slab.add_frame(A).unwrap();
+        assert_eq!(slab.add_frame(A), Err(SlabError::Duplicate));
+
+        let small = slab.allocate(1, 1).unwrap();
+        assert_eq!(
+            small,
+            Object {
+                grant: A,
+                offset: 0,
+                len: GRANULE
+            }
+        );
+        let aligned = slab.allocate(100, 256).unwrap();
+        assert_eq!((aligned.offset, aligned.len), (256, 112));
+        let next = slab.allocate(16, 16).unwrap();
+        assert_eq!(next.offset, 16);
+        assert_eq!(slab.used(), 144);
+
+        assert_eq!(slab.allocate(8, 3), Err(SlabError::BadAlignment));
+        assert_eq!(
+            slab.allocate(8, 2 * PAGE_SIZE),
+            Err(SlabError::BadAlignment)
+        );
+        assert_eq!(slab.allocate(PAGE_SIZE + 1, 8), Err(SlabError::TooLarge));
+        // Doesn't fit in the first frame anymore.
+        assert_eq!(slab.allocate(PAGE_SIZE, 8), Err(SlabError::NoSpace));
+        slab.add_frame(B).unwrap();
+        assert_eq!(slab.allocate(PAGE_SIZE, 8).unwrap().grant, B);
+        assert_eq!(slab.add_frame(C), Err(SlabError::TooManyFrames));
+    }
+
+    #[test]
+    fn frees_only_what_was_allocated() {
+        let mut slab = SlabAllocator::<1>::new();
+        slab.add_frame(A).unwrap();
+        let object = slab.allocate(40, 16).unwrap();
+        let wrong_len = Object { len: 16, ..object };
+        assert_eq!(slab.free(wrong_len), Err(SlabError::NotAllocated));
+        let wrong_grant = Object { grant: B, ..object };
+        assert_eq!(slab.free(wrong_grant), Err(SlabError::NotAllocated));
+        assert_eq!(slab.take_empty(), None);
+        slab.free(object).unwrap();
+        assert_eq!(slab.free(object), Err(SlabError::NotAllocated));
+        assert_eq!(slab.used(), 0);
+        assert_eq!(slab.take_empty(), Some(A));
+        assert_eq!(slab.frames(), 0);
+    }
+
+    #[test]
+    fn compaction_empties_frames() {
+        let mut slab = SlabAllocator::<3>::new();
+        for grant in [A, B, C] {
+            slab.add_frame(grant).unwrap();
+        }
+        // Fill the first two frames with quarter pages, then free most of them.
+        let quarter = PAGE_SIZE / 4;
+        let objects: [Object; 8] = core::array::from_fn(|_| slab.allocate(quarter, 8).unwrap());
+        let aligned = slab.allocate(64, 64).unwrap();
+        assert_eq!(aligned.grant, C);
+        for object in [objects[1], objects[2], objects[3], objects[5], objects[6]] {
+            slab.free(object).unwrap();
+        }
+
+        let mut moves = [None; 4];
+        let mut count = 0;
+        let emptied = slab.compact(|from, to| {
+            moves[count] = Some((from, to));
+            count += 1;
+        });
+        assert_eq!(emptied, 2);
+        assert_eq!(count, 3);
+        // The last frame goes first, into the first frame in use.
+        let (from, to) = moves[0].unwrap();
+        assert_eq!(from, aligned);
+        assert_eq!((to.grant, to.offset % 64), (A, 0));
+        assert!(moves[1..3]
+            .iter()
+            .all(|moved| moved.unwrap().0.grant == B && moved.unwrap().1.grant == A));
+        assert_eq!(slab.used(), 3 * quarter + 64);
+        assert!(slab.take_empty().is_some());
+        assert!(slab.take_empty().is_some());
+        assert_eq!(slab.take_empty(), None);
+        assert_eq!(slab.frames(), 1);
+
+        // A frame whose objects don't all fit elsewhere is left alone.
+        let mut slab = SlabAllocator::<2>::new();
+        slab.add_frame(A).unwrap();
+        slab.add_frame(B).unwrap();
+        slab.allocate(PAGE_SIZE / 2, 8).unwrap();
+        slab.allocate(PAGE_SIZE / 2, 8).unwrap();
+        slab.allocate(PAGE_SIZE, 8).unwrap();
+        assert_eq!(slab.compact(|_, _| panic!("Nothing fits")), 0);
+    }
+}
@@ -0,0 +1,133 @@
+//! Threads owned by a component.
+
+use super::cap::{Owned, Slot};
+use crate::ops::thread::{ThreadOp, ThreadState};
+use crate::ops::SyscallOp as _;
+use crate::raw::{CapError, CapId};
+
+fn to_state(code: u64) -> Result<ThreadState, CapError> {
+    u8::try_from(code)
+        .ok()
+        .and_then(|state| ThreadState::try_from(state).ok())
+        .ok_or(CapError::Internal)
+}
+
+/// A thread capability, dropped along with the value.
+#[derive(Debug)]
+pub struct Thread {
+    cap: Owned,
+}
+
+impl Thread {
+    /// Takes ownership of the thread capability `id`.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Owned::from_raw`].
+    pub unsafe fn from_raw(id: CapId, slot: Option<Slot>) -> Self {
+        Self {
+            // SAFETY: Upheld by the caller.
+            cap: unsafe { Owned::from_raw(id, slot) },
+        }
+    }
+
+    /// Gives up ownership without dropping the thread.
+    pub fn into_raw(self) -> (CapId, Option<Slot>) {
+        self.cap.into_raw()
+    }
+
+    pub fn id(&self) -> CapId {
+        self.cap.id()
+    }
+
+    /// Switches to the thread.
+    ///
+    /// # Safety
+    ///
+    /// The thread runs with whatever memory it was given, which may include
+    /// the caller's.
+    pub unsafe fn activate(&self) -> Result<(), CapError> {
+        // SAFETY: Upheld by the caller.
+        unsafe { ThreadOp::Activate.syscall(self.id()).map(|_| ()) }
+    }
+
+    /// Restricts the cores the thread may be dispatched on, see
+    /// [`ThreadOp::SetAffinity`].
+    pub fn set_affinity(&self, mask: u64) -> Result<(), CapError> {
+        // SAFETY: Changing the affinity doesn't touch any memory.
+        unsafe {
+            ThreadOp::SetAffinity { mask }
+                .syscall(self.id())
+                .map(|_| ())
+        }
+    }
+
+    pub fn affinity(&self) -> Result<u64, CapError> {
+        // SAFETY: Reading the affinity doesn't touch any memory.
+        unsafe { ThreadOp::GetAffinity.syscall(self.id()) }
+    }
+
+    pub fn state(&self) -> Result<ThreadState, CapError> {
+        // SAFETY: Reading the state doesn't touch any memory.
+        unsafe { ThreadOp::GetState.syscall(self.id()).and_then(to_state) }
+    }
+
+    /// Stops the thread from running until it's resumed, returning the state
+    /// it was in.
+    pub fn suspend(&self) -> Result<ThreadState, CapError> {
+        // SAFETY: Suspending a thread doesn't touch any memory.
+        unsafe { ThreadOp::Suspend.syscall(self.id()).and_then(to_state) }
+    }
+
+    /// Lets a suspended thread run again, returning the state it was in.
+    pub fn resume(&self) -> Result<ThreadState, CapError> {
+        // SAFETY: Resuming a thread doesn't touch any memory.
+        unsafe { ThreadOp::Resume.syscall(self.id()).and_then(to_state) }
+    }
+
+    /// Drops the thread, returning the error that dropping the value would
+    /// ignore.
+    pub fn release(self) -> Result<(), CapError> {
+        self.cap.release()
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::raw::RawOperation;
+    use crate::testing::{MockKernel, MockResource};
+
+    #[test]
+    fn suspends_and_drops_the_thread() {
+        let mut kernel = MockKernel::new();
+        let table = CapId::new(1);
+        let root = MockResource::CapTable(kernel.root());
+        kernel.insert(table, root).unwrap();
+        let thread = MockResource::Thread {
+            affinity: 1,
+            suspended: false,
+        };
+        kernel.insert(CapId::new(2), thread).unwrap();
+        let installed = kernel.install();
+
+        let slot = Slot {
+            table,
+            slot: trie::SlotId::try_from(2).ok().unwrap(),
+        };
+        // SAFETY: Nothing else uses the capability.
+        let thread = unsafe { Thread::from_raw(CapId::new(2), Some(slot)) };
+        thread.set_affinity(0b10).unwrap();
+        assert_eq!(thread.affinity(), Ok(0b10));
+        assert_eq!(thread.suspend(), Ok(ThreadState::Ready));
+        assert_eq!(thread.state(), Ok(ThreadState::Suspended));
+        // SAFETY: The mock doesn't run threads.
+        assert_eq!(unsafe { thread.activate() }, Err(CapError::Suspended));
+        assert_eq!(thread.resume(), Ok(ThreadState::Suspended));
+        drop(thread);
+
+        let mut kernel = installed.take();
+        assert_eq!(kernel.ops().last(), Some(&RawOperation::CapTableDrop));
+        assert_eq!(kernel.resource(CapId::new(2)), None);
+    }
+}
@@ -0,0 +1,193 @@
+//! Time keeping for components.
+//!
+//! After [`init`] is given a clock capability, [`Instant::now`] reads the TSC
+//! directly if the kernel reported it as stable and falls back to asking the
+//! kernel for the time otherwise.
+
+use core::ops::{Add, Sub};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use core::time::Duration;
+
+use crate::ops::clock::{Calibration, ClockOp};
+use crate::ops::SyscallOp as _;
+use crate::raw::{CapError, CapId};
+
+static CLOCK: AtomicU32 = AtomicU32::new(u32::MAX);
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+static BOOT_TSC: AtomicU64 = AtomicU64::new(0);
+static STABLE_TSC: AtomicBool = AtomicBool::new(false);
+
+/// Reads the calibration of the clock in `cap` and uses it for [`Instant::now`].
+pub fn init(cap: CapId) -> Result<(), CapError> {
+    let mut calibration = Calibration::default();
+    let op = ClockOp::GetCalibration {
+        buffer: &mut calibration,
+    };
+    // SAFETY: The buffer is valid for writes for the duration of the call.
+    unsafe { op.syscall(cap)? };
+    TSC_FREQUENCY.store(calibration.tsc_frequency, Ordering::Relaxed);
+    BOOT_TSC.store(calibration.boot_tsc, Ordering::Relaxed);
+    STABLE_TSC.store(calibration.stable_tsc, Ordering::Relaxed);
+    CLOCK.store(cap.into(), Ordering::Release);
+    Ok(())
+}
+
+/// Whether [`init`] was called, i.e. whether [`Instant::now`] can be used.
+pub fn is_initialized() -> bool {
+    CLOCK.load(Ordering::Acquire) != u32::MAX
+}
+
+/// The calibration passed to [`init`].
+#[cfg(target_arch = "x86_64")]
+fn calibration() -> Calibration {
+    Calibration {
+        tsc_frequency: TSC_FREQUENCY.load(Ordering::Relaxed),
+        boot_tsc: BOOT_TSC.load(Ordering::Relaxed),
+        stable_tsc: STABLE_TSC.load(Ordering::Relaxed),
+    }
+}
+
+/// A point in time, measured from boot.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    nanos: u64,
+}
+
+impl Instant {
+    /// Returns the current time.
+    ///
+    /// # Panics
+    ///
+    /// If [`init`] wasn't called, or if the TSC isn't stable and the clock
+    /// capability stopped working.
+    pub fn now() -> Self {
+        let cap = CLOCK.load(Ordering::Acquire);
+        assert!(
+            cap != u32::MAX,
+            "time::init must be called before reading the time"
+        );
+        // Other architectures always ask the kernel.
+        #[cfg(target_arch = "x86_64")]
+        {
+            // SAFETY: rdtsc has no side effects.
+            let tsc = unsafe { core::arch::x86_64::_rdtsc() };
+            if let Some(nanos) = calibration().tsc_to_nanos(tsc) {
+                return Self { nanos };
+            }
+        }
+        // SAFETY: Reading the time doesn't touch any memory.
+        match unsafe { ClockOp::GetTimeNs.syscall(CapId::new(cap)) } {
+            Ok(nanos) => Self { nanos },
+            Err(e) => panic!("Couldn't read the clock: {e:?}"),
+        }
+    }
+
+    /// The instant `nanos` nanoseconds after boot.
+    pub const fn from_nanos(nanos: u64) -> Self {
+        Self { nanos }
+    }
+
+    /// Nanoseconds since boot.
+    pub const fn as_nanos(&self) -> u64 {
+        self.nanos
+    }
+
+    /// Time elapsed from `earlier` to `self`, or zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.nanos.saturating_sub(earlier.nanos))
+    }
+
+    /// Time elapsed since `self`.
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        Some(Self {
+            nanos: self.nanos.checked_add(nanos)?,
+        })
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        Some(Self {
+            nanos: self.nanos.checked_sub(nanos)?,
+        })
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration)
+            .expect("Overflow when adding a duration to an instant")
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        self.checked_sub(duration)
+            .expect("Overflow when subtracting a duration from an instant")
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::ops::clock::BOOT_CLOCK_CAP;
+    use crate::raw::RawOperation;
+    use crate::testing::{MockKernel, MockResource};
+
+    #[test]
+    fn falls_back_to_the_kernel_clock() {
+        let mut kernel = MockKernel::new();
+        kernel
+            .insert(BOOT_CLOCK_CAP, MockResource::Clock { nanos: 5_000 })
+            .unwrap();
+        let kernel = kernel.install();
+
+        init(BOOT_CLOCK_CAP).unwrap();
+        let start = Instant::now();
+        assert_eq!(start, Instant::from_nanos(5_000));
+        kernel.with(|kernel| {
+            kernel
+                .insert(BOOT_CLOCK_CAP, MockResource::Clock { nanos: 7_500 })
+                .unwrap();
+        });
+        assert_eq!(start.elapsed(), Duration::from_nanos(2_500));
+        kernel.with(|kernel| {
+            assert_eq!(
+                kernel.ops(),
+                [
+                    RawOperation::ClockGetCalibration,
+                    RawOperation::ClockGetTimeNs,
+                    RawOperation::ClockGetTimeNs
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn instant_math() {
+        let instant = Instant::from_nanos(1_000);
+        let later = instant + Duration::from_micros(2);
+        assert_eq!(later.as_nanos(), 3_000);
+        assert_eq!(later - instant, Duration::from_nanos(2_000));
+        assert_eq!(instant - later, Duration::ZERO);
+        assert_eq!(later - Duration::from_nanos(3_000), Instant::from_nanos(0));
+        assert_eq!(instant.checked_sub(Duration::from_nanos(1_001)), None);
+        assert_eq!(instant.checked_add(Duration::MAX), None);
+    }
+}
@@ -0,0 +1,409 @@
+//! Replacing a running service with a new version of it.
+//!
+//! The composer starts the new instance however it starts any component and
+//! then:
+//!
+//! 1. Asks the old instance to save its state into a handoff region, whose
+//!    frames the composer also mapped into the old instance. The old instance
+//!    stops serving requests, writes its state behind a [`Header`] and
+//!    acknowledges.
+//! 2. Transfers the handoff region to the new instance and asks it to restore
+//!    the state from it.
+//! 3. Tells the service's clients to look the service up again, so that they
+//!    get capabilities for the new instance.
+//! 4. Shuts the old instance down and reclaims its resources with
+//!    [`lifecycle::shutdown`](super::lifecycle::shutdown).
+//!
+//! If the old instance doesn't save its state in time, nothing is transferred
+//! and the old instance keeps running, so the composer can drop the new one
+//! and try again later.
+//!
+//! FIXME: The kernel can't rewrite the capabilities other components hold, so
+//! clients can't be retargeted atomically and have to re-discover the
+//! service. Like shutdown, the channels to the instances and the clients are
+//! traits the composer implements until there are endpoints.
+
+use core::time::Duration;
+
+use super::lifecycle::{self, Component, Management, Reclaimed, Stopped};
+use super::time::Instant;
+use crate::ops::region::RegionOp;
+use crate::ops::SyscallOp as _;
+use crate::raw::{CapError, CapId};
+
+/// Identifies a handoff region holding service state.
+pub const HANDOFF_MAGIC: u32 = u32::from_le_bytes(*b"HOFF");
+
+/// Starts the handoff region.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub magic: u32,
+    /// Layout of the state, chosen by the service. A new version that can't
+    /// read the layout of the old one refuses the state.
+    pub format: u32,
+    /// Bytes of state after the header.
+    pub len: u64,
+}
+
+const HEADER_SIZE: usize = core::mem::size_of::<Header>();
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HandoffError {
+    /// The state doesn't fit in the region.
+    TooLarge,
+    /// The region doesn't start with a header.
+    NoState,
+    /// The state was saved in a format the new version doesn't know.
+    UnknownFormat(u32),
+}
+
+/// Writes the state of the old instance into the handoff region.
+pub fn save(region: &mut [u8], format: u32, state: &[u8]) -> Result<(), HandoffError> {
+    let end = HEADER_SIZE
+        .checked_add(state.len())
+        .filter(|&end| end <= region.len())
+        .ok_or(HandoffError::TooLarge)?;
+    region[HEADER_SIZE..end].copy_from_slice(state);
+    // The header goes last so a region is never seen with a header and only
+    // part of the state.
+    region[0..4].copy_from_slice(&HANDOFF_MAGIC.to_le_bytes());
+    region[4..8].copy_from_slice(&format.to_le_bytes());
+    region[8..16].copy_from_slice(&(state.len() as u64).to_le_bytes());
+    Ok(())
+}
+
+/// Reads the header at the start of the handoff region.
+pub fn header(region: &[u8]) -> Option<Header> {
+    let bytes = region.get(..HEADER_SIZE)?;
+    let header = Header {
+        magic: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+        format: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+        len: u64::from_le_bytes(bytes[8..16].try_into().unwrap()),
+    };
+    (header.magic == HANDOFF_MAGIC).then_some(header)
+}
+
+/// Returns the state in the handoff region if it was saved in one of
+/// `formats`.
+pub fn restore<'a>(region: &'a [u8], formats: &[u32]) -> Result<&'a [u8], HandoffError> {
+    let header = header(region).ok_or(HandoffError::NoState)?;
+    if !formats.contains(&header.format) {
+        return Err(HandoffError::UnknownFormat(header.format));
+    }
+    usize::try_from(header.len)
+        .ok()
+        .and_then(|len| HEADER_SIZE.checked_add(len))
+        .and_then(|end| region.get(HEADER_SIZE..end))
+        .ok_or(HandoffError::NoState)
+}
+
+/// The composer's end of the management endpoint of the instance being
+/// replaced.
+pub trait OldInstance: Management {
+    /// Asks the instance to stop serving and save its state into `region`.
+    fn save_state(&mut self, region: CapId) -> Result<(), CapError>;
+
+    /// Whether the instance finished saving its state.
+    fn saved(&mut self) -> bool;
+}
+
+/// The composer's end of the management endpoint of the new instance.
+pub trait NewInstance {
+    /// Asks the instance to restore the state in the region at `slot` of its
+    /// capability table.
+    fn restore_state(&mut self, slot: CapId) -> Result<(), CapError>;
+}
+
+/// The components using the service.
+pub trait Clients {
+    /// Tells every client to look the service up again. Returns the number of
+    /// clients told.
+    fn rediscover(&mut self) -> Result<usize, CapError>;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UpgradeError {
+    /// The old instance didn't save its state in time. It's still running.
+    NotSaved,
+    Cap(CapError),
+}
+
+impl From<CapError> for UpgradeError {
+    fn from(value: CapError) -> Self {
+        UpgradeError::Cap(value)
+    }
+}
+
+/// Where the handoff region goes.
+#[derive(Debug, Copy, Clone)]
+pub struct Handoff {
+    /// The composer's capability for the handoff region.
+    pub region: CapId,
+    /// The new instance's capability table.
+    pub table: CapId,
+    /// The empty slot of `table` the region is transferred to.
+    pub slot: CapId,
+}
+
+/// How the upgrade went.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Upgraded {
+    /// Clients told to re-discover the service.
+    pub clients: usize,
+    /// How the old instance stopped.
+    pub stopped: Stopped,
+    /// What was reclaimed from the old instance.
+    pub reclaimed: Reclaimed,
+}
+
+/// Replaces the old instance of a service with the new one.
+///
+/// `timeout` bounds both the wait for the state and the wait for the old
+/// instance to acknowledge its shutdown.
+pub fn upgrade(
+    old: &mut impl OldInstance,
+    new: &mut impl NewInstance,
+    clients: &mut impl Clients,
+    handoff: Handoff,
+    timeout: Duration,
+    component: &Component<'_>,
+    destination: impl FnMut() -> Result<(CapId, CapId), CapError>,
+) -> Result<Upgraded, UpgradeError> {
+    old.save_state(handoff.region)?;
+    let deadline = Instant::now()
+        .checked_add(timeout)
+        .unwrap_or(Instant::from_nanos(u64::MAX));
+    while !old.saved() {
+        if Instant::now() >= deadline {
+            return Err(UpgradeError::NotSaved);
+        }
+        core::hint::spin_loop();
+    }
+
+    // SAFETY: Reading the size of a region doesn't touch any memory.
+    let frames = unsafe { RegionOp::Frames.syscall(handoff.region)? };
+    let transfer = RegionOp::Transfer {
+        offset: 0,
+        frames,
+        table: handoff.table,
+        slot: handoff.slot,
+    };
+    // SAFETY: The region is only mapped by the old instance, which stopped
+    // using it.
+    unsafe { transfer.syscall(handoff.region)? };
+    new.restore_state(handoff.slot)?;
+
+    let clients = clients.rediscover()?;
+    let (stopped, reclaimed) = lifecycle::shutdown(old, timeout, component, destination)?;
+    Ok(Upgraded {
+        clients,
+        stopped,
+        reclaimed,
+    })
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::ops::clock::BOOT_CLOCK_CAP;
+    use crate::raw::RawOperation;
+    use crate::testing::{MockKernel, MockResource};
+    use crate::userspace::time;
+
+    const COMPOSER: CapId = CapId::new(0);
+    const OLD_TABLE: CapId = CapId::new(1);
+    const ADDRESS_SPACE: CapId = CapId::new(2);
+    const NEW_TABLE: CapId = CapId::new(5);
+    const HANDOFF: CapId = CapId::new(6);
+    const STATE: CapId = CapId::new(7);
+
+    /// An old instance that saves after being polled `after` times and then
+    /// acknowledges its shutdown right away.
+    struct Old {
+        after: Option<usize>,
+        saving: bool,
+        stopping: bool,
+    }
+
+    impl Management for Old {
+        fn request_shutdown(&mut self) -> Result<(), CapError> {
+            self.stopping = true;
+            Ok(())
+        }
+
+        fn acknowledged(&mut self) -> bool {
+            self.stopping
+        }
+    }
+
+    impl OldInstance for Old {
+        fn save_state(&mut self, region: CapId) -> Result<(), CapError> {
+            assert_eq!(region, HANDOFF);
+            self.saving = true;
+            Ok(())
+        }
+
+        fn saved(&mut self) -> bool {
+            match &mut self.after {
+                Some(0) => self.saving,
+                Some(after) => {
+                    *after -= 1;
+                    false
+                }
+                None => false,
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct New {
+        restored: Option<CapId>,
+    }
+
+    impl NewInstance for New {
+        fn restore_state(&mut self, slot: CapId) -> Result<(), CapError> {
+            self.restored = Some(slot);
+            Ok(())
+        }
+    }
+
+    struct Users(usize);
+
+    impl Clients for Users {
+        fn rediscover(&mut self) -> Result<usize, CapError> {
+            Ok(self.0)
+        }
+    }
+
+    fn kernel() -> MockKernel {
+        let mut kernel = MockKernel::new();
+        let old = kernel.new_table();
+        let new = kernel.new_table();
+        let root = kernel.root();
+        let resources = [
+            (COMPOSER, MockResource::CapTable(root)),
+            (OLD_TABLE, MockResource::CapTable(old)),
+            (ADDRESS_SPACE, MockResource::PageTable { level: 4 }),
+            (NEW_TABLE, MockResource::CapTable(new)),
+            (
+                HANDOFF,
+                MockResource::Region {
+                    base: 0x10_0000,
+                    frames: 2,
+                },
+            ),
+            (BOOT_CLOCK_CAP, MockResource::Clock { nanos: 0 }),
+        ];
+        for (cap, resource) in resources {
+            kernel.insert(cap, resource).unwrap();
+        }
+        kernel
+    }
+
+    fn component() -> Component<'static> {
+        Component {
+            hierarchy: None,
+            address_space: ADDRESS_SPACE,
+            regions: &[],
+            table: OLD_TABLE,
+            objects: &[],
+        }
+    }
+
+    const HANDOFF_SLOTS: Handoff = Handoff {
+        region: HANDOFF,
+        table: NEW_TABLE,
+        slot: STATE,
+    };
+
+    #[test]
+    fn hands_state_over() {
+        let mut region = [0xFF; 64];
+        assert_eq!(restore(&region, &[1]), Err(HandoffError::NoState));
+        assert_eq!(save(&mut region, 1, &[0; 60]), Err(HandoffError::TooLarge));
+        save(&mut region, 2, b"connections").unwrap();
+        assert_eq!(
+            header(&region),
+            Some(Header {
+                magic: HANDOFF_MAGIC,
+                format: 2,
+                len: 11
+            })
+        );
+        assert_eq!(restore(&region, &[1, 2]), Ok(&b"connections"[..]));
+        assert_eq!(restore(&region, &[3]), Err(HandoffError::UnknownFormat(2)));
+    }
+
+    #[test]
+    fn upgrades_a_service() {
+        let kernel = kernel().install();
+        time::init(BOOT_CLOCK_CAP).unwrap();
+        let mut old = Old {
+            after: Some(2),
+            saving: false,
+            stopping: false,
+        };
+        let mut new = New::default();
+        let upgraded = upgrade(
+            &mut old,
+            &mut new,
+            &mut Users(3),
+            HANDOFF_SLOTS,
+            Duration::from_secs(1),
+            &component(),
+            || Err(CapError::OutOfMemory),
+        )
+        .unwrap();
+        assert_eq!(upgraded.clients, 3);
+        assert_eq!(upgraded.stopped, Stopped::Graceful);
+        assert_eq!(new.restored, Some(STATE));
+        kernel.with(|kernel| {
+            assert_eq!(kernel.resource(HANDOFF), None);
+            let ops: Vec<_> = kernel
+                .ops()
+                .into_iter()
+                .filter(|op| *op != RawOperation::ClockGetTimeNs)
+                .collect();
+            assert_eq!(
+                ops[1..],
+                [
+                    RawOperation::MemoryRegionFrames,
+                    RawOperation::MemoryRegionTransfer,
+                    RawOperation::PageTableClear,
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn keeps_the_old_instance_if_it_does_not_save() {
+        let kernel = kernel().install();
+        time::init(BOOT_CLOCK_CAP).unwrap();
+        let mut old = Old {
+            after: None,
+            saving: false,
+            stopping: false,
+        };
+        let mut new = New::default();
+        let result = upgrade(
+            &mut old,
+            &mut new,
+            &mut Users(0),
+            HANDOFF_SLOTS,
+            Duration::ZERO,
+            &component(),
+            || Err(CapError::OutOfMemory),
+        );
+        assert_eq!(result, Err(UpgradeError::NotSaved));
+        assert!(!old.stopping);
+        assert_eq!(new.restored, None);
+        kernel.with(|kernel| {
+            assert!(kernel.resource(HANDOFF).is_some());
+        });
+    }
+}
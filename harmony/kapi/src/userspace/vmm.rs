@@ -0,0 +1,463 @@
+//! Virtual address space management for components.
+//!
+//! A [`VirtAllocator`] owns a window of a component's address space and keeps
+//! track of which parts of it are in use with a bitmap. Each bit covers one
+//! granule of the window. Anything that places mappings (the stack, the heap,
+//! anonymous mappings, child address spaces) should carve its region out of the
+//! same allocator so they can't collide.
+//!
+//! The state of an allocator can be saved with [`VirtAllocator::snapshot`]
+//! and brought back with [`VirtAllocator::from_snapshot`], to look at after a
+//! crash or to hand the window over to a new instance of the memory manager.
+//! A snapshot is a sequence of words:
+//!
+//! ```text
+//! base, granule, bitmap words, used granules, bitmap...
+//! ```
+
+pub use addr::PAGE_SIZE;
+use addr::{Page, VirtAddr};
+
+use crate::layout::{LIBRARY, SHARED_PAGES};
+
+const BITS: usize = u64::BITS as usize;
+
+/// Words of a snapshot before the bitmap.
+pub const SNAPSHOT_HEADER_WORDS: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VmmError {
+    /// There's no free range large enough for the request.
+    NoSpace,
+    /// The requested range is partially or completely in use.
+    Overlap,
+    /// The requested range isn't inside the allocator's window.
+    OutOfRange,
+    /// The alignment isn't a power of two.
+    BadAlignment,
+    /// The region wasn't handed out by this allocator.
+    NotAllocated,
+    /// The buffer can't hold the snapshot.
+    BufferTooSmall,
+    /// The snapshot is truncated, inconsistent or for another size of
+    /// allocator.
+    BadSnapshot,
+}
+
+/// A range of virtual memory handed out by a [`VirtAllocator`].
+///
+/// The region is surrounded by `guard` bytes on each side that are kept
+/// reserved but should never be mapped.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Region {
+    start: usize,
+    len: usize,
+    guard: usize,
+}
+
+impl Region {
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.start + self.len
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Size of the unmapped gap on each side of the region.
+    pub fn guard(&self) -> usize {
+        self.guard
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        (self.start..self.end()).contains(&addr)
+    }
+
+    /// The pages covered by the region.
+    ///
+    /// Regions are granule aligned, so they're always made of whole pages.
+    pub fn pages(&self) -> impl Iterator<Item = Page> {
+        (self.start..self.end())
+            .step_by(PAGE_SIZE)
+            .map(|start| Page::from_start_address(VirtAddr::new(start)))
+    }
+}
+
+/// A bitmap allocator for a window of virtual memory.
+///
+/// The window starts at `base` and spans `WORDS * 64` granules.
+#[derive(Debug, Clone)]
+pub struct VirtAllocator<const WORDS: usize> {
+    base: usize,
+    granule: usize,
+    bitmap: [u64; WORDS],
+}
+
+impl<const WORDS: usize> VirtAllocator<WORDS> {
+    /// Number of granules in the window.
+    pub const GRANULES: usize = WORDS * BITS;
+
+    /// Creates an empty allocator for the window starting at `base`.
+    ///
+    /// # Panics
+    ///
+    /// If the granule isn't a power of two multiple of the page size, `base`
+    /// isn't aligned to the granule, or the window overflows the address space.
+    pub const fn new(base: usize, granule: usize) -> Self {
+        assert!(granule.is_power_of_two() && granule >= PAGE_SIZE);
+        assert!(base % granule == 0);
+        assert!(base.checked_add(Self::GRANULES * granule).is_some());
+        Self {
+            base,
+            granule,
+            bitmap: [0; WORDS],
+        }
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    /// The first address after the window.
+    pub fn end(&self) -> usize {
+        self.base + Self::GRANULES * self.granule
+    }
+
+    pub fn granule(&self) -> usize {
+        self.granule
+    }
+
+    /// Number of bytes that are neither allocated nor reserved.
+    pub fn free(&self) -> usize {
+        (Self::GRANULES - self.used()) * self.granule
+    }
+
+    /// Marks the range `[start, start + len)` as used.
+    ///
+    /// The range is extended to granule boundaries. This is meant for fixed
+    /// mappings such as the program image or pages placed by the kernel.
+    pub fn reserve(&mut self, start: usize, len: usize) -> Result<Region, VmmError> {
+        let end = start.checked_add(len).ok_or(VmmError::OutOfRange)?;
+        if start < self.base || end > self.end() {
+            return Err(VmmError::OutOfRange);
+        }
+        let first = (start - self.base) / self.granule;
+        let last = (end - self.base).div_ceil(self.granule);
+        if self.last_used(first, last).is_some() {
+            return Err(VmmError::Overlap);
+        }
+        self.set(first, last, true);
+        Ok(self.region(first, last - first, 0))
+    }
+
+    /// Reserves the pages the kernel maps into every component, and the span
+    /// the shared library may be mapped at, if they are in the window.
+    pub fn reserve_kernel_regions(&mut self) -> Result<(), VmmError> {
+        for span in [SHARED_PAGES, LIBRARY] {
+            let start = span.start.max(self.base);
+            let end = span.end.min(self.end());
+            if start < end {
+                self.reserve(start, end - start)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds a free region of at least `len` bytes.
+    ///
+    /// The start of the region is aligned to `align` (and at least to the
+    /// granule) and the region is surrounded by at least `guard` bytes that
+    /// won't be handed out to anyone else.
+    pub fn allocate(&mut self, len: usize, align: usize, guard: usize) -> Result<Region, VmmError> {
+        if !align.is_power_of_two() {
+            return Err(VmmError::BadAlignment);
+        }
+        let count = len.div_ceil(self.granule).max(1);
+        let guard = guard.div_ceil(self.granule);
+        let total = count
+            .checked_add(2 * guard)
+            .filter(|&total| total <= Self::GRANULES)
+            .ok_or(VmmError::NoSpace)?;
+        let align = align.max(self.granule);
+
+        let mut first = 0;
+        while first + total <= Self::GRANULES {
+            // Align the usable part of the region, not the guard.
+            let start = self.base + (first + guard) * self.granule;
+            let aligned = start
+                .checked_next_multiple_of(align)
+                .ok_or(VmmError::NoSpace)?;
+            first += (aligned - start) / self.granule;
+            if first + total > Self::GRANULES {
+                break;
+            }
+            match self.last_used(first, first + total) {
+                Some(used) => first = used + 1,
+                None => {
+                    self.set(first, first + total, true);
+                    return Ok(self.region(first + guard, count, guard));
+                }
+            }
+        }
+        Err(VmmError::NoSpace)
+    }
+
+    /// Returns a region, including its guards, back to the allocator.
+    pub fn release(&mut self, region: Region) -> Result<(), VmmError> {
+        let start = region.start - region.guard;
+        let end = region.end() + region.guard;
+        if start < self.base || end > self.end() {
+            return Err(VmmError::OutOfRange);
+        }
+        if (start - self.base) % self.granule != 0 || (end - self.base) % self.granule != 0 {
+            return Err(VmmError::NotAllocated);
+        }
+        let first = (start - self.base) / self.granule;
+        let last = (end - self.base) / self.granule;
+        if (first..last).any(|granule| !self.is_used(granule)) {
+            return Err(VmmError::NotAllocated);
+        }
+        self.set(first, last, false);
+        Ok(())
+    }
+
+    /// Number of words [`snapshot`](Self::snapshot) writes.
+    pub const fn snapshot_len() -> usize {
+        SNAPSHOT_HEADER_WORDS + WORDS
+    }
+
+    /// Writes the state of the allocator to the start of `buffer` and returns
+    /// the number of words written.
+    pub fn snapshot(&self, buffer: &mut [u64]) -> Result<usize, VmmError> {
+        let buffer = buffer
+            .get_mut(..Self::snapshot_len())
+            .ok_or(VmmError::BufferTooSmall)?;
+        let (header, bitmap) = buffer.split_at_mut(SNAPSHOT_HEADER_WORDS);
+        header.copy_from_slice(&[
+            self.base as u64,
+            self.granule as u64,
+            WORDS as u64,
+            self.used() as u64,
+        ]);
+        bitmap.copy_from_slice(&self.bitmap);
+        Ok(Self::snapshot_len())
+    }
+
+    /// Recreates an allocator from a snapshot.
+    ///
+    /// The number of used granules recorded in the snapshot has to match its
+    /// bitmap, which catches most snapshots that were torn or overwritten.
+    pub fn from_snapshot(snapshot: &[u64]) -> Result<Self, VmmError> {
+        let snapshot = snapshot
+            .get(..Self::snapshot_len())
+            .ok_or(VmmError::BadSnapshot)?;
+        let (header, bitmap) = snapshot.split_at(SNAPSHOT_HEADER_WORDS);
+        let header: [u64; SNAPSHOT_HEADER_WORDS] = header.try_into().unwrap();
+        let [base, granule, words, used] = header.map(|word| usize::try_from(word).ok());
+        let (Some(base), Some(granule), Some(words), Some(used)) = (base, granule, words, used)
+        else {
+            return Err(VmmError::BadSnapshot);
+        };
+        let valid = words == WORDS
+            && granule.is_power_of_two()
+            && granule >= PAGE_SIZE
+            && base % granule == 0
+            && Self::GRANULES
+                .checked_mul(granule)
+                .and_then(|len| base.checked_add(len))
+                .is_some();
+        if !valid {
+            return Err(VmmError::BadSnapshot);
+        }
+        let mut allocator = Self::new(base, granule);
+        allocator.bitmap.copy_from_slice(bitmap);
+        if allocator.used() != used {
+            return Err(VmmError::BadSnapshot);
+        }
+        Ok(allocator)
+    }
+
+    /// Number of granules that are allocated or reserved.
+    fn used(&self) -> usize {
+        self.bitmap
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    fn region(&self, first: usize, count: usize, guard: usize) -> Region {
+        Region {
+            start: self.base + first * self.granule,
+            len: count * self.granule,
+            guard: guard * self.granule,
+        }
+    }
+
+    fn is_used(&self, granule: usize) -> bool {
+        self.bitmap[granule / BITS] & (1 << (granule % BITS)) != 0
+    }
+
+    /// Finds the last used granule in `[first, last)`.
+    ///
+    /// The last one is returned so the search for free space can skip past it.
+    fn last_used(&self, first: usize, last: usize) -> Option<usize> {
+        (first..last).rev().find(|&granule| self.is_used(granule))
+    }
+
+    fn set(&mut self, first: usize, last: usize, used: bool) {
+        for granule in first..last {
+            let mask = 1 << (granule % BITS);
+            if used {
+                self.bitmap[granule / BITS] |= mask;
+            } else {
+                self.bitmap[granule / BITS] &= !mask;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::LIBRARY_ADDRESS;
+    use crate::devices::DEVICES_ADDRESS;
+    use crate::diagnostics::DIAGNOSTICS_ADDRESS;
+    use crate::info::INFO_PAGE_ADDRESS;
+
+    const BASE: usize = 0x1000_0000;
+
+    #[test]
+    fn allocations_do_not_overlap() {
+        let mut vmm = VirtAllocator::<2>::new(BASE, PAGE_SIZE);
+        let a = vmm.allocate(3 * PAGE_SIZE, 1, 0).unwrap();
+        let b = vmm.allocate(1, 1, 0).unwrap();
+        assert_eq!(a.start(), BASE);
+        assert_eq!(a.len(), 3 * PAGE_SIZE);
+        assert_eq!(b.start(), a.end());
+        assert_eq!(b.len(), PAGE_SIZE);
+        assert_eq!(vmm.free(), 124 * PAGE_SIZE);
+        assert_eq!(
+            a.pages().map(|page| page.base().as_usize()).last(),
+            Some(BASE + 2 * PAGE_SIZE)
+        );
+
+        vmm.release(a).unwrap();
+        assert_eq!(vmm.release(a), Err(VmmError::NotAllocated));
+        assert_eq!(vmm.allocate(2 * PAGE_SIZE, 1, 0).unwrap().start(), BASE);
+    }
+
+    #[test]
+    fn alignment_and_guards() {
+        let mut vmm = VirtAllocator::<2>::new(BASE, PAGE_SIZE);
+        vmm.reserve(BASE, PAGE_SIZE).unwrap();
+        let stack = vmm.allocate(4 * PAGE_SIZE, 0x4000, PAGE_SIZE).unwrap();
+        assert_eq!(stack.start(), BASE + 0x4000);
+        assert_eq!(stack.guard(), PAGE_SIZE);
+        assert!(!stack.contains(stack.end()));
+
+        // The guard after the stack is never handed out.
+        let next = vmm.allocate(PAGE_SIZE, 1, 0).unwrap();
+        assert_eq!(next.start(), BASE + PAGE_SIZE);
+        let next = vmm.allocate(2 * PAGE_SIZE, 1, 0).unwrap();
+        assert_eq!(next.start(), stack.end() + PAGE_SIZE);
+
+        assert_eq!(vmm.allocate(PAGE_SIZE, 3, 0), Err(VmmError::BadAlignment));
+        assert_eq!(vmm.allocate(128 * PAGE_SIZE, 1, 0), Err(VmmError::NoSpace));
+    }
+
+    #[test]
+    fn reservations() {
+        let mut vmm = VirtAllocator::<1>::new(BASE, PAGE_SIZE);
+        let region = vmm.reserve(BASE + 0x1800, 0x1000).unwrap();
+        assert_eq!(region.start(), BASE + PAGE_SIZE);
+        assert_eq!(region.len(), 2 * PAGE_SIZE);
+        assert_eq!(vmm.reserve(BASE, 0x1001), Err(VmmError::Overlap));
+        assert_eq!(vmm.reserve(BASE - 1, 1), Err(VmmError::OutOfRange));
+        assert_eq!(
+            vmm.reserve(vmm.end() - PAGE_SIZE, 2 * PAGE_SIZE),
+            Err(VmmError::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn kernel_regions() {
+        let base = INFO_PAGE_ADDRESS + PAGE_SIZE - 64 * PAGE_SIZE;
+        let mut vmm = VirtAllocator::<1>::new(base, PAGE_SIZE);
+        vmm.reserve_kernel_regions().unwrap();
+        assert_eq!(
+            vmm.reserve(DEVICES_ADDRESS, PAGE_SIZE),
+            Err(VmmError::Overlap)
+        );
+        assert_eq!(
+            vmm.reserve(INFO_PAGE_ADDRESS, PAGE_SIZE),
+            Err(VmmError::Overlap)
+        );
+        assert_eq!(
+            vmm.reserve(DIAGNOSTICS_ADDRESS, PAGE_SIZE),
+            Err(VmmError::Overlap)
+        );
+        assert_eq!(vmm.free(), 61 * PAGE_SIZE);
+
+        let mut elsewhere = VirtAllocator::<1>::new(BASE, PAGE_SIZE);
+        elsewhere.reserve_kernel_regions().unwrap();
+        assert_eq!(elsewhere.free(), 64 * PAGE_SIZE);
+
+        let mut library = VirtAllocator::<1>::new(LIBRARY_ADDRESS - PAGE_SIZE, PAGE_SIZE);
+        library.reserve_kernel_regions().unwrap();
+        assert_eq!(library.free(), PAGE_SIZE);
+    }
+
+    #[test]
+    fn snapshots_round_trip() {
+        let mut vmm = VirtAllocator::<2>::new(BASE, PAGE_SIZE);
+        vmm.reserve(BASE, PAGE_SIZE).unwrap();
+        let stack = vmm.allocate(4 * PAGE_SIZE, 0x4000, PAGE_SIZE).unwrap();
+
+        let mut buffer = [0; 8];
+        assert_eq!(
+            vmm.snapshot(&mut buffer[..5]),
+            Err(VmmError::BufferTooSmall)
+        );
+        assert_eq!(vmm.snapshot(&mut buffer), Ok(6));
+        assert_eq!(buffer[..4], [BASE as u64, PAGE_SIZE as u64, 2, 7]);
+
+        let mut restored = VirtAllocator::<2>::from_snapshot(&buffer).unwrap();
+        assert_eq!(restored.free(), vmm.free());
+        assert_eq!(
+            restored.allocate(PAGE_SIZE, 1, 0),
+            vmm.allocate(PAGE_SIZE, 1, 0)
+        );
+        restored.release(stack).unwrap();
+
+        // Another size of allocator.
+        assert_eq!(
+            VirtAllocator::<1>::from_snapshot(&buffer).unwrap_err(),
+            VmmError::BadSnapshot
+        );
+        // The used count doesn't match the bitmap.
+        let mut torn = buffer;
+        torn[5] = 1;
+        assert_eq!(
+            VirtAllocator::<2>::from_snapshot(&torn).unwrap_err(),
+            VmmError::BadSnapshot
+        );
+        let mut unaligned = buffer;
+        unaligned[0] += 1;
+        assert_eq!(
+            VirtAllocator::<2>::from_snapshot(&unaligned).unwrap_err(),
+            VmmError::BadSnapshot
+        );
+        assert_eq!(
+            VirtAllocator::<2>::from_snapshot(&buffer[..5]).unwrap_err(),
+            VmmError::BadSnapshot
+        );
+    }
+}
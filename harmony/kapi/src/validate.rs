@@ -0,0 +1,444 @@
+//! Checks on syscall arguments that don't depend on the kernel's state.
+//!
+//! Every operation declares what each of its arguments is in its
+//! [`Signature`], and the kernel runs [`decode`] on every syscall before
+//! dispatching it. Whatever gets past it is in range and aligned, so
+//! operations only have to check what depends on the caller, like whether a
+//! capability exists or a page is mapped. The signatures also label the
+//! arguments in syscall logs.
+
+use core::mem::align_of;
+
+use addr::PAGE_SIZE;
+
+use crate::audit::AuditRecord;
+use crate::diagnostics::{InterruptLatency, RetypeStats, Symbol};
+use crate::layout::{STANDARD, USER_END};
+use crate::ops::cap_table::{RawConstructArgs, SLOT_COUNT};
+use crate::ops::clock::Calibration;
+use crate::ops::endpoint::Message;
+use crate::ops::ipi::MAX_WORK;
+use crate::ops::logger::{LevelFilter, MAX_MARKER_LEN, MAX_WRITE_LEN};
+use crate::ops::page_table::MappingRecord;
+use crate::ops::perf::{PerfEvent, PerfMode, MAX_PERIOD};
+use crate::profile::StackSample;
+use crate::raw::{CapError, CapId, RawOperation, SyscallArgs};
+use crate::trace::TraceRecord;
+
+/// What an argument is, and so which values it can take.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ArgKind {
+    /// A capability id.
+    Cap,
+    /// A slot in a capability table.
+    Slot,
+    /// A non-null pointer into the user half, aligned to `align` bytes.
+    Pointer {
+        align: usize,
+    },
+    /// The start of a 4 KiB page in the user half.
+    Page,
+    /// An untyped frame in one of the caller's memory windows, or 0 if the
+    /// operation is given no frames.
+    Frame,
+    /// A page aligned address as seen by a device.
+    DeviceAddr,
+    /// A value between `min` and `max`, inclusive, like the discriminant of
+    /// an enum.
+    Range {
+        min: usize,
+        max: usize,
+    },
+    /// A length or count.
+    Count,
+    /// A bit mask.
+    Mask,
+    Bool,
+    /// Anything else.
+    Raw,
+}
+
+impl ArgKind {
+    /// Whether `value` is a valid argument of this kind.
+    pub fn accepts(self, value: usize) -> bool {
+        match self {
+            ArgKind::Cap => CapId::try_from(value).is_ok(),
+            ArgKind::Slot => value < SLOT_COUNT,
+            ArgKind::Pointer { align } => value != 0 && value % align == 0 && value < USER_END,
+            ArgKind::Page => value != 0 && value % PAGE_SIZE == 0 && value < USER_END,
+            ArgKind::Frame => {
+                value == 0
+                    || (value % PAGE_SIZE == 0 && STANDARD.untyped.contains(value, PAGE_SIZE))
+            }
+            ArgKind::DeviceAddr => value % PAGE_SIZE == 0,
+            ArgKind::Range { min, max } => (min..=max).contains(&value),
+            ArgKind::Bool => value <= 1,
+            ArgKind::Count | ArgKind::Mask | ArgKind::Raw => true,
+        }
+    }
+}
+
+/// Name of an operation and the arguments it takes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub name: &'static str,
+    /// Labels and kinds of the arguments, or `None` if the operation has no
+    /// encoding yet and can't be issued.
+    pub args: Option<&'static [(&'static str, ArgKind)]>,
+}
+
+const fn pointer<T>() -> ArgKind {
+    ArgKind::Pointer {
+        align: align_of::<T>(),
+    }
+}
+
+const fn range(min: usize, max: usize) -> ArgKind {
+    ArgKind::Range { min, max }
+}
+
+const BYTES: ArgKind = pointer::<u8>();
+const MAPPING_RECORDS: ArgKind = pointer::<MappingRecord>();
+const CALIBRATION: ArgKind = pointer::<Calibration>();
+const SYMBOL: ArgKind = pointer::<Symbol>();
+const TRACE_RECORDS: ArgKind = pointer::<TraceRecord>();
+const LATENCIES: ArgKind = pointer::<InterruptLatency>();
+const STACK_SAMPLES: ArgKind = pointer::<StackSample>();
+const AUDIT_RECORDS: ArgKind = pointer::<AuditRecord>();
+const MESSAGE: ArgKind = pointer::<Message>();
+const RETYPE_STATS: ArgKind = pointer::<RetypeStats>();
+const CONSTRUCT_ARGS: ArgKind = pointer::<RawConstructArgs>();
+const U8: ArgKind = range(0, u8::MAX as usize);
+const U32: ArgKind = range(0, u32::MAX as usize);
+const WORK: ArgKind = range(0, MAX_WORK as usize - 1);
+const PERF_EVENT: ArgKind = range(PerfEvent::Cycles as usize, PerfEvent::BranchMisses as usize);
+const PERF_MODE: ArgKind = range(PerfMode::User as usize, PerfMode::All as usize);
+const PERIOD: ArgKind = range(0, MAX_PERIOD as usize);
+const WRITE_LEN: ArgKind = range(0, MAX_WRITE_LEN);
+const MARKER_LEN: ArgKind = range(0, MAX_MARKER_LEN);
+const LEVEL: ArgKind = range(LevelFilter::Off as usize, LevelFilter::Trace as usize);
+
+/// Returns the signature of `op`.
+pub fn signature(op: RawOperation) -> Signature {
+    use ArgKind::*;
+    use RawOperation::*;
+    let (name, args): (_, Option<&'static [_]>) = match op {
+        ThreadActivate => ("thread.activate", Some(&[])),
+        ThreadSetAffinity => ("thread.set_affinity", Some(&[("mask", Mask)])),
+        ThreadGetAffinity => ("thread.get_affinity", Some(&[])),
+        ThreadSchedule => ("thread.schedule", Some(&[])),
+        ThreadSuspend => ("thread.suspend", Some(&[])),
+        ThreadResume => ("thread.resume", Some(&[])),
+        ThreadGetState => ("thread.get_state", Some(&[])),
+        ThreadYield => ("thread.yield", Some(&[])),
+        ThreadYieldTo => ("thread.yield_to", Some(&[("thread", Cap)])),
+        CapTableLink => (
+            "cap_table.link",
+            Some(&[("other_table", Cap), ("slot", Slot)]),
+        ),
+        CapTableUnlink => ("cap_table.unlink", Some(&[("slot", Slot)])),
+        CapTableConstruct => (
+            "cap_table.construct",
+            Some(&[("args", CONSTRUCT_ARGS), ("region", Frame), ("slot", Slot)]),
+        ),
+        CapTableDrop => ("cap_table.drop", Some(&[("slot", Slot)])),
+        CapTableCopy => ("cap_table.copy", None),
+        CapTableExtend => (
+            "cap_table.extend",
+            Some(&[("cap", Cap), ("region", Frame), ("frames", Count)]),
+        ),
+        PageTableLink => ("page_table.link", None),
+        PageTableUnlink => ("page_table.unlink", None),
+        PageTableDumpMappings => (
+            "page_table.dump_mappings",
+            Some(&[("buffer", MAPPING_RECORDS), ("capacity", Count)]),
+        ),
+        PageTableClear => ("page_table.clear", Some(&[("release", Bool)])),
+        PageTableEvict => (
+            "page_table.evict",
+            Some(&[("page", Page), ("token", Raw), ("buffer", BYTES)]),
+        ),
+        PageTablePin => ("page_table.pin", Some(&[("page", Page)])),
+        PageTableUnpin => ("page_table.unpin", Some(&[("page", Page)])),
+        MemoryRegionRetype => ("memory_region.retype", None),
+        MemoryRegionSplit => ("memory_region.split", None),
+        MemoryRegionTransfer => (
+            "memory_region.transfer",
+            Some(&[
+                ("offset", Count),
+                ("frames", Count),
+                ("table", Cap),
+                ("slot", Slot),
+            ]),
+        ),
+        MemoryRegionBase => ("memory_region.base", Some(&[])),
+        MemoryRegionFrames => ("memory_region.frames", Some(&[])),
+        MemoryRegionMap => ("memory_region.map", Some(&[("base", Frame)])),
+        MemoryRegionStats => ("memory_region.stats", Some(&[("buffer", RETYPE_STATS)])),
+        LoggerSetFilter => (
+            "logger.set_filter",
+            Some(&[
+                ("sink", BYTES),
+                ("sink_len", Count),
+                ("filter", BYTES),
+                ("filter_len", Count),
+            ]),
+        ),
+        LoggerWrite => (
+            "logger.write",
+            Some(&[("buffer", BYTES), ("len", WRITE_LEN)]),
+        ),
+        LoggerSetLevel => (
+            "logger.set_level",
+            Some(&[("sink", BYTES), ("sink_len", Count), ("level", LEVEL)]),
+        ),
+        LoggerSetModuleLevel => (
+            "logger.set_module_level",
+            Some(&[
+                ("names", BYTES),
+                ("sink_len", Count),
+                ("module_len", Count),
+                ("level", LEVEL),
+            ]),
+        ),
+        LoggerInjectMarker => (
+            "logger.inject_marker",
+            Some(&[("text", BYTES), ("len", MARKER_LEN)]),
+        ),
+        IpiSend => ("ipi.send", Some(&[("core", Count), ("work", WORK)])),
+        IpiTakePending => ("ipi.take_pending", Some(&[])),
+        ClockGetCalibration => ("clock.get_calibration", Some(&[("buffer", CALIBRATION)])),
+        ClockGetTimeNs => ("clock.get_time_ns", Some(&[])),
+        DiagnosticsMap => ("diagnostics.map", Some(&[("table", Cap), ("index", Count)])),
+        DiagnosticsRefresh => ("diagnostics.refresh", Some(&[])),
+        DiagnosticsSymbolize => (
+            "diagnostics.symbolize",
+            Some(&[("address", Raw), ("buffer", SYMBOL)]),
+        ),
+        DiagnosticsDrainTrace => (
+            "diagnostics.drain_trace",
+            Some(&[("core", Count), ("buffer", TRACE_RECORDS), ("len", Count)]),
+        ),
+        DiagnosticsInterruptLatency => (
+            "diagnostics.interrupt_latency",
+            Some(&[("vector", U8), ("buffer", LATENCIES)]),
+        ),
+        DiagnosticsProfile => ("diagnostics.profile", Some(&[("every", U32)])),
+        DiagnosticsDrainProfile => (
+            "diagnostics.drain_profile",
+            Some(&[("core", Count), ("buffer", STACK_SAMPLES), ("len", Count)]),
+        ),
+        DiagnosticsDrainAudit => (
+            "diagnostics.drain_audit",
+            Some(&[("core", Count), ("buffer", AUDIT_RECORDS), ("len", Count)]),
+        ),
+        PerfCounters => ("perf.counters", Some(&[])),
+        PerfProgram => (
+            "perf.program",
+            Some(&[
+                ("counter", U8),
+                ("event", PERF_EVENT),
+                ("mode", PERF_MODE),
+                ("period", PERIOD),
+            ]),
+        ),
+        PerfRead => ("perf.read", Some(&[("counter", U8)])),
+        PerfStop => ("perf.stop", Some(&[("counter", U8)])),
+        PerfTakeOverflows => ("perf.take_overflows", Some(&[])),
+        IommuUnits => ("iommu.units", Some(&[])),
+        DmaDomainExtend => (
+            "dma_domain.extend",
+            Some(&[("iova", DeviceAddr), ("region", Frame), ("frames", Count)]),
+        ),
+        DmaDomainGrant => (
+            "dma_domain.grant",
+            Some(&[("page", Page), ("iova", DeviceAddr), ("writable", Bool)]),
+        ),
+        DmaDomainRevoke => ("dma_domain.revoke", Some(&[("iova", DeviceAddr)])),
+        InitrdSize => ("initrd.size", Some(&[])),
+        InitrdMap => ("initrd.map", Some(&[("table", Cap), ("first", Count)])),
+        EndpointSend => (
+            "endpoint.send",
+            Some(&[("a", Raw), ("b", Raw), ("c", Raw), ("d", Raw)]),
+        ),
+        EndpointReceive => ("endpoint.receive", Some(&[("buffer", MESSAGE)])),
+        EndpointLen => ("endpoint.len", Some(&[])),
+        EndpointCapacity => ("endpoint.capacity", Some(&[])),
+        EndpointSetWatermarks => (
+            "endpoint.set_watermarks",
+            Some(&[("low", Count), ("high", Count)]),
+        ),
+        HierarchyAdopt => ("hierarchy.adopt", Some(&[("thread", Cap)])),
+        HierarchyKillTree => ("hierarchy.kill_tree", Some(&[])),
+        HierarchyTakeFaults => ("hierarchy.take_faults", Some(&[])),
+        SystemGetRandom => (
+            "system.get_random",
+            Some(&[("buffer", BYTES), ("len", Count)]),
+        ),
+        SystemIdentify => ("system.identify", Some(&[("cap", Cap)])),
+        DisplayPages => ("display.pages", Some(&[])),
+        DisplayMap => ("display.map", Some(&[("table", Cap), ("first", Count)])),
+    };
+    Signature { name, args }
+}
+
+/// Checks the capability and arguments of a syscall against the signature of
+/// its operation.
+///
+/// This is the first thing the kernel does with a syscall, and it takes the
+/// capability as it comes in, so that it can be fed arbitrary input when
+/// fuzzing. Unused arguments are ignored.
+pub fn decode(capability: usize, args: SyscallArgs) -> Result<(CapId, RawOperation), CapError> {
+    let capability = CapId::try_from(capability).map_err(|_| CapError::InvalidArgument)?;
+    let op = RawOperation::try_from(args.op()).map_err(|_| CapError::InvalidArgument)?;
+    let kinds = signature(op).args.ok_or(CapError::InvalidArgument)?;
+    let (a, b, c, d) = args.args();
+    if kinds
+        .iter()
+        .zip([a, b, c, d])
+        .all(|(&(_, kind), value)| kind.accepts(value))
+    {
+        Ok((capability, op))
+    } else {
+        Err(CapError::InvalidArgument)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::format;
+
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::ops::cap_table::CapTableOp;
+    use crate::ops::clock::ClockOp;
+    use crate::ops::diagnostics::DiagnosticsOp;
+    use crate::ops::display::DisplayOp;
+    use crate::ops::endpoint::EndpointOp;
+    use crate::ops::hierarchy::HierarchyOp;
+    use crate::ops::initrd::InitrdOp;
+    use crate::ops::iommu::{DmaDomainOp, IommuOp};
+    use crate::ops::ipi::IpiOp;
+    use crate::ops::logger::LoggerOp;
+    use crate::ops::page_table::PageTableOp;
+    use crate::ops::perf::PerfOp;
+    use crate::ops::region::RegionOp;
+    use crate::ops::system::SystemOp;
+    use crate::ops::thread::ThreadOp;
+    use crate::ops::SyscallOp;
+    use crate::raw::OPERATION_COUNT;
+
+    /// Runs `args` through the decoder of every kind of resource and returns
+    /// how many of them took it.
+    fn decoders(args: SyscallArgs) -> usize {
+        fn decodes<T: SyscallOp>(args: SyscallArgs) -> bool {
+            T::from_args(args).is_ok()
+        }
+        [
+            decodes::<ThreadOp>(args),
+            decodes::<CapTableOp<SLOT_COUNT>>(args),
+            decodes::<LoggerOp>(args),
+            decodes::<IpiOp>(args),
+            decodes::<PageTableOp>(args),
+            decodes::<RegionOp>(args),
+            decodes::<ClockOp>(args),
+            decodes::<DiagnosticsOp>(args),
+            decodes::<PerfOp>(args),
+            decodes::<IommuOp>(args),
+            decodes::<DmaDomainOp>(args),
+            decodes::<InitrdOp>(args),
+            decodes::<EndpointOp>(args),
+            decodes::<HierarchyOp>(args),
+            decodes::<SystemOp>(args),
+            decodes::<DisplayOp>(args),
+        ]
+        .into_iter()
+        .filter(|&decoded| decoded)
+        .count()
+    }
+
+    /// A value that's more likely than chance to sit on the edge of some
+    /// argument kind.
+    fn value() -> impl Strategy<Value = usize> {
+        prop_oneof![
+            any::<usize>(),
+            0..4usize,
+            Just(usize::MAX),
+            Just(u32::MAX as usize + 1),
+            (0..SLOT_COUNT + 2),
+            (0..USER_END / PAGE_SIZE + 2).prop_map(|page| page * PAGE_SIZE),
+            (STANDARD.untyped.start..STANDARD.untyped.end + PAGE_SIZE),
+        ]
+    }
+
+    #[test]
+    fn every_signature_is_valid() {
+        for op in 0..OPERATION_COUNT {
+            let signature = signature(RawOperation::try_from(op).unwrap());
+            assert!(signature.args.map_or(0, <[_]>::len) <= 4, "{signature:?}");
+        }
+    }
+
+    #[test]
+    fn checks_ranges_and_alignment() {
+        let unpin = |page| SyscallArgs::new(RawOperation::PageTableUnpin.into(), page, 0, 0, 0);
+        assert!(decode(3, unpin(0x5000)).is_ok());
+        for page in [0, 0x5008, USER_END, usize::MAX & !0xFFF] {
+            assert_eq!(decode(3, unpin(page)), Err(CapError::InvalidArgument));
+        }
+        assert_eq!(
+            decode(u32::MAX as usize + 1, unpin(0x5000)),
+            Err(CapError::InvalidArgument)
+        );
+
+        let clear =
+            |release| SyscallArgs::new(RawOperation::PageTableClear.into(), release, 0, 0, 0);
+        assert!(decode(0, clear(1)).is_ok());
+        assert!(decode(0, clear(2)).is_err());
+
+        // A region of 0 only means the operation has no frames to work with.
+        let extend =
+            |region| SyscallArgs::new(RawOperation::CapTableExtend.into(), 5, region, 0, 0);
+        assert!(decode(0, extend(0)).is_ok());
+        assert!(decode(0, extend(STANDARD.untyped.start)).is_ok());
+        assert!(decode(0, extend(0x5000)).is_err());
+
+        let construct =
+            |args, slot| SyscallArgs::new(RawOperation::CapTableConstruct.into(), args, 0, slot, 0);
+        assert!(decode(0, construct(0x5008, 3)).is_ok());
+        for (args, slot) in [(0, 3), (0x5004, 3), (USER_END, 3), (0x5008, SLOT_COUNT)] {
+            assert!(decode(0, construct(args, slot)).is_err());
+        }
+
+        // Operations without an encoding can't be issued at all.
+        let copy = SyscallArgs::new(RawOperation::CapTableCopy.into(), 0, 0, 0, 0);
+        assert!(decode(0, copy).is_err());
+        assert!(decode(0, SyscallArgs::new(OPERATION_COUNT, 0, 0, 0, 0)).is_err());
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(20_000))]
+
+        /// Feeds arbitrary syscalls through the decoding the kernel does
+        /// before and during dispatch. Nothing may panic, and whatever the
+        /// validator lets through has to decode as the operation it names.
+        #[test]
+        fn fuzz_decode(
+            capability in value(),
+            op in prop_oneof![0..OPERATION_COUNT + 1, any::<usize>()],
+            a in value(),
+            b in value(),
+            c in value(),
+            d in value(),
+        ) {
+            let args = SyscallArgs::new(op, a, b, c, d);
+            let decoded = decoders(args);
+            if decode(capability, args).is_ok() {
+                prop_assert_eq!(decoded, 1);
+            }
+        }
+    }
+}
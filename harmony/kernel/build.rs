@@ -1,6 +1,27 @@
+use std::path::PathBuf;
+
 fn main() {
-    // Tell cargo to pass the linker script to the linker..
-    println!("cargo:rustc-link-arg=-Tharmony/kernel/linker.ld");
-    // ..and to re-run if it changes.
+    // Tell cargo to pass the architecture's linker script to the linker,
+    // unless building the library's tests for the host..
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("none") {
+        let script = match std::env::var("CARGO_CFG_TARGET_ARCH").as_deref() {
+            Ok("aarch64") => "linker-aarch64.ld",
+            Ok("riscv64") => "linker-riscv64.ld",
+            _ => "linker.ld",
+        };
+        println!("cargo:rustc-link-arg=-Tharmony/kernel/{script}");
+    }
+    // ..and to re-run if they change.
     println!("cargo:rerun-if-changed=linker.ld");
+    println!("cargo:rerun-if-changed=linker-aarch64.ld");
+    println!("cargo:rerun-if-changed=linker-riscv64.ld");
+
+    // Measure the boot component so that the kernel can verify it before
+    // starting it.
+    let manifest_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    let booter = manifest_dir.join("../../.build/booter");
+    println!("cargo:rerun-if-changed={}", booter.display());
+    if let Ok(image) = std::fs::read(&booter) {
+        println!("cargo:rustc-env=BOOTER_SHA256={}", sha256::digest(&image));
+    }
 }
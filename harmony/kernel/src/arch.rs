@@ -1,4 +1,85 @@
+//! The processor-specific parts of the kernel.
+//!
+//! [`Arch`] is what the rest of the kernel needs from every architecture, and
+//! [`Current`] implements it for the one being built. The x86_64 module still
+//! exposes a lot more than the trait covers (descriptor tables, exception and
+//! syscall entry, thread contexts, the page table types, ACPI, PCI...) and
+//! the kernel reaches into it directly, so only x86_64 runs the full kernel.
+//! Those uses move behind the trait as another architecture needs them.
+//!
+//! The aarch64 and riscv64 modules bring the boot core up on QEMU's `virt`
+//! machine to the serial banner and the timer tick, and stop there.
+
+/// What the kernel needs from the processor it runs on.
+pub trait Arch {
+    /// Sets up the boot core: exception handling, the interrupt controller
+    /// and the timer.
+    fn init();
+
+    /// Masks interrupts on the current core.
+    fn disable_interrupts();
+
+    /// Unmasks interrupts on the current core.
+    ///
+    /// # Safety
+    ///
+    /// Interrupt handlers may run right away and touch anything they share
+    /// with the interrupted code.
+    unsafe fn enable_interrupts();
+
+    /// Whether interrupts are unmasked on the current core.
+    fn interrupts_enabled() -> bool;
+
+    /// Stops the core until the next interrupt.
+    fn wait_for_interrupt();
+
+    /// The hardware id of the current core, e.g. its local APIC id.
+    fn cpu_id() -> u32;
+
+    /// Reads the core's free-running cycle counter.
+    fn counter() -> u64;
+
+    /// Frequency of [`Self::counter`] in Hz.
+    ///
+    /// It may have to be measured, so it's meant to be read once and kept.
+    fn counter_frequency() -> u64;
+
+    /// Physical address of the root page table the core translates with.
+    fn root_table() -> u64;
+
+    /// Switches the core to the root page table at physical address `table`.
+    ///
+    /// # Safety
+    ///
+    /// The table must map the kernel the same way the current one does.
+    unsafe fn set_root_table(table: u64);
+
+    /// Drops the translation of the page holding `addr` from the current
+    /// core's TLB.
+    fn flush_page(addr: usize);
+
+    /// Drops every translation of the current address space from the
+    /// current core's TLB.
+    fn flush_all();
+}
+
 #[cfg(target_arch = "x86_64")]
 mod x86_64;
 #[cfg(target_arch = "x86_64")]
 pub use x86_64::*;
+#[cfg(target_arch = "x86_64")]
+pub type Current = x86_64::X86_64;
+
+#[cfg(target_arch = "aarch64")]
+mod aarch64;
+#[cfg(target_arch = "aarch64")]
+pub use aarch64::*;
+#[cfg(target_arch = "aarch64")]
+pub type Current = aarch64::Aarch64;
+
+#[cfg(target_arch = "riscv64")]
+mod riscv64;
+#[cfg(target_arch = "riscv64")]
+pub use riscv64::*;
+#[cfg(target_arch = "riscv64")]
+pub type Current = riscv64::Riscv64;
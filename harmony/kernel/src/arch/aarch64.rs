@@ -0,0 +1,100 @@
+//! Boot core bring-up on QEMU's `virt` machine.
+//!
+//! The kernel is loaded at its link address with `-kernel` and runs with an
+//! identity map of the low 4GiB. The addresses of the devices below are the
+//! ones `virt` uses rather than ones read from the device tree.
+
+use core::arch::asm;
+
+use crate::arch::Arch;
+
+mod boot;
+mod exceptions;
+pub mod gic;
+pub mod mmu;
+pub mod timer;
+pub mod uart;
+
+/// The [`Arch`] implementation.
+pub struct Aarch64;
+
+impl Arch for Aarch64 {
+    fn init() {
+        uart::init();
+        log::info!("PL011 UART is initialized");
+        exceptions::init();
+        mmu::init();
+        log::info!("Identity map is enabled");
+        gic::init();
+        timer::init();
+        log::info!("Generic timer is ticking at {} Hz", timer::TICK_FREQUENCY);
+
+        log::info!("All aarch64 subsystems initialized");
+    }
+
+    fn disable_interrupts() {
+        // SAFETY: Masking interrupts can't lead to data races.
+        unsafe { asm!("msr daifset, #2", options(nostack, nomem)) };
+    }
+
+    unsafe fn enable_interrupts() {
+        // SAFETY: Precondition.
+        unsafe { asm!("msr daifclr, #2", options(nostack, nomem)) };
+    }
+
+    fn interrupts_enabled() -> bool {
+        let daif: u64;
+        // SAFETY: Reading DAIF has no side effects.
+        unsafe { asm!("mrs {}, daif", out(reg) daif, options(nostack, nomem)) };
+        daif & (1 << 7) == 0
+    }
+
+    fn wait_for_interrupt() {
+        // SAFETY: Waiting for an interrupt has no side effects.
+        unsafe { asm!("wfi", options(nostack, nomem)) };
+    }
+
+    /// Affinity levels 0 to 2 of `MPIDR_EL1`, which is all `virt` uses.
+    fn cpu_id() -> u32 {
+        let mpidr: u64;
+        // SAFETY: Reading MPIDR has no side effects.
+        unsafe { asm!("mrs {}, mpidr_el1", out(reg) mpidr, options(nostack, nomem)) };
+        (mpidr & 0xFF_FFFF) as u32
+    }
+
+    fn counter() -> u64 {
+        timer::counter()
+    }
+
+    fn counter_frequency() -> u64 {
+        timer::frequency()
+    }
+
+    fn root_table() -> u64 {
+        mmu::root_table()
+    }
+
+    unsafe fn set_root_table(table: u64) {
+        // SAFETY: Precondition.
+        unsafe { mmu::set_root_table(table) };
+    }
+
+    fn flush_page(addr: usize) {
+        // SAFETY: Dropping TLB entries only makes the next access walk the
+        // tables again.
+        unsafe {
+            asm!(
+                "dsb ishst",
+                "tlbi vaae1, {}",
+                "dsb nsh",
+                "isb",
+                in(reg) addr >> 12,
+                options(nostack, preserves_flags),
+            );
+        }
+    }
+
+    fn flush_all() {
+        mmu::flush_tlb();
+    }
+}
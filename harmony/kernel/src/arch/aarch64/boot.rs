@@ -0,0 +1,54 @@
+//! The entry point QEMU jumps to.
+//!
+//! Only the boot core gets past `_start`: the others wait in `wfe` for good,
+//! though `virt` keeps them powered off until PSCI starts them anyway. If the
+//! core starts at EL2 (with `virtualization=on`) it drops to EL1, since the
+//! kernel only ever runs there. It then enables the FP/SIMD registers, which
+//! the compiler is free to use, sets up the boot stack and clears `.bss`
+//! before calling `kmain`.
+
+core::arch::global_asm!(
+    ".section .text.boot, \"ax\"",
+    ".global _start",
+    "_start:",
+    "    mrs x0, mpidr_el1",
+    "    and x0, x0, #0xFF",
+    "    cbnz x0, 4f",
+    "    mrs x0, CurrentEL",
+    "    lsr x0, x0, #2",
+    "    cmp x0, #2",
+    "    b.ne 1f",
+    // EL1 runs in AArch64 and can read the physical counter and timer.
+    "    mov x0, #(1 << 31)",
+    "    msr hcr_el2, x0",
+    "    mov x0, #3",
+    "    msr cnthctl_el2, x0",
+    "    msr cntvoff_el2, xzr",
+    // Return to EL1h with every exception masked.
+    "    mov x0, #0x3C5",
+    "    msr spsr_el2, x0",
+    "    adr x0, 1f",
+    "    msr elr_el2, x0",
+    "    eret",
+    "1:",
+    "    mov x0, #(3 << 20)",
+    "    msr cpacr_el1, x0",
+    "    isb",
+    "    adrp x0, __stack_top",
+    "    add x0, x0, :lo12:__stack_top",
+    "    mov sp, x0",
+    "    adrp x0, __bss_start",
+    "    add x0, x0, :lo12:__bss_start",
+    "    adrp x1, __bss_end",
+    "    add x1, x1, :lo12:__bss_end",
+    "2:",
+    "    cmp x0, x1",
+    "    b.hs 3f",
+    "    str xzr, [x0], #8",
+    "    b 2b",
+    "3:",
+    "    bl kmain",
+    "4:",
+    "    wfe",
+    "    b 4b",
+);
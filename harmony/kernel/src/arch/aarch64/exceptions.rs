@@ -0,0 +1,192 @@
+//! The EL1 exception vector table.
+//!
+//! Only IRQs taken at EL1 are handled. They save every register the handler
+//! may clobber, including the whole of the FP/SIMD registers since the
+//! procedure call standard only preserves the low half of some of them.
+//! Anything else panics with the syndrome, since nothing runs at EL0 yet and
+//! the kernel shouldn't fault.
+
+use core::arch::{asm, global_asm};
+
+use crate::arch::aarch64::{gic, timer};
+
+/// Names of the vector table entries, in order.
+const VECTORS: [&str; 16] = [
+    "synchronous from EL1 with SP_EL0",
+    "IRQ from EL1 with SP_EL0",
+    "FIQ from EL1 with SP_EL0",
+    "SError from EL1 with SP_EL0",
+    "synchronous from EL1",
+    "IRQ from EL1",
+    "FIQ from EL1",
+    "SError from EL1",
+    "synchronous from EL0 (AArch64)",
+    "IRQ from EL0 (AArch64)",
+    "FIQ from EL0 (AArch64)",
+    "SError from EL0 (AArch64)",
+    "synchronous from EL0 (AArch32)",
+    "IRQ from EL0 (AArch32)",
+    "FIQ from EL0 (AArch32)",
+    "SError from EL0 (AArch32)",
+];
+
+global_asm!(
+    ".macro unhandled index",
+    "    .balign 0x80",
+    "    mov x0, #\\index",
+    "    b {unhandled}",
+    ".endm",
+    ".section .text.vectors, \"ax\"",
+    ".balign 0x800",
+    ".global exception_vectors",
+    "exception_vectors:",
+    "    unhandled 0",
+    "    unhandled 1",
+    "    unhandled 2",
+    "    unhandled 3",
+    "    unhandled 4",
+    "    .balign 0x80",
+    "    b {irq}",
+    "    unhandled 6",
+    "    unhandled 7",
+    "    unhandled 8",
+    "    unhandled 9",
+    "    unhandled 10",
+    "    unhandled 11",
+    "    unhandled 12",
+    "    unhandled 13",
+    "    unhandled 14",
+    "    unhandled 15",
+    unhandled = sym unhandled_entry,
+    irq = sym irq_entry,
+);
+
+/// Saves the caller-saved state, runs [`irq_handler`] and returns to the
+/// interrupted code.
+#[naked]
+unsafe extern "C" fn irq_entry() -> ! {
+    // SAFETY: Only reached from the vector table.
+    unsafe {
+        asm!(
+            "sub sp, sp, #0x2C0",
+            "stp x0, x1, [sp, #0x00]",
+            "stp x2, x3, [sp, #0x10]",
+            "stp x4, x5, [sp, #0x20]",
+            "stp x6, x7, [sp, #0x30]",
+            "stp x8, x9, [sp, #0x40]",
+            "stp x10, x11, [sp, #0x50]",
+            "stp x12, x13, [sp, #0x60]",
+            "stp x14, x15, [sp, #0x70]",
+            "stp x16, x17, [sp, #0x80]",
+            "stp x18, x29, [sp, #0x90]",
+            "str x30, [sp, #0xA0]",
+            "mrs x1, fpcr",
+            "mrs x2, fpsr",
+            "str x1, [sp, #0xA8]",
+            "str x2, [sp, #0xB0]",
+            "add x0, sp, #0xC0",
+            "stp q0, q1, [x0, #0x000]",
+            "stp q2, q3, [x0, #0x020]",
+            "stp q4, q5, [x0, #0x040]",
+            "stp q6, q7, [x0, #0x060]",
+            "stp q8, q9, [x0, #0x080]",
+            "stp q10, q11, [x0, #0x0A0]",
+            "stp q12, q13, [x0, #0x0C0]",
+            "stp q14, q15, [x0, #0x0E0]",
+            "stp q16, q17, [x0, #0x100]",
+            "stp q18, q19, [x0, #0x120]",
+            "stp q20, q21, [x0, #0x140]",
+            "stp q22, q23, [x0, #0x160]",
+            "stp q24, q25, [x0, #0x180]",
+            "stp q26, q27, [x0, #0x1A0]",
+            "stp q28, q29, [x0, #0x1C0]",
+            "stp q30, q31, [x0, #0x1E0]",
+            "bl {handler}",
+            "ldr x1, [sp, #0xA8]",
+            "ldr x2, [sp, #0xB0]",
+            "msr fpcr, x1",
+            "msr fpsr, x2",
+            "add x0, sp, #0xC0",
+            "ldp q0, q1, [x0, #0x000]",
+            "ldp q2, q3, [x0, #0x020]",
+            "ldp q4, q5, [x0, #0x040]",
+            "ldp q6, q7, [x0, #0x060]",
+            "ldp q8, q9, [x0, #0x080]",
+            "ldp q10, q11, [x0, #0x0A0]",
+            "ldp q12, q13, [x0, #0x0C0]",
+            "ldp q14, q15, [x0, #0x0E0]",
+            "ldp q16, q17, [x0, #0x100]",
+            "ldp q18, q19, [x0, #0x120]",
+            "ldp q20, q21, [x0, #0x140]",
+            "ldp q22, q23, [x0, #0x160]",
+            "ldp q24, q25, [x0, #0x180]",
+            "ldp q26, q27, [x0, #0x1A0]",
+            "ldp q28, q29, [x0, #0x1C0]",
+            "ldp q30, q31, [x0, #0x1E0]",
+            "ldp x0, x1, [sp, #0x00]",
+            "ldp x2, x3, [sp, #0x10]",
+            "ldp x4, x5, [sp, #0x20]",
+            "ldp x6, x7, [sp, #0x30]",
+            "ldp x8, x9, [sp, #0x40]",
+            "ldp x10, x11, [sp, #0x50]",
+            "ldp x12, x13, [sp, #0x60]",
+            "ldp x14, x15, [sp, #0x70]",
+            "ldp x16, x17, [sp, #0x80]",
+            "ldp x18, x29, [sp, #0x90]",
+            "ldr x30, [sp, #0xA0]",
+            "add sp, sp, #0x2C0",
+            "eret",
+            handler = sym irq_handler,
+            options(noreturn),
+        );
+    }
+}
+
+/// Passes the vector index and the syndrome to [`unhandled`].
+#[naked]
+unsafe extern "C" fn unhandled_entry() -> ! {
+    // SAFETY: Only reached from the vector table, with the index in x0.
+    unsafe {
+        asm!(
+            "mrs x1, esr_el1",
+            "mrs x2, elr_el1",
+            "mrs x3, far_el1",
+            "b {}",
+            sym unhandled,
+            options(noreturn),
+        );
+    }
+}
+
+extern "C" fn irq_handler() {
+    while let Some(id) = gic::acknowledge() {
+        match id {
+            timer::TIMER_IRQ => timer::tick(),
+            _ => log::warn!("Unexpected interrupt {id}"),
+        }
+        gic::end(id);
+    }
+}
+
+extern "C" fn unhandled(index: usize, esr: u64, elr: u64, far: u64) -> ! {
+    panic!(
+        "Unhandled {} exception: ESR={esr:#X} ELR={elr:#X} FAR={far:#X}",
+        VECTORS[index]
+    );
+}
+
+/// Points `VBAR_EL1` at the vector table.
+pub fn init() {
+    extern "C" {
+        static exception_vectors: u8;
+    }
+    // SAFETY: The table handles every exception the kernel can take.
+    unsafe {
+        asm!(
+            "msr vbar_el1, {}",
+            "isb",
+            in(reg) core::ptr::addr_of!(exception_vectors),
+            options(nostack),
+        );
+    }
+}
@@ -0,0 +1,64 @@
+//! The GICv2 interrupt controller.
+//!
+//! Every interrupt is routed to the boot core's CPU interface with the same
+//! priority, and only the ones passed to [`enable`] are forwarded.
+
+use core::ptr::{read_volatile, write_volatile};
+
+/// Base of the distributor on `virt`.
+const DISTRIBUTOR: usize = 0x0800_0000;
+/// Base of the CPU interface on `virt`.
+const CPU_INTERFACE: usize = 0x0801_0000;
+
+/// Distributor control register.
+const GICD_CTLR: usize = 0x000;
+/// Distributor set-enable registers, a bit per interrupt.
+const GICD_ISENABLER: usize = 0x100;
+/// CPU interface control register.
+const GICC_CTLR: usize = 0x000;
+/// Priority mask register.
+const GICC_PMR: usize = 0x004;
+/// Interrupt acknowledge register.
+const GICC_IAR: usize = 0x00C;
+/// End of interrupt register.
+const GICC_EOIR: usize = 0x010;
+
+/// The id [`acknowledge`] reads when no interrupt is pending.
+const SPURIOUS: u32 = 1023;
+
+fn read(base: usize, register: usize) -> u32 {
+    // SAFETY: The GIC is identity mapped at `base`.
+    unsafe { read_volatile((base + register) as *const u32) }
+}
+
+fn write(base: usize, register: usize, value: u32) {
+    // SAFETY: The GIC is identity mapped at `base`.
+    unsafe { write_volatile((base + register) as *mut u32, value) }
+}
+
+/// Turns on the distributor and the CPU interface with no interrupts masked
+/// by priority.
+pub fn init() {
+    write(DISTRIBUTOR, GICD_CTLR, 1);
+    write(CPU_INTERFACE, GICC_PMR, 0xFF);
+    write(CPU_INTERFACE, GICC_CTLR, 1);
+}
+
+/// Forwards interrupt `id` to the CPU interface.
+pub fn enable(id: u32) {
+    let register = GICD_ISENABLER + 4 * (id as usize / 32);
+    write(DISTRIBUTOR, register, 1 << (id % 32));
+}
+
+/// Takes the highest priority pending interrupt, if any.
+///
+/// It must be passed to [`end`] once handled.
+pub fn acknowledge() -> Option<u32> {
+    let id = read(CPU_INTERFACE, GICC_IAR) & 0x3FF;
+    (id != SPURIOUS).then_some(id)
+}
+
+/// Lets interrupt `id` be taken again.
+pub fn end(id: u32) {
+    write(CPU_INTERFACE, GICC_EOIR, id);
+}
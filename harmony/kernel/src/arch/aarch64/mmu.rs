@@ -0,0 +1,121 @@
+//! The boot identity map.
+//!
+//! With a 4KiB granule and 39-bit virtual addresses, translation starts at
+//! level 1 where every entry maps 1GiB. The first gigabyte on `virt` is
+//! flash and devices so it's mapped as device memory, and the next three
+//! cover the start of RAM, where the kernel is loaded, as normal memory.
+
+use core::arch::asm;
+
+/// Number of 1GiB blocks that are identity mapped.
+const BLOCKS: usize = 4;
+
+/// Index of device-nGnRnE memory in `MAIR_EL1`.
+const ATTR_DEVICE: u64 = 0;
+/// Index of write-back cacheable memory in `MAIR_EL1`.
+const ATTR_NORMAL: u64 = 1;
+const MAIR: u64 = (0x00 << (8 * ATTR_DEVICE)) | (0xFF << (8 * ATTR_NORMAL));
+
+const BLOCK: u64 = 0b01;
+const ACCESSED: u64 = 1 << 10;
+const INNER_SHAREABLE: u64 = 0b11 << 8;
+const PRIVILEGED_NO_EXECUTE: u64 = 1 << 53;
+const USER_NO_EXECUTE: u64 = 1 << 54;
+
+/// 39-bit addresses in `TTBR0_EL1` with write-back, inner shareable walks
+/// and a 4KiB granule. Walks through `TTBR1_EL1` are disabled.
+const TCR: u64 = 25 | (0b01 << 8) | (0b01 << 10) | (0b11 << 12) | (1 << 23);
+
+/// MMU, data cache and instruction cache enable bits of `SCTLR_EL1`.
+const SCTLR_ENABLE: u64 = (1 << 0) | (1 << 2) | (1 << 12);
+
+#[repr(C, align(4096))]
+struct Table([u64; 512]);
+
+static mut ROOT: Table = Table([0; 512]);
+
+/// Fills in the identity map and turns on the MMU and caches.
+pub fn init() {
+    // SAFETY: Only the boot core runs this, once, before the MMU is on.
+    let root = unsafe { &mut *core::ptr::addr_of_mut!(ROOT) };
+    for (index, entry) in root.0.iter_mut().take(BLOCKS).enumerate() {
+        let base = (index as u64) << 30;
+        *entry = if index == 0 {
+            base | BLOCK | ACCESSED | (ATTR_DEVICE << 2) | PRIVILEGED_NO_EXECUTE | USER_NO_EXECUTE
+        } else {
+            base | BLOCK | ACCESSED | (ATTR_NORMAL << 2) | INNER_SHAREABLE | USER_NO_EXECUTE
+        };
+    }
+
+    let parange: u64;
+    // SAFETY: Reading the feature register has no side effects.
+    unsafe { asm!("mrs {}, id_aa64mmfr0_el1", out(reg) parange, options(nostack, nomem)) };
+    let tcr = TCR | ((parange & 0b111) << 32);
+    // SAFETY: The map is the identity on everything the kernel touches, so
+    // nothing moves when it's turned on.
+    unsafe {
+        asm!(
+            "msr mair_el1, {mair}",
+            "msr tcr_el1, {tcr}",
+            "msr ttbr0_el1, {root}",
+            "dsb ish",
+            "isb",
+            "tlbi vmalle1",
+            "dsb nsh",
+            "isb",
+            "mrs {tmp}, sctlr_el1",
+            "orr {tmp}, {tmp}, {enable}",
+            "msr sctlr_el1, {tmp}",
+            "isb",
+            mair = in(reg) MAIR,
+            tcr = in(reg) tcr,
+            root = in(reg) root.0.as_ptr(),
+            enable = in(reg) SCTLR_ENABLE,
+            tmp = out(reg) _,
+            options(nostack),
+        );
+    }
+}
+
+/// Physical address of the table in `TTBR0_EL1`.
+pub fn root_table() -> u64 {
+    let ttbr: u64;
+    // SAFETY: Reading TTBR0 has no side effects.
+    unsafe { asm!("mrs {}, ttbr0_el1", out(reg) ttbr, options(nostack, nomem)) };
+    // Drops the ASID and the common-not-private bit.
+    ttbr & 0x0000_FFFF_FFFF_FFFE
+}
+
+/// Translates through the level 1 table at `table` from now on.
+///
+/// # Safety
+///
+/// The table must identity map the kernel like the boot one does.
+pub unsafe fn set_root_table(table: u64) {
+    // SAFETY: Precondition.
+    unsafe {
+        asm!(
+            "dsb ish",
+            "msr ttbr0_el1, {}",
+            "isb",
+            in(reg) table,
+            options(nostack),
+        );
+    }
+    flush_tlb();
+}
+
+/// Drops every EL1 translation from the current core's TLB.
+pub fn flush_tlb() {
+    // SAFETY: Dropping TLB entries only makes the next accesses walk the
+    // tables again.
+    unsafe {
+        asm!(
+            "dsb ishst",
+            "tlbi vmalle1",
+            "dsb nsh",
+            "isb",
+            options(nostack)
+        )
+    };
+}
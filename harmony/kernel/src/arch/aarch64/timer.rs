@@ -0,0 +1,69 @@
+//! The EL1 physical timer of the generic timer.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::arch::aarch64::gic;
+
+/// Timer interrupts per second.
+pub const TICK_FREQUENCY: u64 = 100;
+
+/// The private interrupt of the EL1 physical timer.
+pub const TIMER_IRQ: u32 = 30;
+
+/// Enabled, with its interrupt unmasked.
+const CTL_ENABLE: u64 = 1;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Frequency of the system counter in Hz, as the firmware (or QEMU) set it.
+pub fn frequency() -> u64 {
+    let frequency: u64;
+    // SAFETY: Reading CNTFRQ has no side effects.
+    unsafe { asm!("mrs {}, cntfrq_el0", out(reg) frequency, options(nostack, nomem)) };
+    frequency
+}
+
+/// Reads the system counter.
+pub fn counter() -> u64 {
+    let count: u64;
+    // SAFETY: Reading the counter has no side effects. The `isb` keeps it
+    // from being read ahead of earlier instructions.
+    unsafe { asm!("isb", "mrs {}, cntpct_el0", out(reg) count, options(nostack, nomem)) };
+    count
+}
+
+/// Timer interrupts taken so far.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+fn arm() {
+    let interval = frequency() / TICK_FREQUENCY;
+    // SAFETY: Only reprograms the timer, whose interrupt is handled by `tick`.
+    unsafe {
+        asm!(
+            "msr cntp_tval_el0, {interval}",
+            "msr cntp_ctl_el0, {ctl}",
+            "isb",
+            interval = in(reg) interval,
+            ctl = in(reg) CTL_ENABLE,
+            options(nostack, nomem),
+        );
+    }
+}
+
+/// Starts ticking at [`TICK_FREQUENCY`].
+pub fn init() {
+    gic::enable(TIMER_IRQ);
+    arm();
+}
+
+/// Handles the timer interrupt.
+pub fn tick() {
+    let ticks = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    if ticks % TICK_FREQUENCY == 0 {
+        log::debug!("{} seconds since the timer started", ticks / TICK_FREQUENCY);
+    }
+    arm();
+}
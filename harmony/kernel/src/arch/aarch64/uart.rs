@@ -0,0 +1,108 @@
+//! The PL011 UART and a logger that writes to it.
+//!
+//! QEMU's PL011 works without setting up the baud rate or line control, so
+//! this only enables it and polls the FIFO before every byte.
+
+use core::fmt::{self, Write as _};
+use core::ptr::{read_volatile, write_volatile};
+
+use log::{LevelFilter, Metadata, Record};
+use sync::cell::AtomicRefCell;
+
+/// Base of the UART on `virt`.
+const BASE: usize = 0x0900_0000;
+
+/// Data register.
+const DR: usize = 0x00;
+/// Flag register.
+const FR: usize = 0x18;
+/// Control register.
+const CR: usize = 0x30;
+
+/// Transmit FIFO full.
+const FR_TXFF: u32 = 1 << 5;
+/// UART, transmit and receive enable.
+const CR_ENABLE: u32 = (1 << 0) | (1 << 8) | (1 << 9);
+
+pub struct Pl011 {
+    base: usize,
+}
+
+impl Pl011 {
+    /// # Safety
+    ///
+    /// A PL011 must be mapped at `base` and not be used through anything else.
+    pub const unsafe fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    fn read(&self, register: usize) -> u32 {
+        // SAFETY: The UART is mapped at `base`.
+        unsafe { read_volatile((self.base + register) as *const u32) }
+    }
+
+    fn write(&mut self, register: usize, value: u32) {
+        // SAFETY: The UART is mapped at `base`.
+        unsafe { write_volatile((self.base + register) as *mut u32, value) }
+    }
+
+    pub fn enable(&mut self) {
+        self.write(CR, CR_ENABLE);
+    }
+
+    pub fn send(&mut self, byte: u8) {
+        while self.read(FR) & FR_TXFF != 0 {
+            core::hint::spin_loop();
+        }
+        self.write(DR, byte.into());
+    }
+}
+
+impl fmt::Write for Pl011 {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.send(b'\r');
+            }
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
+
+// SAFETY: Nothing else uses the UART at `BASE`.
+static UART: AtomicRefCell<Pl011> = AtomicRefCell::new(unsafe { Pl011::new(BASE) });
+
+struct UartLogger;
+
+impl log::Log for UartLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        // Records logged while another one is being written, e.g. from an
+        // interrupt handler, are dropped.
+        let Ok(mut uart) = UART.borrow_mut() else {
+            return;
+        };
+        let _ = writeln!(
+            uart,
+            "[{}] {}: {}",
+            record.level(),
+            record.module_path().unwrap_or("?"),
+            record.args()
+        );
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: UartLogger = UartLogger;
+
+/// Enables the UART and sends every log record to it.
+pub fn init() {
+    UART.borrow_mut().unwrap().enable();
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(LevelFilter::Info);
+}
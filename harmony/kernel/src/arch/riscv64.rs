@@ -0,0 +1,94 @@
+//! Boot hart bring-up on QEMU's `virt` machine.
+//!
+//! The kernel runs in S-mode under OpenSBI (`-bios default`), which loads it
+//! at its link address and leaves the console and the timer to SBI calls. It
+//! runs with an identity map of the low 4GiB. Like the aarch64 port, nothing
+//! is read from the device tree yet.
+
+use core::arch::asm;
+
+use crate::arch::Arch;
+
+mod boot;
+pub mod console;
+pub mod mmu;
+pub mod sbi;
+pub mod timer;
+pub mod trap;
+
+/// The [`Arch`] implementation.
+pub struct Riscv64;
+
+/// The interrupt enable bit of `sstatus`.
+const SSTATUS_SIE: usize = 1 << 1;
+
+impl Arch for Riscv64 {
+    fn init() {
+        console::init();
+        log::info!("SBI console is initialized");
+        trap::init();
+        mmu::init();
+        log::info!("Sv39 identity map is enabled");
+        timer::init();
+        log::info!("Timer is ticking at {} Hz", timer::TICK_FREQUENCY);
+
+        log::info!("All riscv64 subsystems initialized");
+    }
+
+    fn disable_interrupts() {
+        // SAFETY: Masking interrupts can't lead to data races.
+        unsafe { asm!("csrc sstatus, {}", in(reg) SSTATUS_SIE, options(nostack, nomem)) };
+    }
+
+    unsafe fn enable_interrupts() {
+        // SAFETY: Precondition.
+        unsafe { asm!("csrs sstatus, {}", in(reg) SSTATUS_SIE, options(nostack, nomem)) };
+    }
+
+    fn interrupts_enabled() -> bool {
+        let sstatus: usize;
+        // SAFETY: Reading sstatus has no side effects.
+        unsafe { asm!("csrr {}, sstatus", out(reg) sstatus, options(nostack, nomem)) };
+        sstatus & SSTATUS_SIE != 0
+    }
+
+    fn wait_for_interrupt() {
+        // SAFETY: Waiting for an interrupt has no side effects.
+        unsafe { asm!("wfi", options(nostack, nomem)) };
+    }
+
+    /// The hart id SBI started the kernel with, which `_start` keeps in `tp`.
+    fn cpu_id() -> u32 {
+        let hart: usize;
+        // SAFETY: Reading a register has no side effects.
+        unsafe { asm!("mv {}, tp", out(reg) hart, options(nostack, nomem)) };
+        hart as u32
+    }
+
+    fn counter() -> u64 {
+        timer::counter()
+    }
+
+    fn counter_frequency() -> u64 {
+        timer::FREQUENCY
+    }
+
+    fn root_table() -> u64 {
+        mmu::root_table()
+    }
+
+    unsafe fn set_root_table(table: u64) {
+        // SAFETY: Precondition.
+        unsafe { mmu::set_root_table(table) };
+    }
+
+    fn flush_page(addr: usize) {
+        // SAFETY: Dropping TLB entries only makes the next access walk the
+        // tables again.
+        unsafe { asm!("sfence.vma {}, zero", in(reg) addr, options(nostack)) };
+    }
+
+    fn flush_all() {
+        mmu::flush_tlb();
+    }
+}
@@ -0,0 +1,29 @@
+//! The entry point OpenSBI jumps to.
+//!
+//! SBI starts a single hart, passing its id in `a0`, and keeps the others
+//! stopped until they're started through the HSM extension. `_start` keeps
+//! the id in `tp`, sets up the boot stack, clears `.bss` and turns on the
+//! FP registers, which the compiler is free to use, before calling `kmain`.
+
+core::arch::global_asm!(
+    ".section .text.boot, \"ax\"",
+    ".global _start",
+    "_start:",
+    "    mv tp, a0",
+    "    la sp, __stack_top",
+    "    la t0, __bss_start",
+    "    la t1, __bss_end",
+    "1:",
+    "    bgeu t0, t1, 2f",
+    "    sd zero, 0(t0)",
+    "    addi t0, t0, 8",
+    "    j 1b",
+    "2:",
+    // sstatus.FS = Initial
+    "    li t0, 1 << 13",
+    "    csrs sstatus, t0",
+    "    call kmain",
+    "3:",
+    "    wfi",
+    "    j 3b",
+);
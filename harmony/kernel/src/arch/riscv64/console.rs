@@ -0,0 +1,63 @@
+//! A logger on top of the SBI console.
+//!
+//! Every byte is an SBI call, which is slow but needs no driver.
+
+use core::fmt::{self, Write as _};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use log::{LevelFilter, Metadata, Record};
+
+use crate::arch::riscv64::sbi;
+
+/// Whether to use the debug console over the legacy putchar.
+static DEBUG_CONSOLE: AtomicBool = AtomicBool::new(false);
+
+pub struct SbiConsole;
+
+impl SbiConsole {
+    pub fn send(&mut self, byte: u8) {
+        if DEBUG_CONSOLE.load(Ordering::Relaxed) {
+            sbi::console_write_byte(byte);
+        } else {
+            sbi::legacy_putchar(byte);
+        }
+    }
+}
+
+impl fmt::Write for SbiConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
+
+struct ConsoleLogger;
+
+impl log::Log for ConsoleLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let _ = writeln!(
+            SbiConsole,
+            "[{}] {}: {}",
+            record.level(),
+            record.module_path().unwrap_or("?"),
+            record.args()
+        );
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: ConsoleLogger = ConsoleLogger;
+
+/// Picks the console extension and sends every log record to it.
+pub fn init() {
+    DEBUG_CONSOLE.store(sbi::has_debug_console(), Ordering::Relaxed);
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(LevelFilter::Info);
+}
@@ -0,0 +1,75 @@
+//! The boot identity map.
+//!
+//! Sv39 walks start at level 2, where every entry maps 1GiB. The first two
+//! gigabytes on `virt` are devices and the next two are the start of RAM,
+//! where OpenSBI and the kernel are loaded. Sv39 has no memory types without
+//! the Svpbmt extension, so the only difference is that devices aren't
+//! executable.
+
+use core::arch::asm;
+
+/// Number of 1GiB pages that are identity mapped.
+const PAGES: usize = 4;
+/// The first page that is RAM.
+const RAM_PAGE: usize = 2;
+
+const VALID: u64 = 1 << 0;
+const READ: u64 = 1 << 1;
+const WRITE: u64 = 1 << 2;
+const EXECUTE: u64 = 1 << 3;
+const GLOBAL: u64 = 1 << 5;
+const ACCESSED: u64 = 1 << 6;
+const DIRTY: u64 = 1 << 7;
+
+/// The Sv39 mode in `satp`.
+const SATP_SV39: u64 = 8 << 60;
+/// The physical page number in `satp`.
+const SATP_PPN: u64 = (1 << 44) - 1;
+
+#[repr(C, align(4096))]
+struct Table([u64; 512]);
+
+static mut ROOT: Table = Table([0; 512]);
+
+/// Fills in the identity map and turns on translation.
+pub fn init() {
+    // SAFETY: Only the boot hart runs this, once, before translation is on.
+    let root = unsafe { &mut *core::ptr::addr_of_mut!(ROOT) };
+    for (index, entry) in root.0.iter_mut().take(PAGES).enumerate() {
+        let ppn = ((index as u64) << 30) >> 12;
+        let mut flags = VALID | READ | WRITE | GLOBAL | ACCESSED | DIRTY;
+        if index >= RAM_PAGE {
+            flags |= EXECUTE;
+        }
+        *entry = (ppn << 10) | flags;
+    }
+    // SAFETY: The map is the identity on everything the kernel touches, so
+    // nothing moves when it's turned on.
+    unsafe { set_root_table(root.0.as_ptr() as u64) };
+}
+
+/// Physical address of the table in `satp`.
+pub fn root_table() -> u64 {
+    let satp: u64;
+    // SAFETY: Reading satp has no side effects.
+    unsafe { asm!("csrr {}, satp", out(reg) satp, options(nostack, nomem)) };
+    (satp & SATP_PPN) << 12
+}
+
+/// Translates through the Sv39 root table at `table` from now on.
+///
+/// # Safety
+///
+/// The table must identity map the kernel like the boot one does.
+pub unsafe fn set_root_table(table: u64) {
+    let satp = SATP_SV39 | (table >> 12);
+    // SAFETY: Precondition.
+    unsafe { asm!("csrw satp, {}", "sfence.vma", in(reg) satp, options(nostack)) };
+}
+
+/// Drops every translation from the current hart's TLB.
+pub fn flush_tlb() {
+    // SAFETY: Dropping TLB entries only makes the next accesses walk the
+    // tables again.
+    unsafe { asm!("sfence.vma", options(nostack)) };
+}
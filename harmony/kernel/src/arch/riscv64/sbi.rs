@@ -0,0 +1,62 @@
+//! Calls into the SBI firmware.
+
+use core::arch::asm;
+
+/// The base extension.
+const BASE: usize = 0x10;
+/// The legacy console putchar extension.
+const LEGACY_PUTCHAR: usize = 0x01;
+/// The debug console extension.
+const DBCN: usize = 0x4442_434E;
+/// The timer extension.
+const TIME: usize = 0x5449_4D45;
+
+/// The value and error an SBI call returned.
+#[derive(Debug, Copy, Clone)]
+pub struct SbiRet {
+    pub error: isize,
+    pub value: usize,
+}
+
+fn call(extension: usize, function: usize, arg0: usize) -> SbiRet {
+    let (error, value);
+    // SAFETY: The calls made here don't touch the kernel's memory.
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") arg0 => error,
+            lateout("a1") value,
+            in("a6") function,
+            in("a7") extension,
+            options(nostack),
+        );
+    }
+    SbiRet { error, value }
+}
+
+/// Whether the firmware implements `extension`.
+pub fn probe(extension: usize) -> bool {
+    call(BASE, 3, extension).value != 0
+}
+
+/// Whether the firmware has the debug console extension of SBI 2.0.
+pub fn has_debug_console() -> bool {
+    probe(DBCN)
+}
+
+/// Writes a byte to the debug console.
+pub fn console_write_byte(byte: u8) -> SbiRet {
+    call(DBCN, 2, byte.into())
+}
+
+/// Writes a byte to the console with the legacy extension, which firmware
+/// older than SBI 2.0 may only have.
+pub fn legacy_putchar(byte: u8) {
+    call(LEGACY_PUTCHAR, 0, byte.into());
+}
+
+/// Raises the timer interrupt once `time` reaches `deadline`, and clears the
+/// pending one.
+pub fn set_timer(deadline: u64) -> SbiRet {
+    call(TIME, 0, deadline as usize)
+}
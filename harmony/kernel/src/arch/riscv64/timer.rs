@@ -0,0 +1,54 @@
+//! The supervisor timer, programmed through SBI.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::arch::riscv64::sbi;
+
+/// Timer interrupts per second.
+pub const TICK_FREQUENCY: u64 = 100;
+
+/// Frequency of the `time` CSR in Hz, which is `timebase-frequency` in the
+/// device tree of `virt`.
+pub const FREQUENCY: u64 = 10_000_000;
+
+/// The timer interrupt enable bit of `sie`.
+const SIE_STIE: usize = 1 << 5;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Reads the `time` CSR.
+pub fn counter() -> u64 {
+    let time: u64;
+    // SAFETY: Reading the time has no side effects.
+    unsafe { asm!("rdtime {}", out(reg) time, options(nostack, nomem)) };
+    time
+}
+
+/// Timer interrupts taken so far.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+fn arm() {
+    let result = sbi::set_timer(counter() + FREQUENCY / TICK_FREQUENCY);
+    if result.error != 0 {
+        log::warn!("Couldn't set the timer: SBI error {}", result.error);
+    }
+}
+
+/// Starts ticking at [`TICK_FREQUENCY`].
+pub fn init() {
+    // SAFETY: The timer interrupt is handled by `tick`.
+    unsafe { asm!("csrs sie, {}", in(reg) SIE_STIE, options(nostack, nomem)) };
+    arm();
+}
+
+/// Handles the timer interrupt.
+pub fn tick() {
+    let ticks = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    if ticks % TICK_FREQUENCY == 0 {
+        log::debug!("{} seconds since the timer started", ticks / TICK_FREQUENCY);
+    }
+    arm();
+}
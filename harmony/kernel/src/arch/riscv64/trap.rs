@@ -0,0 +1,177 @@
+//! Trap entry and the syscall path.
+//!
+//! `stvec` points every trap at one entry that saves the whole register file
+//! in a [`TrapFrame`] and calls [`trap_handler`]. Traps from U-mode switch to
+//! the kernel stack kept in `sscratch`, which is zero while the hart runs in
+//! S-mode so that traps from the kernel stay on the current stack. Syscalls
+//! are `ecall`s from U-mode with the arguments in `a0` to `a5` and the result
+//! in `a0`, like `kapi::raw::raw_syscall` expects. Nothing runs in U-mode
+//! yet, so they only fail with [`CapError::InvalidOp`].
+
+use core::arch::{asm, global_asm};
+
+use kapi::raw::CapError;
+
+use crate::arch::riscv64::timer;
+
+/// The registers of the interrupted code.
+#[derive(Debug)]
+#[repr(C)]
+pub struct TrapFrame {
+    /// `x0` to `x31`, where `x0` is unused.
+    pub regs: [usize; 32],
+    /// `f0` to `f31`.
+    pub fregs: [u64; 32],
+    pub sepc: usize,
+    pub sstatus: usize,
+    pub fcsr: usize,
+    _padding: usize,
+}
+
+const _: () = assert!(core::mem::size_of::<TrapFrame>() == 544);
+
+/// Index of `a0` in [`TrapFrame::regs`].
+const A0: usize = 10;
+
+/// The bit of `scause` set for interrupts.
+const INTERRUPT: usize = 1 << 63;
+/// Supervisor timer interrupt.
+const TIMER_INTERRUPT: usize = 5;
+/// Environment call from U-mode.
+const USER_ECALL: usize = 8;
+
+global_asm!(
+    ".section .text.trap, \"ax\"",
+    // The target has the D extension, but global_asm! isn't told.
+    ".option arch, +d",
+    ".balign 4",
+    ".global trap_entry",
+    "trap_entry:",
+    "    csrrw sp, sscratch, sp",
+    "    bnez sp, 1f",
+    // From S-mode: put sp and sscratch back.
+    "    csrrw sp, sscratch, sp",
+    "1:",
+    "    addi sp, sp, -544",
+    "    .irp r, 1,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+    "    sd x\\r, \\r*8(sp)",
+    "    .endr",
+    // The interrupted sp is in sscratch for U-mode and above the frame for
+    // S-mode.
+    "    csrr t0, sscratch",
+    "    bnez t0, 2f",
+    "    addi t0, sp, 544",
+    "2:",
+    "    sd t0, 16(sp)",
+    "    csrw sscratch, zero",
+    "    .irp r, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+    "    fsd f\\r, 256+\\r*8(sp)",
+    "    .endr",
+    "    csrr t0, sepc",
+    "    sd t0, 512(sp)",
+    "    csrr t0, sstatus",
+    "    sd t0, 520(sp)",
+    "    frcsr t0",
+    "    sd t0, 528(sp)",
+    "    mv a0, sp",
+    "    call {handler}",
+    "    ld t0, 512(sp)",
+    "    csrw sepc, t0",
+    "    ld t0, 528(sp)",
+    "    fscsr t0",
+    "    ld t0, 520(sp)",
+    "    csrw sstatus, t0",
+    // Going back to U-mode (SPP clear), keep the kernel stack in sscratch
+    // for the next trap.
+    "    andi t0, t0, 1 << 8",
+    "    bnez t0, 3f",
+    "    addi t0, sp, 544",
+    "    csrw sscratch, t0",
+    "3:",
+    "    .irp r, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+    "    fld f\\r, 256+\\r*8(sp)",
+    "    .endr",
+    "    .irp r, 1,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+    "    ld x\\r, \\r*8(sp)",
+    "    .endr",
+    "    ld sp, 16(sp)",
+    "    sret",
+    handler = sym trap_handler,
+);
+
+fn cause(scause: usize) -> &'static str {
+    if scause & INTERRUPT != 0 {
+        return match scause & !INTERRUPT {
+            1 => "software interrupt",
+            TIMER_INTERRUPT => "timer interrupt",
+            9 => "external interrupt",
+            _ => "unknown interrupt",
+        };
+    }
+    match scause {
+        0 => "misaligned fetch",
+        1 => "fetch access fault",
+        2 => "illegal instruction",
+        3 => "breakpoint",
+        4 => "misaligned load",
+        5 => "load access fault",
+        6 => "misaligned store",
+        7 => "store access fault",
+        USER_ECALL => "environment call from U-mode",
+        9 => "environment call from S-mode",
+        12 => "instruction page fault",
+        13 => "load page fault",
+        15 => "store page fault",
+        _ => "unknown exception",
+    }
+}
+
+extern "C" fn trap_handler(frame: &mut TrapFrame) {
+    let scause: usize;
+    // SAFETY: Reading scause has no side effects.
+    unsafe { asm!("csrr {}, scause", out(reg) scause, options(nostack, nomem)) };
+    match scause {
+        _ if scause == INTERRUPT | TIMER_INTERRUPT => timer::tick(),
+        USER_ECALL => syscall(frame),
+        _ => {
+            let stval: usize;
+            // SAFETY: Reading stval has no side effects.
+            unsafe { asm!("csrr {}, stval", out(reg) stval, options(nostack, nomem)) };
+            panic!(
+                "Unhandled {} at {:#X}: scause={scause:#X} stval={stval:#X}",
+                cause(scause),
+                frame.sepc
+            );
+        }
+    }
+}
+
+fn syscall(frame: &mut TrapFrame) {
+    let args = &frame.regs[A0..A0 + 6];
+    log::warn!(
+        "Syscall {:#X} on capability {} at {:#X}, which riscv64 doesn't implement yet",
+        args[1],
+        args[0],
+        frame.sepc
+    );
+    frame.regs[A0] = CapError::InvalidOp.to_errno() as usize;
+    // Return past the ecall.
+    frame.sepc += 4;
+}
+
+/// Points `stvec` at the trap entry in direct mode.
+pub fn init() {
+    extern "C" {
+        static trap_entry: u8;
+    }
+    // SAFETY: The entry handles every trap the kernel can take, and the
+    // hart is in S-mode.
+    unsafe {
+        asm!(
+            "csrw sscratch, zero",
+            "csrw stvec, {}",
+            in(reg) core::ptr::addr_of!(trap_entry),
+            options(nostack),
+        );
+    }
+}
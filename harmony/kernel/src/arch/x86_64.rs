@@ -1,12 +1,24 @@
 use core::arch::asm;
+use core::arch::x86_64::__cpuid;
 
-use crate::arch::timer::Pit8253;
+use crate::arch::paging::page_table::AnyPageTable;
+use crate::arch::timer::{Pit8253, TICK_RESET_VALUE};
+use crate::arch::Arch;
 
+pub mod acpi;
 pub mod bootup;
+pub mod context;
+pub mod dynlink;
 pub mod exec;
 pub mod instructions;
 pub mod interrupts;
+pub mod iommu;
+pub mod numa;
 pub mod paging;
+pub mod pci;
+pub mod pmu;
+pub mod sections;
+pub mod simd;
 pub mod timer;
 
 mod gdt;
@@ -15,13 +27,128 @@ mod registers;
 pub fn init() {
     gdt::init();
     interrupts::init();
-    let mut _timer = unsafe { Pit8253::steal().into_timer(5966) };
+    let mut _timer = unsafe { Pit8253::steal().into_timer(TICK_RESET_VALUE) };
     log::info!("PIT Timer is initialized");
     sce_enable();
+    simd::init();
 
     log::info!("All x86-64 subsystems initialized");
 }
 
+/// Measures the TSC frequency in Hz.
+pub fn tsc_frequency() -> u64 {
+    // SAFETY: Calibration only uses PIT channel 2 which isn't used elsewhere.
+    unsafe { Pit8253::steal().calibrate_tsc() }
+}
+
+/// Whether the TSC runs at a constant rate in every power state.
+pub fn has_invariant_tsc() -> bool {
+    // SAFETY: CPUID leaf 0x8000_0000 is always available on x86-64.
+    let max_leaf = unsafe { __cpuid(0x8000_0000) }.eax;
+    if max_leaf < 0x8000_0007 {
+        return false;
+    }
+    // SAFETY: Checked that the leaf is supported.
+    let leaf = unsafe { __cpuid(0x8000_0007) };
+    leaf.edx & (1 << 8) != 0
+}
+
+/// Whether the processor has the `rdrand` instruction.
+pub fn has_rdrand() -> bool {
+    // SAFETY: CPUID leaf 1 is always available on x86-64.
+    let leaf = unsafe { __cpuid(1) };
+    leaf.ecx & (1 << 30) != 0
+}
+
+/// Whether the processor has the `rdseed` instruction.
+pub fn has_rdseed() -> bool {
+    // SAFETY: CPUID leaf 0 is always available on x86-64.
+    let max_leaf = unsafe { __cpuid(0) }.eax;
+    if max_leaf < 7 {
+        return false;
+    }
+    // SAFETY: Checked that the leaf is supported.
+    let leaf = unsafe { core::arch::x86_64::__cpuid_count(7, 0) };
+    leaf.ebx & (1 << 18) != 0
+}
+
+/// Returns the hardware (APIC) id of the current core.
+pub fn hardware_cpu_id() -> u32 {
+    // SAFETY: CPUID leaf 1 is always available on x86-64.
+    let leaf = unsafe { __cpuid(1) };
+    leaf.ebx >> 24
+}
+
+/// Stores the core index in `IA32_TSC_AUX` so that userspace can read it with `rdtscp`.
+///
+/// Returns false if the processor doesn't support `rdtscp`.
+pub fn set_core_index(index: u32) -> bool {
+    const IA32_TSC_AUX: u32 = 0xC000_0103;
+    // SAFETY: CPUID leaf 0x8000_0001 is always available on x86-64.
+    let leaf = unsafe { __cpuid(0x8000_0001) };
+    if leaf.edx & (1 << 27) == 0 {
+        return false;
+    }
+    // SAFETY: TSC_AUX is only observable through rdtscp/rdpid.
+    unsafe { registers::wrmsr(IA32_TSC_AUX, index.into()) };
+    true
+}
+
+/// The [`Arch`] implementation, on top of the functions above.
+pub struct X86_64;
+
+impl Arch for X86_64 {
+    fn init() {
+        init();
+    }
+
+    fn disable_interrupts() {
+        interrupts::disable();
+    }
+
+    unsafe fn enable_interrupts() {
+        // SAFETY: Precondition.
+        unsafe { interrupts::enable() };
+    }
+
+    fn interrupts_enabled() -> bool {
+        interrupts::are_enabled()
+    }
+
+    fn wait_for_interrupt() {
+        instructions::hlt();
+    }
+
+    fn cpu_id() -> u32 {
+        hardware_cpu_id()
+    }
+
+    fn counter() -> u64 {
+        instructions::rdtsc()
+    }
+
+    fn counter_frequency() -> u64 {
+        tsc_frequency()
+    }
+
+    fn root_table() -> u64 {
+        AnyPageTable::current_raw().addr().as_u64()
+    }
+
+    unsafe fn set_root_table(table: u64) {
+        // SAFETY: Precondition.
+        unsafe { asm!("mov cr3, {}", in(reg) table, options(nostack, preserves_flags)) };
+    }
+
+    fn flush_page(addr: usize) {
+        instructions::invlpg(addr);
+    }
+
+    fn flush_all() {
+        instructions::flush_tlb();
+    }
+}
+
 fn sce_enable() {
     // SAFETY: Nothing special, just enabling Syscall extension.
     unsafe {
@@ -0,0 +1,135 @@
+//! Lookup of ACPI tables.
+//!
+//! Only the tables themselves are found here. Each user parses the table it
+//! asked for. Tables are read through the direct map, so a table the
+//! bootloader didn't map is treated as missing.
+
+use crate::arch::paging::page_table::AnyPageTable;
+use crate::arch::paging::{Page, PhysAddr, PhysAddrExt as _, VirtAddr, PAGE_SIZE};
+use crate::boot::{self, BootProtocol as _};
+
+/// Size of the header every system description table starts with.
+pub const HEADER_SIZE: usize = 36;
+
+/// Borrows `len` bytes of physical memory at `address` through the direct
+/// map, if it covers them.
+pub fn mapped(address: u64, len: usize) -> Option<&'static [u8]> {
+    let start = PhysAddr::new(address).to_virtual();
+    let end = start.as_usize().checked_add(len)?;
+    let table = AnyPageTable::current();
+    // SAFETY: CR3 always holds a root-level page table.
+    let addrspace = unsafe { table.as_addrspace() };
+    for page in (start.as_usize() & !(PAGE_SIZE - 1)..end).step_by(PAGE_SIZE) {
+        addrspace.leaf(Page::containing_address(VirtAddr::new(page)))?;
+    }
+    // SAFETY: The range is mapped and firmware tables are never written to.
+    Some(unsafe { core::slice::from_raw_parts(start.as_ptr(), len) })
+}
+
+/// Whether the bytes of `table` add up to 0.
+fn checksum(table: &[u8]) -> bool {
+    table.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// Maps the table at `address` if it's whole and its checksum is right.
+fn table_at(address: u64) -> Option<&'static [u8]> {
+    let header = mapped(address, HEADER_SIZE)?;
+    let len = read_u32(header, 4)? as usize;
+    if len < HEADER_SIZE {
+        return None;
+    }
+    let table = mapped(address, len)?;
+    checksum(table).then_some(table)
+}
+
+/// Addresses of the tables listed in an RSDT, or an XSDT if `wide`.
+fn entries(root: &[u8], wide: bool) -> impl Iterator<Item = u64> + '_ {
+    let size = if wide { 8 } else { 4 };
+    root.get(HEADER_SIZE..)
+        .unwrap_or_default()
+        .chunks_exact(size)
+        .map(move |entry| match wide {
+            true => read_u64(entry, 0).unwrap(),
+            false => read_u32(entry, 0).unwrap().into(),
+        })
+}
+
+/// Address of the root table the RSDP points to, and whether it's an XSDT.
+fn root_table(rsdp: &[u8]) -> Option<(u64, bool)> {
+    if rsdp.get(..8)? != b"RSD PTR " || !checksum(rsdp.get(..20)?) {
+        return None;
+    }
+    let revision = *rsdp.get(15)?;
+    if revision >= 2 {
+        let len = read_u32(rsdp, 20)? as usize;
+        if checksum(rsdp.get(..len)?) {
+            return Some((read_u64(rsdp, 24)?, true));
+        }
+    }
+    Some((read_u32(rsdp, 16)?.into(), false))
+}
+
+/// Finds the table with `signature`, including its header.
+pub fn find(signature: &[u8; 4]) -> Option<&'static [u8]> {
+    let rsdp = mapped(boot::protocol().rsdp()?, HEADER_SIZE)?;
+    let (root, wide) = root_table(rsdp)?;
+    let root = table_at(root)?;
+    entries(root, wide)
+        .filter_map(table_at)
+        .find(|table| &table[..4] == signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_checksum<const N: usize>(mut bytes: [u8; N], at: usize, len: usize) -> [u8; N] {
+        bytes[at] = 0;
+        let sum = bytes[..len]
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        bytes[at] = sum.wrapping_neg();
+        bytes
+    }
+
+    #[test_case]
+    fn reads_both_root_pointers() {
+        let mut rsdp = [0; 36];
+        rsdp[..8].copy_from_slice(b"RSD PTR ");
+        rsdp[16..20].copy_from_slice(&0x1234_5000u32.to_le_bytes());
+        let v1 = with_checksum(rsdp, 8, 20);
+        assert_eq!(root_table(&v1), Some((0x1234_5000, false)));
+
+        rsdp[15] = 2;
+        rsdp[20..24].copy_from_slice(&36u32.to_le_bytes());
+        rsdp[24..32].copy_from_slice(&0x1_0000_0000u64.to_le_bytes());
+        let v2 = with_checksum(with_checksum(rsdp, 8, 20), 32, 36);
+        assert_eq!(root_table(&v2), Some((0x1_0000_0000, true)));
+
+        let mut corrupt = v2;
+        corrupt[0] = b'X';
+        assert_eq!(root_table(&corrupt), None);
+    }
+
+    #[test_case]
+    fn lists_root_table_entries() {
+        let mut xsdt = [0; HEADER_SIZE + 16];
+        xsdt[HEADER_SIZE..HEADER_SIZE + 8].copy_from_slice(&0xA000u64.to_le_bytes());
+        xsdt[HEADER_SIZE + 8..].copy_from_slice(&0xB000u64.to_le_bytes());
+        assert!(entries(&xsdt, true).eq([0xA000, 0xB000]));
+        assert!(entries(&xsdt, false).eq([0xA000, 0, 0xB000, 0]));
+        assert_eq!(entries(&xsdt[..HEADER_SIZE], true).count(), 0);
+    }
+}
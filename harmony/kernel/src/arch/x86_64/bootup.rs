@@ -1,124 +1,234 @@
 //! Boot process initialization
 
-use goblin::elf::program_header::{PF_R, PF_W, PF_X, PT_LOAD};
+use goblin::elf::program_header::{PF_R, PF_W, PF_X, PT_LOAD, PT_NOTE};
 use goblin::elf64::header::{Header, SIZEOF_EHDR};
-use goblin::elf64::program_header::ProgramHeader;
+use goblin::elf64::program_header::{ProgramHeader, SIZEOF_PHDR};
+use kapi::component::{Metadata, NoteError};
+use kapi::devices::DEVICES_ADDRESS;
+use kapi::diagnostics::DIAGNOSTICS_ADDRESS;
+use kapi::info::INFO_PAGE_ADDRESS;
+use kapi::layout::{Layout, LayoutError};
 
-use super::paging::page_table::AnyPageTable;
-use crate::arch::exec::{ControlRegs, ExecCtx, Regs};
-use crate::arch::paging::page_table::{Addrspace, PageTableFlags};
-use crate::arch::paging::{Page, PhysAddr, RawFrame, VirtAddr, FRAME_SIZE, PAGE_SIZE};
+use super::dynlink::{self, LinkError};
+use crate::arch::context::{ControlRegs, ExecCtx, Regs};
+use crate::arch::paging::page_table::{
+    Addrspace, AnyPageTable, MapperError, PageTableFlags, PageTableLevel,
+};
+use crate::arch::paging::policy::{MappingPolicy, Permissions, PolicyError};
+use crate::arch::paging::{Page, PhysAddrExt as _, RawFrame, VirtAddr, PAGE_SIZE};
 use crate::bump_allocator::BumpAllocator;
 use crate::kptr::KPtr;
 
-pub struct Process {
+/// Largest stack a component can ask for in its notes.
+const MAX_STACK_PAGES: usize = 512;
+/// Most program headers a program can have.
+const MAX_PROGRAM_HEADERS: usize = 32;
+
+pub struct Process<'prog> {
     pub entry: u64,
     pub rsp: u64,
     pub l4_table: KPtr<AnyPageTable>,
+    /// What the component declared in its notes.
+    pub metadata: Metadata<'prog>,
+    /// What the component's mappings may look like.
+    pub policy: MappingPolicy,
+    /// Where the component's mappings go.
+    pub layout: Layout,
 }
 
 #[derive(Debug)]
-pub enum LoadError {}
+pub enum LoadError {
+    /// The ELF header or the program headers aren't in the file, or there are
+    /// too many program headers.
+    BadHeaders,
+    BadNotes(NoteError),
+    StackTooLarge,
+    Link(LinkError),
+    Map(MapperError),
+    /// A segment asks for a mapping the component isn't allowed.
+    Policy(PolicyError),
+    /// The layout is invalid or something doesn't fit in its region.
+    Layout(LayoutError),
+}
+
+impl From<NoteError> for LoadError {
+    fn from(value: NoteError) -> Self {
+        LoadError::BadNotes(value)
+    }
+}
+
+impl From<LinkError> for LoadError {
+    fn from(value: LinkError) -> Self {
+        LoadError::Link(value)
+    }
+}
+
+impl From<MapperError> for LoadError {
+    fn from(value: MapperError) -> Self {
+        LoadError::Map(value)
+    }
+}
+
+impl From<PolicyError> for LoadError {
+    fn from(value: PolicyError) -> Self {
+        LoadError::Policy(value)
+    }
+}
+
+impl From<LayoutError> for LoadError {
+    fn from(value: LayoutError) -> Self {
+        LoadError::Layout(value)
+    }
+}
 
-impl Process {
+impl<'prog> Process<'prog> {
+    /// Loads the ELF in `program` into a new address space.
+    ///
+    /// The stack gets `default_stack_pages` unless the component declared its
+    /// size in a note. The segments are mapped as `policy` allows and have to
+    /// be in the code region of `layout`, and the stack grows down from the
+    /// end of its stack region. No untyped memory is mapped: the component
+    /// maps windows for the regions it's given. If loading fails part of the
+    /// way, everything mapped so far is [unloaded](Self::unload).
     pub fn load(
-        program: &[u8],
-        stack_pages: usize,
-        untyped_memory_offset: usize,
-        untyped_memory_length: usize,
+        program: &'prog [u8],
+        policy: MappingPolicy,
+        layout: Layout,
+        default_stack_pages: usize,
     ) -> Result<Self, LoadError> {
         let mut fallocator = BumpAllocator::new();
-        assert!(untyped_memory_offset % PAGE_SIZE == 0);
-        assert!(untyped_memory_length % PAGE_SIZE == 0);
-        assert!(untyped_memory_offset + untyped_memory_length < 0xFFFF_8000_0000_0000);
-        assert!(
-            program.as_ptr() as usize % 16 == 0,
-            "ELF must be aligned to 16 bytes"
-        );
-
-        log::debug!("Setting up process address space");
-        let l4_table = {
-            let l4_frame = fallocator.alloc_untyped_frame().unwrap();
-            AnyPageTable::new_l4(l4_frame).unwrap()
-        };
-        let addrspace = unsafe { l4_table.as_addrspace() };
-        let header = Header::from_bytes(program[..SIZEOF_EHDR].try_into().unwrap());
+        layout.validate()?;
+        let header = elf_header(program).ok_or(LoadError::BadHeaders)?;
         let entry = header.e_entry;
         log::trace!("Entry: {:X}", entry);
-        let phdrs = unsafe {
-            assert!(
-                program.len()
-                    > usize::try_from(header.e_phoff).unwrap()
-                        + usize::from(header.e_phentsize) * usize::from(header.e_phnum)
-            );
-            let phdr_start: *const ProgramHeader = program
-                .as_ptr()
-                .add(header.e_phoff.try_into().unwrap())
-                .cast();
-            assert!(phdr_start as usize % core::mem::align_of::<ProgramHeader>() == 0);
-            ProgramHeader::from_raw_parts(phdr_start, header.e_phnum.into())
+        let phdrs = program_headers(program).ok_or(LoadError::BadHeaders)?;
+        let metadata = notes(program, &phdrs)?;
+        let stack_pages = match metadata.stack_size {
+            Some(size) => usize::try_from(size)
+                .map(|size| size.div_ceil(PAGE_SIZE))
+                .ok()
+                .filter(|&pages| pages <= MAX_STACK_PAGES)
+                .ok_or(LoadError::StackTooLarge)?,
+            None => default_stack_pages,
+        };
+        if stack_pages * PAGE_SIZE > layout.stack.len() {
+            return Err(LoadError::StackTooLarge);
+        }
+
+        log::debug!("Setting up process address space");
+        let l4_table = fallocator
+            .alloc_untyped_frame()
+            .and_then(|frame| AnyPageTable::new_l4(frame).ok())
+            .ok_or(MapperError::FrameAllocationError)?;
+        let process = Self {
+            entry,
+            rsp: layout.stack.end as u64,
+            l4_table,
+            metadata,
+            policy,
+            layout,
         };
+        if let Err(e) = process.populate(program, &phdrs, stack_pages, &mut fallocator) {
+            log::warn!("Failed to load process: {e:?}");
+            process.unload();
+            return Err(e);
+        }
+        log::info!("Initialized user process");
+        Ok(process)
+    }
+
+    /// Maps the program, its stack and the pages every component sees.
+    fn populate(
+        &self,
+        program: &[u8],
+        phdrs: &[ProgramHeader],
+        stack_pages: usize,
+        fallocator: &mut BumpAllocator,
+    ) -> Result<(), LoadError> {
+        // SAFETY: The address space isn't in use yet.
+        let addrspace = unsafe { self.l4_table.as_addrspace() };
         for ph in phdrs {
             if ph.p_type == PT_LOAD {
                 log::debug!("Loading segment");
+                self.layout.check_code(
+                    usize::try_from(ph.p_vaddr).map_err(|_| LayoutError::OutsideRegion)?,
+                    usize::try_from(ph.p_memsz).map_err(|_| LayoutError::OutsideRegion)?,
+                )?;
                 let segment = Segment::new(program, ph);
-                segment.load(&addrspace, &mut fallocator);
+                segment.load(0, self.policy, &addrspace, fallocator)?;
             }
         }
+        dynlink::link(program, phdrs, &addrspace, fallocator)?;
 
         log::debug!("Setting up stack pages");
-        let rsp = untyped_memory_offset;
         for i in 0..stack_pages {
-            let frame = fallocator.alloc_user_frame().unwrap().into_raw();
-            let addr = rsp - PAGE_SIZE * (i + 1);
+            let addr = self.layout.stack.end - PAGE_SIZE * (i + 1);
             let page = Page::from_start_address(VirtAddr::new(addr));
-            // SAFETY: Just mapping the stack pages.
-            unsafe {
-                addrspace
-                    .map_to(
-                        page,
-                        frame,
-                        PageTableFlags::PRESENT
-                            | PageTableFlags::WRITABLE
-                            | PageTableFlags::USER_ACCESSIBLE
-                            | PageTableFlags::NO_EXECUTE,
-                        PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE,
-                        &mut fallocator,
-                    )
-                    .unwrap();
-            }
+            map_user_frame(
+                &addrspace,
+                page,
+                PageTableFlags::PRESENT
+                    | PageTableFlags::WRITABLE
+                    | PageTableFlags::USER_ACCESSIBLE
+                    | PageTableFlags::NO_EXECUTE,
+                fallocator,
+            )?;
         }
 
-        let untyped_memory_pages = untyped_memory_length / PAGE_SIZE;
-        log::debug!("Setting up {untyped_memory_pages} untyped memory pages");
-        for i in 0..untyped_memory_pages {
-            let frame = RawFrame::from_start_address(PhysAddr::new(i as u64 * FRAME_SIZE));
-            let page = Page::from_start_address(VirtAddr::new(
-                (frame.base().as_u64() + untyped_memory_offset as u64) as usize,
-            ));
-            // SAFETY: Mapping non-user accessible untyped pages.
-            // FIXME: Do this with huge pages? Since each l1 table can hold
-            // 256 entries, we are effectively wasting a bit over 1/256 frames
-            // in the system.
+        let shared = [
+            ("device inventory", crate::devices::frame(), DEVICES_ADDRESS),
+            (
+                "diagnostics page",
+                crate::diagnostics::frame(),
+                DIAGNOSTICS_ADDRESS,
+            ),
+            ("kernel info page", crate::info::frame(), INFO_PAGE_ADDRESS),
+        ];
+        for (name, frame, address) in shared {
+            let Some(frame) = frame else {
+                continue;
+            };
+            log::debug!("Mapping the {name}");
+            let page = Page::from_start_address(VirtAddr::new(address));
+            // SAFETY: These pages are read-only to userspace.
             unsafe {
-                addrspace
-                    .map_to(
-                        page,
-                        frame,
-                        PageTableFlags::PRESENT,
-                        PageTableFlags::PRESENT,
-                        &mut fallocator,
-                    )
-                    .unwrap();
+                addrspace.map_to(
+                    page,
+                    frame,
+                    PageTableFlags::PRESENT
+                        | PageTableFlags::USER_ACCESSIBLE
+                        | PageTableFlags::NO_EXECUTE,
+                    PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE,
+                    fallocator,
+                )?;
             }
         }
 
-        log::info!("Initialized user process");
-        Ok(Self {
-            entry,
-            rsp: untyped_memory_offset as u64,
-            l4_table,
-        })
+        Ok(())
+    }
+
+    /// Tears down the address space of a process that never ran.
+    ///
+    /// The frames backing the program and its stack, and the page tables
+    /// themselves, go back to untyped memory. Returns the number of pages that
+    /// were unmapped.
+    pub fn unload(self) -> usize {
+        let mut unmapped = 0;
+        let mut budget = usize::MAX;
+        // SAFETY: The address space was never loaded, so no TLB caches it.
+        unsafe {
+            self.l4_table
+                .clear(PageTableLevel::top(), false, &mut budget, &mut |cleared| {
+                    if cleared.release(true) {
+                        unmapped += 1;
+                    }
+                });
+        }
+        let frame = self.l4_table.frame();
+        drop(self.l4_table);
+        let _ = frame.try_into_untyped();
+        log::debug!("Unloaded process, unmapped {unmapped} pages");
+        unmapped
     }
 
     pub fn into_exec(self) -> ExecCtx {
@@ -136,7 +246,93 @@ impl Process {
     }
 }
 
-struct Segment<'prog, 'head> {
+/// The program headers of an ELF file, copied out of it so that the file
+/// can be at any alignment, e.g. inside an archive.
+#[derive(Debug, Copy, Clone)]
+pub(super) struct ProgramHeaders {
+    headers: [ProgramHeader; MAX_PROGRAM_HEADERS],
+    len: usize,
+}
+
+impl ProgramHeaders {
+    /// Copies `phdrs`, if there aren't too many of them.
+    pub fn from_slice(phdrs: &[ProgramHeader]) -> Option<Self> {
+        let mut headers = [ProgramHeader::default(); MAX_PROGRAM_HEADERS];
+        headers.get_mut(..phdrs.len())?.copy_from_slice(phdrs);
+        Some(Self {
+            headers,
+            len: phdrs.len(),
+        })
+    }
+}
+
+impl core::ops::Deref for ProgramHeaders {
+    type Target = [ProgramHeader];
+
+    fn deref(&self) -> &[ProgramHeader] {
+        &self.headers[..self.len]
+    }
+}
+
+/// Reads the ELF header of `program`, if it's long enough to have one.
+pub(super) fn elf_header(program: &[u8]) -> Option<Header> {
+    let bytes = program.get(..SIZEOF_EHDR)?;
+    // SAFETY: The header is in bounds and any bytes make a valid header.
+    Some(unsafe { bytes.as_ptr().cast::<Header>().read_unaligned() })
+}
+
+/// Copies the program headers out of the ELF in `program`.
+///
+/// Returns `None` if they aren't in the file, aren't the size of a 64-bit
+/// program header or there are more than [`MAX_PROGRAM_HEADERS`].
+pub(super) fn program_headers(program: &[u8]) -> Option<ProgramHeaders> {
+    let header = elf_header(program)?;
+    let count = usize::from(header.e_phnum);
+    if usize::from(header.e_phentsize) != SIZEOF_PHDR || count > MAX_PROGRAM_HEADERS {
+        return None;
+    }
+    let start = usize::try_from(header.e_phoff).ok()?;
+    let bytes = program.get(start..start.checked_add(count * SIZEOF_PHDR)?)?;
+    let mut phdrs = ProgramHeaders {
+        headers: [ProgramHeader::default(); MAX_PROGRAM_HEADERS],
+        len: count,
+    };
+    for (ph, bytes) in phdrs
+        .headers
+        .iter_mut()
+        .zip(bytes.chunks_exact(SIZEOF_PHDR))
+    {
+        // SAFETY: The chunk holds a whole header and any bytes make a valid
+        // header.
+        *ph = unsafe { bytes.as_ptr().cast::<ProgramHeader>().read_unaligned() };
+    }
+    Some(phdrs)
+}
+
+/// Finds the component notes among the note segments of `program`.
+fn notes<'prog>(
+    program: &'prog [u8],
+    phdrs: &[ProgramHeader],
+) -> Result<Metadata<'prog>, LoadError> {
+    let mut found = Metadata::default();
+    for ph in phdrs.iter().filter(|ph| ph.p_type == PT_NOTE) {
+        let start = usize::try_from(ph.p_offset).unwrap();
+        let end = start + usize::try_from(ph.p_filesz).unwrap();
+        assert!(end <= program.len());
+        let metadata = Metadata::parse(&program[start..end])?;
+        if metadata.is_empty() {
+            continue;
+        }
+        // The linker puts every `.note.harmony` section in the same segment.
+        if !found.is_empty() {
+            return Err(NoteError::Duplicate.into());
+        }
+        found = metadata;
+    }
+    Ok(found)
+}
+
+pub(super) struct Segment<'prog, 'head> {
     program: &'prog [u8],
     header: &'head ProgramHeader,
 }
@@ -146,40 +342,40 @@ impl<'prog, 'head> Segment<'prog, 'head> {
         Self { program, header }
     }
 
-    pub fn load(&self, address_space: &Addrspace, fallocator: &mut BumpAllocator) {
-        let vm_range = self.header.p_vaddr..(self.header.p_vaddr + self.header.p_memsz);
+    /// What the segment asks to do with its pages.
+    fn permissions(&self) -> Permissions {
+        let flags = self.header.p_flags;
+        assert!(flags & PF_R != 0);
+        Permissions {
+            write: flags & PF_W != 0,
+            execute: flags & PF_X != 0,
+        }
+    }
+
+    /// Loads the segment `base` bytes above the address it was linked at, if
+    /// `policy` allows its permissions and address.
+    pub fn load(
+        &self,
+        base: u64,
+        policy: MappingPolicy,
+        address_space: &Addrspace,
+        fallocator: &mut BumpAllocator,
+    ) -> Result<(), LoadError> {
+        let vm_start = base
+            .checked_add(self.header.p_vaddr)
+            .ok_or(PolicyError::NotUser)?;
+        let pflags = policy.check(vm_start, self.header.p_memsz, self.permissions())?;
+        let vm_range = vm_start..(vm_start + self.header.p_memsz);
         let file_range = self.header.p_offset..(self.header.p_offset + self.header.p_filesz);
 
-        assert!(vm_range.end <= 0xFFFF800000000000);
         assert!(file_range.end <= self.program.len() as u64);
         assert!(self.header.p_memsz >= self.header.p_filesz);
         let mut vcurrent = vm_range.start;
         let mut fcurrent = file_range.start;
         while vcurrent < vm_range.end {
-            let frame = fallocator.alloc_user_frame().unwrap().into_raw();
             let page = Page::containing_address(VirtAddr::new(vcurrent as usize));
-            let flags = self.header.p_flags;
-            let mut pflags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
-            assert!(flags & PF_R != 0);
-            if flags & PF_W != 0 {
-                pflags |= PageTableFlags::WRITABLE;
-            }
-            if flags & PF_X == 0 {
-                pflags |= PageTableFlags::NO_EXECUTE;
-            }
-            log::info!("Mapping {page:?} to {frame:?} with {pflags:?}");
-            // SAFETY: Just mapping the elf data.
-            unsafe {
-                address_space
-                    .map_to(
-                        page,
-                        frame,
-                        pflags,
-                        PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE,
-                        fallocator,
-                    )
-                    .unwrap();
-            }
+            let frame = map_user_frame(address_space, page, pflags, fallocator)?;
+            log::info!("Mapped {page:?} to {frame:?} with {pflags:?}");
 
             let offset_page: *mut u8 = frame.base().to_virtual().as_mut_ptr();
 
@@ -202,5 +398,243 @@ impl<'prog, 'head> Segment<'prog, 'head> {
             vcurrent += PAGE_SIZE as u64 - vcurrent % PAGE_SIZE as u64;
             fcurrent += count as u64;
         }
+        Ok(())
+    }
+}
+
+/// Maps a newly allocated user frame at `page`.
+///
+/// The mapping owns the frame's user reference. If the page can't be mapped,
+/// the frame goes back to untyped memory.
+fn map_user_frame(
+    address_space: &Addrspace,
+    page: Page,
+    flags: PageTableFlags,
+    fallocator: &mut BumpAllocator,
+) -> Result<RawFrame, MapperError> {
+    let frame = fallocator
+        .alloc_user_frame()
+        .ok_or(MapperError::FrameAllocationError)?
+        .into_raw();
+    // SAFETY: The frame was just allocated, nothing else is using it.
+    let mapped = unsafe {
+        address_space.map_to(
+            page,
+            frame,
+            flags,
+            PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE,
+            fallocator,
+        )
+    };
+    if let Err(e) = mapped {
+        // SAFETY: The reference leaked by `into_raw` was never handed out.
+        unsafe {
+            frame.drop_user_ref();
+        }
+        let _ = frame.try_into_untyped();
+        return Err(e);
+    }
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::elf::dynamic::{DT_NEEDED, DT_NULL, DT_STRSZ, DT_STRTAB};
+    use goblin::elf::program_header::PT_DYNAMIC;
+    use kapi::diagnostics::RetypeStats;
+    use kapi::layout::{Span, STANDARD};
+
+    use super::*;
+    use crate::retyping::RetypeTable;
+
+    const SIZE: usize = 0x200;
+    const BASE: u64 = 0x40_0000;
+    const STRINGS: &[u8] = b"\0libother.so\0";
+
+    #[repr(align(16))]
+    struct Image([u8; SIZE]);
+
+    fn put(image: &mut [u8], offset: usize, words: &[u64]) {
+        for (i, word) in words.iter().enumerate() {
+            image[offset + i * 8..offset + i * 8 + 8].copy_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    /// A program with three pages of data that needs a library nobody
+    /// provides, so it only fails once its segment is mapped.
+    fn program() -> Image {
+        let mut image = [0; SIZE];
+        image[..4].copy_from_slice(b"\x7FELF");
+        put(&mut image, 24, &[BASE, SIZEOF_EHDR as u64]);
+        image[54..56].copy_from_slice(&(SIZEOF_PHDR as u16).to_le_bytes());
+        image[56..58].copy_from_slice(&2u16.to_le_bytes());
+        let phdr = |kind: u32, flags: u32, start: usize, len: usize, memsz: usize| {
+            [
+                u64::from(flags) << 32 | u64::from(kind),
+                start as u64,
+                BASE + start as u64,
+                0,
+                len as u64,
+                memsz as u64,
+                0x1000,
+            ]
+        };
+        put(
+            &mut image,
+            SIZEOF_EHDR,
+            &phdr(PT_LOAD, PF_R | PF_W, 0, SIZE, 3 * PAGE_SIZE),
+        );
+        put(
+            &mut image,
+            SIZEOF_EHDR + SIZEOF_PHDR,
+            &phdr(PT_DYNAMIC, PF_R, 0x100, 0x40, 0x40),
+        );
+        put(
+            &mut image,
+            0x100,
+            &[
+                DT_NEEDED,
+                1,
+                DT_STRTAB,
+                BASE + 0x180,
+                DT_STRSZ,
+                STRINGS.len() as u64,
+                DT_NULL,
+                0,
+            ],
+        );
+        image[0x180..0x180 + STRINGS.len()].copy_from_slice(STRINGS);
+        Image(image)
+    }
+
+    fn stats() -> RetypeStats {
+        let stats = RetypeTable::stats().unwrap();
+        RetypeStats { clean: 0, ..stats }
+    }
+
+    #[test_case]
+    fn failed_loads_are_unloaded() {
+        let image = program();
+        let before = stats();
+        let result = Process::load(&image.0, MappingPolicy::STRICT, STANDARD, 2);
+        assert!(matches!(
+            result,
+            Err(LoadError::Link(LinkError::MissingLibrary))
+        ));
+        assert_eq!(stats(), before);
+    }
+
+    #[test_case]
+    fn writable_executable_segments_need_jit() {
+        let mut image = program();
+        image.0[SIZEOF_EHDR + 4..SIZEOF_EHDR + 8]
+            .copy_from_slice(&(PF_R | PF_W | PF_X).to_le_bytes());
+        let before = stats();
+        let result = Process::load(&image.0, MappingPolicy::STRICT, STANDARD, 2);
+        assert!(matches!(
+            result,
+            Err(LoadError::Policy(PolicyError::WriteExecute))
+        ));
+        assert_eq!(stats(), before);
+
+        // Gets past the segment and fails on the library instead.
+        let jit = MappingPolicy { jit: true };
+        let result = Process::load(&image.0, jit, STANDARD, 2);
+        assert!(matches!(
+            result,
+            Err(LoadError::Link(LinkError::MissingLibrary))
+        ));
+        assert_eq!(stats(), before);
+    }
+
+    #[test_case]
+    fn segments_stay_in_the_code_region() {
+        let mut image = program();
+        let before = stats();
+        let vaddr = SIZEOF_EHDR + 16..SIZEOF_EHDR + 24;
+        image.0[vaddr.clone()].copy_from_slice(&(STANDARD.untyped.start as u64).to_le_bytes());
+        let result = Process::load(&image.0, MappingPolicy::STRICT, STANDARD, 2);
+        assert!(matches!(
+            result,
+            Err(LoadError::Layout(LayoutError::Reserved))
+        ));
+        image.0[vaddr].copy_from_slice(&0x1000u64.to_le_bytes());
+        let result = Process::load(&image.0, MappingPolicy::STRICT, STANDARD, 2);
+        assert!(matches!(
+            result,
+            Err(LoadError::Layout(LayoutError::OutsideRegion))
+        ));
+        assert_eq!(stats(), before);
+
+        // Layouts with overlapping regions are refused before anything is
+        // mapped.
+        let layout = Layout {
+            stack: Span::new(STANDARD.heap.start, STANDARD.heap.end),
+            ..STANDARD
+        };
+        let image = program();
+        let result = Process::load(&image.0, MappingPolicy::STRICT, layout, 2);
+        assert!(matches!(
+            result,
+            Err(LoadError::Layout(LayoutError::Overlap))
+        ));
+        assert_eq!(stats(), before);
+    }
+
+    #[test_case]
+    fn unloads_loaded_processes() {
+        let mut image = program();
+        // Drop the dynamic section so that linking succeeds.
+        image.0[56] = 1;
+        let before = stats();
+        let process = Process::load(&image.0, MappingPolicy::STRICT, STANDARD, 2).unwrap();
+        assert_ne!(stats(), before);
+        // The segment and the stack.
+        assert!(process.unload() >= 5);
+        assert_eq!(stats(), before);
+    }
+
+    #[test_case]
+    fn loads_unaligned_programs() {
+        let mut image = program();
+        image.0[56] = 1;
+        let mut unaligned = Image([0; SIZE]);
+        unaligned.0[1..].copy_from_slice(&image.0[..SIZE - 1]);
+        // The image is zero at its end, so nothing is cut off.
+        assert!(image.0[SIZE - 1] == 0);
+        let process = Process::load(&unaligned.0[1..], MappingPolicy::STRICT, STANDARD, 2).unwrap();
+        assert_eq!(process.entry, BASE);
+        process.unload();
+
+        // Headers past the end of the file are refused instead of read.
+        unaligned.0[1 + 56] = 9;
+        let result = Process::load(&unaligned.0[1..], MappingPolicy::STRICT, STANDARD, 2);
+        assert!(matches!(result, Err(LoadError::BadHeaders)));
+    }
+
+    #[cfg(feature = "fault-injection")]
+    #[test_case]
+    fn allocation_failures_are_unloaded() {
+        use crate::fault::{fail_nth, Site};
+
+        let mut image = program();
+        image.0[56] = 1;
+        let before = stats();
+        for n in 1.. {
+            fail_nth(Site::Alloc, n);
+            match Process::load(&image.0, MappingPolicy::STRICT, STANDARD, 2) {
+                Ok(process) => {
+                    process.unload();
+                    break;
+                }
+                Err(e) => assert!(matches!(
+                    e,
+                    LoadError::Map(MapperError::FrameAllocationError)
+                )),
+            }
+            assert_eq!(stats(), before);
+        }
+        fail_nth(Site::Alloc, 0);
+        assert_eq!(stats(), before);
     }
 }
@@ -0,0 +1,872 @@
+//! How threads enter and leave the kernel on x86-64.
+//!
+//! A thread's registers are kept in three places, depending on how it last
+//! entered the kernel:
+//!
+//! - An [`IrqFrame`], pushed on the thread's kernel stack by the `interrupt!`
+//!   stubs on top of the [`IretFrame`] the CPU pushes.
+//! - A [`SyscallFrame`], pushed by the syscall stub. Only the preserved
+//!   registers are saved, the scratch ones are clobbered as the sysv64 ABI
+//!   allows.
+//! - An [`ExecCtx`], where a thread's registers are kept while it isn't
+//!   running and which [`ExecCtx::dispatch`] loads them from.
+//!
+//! A [`SaveState`] copies the frame of the current entry into the thread's
+//! [`ExecCtx`] when it's switched away from. The frames are arrays of 64-bit
+//! words that the asm addresses by offset, laid out as follows:
+//!
+//! ```text
+//! word  IrqFrame  SyscallFrame  ExecCtx
+//!    0  rax       (align)       rax
+//!    1  rcx       rbx           rcx
+//!    2  rdx       rbp           rdx
+//!    3  rsi       r12           rsi
+//!    4  rdi       r13           rdi
+//!    5  r8        r14           r8
+//!    6  r9        r15           r9
+//!    7  r10       rip           r10
+//!    8  r11       cs            r11
+//!    9  rbx       rflags        rbx
+//!   10  rbp       rsp           rbp
+//!   11  r12       ss            r12
+//!   12  r13                     r13
+//!   13  r14                     r14
+//!   14  r15                     r15
+//!   15  rip                     rflags
+//!   16  cs                      rsp
+//!   17  rflags                  rip
+//!   18  rsp                     cr3
+//!   19  ss
+//! ```
+//!
+//! Every offset is checked at compile time. Segment registers aren't saved:
+//! threads always run with the user selectors, which `dispatch` loads.
+
+use core::arch::asm;
+use core::mem::size_of;
+
+use super::gdt;
+use super::paging::RawFrame;
+
+/// Selector of the user code segment, with RPL 3.
+pub const USER_CODE_SELECTOR: u16 = (3 * 8) | 3;
+/// Selector of the user data segment, with RPL 3.
+pub const USER_DATA_SELECTOR: u16 = (4 * 8) | 3;
+
+pub trait SaveState: Sized {
+    fn save_state(self, regs: &mut Regs);
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct NoopSaver {}
+impl NoopSaver {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl SaveState for NoopSaver {
+    fn save_state(self, _regs: &mut Regs) {
+        // Purpusely empty
+    }
+}
+
+/// Execution context that can be dispatched.
+#[repr(C)]
+pub struct ExecCtx {
+    regs: Regs,
+    l4_frame: RawFrame, // Off: 18
+}
+
+// SAFETY: Don't change the order of any of these
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreservedRegs {
+    pub rbx: u64,
+    pub rbp: u64, // Off: 10
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+}
+
+// SAFETY: Don't change the order of any of these
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScratchRegs {
+    pub rax: u64, // Off: 0
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub r8: u64, // Off: 5
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+}
+
+// SAFETY: Don't change the order of any of these
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlRegs {
+    pub rflags: u64, // Off: 15
+    pub rsp: u64,
+    pub rip: u64,
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Regs {
+    pub scratch: ScratchRegs,
+    pub preserved: PreservedRegs,
+    pub control: ControlRegs,
+}
+
+impl ExecCtx {
+    pub fn new(l4_frame: RawFrame, regs: Regs) -> Self {
+        Self { l4_frame, regs }
+    }
+
+    pub fn regs(&self) -> &Regs {
+        &self.regs
+    }
+
+    pub fn regs_mut(&mut self) -> &mut Regs {
+        &mut self.regs
+    }
+
+    pub fn l4_frame(&self) -> RawFrame {
+        self.l4_frame
+    }
+
+    pub fn set_l4_frame(&mut self, l4_frame: RawFrame) {
+        self.l4_frame = l4_frame;
+    }
+
+    #[naked]
+    pub extern "sysv64" fn dispatch(&self) -> ! {
+        // SAFETY: All ExecCtx must be safe to dispatch. Every l4_frame
+        // must have the top half kernel mapped.
+        unsafe {
+            asm!(
+                "pop rax",
+                "mov rbx, cr3",            // Current CR3
+                "mov rax, [rdi + 8 * 18]", // New cr3
+                "cmp rax, rbx",
+                "mov cr3, rax",
+                // Setup the segment selectors, see `USER_DATA_SELECTOR`
+                "mov ax, (4 * 8) | 3",
+                "mov ds, ax",
+                "mov es, ax",
+                "mov fs, ax",
+                "mov gs, ax",
+                // Restore SCRATCH
+                "mov rax, [rdi + 8*0]",
+                "mov rcx, [rdi + 8*1]",
+                "mov rdx, [rdi + 8*2]",
+                "mov rsi, [rdi + 8*3]",
+                // RDI: Later as it holds arg0
+                "mov r8, [rdi + 8*5]",
+                "mov r9, [rdi + 8*6]",
+                "mov r10, [rdi + 8*7]",
+                "mov r11, [rdi + 8*8]",
+                // Restore PRESEVED
+                "mov rbx, [rdi + 8*9]",
+                "mov rbp, [rdi + 8*10]",
+                "mov r12, [rdi + 8*11]",
+                "mov r13, [rdi + 8*12]",
+                "mov r14, [rdi + 8*13]",
+                "mov r15, [rdi + 8*14]",
+                "push (4 * 8) | 3",     // SS
+                "push [rdi + 8*16]",    // Push rsp
+                "push [rdi + 8*15]",    // push rflags
+                "push (3 * 8) | 3",     // CS, see `USER_CODE_SELECTOR`
+                "push [rdi + 8*17]",    // Push the new instruction pointer
+                "mov rdi, [rdi + 8*4]", // And the RDI register
+                "iretq",
+                options(noreturn)
+            )
+        }
+    }
+}
+
+// `dispatch` loads the registers by offset, so the layout of the context must
+// match the offsets in the asm exactly.
+const _: () = {
+    use core::mem::{offset_of, size_of};
+
+    const fn slot(offset: usize) -> usize {
+        assert!(offset % 8 == 0);
+        offset / 8
+    }
+    let regs = offset_of!(ExecCtx, regs);
+    let scratch = regs + offset_of!(Regs, scratch);
+    let preserved = regs + offset_of!(Regs, preserved);
+    let control = regs + offset_of!(Regs, control);
+
+    assert!(slot(scratch + offset_of!(ScratchRegs, rax)) == 0);
+    assert!(slot(scratch + offset_of!(ScratchRegs, rcx)) == 1);
+    assert!(slot(scratch + offset_of!(ScratchRegs, rdx)) == 2);
+    assert!(slot(scratch + offset_of!(ScratchRegs, rsi)) == 3);
+    assert!(slot(scratch + offset_of!(ScratchRegs, rdi)) == 4);
+    assert!(slot(scratch + offset_of!(ScratchRegs, r8)) == 5);
+    assert!(slot(scratch + offset_of!(ScratchRegs, r9)) == 6);
+    assert!(slot(scratch + offset_of!(ScratchRegs, r10)) == 7);
+    assert!(slot(scratch + offset_of!(ScratchRegs, r11)) == 8);
+
+    assert!(slot(preserved + offset_of!(PreservedRegs, rbx)) == 9);
+    assert!(slot(preserved + offset_of!(PreservedRegs, rbp)) == 10);
+    assert!(slot(preserved + offset_of!(PreservedRegs, r12)) == 11);
+    assert!(slot(preserved + offset_of!(PreservedRegs, r13)) == 12);
+    assert!(slot(preserved + offset_of!(PreservedRegs, r14)) == 13);
+    assert!(slot(preserved + offset_of!(PreservedRegs, r15)) == 14);
+
+    assert!(slot(control + offset_of!(ControlRegs, rflags)) == 15);
+    assert!(slot(control + offset_of!(ControlRegs, rsp)) == 16);
+    assert!(slot(control + offset_of!(ControlRegs, rip)) == 17);
+
+    // CR3 is loaded straight from the frame, which must be its address.
+    assert!(slot(offset_of!(ExecCtx, l4_frame)) == 18);
+    assert!(size_of::<RawFrame>() == 8);
+};
+
+/// What the CPU pushes when it enters the kernel through an interrupt gate.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct IretFrame {
+    rip: u64,
+    cs: u64,
+    rflags: u64,
+    rsp: u64,
+    ss: u64,
+}
+
+impl IretFrame {
+    fn control_regs(&self) -> ControlRegs {
+        ControlRegs {
+            rflags: self.rflags,
+            rsp: self.rsp,
+            rip: self.rip,
+        }
+    }
+}
+
+/// Top of the kernel stack while `syscall_interrupt` handles a syscall.
+///
+/// This is the only description of what the entry stub saves. The scratch
+/// registers are clobbered as allowed by the sysv64 ABI and the SIMD
+/// registers are left alone, since the kernel is built without SIMD. They're
+/// switched lazily when threads change, see [`crate::arch::simd`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SyscallFrame {
+    /// Keeps the stack aligned to 16 bytes for the call into the handler.
+    _align: u64,
+    preserved: PreservedRegs,
+    iret: IretFrame,
+}
+
+/// What an `interrupt!` stub pushes. It's at the top of the kernel stack if
+/// the interrupt came from userspace.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct IrqFrame {
+    scratch: ScratchRegs,
+    preserved: PreservedRegs,
+    iret: IretFrame,
+}
+
+impl IrqFrame {
+    /// The interrupted instruction.
+    pub fn rip(&self) -> u64 {
+        self.iret.rip
+    }
+
+    /// The frame pointer of the interrupted code.
+    pub fn rbp(&self) -> u64 {
+        self.preserved.rbp
+    }
+
+    /// Whether the interrupt was raised while running in userspace.
+    pub fn from_user(&self) -> bool {
+        self.iret.cs & 0b11 == 3
+    }
+}
+
+/// What the page fault stub pushes, on the page fault stack. The processor
+/// pushes an error code between the registers and the `iretq` frame.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FaultFrame {
+    /// Keeps the stack aligned to 16 bytes for the call into the handler.
+    _align: u64,
+    scratch: ScratchRegs,
+    preserved: PreservedRegs,
+    code: u64,
+    iret: IretFrame,
+}
+
+impl FaultFrame {
+    /// The error code pushed by the processor.
+    pub fn code(&self) -> u64 {
+        self.code
+    }
+
+    /// The faulting instruction.
+    pub fn rip(&self) -> u64 {
+        self.iret.rip
+    }
+}
+
+// The entry stubs push these frames one register at a time, so their layouts
+// must match the pushes exactly, and the stubs call into Rust with the stack
+// aligned as the ABI requires.
+const _: () = {
+    use core::mem::{offset_of, size_of};
+
+    assert!(size_of::<IretFrame>() == 5 * 8);
+    assert!(size_of::<PreservedRegs>() == 6 * 8);
+    assert!(size_of::<ScratchRegs>() == 9 * 8);
+
+    assert!(offset_of!(SyscallFrame, preserved) == 8);
+    assert!(offset_of!(SyscallFrame, iret) == 7 * 8);
+    assert!(size_of::<SyscallFrame>() == 12 * 8);
+    assert!(size_of::<SyscallFrame>() % 16 == 0);
+
+    assert!(offset_of!(IrqFrame, preserved) == 9 * 8);
+    assert!(offset_of!(IrqFrame, iret) == 15 * 8);
+    assert!(size_of::<IrqFrame>() == 20 * 8);
+    assert!(size_of::<IrqFrame>() % 16 == 0);
+
+    assert!(offset_of!(FaultFrame, scratch) == 8);
+    assert!(offset_of!(FaultFrame, code) == 16 * 8);
+    assert!(offset_of!(FaultFrame, iret) == 17 * 8);
+    assert!(size_of::<FaultFrame>() == 22 * 8);
+    assert!(size_of::<FaultFrame>() % 16 == 0);
+};
+
+/// Reads the frame an entry stub left at the top of the kernel stack.
+///
+/// # Safety
+///
+/// Must be handling an entry from userspace that pushed a `T`.
+unsafe fn entry_frame<T: Copy>() -> T {
+    let stack_end: *const u8 = gdt::kernel_stack_end().as_ptr();
+    // SAFETY: Precondition.
+    unsafe { stack_end.sub(size_of::<T>()).cast::<T>().read() }
+}
+
+pub struct SyscallCtx {
+    pub control_regs: ControlRegs,
+    pub preserved_regs: PreservedRegs,
+}
+
+impl SaveState for SyscallCtx {
+    fn save_state(self, regs: &mut Regs) {
+        regs.control = self.control_regs;
+        regs.preserved = self.preserved_regs;
+        // The syscall returns successfully once the thread is dispatched again.
+        regs.scratch.rax = 0;
+    }
+}
+
+impl SyscallCtx {
+    /// Reads the syscall context from the stack
+    ///
+    /// # Safety
+    ///
+    /// Must be currently handling a syscall
+    pub unsafe fn current() -> Self {
+        // SAFETY: Precondition.
+        Self::from_frame(unsafe { entry_frame() })
+    }
+
+    fn from_frame(frame: SyscallFrame) -> Self {
+        Self {
+            control_regs: frame.iret.control_regs(),
+            preserved_regs: frame.preserved,
+        }
+    }
+}
+
+/// Length of the `int 0x80` instruction that enters a syscall.
+const SYSCALL_INSTRUCTION_LEN: u64 = 2;
+
+/// State that makes a thread re-execute the syscall it's currently in.
+///
+/// The thread is rewound to its syscall instruction with the original
+/// arguments in place, so that the next time it's dispatched it enters the
+/// kernel again with the same request.
+pub struct RestartCtx {
+    syscall: SyscallCtx,
+    args: [usize; 6],
+}
+
+impl RestartCtx {
+    pub fn new(syscall: SyscallCtx, args: [usize; 6]) -> Self {
+        Self { syscall, args }
+    }
+}
+
+impl SaveState for RestartCtx {
+    fn save_state(self, regs: &mut Regs) {
+        let [a, b, c, d, e, f] = self.args.map(|arg| arg as u64);
+        regs.control = self.syscall.control_regs;
+        regs.control.rip -= SYSCALL_INSTRUCTION_LEN;
+        regs.preserved = self.syscall.preserved_regs;
+        regs.scratch.rdi = a;
+        regs.scratch.rsi = b;
+        regs.scratch.rdx = c;
+        regs.scratch.rcx = d;
+        regs.scratch.r8 = e;
+        regs.scratch.r9 = f;
+    }
+}
+
+pub struct IrqCtx {
+    pub control_regs: ControlRegs,
+    pub preserved_regs: PreservedRegs,
+    pub scratch_regs: ScratchRegs,
+}
+
+impl SaveState for IrqCtx {
+    fn save_state(self, regs: &mut Regs) {
+        regs.control = self.control_regs;
+        regs.preserved = self.preserved_regs;
+        regs.scratch = self.scratch_regs;
+    }
+}
+
+impl IrqCtx {
+    /// Reads the interrupt context from the stack
+    ///
+    /// # Safety
+    ///
+    /// Must be currently handling an interrupt from userspace
+    pub unsafe fn current() -> Self {
+        // SAFETY: Precondition.
+        Self::from_frame(unsafe { entry_frame() })
+    }
+
+    fn from_frame(frame: IrqFrame) -> Self {
+        Self {
+            control_regs: frame.iret.control_regs(),
+            preserved_regs: frame.preserved,
+            scratch_regs: frame.scratch,
+        }
+    }
+}
+
+impl From<&FaultFrame> for IrqCtx {
+    fn from(frame: &FaultFrame) -> Self {
+        Self {
+            control_regs: frame.iret.control_regs(),
+            preserved_regs: frame.preserved,
+            scratch_regs: frame.scratch,
+        }
+    }
+}
+
+impl IrqCtx {
+    /// Whether the interrupt was raised while running in userspace.
+    ///
+    /// # Safety
+    ///
+    /// Must be currently handling an interrupt
+    pub unsafe fn from_user() -> bool {
+        // SAFETY: Every entry ends with the frame the CPU pushed.
+        let frame: IretFrame = unsafe { entry_frame() };
+        frame.cs & 0b11 == 3
+    }
+}
+
+macro_rules! push_scratch {
+    () => {
+        r#"
+        push r11
+        push r10
+        push r9
+        push r8
+        push rdi
+        push rsi
+        push rdx
+        push rcx
+        push rax
+        "#
+    };
+}
+
+macro_rules! pop_scratch {
+    () => {
+        r#"
+        pop rax
+        pop rcx
+        pop rdx
+        pop rsi
+        pop rdi
+        pop r8
+        pop r9
+        pop r10
+        pop r11
+        "#
+    };
+}
+
+macro_rules! push_preserved {
+    () => {
+        r#"
+        push r15
+        push r14
+        push r13
+        push r12
+        push rbp
+        push rbx
+        "#
+    };
+}
+
+macro_rules! pop_preserved {
+    () => {
+        r#"
+        pop rbx
+        pop rbp
+        pop r12
+        pop r13
+        pop r14
+        pop r15
+        "#
+    };
+}
+
+pub(crate) use {pop_preserved, pop_scratch, push_preserved, push_scratch};
+
+#[cfg(test)]
+mod tests {
+    use kapi::raw::{RawOperation, OPERATION_COUNT};
+    use x86_64_impl::registers::segmentation::{Segment, CS, DS, ES, FS, GS, SS};
+
+    use super::*;
+    use crate::arch::paging::PhysAddr;
+
+    /// General purpose registers in the order of [`Regs`].
+    const GPRS: [&str; 15] = [
+        "rax", "rcx", "rdx", "rsi", "rdi", "r8", "r9", "r10", "r11", "rbx", "rbp", "r12", "r13",
+        "r14", "r15",
+    ];
+
+    /// A distinct value for every register, with bits set in every byte.
+    const PATTERNS: [u64; 15] = {
+        let mut patterns = [0; 15];
+        let mut i = 0;
+        while i < patterns.len() {
+            patterns[i] = 0x1111_1111_1111_1111 * (i as u64 + 1);
+            i += 1;
+        }
+        patterns
+    };
+
+    const STACK_WORDS: usize = 32;
+
+    /// Runs the pushes and pops of the entry stubs on a simulated stack.
+    struct Machine {
+        gprs: [u64; 15],
+        stack: [u64; STACK_WORDS],
+        /// Index of the word at the top of the stack.
+        sp: usize,
+    }
+
+    impl Machine {
+        fn new() -> Self {
+            Self {
+                gprs: PATTERNS,
+                stack: [0; STACK_WORDS],
+                sp: STACK_WORDS,
+            }
+        }
+
+        fn push(&mut self, word: u64) {
+            self.sp -= 1;
+            self.stack[self.sp] = word;
+        }
+
+        fn pop(&mut self) -> u64 {
+            self.sp += 1;
+            self.stack[self.sp - 1]
+        }
+
+        fn run(&mut self, code: &str) {
+            for instruction in code.lines().map(str::trim).filter(|line| !line.is_empty()) {
+                let (op, register) = instruction.split_once(' ').unwrap();
+                let register = GPRS.iter().position(|gpr| *gpr == register).unwrap();
+                match op {
+                    "push" => self.push(self.gprs[register]),
+                    "pop" => self.gprs[register] = self.pop(),
+                    _ => panic!("Can't simulate {instruction}"),
+                }
+            }
+        }
+
+        /// Reads a `T` from the top of the stack.
+        fn top<T: Copy>(&self) -> T {
+            let words = &self.stack[self.sp..];
+            assert!(size_of::<T>() <= size_of_val(words));
+            // SAFETY: The words are in bounds and every frame is made of words.
+            unsafe { words.as_ptr().cast::<T>().read() }
+        }
+
+        /// What the CPU pushes when entering the kernel.
+        fn interrupt(&mut self, iret: IretFrame) {
+            for word in [iret.ss, iret.rsp, iret.rflags, iret.cs, iret.rip] {
+                self.push(word);
+            }
+        }
+    }
+
+    /// [`PATTERNS`] as the registers of a thread interrupted at `iret`.
+    fn patterned_regs(iret: IretFrame) -> Regs {
+        let mut words = [0; 18];
+        words[..15].copy_from_slice(&PATTERNS);
+        words[15..].copy_from_slice(&[iret.rflags, iret.rsp, iret.rip]);
+        // SAFETY: `Regs` is 18 words, in the order checked by the layout asserts.
+        unsafe { core::mem::transmute::<[u64; 18], Regs>(words) }
+    }
+
+    const PRESERVED: PreservedRegs = PreservedRegs {
+        rbx: 1,
+        rbp: 2,
+        r12: 3,
+        r13: 4,
+        r14: 5,
+        r15: 6,
+    };
+
+    const IRET: IretFrame = IretFrame {
+        rip: 0x1000,
+        cs: (3 * 8) | 3,
+        rflags: 0x202,
+        rsp: 0x8000,
+        ss: (4 * 8) | 3,
+    };
+
+    fn assert_preserved(regs: &Regs) {
+        assert_eq!(
+            [
+                regs.preserved.rbx,
+                regs.preserved.rbp,
+                regs.preserved.r12,
+                regs.preserved.r13,
+                regs.preserved.r14,
+                regs.preserved.r15
+            ],
+            [1, 2, 3, 4, 5, 6]
+        );
+        assert_eq!(regs.control.rflags, IRET.rflags);
+        assert_eq!(regs.control.rsp, IRET.rsp);
+    }
+
+    #[test_case]
+    fn syscall_frames_round_trip() {
+        let frame = SyscallFrame {
+            _align: u64::MAX,
+            preserved: PRESERVED,
+            iret: IRET,
+        };
+        let mut regs = Regs::default();
+        regs.scratch.rax = u64::MAX;
+        SyscallCtx::from_frame(frame).save_state(&mut regs);
+        assert_preserved(&regs);
+        assert_eq!(regs.control.rip, IRET.rip);
+        assert_eq!(regs.scratch.rax, 0);
+    }
+
+    #[test_case]
+    fn restarts_keep_registers_for_every_op() {
+        let ops = (0..OPERATION_COUNT).map(|op| RawOperation::try_from(op).unwrap());
+        for op in ops {
+            let args = [7, usize::from(op), 0xA, 0xB, 0xC, 0xD];
+            let frame = SyscallFrame {
+                _align: 0,
+                preserved: PRESERVED,
+                iret: IRET,
+            };
+            let mut regs = Regs::default();
+            RestartCtx::new(SyscallCtx::from_frame(frame), args).save_state(&mut regs);
+            assert_preserved(&regs);
+            assert_eq!(regs.control.rip, IRET.rip - SYSCALL_INSTRUCTION_LEN);
+            let scratch = regs.scratch;
+            assert_eq!(
+                [
+                    scratch.rdi,
+                    scratch.rsi,
+                    scratch.rdx,
+                    scratch.rcx,
+                    scratch.r8,
+                    scratch.r9
+                ],
+                args.map(|arg| arg as u64)
+            );
+        }
+    }
+
+    #[test_case]
+    fn irq_entries_round_trip_every_register() {
+        let mut machine = Machine::new();
+        machine.interrupt(IRET);
+        machine.run(push_preserved!());
+        machine.run(push_scratch!());
+        assert_eq!(machine.sp, STACK_WORDS - size_of::<IrqFrame>() / 8);
+
+        let frame: IrqFrame = machine.top();
+        assert!(frame.from_user());
+        let mut regs = Regs::default();
+        IrqCtx::from_frame(frame).save_state(&mut regs);
+        assert_eq!(regs, patterned_regs(IRET));
+
+        machine.gprs = [0; 15];
+        machine.run(pop_scratch!());
+        machine.run(pop_preserved!());
+        assert_eq!(machine.gprs, PATTERNS);
+        assert_eq!(machine.sp, STACK_WORDS - size_of::<IretFrame>() / 8);
+    }
+
+    #[test_case]
+    fn syscall_entries_round_trip_preserved_registers() {
+        let mut machine = Machine::new();
+        machine.interrupt(IRET);
+        machine.run(push_preserved!());
+        machine.push(u64::MAX); // SyscallFrame::_align
+        assert_eq!(machine.sp, STACK_WORDS - size_of::<SyscallFrame>() / 8);
+
+        let frame: SyscallFrame = machine.top();
+        let mut regs = Regs::default();
+        SyscallCtx::from_frame(frame).save_state(&mut regs);
+        let expected = patterned_regs(IRET);
+        assert_eq!(regs.preserved, expected.preserved);
+        assert_eq!(regs.control, expected.control);
+
+        machine.pop();
+        machine.gprs = [0; 15];
+        machine.run(pop_preserved!());
+        assert_eq!(machine.gprs[9..], PATTERNS[9..]);
+    }
+
+    #[test_case]
+    fn contexts_keep_registers_where_dispatch_loads_them() {
+        let l4_frame = RawFrame::from_start_address(PhysAddr::new(0x1234_5000));
+        let ctx = ExecCtx::new(l4_frame, patterned_regs(IRET));
+        // SAFETY: `ExecCtx` is 19 words, see the layout asserts.
+        let words = unsafe { &*(&ctx as *const ExecCtx).cast::<[u64; 19]>() };
+        assert_eq!(words[..15], PATTERNS);
+        assert_eq!(words[15..18], [IRET.rflags, IRET.rsp, IRET.rip]);
+        assert_eq!(words[18], 0x1234_5000);
+    }
+
+    /// Loads `input` into the registers, runs the pushes and pops of the
+    /// interrupt stubs with every register clobbered in between, and stores
+    /// the registers into `output`. RSP and RIP are left alone.
+    #[naked]
+    extern "sysv64" fn round_trip(_input: &Regs, _output: &mut Regs) {
+        // SAFETY: Every callee-saved register and the caller's flags are
+        // restored before returning.
+        unsafe {
+            asm!(
+                push_preserved!(),
+                "pushfq",
+                "push rsi",
+                // Load the input.
+                "push [rdi + 8*15]",
+                "popfq",
+                "mov rax, [rdi + 8*0]",
+                "mov rcx, [rdi + 8*1]",
+                "mov rdx, [rdi + 8*2]",
+                "mov rsi, [rdi + 8*3]",
+                "mov r8, [rdi + 8*5]",
+                "mov r9, [rdi + 8*6]",
+                "mov r10, [rdi + 8*7]",
+                "mov r11, [rdi + 8*8]",
+                "mov rbx, [rdi + 8*9]",
+                "mov rbp, [rdi + 8*10]",
+                "mov r12, [rdi + 8*11]",
+                "mov r13, [rdi + 8*12]",
+                "mov r14, [rdi + 8*13]",
+                "mov r15, [rdi + 8*14]",
+                "mov rdi, [rdi + 8*4]",
+                // Enter as an interrupt would.
+                "pushfq",
+                push_preserved!(),
+                push_scratch!(),
+                // Clobber everything.
+                "xor eax, eax",
+                "xor ecx, ecx",
+                "xor edx, edx",
+                "xor esi, esi",
+                "xor edi, edi",
+                "xor r8d, r8d",
+                "xor r9d, r9d",
+                "xor r10d, r10d",
+                "xor r11d, r11d",
+                "xor ebx, ebx",
+                "xor ebp, ebp",
+                "xor r12d, r12d",
+                "xor r13d, r13d",
+                "xor r14d, r14d",
+                "xor r15d, r15d",
+                "push 0x2",
+                "popfq",
+                // Leave as an interrupt would.
+                pop_scratch!(),
+                pop_preserved!(),
+                "popfq",
+                // Store the output.
+                "push rdi",
+                "mov rdi, [rsp + 8]",
+                "pushfq",
+                "pop qword ptr [rdi + 8*15]",
+                "mov [rdi + 8*0], rax",
+                "mov [rdi + 8*1], rcx",
+                "mov [rdi + 8*2], rdx",
+                "mov [rdi + 8*3], rsi",
+                "pop qword ptr [rdi + 8*4]",
+                "mov [rdi + 8*5], r8",
+                "mov [rdi + 8*6], r9",
+                "mov [rdi + 8*7], r10",
+                "mov [rdi + 8*8], r11",
+                "mov [rdi + 8*9], rbx",
+                "mov [rdi + 8*10], rbp",
+                "mov [rdi + 8*11], r12",
+                "mov [rdi + 8*12], r13",
+                "mov [rdi + 8*13], r14",
+                "mov [rdi + 8*14], r15",
+                "add rsp, 8",
+                "popfq",
+                pop_preserved!(),
+                "ret",
+                options(noreturn)
+            )
+        }
+    }
+
+    #[test_case]
+    fn registers_round_trip_on_the_cpu() {
+        let mut input = patterned_regs(IRET);
+        // CF, PF, AF, ZF, SF and OF, with interrupts disabled.
+        input.control.rflags = 0x8D7;
+        input.control.rsp = 0;
+        input.control.rip = 0;
+        let segments = || {
+            [
+                CS::get_reg(),
+                DS::get_reg(),
+                ES::get_reg(),
+                FS::get_reg(),
+                GS::get_reg(),
+                SS::get_reg(),
+            ]
+            .map(|selector| selector.0)
+        };
+        let before = segments();
+        let mut output = Regs::default();
+        round_trip(&input, &mut output);
+        assert_eq!(output, input);
+        assert_eq!(segments(), before);
+    }
+}
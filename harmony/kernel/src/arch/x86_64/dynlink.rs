@@ -0,0 +1,649 @@
+//! Dynamic linking against the shared library.
+//!
+//! Components can link dynamically against a single shared library instead of
+//! carrying their own copy of it. The library is found in the boot module
+//! named [`LIBRARY_MODULE`] and loaded once, at [`LIBRARY_ADDRESS`], into a
+//! template address space where its own relocations are applied. Every
+//! component that needs it then gets:
+//!
+//! * The frames of its read-only segments, shared with every other component.
+//! * Copies of the frames of its writable segments (data and GOT), so each
+//!   component has its own.
+//!
+//! The loader resolves the component's imports against the library's dynamic
+//! symbols and applies the component's relocations before it runs. Only the
+//! relocations `x86_64-unknown-none` code needs are supported, and they may
+//! only patch writable segments.
+//!
+//! FIXME: The build doesn't produce the library yet, so every component still
+//! links everything statically and never has a `PT_DYNAMIC` segment.
+
+use goblin::elf::dynamic::{
+    DT_HASH, DT_JMPREL, DT_NEEDED, DT_NULL, DT_PLTRELSZ, DT_RELA, DT_RELAENT, DT_RELASZ, DT_STRSZ,
+    DT_STRTAB, DT_SYMENT, DT_SYMTAB,
+};
+use goblin::elf::header::ET_DYN;
+use goblin::elf::program_header::{PF_W, PT_DYNAMIC, PT_LOAD};
+use goblin::elf::reloc::{
+    R_X86_64_64, R_X86_64_GLOB_DAT, R_X86_64_JUMP_SLOT, R_X86_64_NONE, R_X86_64_RELATIVE,
+};
+use goblin::elf::section_header::SHN_UNDEF;
+use goblin::elf::sym::{STB_LOCAL, STB_WEAK};
+use goblin::elf64::program_header::ProgramHeader;
+use goblin::elf64::reloc::SIZEOF_RELA;
+use goblin::elf64::sym::SIZEOF_SYM;
+use kapi::component::{LIBRARY_ADDRESS, LIBRARY_MODULE, LIBRARY_SIZE};
+use sync::cell::AtomicOnceCell;
+
+use super::bootup::{elf_header, program_headers, LoadError, ProgramHeaders, Segment};
+use super::paging::page_table::{Addrspace, AnyPageTable, PageTableFlags};
+use super::paging::policy::MappingPolicy;
+use super::paging::{Page, PhysAddrExt as _, VirtAddr, PAGE_SIZE};
+use crate::boot::{self, BootProtocol as _};
+use crate::bump_allocator::BumpAllocator;
+use crate::kptr::KPtr;
+
+static LIBRARY: AtomicOnceCell<Library> = AtomicOnceCell::new();
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LinkError {
+    /// The dynamic section or a table it points to is malformed.
+    BadDynamic,
+    /// The library isn't a shared object or doesn't fit at its address.
+    BadLibrary,
+    /// The component needs a library other than [`LIBRARY_MODULE`], or the
+    /// library wasn't loaded.
+    MissingLibrary,
+    UnsupportedRelocation(u32),
+    /// A relocation patches a read-only segment.
+    TextRelocation,
+    /// A symbol isn't defined anywhere.
+    Undefined,
+    OutOfMemory,
+}
+
+/// A symbol in the dynamic symbol table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Symbol<'prog> {
+    name: &'prog [u8],
+    value: u64,
+    defined: bool,
+    bind: u8,
+}
+
+/// A relocation from `DT_RELA` or `DT_JMPREL`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Relocation {
+    offset: u64,
+    kind: u32,
+    symbol: u32,
+    addend: i64,
+}
+
+/// The tables the dynamic section of an ELF file points to.
+#[derive(Debug, Copy, Clone)]
+pub struct Dynamic<'prog> {
+    phdrs: ProgramHeaders,
+    entries: &'prog [u8],
+    rela: &'prog [u8],
+    jmprel: &'prog [u8],
+    symtab: &'prog [u8],
+    strtab: &'prog [u8],
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    let bytes = bytes.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+impl<'prog> Dynamic<'prog> {
+    /// Finds the dynamic section of `program`, if it has one.
+    pub fn parse(program: &'prog [u8], phdrs: &[ProgramHeader]) -> Result<Option<Self>, LinkError> {
+        let Some(ph) = phdrs.iter().find(|ph| ph.p_type == PT_DYNAMIC) else {
+            return Ok(None);
+        };
+        let start = usize::try_from(ph.p_offset).map_err(|_| LinkError::BadDynamic)?;
+        let len = usize::try_from(ph.p_filesz).map_err(|_| LinkError::BadDynamic)?;
+        let entries = start
+            .checked_add(len)
+            .and_then(|end| program.get(start..end))
+            .ok_or(LinkError::BadDynamic)?;
+
+        let empty = Self {
+            phdrs: ProgramHeaders::from_slice(phdrs).ok_or(LinkError::BadDynamic)?,
+            entries,
+            rela: &[],
+            jmprel: &[],
+            symtab: &[],
+            strtab: &[],
+        };
+        let value = |tag| empty.entries().find(|&(t, _)| t == tag).map(|(_, v)| v);
+        if value(DT_RELAENT).is_some_and(|size| size != SIZEOF_RELA as u64)
+            || value(DT_SYMENT).is_some_and(|size| size != SIZEOF_SYM as u64)
+        {
+            return Err(LinkError::BadDynamic);
+        }
+        let table = |address: Option<u64>, len: Option<u64>| match (address, len) {
+            (Some(address), Some(len)) => file_bytes(program, phdrs, address, len),
+            (None, None) => Some(&[][..]),
+            _ => None,
+        };
+        let rela = table(value(DT_RELA), value(DT_RELASZ));
+        let jmprel = table(value(DT_JMPREL), value(DT_PLTRELSZ));
+        let strtab = table(value(DT_STRTAB), value(DT_STRSZ));
+        // The symbol table doesn't record its size, but the hash table has a
+        // chain entry per symbol.
+        let symbols = value(DT_HASH)
+            .and_then(|hash| file_bytes(program, phdrs, hash, 8))
+            .and_then(|hash| read_u32(hash, 4));
+        let symtab = match (value(DT_SYMTAB), symbols) {
+            (None, _) => Some(&[][..]),
+            (Some(address), Some(count)) => file_bytes(
+                program,
+                phdrs,
+                address,
+                u64::from(count) * SIZEOF_SYM as u64,
+            ),
+            (Some(_), None) => None,
+        };
+        let (Some(rela), Some(jmprel), Some(symtab), Some(strtab)) = (rela, jmprel, symtab, strtab)
+        else {
+            return Err(LinkError::BadDynamic);
+        };
+        Ok(Some(Self {
+            rela,
+            jmprel,
+            symtab,
+            strtab,
+            ..empty
+        }))
+    }
+
+    /// The `(tag, value)` pairs up to `DT_NULL`.
+    fn entries(&self) -> impl Iterator<Item = (u64, u64)> + 'prog {
+        let entries = self.entries;
+        (0..entries.len() / 16)
+            .map(move |i| (read_u64(entries, i * 16), read_u64(entries, i * 16 + 8)))
+            .map_while(|(tag, value)| Some((tag?, value?)))
+            .take_while(|&(tag, _)| tag != DT_NULL)
+    }
+
+    fn string(&self, offset: u64) -> Option<&'prog [u8]> {
+        let rest = self.strtab.get(usize::try_from(offset).ok()?..)?;
+        let end = rest.iter().position(|&b| b == 0)?;
+        Some(&rest[..end])
+    }
+
+    /// Names of the shared objects the program needs.
+    pub fn needed(&self) -> impl Iterator<Item = Result<&'prog [u8], LinkError>> + '_ {
+        self.entries()
+            .filter(|&(tag, _)| tag == DT_NEEDED)
+            .map(|(_, name)| self.string(name).ok_or(LinkError::BadDynamic))
+    }
+
+    fn symbol(&self, index: u32) -> Option<Symbol<'prog>> {
+        let start = usize::try_from(index).ok()?.checked_mul(SIZEOF_SYM)?;
+        let sym = self.symtab.get(start..start.checked_add(SIZEOF_SYM)?)?;
+        Some(Symbol {
+            name: self.string(read_u32(sym, 0)?.into())?,
+            value: read_u64(sym, 8)?,
+            defined: u32::from(u16::from_le_bytes([sym[6], sym[7]])) != SHN_UNDEF,
+            bind: sym[4] >> 4,
+        })
+    }
+
+    /// Returns the unrelocated value of the exported symbol `name`.
+    pub fn lookup(&self, name: &[u8]) -> Option<u64> {
+        (0..(self.symtab.len() / SIZEOF_SYM) as u32)
+            .filter_map(|index| self.symbol(index))
+            .find(|sym| sym.defined && sym.bind != STB_LOCAL && sym.name == name)
+            .map(|sym| sym.value)
+    }
+
+    fn relocations(&self) -> impl Iterator<Item = Option<Relocation>> + 'prog {
+        let (rela, jmprel) = (self.rela, self.jmprel);
+        rela.chunks(SIZEOF_RELA)
+            .chain(jmprel.chunks(SIZEOF_RELA))
+            .map(|entry| {
+                let info = read_u64(entry, 8)?;
+                Some(Relocation {
+                    offset: read_u64(entry, 0)?,
+                    kind: info as u32,
+                    symbol: (info >> 32) as u32,
+                    addend: read_u64(entry, 16)? as i64,
+                })
+            })
+    }
+
+    /// Whether `address..address + len` is in a writable segment.
+    fn is_writable(&self, address: u64, len: u64) -> bool {
+        self.phdrs
+            .iter()
+            .filter(|ph| ph.p_type == PT_LOAD && ph.p_flags & PF_W != 0)
+            .any(|ph| {
+                address >= ph.p_vaddr
+                    && address
+                        .checked_add(len)
+                        .is_some_and(|end| end <= ph.p_vaddr + ph.p_memsz)
+            })
+    }
+
+    /// Applies every relocation to the program loaded at `base`.
+    ///
+    /// Symbols the program doesn't define are looked up with `resolve`, which
+    /// returns their final address. `write` stores a word at an address.
+    /// Returns the number of relocations applied.
+    pub fn relocate(
+        &self,
+        base: u64,
+        mut resolve: impl FnMut(&[u8]) -> Option<u64>,
+        mut write: impl FnMut(u64, u64) -> Result<(), LinkError>,
+    ) -> Result<usize, LinkError> {
+        let mut applied = 0;
+        for relocation in self.relocations() {
+            let relocation = relocation.ok_or(LinkError::BadDynamic)?;
+            if relocation.kind == R_X86_64_NONE {
+                continue;
+            }
+            let mut symbol = || -> Result<u64, LinkError> {
+                let sym = self
+                    .symbol(relocation.symbol)
+                    .ok_or(LinkError::BadDynamic)?;
+                if sym.defined {
+                    return Ok(base.wrapping_add(sym.value));
+                }
+                match resolve(sym.name) {
+                    Some(address) => Ok(address),
+                    None if sym.bind == STB_WEAK => Ok(0),
+                    None => {
+                        log::error!(
+                            "Undefined symbol {}",
+                            core::str::from_utf8(sym.name).unwrap_or("<invalid>")
+                        );
+                        Err(LinkError::Undefined)
+                    }
+                }
+            };
+            let value = match relocation.kind {
+                R_X86_64_RELATIVE => base.wrapping_add_signed(relocation.addend),
+                R_X86_64_64 => symbol()?.wrapping_add_signed(relocation.addend),
+                R_X86_64_GLOB_DAT | R_X86_64_JUMP_SLOT => symbol()?,
+                kind => return Err(LinkError::UnsupportedRelocation(kind)),
+            };
+            if !self.is_writable(relocation.offset, 8) {
+                return Err(LinkError::TextRelocation);
+            }
+            write(base.wrapping_add(relocation.offset), value)?;
+            applied += 1;
+        }
+        Ok(applied)
+    }
+}
+
+/// Returns the `len` bytes of the file that are loaded at `address`.
+fn file_bytes<'prog>(
+    program: &'prog [u8],
+    phdrs: &[ProgramHeader],
+    address: u64,
+    len: u64,
+) -> Option<&'prog [u8]> {
+    let ph = phdrs.iter().filter(|ph| ph.p_type == PT_LOAD).find(|ph| {
+        address >= ph.p_vaddr
+            && address
+                .checked_add(len)
+                .is_some_and(|end| end <= ph.p_vaddr + ph.p_filesz)
+    })?;
+    let start = usize::try_from(ph.p_offset + (address - ph.p_vaddr)).ok()?;
+    program.get(start..start.checked_add(usize::try_from(len).ok()?)?)
+}
+
+/// Writes `value` at `address` in `addrspace`.
+fn write_word(addrspace: &Addrspace, address: u64, value: u64) -> Result<(), LinkError> {
+    // The word may straddle two pages.
+    for (i, byte) in value.to_le_bytes().into_iter().enumerate() {
+        let address = VirtAddr::new(address as usize + i);
+        let (frame, _) = addrspace
+            .get(Page::containing_address(address))
+            .ok_or(LinkError::BadDynamic)?;
+        let page: *mut u8 = frame.base().to_virtual().as_mut_ptr();
+        // SAFETY: The frame is mapped in the address space being loaded, which
+        // isn't running yet.
+        unsafe { page.add(address.as_usize() % PAGE_SIZE).write(byte) };
+    }
+    Ok(())
+}
+
+/// The shared library, loaded and relocated once.
+pub struct Library {
+    dynamic: Dynamic<'static>,
+    /// The address space holding the loaded library, which components map
+    /// their copy from.
+    template: KPtr<AnyPageTable>,
+}
+
+impl Library {
+    pub fn load(program: &'static [u8]) -> Result<Self, LinkError> {
+        let header = elf_header(program).ok_or(LinkError::BadLibrary)?;
+        if header.e_type != ET_DYN {
+            return Err(LinkError::BadLibrary);
+        }
+        let phdrs = program_headers(program).ok_or(LinkError::BadLibrary)?;
+        let fits = phdrs
+            .iter()
+            .filter(|ph| ph.p_type == PT_LOAD)
+            .all(|ph| ph.p_vaddr.saturating_add(ph.p_memsz) <= LIBRARY_SIZE as u64);
+        if !fits {
+            return Err(LinkError::BadLibrary);
+        }
+        let dynamic = Dynamic::parse(program, &phdrs)?.ok_or(LinkError::BadLibrary)?;
+        if dynamic.needed().next().is_some() {
+            return Err(LinkError::MissingLibrary);
+        }
+
+        let mut fallocator = BumpAllocator::new();
+        let template = fallocator
+            .alloc_untyped_frame()
+            .and_then(|frame| AnyPageTable::new_l4(frame).ok())
+            .ok_or(LinkError::OutOfMemory)?;
+        // SAFETY: The template is never loaded.
+        let addrspace = unsafe { template.as_addrspace() };
+        for ph in phdrs.iter().filter(|ph| ph.p_type == PT_LOAD) {
+            // The library is mapped into every component, so it's held to the
+            // strictest policy.
+            Segment::new(program, ph)
+                .load(
+                    LIBRARY_ADDRESS as u64,
+                    MappingPolicy::STRICT,
+                    &addrspace,
+                    &mut fallocator,
+                )
+                .map_err(|e| match e {
+                    LoadError::Policy(_) => LinkError::BadLibrary,
+                    _ => LinkError::OutOfMemory,
+                })?;
+        }
+        let relocations = dynamic.relocate(
+            LIBRARY_ADDRESS as u64,
+            |_| None,
+            |address, value| write_word(&addrspace, address, value),
+        )?;
+        log::debug!("Applied {relocations} relocations to the shared library");
+        Ok(Self { dynamic, template })
+    }
+
+    /// Returns the address of the exported symbol `name`.
+    pub fn lookup(&self, name: &[u8]) -> Option<u64> {
+        Some(self.dynamic.lookup(name)? + LIBRARY_ADDRESS as u64)
+    }
+
+    /// Maps the library into `addrspace`, sharing the read-only pages and
+    /// copying the writable ones.
+    pub fn map_into(
+        &self,
+        addrspace: &Addrspace,
+        fallocator: &mut BumpAllocator,
+    ) -> Result<(), LinkError> {
+        // SAFETY: The template is never loaded.
+        let template = unsafe { self.template.as_addrspace() };
+        for ph in self.dynamic.phdrs.iter().filter(|ph| ph.p_type == PT_LOAD) {
+            let start = LIBRARY_ADDRESS as u64 + ph.p_vaddr;
+            let end = start + ph.p_memsz;
+            let mut address = start - start % PAGE_SIZE as u64;
+            while address < end {
+                let page = Page::from_start_address(VirtAddr::new(address as usize));
+                let (source, flags) = template.get(page).unwrap();
+                let frame = if flags.contains(PageTableFlags::WRITABLE) {
+                    let frame = fallocator
+                        .alloc_user_frame()
+                        .ok_or(LinkError::OutOfMemory)?
+                        .into_raw();
+                    // SAFETY: Both frames are user frames of a page, and the
+                    // new one isn't mapped anywhere else.
+                    unsafe {
+                        core::ptr::copy_nonoverlapping(
+                            source.base().to_virtual().as_ptr::<u8>(),
+                            frame.base().to_virtual().as_mut_ptr::<u8>(),
+                            PAGE_SIZE,
+                        );
+                    }
+                    frame
+                } else {
+                    source
+                        .try_as_user()
+                        .map_err(|_| LinkError::OutOfMemory)?
+                        .into_raw()
+                };
+                // SAFETY: The library's span is reserved in every component.
+                unsafe {
+                    addrspace
+                        .map_to(
+                            page,
+                            frame,
+                            flags,
+                            PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE,
+                            fallocator,
+                        )
+                        .map_err(|_| LinkError::OutOfMemory)?;
+                }
+                address += PAGE_SIZE as u64;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the shared library, if one was loaded.
+pub fn library() -> Option<&'static Library> {
+    LIBRARY.get()
+}
+
+/// Loads the shared library from its boot module, if there is one.
+pub fn init() {
+    let protocol = boot::protocol();
+    let module = (0..)
+        .map_while(|index| protocol.module(index))
+        .find(|module| module.name.ends_with(LIBRARY_MODULE));
+    let Some(module) = module else {
+        log::info!("No shared library, components must link statically");
+        return;
+    };
+    match Library::load(module.data) {
+        Ok(library) => {
+            let _ = LIBRARY.set(library);
+            log::info!("Loaded the shared library at {LIBRARY_ADDRESS:#X}");
+        }
+        Err(e) => log::error!("Couldn't load the shared library: {e:?}"),
+    }
+}
+
+/// Links the program in `addrspace` against the shared library, if it has a
+/// dynamic section.
+///
+/// The program must be loaded at the addresses it was linked at.
+pub fn link(
+    program: &[u8],
+    phdrs: &[ProgramHeader],
+    addrspace: &Addrspace,
+    fallocator: &mut BumpAllocator,
+) -> Result<(), LinkError> {
+    let Some(dynamic) = Dynamic::parse(program, phdrs)? else {
+        return Ok(());
+    };
+    let mut library = None;
+    for name in dynamic.needed() {
+        if name? != LIBRARY_MODULE {
+            return Err(LinkError::MissingLibrary);
+        }
+        library = Some(self::library().ok_or(LinkError::MissingLibrary)?);
+    }
+    if let Some(library) = library {
+        library.map_into(addrspace, fallocator)?;
+    }
+    let relocations = dynamic.relocate(
+        0,
+        |name| library?.lookup(name),
+        |address, value| write_word(addrspace, address, value),
+    )?;
+    log::debug!("Applied {relocations} relocations");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::elf::program_header::PF_R;
+
+    use super::*;
+
+    const SIZE: usize = 0x400;
+    const GOT: usize = 0x280;
+    const TEXT: usize = 0x300;
+    const STRINGS: &[u8] = b"\0libkapi.so\0kapi_call\0missing\0local\0";
+
+    fn put(image: &mut [u8], offset: usize, bytes: &[u8]) {
+        image[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn put_u64s(image: &mut [u8], offset: usize, words: &[u64]) {
+        for (i, word) in words.iter().enumerate() {
+            put(image, offset + i * 8, &word.to_le_bytes());
+        }
+    }
+
+    fn name(name: &str) -> u64 {
+        STRINGS
+            .windows(name.len())
+            .position(|window| window == name.as_bytes())
+            .unwrap() as u64
+    }
+
+    /// A writable segment holding the dynamic tables and a GOT, and a
+    /// read-only one after it.
+    fn elf(relocations: &[(u64, u64, u32, i64)]) -> ([u8; SIZE], [ProgramHeader; 3]) {
+        let mut image = [0; SIZE];
+        put_u64s(
+            &mut image,
+            0,
+            &[
+                DT_NEEDED,
+                name("libkapi.so"),
+                DT_HASH,
+                0x180,
+                DT_SYMTAB,
+                0x100,
+                DT_STRTAB,
+                0x1C0,
+                DT_STRSZ,
+                STRINGS.len() as u64,
+                DT_RELA,
+                0x200,
+                DT_RELASZ,
+                (relocations.len() * SIZEOF_RELA) as u64,
+                DT_NULL,
+                0,
+            ],
+        );
+        // Symbols: null, two imports (one weak) and an export.
+        for (i, (name, bind, shndx, value)) in [
+            (0, 0, 0, 0),
+            (name("kapi_call"), 1, 0, 0),
+            (name("missing"), STB_WEAK, 0, 0),
+            (name("local"), 1, 1, 0x2F0),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let sym = 0x100 + i * SIZEOF_SYM;
+            put(&mut image, sym, &(name as u32).to_le_bytes());
+            image[sym + 4] = bind << 4;
+            put(&mut image, sym + 6, &(shndx as u16).to_le_bytes());
+            put(&mut image, sym + 8, &(value as u64).to_le_bytes());
+        }
+        put(&mut image, 0x184, &4u32.to_le_bytes());
+        put(&mut image, 0x1C0, STRINGS);
+        for (i, &(offset, symbol, kind, addend)) in relocations.iter().enumerate() {
+            put_u64s(
+                &mut image,
+                0x200 + i * SIZEOF_RELA,
+                &[offset, symbol << 32 | u64::from(kind), addend as u64],
+            );
+        }
+        let segment = |kind, flags, start: usize, end: usize| ProgramHeader {
+            p_type: kind,
+            p_flags: flags,
+            p_offset: start as u64,
+            p_vaddr: start as u64,
+            p_filesz: (end - start) as u64,
+            p_memsz: (end - start) as u64,
+            ..Default::default()
+        };
+        let phdrs = [
+            segment(PT_LOAD, PF_R | PF_W, 0, TEXT),
+            segment(PT_LOAD, PF_R, TEXT, SIZE),
+            segment(PT_DYNAMIC, PF_R | PF_W, 0, 0x100),
+        ];
+        (image, phdrs)
+    }
+
+    #[test_case]
+    fn parses_the_dynamic_section() {
+        let (image, phdrs) = elf(&[]);
+        let dynamic = Dynamic::parse(&image, &phdrs).unwrap().unwrap();
+        let mut needed = dynamic.needed();
+        assert_eq!(needed.next(), Some(Ok(&b"libkapi.so"[..])));
+        assert_eq!(needed.next(), None);
+        assert_eq!(dynamic.lookup(b"local"), Some(0x2F0));
+        assert_eq!(dynamic.lookup(b"kapi_call"), None);
+
+        assert!(Dynamic::parse(&image, &phdrs[..2]).unwrap().is_none());
+    }
+
+    #[test_case]
+    fn relocates_against_the_library() {
+        let got = GOT as u64;
+        let (image, phdrs) = elf(&[
+            (got, 0, R_X86_64_RELATIVE, 0x10),
+            (got + 8, 1, R_X86_64_GLOB_DAT, 0),
+            (got + 16, 3, R_X86_64_64, 4),
+            (got + 24, 2, R_X86_64_JUMP_SLOT, 0),
+        ]);
+        let dynamic = Dynamic::parse(&image, &phdrs).unwrap().unwrap();
+        let mut got = [0; 4];
+        let applied = dynamic
+            .relocate(
+                0x5000,
+                |name| (name == b"kapi_call").then_some(0x6000_0000_0040),
+                |address, value| {
+                    got[(address as usize - 0x5000 - GOT) / 8] = value;
+                    Ok(())
+                },
+            )
+            .unwrap();
+        assert_eq!(applied, 4);
+        assert_eq!(got, [0x5010, 0x6000_0000_0040, 0x5000 + 0x2F4, 0]);
+
+        let undefined = dynamic.relocate(0, |_| None, |_, _| Ok(()));
+        assert_eq!(undefined, Err(LinkError::Undefined));
+    }
+
+    #[test_case]
+    fn refuses_text_relocations() {
+        let (image, phdrs) = elf(&[(TEXT as u64, 0, R_X86_64_RELATIVE, 0)]);
+        let dynamic = Dynamic::parse(&image, &phdrs).unwrap().unwrap();
+        assert_eq!(
+            dynamic.relocate(0, |_| None, |_, _| Ok(())),
+            Err(LinkError::TextRelocation)
+        );
+
+        let (image, phdrs) = elf(&[(GOT as u64, 0, 37, 0)]);
+        let dynamic = Dynamic::parse(&image, &phdrs).unwrap().unwrap();
+        assert_eq!(
+            dynamic.relocate(0, |_| None, |_, _| Ok(())),
+            Err(LinkError::UnsupportedRelocation(37))
+        );
+    }
+}
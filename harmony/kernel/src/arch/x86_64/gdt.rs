@@ -7,7 +7,8 @@ use x86_64_impl::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSel
 use x86_64_impl::structures::tss::TaskStateSegment;
 use x86_64_impl::VirtAddr;
 
-use crate::arch::paging::PAGE_SIZE;
+use crate::arch::context;
+use crate::arch::paging::{self, PAGE_SIZE};
 
 /// The TSS stack table index to be used for the Double Fault exception.
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
@@ -25,30 +26,49 @@ struct Selectors {
 #[used]
 static mut INTERRUPT_STACK: [u8; PAGE_SIZE] = [0; PAGE_SIZE];
 // FIXME: This needs to be per-core.
-static TSS: AtomicLazyCell<TaskStateSegment> = AtomicLazyCell::new(|| {
-    let mut tss = TaskStateSegment::new();
-    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-        const STACK_SIZE: usize = PAGE_SIZE;
-        #[used]
-        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
 
-        // SAFETY: Although it's a static mut, STACK is only used in this context.
-        let stack_start = VirtAddr::from_ptr(unsafe { STACK.as_slice() });
-        stack_start + STACK_SIZE as u64 // stack end.
-    };
-    // Privilege stack table used on interrupts.
-    tss.privilege_stack_table[0] = {
-        // SAFETY: The interrupt stack is (almost) only used as, well, a stack. Other than getting the pointer
-        // to define it in the TSS we don't do anything else... except for reading the pushed registers
-        // on an interrupt/syscall so that we can save them in case of a thread dispatch *cough* *cough*.
-        interrupt_stack_end()
-    };
-    tss
-});
+/// Fills in the TSS stacks. Must run before the GDT is built.
+fn init_tss() {
+    const STACK_SIZE: usize = PAGE_SIZE;
+    #[used]
+    static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+    // SAFETY: Although they are static mut, the stacks are only used in this
+    // context and the TSS isn't loaded yet.
+    unsafe {
+        let stack_start = VirtAddr::from_ptr(STACK.as_slice());
+        TSS.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            stack_start + STACK_SIZE as u64; // stack end.
+
+        // Privilege stack used on interrupts until the first thread is
+        // dispatched.
+        let start = VirtAddr::from_ptr(INTERRUPT_STACK.as_ptr());
+        TSS.privilege_stack_table[0] = start + PAGE_SIZE as u64;
+    }
+}
+
+/// Sets the stack the CPU switches to when entering the kernel from ring 3.
+///
+/// # Safety
+///
+/// The stack must remain valid while it's active and nothing else may be
+/// using it.
+pub(super) unsafe fn set_kernel_stack(end: paging::VirtAddr) {
+    // SAFETY: Interrupts are disabled in the kernel so nothing can observe the
+    // TSS while it's being modified.
+    unsafe {
+        TSS.privilege_stack_table[0] = VirtAddr::new(end.as_usize() as u64);
+    }
+}
 
-pub(super) fn interrupt_stack_end() -> VirtAddr {
-    let start = VirtAddr::from_ptr(unsafe { INTERRUPT_STACK.as_ptr() });
-    start + PAGE_SIZE as u64
+/// Returns the end of the stack used when entering the kernel from ring 3.
+///
+/// The interrupt and syscall entry points push the user context at the top of
+/// this stack.
+pub(super) fn kernel_stack_end() -> VirtAddr {
+    // SAFETY: See `set_kernel_stack`.
+    unsafe { TSS.privilege_stack_table[0] }
 }
 
 static GDT: AtomicLazyCell<(GlobalDescriptorTable, Selectors)> = AtomicLazyCell::new(|| {
@@ -57,7 +77,12 @@ static GDT: AtomicLazyCell<(GlobalDescriptorTable, Selectors)> = AtomicLazyCell:
     let data_selector = gdt.append(Descriptor::kernel_data_segment());
     let user_code_selector = gdt.append(Descriptor::user_code_segment());
     let user_data_selector = gdt.append(Descriptor::user_data_segment());
-    let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
+    // The dispatch code hardcodes the user selectors.
+    assert_eq!(user_code_selector.0, context::USER_CODE_SELECTOR);
+    assert_eq!(user_data_selector.0, context::USER_DATA_SELECTOR);
+    let tss_selector = gdt.append(Descriptor::tss_segment(unsafe {
+        &*core::ptr::addr_of!(TSS)
+    }));
     (
         gdt,
         Selectors {
@@ -73,6 +98,7 @@ static GDT: AtomicLazyCell<(GlobalDescriptorTable, Selectors)> = AtomicLazyCell:
 /// Sets up the GDT with a TSS that is used for double fault handler stack, a
 /// kernel code segment and a kernel data segment.
 pub fn init() {
+    init_tss();
     GDT.0.load();
     // SAFETY: Segment selectors are valid, and appropriately setup in the GDT.
     unsafe {
@@ -13,3 +13,74 @@ pub fn nop() {
         core::arch::asm!("nop", options(nomem, preserves_flags, nostack));
     }
 }
+
+/// Reads the processor's time-stamp counter.
+pub fn rdtsc() -> u64 {
+    // SAFETY: Reading the TSC has no side effects.
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Reads a random word from the processor's DRBG, or returns `None` if it
+/// couldn't produce one right away.
+///
+/// # Safety
+///
+/// The processor must support `rdrand`.
+pub unsafe fn rdrand() -> Option<u64> {
+    let value: u64;
+    let ok: u8;
+    // SAFETY: Precondition.
+    unsafe {
+        core::arch::asm!(
+            "rdrand {value}",
+            "setc {ok}",
+            value = out(reg) value,
+            ok = out(reg_byte) ok,
+            options(nomem, nostack),
+        );
+    }
+    (ok != 0).then_some(value)
+}
+
+/// Reads a random word straight from the processor's entropy source, or
+/// returns `None` if it has none available right away.
+///
+/// # Safety
+///
+/// The processor must support `rdseed`.
+pub unsafe fn rdseed() -> Option<u64> {
+    let value: u64;
+    let ok: u8;
+    // SAFETY: Precondition.
+    unsafe {
+        core::arch::asm!(
+            "rdseed {value}",
+            "setc {ok}",
+            value = out(reg) value,
+            ok = out(reg_byte) ok,
+            options(nomem, nostack),
+        );
+    }
+    (ok != 0).then_some(value)
+}
+
+/// Invalidates the TLB entry for the page containing `addr`.
+pub fn invlpg(addr: usize) {
+    // SAFETY: Invalidating a TLB entry only forces a new page walk.
+    unsafe {
+        core::arch::asm!("invlpg [{}]", in(reg) addr, options(nostack, preserves_flags));
+    }
+}
+
+/// Flushes every non-global TLB entry by reloading CR3.
+pub fn flush_tlb() {
+    // SAFETY: Reloading the same page table only flushes the TLB.
+    unsafe {
+        core::arch::asm!(
+            "mov {tmp}, cr3",
+            "mov cr3, {tmp}",
+            tmp = out(reg) _,
+            options(nostack, preserves_flags),
+        );
+    }
+}
@@ -1,23 +1,31 @@
 use core::arch::asm;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
+use kapi::diagnostics::PageFaultStats;
+use kernel::page_fault::FaultCounters;
 use pic8259::ChainedPics;
 use sync::cell::AtomicLazyCell;
 use x86_64_impl::structures::idt::InterruptDescriptorTable;
 use x86_64_impl::PrivilegeLevel;
 
 use crate::arch::x86_64::{self, gdt};
+use crate::core_local::{self, NUM_CORES};
 
 mod handlers;
-pub use handlers::{IrqCtx, SyscallCtx};
 
 const PIC1_OFFSET: u8 = 32;
 const PIC2_OFFSET: u8 = PIC1_OFFSET + 8;
 
+/// Vectors of the hardware interrupts.
+pub const IRQ_VECTORS: Range<u8> = PIC1_OFFSET..PIC2_OFFSET + 8;
+
 // TODO: Better way to manage mutual exclusion (core local?).
 static mut PICS: ChainedPics = unsafe { ChainedPics::new(PIC1_OFFSET, PIC2_OFFSET) };
 
 const TIMER_INT: u8 = PIC1_OFFSET;
 const KEYBOARD_INT: u8 = PIC1_OFFSET + 1;
+const SERIAL_INT: u8 = PIC1_OFFSET + 4;
 
 const SYSCALL_INT: u8 = 0x80;
 
@@ -48,6 +56,47 @@ pub fn are_enabled() -> bool {
     (rflags & (1 << 9)) > 0
 }
 
+/// Page faults handled so far, by class.
+static PAGE_FAULTS: FaultCounters = FaultCounters::new();
+
+/// Returns the number of page faults of each class handled so far.
+pub fn page_fault_stats() -> PageFaultStats {
+    PAGE_FAULTS.stats()
+}
+
+/// Number of hardware interrupt handlers running on each core.
+///
+/// The kernel always runs with interrupts disabled, so the interrupt flag
+/// can't tell handlers apart from syscalls. Handlers are tracked here instead.
+static IRQ_DEPTH: [AtomicUsize; NUM_CORES] = [const { AtomicUsize::new(0) }; NUM_CORES];
+
+/// Returns whether the core is handling a hardware interrupt.
+pub fn in_irq() -> bool {
+    IRQ_DEPTH[core_local::current_core()].load(Ordering::Relaxed) > 0
+}
+
+fn enter_irq(vector: u8) {
+    if IRQ_DEPTH[core_local::current_core()].fetch_add(1, Ordering::Relaxed) == 0 {
+        crate::latency::enter(vector);
+    }
+}
+
+fn leave_irq() {
+    if IRQ_DEPTH[core_local::current_core()].fetch_sub(1, Ordering::Relaxed) == 1 {
+        crate::latency::handoff();
+    }
+}
+
+/// Marks the core as no longer handling interrupts.
+///
+/// Handlers that leave the kernel without returning, e.g. by dispatching a
+/// thread, must call this on their way out.
+pub fn exit_irq_context() {
+    if IRQ_DEPTH[core_local::current_core()].swap(0, Ordering::Relaxed) > 0 {
+        crate::latency::handoff();
+    }
+}
+
 /// Initializes the interrupt descriptor table.
 fn init_idt() {
     static IDT: AtomicLazyCell<InterruptDescriptorTable> = AtomicLazyCell::new(|| {
@@ -102,6 +151,7 @@ fn init_idt() {
         // PIC interrupts
         idt[TIMER_INT].set_handler_fn(handlers::timer_interrupt);
         idt[KEYBOARD_INT].set_handler_fn(handlers::keyboard_interrupt);
+        idt[SERIAL_INT].set_handler_fn(handlers::serial_interrupt);
         idt
     });
     IDT.load();
@@ -113,7 +163,7 @@ pub fn init() {
     // SAFETY: PIC Initialization. We only initialize interrupts that we are currently handling.
     unsafe {
         PICS.initialize();
-        PICS.write_masks(0xFC, 0xFF);
+        PICS.write_masks(0xEC, 0xFF);
     }
     log::info!("Interrupt tables initialized");
 }
@@ -1,173 +1,39 @@
 use core::arch::asm;
-use core::mem::MaybeUninit;
 
+use kapi::trace::EventKind;
+use kernel::page_fault::{self, FaultClass, FaultCode};
 use x86_64_impl::registers::control::Cr2;
 use x86_64_impl::structures::idt::{InterruptStackFrame, PageFaultErrorCode};
-
-use super::{KEYBOARD_INT, PICS, TIMER_INT};
-use crate::arch::exec::{ControlRegs, PreservedRegs, Regs, SaveState, ScratchRegs};
-use crate::arch::x86_64::gdt;
-
-pub struct SyscallCtx {
-    pub control_regs: ControlRegs,
-    pub preserved_regs: PreservedRegs,
-}
-
-impl SaveState for SyscallCtx {
-    fn save_state(self, regs: &mut Regs) {
-        regs.control = self.control_regs;
-        regs.preserved = self.preserved_regs;
-    }
-}
-
-impl SyscallCtx {
-    /// Reads the syscall context from the stack
-    ///
-    /// # Safety
-    ///
-    /// Must be currently handling a syscall
-    pub unsafe fn current() -> Self {
-        let stack_end: *mut u64 = gdt::interrupt_stack_end().as_mut_ptr();
-        let rsp = unsafe { *stack_end.sub(2) };
-        let rflags = unsafe { *stack_end.sub(3) };
-        let rip = unsafe { *stack_end.sub(5) };
-        let mut preserved: MaybeUninit<PreservedRegs> = MaybeUninit::uninit();
-        unsafe {
-            core::ptr::copy_nonoverlapping(
-                stack_end.sub(12) as *const PreservedRegs,
-                preserved.as_mut_ptr(),
-                1,
-            );
-        }
-        Self {
-            control_regs: ControlRegs { rflags, rsp, rip },
-            preserved_regs: unsafe { preserved.assume_init() },
-        }
-    }
-}
-
-pub struct IrqCtx {
-    pub control_regs: ControlRegs,
-    pub preserved_regs: PreservedRegs,
-    pub scratch_regs: ScratchRegs,
-}
-
-impl SaveState for IrqCtx {
-    fn save_state(self, regs: &mut Regs) {
-        regs.control = self.control_regs;
-        regs.preserved = self.preserved_regs;
-        regs.scratch = self.scratch_regs;
-    }
-}
-
-impl IrqCtx {
-    /// Reads the syscall context from the stack
-    ///
-    /// # Safety
-    ///
-    /// Must be currently handling a syscall
-    pub unsafe fn current() -> Self {
-        let stack_end: *mut u64 = gdt::interrupt_stack_end().as_mut_ptr();
-        let rsp = unsafe { *stack_end.sub(2) };
-        let rflags = unsafe { *stack_end.sub(3) };
-        let rip = unsafe { *stack_end.sub(5) };
-        let mut preserved: MaybeUninit<PreservedRegs> = MaybeUninit::uninit();
-        unsafe {
-            core::ptr::copy_nonoverlapping(
-                stack_end.sub(12) as *const PreservedRegs,
-                preserved.as_mut_ptr(),
-                1,
-            );
-        }
-        let mut scratch: MaybeUninit<ScratchRegs> = MaybeUninit::uninit();
-        unsafe {
-            core::ptr::copy_nonoverlapping(
-                stack_end.sub(21) as *const ScratchRegs,
-                scratch.as_mut_ptr(),
-                1,
-            );
-        }
-        Self {
-            control_regs: ControlRegs { rflags, rsp, rip },
-            preserved_regs: unsafe { preserved.assume_init() },
-            scratch_regs: unsafe { scratch.assume_init() },
-        }
-    }
-}
-
-impl IrqCtx {}
-
-macro_rules! push_scratch {
-    () => {
-        r#"
-        push r11
-        push r10
-        push r9
-        push r8
-        push rdi
-        push rsi
-        push rdx
-        push rcx
-        push rax
-        "#
-    };
-}
-
-macro_rules! pop_scratch {
-    () => {
-        r#"
-        pop rax
-        pop rcx
-        pop rdx
-        pop rsi
-        pop rdi
-        pop r8
-        pop r9
-        pop r10
-        pop r11
-        "#
-    };
-}
-
-macro_rules! push_preserved {
-    () => {
-        r#"
-        push r15
-        push r14
-        push r13
-        push r12
-        push rbp
-        push rbx
-        "#
-    };
-}
-
-macro_rules! pop_preserved {
-    () => {
-        r#"
-        pop rbx
-        pop rbp
-        pop r12
-        pop r13
-        pop r14
-        pop r15
-        "#
-    };
-}
+use x86_64_impl::PrivilegeLevel;
+
+use super::{KEYBOARD_INT, PICS, SERIAL_INT, TIMER_INT};
+use crate::arch::context::{
+    pop_preserved, pop_scratch, push_preserved, push_scratch, FaultFrame, IrqFrame,
+};
+use crate::arch::paging::page_table::AnyPageTable;
+use crate::arch::paging::{Page, VirtAddr};
+use crate::arch::x86_64::instructions;
+use crate::component::Thread;
+use crate::trace;
 
 macro_rules! interrupt {
-    ($name:ident, $handler:expr) => {
+    ($name:ident, $vector:expr, $handler:expr) => {
         #[naked]
         pub(super) extern "x86-interrupt" fn $name(_frame: InterruptStackFrame) {
-            extern "C" fn inner() {
+            extern "C" fn inner(frame: &IrqFrame) {
+                super::enter_irq($vector);
                 #[allow(clippy::redundant_closure_call)]
-                $handler();
+                $handler(frame);
+                super::leave_irq();
             }
-            // SAFETY: Following ABI with iretq and we only wrap a C call with push/pop scratch registers.
+            // SAFETY: Following ABI with iretq and we only wrap a C call with
+            // push/pop scratch registers. The pushes build the `IrqFrame`
+            // passed to the handler.
             unsafe {
                 core::arch::asm!(
                     push_preserved!(),
                     push_scratch!(),
+                    "mov rdi, rsp",
                     "call {inner}",
                     pop_scratch!(),
                     pop_preserved!(),
@@ -180,28 +46,46 @@ macro_rules! interrupt {
     }
 }
 
-interrupt!(timer_interrupt, || {
+interrupt!(timer_interrupt, TIMER_INT, |frame: &IrqFrame| {
+    crate::info::tick();
+    crate::profile::tick(frame);
+    crate::ipi::handle_requests();
+    crate::scrub::tick();
+    crate::stack::tick();
+    #[cfg(feature = "control")]
+    crate::control::tick();
     // SAFETY: Notify timer interrupt vector.
     unsafe {
         PICS.notify_end_of_interrupt(TIMER_INT);
     }
+    #[cfg(feature = "round-robin")]
+    crate::sched::tick();
 });
 
-interrupt!(keyboard_interrupt, || {
+interrupt!(keyboard_interrupt, KEYBOARD_INT, |_: &IrqFrame| {
     // SAFETY: Notify keyboard interrupt vector.
     unsafe {
         PICS.notify_end_of_interrupt(KEYBOARD_INT);
     }
 });
 
+interrupt!(serial_interrupt, SERIAL_INT, |_: &IrqFrame| {
+    crate::serial::receive_pending();
+    // SAFETY: Notify serial interrupt vector.
+    unsafe {
+        PICS.notify_end_of_interrupt(SERIAL_INT);
+    }
+});
+
 #[naked]
 pub(super) extern "x86-interrupt" fn syscall_interrupt(stack_frame: InterruptStackFrame) {
-    // SAFETY: Very thin wrapper over a syscall. We don't need to do callee saved since sysv64 abi will
-    // take care of that.
+    // SAFETY: Very thin wrapper over a syscall that pushes a `SyscallFrame`.
+    // The handler would keep the preserved registers anyway, but they're saved
+    // on the stack so that the thread's context can be read while dispatching.
     unsafe {
         asm!(
             push_preserved!(),
-            "sub rsp, 8",
+            "sub rsp, 8", // SyscallFrame::_align
             "call {handle_syscall}",
             "add rsp, 8",
             pop_preserved!(),
@@ -230,7 +114,12 @@ pub(super) extern "x86-interrupt" fn invalid_opcode(stack_frame: InterruptStackF
 }
 
 pub(super) extern "x86-interrupt" fn device_not_available(stack_frame: InterruptStackFrame) {
-    panic!("DEVICE NOT AVAILABLE:\n{stack_frame:#?}");
+    if stack_frame.code_segment.rpl() != PrivilegeLevel::Ring3 {
+        panic!("DEVICE NOT AVAILABLE:\n{stack_frame:#?}");
+    }
+    // SAFETY: The thread's first SIMD instruction since it was dispatched
+    // trapped.
+    unsafe { crate::component::Thread::take_simd() };
 }
 
 pub(super) extern "x86-interrupt" fn invalid_tss(stack_frame: InterruptStackFrame, code: u64) {
@@ -299,12 +188,84 @@ pub(super) extern "x86-interrupt" fn general_protection_fault(
     panic!("EXCEPTION: GENERAL PROTECTION - {error_code:#02X}\n{stack_frame:#?}");
 }
 
+#[naked]
 pub(super) extern "x86-interrupt" fn page_fault(
-    stack_frame: InterruptStackFrame,
-    error_code: PageFaultErrorCode,
+    _stack_frame: InterruptStackFrame,
+    _error_code: PageFaultErrorCode,
 ) {
-    let addrs: *const () = Cr2::read().unwrap().as_ptr();
-    panic!("EXCEPTION: PAGE FAULT @ {addrs:#?} - {error_code:#02X}\n{stack_frame:#?}");
+    // SAFETY: Like `interrupt!`, but the processor pushed an error code that
+    // has to be dropped before `iretq`. The pushes build the `FaultFrame`
+    // passed to the handler.
+    unsafe {
+        asm!(
+            push_preserved!(),
+            push_scratch!(),
+            "sub rsp, 8", // FaultFrame::_align
+            "mov rdi, rsp",
+            "call {handle_page_fault}",
+            "add rsp, 8",
+            pop_scratch!(),
+            pop_preserved!(),
+            "add rsp, 8", // FaultFrame::code
+            "iretq",
+            handle_page_fault = sym handle_page_fault,
+            options(noreturn),
+        )
+    }
+}
+
+extern "C" fn handle_page_fault(frame: &FaultFrame) {
+    let address = Cr2::read_raw();
+    let code = FaultCode::new(frame.code());
+    let page = Page::containing_address(VirtAddr::new_truncate(address as usize));
+    let class = page_fault::classify(code, || {
+        let table = AnyPageTable::current();
+        // SAFETY: CR3 always holds a root-level page table.
+        unsafe { table.as_addrspace() }.swap_token(page)
+    });
+    super::PAGE_FAULTS.count(class);
+    let thread = match class {
+        FaultClass::Kernel => panic!(
+            "EXCEPTION: PAGE FAULT in the kernel: {code} at {address:#X}, rip {:#X} ({:#X})",
+            frame.rip(),
+            frame.code(),
+        ),
+        FaultClass::User | FaultClass::Swapped { .. } => Thread::current()
+            .unwrap_or_else(|| panic!("User page fault at {address:#X} without a thread")),
+    };
+    trace::event_current(EventKind::PageFault, address);
+    match class {
+        FaultClass::Swapped { token } => {
+            // FIXME: Upcall the component's pager to bring the page back in.
+            // There's no way to run a thread on behalf of another one yet, so
+            // it's left to the supervisor, which has to map the page again
+            // and resume the thread.
+            log::info!("Fault on swapped out page {page:?} (token {token:#X})");
+        }
+        _ => log::warn!(
+            "Thread faulted: {code} at {address:#X}, rip {:#X}",
+            frame.rip()
+        ),
+    }
+    // The thread can't continue until its supervisor fixes the mapping and
+    // resumes it, so another queued thread takes over the core. If there's
+    // none, the core idles until there is or the thread is resumed.
+    thread.fault();
+    thread.report_fault();
+    loop {
+        #[cfg(feature = "round-robin")]
+        if let Some(next) = crate::sched::yield_now(thread.clone()) {
+            Thread::dispatch(next, crate::arch::context::IrqCtx::from(frame));
+        }
+        if !thread.is_suspended() {
+            return;
+        }
+        // SAFETY: The thread's registers are saved in the fault frame, and
+        // interrupts from the kernel return without switching threads.
+        unsafe { super::enable() };
+        instructions::hlt();
+        super::disable();
+    }
 }
 
 pub(super) extern "x86-interrupt" fn double_fault(
@@ -0,0 +1,842 @@
+//! DMA remapping with Intel VT-d.
+//!
+//! The DMAR table lists the remapping units and the PCI functions each of
+//! them translates. At boot every unit gets a root table and a context table
+//! for each bus with a function on it, and then translation is turned on.
+//! From then on a function can only reach memory through its context entry,
+//! which is empty until a driver builds a [`Domain`] for it. Everything a
+//! function without a domain tries to access is blocked.
+//!
+//! A domain owns the second-level page tables of one function. Drivers extend
+//! them with untyped memory like any other kernel object and grant user frames
+//! from their own regions into them. Granted frames stay pinned until they
+//! are revoked so that they can't be retyped while the device can reach them.
+//!
+//! Registers are accessed through the direct map, which relies on the MTRRs
+//! making the register ranges uncacheable. Faults are recorded by the units
+//! but not reported yet.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use kapi::ops::iommu::requester_id;
+use kapi::raw::CapError;
+use sync::cell::AtomicOnceCell;
+
+use crate::arch::acpi;
+use crate::arch::paging::{PhysAddr, PhysAddrExt as _, RawFrame, PAGE_SIZE};
+use crate::arch::pci::{self, Function};
+use crate::bump_allocator::BumpAllocator;
+use crate::kptr::KPtr;
+use crate::retyping::{KernelFrame, PinError};
+
+/// Remapping units the kernel drives.
+const MAX_UNITS: usize = 4;
+
+/// Size of the unit's register block.
+const REGISTERS_SIZE: usize = 0x1000;
+const CAP: usize = 0x08;
+const ECAP: usize = 0x10;
+const GCMD: usize = 0x18;
+const GSTS: usize = 0x1C;
+const RTADDR: usize = 0x20;
+const CCMD: usize = 0x28;
+
+const GCMD_TE: u32 = 1 << 31;
+const GCMD_SRTP: u32 = 1 << 30;
+/// Status bits that have to be written back along with a command.
+const GSTS_PERSISTENT: u32 = 0x96FF_FFFF;
+
+const CCMD_ICC: u64 = 1 << 63;
+const CCMD_GLOBAL: u64 = 0b01 << 61;
+const CCMD_DEVICE: u64 = 0b11 << 61;
+const IOTLB_IVT: u64 = 1 << 63;
+const IOTLB_GLOBAL: u64 = 0b01 << 60;
+const IOTLB_DOMAIN: u64 = 0b10 << 60;
+const IOTLB_DRAIN: u64 = 0b11 << 48;
+
+/// Entries are readable by the device.
+const READ: u64 = 1 << 0;
+/// Entries are writable by the device.
+const WRITE: u64 = 1 << 1;
+/// Root and context entries are present.
+const PRESENT: u64 = 1 << 0;
+const ADDRESS: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// Parsing of the DMAR table.
+mod dmar {
+    use kapi::ops::iommu::requester_id;
+
+    use crate::arch::acpi::HEADER_SIZE;
+    use crate::arch::pci::Function;
+
+    /// Remapping structures start after the header, the host address width,
+    /// the flags and 10 reserved bytes.
+    const STRUCTURES: usize = HEADER_SIZE + 12;
+    const HARDWARE_UNIT: u16 = 0;
+    const INCLUDE_PCI_ALL: u8 = 1 << 0;
+    const SCOPE_ENDPOINT: u8 = 1;
+    const SCOPE_BRIDGE: u8 = 2;
+
+    fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+        Some(u16::from_le_bytes(
+            bytes.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    }
+
+    /// A DMA remapping hardware unit definition.
+    #[derive(Debug, Copy, Clone)]
+    pub struct Unit<'a> {
+        /// Translates every function of the segment that no other unit lists.
+        pub include_all: bool,
+        pub segment: u16,
+        /// Physical address of the registers.
+        pub base: u64,
+        scopes: &'a [u8],
+    }
+
+    /// The remapping units in `dmar`, in the order they are listed.
+    pub fn units(dmar: &[u8]) -> impl Iterator<Item = Unit<'_>> {
+        let mut rest = dmar.get(STRUCTURES..).unwrap_or_default();
+        core::iter::from_fn(move || loop {
+            let kind = read_u16(rest, 0)?;
+            let len = usize::from(read_u16(rest, 2)?);
+            if len < 4 || len > rest.len() {
+                return None;
+            }
+            let (structure, next) = rest.split_at(len);
+            rest = next;
+            if kind == HARDWARE_UNIT && len >= 16 {
+                return Some(Unit {
+                    include_all: structure[4] & INCLUDE_PCI_ALL != 0,
+                    segment: read_u16(structure, 6)?,
+                    base: u64::from_le_bytes(structure[8..16].try_into().ok()?),
+                    scopes: &structure[16..],
+                });
+            }
+        })
+    }
+
+    impl Unit<'_> {
+        /// Whether the unit lists the function with `requester` in its scope.
+        ///
+        /// `bridge` returns the secondary and subordinate buses of a bridge,
+        /// which are needed to follow scope paths.
+        pub fn lists(
+            &self,
+            requester: u16,
+            bridge: &impl Fn(Function) -> Option<(u8, u8)>,
+        ) -> bool {
+            let mut rest = self.scopes;
+            while let [kind, len, ..] = *rest {
+                let len = usize::from(len);
+                if len < 6 || len > rest.len() {
+                    return false;
+                }
+                let (scope, next) = rest.split_at(len);
+                rest = next;
+                if scope_lists(kind, scope[5], &scope[6..], requester, bridge) {
+                    return true;
+                }
+            }
+            false
+        }
+    }
+
+    fn scope_lists(
+        kind: u8,
+        mut bus: u8,
+        path: &[u8],
+        requester: u16,
+        bridge: &impl Fn(Function) -> Option<(u8, u8)>,
+    ) -> bool {
+        let mut hops = path.chunks_exact(2).peekable();
+        while let Some(hop) = hops.next() {
+            let function = Function {
+                bus,
+                device: hop[0],
+                function: hop[1],
+            };
+            let id = requester_id(function.bus, function.device, function.function);
+            if hops.peek().is_some() {
+                match bridge(function) {
+                    Some((secondary, _)) => bus = secondary,
+                    None => return false,
+                }
+                continue;
+            }
+            return match kind {
+                SCOPE_ENDPOINT => id == requester,
+                SCOPE_BRIDGE => {
+                    let below = |(secondary, subordinate)| {
+                        (secondary..=subordinate).contains(&((requester >> 8) as u8))
+                    };
+                    id == requester || bridge(function).is_some_and(below)
+                }
+                _ => false,
+            };
+        }
+        false
+    }
+
+    /// Position of the unit that translates the DMA of `requester` on
+    /// segment 0.
+    pub fn unit_for(
+        dmar: &[u8],
+        requester: u16,
+        bridge: &impl Fn(Function) -> Option<(u8, u8)>,
+    ) -> Option<usize> {
+        let mut fallback = None;
+        for (index, unit) in units(dmar).enumerate() {
+            if unit.segment != 0 {
+                continue;
+            }
+            if unit.lists(requester, bridge) {
+                return Some(index);
+            }
+            if unit.include_all {
+                fallback = Some(index);
+            }
+        }
+        fallback
+    }
+}
+
+/// A page of 64-bit table entries.
+///
+/// Root and context tables use pairs of entries.
+#[repr(C, align(4096))]
+pub struct IoTable([AtomicU64; 512]);
+
+impl IoTable {
+    const fn new() -> Self {
+        Self([const { AtomicU64::new(0) }; 512])
+    }
+
+    /// The table at `address`.
+    ///
+    /// # Safety
+    ///
+    /// `address` must hold an `IoTable` that outlives the reference.
+    unsafe fn at<'a>(address: u64) -> &'a Self {
+        // SAFETY: Guaranteed by the caller.
+        unsafe { &*PhysAddr::new(address).to_virtual().as_ptr() }
+    }
+}
+
+/// Writes the cache line holding `entry` back to memory for units that don't
+/// snoop the CPU caches.
+fn flush(entry: &AtomicU64, coherent: bool) {
+    if !coherent {
+        // SAFETY: The entry is valid memory.
+        unsafe { core::arch::x86_64::_mm_clflush(entry.as_ptr() as *const u8) };
+    }
+}
+
+/// A remapping unit.
+struct Unit {
+    /// Position of the unit in the DMAR table.
+    index: usize,
+    /// Where the registers are in the direct map.
+    registers: usize,
+    /// Offset of the IOTLB invalidation register.
+    iotlb: usize,
+    root: KPtr<IoTable>,
+    /// Levels of second-level page tables.
+    levels: u8,
+    /// Number of domain ids.
+    domains: usize,
+    /// Whether the unit snoops the CPU caches when it walks tables.
+    coherent: bool,
+    /// Whether the unit caches non-present entries.
+    caching_mode: bool,
+    /// Held while an invalidation is in progress.
+    busy: AtomicBool,
+}
+
+impl Unit {
+    fn new(index: usize, base: u64, allocator: &mut BumpAllocator) -> Option<Self> {
+        let registers = acpi::mapped(base, REGISTERS_SIZE)?.as_ptr() as usize;
+        let mut unit = Self {
+            index,
+            registers,
+            iotlb: 0,
+            root: KPtr::new(allocator.alloc_untyped_frame()?, IoTable::new()).ok()?,
+            levels: 0,
+            domains: 0,
+            coherent: false,
+            caching_mode: false,
+            busy: AtomicBool::new(false),
+        };
+        let cap = unit.read64(CAP);
+        let ecap = unit.read64(ECAP);
+        let sagaw = (cap >> 8) & 0x1F;
+        unit.levels = match sagaw {
+            _ if sagaw & 0b100 != 0 => 4,
+            _ if sagaw & 0b010 != 0 => 3,
+            _ => {
+                log::warn!("IOMMU {index} doesn't support 3 or 4 level tables");
+                return None;
+            }
+        };
+        unit.domains = 1 << (4 + 2 * (cap & 0b111));
+        unit.caching_mode = cap & (1 << 7) != 0;
+        unit.coherent = ecap & 1 != 0;
+        unit.iotlb = ((ecap >> 8) & 0x3FF) as usize * 16 + 8;
+        Some(unit)
+    }
+
+    fn read32(&self, offset: usize) -> u32 {
+        // SAFETY: The registers are mapped and `offset` is within them.
+        unsafe { ((self.registers + offset) as *const u32).read_volatile() }
+    }
+
+    fn write32(&self, offset: usize, value: u32) {
+        // SAFETY: The registers are mapped and `offset` is within them.
+        unsafe { ((self.registers + offset) as *mut u32).write_volatile(value) }
+    }
+
+    fn read64(&self, offset: usize) -> u64 {
+        // SAFETY: The registers are mapped and `offset` is within them.
+        unsafe { ((self.registers + offset) as *const u64).read_volatile() }
+    }
+
+    fn write64(&self, offset: usize, value: u64) {
+        // SAFETY: The registers are mapped and `offset` is within them.
+        unsafe { ((self.registers + offset) as *mut u64).write_volatile(value) }
+    }
+
+    /// Issues a global command and waits for the unit to carry it out.
+    fn command(&self, bit: u32) {
+        let status = self.read32(GSTS);
+        self.write32(GCMD, (status & GSTS_PERSISTENT) | bit);
+        while self.read32(GSTS) & bit == 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Runs an invalidation, waiting for it to complete.
+    fn invalidate(&self, offset: usize, request: u64) {
+        while self
+            .busy
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        self.write64(offset, request);
+        // The top bit of both registers stays set until it's done.
+        while self.read64(offset) & (1 << 63) != 0 {
+            core::hint::spin_loop();
+        }
+        self.busy.store(false, Ordering::Release);
+    }
+
+    fn invalidate_context(&self, requester: u16, id: u16) {
+        let request = CCMD_ICC | CCMD_DEVICE | u64::from(requester) << 16 | u64::from(id);
+        self.invalidate(CCMD, request);
+    }
+
+    fn invalidate_domain(&self, id: u16) {
+        let request = IOTLB_IVT | IOTLB_DOMAIN | IOTLB_DRAIN | u64::from(id) << 32;
+        self.invalidate(self.iotlb, request);
+    }
+
+    /// Builds the context table for `bus` if it's missing.
+    fn add_bus(&self, bus: u8, allocator: &mut BumpAllocator) -> Option<()> {
+        let entry = &self.root.0[2 * usize::from(bus)];
+        if entry.load(Ordering::Relaxed) & PRESENT != 0 {
+            return Some(());
+        }
+        let table = KPtr::new(allocator.alloc_untyped_frame()?, IoTable::new()).ok()?;
+        let address = table.into_raw().addr().as_u64();
+        entry.store(address | PRESENT, Ordering::Release);
+        flush(entry, self.coherent);
+        Some(())
+    }
+
+    /// Points the unit at its root table and turns translation on.
+    fn enable(&self) {
+        self.write64(RTADDR, self.root.frame().addr().as_u64());
+        self.command(GCMD_SRTP);
+        self.invalidate(CCMD, CCMD_ICC | CCMD_GLOBAL);
+        self.invalidate(self.iotlb, IOTLB_IVT | IOTLB_GLOBAL | IOTLB_DRAIN);
+        self.command(GCMD_TE);
+    }
+
+    /// The low and high halves of the context entry of `requester`, if its
+    /// bus has a context table.
+    fn context(&self, requester: u16) -> Option<(&AtomicU64, &AtomicU64)> {
+        let root = self.root.0[2 * usize::from(requester >> 8)].load(Ordering::Acquire);
+        if root & PRESENT == 0 {
+            return None;
+        }
+        // SAFETY: Context tables are never released.
+        let table = unsafe { IoTable::at(root & ADDRESS) };
+        let index = 2 * usize::from(requester & 0xFF);
+        Some((&table.0[index], &table.0[index + 1]))
+    }
+}
+
+struct Iommu {
+    dmar: &'static [u8],
+    units: [Option<Unit>; MAX_UNITS],
+}
+
+impl Iommu {
+    fn unit_for(&self, requester: u16) -> Option<&Unit> {
+        let index = dmar::unit_for(self.dmar, requester, &|function: Function| {
+            function.bridge_buses()
+        })?;
+        self.units.get(index)?.as_ref()
+    }
+}
+
+static IOMMU: AtomicOnceCell<Iommu> = AtomicOnceCell::new();
+
+/// Domain ids in use. Id 0 is never handed out since units in caching mode
+/// reserve it.
+static DOMAIN_IDS: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+
+fn alloc_id(limit: usize) -> Option<u16> {
+    let limit = limit.min(DOMAIN_IDS.len() * 64);
+    (1..limit).find_map(|id| {
+        let bit = 1 << (id % 64);
+        let previous = DOMAIN_IDS[id / 64].fetch_or(bit, Ordering::Relaxed);
+        (previous & bit == 0).then_some(id as u16)
+    })
+}
+
+fn free_id(id: u16) {
+    if id != 0 {
+        let id = usize::from(id);
+        DOMAIN_IDS[id / 64].fetch_and(!(1 << (id % 64)), Ordering::Relaxed);
+    }
+}
+
+/// Finds the remapping units and turns translation on.
+///
+/// Must be called after the retype table has been initialized. Devices lose
+/// access to memory until drivers build domains for them.
+pub fn init() {
+    let Some(table) = acpi::find(b"DMAR") else {
+        log::info!("No DMAR table, device DMA isn't isolated");
+        return;
+    };
+    let mut allocator = BumpAllocator::new();
+    let mut units = [const { None }; MAX_UNITS];
+    for (index, unit) in dmar::units(table).enumerate() {
+        if index >= MAX_UNITS {
+            log::warn!("Ignoring IOMMUs past the first {MAX_UNITS}");
+            break;
+        }
+        if unit.segment != 0 {
+            log::warn!("Ignoring IOMMU {index} on PCI segment {}", unit.segment);
+            continue;
+        }
+        units[index] = Unit::new(index, unit.base, &mut allocator);
+    }
+    let iommu = Iommu { dmar: table, units };
+    pci::enumerate(|function| {
+        let requester = requester_id(function.bus, function.device, function.function);
+        if let Some(unit) = iommu.unit_for(requester) {
+            if unit.add_bus(function.bus, &mut allocator).is_none() {
+                log::error!("No memory for the context table of bus {}", function.bus);
+            }
+        }
+    });
+    for unit in iommu.units.iter().flatten() {
+        unit.enable();
+        log::info!(
+            "IOMMU {} translates DMA with {} level tables",
+            unit.index,
+            unit.levels
+        );
+    }
+    if IOMMU.set_with(|| iommu).is_err() {
+        log::error!("IOMMUs were already initialized");
+    }
+}
+
+/// Number of remapping units translating DMA.
+pub fn units() -> usize {
+    IOMMU
+        .try_get()
+        .map_or(0, |iommu| iommu.units.iter().flatten().count())
+}
+
+/// The DMA address space of a PCI function.
+///
+/// The function's context entry is claimed when the domain is built and
+/// points at the domain's tables once the first of them is built. Dropping
+/// the domain blocks the function again and releases its tables and grants.
+pub struct Domain {
+    unit: Option<&'static Unit>,
+    requester: u16,
+    id: u16,
+    levels: u8,
+    /// Physical address of the top table, or 0 until it's built.
+    root: AtomicU64,
+}
+
+impl Domain {
+    /// Builds the domain of the function with `requester` in `frame`.
+    pub fn construct(frame: RawFrame, requester: u16) -> Result<KPtr<Self>, CapError> {
+        let unit = IOMMU
+            .try_get()
+            .ok()
+            .and_then(|iommu| iommu.unit_for(requester))
+            .ok_or(CapError::NotFound)?;
+        let (_, high) = unit.context(requester).ok_or(CapError::NotFound)?;
+        let id = alloc_id(unit.domains).ok_or(CapError::OutOfMemory)?;
+        // Address widths 1 and 2 are 3 and 4 level tables.
+        let claim = u64::from(unit.levels - 2) | u64::from(id) << 8;
+        if high
+            .compare_exchange(0, claim, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            free_id(id);
+            return Err(CapError::ResourceInUse);
+        }
+        flush(high, unit.coherent);
+        let domain = Self {
+            unit: Some(unit),
+            requester,
+            id,
+            levels: unit.levels,
+            root: AtomicU64::new(0),
+        };
+        // Dropping the domain gives up the claim if this fails.
+        KPtr::new(frame, domain).map_err(|_| CapError::InvalidArgument)
+    }
+
+    /// End of the addresses the domain translates.
+    fn limit(&self) -> usize {
+        1 << (12 + 9 * usize::from(self.levels))
+    }
+
+    fn check(&self, iova: usize) -> Result<(), CapError> {
+        if iova % PAGE_SIZE != 0 || iova >= self.limit() {
+            return Err(CapError::InvalidArgument);
+        }
+        Ok(())
+    }
+
+    fn coherent(&self) -> bool {
+        self.unit.map_or(true, |unit| unit.coherent)
+    }
+
+    /// Builds a table in the frame `next_frame` returns and returns its
+    /// address.
+    fn new_table<F>(next_frame: &mut F) -> Result<u64, CapError>
+    where
+        F: FnMut() -> Result<RawFrame, CapError>,
+    {
+        let table =
+            KPtr::new(next_frame()?, IoTable::new()).map_err(|_| CapError::InvalidArgument)?;
+        Ok(table.into_raw().addr().as_u64())
+    }
+
+    /// Builds the tables missing on the way to `iova`, taking their frames
+    /// from `next_frame`. Returns the number of tables built.
+    pub fn extend<F>(&self, iova: usize, mut next_frame: F) -> Result<usize, CapError>
+    where
+        F: FnMut() -> Result<RawFrame, CapError>,
+    {
+        self.check(iova)?;
+        let mut built = 0;
+        let mut table = self.root.load(Ordering::Acquire);
+        if table == 0 {
+            let new = Self::new_table(&mut next_frame)?;
+            match self
+                .root
+                .compare_exchange(0, new, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => {
+                    built += 1;
+                    self.attach(new);
+                    table = new;
+                }
+                Err(existing) => {
+                    // SAFETY: The table was never linked anywhere.
+                    unsafe { release_table(new) };
+                    table = existing;
+                }
+            }
+        }
+        for level in (2..=self.levels).rev() {
+            // SAFETY: Tables are only released when the domain is dropped.
+            let entry = &unsafe { IoTable::at(table) }.0[index(iova, level)];
+            let mut next = entry.load(Ordering::Acquire) & ADDRESS;
+            if next == 0 {
+                let new = Self::new_table(&mut next_frame)?;
+                match entry.compare_exchange(
+                    0,
+                    new | READ | WRITE,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                ) {
+                    Ok(_) => {
+                        flush(entry, self.coherent());
+                        built += 1;
+                        next = new;
+                    }
+                    Err(existing) => {
+                        // SAFETY: The table was never linked anywhere.
+                        unsafe { release_table(new) };
+                        next = existing & ADDRESS;
+                    }
+                }
+            }
+            table = next;
+        }
+        Ok(built)
+    }
+
+    /// Points the function's context entry at the top table.
+    fn attach(&self, root: u64) {
+        let Some(unit) = self.unit else {
+            return;
+        };
+        let (low, _) = unit.context(self.requester).unwrap();
+        low.store(root | PRESENT, Ordering::Release);
+        flush(low, unit.coherent);
+        unit.invalidate_context(self.requester, self.id);
+        unit.invalidate_domain(self.id);
+    }
+
+    /// The last-level entry for `iova`, if its tables were built.
+    fn leaf(&self, iova: usize) -> Option<&AtomicU64> {
+        let mut table = self.root.load(Ordering::Acquire);
+        for level in (1..=self.levels).rev() {
+            if table == 0 {
+                return None;
+            }
+            // SAFETY: Tables are only released when the domain is dropped.
+            let entry = &unsafe { IoTable::at(table) }.0[index(iova, level)];
+            if level == 1 {
+                return Some(entry);
+            }
+            table = entry.load(Ordering::Acquire) & ADDRESS;
+        }
+        None
+    }
+
+    /// Lets the device access the user frame `frame` at `iova`, pinning it
+    /// until it's revoked.
+    pub fn grant(&self, frame: RawFrame, iova: usize, writable: bool) -> Result<(), CapError> {
+        self.check(iova)?;
+        let entry = self.leaf(iova).ok_or(CapError::OutOfMemory)?;
+        let user = frame.try_as_user().map_err(|_| CapError::FrameNotUser)?;
+        user.pin().map_err(|e| match e {
+            PinError::MaxPins => CapError::ResourceInUse,
+            PinError::StaleEpoch | PinError::NotPinned => CapError::FrameNotUser,
+        })?;
+        let access = if writable { READ | WRITE } else { READ };
+        let value = frame.addr().as_u64() | access;
+        if entry
+            .compare_exchange(0, value, Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+        {
+            let _ = user.unpin();
+            return Err(CapError::ResourceInUse);
+        }
+        flush(entry, self.coherent());
+        if let Some(unit) = self.unit.filter(|unit| unit.caching_mode) {
+            unit.invalidate_domain(self.id);
+        }
+        // The entry keeps the reference.
+        user.into_raw();
+        Ok(())
+    }
+
+    /// Takes the frame granted at `iova` away from the device.
+    pub fn revoke(&self, iova: usize) -> Result<(), CapError> {
+        self.check(iova)?;
+        let entry = self.leaf(iova).ok_or(CapError::InvalidArgument)?;
+        let value = entry.swap(0, Ordering::AcqRel);
+        if value == 0 {
+            return Err(CapError::InvalidArgument);
+        }
+        flush(entry, self.coherent());
+        if let Some(unit) = self.unit {
+            unit.invalidate_domain(self.id);
+        }
+        // SAFETY: The device can no longer reach the frame.
+        unsafe { release_frame(value) };
+        Ok(())
+    }
+}
+
+impl Drop for Domain {
+    fn drop(&mut self) {
+        if let Some(unit) = self.unit {
+            if let Some((low, high)) = unit.context(self.requester) {
+                low.store(0, Ordering::Release);
+                flush(low, unit.coherent);
+                unit.invalidate_context(self.requester, self.id);
+                unit.invalidate_domain(self.id);
+                high.store(0, Ordering::Release);
+                flush(high, unit.coherent);
+            }
+            free_id(self.id);
+        }
+        let root = *self.root.get_mut();
+        if root != 0 {
+            // SAFETY: The device can no longer reach the tables.
+            unsafe { release_tree(root, self.levels) };
+        }
+    }
+}
+
+/// Index of the entry for `iova` in a table at `level`.
+fn index(iova: usize, level: u8) -> usize {
+    (iova >> (12 + 9 * (usize::from(level) - 1))) & 0x1FF
+}
+
+/// Unpins a granted frame and drops the reference its entry held.
+///
+/// # Safety
+///
+/// `entry` must be a last-level entry that the device can no longer use.
+unsafe fn release_frame(entry: u64) {
+    let frame = RawFrame::from_start_address(PhysAddr::new(entry & ADDRESS));
+    if let Ok(user) = frame.try_as_user() {
+        let _ = user.unpin();
+    }
+    // SAFETY: The entry owned a reference.
+    if unsafe { frame.drop_user_ref() } == Some(0) {
+        let _ = frame.try_into_untyped();
+    }
+}
+
+/// Releases the table at `address`.
+///
+/// # Safety
+///
+/// The table must not be linked anywhere the device can use.
+unsafe fn release_table(address: u64) {
+    let frame = RawFrame::from_start_address(PhysAddr::new(address));
+    // SAFETY: Tables are leaked into their entry when they are built.
+    if unsafe { KernelFrame::from_raw(frame) }.drop() == 1 {
+        let _ = frame.try_into_untyped();
+    }
+}
+
+/// Releases the table at `address` along with everything below it.
+///
+/// # Safety
+///
+/// The table must not be linked anywhere the device can use.
+unsafe fn release_tree(address: u64, level: u8) {
+    // SAFETY: Guaranteed by the caller.
+    let table = unsafe { IoTable::at(address) };
+    for entry in table.0.iter() {
+        let value = entry.swap(0, Ordering::Relaxed);
+        if value == 0 {
+            continue;
+        }
+        // SAFETY: The tree is no longer reachable.
+        unsafe {
+            match level {
+                1 => release_frame(value),
+                _ => release_tree(value & ADDRESS, level - 1),
+            }
+        }
+    }
+    // SAFETY: Guaranteed by the caller.
+    unsafe { release_table(address) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::acpi::HEADER_SIZE;
+
+    /// A domain that isn't attached to any function.
+    fn detached(levels: u8) -> Domain {
+        Domain {
+            unit: None,
+            requester: 0,
+            id: 0,
+            levels,
+            root: AtomicU64::new(0),
+        }
+    }
+
+    #[test_case]
+    fn finds_the_unit_of_a_function() {
+        let mut table = [0u8; HEADER_SIZE + 12 + 40 + 16];
+        let units = &mut table[HEADER_SIZE + 12..];
+        // Lists 00:02.0 and the bus behind the bridge at 00:1c.0.
+        units[..4].copy_from_slice(&[0, 0, 40, 0]);
+        units[8..16].copy_from_slice(&0xFED9_0000u64.to_le_bytes());
+        units[16..24].copy_from_slice(&[1, 8, 0, 0, 0, 0, 2, 0]);
+        units[24..32].copy_from_slice(&[2, 8, 0, 0, 0, 0, 0x1C, 0]);
+        // Translates everything else.
+        units[40..44].copy_from_slice(&[0, 0, 16, 0]);
+        units[44] = 1;
+        units[48..56].copy_from_slice(&0xFED9_1000u64.to_le_bytes());
+
+        assert_eq!(dmar::units(&table).count(), 2);
+        assert_eq!(dmar::units(&table).next().unwrap().base, 0xFED9_0000);
+        let bridge =
+            |function: Function| (function.bus == 0 && function.device == 0x1C).then_some((3, 4));
+        let unit_of = |bus, device, function| {
+            dmar::unit_for(&table, requester_id(bus, device, function), &bridge)
+        };
+        assert_eq!(unit_of(0, 2, 0), Some(0));
+        assert_eq!(unit_of(4, 0, 0), Some(0));
+        assert_eq!(unit_of(0, 2, 1), Some(1));
+        assert_eq!(unit_of(5, 0, 0), Some(1));
+
+        // Truncated structures end the list.
+        assert_eq!(dmar::units(&table[..HEADER_SIZE + 12 + 20]).count(), 0);
+    }
+
+    #[test_case]
+    fn grants_pin_frames_until_revoked() {
+        let mut allocator = BumpAllocator::new();
+        let domain = detached(4);
+        let frame = allocator.alloc_user_frame().unwrap();
+        let iova = 0x4000_2000;
+        assert_eq!(
+            domain.grant(frame.frame(), iova, true),
+            Err(CapError::OutOfMemory)
+        );
+        let mut frames = core::iter::from_fn(|| allocator.alloc_untyped_frame());
+        let mut next_frame = || frames.next().ok_or(CapError::OutOfMemory);
+        assert_eq!(domain.extend(iova, &mut next_frame), Ok(4));
+        assert_eq!(domain.extend(iova + PAGE_SIZE, &mut next_frame), Ok(0));
+        assert_eq!(
+            domain.extend(domain.limit(), &mut next_frame),
+            Err(CapError::InvalidArgument)
+        );
+
+        assert_eq!(domain.grant(frame.frame(), iova, true), Ok(()));
+        assert!(frame.is_pinned());
+        assert_eq!(
+            domain.grant(frame.frame(), iova, false),
+            Err(CapError::ResourceInUse)
+        );
+        assert_eq!(
+            domain.grant(frame.frame(), iova + 1, false),
+            Err(CapError::InvalidArgument)
+        );
+        assert!(domain.leaf(iova).unwrap().load(Ordering::Relaxed) & WRITE != 0);
+
+        assert_eq!(domain.revoke(iova), Ok(()));
+        assert!(!frame.is_pinned());
+        assert_eq!(domain.revoke(iova), Err(CapError::InvalidArgument));
+        assert_eq!(
+            domain.revoke(iova + 0x20_0000 * 512),
+            Err(CapError::InvalidArgument)
+        );
+
+        // Dropping the domain releases whatever is still granted.
+        assert_eq!(domain.grant(frame.frame(), iova, false), Ok(()));
+        let root = RawFrame::from_start_address(PhysAddr::new(domain.root.load(Ordering::Relaxed)));
+        drop(domain);
+        assert!(!frame.is_pinned());
+        assert!(root.try_as_untyped().is_ok());
+    }
+}
@@ -0,0 +1,206 @@
+//! NUMA topology from the ACPI SRAT and SLIT.
+//!
+//! The SRAT assigns processors and memory ranges to proximity domains, which
+//! are the NUMA nodes, and the SLIT gives the relative distance between every
+//! pair of them. The kernel doesn't place anything by node itself. It only
+//! lists the nodes in the device inventory, where components that care can
+//! find the memory close to the core they run on.
+
+use crate::arch::acpi::{self, HEADER_SIZE};
+
+/// Affinity structures start after the header and 12 reserved bytes.
+const STRUCTURES: usize = HEADER_SIZE + 12;
+const LOCAL_APIC: u8 = 0;
+const MEMORY: u8 = 1;
+const LOCAL_X2APIC: u8 = 2;
+const ENABLED: u32 = 1 << 0;
+
+/// Matrix of the SLIT, after the header and the number of localities.
+const DISTANCES: usize = HEADER_SIZE + 8;
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// Something the SRAT places in a node.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Affinity {
+    Cpu { node: u32, apic_id: u32 },
+    Memory { node: u32, start: u64, length: u64 },
+}
+
+impl Affinity {
+    pub fn node(&self) -> u32 {
+        match *self {
+            Affinity::Cpu { node, .. } | Affinity::Memory { node, .. } => node,
+        }
+    }
+}
+
+/// The enabled processors and memory ranges in `srat`, in the order they
+/// are listed.
+pub fn affinities(srat: &[u8]) -> impl Iterator<Item = Affinity> + '_ {
+    let mut rest = srat.get(STRUCTURES..).unwrap_or_default();
+    core::iter::from_fn(move || loop {
+        let (&kind, &len) = (rest.first()?, rest.get(1)?);
+        let len = usize::from(len);
+        if len < 2 || len > rest.len() {
+            return None;
+        }
+        let (structure, next) = rest.split_at(len);
+        rest = next;
+        if let Some(affinity) = affinity(kind, structure) {
+            return Some(affinity);
+        }
+    })
+}
+
+/// Decodes an affinity structure, or returns `None` if it's disabled or of
+/// a kind that doesn't matter here.
+fn affinity(kind: u8, structure: &[u8]) -> Option<Affinity> {
+    match kind {
+        LOCAL_APIC if structure.len() >= 16 => {
+            // The domain is split between byte 2 and bytes 9 to 11.
+            let high = read_u32(structure, 8)? >> 8;
+            let node = high << 8 | u32::from(structure[2]);
+            (read_u32(structure, 4)? & ENABLED != 0).then_some(Affinity::Cpu {
+                node,
+                apic_id: structure[3].into(),
+            })
+        }
+        MEMORY if structure.len() >= 40 => {
+            let length = read_u64(structure, 16)?;
+            (read_u32(structure, 28)? & ENABLED != 0 && length > 0).then_some(Affinity::Memory {
+                node: read_u32(structure, 2)?,
+                start: read_u64(structure, 8)?,
+                length,
+            })
+        }
+        LOCAL_X2APIC if structure.len() >= 24 => (read_u32(structure, 12)? & ENABLED != 0)
+            .then_some(Affinity::Cpu {
+                node: read_u32(structure, 4)?,
+                apic_id: read_u32(structure, 8)?,
+            }),
+        _ => None,
+    }
+}
+
+/// Distance from node `from` to node `to` in `slit`.
+pub fn distance(slit: &[u8], from: u32, to: u32) -> Option<u8> {
+    let localities = read_u64(slit, HEADER_SIZE)?;
+    if u64::from(from) >= localities || u64::from(to) >= localities {
+        return None;
+    }
+    let index = u64::from(from)
+        .checked_mul(localities)?
+        .checked_add(to.into())?;
+    slit.get(DISTANCES.checked_add(usize::try_from(index).ok()?)?)
+        .copied()
+}
+
+/// The firmware's topology tables, if it has them.
+pub struct Tables {
+    pub srat: &'static [u8],
+    pub slit: Option<&'static [u8]>,
+}
+
+/// Finds the topology tables. Machines without an SRAT have a single node.
+pub fn tables() -> Option<Tables> {
+    Some(Tables {
+        srat: acpi::find(b"SRAT")?,
+        slit: acpi::find(b"SLIT"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An SRAT with two nodes: the first has a processor listed by local
+    /// APIC and a memory range, the second a processor listed by x2APIC, a
+    /// disabled processor and a disabled memory range.
+    fn srat() -> [u8; STRUCTURES + 16 + 40 + 24 + 16 + 40] {
+        let mut srat = [0; STRUCTURES + 16 + 40 + 24 + 16 + 40];
+        let mut at = STRUCTURES;
+        let mut push = |structure: &[u8]| {
+            srat[at..at + structure.len()].copy_from_slice(structure);
+            at += structure.len();
+        };
+        let mut apic = [0; 16];
+        apic[..4].copy_from_slice(&[LOCAL_APIC, 16, 0, 3]);
+        apic[4] = 1;
+        push(&apic);
+        let mut memory = [0; 40];
+        memory[..2].copy_from_slice(&[MEMORY, 40]);
+        memory[8..16].copy_from_slice(&0x10_0000u64.to_le_bytes());
+        memory[16..24].copy_from_slice(&0x4000_0000u64.to_le_bytes());
+        memory[28] = 1;
+        push(&memory);
+        let mut x2apic = [0; 24];
+        x2apic[..2].copy_from_slice(&[LOCAL_X2APIC, 24]);
+        x2apic[4..8].copy_from_slice(&1u32.to_le_bytes());
+        x2apic[8..12].copy_from_slice(&0x100u32.to_le_bytes());
+        x2apic[12] = 1;
+        push(&x2apic);
+        apic[3] = 4;
+        apic[4] = 0;
+        push(&apic);
+        memory[28] = 0;
+        push(&memory);
+        srat
+    }
+
+    #[test_case]
+    fn reads_enabled_affinities() {
+        let srat = srat();
+        let mut affinities = affinities(&srat);
+        assert_eq!(
+            affinities.next(),
+            Some(Affinity::Cpu {
+                node: 0,
+                apic_id: 3
+            })
+        );
+        assert_eq!(
+            affinities.next(),
+            Some(Affinity::Memory {
+                node: 0,
+                start: 0x10_0000,
+                length: 0x4000_0000
+            })
+        );
+        assert_eq!(
+            affinities.next(),
+            Some(Affinity::Cpu {
+                node: 1,
+                apic_id: 0x100
+            })
+        );
+        assert_eq!(affinities.next(), None);
+
+        // A structure that runs past the table ends the list.
+        let mut truncated = srat;
+        truncated[STRUCTURES + 1] = 200;
+        assert_eq!(super::affinities(&truncated).next(), None);
+    }
+
+    #[test_case]
+    fn reads_distances() {
+        let mut slit = [0; DISTANCES + 4];
+        slit[HEADER_SIZE..DISTANCES].copy_from_slice(&2u64.to_le_bytes());
+        slit[DISTANCES..].copy_from_slice(&[10, 21, 22, 10]);
+        assert_eq!(distance(&slit, 0, 0), Some(10));
+        assert_eq!(distance(&slit, 0, 1), Some(21));
+        assert_eq!(distance(&slit, 1, 0), Some(22));
+        assert_eq!(distance(&slit, 2, 0), None);
+        assert_eq!(distance(&slit[..DISTANCES + 2], 1, 1), None);
+    }
+}
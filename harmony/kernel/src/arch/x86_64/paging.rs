@@ -1,16 +1,16 @@
-pub const PAGE_SIZE: usize = 4096;
-pub const FRAME_SIZE: u64 = 4096;
+pub const PAGE_SIZE: usize = addr::PAGE_SIZE;
+pub const FRAME_SIZE: u64 = addr::PAGE_SIZE as u64;
 
 pub mod frames;
 pub use frames::RawFrame;
 
-pub mod pages;
-pub use pages::Page;
+pub use addr::Page;
 
 pub mod page_table;
+pub mod policy;
 
 mod physical_address;
-pub use physical_address::PhysAddr;
+pub use physical_address::{PhysAddr, PhysAddrExt};
 
 mod virtual_address;
-pub use virtual_address::VirtAddr;
+pub use virtual_address::{VirtAddr, VirtAddrExt};
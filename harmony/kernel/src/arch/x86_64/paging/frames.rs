@@ -1,38 +1,45 @@
 //! Physical memory frames for the x86-64 architecture
 
-use super::{PhysAddr, FRAME_SIZE};
+use addr::{Frame, Unaligned};
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-#[repr(transparent)]
-pub struct RawFrame {
-    base: PhysAddr,
-}
+use super::PhysAddr;
 
+/// A frame of physical memory whose type is tracked in the retype table.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub struct UnalignedAddress;
+#[repr(transparent)]
+pub struct RawFrame(Frame);
 
 impl RawFrame {
     pub fn from_start_address(base: PhysAddr) -> Self {
-        Self::try_from_start_address(base).unwrap()
+        Self(Frame::from_start_address(base))
     }
 
     pub fn base(&self) -> PhysAddr {
-        self.base
+        self.0.base()
     }
 
-    pub fn try_from_start_address(base: PhysAddr) -> Result<Self, UnalignedAddress> {
-        if base.as_u64() % FRAME_SIZE != 0 {
-            return Err(UnalignedAddress);
-        }
-        Ok(Self { base })
+    pub fn try_from_start_address(base: PhysAddr) -> Result<Self, Unaligned> {
+        Frame::try_from_start_address(base).map(Self)
     }
 
+    /// The frame that `addr` falls in.
     pub fn within_frame(addr: PhysAddr) -> Self {
-        let base = PhysAddr::new(addr.as_u64() % FRAME_SIZE);
-        Self { base }
+        Self(Frame::containing_address(addr))
     }
 
     pub fn addr(&self) -> PhysAddr {
-        self.base
+        self.0.addr()
+    }
+}
+
+impl From<Frame> for RawFrame {
+    fn from(frame: Frame) -> Self {
+        Self(frame)
+    }
+}
+
+impl From<RawFrame> for Frame {
+    fn from(frame: RawFrame) -> Self {
+        frame.0
     }
 }
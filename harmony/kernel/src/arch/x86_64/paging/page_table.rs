@@ -3,10 +3,10 @@ use core::sync::atomic::{AtomicU64, Ordering};
 use x86_64_impl::registers::control::Cr3;
 pub use x86_64_impl::structures::paging::PageTableFlags;
 
-use super::{Page, PhysAddr, RawFrame};
+use super::{Page, PhysAddr, PhysAddrExt as _, RawFrame, VirtAddrExt as _, PAGE_SIZE};
 use crate::bump_allocator::BumpAllocator;
 use crate::kptr::KPtr;
-use crate::retyping::RetypeError;
+use crate::retyping::{KernelFrame, RetypeError};
 
 #[repr(transparent)]
 pub struct Addrspace<'a>(&'a AnyPageTable);
@@ -39,8 +39,138 @@ impl<'a> Addrspace<'a> {
     }
 
     /// Recursively finds the mapping for a page to a frame.
-    pub fn get(&self, _page: Page) -> Option<(RawFrame, PageTableFlags)> {
-        todo!();
+    pub fn get(&self, page: Page) -> Option<(RawFrame, PageTableFlags)> {
+        let (entry, _level) = self.leaf(page)?;
+        entry.get()
+    }
+
+    /// Finds the entry that maps `page` and the level of the table it's in.
+    ///
+    /// The entry may map a huge page if the level is above 1.
+    pub fn leaf(&self, page: Page) -> Option<(&'a PageTableEntry, PageTableLevel)> {
+        let mut level = PageTableLevel::top();
+        let mut table = self.0;
+        let addr = page.base();
+        loop {
+            let entry = table.get(addr.page_table_index(level));
+            let (frame, flags) = entry.get()?;
+            if !flags.contains(PageTableFlags::PRESENT) {
+                return None;
+            }
+            match level.lower() {
+                Some(lower) if !flags.contains(PageTableFlags::HUGE_PAGE) => {
+                    // SAFETY: Non-leaf entries point to page tables.
+                    table = unsafe { &*frame.base().to_virtual().as_ptr() };
+                    level = lower;
+                }
+                _ => return Some((entry, level)),
+            }
+        }
+    }
+
+    /// Returns the flags userspace gets on `page` and the level of the entry
+    /// that maps it.
+    ///
+    /// A page is only user accessible or writable if every entry on the way
+    /// to it is, so the flags of the leaf entry lose those that an upper
+    /// level denies.
+    pub fn user_flags(&self, page: Page) -> Option<(PageTableFlags, PageTableLevel)> {
+        let mut level = PageTableLevel::top();
+        let mut table = self.0;
+        let mut denied = PageTableFlags::empty();
+        let addr = page.base();
+        loop {
+            let (frame, flags) = table.get(addr.page_table_index(level)).get()?;
+            if !flags.contains(PageTableFlags::PRESENT) {
+                return None;
+            }
+            match level.lower() {
+                Some(lower) if !flags.contains(PageTableFlags::HUGE_PAGE) => {
+                    denied |= (PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE)
+                        .difference(flags);
+                    // SAFETY: Non-leaf entries point to page tables.
+                    table = unsafe { &*frame.base().to_virtual().as_ptr() };
+                    level = lower;
+                }
+                _ => return Some((flags.difference(denied), level)),
+            }
+        }
+    }
+
+    /// Finds the entry for `page` in the lowest level table that exists.
+    ///
+    /// Unlike [`Addrspace::leaf`], the entry may be empty or swapped out.
+    fn entry(&self, page: Page) -> Option<(&'a PageTableEntry, PageTableLevel)> {
+        let mut level = PageTableLevel::top();
+        let mut table = self.0;
+        let addr = page.base();
+        loop {
+            let entry = table.get(addr.page_table_index(level));
+            let Some((frame, flags)) = entry.get() else {
+                return Some((entry, level));
+            };
+            match level.lower() {
+                Some(lower)
+                    if flags.contains(PageTableFlags::PRESENT)
+                        && !flags.contains(PageTableFlags::HUGE_PAGE) =>
+                {
+                    // SAFETY: Non-leaf entries point to page tables.
+                    table = unsafe { &*frame.base().to_virtual().as_ptr() };
+                    level = lower;
+                }
+                _ => return Some((entry, level)),
+            }
+        }
+    }
+
+    /// Returns the token left in place of `page` when it was evicted.
+    pub fn swap_token(&self, page: Page) -> Option<u64> {
+        let (entry, level) = self.entry(page)?;
+        if !level.is_bottom() {
+            return None;
+        }
+        entry.swap_token()
+    }
+
+    /// Unmaps `page` and leaves `token` in its entry so that faults on it can
+    /// be told apart from faults on memory that was never mapped.
+    ///
+    /// Returns the frame and flags that were mapped.
+    ///
+    /// # Safety
+    ///
+    /// The caller must flush the TLBs of every core that may be using the
+    /// address space.
+    pub unsafe fn evict(
+        &self,
+        page: Page,
+        token: u64,
+    ) -> Result<(RawFrame, PageTableFlags), EvictError> {
+        if token > PageTableEntry::MAX_SWAP_TOKEN {
+            return Err(EvictError::BadToken);
+        }
+        let (entry, level) = self.leaf(page).ok_or(EvictError::NotMapped)?;
+        if !level.is_bottom() {
+            return Err(EvictError::HugePage);
+        }
+        // SAFETY: Precondition.
+        unsafe { entry.set_swapped(token) }.ok_or(EvictError::NotMapped)
+    }
+
+    /// Maps a swapped out page back to `frame`.
+    ///
+    /// Does nothing if the page isn't swapped out.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Addrspace::map_to`].
+    pub unsafe fn restore(&self, page: Page, frame: RawFrame, flags: PageTableFlags) {
+        if let Some((entry, level)) = self.entry(page) {
+            if level.is_bottom() && entry.swap_token().is_some() {
+                // SAFETY: Precondition.
+                unsafe { entry.set(frame, flags) };
+            }
+        }
     }
 
     /// Maps a virtual page to a physical frame.
@@ -94,6 +224,66 @@ impl<'a> Addrspace<'a> {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EvictError {
+    NotMapped,
+    /// Only 4KiB pages can be evicted.
+    HugePage,
+    /// The token doesn't fit in a page table entry.
+    BadToken,
+}
+
+/// An entry removed by [`AnyPageTable::clear`].
+#[derive(Debug, Copy, Clone)]
+pub enum Cleared {
+    /// A lower level table. Tables hold a reference to their kernel frame.
+    Table(RawFrame),
+    /// A leaf mapping found at `level`.
+    Page(RawFrame, PageTableFlags, PageTableLevel),
+}
+
+impl Cleared {
+    /// Drops the reference the entry held on its frame and returns whether
+    /// it was a page.
+    ///
+    /// Only user accessible pages own their frame. The untyped memory window
+    /// and kernel objects like the info page are borrowed. With `release`,
+    /// frames that lost their last reference go back to untyped memory.
+    ///
+    /// # Safety
+    ///
+    /// The entry must have been removed by [`AnyPageTable::clear`].
+    pub unsafe fn release(self, release: bool) -> bool {
+        match self {
+            Cleared::Table(frame) => {
+                // SAFETY: Tables were leaked into their parent entry when they
+                // were mapped.
+                let refs = unsafe { KernelFrame::from_raw(frame).drop() };
+                if release && refs == 1 {
+                    let _ = frame.try_into_untyped();
+                }
+                false
+            }
+            Cleared::Page(frame, flags, _) => {
+                if flags.contains(PageTableFlags::USER_ACCESSIBLE) {
+                    // The pin went away with the mapping it was taken through.
+                    if flags.contains(PageTableEntry::PINNED) {
+                        if let Ok(user) = frame.try_as_user() {
+                            let _ = user.unpin();
+                        }
+                    }
+                    // SAFETY: The mapping owned a reference.
+                    let refs = unsafe { frame.drop_user_ref() };
+                    if release && refs == Some(0) {
+                        let _ = frame.try_into_untyped();
+                    }
+                }
+                true
+            }
+        }
+    }
+}
+
 #[repr(C, align(4096))]
 pub struct AnyPageTable([PageTableEntry; 512]);
 
@@ -148,6 +338,114 @@ impl AnyPageTable {
         new
     }
 
+    /// Calls `fun` with the address, frame, flags and level of every present leaf mapping.
+    ///
+    /// `level` is the level of this table and `base` the first address it maps.
+    /// Only the lower half of level 4 tables is walked since the upper half is
+    /// shared with the kernel.
+    pub fn for_each_mapping<F>(&self, level: PageTableLevel, base: usize, fun: &mut F)
+    where
+        F: FnMut(usize, RawFrame, PageTableFlags, PageTableLevel),
+    {
+        let span = PAGE_SIZE << (9 * (level.level() - 1));
+        let entries = if level.level() == 4 { 256 } else { 512 };
+        for (index, entry) in self.0[..entries].iter().enumerate() {
+            let Some((frame, flags)) = entry.get() else {
+                continue;
+            };
+            if !flags.contains(PageTableFlags::PRESENT) {
+                continue;
+            }
+            let addr = base + index * span;
+            match level.lower() {
+                Some(lower) if !flags.contains(PageTableFlags::HUGE_PAGE) => {
+                    // SAFETY: Non-leaf entries point to page tables.
+                    let table: &AnyPageTable = unsafe { &*frame.base().to_virtual().as_ptr() };
+                    table.for_each_mapping(lower, addr, fun);
+                }
+                _ => fun(addr, frame, flags, level),
+            }
+        }
+    }
+
+    /// Whether the table has no entries left.
+    ///
+    /// Only the lower half of level 4 tables is checked.
+    pub fn is_cleared(&self, level: PageTableLevel) -> bool {
+        let entries = if level.level() == 4 { 256 } else { 512 };
+        self.0[..entries]
+            .iter()
+            .all(|entry| entry.get().is_none() && entry.swap_token().is_none())
+    }
+
+    /// Unmaps every entry in this table and the tables below it.
+    ///
+    /// `fun` is called with every entry that was removed. A lower level table
+    /// is reported after its own entries. At most `budget` entries are
+    /// removed; if it runs out, `false` is returned and the rest are left in
+    /// place so that the walk can be repeated later. Like
+    /// [`AnyPageTable::for_each_mapping`], only the lower half of level 4
+    /// tables is touched.
+    ///
+    /// With `keep_pinned`, pages marked [`PageTableEntry::PINNED`] stay
+    /// mapped, along with the tables leading to them. Otherwise they're
+    /// removed like any other page.
+    ///
+    /// # Safety
+    ///
+    /// The caller must flush the TLBs of every core that may be using the
+    /// tables.
+    pub unsafe fn clear<F>(
+        &self,
+        level: PageTableLevel,
+        keep_pinned: bool,
+        budget: &mut usize,
+        fun: &mut F,
+    ) -> bool
+    where
+        F: FnMut(Cleared),
+    {
+        let entries = if level.level() == 4 { 256 } else { 512 };
+        for entry in &self.0[..entries] {
+            if *budget == 0 {
+                return false;
+            }
+            let Some((frame, flags)) = entry.get() else {
+                // Drop the token of swapped out pages along with the mapping.
+                if entry.swap_token().is_some() {
+                    unsafe {
+                        entry.reset();
+                    }
+                }
+                continue;
+            };
+            let removed = match level.lower() {
+                Some(lower)
+                    if flags.contains(PageTableFlags::PRESENT)
+                        && !flags.contains(PageTableFlags::HUGE_PAGE) =>
+                {
+                    // SAFETY: Non-leaf entries point to page tables.
+                    let table: &AnyPageTable = unsafe { &*frame.base().to_virtual().as_ptr() };
+                    if !unsafe { table.clear(lower, keep_pinned, budget, fun) } {
+                        return false;
+                    }
+                    if !table.is_cleared(lower) {
+                        continue;
+                    }
+                    Cleared::Table(frame)
+                }
+                _ if keep_pinned && flags.contains(PageTableEntry::PINNED) => continue,
+                _ => Cleared::Page(frame, flags, level),
+            };
+            unsafe {
+                entry.reset();
+            }
+            *budget = budget.saturating_sub(1);
+            fun(removed);
+        }
+        true
+    }
+
     pub fn get(&self, offset: PageTableOffset) -> &PageTableEntry {
         // SAFETY: Offset is within [0, 512)
         unsafe { self.0.get_unchecked(offset.0 as usize) }
@@ -210,14 +508,23 @@ impl Default for PageTableEntry {
 impl PageTableEntry {
     const FRAME_MASK: u64 = 0x000F_FFFF_FFFF_F000;
     const FLAGS_MASK: u64 = !Self::FRAME_MASK;
+    /// Marks a non-present entry whose address bits hold a swap token.
+    const SWAPPED: u64 = PageTableFlags::BIT_9.bits();
+    /// Largest token that fits in a swapped out entry.
+    pub const MAX_SWAP_TOKEN: u64 = Self::FRAME_MASK >> 12;
+    /// Marks a present user page whose frame was pinned through it. The
+    /// mapping owns that pin.
+    pub const PINNED: PageTableFlags = PageTableFlags::BIT_10;
 
     pub const fn new() -> Self {
         Self(AtomicU64::new(0))
     }
 
-    pub fn get(&self) -> Option<(RawFrame, PageTableFlags)> {
-        let value = self.0.load(Ordering::Relaxed);
-        if value == 0 {
+    /// Splits a raw entry into its frame and flags.
+    ///
+    /// Empty and swapped out entries don't reference a frame.
+    fn decode(value: u64) -> Option<(RawFrame, PageTableFlags)> {
+        if value == 0 || Self::is_swapped(value) {
             return None;
         }
         let frame = RawFrame::from_start_address(PhysAddr::new(value & Self::FRAME_MASK));
@@ -225,6 +532,20 @@ impl PageTableEntry {
         Some((frame, flags))
     }
 
+    fn is_swapped(value: u64) -> bool {
+        value & PageTableFlags::PRESENT.bits() == 0 && value & Self::SWAPPED != 0
+    }
+
+    pub fn get(&self) -> Option<(RawFrame, PageTableFlags)> {
+        Self::decode(self.0.load(Ordering::Relaxed))
+    }
+
+    /// Returns the token stored in the entry if it was swapped out.
+    pub fn swap_token(&self) -> Option<u64> {
+        let value = self.0.load(Ordering::Relaxed);
+        Self::is_swapped(value).then_some((value & Self::FRAME_MASK) >> 12)
+    }
+
     pub fn frame(&self) -> Option<RawFrame> {
         self.get().map(|x| x.0)
     }
@@ -234,17 +555,41 @@ impl PageTableEntry {
     }
 
     unsafe fn set_bits(&self, bits: u64) -> Option<(RawFrame, PageTableFlags)> {
-        let old = self.0.swap(bits, Ordering::Relaxed);
+        Self::decode(self.0.swap(bits, Ordering::Relaxed))
+    }
 
-        if old == 0 {
-            return None;
-        }
-        let addr = old & Self::FRAME_MASK;
-        let attributes = PageTableFlags::from_bits(old & Self::FLAGS_MASK).unwrap();
-        Some((
-            RawFrame::from_start_address(PhysAddr::new(addr)),
-            attributes,
-        ))
+    /// Atomically replaces a present entry with a swapped out one holding `token`.
+    ///
+    /// Returns the frame and flags that were mapped, or `None` if the entry
+    /// wasn't present, in which case it's left unchanged.
+    ///
+    /// # Safety
+    ///
+    /// This could fundamentally change memory, leading to unsoundness.
+    pub unsafe fn set_swapped(&self, token: u64) -> Option<(RawFrame, PageTableFlags)> {
+        debug_assert!(token <= Self::MAX_SWAP_TOKEN);
+        let swapped = (token << 12) | Self::SWAPPED;
+        let old = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+                (value & PageTableFlags::PRESENT.bits() != 0).then_some(swapped)
+            })
+            .ok()?;
+        Self::decode(old)
+    }
+
+    /// Atomically sets or clears [`PageTableEntry::PINNED`] on a present entry.
+    ///
+    /// Returns `false`, leaving the entry unchanged, if it isn't present or
+    /// the bit is already in the requested state.
+    pub fn set_pinned(&self, pinned: bool) -> bool {
+        self.0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+                let present = value & PageTableFlags::PRESENT.bits() != 0;
+                let marked = value & Self::PINNED.bits() != 0;
+                (present && marked != pinned).then_some(value ^ Self::PINNED.bits())
+            })
+            .is_ok()
     }
 
     /// Atomically sets this entry to the frame and the attributes
@@ -368,3 +713,167 @@ impl PageTableOffset {
         Self(addr % 512)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::paging::VirtAddr;
+
+    #[test_case]
+    fn walk_mappings() {
+        let mut allocator = BumpAllocator::new();
+        let l4 = AnyPageTable::new_l4(allocator.alloc_untyped_frame().unwrap()).unwrap();
+        let frame = allocator.alloc_user_frame().unwrap().into_raw();
+        let page = Page::from_start_address(VirtAddr::new(0x4020_1000));
+        // SAFETY: The address space is never loaded.
+        unsafe {
+            l4.as_addrspace()
+                .map_to(
+                    page,
+                    frame,
+                    PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
+                    PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
+                    &mut allocator,
+                )
+                .unwrap();
+        }
+        let mut found = 0;
+        l4.for_each_mapping(
+            PageTableLevel::top(),
+            0,
+            &mut |addr, mapped, flags, level| {
+                assert_eq!(addr, 0x4020_1000);
+                assert_eq!(mapped.base(), frame.base());
+                assert!(flags.contains(PageTableFlags::USER_ACCESSIBLE));
+                assert!(level.is_bottom());
+                found += 1;
+            },
+        );
+        assert_eq!(found, 1);
+    }
+
+    #[test_case]
+    fn clear_tree() {
+        let mut allocator = BumpAllocator::new();
+        let l4 = AnyPageTable::new_l4(allocator.alloc_untyped_frame().unwrap()).unwrap();
+        for addr in [0x4020_1000, 0x4020_2000] {
+            let frame = allocator.alloc_user_frame().unwrap().into_raw();
+            // SAFETY: The address space is never loaded.
+            unsafe {
+                l4.as_addrspace()
+                    .map_to(
+                        Page::from_start_address(VirtAddr::new(addr)),
+                        frame,
+                        PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
+                        PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
+                        &mut allocator,
+                    )
+                    .unwrap();
+            }
+        }
+
+        // Runs out after the first page.
+        let (mut pages, mut tables) = (0, 0);
+        let mut count = |cleared| match cleared {
+            Cleared::Page(..) => pages += 1,
+            Cleared::Table(_) => tables += 1,
+        };
+        let mut budget = 1;
+        // SAFETY: The address space is never loaded.
+        assert!(!unsafe { l4.clear(PageTableLevel::top(), false, &mut budget, &mut count) });
+        let mut budget = usize::MAX;
+        // SAFETY: The address space is never loaded.
+        assert!(unsafe { l4.clear(PageTableLevel::top(), false, &mut budget, &mut count) });
+        assert_eq!((pages, tables), (2, 3));
+
+        let mut remaining = 0;
+        l4.for_each_mapping(PageTableLevel::top(), 0, &mut |_, _, _, _| remaining += 1);
+        assert_eq!(remaining, 0);
+    }
+
+    #[test_case]
+    fn clear_can_keep_pinned_pages() {
+        let mut allocator = BumpAllocator::new();
+        let l4 = AnyPageTable::new_l4(allocator.alloc_untyped_frame().unwrap()).unwrap();
+        let pages =
+            [0x4020_1000, 0x8020_1000].map(|addr| Page::from_start_address(VirtAddr::new(addr)));
+        for page in pages {
+            let frame = allocator.alloc_user_frame().unwrap().into_raw();
+            // SAFETY: The address space is never loaded.
+            unsafe {
+                l4.as_addrspace()
+                    .map_to(
+                        page,
+                        frame,
+                        PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
+                        PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
+                        &mut allocator,
+                    )
+                    .unwrap();
+            }
+        }
+        // SAFETY: The address space is never loaded.
+        let (entry, _) = unsafe { l4.as_addrspace() }.leaf(pages[0]).unwrap();
+        assert!(entry.set_pinned(true));
+        assert!(!entry.set_pinned(true));
+
+        let mut budget = usize::MAX;
+        let mut cleared = 0;
+        // SAFETY: The address space is never loaded.
+        assert!(unsafe {
+            l4.clear(PageTableLevel::top(), true, &mut budget, &mut |_| {
+                cleared += 1
+            })
+        });
+        // The other page and its level 1 and 2 tables are gone.
+        assert_eq!(cleared, 3);
+        assert!(!l4.is_cleared(PageTableLevel::top()));
+        // SAFETY: The address space is never loaded.
+        assert!(unsafe { l4.as_addrspace() }.get(pages[0]).is_some());
+
+        assert!(entry.set_pinned(false));
+        // SAFETY: The address space is never loaded.
+        assert!(unsafe { l4.clear(PageTableLevel::top(), true, &mut budget, &mut |_| {}) });
+        assert!(l4.is_cleared(PageTableLevel::top()));
+    }
+
+    #[test_case]
+    fn evict_leaves_a_token() {
+        let mut allocator = BumpAllocator::new();
+        let l4 = AnyPageTable::new_l4(allocator.alloc_untyped_frame().unwrap()).unwrap();
+        let frame = allocator.alloc_user_frame().unwrap().into_raw();
+        let page = Page::from_start_address(VirtAddr::new(0x4020_1000));
+        // SAFETY: The address space is never loaded.
+        unsafe {
+            let addrspace = l4.as_addrspace();
+            addrspace
+                .map_to(
+                    page,
+                    frame,
+                    PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
+                    PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
+                    &mut allocator,
+                )
+                .unwrap();
+            assert_eq!(addrspace.swap_token(page), None);
+            assert_eq!(
+                addrspace.evict(page, PageTableEntry::MAX_SWAP_TOKEN + 1),
+                Err(EvictError::BadToken)
+            );
+            let (evicted, _flags) = addrspace.evict(page, 0x1234).unwrap();
+            assert_eq!(evicted, frame);
+            assert!(addrspace.get(page).is_none());
+            assert_eq!(addrspace.swap_token(page), Some(0x1234));
+            assert_eq!(addrspace.evict(page, 1), Err(EvictError::NotMapped));
+        }
+
+        let mut found = 0;
+        l4.for_each_mapping(PageTableLevel::top(), 0, &mut |_, _, _, _| found += 1);
+        assert_eq!(found, 0);
+        let mut budget = usize::MAX;
+        // SAFETY: The address space is never loaded.
+        assert!(unsafe { l4.clear(PageTableLevel::top(), false, &mut budget, &mut |_| {}) });
+        // SAFETY: The address space is never loaded.
+        assert_eq!(unsafe { l4.as_addrspace() }.swap_token(page), None);
+    }
+}
@@ -0,0 +1,137 @@
+//! Checks on the mappings the kernel makes for components.
+//!
+//! Components choose the permissions of the pages they're loaded into, so the
+//! kernel checks them before mapping anything. No page may be writable and
+//! executable at once unless the composer allowed the component to generate
+//! code, and every page has to be in the canonical lower half of the address
+//! space.
+//!
+//! FIXME: Nothing grants [`MappingPolicy::jit`] yet. The kernel only loads the
+//! boot component, always with [`MappingPolicy::STRICT`], and there's no way
+//! for the composer to pass a policy for the components it starts.
+
+use super::page_table::PageTableFlags;
+
+/// First address past the lower half of the address space.
+pub const USER_END: u64 = 0x0000_8000_0000_0000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PolicyError {
+    /// The mapping would be writable and executable.
+    WriteExecute,
+    /// The mapping isn't entirely within the canonical lower half.
+    NotUser,
+}
+
+/// What a component asks to do with a mapping.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Permissions {
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Permissions {
+    /// Flags of a user page with these permissions.
+    pub fn into_flags(self) -> PageTableFlags {
+        let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        if self.write {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if !self.execute {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        flags
+    }
+}
+
+/// What the mappings of a component may look like.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct MappingPolicy {
+    /// Whether pages may be writable and executable at once, for components
+    /// that generate code. Only tests set it for now.
+    pub jit: bool,
+}
+
+impl MappingPolicy {
+    pub const STRICT: Self = Self { jit: false };
+
+    /// Checks that the `len` bytes at `start` may be mapped with
+    /// `permissions` and returns the flags to map them with.
+    pub fn check(
+        &self,
+        start: u64,
+        len: u64,
+        permissions: Permissions,
+    ) -> Result<PageTableFlags, PolicyError> {
+        if permissions.write && permissions.execute && !self.jit {
+            return Err(PolicyError::WriteExecute);
+        }
+        let end = start.checked_add(len).ok_or(PolicyError::NotUser)?;
+        if end > USER_END {
+            return Err(PolicyError::NotUser);
+        }
+        Ok(permissions.into_flags())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RWX: Permissions = Permissions {
+        write: true,
+        execute: true,
+    };
+
+    #[test_case]
+    fn rejects_writable_executable_pages() {
+        let policy = MappingPolicy::STRICT;
+        assert_eq!(
+            policy.check(0x40_0000, 0x1000, RWX),
+            Err(PolicyError::WriteExecute)
+        );
+        for (write, execute) in [(false, false), (true, false), (false, true)] {
+            let permissions = Permissions { write, execute };
+            let flags = policy.check(0x40_0000, 0x1000, permissions).unwrap();
+            assert!(flags.contains(PageTableFlags::USER_ACCESSIBLE));
+            assert_eq!(flags.contains(PageTableFlags::WRITABLE), write);
+            assert_eq!(flags.contains(PageTableFlags::NO_EXECUTE), !execute);
+        }
+    }
+
+    #[test_case]
+    fn allows_writable_executable_pages_for_jit() {
+        let flags = MappingPolicy { jit: true }
+            .check(0x40_0000, 0x1000, RWX)
+            .unwrap();
+        assert!(flags.contains(PageTableFlags::WRITABLE));
+        assert!(!flags.contains(PageTableFlags::NO_EXECUTE));
+    }
+
+    #[test_case]
+    fn rejects_addresses_outside_the_lower_half() {
+        let policy = MappingPolicy { jit: true };
+        let read = Permissions::default();
+        assert!(policy.check(USER_END - 0x1000, 0x1000, read).is_ok());
+        // Crosses into the non-canonical hole.
+        assert_eq!(
+            policy.check(USER_END - 0x1000, 0x2000, read),
+            Err(PolicyError::NotUser)
+        );
+        // Non-canonical.
+        assert_eq!(
+            policy.check(USER_END, 0x1000, read),
+            Err(PolicyError::NotUser)
+        );
+        // The kernel's half.
+        assert_eq!(
+            policy.check(0xFFFF_8000_0000_0000, 0x1000, read),
+            Err(PolicyError::NotUser)
+        );
+        // Wraps around.
+        assert_eq!(
+            policy.check(0x1000, u64::MAX, read),
+            Err(PolicyError::NotUser)
+        );
+    }
+}
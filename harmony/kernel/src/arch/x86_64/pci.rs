@@ -0,0 +1,228 @@
+//! PCI enumeration through configuration mechanism #1.
+
+use x86_64_impl::instructions::port::Port;
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+const COMMAND_IO: u16 = 1 << 0;
+const COMMAND_MEMORY: u16 = 1 << 1;
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+/// The capabilities list bit of the status register.
+const STATUS_CAPABILITIES: u32 = 1 << 20;
+
+/// Location of a function on the PCI bus.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+/// A base address register.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        base: u64,
+        size: u64,
+        prefetchable: bool,
+    },
+    Io {
+        base: u16,
+        size: u16,
+    },
+}
+
+impl Function {
+    fn address(&self, offset: u8) -> u32 {
+        0x8000_0000
+            | u32::from(self.bus) << 16
+            | u32::from(self.device) << 11
+            | u32::from(self.function) << 8
+            | u32::from(offset & 0xFC)
+    }
+
+    /// Reads the configuration register holding `offset`, which is rounded
+    /// down to a multiple of 4.
+    pub fn read(&self, offset: u8) -> u32 {
+        // SAFETY: The configuration ports only access PCI configuration space.
+        unsafe {
+            Port::<u32>::new(CONFIG_ADDRESS).write(self.address(offset));
+            Port::<u32>::new(CONFIG_DATA).read()
+        }
+    }
+
+    fn write(&self, offset: u8, value: u32) {
+        // SAFETY: The configuration ports only access PCI configuration space.
+        unsafe {
+            Port::<u32>::new(CONFIG_ADDRESS).write(self.address(offset));
+            Port::<u32>::new(CONFIG_DATA).write(value);
+        }
+    }
+
+    pub fn vendor(&self) -> u16 {
+        self.read(0x00) as u16
+    }
+
+    pub fn device_id(&self) -> u16 {
+        (self.read(0x00) >> 16) as u16
+    }
+
+    /// Class code, subclass and programming interface as `0xCCSSPP`.
+    pub fn class(&self) -> u32 {
+        self.read(0x08) >> 8
+    }
+
+    fn header_type(&self) -> u8 {
+        (self.read(0x0C) >> 16) as u8
+    }
+
+    /// Legacy interrupt line routed by the firmware, if any.
+    pub fn irq_line(&self) -> Option<u8> {
+        match self.read(0x3C) as u8 {
+            0 | 0xFF => None,
+            line => Some(line),
+        }
+    }
+
+    /// The secondary and subordinate bus numbers of a PCI-to-PCI bridge.
+    pub fn bridge_buses(&self) -> Option<(u8, u8)> {
+        if self.header_type() & 0x7F != 1 {
+            return None;
+        }
+        let buses = self.read(0x18);
+        Some(((buses >> 8) as u8, (buses >> 16) as u8))
+    }
+
+    /// Calls `fun` with the id and configuration offset of every capability
+    /// of the function.
+    pub fn capabilities(&self, mut fun: impl FnMut(u8, u8)) {
+        if self.read(0x04) & STATUS_CAPABILITIES == 0 {
+            return;
+        }
+        let mut offset = self.read(0x34) as u8 & 0xFC;
+        // Bounds the walk in case the list loops.
+        for _ in 0..48 {
+            if offset == 0 {
+                return;
+            }
+            let header = self.read(offset);
+            fun(header as u8, offset);
+            offset = (header >> 8) as u8 & 0xFC;
+        }
+    }
+
+    /// Address of the memory BAR `index` without sizing it, or `None` if
+    /// it's an I/O BAR.
+    pub fn memory_bar(&self, index: u8) -> Option<u64> {
+        let offset = 0x10 + 4 * index;
+        let low = self.read(offset);
+        if low & 1 == 1 {
+            return None;
+        }
+        let mut base = u64::from(low & 0xFFFF_FFF0);
+        if (low >> 1) & 0b11 == 0b10 {
+            base |= u64::from(self.read(offset + 4)) << 32;
+        }
+        Some(base)
+    }
+
+    /// Lets the function decode its memory BARs and access memory itself.
+    pub fn enable_bus_master(&self) {
+        let command = self.read(0x04) & 0xFFFF;
+        self.write(
+            0x04,
+            command | u32::from(COMMAND_MEMORY | COMMAND_BUS_MASTER),
+        );
+    }
+
+    /// Probes the base address registers of the function.
+    ///
+    /// Decoding is disabled while the BARs are sized so that the device doesn't
+    /// respond at the temporary all-ones address.
+    pub fn bars(&self, mut fun: impl FnMut(Bar)) {
+        let count = match self.header_type() & 0x7F {
+            0 => 6,
+            1 => 2,
+            _ => return,
+        };
+        let command = self.read(0x04);
+        self.write(0x04, command & !u32::from(COMMAND_IO | COMMAND_MEMORY));
+
+        let mut index = 0;
+        while index < count {
+            let offset = 0x10 + 4 * index;
+            let original = self.read(offset);
+            self.write(offset, 0xFFFF_FFFF);
+            let mask = self.read(offset);
+            self.write(offset, original);
+            index += 1;
+
+            if original & 1 == 1 {
+                let size = !(mask & 0xFFFF_FFFC) as u16 + 1;
+                if mask != 0 && size != 0 {
+                    fun(Bar::Io {
+                        base: (original & 0xFFFC) as u16,
+                        size,
+                    });
+                }
+                continue;
+            }
+
+            let prefetchable = original & (1 << 3) != 0;
+            let mut base = u64::from(original & 0xFFFF_FFF0);
+            let mut mask = u64::from(mask & 0xFFFF_FFF0) | 0xFFFF_FFFF_0000_0000;
+            if (original >> 1) & 0b11 == 0b10 && index < count {
+                // 64-bit BAR, the upper half lives in the next register.
+                let offset = 0x10 + 4 * index;
+                let high = self.read(offset);
+                self.write(offset, 0xFFFF_FFFF);
+                let high_mask = self.read(offset);
+                self.write(offset, high);
+                index += 1;
+                base |= u64::from(high) << 32;
+                mask = (mask & 0xFFFF_FFFF) | u64::from(high_mask) << 32;
+            }
+            if mask & 0xFFFF_FFF0 != 0 {
+                fun(Bar::Memory {
+                    base,
+                    size: !mask + 1,
+                    prefetchable,
+                });
+            }
+        }
+
+        self.write(0x04, command);
+    }
+}
+
+/// Calls `fun` for every function present on the PCI buses.
+pub fn enumerate(mut fun: impl FnMut(Function)) {
+    for bus in 0..=255 {
+        for device in 0..32 {
+            let first = Function {
+                bus,
+                device,
+                function: 0,
+            };
+            if first.vendor() == 0xFFFF {
+                continue;
+            }
+            let functions = if first.header_type() & 0x80 != 0 {
+                8
+            } else {
+                1
+            };
+            for function in 0..functions {
+                let func = Function {
+                    bus,
+                    device,
+                    function,
+                };
+                if func.vendor() != 0xFFFF {
+                    fun(func);
+                }
+            }
+        }
+    }
+}
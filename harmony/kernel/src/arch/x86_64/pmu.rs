@@ -0,0 +1,212 @@
+//! Architectural performance counters.
+//!
+//! Components program the general purpose counters through the performance
+//! counter capability. The kernel builds every value it writes to the counter
+//! MSRs itself, from an event and a mode, and only ever touches the MSRs of
+//! the counters the CPU reports, so a component can't use the capability to
+//! write arbitrary MSRs.
+
+use core::arch::x86_64::__cpuid;
+
+use kapi::ops::perf::{PerfEvent, PerfMode, MAX_PERIOD};
+
+use super::registers::{rdmsr, wrmsr};
+
+const IA32_PMC0: u32 = 0xC1;
+const IA32_PERFEVTSEL0: u32 = 0x186;
+const IA32_PERF_GLOBAL_STATUS: u32 = 0x38E;
+const IA32_PERF_GLOBAL_CTRL: u32 = 0x38F;
+const IA32_PERF_GLOBAL_OVF_CTRL: u32 = 0x390;
+
+const EVTSEL_USR: u64 = 1 << 16;
+const EVTSEL_OS: u64 = 1 << 17;
+const EVTSEL_EN: u64 = 1 << 22;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PmuError {
+    /// The CPU doesn't have the counter.
+    NoCounter,
+    /// The CPU can't count the event.
+    Unsupported,
+}
+
+/// What CPUID reports about the performance counters.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Pmu {
+    version: u8,
+    counters: u8,
+    /// Bit `n` is set if architectural event `n` isn't available.
+    unavailable: u32,
+}
+
+impl Pmu {
+    /// Reads the counters of the current core.
+    pub fn current() -> Self {
+        // SAFETY: Leaf 0 is always available.
+        let max_leaf = unsafe { __cpuid(0) }.eax;
+        if max_leaf < 0xA {
+            return Self {
+                version: 0,
+                counters: 0,
+                unavailable: u32::MAX,
+            };
+        }
+        // SAFETY: Checked that the leaf is supported.
+        let leaf = unsafe { __cpuid(0xA) };
+        let version = leaf.eax as u8;
+        let events = (leaf.eax >> 24) as u8;
+        Self {
+            version,
+            counters: if version == 0 {
+                0
+            } else {
+                (leaf.eax >> 8) as u8
+            },
+            // Events past the length of the bit vector aren't available either.
+            unavailable: leaf.ebx | u32::MAX.checked_shl(events.into()).unwrap_or(0),
+        }
+    }
+
+    /// Number of general purpose counters.
+    pub fn counters(&self) -> u8 {
+        self.counters
+    }
+
+    /// Builds the event select value that counts `event` in `mode`.
+    fn event_select(&self, event: PerfEvent, mode: PerfMode) -> Result<u64, PmuError> {
+        // Bit in the CPUID availability vector, event select and unit mask.
+        let (bit, select, umask) = match event {
+            PerfEvent::Cycles => (0, 0x3C, 0x00),
+            PerfEvent::Instructions => (1, 0xC0, 0x00),
+            PerfEvent::CacheReferences => (3, 0x2E, 0x4F),
+            PerfEvent::CacheMisses => (4, 0x2E, 0x41),
+            PerfEvent::Branches => (5, 0xC4, 0x00),
+            PerfEvent::BranchMisses => (6, 0xC5, 0x00),
+        };
+        if self.unavailable & (1 << bit) != 0 {
+            return Err(PmuError::Unsupported);
+        }
+        let mode = match mode {
+            PerfMode::User => EVTSEL_USR,
+            PerfMode::Kernel => EVTSEL_OS,
+            PerfMode::All => EVTSEL_USR | EVTSEL_OS,
+        };
+        Ok(select | umask << 8 | mode | EVTSEL_EN)
+    }
+
+    fn check(&self, counter: u8) -> Result<u32, PmuError> {
+        if counter < self.counters {
+            Ok(counter.into())
+        } else {
+            Err(PmuError::NoCounter)
+        }
+    }
+
+    /// Resets `counter` and starts counting `event`, overflowing after
+    /// `period` events if there's one.
+    pub fn program(
+        &self,
+        counter: u8,
+        event: PerfEvent,
+        mode: PerfMode,
+        period: Option<u32>,
+    ) -> Result<(), PmuError> {
+        let index = self.check(counter)?;
+        let select = self.event_select(event, mode)?;
+        // Writes to the counter are sign extended from 32 bits, so a negative
+        // start overflows after `period` events regardless of its width.
+        let start = period.map_or(0, |period| {
+            debug_assert!(period <= MAX_PERIOD);
+            (-i64::from(period)) as u64 & u64::from(u32::MAX)
+        });
+        // FIXME: Raise an interrupt on overflow and deliver it as a
+        // notification once the local APIC is set up.
+        // SAFETY: The counter exists and the select value only counts an
+        // architectural event.
+        unsafe {
+            wrmsr(IA32_PERFEVTSEL0 + index, 0);
+            wrmsr(IA32_PMC0 + index, start);
+            wrmsr(IA32_PERFEVTSEL0 + index, select);
+            if self.version >= 2 {
+                let enabled = rdmsr(IA32_PERF_GLOBAL_CTRL);
+                wrmsr(IA32_PERF_GLOBAL_CTRL, enabled | 1 << index);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read(&self, counter: u8) -> Result<u64, PmuError> {
+        let index = self.check(counter)?;
+        // SAFETY: The counter exists.
+        Ok(unsafe { rdmsr(IA32_PMC0 + index) })
+    }
+
+    pub fn stop(&self, counter: u8) -> Result<(), PmuError> {
+        let index = self.check(counter)?;
+        // SAFETY: The counter exists and disabling it has no other effects.
+        unsafe { wrmsr(IA32_PERFEVTSEL0 + index, 0) };
+        Ok(())
+    }
+
+    /// Returns and clears the mask of counters that overflowed.
+    ///
+    /// Always 0 on CPUs without the global overflow status.
+    pub fn take_overflows(&self) -> u64 {
+        if self.version < 2 {
+            return 0;
+        }
+        let counters = u64::MAX
+            .checked_shr(64 - u32::from(self.counters))
+            .unwrap_or(0);
+        // SAFETY: The global status MSRs exist from version 2 on, and only the
+        // bits of the general purpose counters are cleared.
+        unsafe {
+            let overflowed = rdmsr(IA32_PERF_GLOBAL_STATUS) & counters;
+            wrmsr(IA32_PERF_GLOBAL_OVF_CTRL, overflowed);
+            overflowed
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn builds_event_selects() {
+        let pmu = Pmu {
+            version: 2,
+            counters: 4,
+            unavailable: 1 << 4,
+        };
+        assert_eq!(
+            pmu.event_select(PerfEvent::Instructions, PerfMode::User),
+            Ok(0xC0 | EVTSEL_USR | EVTSEL_EN)
+        );
+        assert_eq!(
+            pmu.event_select(PerfEvent::CacheReferences, PerfMode::All),
+            Ok(0x4F2E | EVTSEL_USR | EVTSEL_OS | EVTSEL_EN)
+        );
+        assert_eq!(
+            pmu.event_select(PerfEvent::CacheMisses, PerfMode::User),
+            Err(PmuError::Unsupported)
+        );
+        assert_eq!(pmu.check(3), Ok(3));
+        assert_eq!(pmu.check(4), Err(PmuError::NoCounter));
+    }
+
+    #[test_case]
+    fn missing_counters_are_refused() {
+        let pmu = Pmu {
+            version: 0,
+            counters: 0,
+            unavailable: u32::MAX,
+        };
+        assert_eq!(pmu.read(0), Err(PmuError::NoCounter));
+        assert_eq!(
+            pmu.program(0, PerfEvent::Cycles, PerfMode::User, None),
+            Err(PmuError::NoCounter)
+        );
+        assert_eq!(pmu.take_overflows(), 0);
+    }
+}
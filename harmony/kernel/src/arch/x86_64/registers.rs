@@ -12,3 +12,41 @@ pub fn rflags() -> u64 {
     }
     rflags
 }
+
+/// Writes `value` into the model specific register `msr`.
+///
+/// # Safety
+///
+/// Writing to an MSR may arbitrarily change the behaviour of the processor.
+pub unsafe fn wrmsr(msr: u32, value: u64) {
+    // SAFETY: Precondition.
+    unsafe {
+        asm!(
+            "wrmsr",
+            in("ecx") msr,
+            in("eax") value as u32,
+            in("edx") (value >> 32) as u32,
+            options(nostack, nomem),
+        );
+    }
+}
+
+/// Reads the model specific register `msr`.
+///
+/// # Safety
+///
+/// The MSR must exist, and reading some MSRs has side effects.
+pub unsafe fn rdmsr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    // SAFETY: Precondition.
+    unsafe {
+        asm!(
+            "rdmsr",
+            in("ecx") msr,
+            out("eax") low,
+            out("edx") high,
+            options(nostack, nomem),
+        );
+    }
+    u64::from(high) << 32 | u64::from(low)
+}
@@ -0,0 +1,184 @@
+//! Memory protection of the kernel image.
+//!
+//! The bootloader may map the kernel with looser permissions than its
+//! sections need. After initialization, [`protect_kernel`] remaps `.text` as
+//! read + execute, `.rodata` as read only and the data sections as read +
+//! write without execute. [`check_wx`] then verifies that no page in the
+//! active address space is both writable and executable.
+
+use core::ptr::addr_of;
+
+use x86_64_impl::registers::control::{Cr0, Cr0Flags};
+
+use super::instructions;
+use super::paging::page_table::{AnyPageTable, PageTableFlags, PageTableLevel};
+use super::paging::{Page, PhysAddrExt as _, VirtAddr, PAGE_SIZE};
+use crate::boot::{self, BootProtocol as _};
+
+/// The address the kernel is linked at. See `linker.ld`.
+pub const LINK_ADDRESS: u64 = 0xffff_ffff_8000_0000;
+
+extern "C" {
+    static __text_start: u8;
+    static __text_end: u8;
+    static __rodata_start: u8;
+    static __rodata_end: u8;
+    static __data_start: u8;
+    static __kernel_end: u8;
+}
+
+/// Size of the kernel image once loaded, in bytes.
+pub fn image_size() -> usize {
+    // SAFETY: The symbols are defined by the linker script, only their
+    // addresses are used.
+    unsafe { addr_of!(__kernel_end) as usize - addr_of!(__text_start) as usize }
+}
+
+/// Logs where the bootloader placed the kernel image.
+pub fn log_kernel_address() {
+    let Some(address) = boot::protocol().kernel_address() else {
+        log::warn!("Bootloader didn't report the kernel address");
+        return;
+    };
+    log::info!(
+        "Kernel loaded at {:#X} (physical {:#X})",
+        address.virtual_base,
+        address.physical_base
+    );
+    if address.virtual_base == LINK_ADDRESS {
+        log::warn!("Kernel image isn't randomized");
+    }
+}
+
+/// Page-aligned ranges of the kernel image and the flags they should have.
+fn sections() -> [(&'static str, usize, usize, PageTableFlags); 3] {
+    fn page_range(start: usize, end: usize) -> (usize, usize) {
+        (start & !(PAGE_SIZE - 1), end.next_multiple_of(PAGE_SIZE))
+    }
+    // SAFETY: The symbols are defined by the linker script, only their
+    // addresses are used.
+    let (text, rodata, data) = unsafe {
+        (
+            page_range(
+                addr_of!(__text_start) as usize,
+                addr_of!(__text_end) as usize,
+            ),
+            page_range(
+                addr_of!(__rodata_start) as usize,
+                addr_of!(__rodata_end) as usize,
+            ),
+            page_range(
+                addr_of!(__data_start) as usize,
+                addr_of!(__kernel_end) as usize,
+            ),
+        )
+    };
+    [
+        (".text", text.0, text.1, PageTableFlags::empty()),
+        (".rodata", rodata.0, rodata.1, PageTableFlags::NO_EXECUTE),
+        (
+            ".data",
+            data.0,
+            data.1,
+            PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+        ),
+    ]
+}
+
+/// Remaps the kernel sections with the minimum permissions they need.
+pub fn protect_kernel() {
+    // Make read only pages read only for the kernel too.
+    // SAFETY: The kernel doesn't write to its read-only sections.
+    unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT)) };
+
+    let table = AnyPageTable::current();
+    // SAFETY: The current page table is a root table.
+    let addrspace = unsafe { table.as_addrspace() };
+    for (name, start, end, permissions) in sections() {
+        for addr in (start..end).step_by(PAGE_SIZE) {
+            let page = Page::from_start_address(VirtAddr::new(addr));
+            let Some((entry, level)) = addrspace.leaf(page) else {
+                log::warn!("{name} page at {addr:#X} isn't mapped");
+                continue;
+            };
+            if !level.is_bottom() {
+                log::warn!("{name} page at {addr:#X} is part of a huge page");
+                continue;
+            }
+            let flags = entry.flags().unwrap();
+            let flags =
+                (flags - PageTableFlags::WRITABLE - PageTableFlags::NO_EXECUTE) | permissions;
+            // SAFETY: The permissions match how the section is used.
+            unsafe {
+                entry.set_flags(flags);
+            }
+            instructions::invlpg(addr);
+        }
+        log::debug!("Protected {name} at {start:#X}..{end:#X}");
+    }
+}
+
+/// Logs every page in the active address space that is both writable and
+/// executable and returns how many there are.
+pub fn check_wx() -> usize {
+    let table = AnyPageTable::current();
+    let mut violations = 0;
+    walk(
+        &table,
+        PageTableLevel::top(),
+        0,
+        PageTableFlags::WRITABLE,
+        &mut |addr, flags, level| {
+            if flags.contains(PageTableFlags::WRITABLE)
+                && !flags.contains(PageTableFlags::NO_EXECUTE)
+            {
+                log::warn!(
+                    "W^X violation at {addr:#X} (level {}): {flags:?}",
+                    level.level()
+                );
+                violations += 1;
+            }
+        },
+    );
+    if violations == 0 {
+        log::info!("No writable and executable pages found");
+    }
+    violations
+}
+
+/// Calls `fun` with the address, effective flags and level of every leaf mapping.
+///
+/// Pages are only writable if every level allows it and executable unless
+/// any level forbids it, so the flags passed are accumulated along the walk.
+fn walk<F>(
+    table: &AnyPageTable,
+    level: PageTableLevel,
+    base: usize,
+    parent: PageTableFlags,
+    fun: &mut F,
+) where
+    F: FnMut(usize, PageTableFlags, PageTableLevel),
+{
+    let span = PAGE_SIZE << (9 * (level.level() - 1));
+    for index in 0..512 {
+        let offset = index.try_into().unwrap();
+        let Some((frame, flags)) = table.get(offset).get() else {
+            continue;
+        };
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        let addr = VirtAddr::new_truncate(base + index * span).as_usize();
+        let writable = flags & parent & PageTableFlags::WRITABLE;
+        let flags =
+            (flags - PageTableFlags::WRITABLE) | writable | (parent & PageTableFlags::NO_EXECUTE);
+        match level.lower() {
+            Some(lower) if !flags.contains(PageTableFlags::HUGE_PAGE) => {
+                // SAFETY: Non-leaf entries point to page tables.
+                let table: &AnyPageTable = unsafe { &*frame.base().to_virtual().as_ptr() };
+                walk(table, lower, addr, flags, fun);
+            }
+            _ => fun(addr, flags, level),
+        }
+    }
+}
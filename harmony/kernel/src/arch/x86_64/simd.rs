@@ -0,0 +1,119 @@
+//! Lazily switched SIMD state.
+//!
+//! The kernel is built without SIMD, so entering and leaving it only saves the
+//! integer registers and the x87/SSE registers always hold the state of some
+//! thread. Threads that never use them never pay for saving them. Whenever a
+//! thread is dispatched without its state in the registers, `CR0.TS` is set so
+//! that its first SIMD instruction traps with #NM, and the handler saves the
+//! registers for the thread that last used them and loads the state of the
+//! current one.
+
+use core::arch::asm;
+
+use x86_64_impl::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+
+/// Size of the area `fxsave64` writes.
+const FXSAVE_LEN: usize = 512;
+/// Offset of the x87 control word.
+const FCW_OFFSET: usize = 0;
+/// Offset of the SSE control and status register.
+const MXCSR_OFFSET: usize = 24;
+
+/// The x87 and SSE registers of a thread.
+#[repr(C, align(16))]
+pub struct SimdState([u8; FXSAVE_LEN]);
+
+impl SimdState {
+    /// State after `fninit`, with every floating point exception masked.
+    pub const fn new() -> Self {
+        let mut area = [0; FXSAVE_LEN];
+        let fcw = 0x037Fu16.to_le_bytes();
+        area[FCW_OFFSET] = fcw[0];
+        area[FCW_OFFSET + 1] = fcw[1];
+        let mxcsr = 0x1F80u32.to_le_bytes();
+        let mut i = 0;
+        while i < mxcsr.len() {
+            area[MXCSR_OFFSET + i] = mxcsr[i];
+            i += 1;
+        }
+        Self(area)
+    }
+
+    /// Saves the registers into `self`.
+    ///
+    /// # Safety
+    ///
+    /// [`init`] must have run and `CR0.TS` must be clear.
+    pub unsafe fn save(&mut self) {
+        // SAFETY: Precondition. The area is aligned to 16 bytes.
+        unsafe { asm!("fxsave64 [{}]", in(reg) self.0.as_mut_ptr(), options(nostack)) }
+    }
+
+    /// Loads the registers from `self`.
+    ///
+    /// # Safety
+    ///
+    /// [`init`] must have run and `CR0.TS` must be clear.
+    pub unsafe fn restore(&self) {
+        // SAFETY: Precondition. The area is aligned to 16 bytes and holds a
+        // valid state, since it was either saved or built by `new`.
+        unsafe { asm!("fxrstor64 [{}]", in(reg) self.0.as_ptr(), options(nostack)) }
+    }
+
+    /// The SSE control and status register.
+    pub fn mxcsr(&self) -> u32 {
+        let bytes = &self.0[MXCSR_OFFSET..MXCSR_OFFSET + 4];
+        u32::from_le_bytes(bytes.try_into().unwrap())
+    }
+}
+
+impl Default for SimdState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Enables SSE for userspace and makes the registers usable by the kernel to
+/// switch them.
+pub fn init() {
+    // SAFETY: The kernel doesn't use SIMD so nothing depends on these.
+    unsafe {
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR);
+        });
+        Cr4::update(|flags| flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+    }
+    log::info!("Enabled lazy SIMD switching");
+}
+
+/// Makes the next SIMD instruction trap with #NM if `trap`.
+pub fn set_trap(trap: bool) {
+    // SAFETY: `TS` only decides whether SIMD instructions trap.
+    unsafe {
+        Cr0::update(|flags| flags.set(Cr0Flags::TASK_SWITCHED, trap));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn states_round_trip() {
+        let mut state = SimdState::new();
+        assert_eq!(state.mxcsr(), 0x1F80);
+        set_trap(false);
+        // SAFETY: The kernel doesn't use SIMD, so the registers can be
+        // replaced and are put back before returning.
+        unsafe {
+            let mut previous = SimdState::new();
+            previous.save();
+            state.restore();
+            state.0[MXCSR_OFFSET] = 0;
+            state.save();
+            previous.restore();
+        }
+        assert_eq!(state.mxcsr(), 0x1F80);
+    }
+}
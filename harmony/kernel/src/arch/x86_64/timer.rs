@@ -5,9 +5,17 @@ use core::marker::PhantomData;
 
 use x86_64_impl::instructions::port::Port;
 
+use super::instructions::rdtsc;
+
 /// Frequency of the internal oscillator in Hz.
 pub const OSCILATING_FREQ: f32 = 1193182.0;
 
+/// Reset value used for the periodic timer interrupt.
+pub const TICK_RESET_VALUE: u16 = 5966;
+
+/// Frequency of the periodic timer interrupt in Hz.
+pub const TICK_FREQUENCY: u64 = OSCILATING_FREQ as u64 / TICK_RESET_VALUE as u64;
+
 #[allow(missing_copy_implementations)]
 #[derive(Debug)]
 pub struct Pit8253 {
@@ -24,6 +32,35 @@ impl Pit8253 {
         Self { _private: () }
     }
 
+    /// Measures the frequency of the TSC in Hz by busy-waiting on channel 2.
+    ///
+    /// Channel 2 is independent from the IRQ0 timer so this can be used
+    /// regardless of the state of channel 0.
+    pub fn calibrate_tsc(&mut self) -> u64 {
+        const CALIBRATION_MS: u64 = 10;
+        const COUNT: u64 = OSCILATING_FREQ as u64 * CALIBRATION_MS / 1000;
+        let mut gate: Port<u8> = Port::new(0x61);
+        let mut mode: Port<u8> = Port::new(0x43);
+        let mut ch2: Port<u8> = Port::new(0x42);
+        // SAFETY: Channel 2 and the speaker gate have no other users.
+        unsafe {
+            // Stop channel 2 and disconnect the speaker.
+            let value = gate.read() & !0b11;
+            gate.write(value);
+            // Set PIT to channel 2, mode 0 in low/high byte.
+            mode.write(0b10110000);
+            ch2.write((COUNT & 0xFF) as u8);
+            ch2.write(((COUNT >> 8) & 0xFF) as u8);
+            // Gate the channel on to start counting down.
+            gate.write(value | 1);
+            let start = rdtsc();
+            while gate.read() & 0x20 == 0 {}
+            let end = rdtsc();
+            gate.write(value);
+            (end - start) * 1000 / CALIBRATION_MS
+        }
+    }
+
     /// Transforms this PIT into a timer with interrupts at IRQ0.
     pub fn into_timer(self, reset_value: u16) -> PitTimer {
         PitTimer::new(reset_value, Port::new(0x43), Port::new(0x40))
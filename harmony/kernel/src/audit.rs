@@ -0,0 +1,193 @@
+//! Audit log of capability operations.
+//!
+//! Every [audited](kapi::audit::is_audited) operation is appended to the log
+//! of the core it ran on once it's done, including the ones that failed.
+//! Unlike the event traces, logs never overwrite records: a full log and a
+//! core that ran out of its rate both drop the new record and count it, so a
+//! component can't bury what it did under more records or make the kernel
+//! spend its time logging.
+
+use kapi::audit::{is_audited, AuditRecord, LOST};
+use kapi::raw::{CapError, CapId, RawOperation, SyscallArgs};
+use sync::cell::AtomicRefCell;
+
+use crate::component::Thread;
+use crate::core_local::{current_core, NUM_CORES};
+use crate::trace;
+
+/// Records each log holds.
+const LOG_CAPACITY: usize = 256;
+/// Records a core may log at once.
+const BURST: u64 = 64;
+/// Records a core may log per second once its burst is spent.
+const RATE: u64 = 1000;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+struct Log {
+    records: [AuditRecord; LOG_CAPACITY],
+    /// Index of the oldest record.
+    start: usize,
+    len: usize,
+    /// Records dropped since the last drain.
+    lost: u64,
+    /// Records that may be logged before the rate kicks in.
+    tokens: u64,
+    /// When tokens were last added.
+    refilled: u64,
+}
+
+impl Log {
+    const fn new() -> Self {
+        Self {
+            records: [AuditRecord {
+                nanos: 0,
+                op: 0,
+                core: 0,
+                thread: 0,
+                cap: 0,
+                error: 0,
+                args: [0; 4],
+            }; LOG_CAPACITY],
+            start: 0,
+            len: 0,
+            lost: 0,
+            tokens: BURST,
+            refilled: 0,
+        }
+    }
+
+    /// Takes a token for a record logged at `now`, if there's one left.
+    fn admit(&mut self, now: u64) -> bool {
+        let earned = now.saturating_sub(self.refilled).saturating_mul(RATE) / NANOS_PER_SEC;
+        if earned > 0 {
+            self.tokens = (self.tokens + earned).min(BURST);
+            self.refilled = now;
+        }
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+
+    fn push(&mut self, record: AuditRecord) {
+        if self.len == LOG_CAPACITY || !self.admit(record.nanos) {
+            self.lost += 1;
+            return;
+        }
+        self.records[(self.start + self.len) % LOG_CAPACITY] = record;
+        self.len += 1;
+    }
+
+    /// Moves the oldest records into `out`, after a [`LOST`] record if any
+    /// were dropped.
+    fn drain(&mut self, out: &mut [AuditRecord], now: u64, core: u32) -> usize {
+        let mut count = 0;
+        if self.lost > 0 && !out.is_empty() {
+            out[0] = AuditRecord {
+                nanos: now,
+                op: LOST,
+                core,
+                args: [self.lost, 0, 0, 0],
+                ..Default::default()
+            };
+            self.lost = 0;
+            count = 1;
+        }
+        let moved = (out.len() - count).min(self.len);
+        for (i, slot) in out[count..count + moved].iter_mut().enumerate() {
+            *slot = self.records[(self.start + i) % LOG_CAPACITY];
+        }
+        self.start = (self.start + moved) % LOG_CAPACITY;
+        self.len -= moved;
+        count + moved
+    }
+}
+
+static LOGS: [AtomicRefCell<Log>; NUM_CORES] =
+    [const { AtomicRefCell::new(Log::new()) }; NUM_CORES];
+
+/// Logs the outcome of exercising `cap` with `args` if the operation is
+/// audited.
+pub fn record(cap: CapId, args: SyscallArgs, result: &Result<usize, CapError>) {
+    let Ok(op) = RawOperation::try_from(args.op()) else {
+        return;
+    };
+    if !is_audited(op) {
+        return;
+    }
+    let core = current_core();
+    let (a, b, c, d) = args.args();
+    let record = AuditRecord {
+        nanos: crate::info::nanos_since_boot(),
+        op: op as u32,
+        core: core as u32,
+        thread: Thread::current().map_or(0, |thread| trace::thread_id(&thread)),
+        cap: u32::from(cap),
+        error: result.err().map_or(0, |error| u8::from(error).into()),
+        args: [a as u64, b as u64, c as u64, d as u64],
+    };
+    match LOGS[core].borrow_mut() {
+        Ok(mut log) => log.push(record),
+        // The log is being drained, which can't take long.
+        Err(_) => log::warn!("Dropped an audit record of {op:?} on core {core}"),
+    }
+}
+
+/// Moves the oldest records logged on `core` into `out` and returns how many
+/// were moved, or `None` if there's no such core or its log is in use.
+pub fn drain(core: usize, out: &mut [AuditRecord]) -> Option<usize> {
+    let mut log = LOGS.get(core)?.borrow_mut().ok()?;
+    Some(log.drain(out, crate::info::nanos_since_boot(), core as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(nanos: u64, cap: u32) -> AuditRecord {
+        AuditRecord {
+            nanos,
+            op: RawOperation::CapTableDrop as u32,
+            cap,
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn logs_are_rate_limited() {
+        let mut log = Log::new();
+        for cap in 0..BURST as u32 + 3 {
+            log.push(record(0, cap));
+        }
+        assert_eq!((log.len, log.lost), (BURST as usize, 3));
+        // A few milliseconds later there's room for a few more.
+        log.push(record(2_000_000, 100));
+        log.push(record(2_000_000, 101));
+        log.push(record(2_000_000, 102));
+        assert_eq!((log.len, log.lost), (BURST as usize + 2, 4));
+
+        let mut out = [AuditRecord::default(); 3];
+        assert_eq!(log.drain(&mut out, 5, 0), 3);
+        assert_eq!((out[0].op, out[0].args[0]), (LOST, 4));
+        assert_eq!([out[1].cap, out[2].cap], [0, 1]);
+        assert_eq!(log.drain(&mut out, 5, 0), 3);
+        assert_eq!(out.map(|record| record.cap), [2, 3, 4]);
+    }
+
+    #[test_case]
+    fn full_logs_keep_the_oldest_records() {
+        let mut log = Log::new();
+        for cap in 0..LOG_CAPACITY as u32 + 1 {
+            let nanos = u64::from(cap) * NANOS_PER_SEC;
+            log.push(record(nanos, cap));
+        }
+        assert_eq!((log.len, log.lost), (LOG_CAPACITY, 1));
+        let mut out = [AuditRecord::default(); LOG_CAPACITY + 1];
+        assert_eq!(log.drain(&mut out, 0, 0), LOG_CAPACITY + 1);
+        assert_eq!(out[0].op, LOST);
+        assert_eq!(out[1].cap, 0);
+        assert_eq!(out[LOG_CAPACITY].cap, LOG_CAPACITY as u32 - 1);
+        assert_eq!(log.drain(&mut out, 0, 0), 0);
+    }
+}
@@ -0,0 +1,106 @@
+//! Micro-benchmarks for the test kernel.
+//!
+//! Built with the `bench` feature, the test kernel runs these after the tests
+//! and prints a [`Measurement`] per benchmark to the serial port. Times are in
+//! TSC cycles and include reading the TSC.
+//!
+//! The test kernel never enters userspace, so none of these is a syscall:
+//! `dispatch_getclock` calls the capability dispatch directly, without the
+//! trap, the register save or the return to userspace.
+
+use core::hint::black_box;
+
+use kapi::bench::Measurement;
+use kapi::ops::cap_table::SLOT_COUNT;
+use kapi::ops::clock::ClockOp;
+use kapi::ops::SyscallOp as _;
+use kapi::raw::{CapError, CapId};
+
+use crate::arch::context::Regs;
+use crate::arch::exec::KernelStack;
+use crate::arch::instructions::rdtsc;
+use crate::arch::paging::page_table::{AnyPageTable, PageTableFlags, PageTableOffset};
+use crate::bump_allocator::BumpAllocator;
+use crate::caps::{self, CapEntryExtension as _, RawCapEntry, Resource};
+use crate::component::Thread;
+use crate::kptr::KPtr;
+use crate::sprintln;
+
+const ITERATIONS: usize = 1000;
+
+/// A capability found in the root table.
+const SHALLOW_CAP: CapId = CapId::new(5);
+/// A capability found two tables below the root.
+const DEEP_CAP: CapId = CapId::new((SLOT_COUNT * SLOT_COUNT + 5) as u32);
+
+pub fn run() {
+    sprintln!("Running benchmarks");
+    let mut allocator = BumpAllocator::new();
+    let resources = KPtr::new(
+        allocator.alloc_untyped_frame().unwrap(),
+        RawCapEntry::default(),
+    )
+    .unwrap();
+    caps::extend(resources.clone(), DEEP_CAP, || {
+        allocator.alloc_untyped_frame().ok_or(CapError::OutOfMemory)
+    })
+    .unwrap();
+    for cap in [SHALLOW_CAP, DEEP_CAP] {
+        resources
+            .clone()
+            .find(cap)
+            .unwrap()
+            .change(|slot| slot.resource = Resource::Clock);
+    }
+    let l4 = AnyPageTable::new_l4(allocator.alloc_untyped_frame().unwrap()).unwrap();
+    let kernel_stack =
+        KPtr::new(allocator.alloc_untyped_frame().unwrap(), KernelStack::new()).unwrap();
+    let thread = Thread::new(Regs::default(), l4, resources.clone(), kernel_stack);
+
+    let args = ClockOp::GetTimeNs.into_args();
+    measure("dispatch_getclock", || {
+        black_box(thread.exercise_cap(SHALLOW_CAP, args)).unwrap();
+    });
+    measure("cap_lookup_depth_1", || {
+        black_box(resources.clone().get_capability(SHALLOW_CAP)).unwrap();
+    });
+    measure("cap_lookup_depth_3", || {
+        black_box(resources.clone().get_capability(DEEP_CAP)).unwrap();
+    });
+
+    let table = KPtr::new(
+        allocator.alloc_untyped_frame().unwrap(),
+        AnyPageTable::new(),
+    )
+    .unwrap();
+    let frame = allocator
+        .alloc_untyped_frame()
+        .unwrap()
+        .try_into_user()
+        .unwrap();
+    let offset = PageTableOffset::try_from(0usize).unwrap();
+    measure("map_unmap", || {
+        // SAFETY: The table is never loaded.
+        unsafe {
+            table.map(offset, frame.frame(), PageTableFlags::PRESENT);
+            black_box(table.unmap(offset)).unwrap();
+        }
+    });
+
+    let frame = allocator.alloc_untyped_frame().unwrap();
+    measure("retype", || {
+        frame.try_into_kernel().unwrap().drop();
+        black_box(frame.try_into_untyped()).unwrap();
+    });
+}
+
+/// Times `ITERATIONS` runs of `op` and prints the result.
+fn measure(name: &str, mut op: impl FnMut()) {
+    let mut samples = [0; ITERATIONS];
+    for sample in &mut samples {
+        let start = rdtsc();
+        op();
+        *sample = rdtsc().saturating_sub(start);
+    }
+    sprintln!("{}", Measurement::new(name, &mut samples));
+}
@@ -0,0 +1,382 @@
+//! Boot protocol abstraction.
+//!
+//! Everything the kernel learns from the bootloader (the memory map, the
+//! higher-half direct map, the command line, modules, the framebuffer and the
+//! ACPI RSDP) goes through [`BootProtocol`] so the rest of the kernel doesn't
+//! depend on a particular bootloader. The kernel boots through Limine. The
+//! [`multiboot2`] module only parses Multiboot2 boot information so far, and
+//! nothing can boot the kernel through it yet.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use kernel::pixel::{PixelError, PixelFormat};
+
+mod limine;
+#[cfg(test)]
+mod memory_maps;
+pub mod multiboot2;
+
+pub use self::limine::Limine as Protocol;
+
+/// Maximum number of regions in the memory map.
+const MAX_REGIONS: usize = 128;
+
+/// What a range of physical memory may be used for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemoryKind {
+    /// Free for the kernel to hand out.
+    Usable,
+    /// Used by the bootloader and only needed until the kernel is initialized.
+    BootloaderReclaimable,
+    /// The kernel image and the modules loaded with it.
+    KernelAndModules,
+    /// Anything the kernel must not touch.
+    Reserved,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub base: u64,
+    pub length: u64,
+    pub kind: MemoryKind,
+}
+
+impl MemoryRegion {
+    const EMPTY: Self = Self {
+        base: 0,
+        length: 0,
+        kind: MemoryKind::Reserved,
+    };
+
+    /// End of the region, clamped to the end of the address space.
+    pub fn end(&self) -> u64 {
+        self.base.saturating_add(self.length)
+    }
+}
+
+/// A file loaded by the bootloader along with the kernel.
+#[derive(Debug, Copy, Clone)]
+pub struct Module {
+    /// The path or command line the module was loaded with.
+    pub name: &'static [u8],
+    pub data: &'static [u8],
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    /// Physical address of the first pixel.
+    pub address: u64,
+    pub width: u64,
+    pub height: u64,
+    /// Bytes per row.
+    pub pitch: u64,
+    pub bpp: u16,
+    /// Whether it's a text mode buffer, measured in characters instead of
+    /// pixels.
+    pub text: bool,
+    /// How colors are encoded in the pixels, if they're encoded directly.
+    pub format: Result<PixelFormat, PixelError>,
+}
+
+/// Where the kernel image ended up.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KernelAddress {
+    pub physical_base: u64,
+    pub virtual_base: u64,
+}
+
+/// What the kernel needs from a bootloader.
+pub trait BootProtocol {
+    /// Name of the protocol, for logging.
+    fn name(&self) -> &'static str;
+    /// Whether the kernel was booted with a version of the protocol it understands.
+    fn is_supported(&self) -> bool;
+    /// Offset at which all of physical memory is mapped.
+    fn hhdm_offset(&self) -> Option<u64>;
+    /// Collects the memory map, sorted by address and with page aligned regions.
+    ///
+    /// Can only be taken once since the kernel allocates out of it.
+    fn memory_map(&self) -> Option<MemoryMap>;
+    fn cmdline(&self) -> Option<&'static [u8]>;
+    /// Returns the `index`th module, if there is one.
+    fn module(&self, index: usize) -> Option<Module>;
+    fn framebuffer(&self) -> Option<Framebuffer>;
+    /// Physical address of the ACPI RSDP.
+    fn rsdp(&self) -> Option<u64>;
+    fn kernel_address(&self) -> Option<KernelAddress>;
+    /// The kernel's ELF file as it was loaded from disk.
+    fn kernel_file(&self) -> Option<&'static [u8]>;
+    /// Size of the stack the kernel was entered on.
+    fn stack_size(&self) -> Option<usize>;
+}
+
+/// The memory map handed to the retype table.
+pub type MemoryMap = &'static mut [MemoryRegion];
+
+/// Returns the protocol the kernel was booted with.
+pub fn protocol() -> &'static Protocol {
+    static PROTOCOL: Protocol = Protocol::new();
+    &PROTOCOL
+}
+
+/// Logs what the bootloader provided.
+pub fn log_info() {
+    let protocol = protocol();
+    log::info!("Booted through {}", protocol.name());
+    let modules = (0..).take_while(|&i| protocol.module(i).is_some()).count();
+    log::info!("{modules} boot modules");
+    match protocol.framebuffer() {
+        Some(fb) if fb.text => log::info!(
+            "Text mode buffer at {:#X}: {}x{}",
+            fb.address,
+            fb.width,
+            fb.height
+        ),
+        Some(fb) => log::info!(
+            "Framebuffer at {:#X}: {}x{} ({} bpp, {:?})",
+            fb.address,
+            fb.width,
+            fb.height,
+            fb.bpp,
+            fb.format
+        ),
+        None => log::info!("No framebuffer"),
+    }
+    match protocol.rsdp() {
+        Some(rsdp) => log::info!("ACPI RSDP at {rsdp:#X}"),
+        None => log::warn!("No ACPI RSDP"),
+    }
+}
+
+/// A fixed-capacity list of memory regions.
+#[derive(Debug)]
+struct RegionList<'a> {
+    regions: &'a mut [MemoryRegion],
+    len: usize,
+}
+
+impl<'a> RegionList<'a> {
+    fn new(regions: &'a mut [MemoryRegion]) -> Self {
+        Self { regions, len: 0 }
+    }
+
+    fn push(&mut self, region: MemoryRegion) {
+        if region.length == 0 {
+            return;
+        }
+        match self.regions.get_mut(self.len) {
+            Some(slot) => {
+                *slot = region;
+                self.len += 1;
+            }
+            None => log::warn!("Memory map is full, dropping {region:X?}"),
+        }
+    }
+
+    /// Marks `[base, end)` as `kind`, splitting the usable regions it overlaps.
+    fn carve(&mut self, base: u64, end: u64, kind: MemoryKind) {
+        let mut i = 0;
+        while i < self.len {
+            let region = self.regions[i];
+            if region.kind != MemoryKind::Usable || region.end() <= base || end <= region.base {
+                i += 1;
+                continue;
+            }
+            let overlap_base = base.max(region.base);
+            let overlap_end = end.min(region.end());
+            self.regions[i] = MemoryRegion {
+                base: overlap_base,
+                length: overlap_end - overlap_base,
+                kind,
+            };
+            self.push(MemoryRegion {
+                base: region.base,
+                length: overlap_base - region.base,
+                kind: MemoryKind::Usable,
+            });
+            self.push(MemoryRegion {
+                base: overlap_end,
+                length: region.end() - overlap_end,
+                kind: MemoryKind::Usable,
+            });
+            i += 1;
+        }
+    }
+
+    /// Takes `[base, end)` out of every usable region.
+    fn subtract(&mut self, base: u64, end: u64) {
+        let mut i = 0;
+        while i < self.len {
+            let region = self.regions[i];
+            if region.kind != MemoryKind::Usable || region.end() <= base || end <= region.base {
+                i += 1;
+                continue;
+            }
+            self.regions[i].length = 0;
+            if region.base < base {
+                self.regions[i].length = base - region.base;
+            }
+            if end < region.end() {
+                self.push(MemoryRegion {
+                    base: end,
+                    length: region.end() - end,
+                    kind: MemoryKind::Usable,
+                });
+            }
+            i += 1;
+        }
+    }
+
+    /// Makes the usable regions disjoint from every other region. Memory that
+    /// anything else claims isn't usable.
+    fn resolve_overlaps(&mut self) {
+        for i in 0..self.len {
+            let region = self.regions[i];
+            if region.kind != MemoryKind::Usable {
+                self.subtract(region.base, region.end());
+            }
+        }
+        self.sort();
+        let mut covered = 0;
+        for region in &mut self.regions[..self.len] {
+            if region.kind == MemoryKind::Usable && region.length > 0 {
+                let end = region.end();
+                region.base = region.base.max(covered);
+                region.length = end.saturating_sub(region.base);
+                covered = covered.max(end);
+            }
+        }
+        self.remove_empty();
+    }
+
+    fn remove_empty(&mut self) {
+        let mut kept = 0;
+        for i in 0..self.len {
+            if self.regions[i].length > 0 {
+                self.regions[kept] = self.regions[i];
+                kept += 1;
+            }
+        }
+        self.len = kept;
+    }
+
+    fn sort(&mut self) {
+        self.regions[..self.len].sort_unstable_by_key(|region| region.base);
+    }
+
+    fn into_slice(self) -> &'a mut [MemoryRegion] {
+        &mut self.regions[..self.len]
+    }
+}
+
+/// Copies `regions` into the kernel's memory map.
+///
+/// Usable regions are shrunk to page boundaries and the others are grown to
+/// them. Everything in `carve` is then taken out of the usable regions, and so
+/// is anything else that overlaps them. The map ends up sorted, with no empty
+/// regions and no usable memory that's claimed twice, whatever the bootloader
+/// reported.
+///
+/// Returns `None` if the memory map was already taken.
+fn collect<I, C>(regions: I, carve: C) -> Option<MemoryMap>
+where
+    I: IntoIterator<Item = MemoryRegion>,
+    C: IntoIterator<Item = (u64, u64, MemoryKind)>,
+{
+    static TAKEN: AtomicBool = AtomicBool::new(false);
+    static mut REGIONS: [MemoryRegion; MAX_REGIONS] = [MemoryRegion::EMPTY; MAX_REGIONS];
+
+    if TAKEN.swap(true, Ordering::AcqRel) {
+        return None;
+    }
+    // SAFETY: Only the first caller gets here.
+    #[allow(static_mut_refs)]
+    let buffer = unsafe { &mut REGIONS };
+    Some(fill(buffer, regions, carve))
+}
+
+fn fill<I, C>(buffer: &mut [MemoryRegion], regions: I, carve: C) -> &mut [MemoryRegion]
+where
+    I: IntoIterator<Item = MemoryRegion>,
+    C: IntoIterator<Item = (u64, u64, MemoryKind)>,
+{
+    const PAGE: u64 = crate::arch::paging::FRAME_SIZE;
+    let align_down = |address: u64| address & !(PAGE - 1);
+    let align_up = |address: u64| {
+        address
+            .checked_next_multiple_of(PAGE)
+            .unwrap_or(align_down(u64::MAX))
+    };
+    let mut list = RegionList::new(buffer);
+    for region in regions {
+        let (base, end) = if region.kind == MemoryKind::Usable {
+            (align_up(region.base), align_down(region.end()))
+        } else {
+            (align_down(region.base), align_up(region.end()))
+        };
+        if base < end {
+            list.push(MemoryRegion {
+                base,
+                length: end - base,
+                kind: region.kind,
+            });
+        }
+    }
+    for (base, end, kind) in carve {
+        list.carve(align_down(base), align_up(end), kind);
+    }
+    list.resolve_overlaps();
+    list.into_slice()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::paging::FRAME_SIZE;
+
+    fn region(base: u64, length: u64, kind: MemoryKind) -> MemoryRegion {
+        MemoryRegion { base, length, kind }
+    }
+
+    #[test_case]
+    fn aligns_and_sorts_regions() {
+        let mut buffer = [MemoryRegion::EMPTY; 8];
+        let map = fill(
+            &mut buffer,
+            [
+                region(FRAME_SIZE * 4 + 1, FRAME_SIZE * 2, MemoryKind::Usable),
+                region(10, 20, MemoryKind::Reserved),
+            ],
+            [],
+        );
+        assert_eq!(
+            map,
+            [
+                region(0, FRAME_SIZE, MemoryKind::Reserved),
+                region(FRAME_SIZE * 5, FRAME_SIZE, MemoryKind::Usable),
+            ]
+        );
+    }
+
+    #[test_case]
+    fn carves_out_the_kernel() {
+        let mut buffer = [MemoryRegion::EMPTY; 8];
+        let map = fill(
+            &mut buffer,
+            [region(0, FRAME_SIZE * 8, MemoryKind::Usable)],
+            [(
+                FRAME_SIZE * 2,
+                FRAME_SIZE * 3 + 1,
+                MemoryKind::KernelAndModules,
+            )],
+        );
+        assert_eq!(
+            map,
+            [
+                region(0, FRAME_SIZE * 2, MemoryKind::Usable),
+                region(FRAME_SIZE * 2, FRAME_SIZE * 2, MemoryKind::KernelAndModules),
+                region(FRAME_SIZE * 4, FRAME_SIZE * 4, MemoryKind::Usable),
+            ]
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! The [Limine](https://github.com/limine-bootloader/limine) boot protocol.
+
+use kernel::pixel::{Channel, PixelError, PixelFormat};
+use limine::framebuffer::MemoryModel;
+use limine::memory_map::EntryType;
+use limine::request::{
+    FramebufferRequest, HhdmRequest, KernelAddressRequest, KernelFileRequest, MemoryMapRequest,
+    ModuleRequest, RsdpRequest, StackSizeRequest,
+};
+use limine::BaseRevision;
+
+use super::{
+    BootProtocol, Framebuffer, KernelAddress, MemoryKind, MemoryMap, MemoryRegion, Module,
+};
+
+#[used]
+static BASE_REVISION: BaseRevision = BaseRevision::with_revision(1);
+#[used]
+static STACK_SIZE: StackSizeRequest = StackSizeRequest::new().with_size(BOOT_STACK_SIZE);
+
+const BOOT_STACK_SIZE: u64 = 0x32000;
+#[used]
+static HHDM: HhdmRequest = HhdmRequest::new();
+#[used]
+static MEMORY_MAP: MemoryMapRequest = MemoryMapRequest::new();
+#[used]
+static KERNEL_FILE: KernelFileRequest = KernelFileRequest::new();
+#[used]
+static KERNEL_ADDRESS: KernelAddressRequest = KernelAddressRequest::new();
+#[used]
+static MODULES: ModuleRequest = ModuleRequest::new();
+#[used]
+static FRAMEBUFFER: FramebufferRequest = FramebufferRequest::new();
+#[used]
+static RSDP: RsdpRequest = RsdpRequest::new();
+
+#[derive(Default)]
+pub struct Limine {
+    _private: (),
+}
+
+impl Limine {
+    pub const fn new() -> Self {
+        Self { _private: () }
+    }
+
+    /// Translates an address in the higher-half direct map to a physical one.
+    fn physical(&self, addr: u64) -> u64 {
+        addr - self.hhdm_offset().unwrap_or(0)
+    }
+}
+
+impl BootProtocol for Limine {
+    fn name(&self) -> &'static str {
+        "Limine"
+    }
+
+    fn is_supported(&self) -> bool {
+        BASE_REVISION.is_supported() && STACK_SIZE.get_response().is_some()
+    }
+
+    fn hhdm_offset(&self) -> Option<u64> {
+        HHDM.get_response().map(|response| response.offset())
+    }
+
+    fn memory_map(&self) -> Option<MemoryMap> {
+        let entries = MEMORY_MAP.get_response()?.entries();
+        let regions = entries.iter().map(|entry| MemoryRegion {
+            base: entry.base,
+            length: entry.length,
+            kind: match entry.entry_type {
+                EntryType::USABLE => MemoryKind::Usable,
+                EntryType::BOOTLOADER_RECLAIMABLE => MemoryKind::BootloaderReclaimable,
+                EntryType::KERNEL_AND_MODULES => MemoryKind::KernelAndModules,
+                _ => MemoryKind::Reserved,
+            },
+        });
+        // Limine already keeps the kernel and modules out of usable memory.
+        super::collect(regions, [])
+    }
+
+    fn cmdline(&self) -> Option<&'static [u8]> {
+        KERNEL_FILE
+            .get_response()
+            .map(|response| response.file().cmdline())
+    }
+
+    fn module(&self, index: usize) -> Option<Module> {
+        let file = MODULES.get_response()?.modules().get(index)?;
+        // SAFETY: Limine keeps modules mapped and marks their memory as used.
+        let data = unsafe { core::slice::from_raw_parts(file.addr(), file.size() as usize) };
+        Some(Module {
+            name: file.path(),
+            data,
+        })
+    }
+
+    fn framebuffer(&self) -> Option<Framebuffer> {
+        let framebuffer = FRAMEBUFFER.get_response()?.framebuffers().next()?;
+        let format = if framebuffer.memory_model() == MemoryModel::RGB {
+            PixelFormat::new(
+                framebuffer.bpp(),
+                Channel::new(framebuffer.red_mask_shift(), framebuffer.red_mask_size()),
+                Channel::new(
+                    framebuffer.green_mask_shift(),
+                    framebuffer.green_mask_size(),
+                ),
+                Channel::new(framebuffer.blue_mask_shift(), framebuffer.blue_mask_size()),
+            )
+        } else {
+            Err(PixelError::Unsupported)
+        };
+        Some(Framebuffer {
+            address: self.physical(framebuffer.addr() as u64),
+            width: framebuffer.width(),
+            height: framebuffer.height(),
+            pitch: framebuffer.pitch(),
+            bpp: framebuffer.bpp(),
+            text: false,
+            format,
+        })
+    }
+
+    fn rsdp(&self) -> Option<u64> {
+        // Base revision 1 hands out the RSDP in the higher-half direct map.
+        RSDP.get_response()
+            .map(|response| self.physical(response.address() as u64))
+    }
+
+    fn kernel_address(&self) -> Option<KernelAddress> {
+        KERNEL_ADDRESS.get_response().map(|response| KernelAddress {
+            physical_base: response.physical_base(),
+            virtual_base: response.virtual_base(),
+        })
+    }
+
+    fn kernel_file(&self) -> Option<&'static [u8]> {
+        let file = KERNEL_FILE.get_response()?.file();
+        // SAFETY: Limine keeps the kernel file mapped and marks its memory as used.
+        Some(unsafe { core::slice::from_raw_parts(file.addr(), file.size() as usize) })
+    }
+
+    fn stack_size(&self) -> Option<usize> {
+        STACK_SIZE.get_response().map(|_| BOOT_STACK_SIZE as usize)
+    }
+}
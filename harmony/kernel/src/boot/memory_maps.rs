@@ -0,0 +1,213 @@
+//! Odd memory maps run through the boot-time memory setup.
+//!
+//! Bootloaders don't always hand out sorted, aligned and disjoint regions.
+//! Every map here goes through [`fill`](super::fill) and then through the
+//! retype table's [`Layout`], which is where the table would be placed, and
+//! must either come out usable or be rejected with an error instead of
+//! tripping an assert.
+//!
+//! Maps are generated from seeds. When one fails, the assert message has its
+//! seed, which can be added to [`RECORDED_SEEDS`] to replay it from then on.
+//! Hand-written maps for known shapes are in [`recorded_shapes`].
+
+use super::{fill, MemoryKind, MemoryRegion};
+use crate::arch::paging::FRAME_SIZE;
+use crate::retyping::{Layout, MapError};
+
+/// Seeds that found bugs, replayed before the generated ones.
+const RECORDED_SEEDS: &[u64] = &[];
+/// Generated maps to try.
+const GENERATED: u64 = 256;
+/// Most regions in a generated map.
+const MAX_GENERATED: usize = 24;
+
+const KINDS: [MemoryKind; 4] = [
+    MemoryKind::Usable,
+    MemoryKind::BootloaderReclaimable,
+    MemoryKind::KernelAndModules,
+    MemoryKind::Reserved,
+];
+
+/// xorshift64*, seeded so that maps can be replayed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+fn region(base: u64, length: u64, kind: MemoryKind) -> MemoryRegion {
+    MemoryRegion { base, length, kind }
+}
+
+/// Generates a map mixing overlaps, empty and unaligned regions and huge
+/// holes.
+fn generate(seed: u64, map: &mut [MemoryRegion; MAX_GENERATED]) -> usize {
+    let mut rng = Rng::new(seed);
+    let len = rng.below(MAX_GENERATED as u64 + 1) as usize;
+    for i in 0..len {
+        let kind = KINDS[rng.below(KINDS.len() as u64) as usize];
+        let (base, length) = match rng.below(6) {
+            // Overlaps an earlier region.
+            0 if i > 0 => {
+                let other = map[rng.below(i as u64) as usize];
+                let base = other.base.saturating_add(rng.below(other.length + 1));
+                (base, rng.below(64) * FRAME_SIZE)
+            }
+            1 => (rng.below(1 << 30), 0),
+            2 => (rng.below(1 << 30), rng.below(1 << 24)),
+            // Far past the rest of memory.
+            3 => (1 << (40 + rng.below(20)), rng.below(1 << 30)),
+            // Runs into the end of the address space.
+            4 => (u64::MAX - rng.below(1 << 20), rng.below(1 << 21)),
+            _ => (
+                rng.below(1 << 18) * FRAME_SIZE,
+                (1 + rng.below(1 << 12)) * FRAME_SIZE,
+            ),
+        };
+        map[i] = region(base, length, kind);
+    }
+    len
+}
+
+/// Checks what `fill` makes of `regions` and where the table would go.
+fn check(label: &dyn core::fmt::Debug, regions: &[MemoryRegion]) -> Result<Layout, MapError> {
+    let mut raw = [MemoryRegion::EMPTY; MAX_GENERATED];
+    raw[..regions.len()].copy_from_slice(regions);
+    // The layout rejects what it can't use as it is.
+    let _ = Layout::new(&mut raw[..regions.len()]);
+
+    let mut buffer = [MemoryRegion::EMPTY; 64];
+    let map = fill(&mut buffer, regions.iter().copied(), []);
+    let mut previous: Option<MemoryRegion> = None;
+    for &region in map.iter() {
+        assert!(region.length > 0, "{label:?}: empty {region:X?}");
+        assert!(
+            region.base % FRAME_SIZE == 0 && region.length % FRAME_SIZE == 0,
+            "{label:?}: unaligned {region:X?}"
+        );
+        if let Some(previous) = previous {
+            assert!(previous.base <= region.base, "{label:?}: unsorted");
+        }
+        previous = Some(region);
+    }
+    let filled = map.len();
+    let mut remaining = buffer;
+    let layout = Layout::new(&mut remaining[..filled]);
+    assert!(
+        matches!(layout, Ok(_) | Err(MapError::Empty | MapError::NoRoom)),
+        "{label:?}: filled map rejected with {layout:?}"
+    );
+    if let Ok(layout) = layout {
+        let table = layout.table.as_u64();
+        let holder = buffer[..filled]
+            .iter()
+            .find(|region| region.base <= table && table < region.end());
+        assert!(
+            holder.is_some_and(|region| region.kind == MemoryKind::Usable),
+            "{label:?}: table at {table:#X} outside usable memory"
+        );
+        assert!(table / FRAME_SIZE < layout.frames as u64);
+    }
+    layout
+}
+
+#[test_case]
+fn recorded_shapes() {
+    use MemoryKind::*;
+
+    const MIB: u64 = 1 << 20;
+    assert_eq!(check(&"empty", &[]), Err(MapError::Empty));
+    assert_eq!(
+        check(&"reserved", &[region(0, 16 * MIB, Reserved)]),
+        Err(MapError::Empty)
+    );
+    assert_eq!(
+        check(&"zero length", &[region(0, 0, Usable)]),
+        Err(MapError::Empty)
+    );
+    assert_eq!(
+        check(&"less than a page", &[region(1, FRAME_SIZE, Usable)]),
+        Err(MapError::Empty)
+    );
+    // The kernel claims all of the usable memory it overlaps.
+    assert_eq!(
+        check(
+            &"overlapped",
+            &[
+                region(0, 16 * MIB, Usable),
+                region(0, 16 * MIB, KernelAndModules),
+            ]
+        ),
+        Err(MapError::NoRoom)
+    );
+    let layout = check(
+        &"usable twice",
+        &[region(MIB, 16 * MIB, Usable), region(0, 8 * MIB, Usable)],
+    )
+    .unwrap();
+    assert_eq!(layout.frames as u64, 17 * MIB / FRAME_SIZE);
+    // A reserved region far away doesn't grow the table.
+    let layout = check(
+        &"hole",
+        &[region(0, 16 * MIB, Usable), region(1 << 46, MIB, Reserved)],
+    )
+    .unwrap();
+    assert_eq!(layout.frames as u64, 16 * MIB / FRAME_SIZE);
+    // Memory far away does, and then there's no room for the table.
+    assert_eq!(
+        check(
+            &"far memory",
+            &[region(0, MIB, Usable), region(1 << 46, MIB, Usable)]
+        ),
+        Err(MapError::NoRoom)
+    );
+    assert!(check(
+        &"end of the address space",
+        &[
+            region(0, 16 * MIB, Usable),
+            region(u64::MAX - 10, 20, Reserved),
+            region(u64::MAX - FRAME_SIZE, FRAME_SIZE * 2, Usable),
+        ]
+    )
+    .is_ok());
+
+    // Unaligned and unsorted maps are rejected before anything is laid out.
+    let mut unsorted = [region(MIB, MIB, Usable), region(0, MIB, Usable)];
+    assert!(matches!(
+        Layout::new(&mut unsorted),
+        Err(MapError::Overlapping(_))
+    ));
+    let mut unaligned = [region(1, MIB, Usable)];
+    assert!(matches!(
+        Layout::new(&mut unaligned),
+        Err(MapError::Unaligned(_))
+    ));
+    let mut wrapping = [region(u64::MAX - FRAME_SIZE + 1, FRAME_SIZE * 2, Usable)];
+    assert!(matches!(
+        Layout::new(&mut wrapping),
+        Err(MapError::Unaligned(_))
+    ));
+}
+
+#[test_case]
+fn generated_shapes() {
+    let mut map = [MemoryRegion::EMPTY; MAX_GENERATED];
+    let seeds = RECORDED_SEEDS.iter().copied().chain(0..GENERATED);
+    for seed in seeds {
+        let len = generate(seed, &mut map);
+        let _ = check(&format_args!("seed {seed}"), &map[..len]);
+    }
+}
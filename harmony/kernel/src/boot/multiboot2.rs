@@ -0,0 +1,411 @@
+//! The [Multiboot2](https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html)
+//! boot protocol, used by GRUB among others.
+//!
+//! The loader passes the physical address of the boot information: a list of
+//! tags describing the memory map, modules, framebuffer and so on.
+//!
+//! This is only the parsing of the boot information, tested against tags
+//! built in memory, and nothing boots through it: the kernel image has no
+//! Multiboot2 header and always uses Limine. Multiboot2 loaders enter in
+//! 32-bit protected mode with paging disabled, so booting this way also needs
+//! a trampoline that switches to long mode, maps the kernel at its link
+//! address and physical memory at [`HHDM_OFFSET`], and then calls [`enter`].
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use kernel::pixel::{Channel, PixelError, PixelFormat};
+
+use super::{
+    BootProtocol, Framebuffer, KernelAddress, MemoryKind, MemoryMap, MemoryRegion, Module,
+};
+
+/// Value the loader leaves in `eax` when it passes the boot information.
+pub const BOOTLOADER_MAGIC: u32 = 0x36D7_6289;
+
+/// Where the early boot code maps all of physical memory.
+pub const HHDM_OFFSET: u64 = 0xFFFF_8000_0000_0000;
+
+const TAG_END: u32 = 0;
+const TAG_CMDLINE: u32 = 1;
+const TAG_MODULE: u32 = 3;
+const TAG_MEMORY_MAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+const TAG_ACPI_OLD: u32 = 14;
+const TAG_ACPI_NEW: u32 = 15;
+const TAG_LOAD_BASE: u32 = 21;
+
+/// Framebuffer type of directly encoded colors, described by bit fields.
+const FRAMEBUFFER_RGB: u8 = 1;
+/// Framebuffer type of EGA text mode.
+const FRAMEBUFFER_TEXT: u8 = 2;
+
+/// Physical address of the boot information. Zero until [`enter`] is called.
+static INFO: AtomicU64 = AtomicU64::new(0);
+
+/// Records the boot information handed over by the loader.
+///
+/// # Safety
+///
+/// `info` must be the address the loader passed along with `magic`, and
+/// physical memory must be mapped at [`HHDM_OFFSET`].
+pub unsafe fn enter(magic: u32, info: u64) {
+    assert_eq!(magic, BOOTLOADER_MAGIC, "Not booted by a Multiboot2 loader");
+    INFO.store(info, Ordering::Release);
+}
+
+/// A tag in the boot information.
+#[derive(Debug, Copy, Clone)]
+pub struct Tag<'a> {
+    pub kind: u32,
+    /// Contents of the tag after its type and size.
+    pub data: &'a [u8],
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().unwrap(),
+    ))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().unwrap(),
+    ))
+}
+
+/// Cuts a string at its NUL terminator.
+fn c_str(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    &bytes[..len]
+}
+
+/// The boot information structure.
+#[derive(Debug, Copy, Clone)]
+pub struct BootInfo<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> BootInfo<'a> {
+    /// Wraps the boot information in `bytes`.
+    ///
+    /// Returns `None` if the structure is larger than `bytes`.
+    pub fn new(bytes: &'a [u8]) -> Option<Self> {
+        let size = read_u32(bytes, 0)? as usize;
+        Some(Self {
+            bytes: bytes.get(..size)?,
+        })
+    }
+
+    /// Wraps the boot information at the physical address `info`.
+    ///
+    /// # Safety
+    ///
+    /// `info` must point to a valid boot information structure and physical
+    /// memory must be mapped at [`HHDM_OFFSET`].
+    unsafe fn from_physical(info: u64) -> Option<BootInfo<'static>> {
+        let ptr = (info + HHDM_OFFSET) as *const u8;
+        // SAFETY: The structure starts with its total size.
+        let size = unsafe { ptr.cast::<u32>().read() } as usize;
+        // SAFETY: The loader leaves the structure in memory it reports as used.
+        BootInfo::new(unsafe { core::slice::from_raw_parts(ptr, size) })
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn tags(&self) -> impl Iterator<Item = Tag<'a>> + 'a {
+        let bytes = self.bytes;
+        let mut offset = 8;
+        core::iter::from_fn(move || {
+            let kind = read_u32(bytes, offset)?;
+            let size = read_u32(bytes, offset + 4)? as usize;
+            if kind == TAG_END || size < 8 {
+                return None;
+            }
+            let data = bytes.get(offset + 8..offset + size)?;
+            offset += size.next_multiple_of(8);
+            Some(Tag { kind, data })
+        })
+    }
+
+    fn tag(&self, kind: u32) -> Option<Tag<'a>> {
+        self.tags().find(|tag| tag.kind == kind)
+    }
+
+    /// Offset of `tag`'s data from the start of the structure.
+    fn offset_of(&self, tag: &Tag<'a>) -> usize {
+        tag.data.as_ptr() as usize - self.bytes.as_ptr() as usize
+    }
+
+    pub fn cmdline(&self) -> Option<&'a [u8]> {
+        self.tag(TAG_CMDLINE).map(|tag| c_str(tag.data))
+    }
+
+    /// Returns the physical range and name of the `index`th module.
+    pub fn module(&self, index: usize) -> Option<(u64, u64, &'a [u8])> {
+        let tag = self
+            .tags()
+            .filter(|tag| tag.kind == TAG_MODULE)
+            .nth(index)?;
+        let start = read_u32(tag.data, 0)?;
+        let end = read_u32(tag.data, 4)?;
+        Some((start.into(), end.into(), c_str(tag.data.get(8..)?)))
+    }
+
+    pub fn memory_regions(&self) -> impl Iterator<Item = MemoryRegion> + 'a {
+        let (entry_size, entries) = self
+            .tag(TAG_MEMORY_MAP)
+            .and_then(|tag| Some((read_u32(tag.data, 0)? as usize, tag.data.get(8..)?)))
+            .unwrap_or((24, &[]));
+        entries
+            .chunks_exact(entry_size.max(24))
+            .map(|entry| MemoryRegion {
+                base: read_u64(entry, 0).unwrap(),
+                length: read_u64(entry, 8).unwrap(),
+                kind: match read_u32(entry, 16).unwrap() {
+                    1 => MemoryKind::Usable,
+                    _ => MemoryKind::Reserved,
+                },
+            })
+    }
+
+    pub fn framebuffer(&self) -> Option<Framebuffer> {
+        let data = self.tag(TAG_FRAMEBUFFER)?.data;
+        let bpp = (*data.get(20)?).into();
+        let kind = *data.get(21)?;
+        // The color info of an RGB framebuffer is the position and size of
+        // each channel, after two reserved bytes.
+        let format = match data.get(24..30) {
+            Some(&[red_shift, red_size, green_shift, green_size, blue_shift, blue_size])
+                if kind == FRAMEBUFFER_RGB =>
+            {
+                PixelFormat::new(
+                    bpp,
+                    Channel::new(red_shift, red_size),
+                    Channel::new(green_shift, green_size),
+                    Channel::new(blue_shift, blue_size),
+                )
+            }
+            _ => Err(PixelError::Unsupported),
+        };
+        Some(Framebuffer {
+            address: read_u64(data, 0)?,
+            pitch: read_u32(data, 8)?.into(),
+            width: read_u32(data, 12)?.into(),
+            height: read_u32(data, 16)?.into(),
+            bpp,
+            text: kind == FRAMEBUFFER_TEXT,
+            format,
+        })
+    }
+
+    /// Offset from the start of the structure to the copy of the RSDP.
+    ///
+    /// Multiboot2 hands out a copy of the RSDP rather than its address.
+    pub fn rsdp_offset(&self) -> Option<usize> {
+        let tag = self.tag(TAG_ACPI_NEW).or_else(|| self.tag(TAG_ACPI_OLD))?;
+        Some(self.offset_of(&tag))
+    }
+
+    /// Physical address the kernel image was loaded at.
+    pub fn load_base(&self) -> Option<u64> {
+        self.tag(TAG_LOAD_BASE)
+            .and_then(|tag| read_u32(tag.data, 0))
+            .map(u64::from)
+    }
+}
+
+#[derive(Default)]
+pub struct Multiboot2 {
+    _private: (),
+}
+
+impl Multiboot2 {
+    pub const fn new() -> Self {
+        Self { _private: () }
+    }
+
+    fn info(&self) -> Option<(u64, BootInfo<'static>)> {
+        let info = INFO.load(Ordering::Acquire);
+        if info == 0 {
+            return None;
+        }
+        // SAFETY: Checked by `enter`.
+        Some((info, unsafe { BootInfo::from_physical(info)? }))
+    }
+}
+
+impl BootProtocol for Multiboot2 {
+    fn name(&self) -> &'static str {
+        "Multiboot2"
+    }
+
+    fn is_supported(&self) -> bool {
+        self.info().is_some()
+    }
+
+    fn hhdm_offset(&self) -> Option<u64> {
+        Some(HHDM_OFFSET)
+    }
+
+    fn memory_map(&self) -> Option<MemoryMap> {
+        let (address, info) = self.info()?;
+        // Unlike Limine, the loader reports the memory it loaded things into
+        // as available.
+        let modules = (0..)
+            .map_while(|index| info.module(index))
+            .map(|(start, end, _)| (start, end, MemoryKind::KernelAndModules));
+        let kernel = self.kernel_address().map(|kernel| {
+            let end = kernel.physical_base + crate::arch::sections::image_size() as u64;
+            (kernel.physical_base, end, MemoryKind::KernelAndModules)
+        });
+        let info_range = (
+            address,
+            address + info.len() as u64,
+            MemoryKind::BootloaderReclaimable,
+        );
+        super::collect(
+            info.memory_regions(),
+            modules.chain(kernel).chain([info_range]),
+        )
+    }
+
+    fn cmdline(&self) -> Option<&'static [u8]> {
+        self.info()?.1.cmdline()
+    }
+
+    fn module(&self, index: usize) -> Option<Module> {
+        let (start, end, name) = self.info()?.1.module(index)?;
+        let ptr = (start + HHDM_OFFSET) as *const u8;
+        // SAFETY: Modules are kept out of usable memory.
+        let data = unsafe { core::slice::from_raw_parts(ptr, (end - start) as usize) };
+        Some(Module { name, data })
+    }
+
+    fn framebuffer(&self) -> Option<Framebuffer> {
+        self.info()?.1.framebuffer()
+    }
+
+    fn rsdp(&self) -> Option<u64> {
+        let (address, info) = self.info()?;
+        Some(address + info.rsdp_offset()? as u64)
+    }
+
+    fn kernel_address(&self) -> Option<KernelAddress> {
+        Some(KernelAddress {
+            physical_base: self.info()?.1.load_base()?,
+            virtual_base: crate::arch::sections::LINK_ADDRESS,
+        })
+    }
+
+    fn kernel_file(&self) -> Option<&'static [u8]> {
+        // Multiboot2 only loads the segments of the kernel.
+        None
+    }
+
+    fn stack_size(&self) -> Option<usize> {
+        // The stack is set up by the trampoline, which doesn't exist yet.
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a boot information structure out of `(type, data)` tags.
+    fn build<'a>(buffer: &'a mut [u8], tags: &[(u32, &[u8])]) -> BootInfo<'a> {
+        let mut offset = 8;
+        for (kind, data) in tags {
+            let size = 8 + data.len();
+            buffer[offset..offset + 4].copy_from_slice(&kind.to_le_bytes());
+            buffer[offset + 4..offset + 8].copy_from_slice(&(size as u32).to_le_bytes());
+            buffer[offset + 8..offset + size].copy_from_slice(data);
+            offset += size.next_multiple_of(8);
+        }
+        buffer[offset..offset + 8].copy_from_slice(&[0, 0, 0, 0, 8, 0, 0, 0]);
+        offset += 8;
+        buffer[..4].copy_from_slice(&(offset as u32).to_le_bytes());
+        BootInfo::new(buffer).unwrap()
+    }
+
+    fn mmap_entry(base: u64, length: u64, kind: u32) -> [u8; 24] {
+        let mut entry = [0; 24];
+        entry[..8].copy_from_slice(&base.to_le_bytes());
+        entry[8..16].copy_from_slice(&length.to_le_bytes());
+        entry[16..20].copy_from_slice(&kind.to_le_bytes());
+        entry
+    }
+
+    #[test_case]
+    fn parses_tags() {
+        let mut mmap = [0; 8 + 48];
+        mmap[..4].copy_from_slice(&24u32.to_le_bytes());
+        mmap[8..32].copy_from_slice(&mmap_entry(0, 0x9F000, 1));
+        mmap[32..].copy_from_slice(&mmap_entry(0x9F000, 0x1000, 2));
+        let mut module = [0; 8 + 5];
+        module[..4].copy_from_slice(&0x10_0000u32.to_le_bytes());
+        module[4..8].copy_from_slice(&0x10_2000u32.to_le_bytes());
+        module[8..].copy_from_slice(b"init\0");
+
+        let mut buffer = [0; 256];
+        let info = build(
+            &mut buffer,
+            &[
+                (TAG_CMDLINE, b"scrub=8\0"),
+                (TAG_MEMORY_MAP, &mmap),
+                (TAG_MODULE, &module),
+                (TAG_ACPI_OLD, b"RSD PTR "),
+            ],
+        );
+        assert_eq!(info.cmdline(), Some(&b"scrub=8"[..]));
+        assert_eq!(info.module(0), Some((0x10_0000, 0x10_2000, &b"init"[..])));
+        assert_eq!(info.module(1), None);
+        let mut regions = info.memory_regions();
+        assert_eq!(
+            regions.next(),
+            Some(MemoryRegion {
+                base: 0,
+                length: 0x9F000,
+                kind: MemoryKind::Usable
+            })
+        );
+        assert_eq!(regions.next().unwrap().kind, MemoryKind::Reserved);
+        assert!(regions.next().is_none());
+        assert!(info.framebuffer().is_none());
+        let rsdp = info.rsdp_offset().unwrap();
+        assert_eq!(&info.bytes[rsdp..rsdp + 8], b"RSD PTR ");
+    }
+
+    #[test_case]
+    fn framebuffer_formats() {
+        let mut tag = [0; 30];
+        tag[..8].copy_from_slice(&0xFD00_0000u64.to_le_bytes());
+        tag[8..12].copy_from_slice(&4096u32.to_le_bytes());
+        tag[12..16].copy_from_slice(&1000u32.to_le_bytes());
+        tag[16..20].copy_from_slice(&768u32.to_le_bytes());
+        tag[20] = 32;
+        tag[21] = FRAMEBUFFER_RGB;
+        tag[24..30].copy_from_slice(&[16, 8, 8, 8, 0, 8]);
+        let mut buffer = [0; 64];
+        let framebuffer = build(&mut buffer, &[(TAG_FRAMEBUFFER, &tag)])
+            .framebuffer()
+            .unwrap();
+        assert_eq!(framebuffer.pitch, 4096);
+        assert_eq!(framebuffer.format, Ok(PixelFormat::BGRX));
+
+        // Indexed colors.
+        tag[21] = 0;
+        let mut buffer = [0; 64];
+        let framebuffer = build(&mut buffer, &[(TAG_FRAMEBUFFER, &tag)])
+            .framebuffer()
+            .unwrap();
+        assert_eq!(framebuffer.format, Err(PixelError::Unsupported));
+        assert!(!framebuffer.text);
+    }
+}
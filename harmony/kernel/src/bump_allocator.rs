@@ -1,3 +1,4 @@
+use crate::arch::interrupts;
 use crate::arch::paging::{PhysAddr, RawFrame, FRAME_SIZE};
 use crate::retyping::{AsTypeError, KernelFrame, RetypeError, UserFrame};
 
@@ -17,6 +18,11 @@ impl BumpAllocator {
     }
 
     pub fn alloc_user_frame(&mut self) -> Option<UserFrame> {
+        check_context();
+        #[cfg(feature = "fault-injection")]
+        if crate::fault::should_fail(crate::fault::Site::Alloc) {
+            return None;
+        }
         loop {
             let frame = RawFrame::from_start_address(PhysAddr::new(FRAME_SIZE * self.index));
             log::trace!("Trying to allocate user frame: {frame:?}");
@@ -30,6 +36,11 @@ impl BumpAllocator {
     }
 
     pub fn alloc_untyped_frame(&mut self) -> Option<RawFrame> {
+        check_context();
+        #[cfg(feature = "fault-injection")]
+        if crate::fault::should_fail(crate::fault::Site::Alloc) {
+            return None;
+        }
         loop {
             let frame = RawFrame::from_start_address(PhysAddr::new(FRAME_SIZE * self.index));
             log::trace!("Trying to allocate untyped frame: {frame:?}");
@@ -43,6 +54,11 @@ impl BumpAllocator {
     }
 
     pub fn alloc_kernel_frame(&mut self) -> Option<KernelFrame> {
+        check_context();
+        #[cfg(feature = "fault-injection")]
+        if crate::fault::should_fail(crate::fault::Site::Alloc) {
+            return None;
+        }
         loop {
             let frame = RawFrame::from_start_address(PhysAddr::new(FRAME_SIZE * self.index));
             log::trace!("Trying to allocate kernel frame: {frame:?}");
@@ -55,3 +71,11 @@ impl BumpAllocator {
         }
     }
 }
+
+/// Interrupt handlers must allocate from [`crate::reserve`] instead.
+fn check_context() {
+    debug_assert!(
+        !interrupts::in_irq(),
+        "General allocation from interrupt context"
+    );
+}
@@ -2,13 +2,18 @@
 
 use core::convert::Infallible;
 
+use kapi::endowment::ResourceKind;
+pub use kapi::ops::region::Region;
 use kapi::raw::{CapError, CapId};
-use sync::cell::AtomicCell;
+use sync::cell::{AtomicCell, AtomicRefCell};
 use trie::{Ptr, Slot, SlotId, TrieEntry};
 
-use crate::arch::paging::page_table::AnyPageTable;
-use crate::arch::paging::PAGE_SIZE;
+use crate::arch::iommu::Domain;
+use crate::arch::paging::page_table::{AnyPageTable, PageTableLevel};
+use crate::arch::paging::{RawFrame, PAGE_SIZE};
 use crate::component::Thread;
+use crate::endpoint::Endpoint;
+use crate::hierarchy::Hierarchy;
 use crate::kptr::KPtr;
 
 const SLOT_SIZE: usize = 32;
@@ -58,6 +63,16 @@ impl TryFrom<Resource> for KPtr<Thread> {
         }
     }
 }
+impl TryFrom<Resource> for KPtr<Hierarchy> {
+    type Error = WrongVariant;
+
+    fn try_from(value: Resource) -> Result<Self, Self::Error> {
+        match value {
+            Resource::Hierarchy(hierarchy) => Ok(hierarchy),
+            _ => Err(WrongVariant),
+        }
+    }
+}
 
 pub trait CapEntryExtension: Sized {
     fn find(self, cap: CapId) -> Result<impl Ptr<AtomicCapSlot>, CapError>;
@@ -81,7 +96,7 @@ pub trait CapEntryExtension: Sized {
 impl CapEntryExtension for KPtr<RawCapEntry> {
     fn find(self, cap: CapId) -> Result<impl Ptr<AtomicCapSlot>, CapError> {
         RawCapEntry::get(self, cap.into())
-            .map_err(|_| CapError::Internal)?
+            .unwrap_or_else(|never| match never {})
             .ok_or(CapError::NotFound)
     }
 
@@ -141,6 +156,7 @@ impl Slot<NUM_SLOTS> for AtomicCapSlot {
 const _SIZE_OF_ENTRY: () = {
     assert!(core::mem::size_of::<AtomicCapSlot>() == SLOT_SIZE);
     assert!(core::mem::size_of::<RawCapEntry>() == PAGE_SIZE);
+    assert!(core::mem::size_of::<RawCapEntry>() == kapi::ops::cap_table::SLOT_COUNT * SLOT_SIZE);
     assert!(PAGE_SIZE % core::mem::align_of::<RawCapEntry>() == 0);
 };
 
@@ -154,6 +170,111 @@ pub enum Resource {
         table: KPtr<AnyPageTable>,
         flags: PageCapFlags,
     },
+    Logger,
+    /// Allows queueing work on other cores.
+    Ipi,
+    Region(Region),
+    /// Allows reading the time and the TSC calibration.
+    Clock,
+    /// Allows mapping and refreshing the diagnostics page and looking up
+    /// kernel symbols.
+    Diagnostics,
+    /// Allows programming and reading the performance counters.
+    PerfCounter,
+    /// Allows building DMA domains for PCI functions.
+    Iommu,
+    DmaDomain(KPtr<Domain>),
+    /// Allows mapping the initrd read-only.
+    Initrd,
+    Endpoint(KPtr<Endpoint>),
+    Hierarchy(KPtr<Hierarchy>),
+    /// Gives access to services of the kernel as a whole, like random numbers.
+    System,
+    /// Allows mapping the display info page and the font read-only.
+    Display,
+}
+
+/// Returns whether a region in the first node of `table` contains `frame`.
+///
+/// Components can only retype memory in the regions they hold. Transfers only
+/// place regions in the first node of a table, so the check doesn't have to
+/// walk the whole table.
+pub fn holds_frame(table: &KPtr<RawCapEntry>, frame: RawFrame) -> bool {
+    (0..NUM_SLOTS).any(|slot| {
+        let slot = table.clone().index_slot(SlotId::try_from(slot).unwrap());
+        matches!(slot.get().resource, Resource::Region(region) if region.contains(frame.into()))
+    })
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TransferError {
+    /// Another transfer is in progress or the source changed since it was read.
+    Retry,
+    /// The destination slot isn't empty.
+    InUse,
+}
+
+/// Serializes changes to slots holding regions.
+static TRANSFER: AtomicRefCell<()> = AtomicRefCell::new(());
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DropError {
+    /// A region is being transferred.
+    Retry,
+    /// The slot is empty.
+    Empty,
+    /// The resource still holds memory that would be lost: a region, or a
+    /// page table with entries.
+    HoldsMemory,
+}
+
+/// Empties `slot` and returns the resource it held.
+///
+/// Regions and page tables that still map something can't be dropped since
+/// their memory would be lost. They must be transferred or cleared first.
+pub fn drop_resource(slot: &AtomicCapSlot) -> Result<Resource, DropError> {
+    let _guard = TRANSFER.borrow_mut().map_err(|_| DropError::Retry)?;
+    let resource = slot.get().resource;
+    match &resource {
+        Resource::Empty => return Err(DropError::Empty),
+        Resource::Region(_) => return Err(DropError::HoldsMemory),
+        Resource::PageTable { table, flags } => {
+            let level =
+                PageTableLevel::try_new(flags.level()).map_err(|_| DropError::HoldsMemory)?;
+            if !table.is_cleared(level) {
+                return Err(DropError::HoldsMemory);
+            }
+        }
+        _ => {}
+    }
+    slot.change(|slot| slot.resource = Resource::Empty);
+    Ok(resource)
+}
+
+/// Moves `split` out of the region in `source` into `destination`, leaving `kept` behind.
+///
+/// Transfers are serialized so that a frame is never held by two region
+/// capabilities. `expected` is the region `split` and `kept` were computed
+/// from. If the source no longer holds it, the frames moved elsewhere in the
+/// meantime and the transfer must be recomputed.
+pub fn transfer_region(
+    source: &AtomicCapSlot,
+    expected: Region,
+    kept: Option<Region>,
+    destination: &AtomicCapSlot,
+    split: Region,
+) -> Result<(), TransferError> {
+    let _guard = TRANSFER.borrow_mut().map_err(|_| TransferError::Retry)?;
+    if !matches!(source.get().resource, Resource::Region(region) if region == expected) {
+        return Err(TransferError::Retry);
+    }
+    if !destination.get().resource.is_empty() {
+        return Err(TransferError::InUse);
+    }
+    // Shrink the source first so that the frames are never in both slots.
+    source.change(|slot| slot.resource = kept.map_or(Resource::Empty, Resource::Region));
+    destination.change(|slot| slot.resource = Resource::Region(split));
+    Ok(())
 }
 
 #[repr(transparent)]
@@ -174,6 +295,118 @@ impl Resource {
     pub fn is_empty(&self) -> bool {
         matches!(self, Self::Empty)
     }
+
+    pub fn kind(&self) -> ResourceKind {
+        match self {
+            Resource::Empty => ResourceKind::Empty,
+            Resource::CapEntry(_) => ResourceKind::CapTable,
+            Resource::Thread(_) => ResourceKind::Thread,
+            Resource::PageTable { .. } => ResourceKind::PageTable,
+            Resource::Logger => ResourceKind::Logger,
+            Resource::Ipi => ResourceKind::Ipi,
+            Resource::Region(_) => ResourceKind::Region,
+            Resource::Clock => ResourceKind::Clock,
+            Resource::Diagnostics => ResourceKind::Diagnostics,
+            Resource::PerfCounter => ResourceKind::PerfCounter,
+            Resource::Iommu => ResourceKind::Iommu,
+            Resource::DmaDomain(_) => ResourceKind::DmaDomain,
+            Resource::Initrd => ResourceKind::Initrd,
+            Resource::Endpoint(_) => ResourceKind::Endpoint,
+            Resource::Hierarchy(_) => ResourceKind::Hierarchy,
+            Resource::System => ResourceKind::System,
+            Resource::Display => ResourceKind::Display,
+        }
+    }
+
+    /// The frame holding the kernel object referenced by this resource.
+    pub fn frame(&self) -> Option<RawFrame> {
+        match self {
+            Resource::Empty
+            | Resource::Logger
+            | Resource::Ipi
+            | Resource::Region(_)
+            | Resource::Clock
+            | Resource::Diagnostics
+            | Resource::PerfCounter
+            | Resource::Iommu
+            | Resource::Initrd
+            | Resource::System
+            | Resource::Display => None,
+            Resource::CapEntry(entry) => Some(entry.frame()),
+            Resource::Thread(thread) => Some(thread.frame()),
+            Resource::PageTable { table, flags: _ } => Some(table.frame()),
+            Resource::DmaDomain(domain) => Some(domain.frame()),
+            Resource::Endpoint(endpoint) => Some(endpoint.frame()),
+            Resource::Hierarchy(hierarchy) => Some(hierarchy.frame()),
+        }
+    }
+}
+
+/// Links new child tables along the path to `cap` until it's addressable from `root`.
+///
+/// `next_frame` is called for every table that has to be built and returns the
+/// untyped frame to build it in. Returns the number of tables built.
+pub fn extend<F>(root: KPtr<RawCapEntry>, cap: CapId, mut next_frame: F) -> Result<usize, CapError>
+where
+    F: FnMut() -> Result<RawFrame, CapError>,
+{
+    let mut id = usize::from(cap);
+    let mut table = root;
+    let mut built = 0;
+    loop {
+        let offset = SlotId::try_from(id % NUM_SLOTS).unwrap_or_else(|_| unreachable!());
+        id /= NUM_SLOTS;
+        if id == 0 {
+            return Ok(built);
+        }
+        let slot = table.index_slot(offset);
+        table = match slot.get().child {
+            Some(child) => child,
+            None => {
+                let child = KPtr::new(next_frame()?, RawCapEntry::default())
+                    .map_err(|_| CapError::InvalidArgument)?;
+                slot.change(|slot| slot.child = Some(child.clone()));
+                built += 1;
+                child
+            }
+        };
+    }
 }
 
 impl<T> trie::Ptr<T> for KPtr<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bump_allocator::BumpAllocator;
+
+    #[test_case]
+    fn extend_links_missing_tables() {
+        let mut allocator = BumpAllocator::new();
+        let root = KPtr::new(
+            allocator.alloc_untyped_frame().unwrap(),
+            RawCapEntry::default(),
+        )
+        .unwrap();
+        let cap = CapId::from((NUM_SLOTS * NUM_SLOTS + 5) as u32);
+        assert!(matches!(root.clone().find(cap), Err(CapError::NotFound)));
+
+        // The first table is built and stays linked when the frames run out.
+        let mut frames = 1;
+        let result = extend(root.clone(), cap, || {
+            if frames == 0 {
+                return Err(CapError::OutOfMemory);
+            }
+            frames -= 1;
+            Ok(allocator.alloc_untyped_frame().unwrap())
+        });
+        assert!(matches!(result, Err(CapError::OutOfMemory)));
+        let result = extend(root.clone(), cap, || {
+            Ok(allocator.alloc_untyped_frame().unwrap())
+        });
+        assert_eq!(result.ok(), Some(1));
+        assert!(root.clone().find(cap).is_ok());
+        let result = extend(root.clone(), cap, || Err(CapError::OutOfMemory));
+        assert_eq!(result.ok(), Some(0));
+    }
+}
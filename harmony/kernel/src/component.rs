@@ -1,28 +1,66 @@
 //! A collection of resources provided to userspace threads.
 
-use core::cell::{RefCell, UnsafeCell};
+use core::cell::{Cell, RefCell, UnsafeCell};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
-use kapi::ops::cap_table::{CapTableOp, ConstructArgs};
-use kapi::ops::thread::ThreadOp;
+use kapi::endowment::ResourceKind;
+use kapi::layout::STANDARD;
+use kapi::ops::cap_table::{CapTableOp, ConstructArgs, SLOT_COUNT};
+use kapi::ops::clock::ClockOp;
+use kapi::ops::diagnostics::DiagnosticsOp;
+use kapi::ops::display::DisplayOp;
+use kapi::ops::endpoint::EndpointOp;
+use kapi::ops::hierarchy::HierarchyOp;
+use kapi::ops::initrd::InitrdOp;
+use kapi::ops::iommu::{DmaDomainOp, IommuOp};
+use kapi::ops::ipi::IpiOp;
+use kapi::ops::logger::{LevelFilter, LoggerOp};
+use kapi::ops::page_table::PageTableOp;
+use kapi::ops::perf::PerfOp;
+use kapi::ops::region::RegionOp;
+use kapi::ops::system::{SystemOp, MAX_RANDOM_LEN};
+use kapi::ops::thread::{ThreadOp, ThreadState};
 use kapi::ops::SyscallOp as _;
 use kapi::raw::{CapError, CapId, SyscallArgs};
+use kapi::trace::EventKind;
+use kernel::kbail;
 use sync::cell::AtomicOnceCell;
 
-use crate::arch::exec::{ControlRegs, ExecCtx, Regs, SaveState};
-use crate::arch::interrupts::SyscallCtx;
-use crate::arch::paging::page_table::{Addrspace, AnyPageTable, PageTableFlags};
-use crate::arch::paging::{Page, RawFrame, VirtAddr};
-use crate::caps::{CapEntryExtension as _, PageCapFlags, RawCapEntry, Resource};
-use crate::core_local::CoreLocal;
-use crate::kptr::KPtr;
-use crate::UNTYPED_MEMORY_OFFSET;
+use crate::arch::context::{ControlRegs, ExecCtx, Regs, SaveState, SyscallCtx};
+use crate::arch::exec::KernelStack;
+use crate::arch::interrupts::IRQ_VECTORS;
+use crate::arch::iommu::{self, Domain};
+use crate::arch::paging::page_table::{
+    Addrspace, AnyPageTable, Cleared, MapperError, PageTableEntry, PageTableFlags, PageTableLevel,
+    PageTableOffset,
+};
+use crate::arch::paging::{Page, PhysAddr, PhysAddrExt, RawFrame, VirtAddr, PAGE_SIZE};
+use crate::arch::pmu::{Pmu, PmuError};
+use crate::arch::simd::{self, SimdState};
+use crate::bump_allocator::BumpAllocator;
+use crate::caps::{
+    self, CapEntryExtension as _, DropError, PageCapFlags, RawCapEntry, Resource, TransferError,
+};
+use crate::core_local::{self, CoreLocal, NUM_CORES};
+use crate::endpoint::Endpoint;
+use crate::hierarchy::Hierarchy;
+use crate::kptr::{KPtr, WeakKPtr};
+use crate::logging::{self, Filter};
+use crate::retyping::{PinError, RetypeTable, UserFrame};
+use crate::user_frame::UserFrameGuard;
+use crate::{audit, diagnostics, display, entropy, initrd, ipi, latency, profile, trace};
 
 static ACTIVE_THREAD: AtomicOnceCell<CoreLocal<RefCell<Option<KPtr<Thread>>>>> =
     AtomicOnceCell::new();
 
+/// Thread whose SIMD state is in the registers of each core.
+static SIMD_OWNER: AtomicOnceCell<CoreLocal<RefCell<Option<KPtr<Thread>>>>> = AtomicOnceCell::new();
+
 pub fn init() {
     let threads = CoreLocal::new_with(|_| RefCell::new(None));
     ACTIVE_THREAD.set(threads).unwrap();
+    let owners = CoreLocal::new_with(|_| RefCell::new(None));
+    SIMD_OWNER.set(owners).unwrap();
 }
 
 // TODO: Implement thread migration
@@ -34,26 +72,409 @@ pub fn init() {
 pub struct Thread {
     // FIXME: This is not the correct way to do this...
     exec_ctx: UnsafeCell<ExecCtx>,
+    /// Saved while another thread uses the SIMD registers.
+    simd: UnsafeCell<SimdState>,
     resources: KPtr<RawCapEntry>,
+    kernel_stack: KPtr<KernelStack>,
+    /// Bitmask of the cores this thread may be dispatched on.
+    affinity: AtomicU64,
+    /// Operation to pick up once the thread re-executes its syscall.
+    continuation: Cell<Option<Continuation>>,
+    /// Whether the thread is active on some core.
+    running: AtomicBool,
+    /// Whether the thread was suspended, which keeps it from being activated.
+    suspended: AtomicBool,
+    /// Whether the thread was suspended by a fault and hasn't been resumed.
+    faulted: AtomicBool,
+    /// Hierarchy the thread was adopted into, if any.
+    hierarchy: AtomicOnceCell<WeakKPtr<Hierarchy>>,
+}
+
+/// Progress of an operation that couldn't complete in a single pass.
+///
+/// Rather than blocking inside the kernel, an operation records how far it got
+/// and the thread is rewound to its syscall instruction. When the thread runs
+/// again it re-issues the same request and the operation resumes from
+/// `cursor`. Operations must therefore be idempotent up to the cursor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Continuation {
+    capability: CapId,
+    args: SyscallArgs,
+    cursor: usize,
 }
 
 impl Thread {
-    pub fn new(regs: Regs, l4_table: KPtr<AnyPageTable>, resources: KPtr<RawCapEntry>) -> Self {
+    pub fn new(
+        regs: Regs,
+        l4_table: KPtr<AnyPageTable>,
+        resources: KPtr<RawCapEntry>,
+        kernel_stack: KPtr<KernelStack>,
+    ) -> Self {
         let exec_ctx = ExecCtx::new(l4_table.into_raw(), regs);
-        Self::new_with_ctx(exec_ctx, resources)
+        Self::new_with_ctx(exec_ctx, resources, kernel_stack)
     }
 
-    pub fn new_with_ctx(ctx: ExecCtx, resources: KPtr<RawCapEntry>) -> Self {
+    pub fn new_with_ctx(
+        ctx: ExecCtx,
+        resources: KPtr<RawCapEntry>,
+        kernel_stack: KPtr<KernelStack>,
+    ) -> Self {
         Self {
             exec_ctx: UnsafeCell::new(ctx),
+            simd: UnsafeCell::new(SimdState::new()),
             resources,
+            kernel_stack,
+            affinity: AtomicU64::new(u64::MAX),
+            continuation: Cell::new(None),
+            running: AtomicBool::new(false),
+            suspended: AtomicBool::new(false),
+            faulted: AtomicBool::new(false),
+            hierarchy: AtomicOnceCell::new(),
+        }
+    }
+
+    /// Builds a thread in the untyped `frame` with its stack in `kernel_stack`.
+    ///
+    /// Both frames are left untyped if the thread can't be built.
+    pub fn construct(
+        frame: RawFrame,
+        kernel_stack: RawFrame,
+        regs: Regs,
+        l4_table: KPtr<AnyPageTable>,
+        resources: KPtr<RawCapEntry>,
+    ) -> Result<KPtr<Self>, CapError> {
+        let kernel_stack =
+            KPtr::new(kernel_stack, KernelStack::new()).map_err(|_| CapError::InvalidArgument)?;
+        let frame = match frame.try_into_kernel() {
+            Ok(frame) => frame,
+            Err(_) => {
+                kernel_stack.into_untyped();
+                return Err(CapError::InvalidArgument);
+            }
+        };
+        let thread = Thread::new(regs, l4_table, resources, kernel_stack);
+        // SAFETY: The frame was just retyped so nothing else refers to it.
+        Ok(unsafe { KPtr::new_unchecked(frame, thread) })
+    }
+
+    pub fn affinity(&self) -> u64 {
+        self.affinity.load(Ordering::Relaxed)
+    }
+
+    /// Sets the cores this thread may run on.
+    ///
+    /// Returns `false` if the mask doesn't include any of the cores present.
+    pub fn set_affinity(&self, mask: u64) -> bool {
+        if mask & present_cores() == 0 {
+            return false;
+        }
+        self.affinity.store(mask, Ordering::Relaxed);
+        true
+    }
+
+    /// Whether the thread is allowed to run on the current core.
+    pub fn can_run_here(&self) -> bool {
+        self.affinity() & (1 << core_local::current_core()) != 0
+    }
+
+    /// Whether the thread is the active thread of any core.
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+    }
+
+    /// Whether the thread was suspended or its hierarchy was killed.
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.load(Ordering::Acquire)
+            || self
+                .hierarchy()
+                .is_some_and(|hierarchy| hierarchy.is_killed())
+    }
+
+    pub fn state(&self) -> ThreadState {
+        Self::state_of(
+            self.is_running(),
+            self.is_suspended(),
+            self.faulted.load(Ordering::Acquire),
+        )
+    }
+
+    /// A faulted thread keeps its core while it waits, so faults take
+    /// precedence over running.
+    fn state_of(running: bool, suspended: bool, faulted: bool) -> ThreadState {
+        match (running, suspended, faulted) {
+            (_, true, true) => ThreadState::Faulted,
+            (true, _, _) => ThreadState::Running,
+            (false, true, _) => ThreadState::Suspended,
+            (false, false, _) => ThreadState::Ready,
+        }
+    }
+
+    /// Keeps the thread from being activated or scheduled until it's resumed,
+    /// returning its state from before.
+    ///
+    /// The thread isn't taken off its core if it's running.
+    pub fn suspend(&self) -> ThreadState {
+        let suspended = self.suspended.swap(true, Ordering::AcqRel);
+        Self::state_of(
+            self.is_running(),
+            suspended,
+            self.faulted.load(Ordering::Acquire),
+        )
+    }
+
+    /// Suspends the thread after it faulted, so that it's reported as
+    /// [`ThreadState::Faulted`] until it's resumed.
+    pub fn fault(&self) {
+        self.faulted.store(true, Ordering::Release);
+        self.suspended.store(true, Ordering::Release);
+    }
+
+    /// Lets the thread be activated again, returning its state from before.
+    pub fn resume(&self) -> ThreadState {
+        let faulted = self.faulted.swap(false, Ordering::AcqRel);
+        let suspended = self.suspended.swap(false, Ordering::AcqRel);
+        Self::state_of(self.is_running(), suspended, faulted)
+    }
+
+    /// Hierarchy the thread belongs to, unless it was released.
+    pub fn hierarchy(&self) -> Option<KPtr<Hierarchy>> {
+        self.hierarchy.get()?.upgrade()
+    }
+
+    /// Records that the thread was adopted into `hierarchy`.
+    ///
+    /// Returns `false` if it already belongs to one.
+    pub fn join(&self, hierarchy: WeakKPtr<Hierarchy>) -> bool {
+        self.hierarchy.set(hierarchy).is_ok()
+    }
+
+    /// Counts a fault raised by the thread in its hierarchy and the ones
+    /// above it.
+    pub fn report_fault(&self) {
+        if let Some(hierarchy) = self.hierarchy() {
+            hierarchy.report_fault();
         }
     }
 
+    /// Asks for the current syscall to be re-executed once the thread resumes.
+    ///
+    /// The operation will see `cursor` from [`Thread::resume_cursor`] on the
+    /// next pass.
+    fn restart_later(&self, capability: CapId, args: SyscallArgs, cursor: usize) {
+        self.continuation.set(Some(Continuation {
+            capability,
+            args,
+            cursor,
+        }));
+    }
+
+    /// Returns the progress made by earlier passes of this same request.
+    ///
+    /// A continuation left by a different request is discarded.
+    fn resume_cursor(&self, capability: CapId, args: SyscallArgs) -> usize {
+        match self.continuation.take() {
+            Some(continuation)
+                if continuation.capability == capability && continuation.args == args =>
+            {
+                continuation.cursor
+            }
+            _ => 0,
+        }
+    }
+
+    /// Whether the last syscall asked to be re-executed.
+    pub fn restart_pending(&self) -> bool {
+        self.continuation.get().is_some()
+    }
+
     pub fn addrspace(&self) -> Addrspace<'_> {
         unsafe { Addrspace::from_frame((*self.exec_ctx.get()).l4_frame()) }
     }
 
+    fn l4_frame(&self) -> RawFrame {
+        unsafe { (*self.exec_ctx.get()).l4_frame() }
+    }
+
+    /// Unmaps the user half of the thread's address space, releasing the
+    /// frames nothing else references, until `budget` runs out. Pinned pages
+    /// lose their pin along with the mapping.
+    ///
+    /// Returns whether it's empty.
+    ///
+    /// # Safety
+    ///
+    /// Every TLB must be flushed before the thread runs again or the frames
+    /// are reused.
+    unsafe fn clear_addrspace(&self, budget: &mut usize) -> bool {
+        // SAFETY: Threads are built with a level 4 table.
+        let table: &AnyPageTable = unsafe { &*self.l4_frame().base().to_virtual().as_ptr() };
+        let mut release = |cleared: Cleared| {
+            // SAFETY: The entry was just cleared.
+            unsafe { cleared.release(true) };
+        };
+        // SAFETY: Precondition.
+        unsafe { table.clear(PageTableLevel::top(), false, budget, &mut release) }
+    }
+
+    /// Returns the frame behind `address` in one of the thread's untyped
+    /// memory windows.
+    fn untyped_frame(&self, address: usize) -> Result<RawFrame, CapError> {
+        if !STANDARD.untyped.contains(address, PAGE_SIZE) {
+            return Err(CapError::InvalidArgument);
+        }
+        let page = Page::try_from_start_address(
+            VirtAddr::try_new(address).map_err(|_| CapError::InvalidArgument)?,
+        )
+        .map_err(|_| CapError::InvalidArgument)?;
+
+        let (frame, flags) = self
+            .addrspace()
+            .get(page)
+            .ok_or(CapError::InvalidArgument)?;
+        // Windows are never user accessible, so anything else is a page the
+        // thread mapped for itself.
+        if !flags.contains(PageTableFlags::PRESENT)
+            || flags.contains(PageTableFlags::USER_ACCESSIBLE)
+        {
+            return Err(CapError::InvalidArgument);
+        }
+        if !caps::holds_frame(&self.resources, frame) {
+            return Err(CapError::FrameOutsideOfRegion);
+        }
+        Ok(frame)
+    }
+
+    /// Returns the frame the thread mapped for itself at `address`, which has
+    /// to be in one of the regions it holds.
+    fn user_frame(&self, address: usize) -> Result<RawFrame, CapError> {
+        check_user_range(address, PAGE_SIZE)?;
+        let page = Page::try_from_start_address(
+            VirtAddr::try_new(address).map_err(|_| CapError::InvalidArgument)?,
+        )
+        .map_err(|_| CapError::InvalidArgument)?;
+        let (frame, flags) = self
+            .addrspace()
+            .get(page)
+            .ok_or(CapError::InvalidArgument)?;
+        if !flags.contains(PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE) {
+            return Err(CapError::InvalidArgument);
+        }
+        if !caps::holds_frame(&self.resources, frame) {
+            return Err(CapError::FrameOutsideOfRegion);
+        }
+        Ok(frame)
+    }
+
+    /// Checks that the thread can access `len` bytes at `addr` in its address
+    /// space, and write to them if `write` is set.
+    ///
+    /// Syscalls copy from and to user memory through this, so that memory
+    /// that isn't mapped for the thread fails the call instead of faulting in
+    /// the kernel.
+    fn check_user_memory(&self, addr: usize, len: usize, write: bool) -> Result<(), CapError> {
+        check_user_range(addr, len)?;
+        let mut required = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        if write {
+            required |= PageTableFlags::WRITABLE;
+        }
+        let addrspace = self.addrspace();
+        let end = addr + len;
+        let mut page = addr & !(PAGE_SIZE - 1);
+        while page < end {
+            let (flags, level) = addrspace
+                .user_flags(Page::from_start_address(VirtAddr::new(page)))
+                .ok_or(CapError::InvalidArgument)?;
+            if !flags.contains(required) {
+                return Err(CapError::InvalidArgument);
+            }
+            // Huge pages are checked once.
+            let span = PAGE_SIZE << (9 * (level.level() - 1));
+            page = (page & !(span - 1)) + span;
+        }
+        Ok(())
+    }
+
+    /// Copies a `T` out of the thread's address space.
+    ///
+    /// Unlike [`Thread::user_str`], this reads the frames through the
+    /// thread's page tables and the direct map, so the thread's address space
+    /// doesn't have to be the active one.
+    ///
+    /// # Safety
+    ///
+    /// Any bytes have to be a valid `T`.
+    unsafe fn read_user<T: Copy>(&self, ptr: *const T) -> Result<T, CapError> {
+        let len = core::mem::size_of::<T>();
+        if !ptr.is_aligned() {
+            return Err(CapError::InvalidArgument);
+        }
+        self.check_user_memory(ptr as usize, len, false)?;
+        let addrspace = self.addrspace();
+        let mut value = core::mem::MaybeUninit::<T>::uninit();
+        let out = value.as_mut_ptr() as *mut u8;
+        let mut copied = 0;
+        while copied < len {
+            let addr = ptr as usize + copied;
+            let (entry, level) = addrspace
+                .leaf(Page::containing_address(VirtAddr::new(addr)))
+                .ok_or(CapError::InvalidArgument)?;
+            let (frame, _) = entry.get().ok_or(CapError::InvalidArgument)?;
+            // Huge pages map many frames, and only the one holding `addr` is
+            // borrowed.
+            let span = PAGE_SIZE << (9 * (level.level() - 1));
+            let offset = addr & (span - 1) & !(PAGE_SIZE - 1);
+            let frame =
+                RawFrame::from_start_address(PhysAddr::new(frame.base().as_u64() + offset as u64));
+            let guard = UserFrameGuard::new(frame).map_err(|_| CapError::InvalidArgument)?;
+            let start = addr % PAGE_SIZE;
+            let chunk = (PAGE_SIZE - start).min(len - copied);
+            // SAFETY: `out` has room for `len` bytes, past the `copied` ones.
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    guard.bytes()[start..].as_ptr(),
+                    out.add(copied),
+                    chunk,
+                );
+            }
+            copied += chunk;
+        }
+        // SAFETY: Every byte was copied, and any bytes are a valid `T`.
+        Ok(unsafe { value.assume_init() })
+    }
+
+    /// Borrows a buffer in the thread's address space.
+    ///
+    /// # Safety
+    ///
+    /// The thread's address space must be the active one.
+    unsafe fn user_slice_mut<'a, T>(
+        &self,
+        ptr: *mut T,
+        len: usize,
+    ) -> Result<&'a mut [T], CapError> {
+        let bytes = len
+            .checked_mul(core::mem::size_of::<T>())
+            .ok_or(CapError::InvalidArgument)?;
+        if !ptr.is_aligned() {
+            return Err(CapError::InvalidArgument);
+        }
+        self.check_user_memory(ptr as usize, bytes, true)?;
+        // SAFETY: The range is mapped writable for the thread.
+        Ok(unsafe { core::slice::from_raw_parts_mut(ptr, len) })
+    }
+
+    /// Reads a string from the thread's address space.
+    ///
+    /// # Safety
+    ///
+    /// The thread's address space must be the active one.
+    unsafe fn user_str<'a>(&self, ptr: *const u8, len: usize) -> Result<&'a str, CapError> {
+        self.check_user_memory(ptr as usize, len, false)?;
+        // SAFETY: The range is mapped for the thread.
+        let bytes = unsafe { core::slice::from_raw_parts(ptr, len) };
+        core::str::from_utf8(bytes).map_err(|_| CapError::InvalidArgument)
+    }
+
     pub fn current() -> Option<KPtr<Thread>> {
         ACTIVE_THREAD.get().unwrap().get().borrow().clone()
     }
@@ -64,9 +485,10 @@ impl Thread {
         // dispatching is somewhat weird because we exit the kernel early on the
         // dispatch and never return back to the caller in a traditional sense (i.e.
         // dispatch return !). The way we come back is by having another dispatch
-        // call back into the original thread. Note, we have a singular kernel
-        // execution stack, so once we leave here, the stack will be mangled and
-        // can't come back to the kernel to return to the normal flow of execution.
+        // call back into the original thread. Each thread enters the kernel on
+        // its own stack, but dispatching still abandons whatever is on the
+        // current one, so we can't come back here to return to the normal flow
+        // of execution.
         //
         // When that happens, the state of the (current) thread needs to be valid,
         // specifically, to the thread it needs to look like the original Activate
@@ -76,22 +498,73 @@ impl Thread {
         // 2. rflags register needs to be valid (interrupts enabled, ring 3 execution, etc.)
         // 3. stack register needs to be whatever it was before syscall
         // 4. All callee-saved registers need to be set back (done in userspace)
+        // Whatever brought us here, we are about to return to userspace.
+        crate::arch::interrupts::exit_irq_context();
         // SAFETY: Running a syscall.
         {
             let mut current = ACTIVE_THREAD.get().unwrap().get().borrow_mut();
+            let previous = current.as_ref().map_or(0, trace::thread_id);
+            trace::event(EventKind::ContextSwitch, trace::thread_id(&this), previous);
             if let Some(ref current) = *current {
                 let regs = unsafe { (*current.exec_ctx.get()).regs_mut() };
                 saver.save_state(regs);
+                current.running.store(false, Ordering::Release);
             }
+            this.running.store(true, Ordering::Release);
             current.replace(this.clone());
         }
+        let owner = SIMD_OWNER.get().unwrap().get().borrow();
+        simd::set_trap(owner.as_ref() != Some(&this));
+        drop(owner);
         log::info!("Set the active thread");
-        unsafe { (*this.exec_ctx.get()).dispatch() }
+        // SAFETY: The active thread keeps its stack alive and a thread is only
+        // ever active on a single core.
+        unsafe {
+            this.kernel_stack.activate();
+            (*this.exec_ctx.get()).dispatch()
+        }
     }
 }
 
 impl Thread {
+    /// Gives the SIMD registers to the current thread, saving them for the
+    /// thread that used them last.
+    ///
+    /// # Safety
+    ///
+    /// Must be handling the #NM a thread raised with its first SIMD
+    /// instruction since it was dispatched.
+    pub unsafe fn take_simd() {
+        let current = Thread::current().expect("SIMD used without a thread");
+        let mut owner = SIMD_OWNER.get().unwrap().get().borrow_mut();
+        simd::set_trap(false);
+        // SAFETY: The registers are usable, and the states are only accessed
+        // while their thread isn't running.
+        unsafe {
+            if let Some(ref owner) = *owner {
+                (*owner.simd.get()).save();
+            }
+            (*current.simd.get()).restore();
+        }
+        owner.replace(current);
+    }
+
+    /// Returns the resource held by `capability` if there is one.
+    pub fn resource(&self, capability: CapId) -> Option<Resource> {
+        Some(self.resources.clone().find(capability).ok()?.get().resource)
+    }
+
+    /// Runs the operation in `args` on `capability` and logs it in the
+    /// [audit log](audit) once it's done.
     pub fn exercise_cap(&self, capability: CapId, args: SyscallArgs) -> Result<usize, CapError> {
+        let result = self.exercise(capability, args);
+        if !self.restart_pending() {
+            audit::record(capability, args, &result);
+        }
+        result
+    }
+
+    fn exercise(&self, capability: CapId, args: SyscallArgs) -> Result<usize, CapError> {
         let slot = self.resources.clone().find(capability)?.get();
         match slot.resource {
             Resource::Empty => Err(CapError::NotFound),
@@ -118,24 +591,16 @@ impl Thread {
                         });
                         Ok(0)
                     }
-                    CapTableOp::Construct { kind, region, slot } => {
-                        if region > RawFrame::memory_limit() {
-                            return Err(CapError::InvalidArgument);
-                        }
-                        let page_address = region + UNTYPED_MEMORY_OFFSET;
-                        let region = Page::try_from_start_address(
-                            VirtAddr::try_new(page_address)
-                                .map_err(|_| CapError::InvalidArgument)?,
-                        )
-                        .map_err(|_| CapError::InvalidArgument)?;
-
-                        let (frame, flags) = self
-                            .addrspace()
-                            .get(region)
-                            .ok_or(CapError::InvalidArgument)?;
-                        if !flags.contains(PageTableFlags::PRESENT) {
-                            return Err(CapError::InvalidArgument);
-                        }
+                    CapTableOp::Construct {
+                        args: kind,
+                        region,
+                        slot,
+                    } => {
+                        // SAFETY: The raw arguments are plain words.
+                        let raw = unsafe { self.read_user(kind)? };
+                        let kind =
+                            ConstructArgs::try_from(raw).map_err(|_| CapError::InvalidArgument)?;
+                        let frame = self.untyped_frame(region)?;
                         let resource = match kind {
                             ConstructArgs::CapTable => {
                                 let ptr = KPtr::new(frame, RawCapEntry::default())
@@ -147,6 +612,7 @@ impl Thread {
                                 stack_pointer,
                                 cap_table,
                                 page_table,
+                                kernel_stack,
                             } => {
                                 let regs = Regs {
                                     control: ControlRegs {
@@ -160,13 +626,18 @@ impl Thread {
                                     self.resources.clone().get_resource_as(cap_table)?;
                                 let (page_table, flags): (KPtr<AnyPageTable>, PageCapFlags) =
                                     self.resources.clone().get_resource_as(page_table)?;
-                                if !flags.level() == 4 {
+                                if flags.level() != 4 {
                                     return Err(CapError::InvalidArgument);
                                 }
-                                Resource::Thread(
-                                    KPtr::new(frame, Thread::new(regs, page_table, cap_table))
-                                        .map_err(|_| CapError::InvalidArgument)?,
-                                )
+                                check_stack(&page_table, stack_pointer)?;
+                                let kernel_stack = self.untyped_frame(kernel_stack)?;
+                                Resource::Thread(Thread::construct(
+                                    frame,
+                                    kernel_stack,
+                                    regs,
+                                    page_table,
+                                    cap_table,
+                                )?)
                             }
                             ConstructArgs::PageTable { level } => {
                                 if level > 4 || level == 0 {
@@ -184,31 +655,2073 @@ impl Thread {
                                     flags,
                                 }
                             }
+                            ConstructArgs::DmaDomain { iommu, requester } => {
+                                let iommu = self.resources.clone().get_capability(iommu)?;
+                                if !matches!(iommu.resource, Resource::Iommu) {
+                                    return Err(CapError::InvalidArgument);
+                                }
+                                Resource::DmaDomain(Domain::construct(frame, requester)?)
+                            }
+                            ConstructArgs::Endpoint { buffer, pages } => {
+                                let mut regions = (0..usize::from(pages))
+                                    .map(|i| buffer.checked_add(i * PAGE_SIZE));
+                                Resource::Endpoint(Endpoint::construct(
+                                    frame,
+                                    pages.into(),
+                                    || {
+                                        let region = regions
+                                            .next()
+                                            .ok_or(CapError::OutOfMemory)?
+                                            .ok_or(CapError::InvalidArgument)?;
+                                        self.untyped_frame(region)
+                                    },
+                                )?)
+                            }
+                            ConstructArgs::Hierarchy { parent } => {
+                                let parent = parent
+                                    .map(|parent| self.resources.clone().get_resource_as(parent))
+                                    .transpose()?;
+                                match Hierarchy::construct(frame, parent) {
+                                    Some(hierarchy) => Resource::Hierarchy(hierarchy?),
+                                    None => {
+                                        self.resume_cursor(capability, args);
+                                        self.restart_later(capability, args, 0);
+                                        return Ok(0);
+                                    }
+                                }
+                            }
                         };
                         capability_table.index_slot(slot).change(|cap| {
                             cap.resource = resource;
                         });
                         Ok(0)
                     }
-                    CapTableOp::Drop { slot: _ } => todo!(),
-                    CapTableOp::Copy {
-                        slot: _,
-                        other_table_cap: _,
-                        other_slot: _,
-                    } => todo!(),
+                    CapTableOp::Drop { slot } => {
+                        self.resume_cursor(capability, args);
+                        let slot = capability_table.index_slot(slot);
+                        let resource = match caps::drop_resource(&slot) {
+                            Ok(resource) => resource,
+                            Err(DropError::Retry) => {
+                                self.restart_later(capability, args, 0);
+                                return Ok(0);
+                            }
+                            Err(DropError::Empty) => return Err(CapError::NotFound),
+                            Err(DropError::HoldsMemory) => return Err(CapError::ResourceInUse),
+                        };
+                        // Objects nobody else references go back to untyped
+                        // memory. Anything they reference is only released
+                        // once its own slots are dropped.
+                        let _ = match resource {
+                            Resource::CapEntry(table) => table.into_untyped(),
+                            Resource::Thread(thread) => thread.into_untyped(),
+                            Resource::PageTable { table, .. } => table.into_untyped(),
+                            Resource::Endpoint(endpoint) => endpoint.into_untyped(),
+                            Resource::Hierarchy(hierarchy) => hierarchy.into_untyped(),
+                            _ => None,
+                        };
+                        Ok(0)
+                    }
+                    CapTableOp::Copy { .. } => {
+                        kbail!(CapError::InvalidOp, "cap_table.copy isn't implemented")
+                    }
+                    CapTableOp::Extend {
+                        cap,
+                        region,
+                        frames,
+                    } => {
+                        let mut regions = (0..frames).map(|i| region.checked_add(i * PAGE_SIZE));
+                        caps::extend(capability_table, cap, || {
+                            let region = regions
+                                .next()
+                                .ok_or(CapError::OutOfMemory)?
+                                .ok_or(CapError::InvalidArgument)?;
+                            self.untyped_frame(region)
+                        })
+                    }
                 }
             }
             Resource::Thread(thread) => {
                 let operation = ThreadOp::from_args(args).map_err(|_| CapError::InvalidArgument)?;
                 match operation {
                     ThreadOp::Activate => {
+                        if thread.is_suspended() {
+                            return Err(CapError::Suspended);
+                        }
+                        if !thread.can_run_here() {
+                            return Err(CapError::WrongCore);
+                        }
                         let ctx = unsafe { SyscallCtx::current() };
                         Thread::dispatch(thread, ctx);
                     }
-                    ThreadOp::ChangeAffinity => todo!(),
+                    ThreadOp::SetAffinity { mask } => {
+                        if !thread.set_affinity(mask) {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        Ok(0)
+                    }
+                    ThreadOp::GetAffinity => Ok(thread.affinity() as usize),
+                    ThreadOp::Suspend => {
+                        let state = thread.suspend();
+                        if !core::ptr::eq(&*thread, self) {
+                            trace::event_current(
+                                EventKind::ThreadSuspended,
+                                trace::thread_id(&thread),
+                            );
+                        }
+                        Ok(u8::from(state).into())
+                    }
+                    ThreadOp::Resume => {
+                        let state = thread.resume();
+                        if !core::ptr::eq(&*thread, self) {
+                            trace::event_current(
+                                EventKind::ThreadResumed,
+                                trace::thread_id(&thread),
+                            );
+                        }
+                        Ok(u8::from(state).into())
+                    }
+                    ThreadOp::GetState => Ok(u8::from(thread.state()).into()),
+                    #[cfg(feature = "round-robin")]
+                    ThreadOp::Schedule => match crate::sched::enqueue(thread) {
+                        Ok(()) => Ok(0),
+                        Err(crate::sched::SchedError::QueueFull) => Err(CapError::OutOfMemory),
+                        Err(crate::sched::SchedError::AlreadyQueued) => {
+                            Err(CapError::ResourceInUse)
+                        }
+                    },
+                    #[cfg(not(feature = "round-robin"))]
+                    ThreadOp::Schedule => Err(CapError::InvalidOp),
+                    ThreadOp::Yield => {
+                        if !core::ptr::eq(&*thread, self) {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        #[cfg(feature = "round-robin")]
+                        if let Some(next) = crate::sched::yield_now(thread) {
+                            // SAFETY: We are handling a syscall.
+                            let ctx = unsafe { SyscallCtx::current() };
+                            Thread::dispatch(next, ctx);
+                        }
+                        Ok(0)
+                    }
+                    ThreadOp::YieldTo { thread: target } => {
+                        if !core::ptr::eq(&*thread, self) {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        let Some(Resource::Thread(target)) = self.resource(target) else {
+                            return Err(CapError::InvalidArgument);
+                        };
+                        if target.is_suspended() {
+                            return Err(CapError::Suspended);
+                        }
+                        if !target.can_run_here() {
+                            return Err(CapError::WrongCore);
+                        }
+                        if target.is_running() {
+                            return Err(CapError::ResourceInUse);
+                        }
+                        #[cfg(feature = "round-robin")]
+                        crate::sched::donate(thread);
+                        // SAFETY: We are handling a syscall.
+                        let ctx = unsafe { SyscallCtx::current() };
+                        Thread::dispatch(target, ctx);
+                    }
+                }
+            }
+            Resource::PageTable { table, flags } => {
+                let operation =
+                    PageTableOp::from_args(args).map_err(|_| CapError::InvalidArgument)?;
+                match operation {
+                    #[cfg(feature = "debug-ops")]
+                    PageTableOp::DumpMappings { buffer, capacity } => {
+                        let Ok(level) = PageTableLevel::try_new(flags.level()) else {
+                            kbail!(
+                                CapError::Internal,
+                                "page table capability of level {}",
+                                flags.level()
+                            );
+                        };
+                        // SAFETY: We are handling a syscall from this thread.
+                        let records = unsafe { self.user_slice_mut(buffer, capacity)? };
+                        let mut count = 0;
+                        table.for_each_mapping(level, 0, &mut |virt, frame, flags, level| {
+                            if let Some(record) = records.get_mut(count) {
+                                *record = kapi::ops::page_table::MappingRecord {
+                                    virt: virt as u64,
+                                    phys: frame.base().as_u64(),
+                                    flags: flags.bits(),
+                                    level: level.level(),
+                                };
+                            }
+                            count += 1;
+                        });
+                        Ok(count)
+                    }
+                    #[cfg(not(feature = "debug-ops"))]
+                    PageTableOp::DumpMappings { .. } => Err(CapError::InvalidOp),
+                    PageTableOp::Clear { release } => {
+                        let Ok(level) = PageTableLevel::try_new(flags.level()) else {
+                            kbail!(
+                                CapError::Internal,
+                                "page table capability of level {}",
+                                flags.level()
+                            );
+                        };
+                        let mut unmapped = self.resume_cursor(capability, args);
+                        let mut budget = CLEAR_BUDGET;
+                        let mut drop_entry = |cleared: Cleared| {
+                            // SAFETY: The entry was just cleared.
+                            if unsafe { cleared.release(release) } {
+                                unmapped += 1;
+                            }
+                        };
+                        // SAFETY: Every TLB is flushed before the thread
+                        // returns.
+                        let done =
+                            unsafe { table.clear(level, true, &mut budget, &mut drop_entry) };
+                        if ipi::shootdown_all().is_err() || !done {
+                            self.restart_later(capability, args, unmapped);
+                        } else if !table.is_cleared(level) {
+                            // Pinned pages are only unmapped once unpinned.
+                            return Err(CapError::ResourceInUse);
+                        }
+                        Ok(unmapped)
+                    }
+                    PageTableOp::Evict {
+                        page,
+                        token,
+                        buffer,
+                    } => {
+                        self.resume_cursor(capability, args);
+                        if flags.level() != 4 {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        check_user_range(page, PAGE_SIZE)?;
+                        let page = Page::try_from_start_address(
+                            VirtAddr::try_new(page).map_err(|_| CapError::InvalidArgument)?,
+                        )
+                        .map_err(|_| CapError::InvalidArgument)?;
+                        // SAFETY: Level 4 tables are root tables.
+                        let addrspace = unsafe { table.as_addrspace() };
+                        let evicted = match addrspace.get(page) {
+                            Some((frame, flags))
+                                if flags.contains(PageTableFlags::USER_ACCESSIBLE) =>
+                            {
+                                // A device may be writing to it.
+                                if frame.try_as_user().is_ok_and(|frame| frame.is_pinned()) {
+                                    return Err(CapError::ResourceInUse);
+                                }
+                                frame
+                            }
+                            _ => return Err(CapError::InvalidArgument),
+                        };
+                        // SAFETY: We are handling a syscall from this thread.
+                        let buffer = unsafe { self.user_slice_mut(buffer, PAGE_SIZE)? };
+                        // The buffer can't be in the frame it's filled from,
+                        // and if it's in the evicted page the kernel would
+                        // fault on it once the page is gone.
+                        let start = buffer.as_ptr() as usize & !(PAGE_SIZE - 1);
+                        let own = self.addrspace();
+                        if [start, start + PAGE_SIZE].into_iter().any(|address| {
+                            own.get(Page::from_start_address(VirtAddr::new(address)))
+                                .is_some_and(|(frame, _)| frame == evicted)
+                        }) {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        // SAFETY: Every TLB is flushed before the frame is read.
+                        let (frame, flags) = unsafe { addrspace.evict(page, token) }
+                            .map_err(|_| CapError::InvalidArgument)?;
+                        if ipi::shootdown_all().is_err() {
+                            // Put the page back until every core can be flushed.
+                            // SAFETY: The page was just swapped out by us.
+                            unsafe { addrspace.restore(page, frame, flags) };
+                            self.restart_later(capability, args, 0);
+                            return Ok(0);
+                        }
+                        // The frame is no longer mapped anywhere the owner
+                        // can write to it.
+                        match UserFrameGuard::new(frame) {
+                            Ok(contents) => buffer.copy_from_slice(contents.bytes()),
+                            Err(e) => {
+                                log::error!("Can't read evicted {frame:?}: {e:?}");
+                                // SAFETY: The page was just swapped out by us.
+                                unsafe { addrspace.restore(page, frame, flags) };
+                                return Err(CapError::InvalidArgument);
+                            }
+                        }
+                        // SAFETY: The mapping owned a reference.
+                        if unsafe { frame.drop_user_ref() } == Some(0) {
+                            let _ = frame.try_into_untyped();
+                        }
+                        Ok(0)
+                    }
+                    PageTableOp::Pin { page } => {
+                        if flags.level() != 4 {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        let (entry, frame) = user_page(&table, page)?;
+                        // The mapping holds the pin, so a page is pinned at
+                        // most once.
+                        if !entry.set_pinned(true) {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        match frame.pin() {
+                            Ok(_) => Ok(frame.frame().addr().as_u64() as usize),
+                            Err(e) => {
+                                entry.set_pinned(false);
+                                match e {
+                                    PinError::MaxPins => Err(CapError::ResourceInUse),
+                                    _ => Err(CapError::InvalidArgument),
+                                }
+                            }
+                        }
+                    }
+                    PageTableOp::Unpin { page } => {
+                        if flags.level() != 4 {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        // Only pins taken through this page are dropped, never
+                        // ones held by someone else, e.g. an IOMMU grant.
+                        let (entry, frame) = user_page(&table, page)?;
+                        if !entry.set_pinned(false) {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        frame
+                            .unpin()
+                            .map(|_| 0)
+                            .map_err(|_| CapError::InvalidArgument)
+                    }
+                }
+            }
+            Resource::Logger => {
+                let operation = LoggerOp::from_args(args).map_err(|_| CapError::InvalidArgument)?;
+                match operation {
+                    LoggerOp::SetFilter {
+                        sink,
+                        sink_len,
+                        filter,
+                        filter_len,
+                    } => {
+                        // SAFETY: We are handling a syscall from this thread.
+                        let (sink, filter) = unsafe {
+                            (
+                                self.user_str(sink, sink_len)?,
+                                self.user_str(filter, filter_len)?,
+                            )
+                        };
+                        let filter =
+                            Filter::parse(filter).map_err(|_| CapError::InvalidArgument)?;
+                        logging::set_filter(sink, filter).map_err(|_| CapError::NotFound)?;
+                        Ok(0)
+                    }
+                    LoggerOp::SetLevel {
+                        sink,
+                        sink_len,
+                        level,
+                    } => {
+                        // SAFETY: We are handling a syscall from this thread.
+                        let sink = unsafe { self.user_str(sink, sink_len)? };
+                        let mut filter = logging::filter(sink).map_err(|_| CapError::NotFound)?;
+                        filter.set_default(log_level(level));
+                        logging::set_filter(sink, filter).map_err(|_| CapError::NotFound)?;
+                        Ok(0)
+                    }
+                    LoggerOp::SetModuleLevel {
+                        names,
+                        sink_len,
+                        module_len,
+                        level,
+                    } => {
+                        let len = sink_len
+                            .checked_add(module_len)
+                            .ok_or(CapError::InvalidArgument)?;
+                        // SAFETY: We are handling a syscall from this thread.
+                        let names = unsafe { self.user_str(names, len)? };
+                        let (sink, module) = names
+                            .split_at_checked(sink_len)
+                            .ok_or(CapError::InvalidArgument)?;
+                        let mut filter = logging::filter(sink).map_err(|_| CapError::NotFound)?;
+                        filter
+                            .set_module(module, log_level(level))
+                            .map_err(|_| CapError::InvalidArgument)?;
+                        logging::set_filter(sink, filter).map_err(|_| CapError::NotFound)?;
+                        Ok(0)
+                    }
+                    LoggerOp::InjectMarker { text, len } => {
+                        // SAFETY: We are handling a syscall from this thread.
+                        let text = unsafe { self.user_str(text, len)? };
+                        // A marker is a single line.
+                        if text.contains(['\n', '\r']) {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        logging::marker(text);
+                        Ok(0)
+                    }
+                    LoggerOp::Write { buffer, len } => {
+                        // SAFETY: We are handling a syscall from this thread.
+                        let text = unsafe { self.user_str(buffer, len)? };
+                        crate::sprint!("{text}");
+                        Ok(0)
+                    }
+                }
+            }
+            Resource::Ipi => {
+                let operation = IpiOp::from_args(args).map_err(|_| CapError::InvalidArgument)?;
+                match operation {
+                    IpiOp::Send { core, work } => {
+                        self.resume_cursor(capability, args);
+                        match ipi::send(core, work) {
+                            Ok(()) => Ok(0),
+                            // Wait for the target to drain its queue.
+                            Err(ipi::IpiError::QueueFull) => {
+                                self.restart_later(capability, args, 0);
+                                Ok(0)
+                            }
+                            Err(_) => Err(CapError::InvalidArgument),
+                        }
+                    }
+                    IpiOp::TakePending => Ok(ipi::take_pending() as usize),
+                }
+            }
+            Resource::Region(region) => {
+                let operation = RegionOp::from_args(args).map_err(|_| CapError::InvalidArgument)?;
+                match operation {
+                    RegionOp::Transfer {
+                        offset,
+                        frames,
+                        table,
+                        slot,
+                    } => {
+                        self.resume_cursor(capability, args);
+                        // Regions are only looked up in the first node of a
+                        // table, see `caps::holds_frame`.
+                        if usize::from(slot) >= SLOT_COUNT {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        let table: KPtr<RawCapEntry> =
+                            self.resources.clone().get_resource_as(table)?;
+                        let (kept, split) = region.split_off(offset, frames)?;
+                        let source = self.resources.clone().find(capability)?;
+                        let destination = table.find(slot)?;
+                        match caps::transfer_region(&source, region, kept, &destination, split) {
+                            Ok(()) => Ok(0),
+                            // Re-read the region once the other transfer is done.
+                            Err(TransferError::Retry) => {
+                                self.restart_later(capability, args, 0);
+                                Ok(0)
+                            }
+                            Err(TransferError::InUse) => Err(CapError::ResourceInUse),
+                        }
+                    }
+                    RegionOp::Base => Ok(region.base().addr().as_u64() as usize),
+                    RegionOp::Frames => Ok(region.frames() as usize),
+                    RegionOp::Stats { buffer } => {
+                        let first = PhysAddr::new(region.base().addr().as_u64());
+                        let first = RawFrame::from_start_address(first);
+                        let Some(stats) =
+                            RetypeTable::region_stats(first, region.frames() as usize)
+                        else {
+                            kbail!(
+                                CapError::Internal,
+                                "{region:?} isn't covered by the retype table"
+                            );
+                        };
+                        // SAFETY: We are handling a syscall from this thread.
+                        let buffer = unsafe { self.user_slice_mut(buffer, 1)? };
+                        buffer[0] = stats;
+                        Ok(0)
+                    }
+                    RegionOp::Map { base } => {
+                        let mut mapped = self.resume_cursor(capability, args);
+                        let frames = region.frames() as usize;
+                        if base % PAGE_SIZE != 0
+                            || !STANDARD.untyped.contains(base, frames * PAGE_SIZE)
+                        {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        let addrspace = self.addrspace();
+                        let first = region.base().addr().as_u64();
+                        let mut allocator = BumpAllocator::new();
+                        let end = frames.min(mapped + MAP_BUDGET);
+                        while mapped < end {
+                            let offset = mapped * PAGE_SIZE;
+                            let frame =
+                                RawFrame::from_start_address(PhysAddr::new(first + offset as u64));
+                            let page = Page::from_start_address(VirtAddr::new(base + offset));
+                            // SAFETY: Window pages aren't user accessible, so
+                            // the thread can't touch the frames through them.
+                            unsafe {
+                                addrspace.map_to(
+                                    page,
+                                    frame,
+                                    PageTableFlags::PRESENT,
+                                    PageTableFlags::PRESENT,
+                                    &mut allocator,
+                                )
+                            }
+                            .map_err(|e| match e {
+                                MapperError::AlreadyMapped(_) => CapError::ResourceInUse,
+                                MapperError::FrameAllocationError => CapError::OutOfMemory,
+                                MapperError::HugeParentEntry => CapError::InvalidArgument,
+                            })?;
+                            mapped += 1;
+                        }
+                        if mapped < frames {
+                            self.restart_later(capability, args, mapped);
+                        }
+                        Ok(0)
+                    }
+                }
+            }
+            Resource::Clock => {
+                let operation = ClockOp::from_args(args).map_err(|_| CapError::InvalidArgument)?;
+                match operation {
+                    ClockOp::GetCalibration { buffer } => {
+                        // SAFETY: We are handling a syscall from this thread.
+                        let buffer = unsafe { self.user_slice_mut(buffer, 1)? };
+                        buffer[0] = crate::info::calibration();
+                        Ok(0)
+                    }
+                    ClockOp::GetTimeNs => Ok(crate::info::nanos_since_boot() as usize),
+                }
+            }
+            Resource::Diagnostics => {
+                let operation =
+                    DiagnosticsOp::from_args(args).map_err(|_| CapError::InvalidArgument)?;
+                match operation {
+                    DiagnosticsOp::Map { table, index } => {
+                        let (table, flags): (KPtr<AnyPageTable>, PageCapFlags) =
+                            self.resources.clone().get_resource_as(table)?;
+                        if flags.level() != 1 {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        let offset = PageTableOffset::try_from(index)
+                            .map_err(|_| CapError::InvalidArgument)?;
+                        let frame = diagnostics::frame().ok_or(CapError::NotFound)?;
+                        if table.get(offset).get().is_some() {
+                            return Err(CapError::ResourceInUse);
+                        }
+                        // SAFETY: The page is read-only to userspace and never
+                        // holds kernel pointers.
+                        unsafe {
+                            table.map(
+                                offset,
+                                frame,
+                                PageTableFlags::PRESENT
+                                    | PageTableFlags::USER_ACCESSIBLE
+                                    | PageTableFlags::NO_EXECUTE,
+                            )
+                        };
+                        Ok(0)
+                    }
+                    DiagnosticsOp::Refresh => diagnostics::refresh()
+                        .map(|generation| generation as usize)
+                        .ok_or(CapError::ResourceInUse),
+                    DiagnosticsOp::Symbolize { address, buffer } => {
+                        // SAFETY: We are handling a syscall from this thread.
+                        let buffer = unsafe { self.user_slice_mut(buffer, 1)? };
+                        buffer[0] = diagnostics::symbolize(address).ok_or(CapError::NotFound)?;
+                        Ok(0)
+                    }
+                    DiagnosticsOp::DrainTrace { core, buffer, len } => {
+                        if core >= NUM_CORES {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        // SAFETY: We are handling a syscall from this thread.
+                        let buffer = unsafe { self.user_slice_mut(buffer, len)? };
+                        trace::drain(core, buffer).ok_or(CapError::ResourceInUse)
+                    }
+                    DiagnosticsOp::InterruptLatency { vector, buffer } => {
+                        if !IRQ_VECTORS.contains(&vector) {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        // SAFETY: We are handling a syscall from this thread.
+                        let buffer = unsafe { self.user_slice_mut(buffer, 1)? };
+                        buffer[0] = latency::stats(vector).ok_or(CapError::ResourceInUse)?;
+                        Ok(0)
+                    }
+                    DiagnosticsOp::Profile { every } => {
+                        profile::start(every);
+                        Ok(0)
+                    }
+                    DiagnosticsOp::DrainProfile { core, buffer, len } => {
+                        if core >= NUM_CORES {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        // SAFETY: We are handling a syscall from this thread.
+                        let buffer = unsafe { self.user_slice_mut(buffer, len)? };
+                        profile::drain(core, buffer).ok_or(CapError::ResourceInUse)
+                    }
+                    DiagnosticsOp::DrainAudit { core, buffer, len } => {
+                        if core >= NUM_CORES {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        // SAFETY: We are handling a syscall from this thread.
+                        let buffer = unsafe { self.user_slice_mut(buffer, len)? };
+                        audit::drain(core, buffer).ok_or(CapError::ResourceInUse)
+                    }
+                }
+            }
+            Resource::PerfCounter => {
+                let operation = PerfOp::from_args(args).map_err(|_| CapError::InvalidArgument)?;
+                let pmu = Pmu::current();
+                let error = |e| match e {
+                    PmuError::NoCounter => CapError::InvalidArgument,
+                    PmuError::Unsupported => CapError::NotFound,
+                };
+                match operation {
+                    PerfOp::Counters => Ok(pmu.counters().into()),
+                    PerfOp::Program {
+                        counter,
+                        event,
+                        mode,
+                        period,
+                    } => pmu
+                        .program(counter, event, mode, period)
+                        .map(|()| 0)
+                        .map_err(error),
+                    PerfOp::Read { counter } => {
+                        pmu.read(counter).map(|value| value as usize).map_err(error)
+                    }
+                    PerfOp::Stop { counter } => pmu.stop(counter).map(|()| 0).map_err(error),
+                    PerfOp::TakeOverflows => Ok(pmu.take_overflows() as usize),
+                }
+            }
+            Resource::Initrd => {
+                let operation = InitrdOp::from_args(args).map_err(|_| CapError::InvalidArgument)?;
+                let initrd = initrd::get().ok_or(CapError::NotFound)?;
+                match operation {
+                    InitrdOp::Size => Ok(initrd.size()),
+                    InitrdOp::Map { table, first } => {
+                        let (table, flags): (KPtr<AnyPageTable>, PageCapFlags) =
+                            self.resources.clone().get_resource_as(table)?;
+                        if flags.level() != 1 {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        map_read_only(&table, first, initrd.pages(), "initrd", |index| {
+                            initrd.frame(index)
+                        })
+                    }
+                }
+            }
+            Resource::Display => {
+                let operation =
+                    DisplayOp::from_args(args).map_err(|_| CapError::InvalidArgument)?;
+                let display = display::get().ok_or(CapError::NotFound)?;
+                match operation {
+                    DisplayOp::Pages => Ok(display.pages()),
+                    DisplayOp::Map { table, first } => {
+                        let (table, flags): (KPtr<AnyPageTable>, PageCapFlags) =
+                            self.resources.clone().get_resource_as(table)?;
+                        if flags.level() != 1 {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        map_read_only(&table, first, display.pages(), "display", |index| {
+                            display.frame(index)
+                        })
+                    }
+                }
+            }
+            Resource::Iommu => {
+                let operation = IommuOp::from_args(args).map_err(|_| CapError::InvalidArgument)?;
+                match operation {
+                    IommuOp::Units => Ok(iommu::units()),
+                }
+            }
+            Resource::DmaDomain(domain) => {
+                let operation =
+                    DmaDomainOp::from_args(args).map_err(|_| CapError::InvalidArgument)?;
+                match operation {
+                    DmaDomainOp::Extend {
+                        iova,
+                        region,
+                        frames,
+                    } => {
+                        let mut regions = (0..frames).map(|i| region.checked_add(i * PAGE_SIZE));
+                        domain.extend(iova, || {
+                            let region = regions
+                                .next()
+                                .ok_or(CapError::OutOfMemory)?
+                                .ok_or(CapError::InvalidArgument)?;
+                            self.untyped_frame(region)
+                        })
+                    }
+                    DmaDomainOp::Grant {
+                        page,
+                        iova,
+                        writable,
+                    } => domain
+                        .grant(self.user_frame(page)?, iova, writable)
+                        .map(|()| 0),
+                    DmaDomainOp::Revoke { iova } => domain.revoke(iova).map(|()| 0),
+                }
+            }
+            Resource::Endpoint(endpoint) => {
+                let operation =
+                    EndpointOp::from_args(args).map_err(|_| CapError::InvalidArgument)?;
+                // Another core using the queue is waited out by retrying.
+                self.resume_cursor(capability, args);
+                let result = match operation {
+                    EndpointOp::Send { message } => endpoint
+                        .send(message)
+                        .map(|result| result.map(|crossed| u8::from(crossed).into())),
+                    EndpointOp::Receive { buffer } => {
+                        // SAFETY: We are handling a syscall from this thread.
+                        let buffer = unsafe { self.user_slice_mut(buffer, 1)? };
+                        endpoint.receive().map(|result| {
+                            result.map(|(message, crossed)| {
+                                buffer[0] = message;
+                                u8::from(crossed).into()
+                            })
+                        })
+                    }
+                    EndpointOp::Len => endpoint.queued().map(Ok),
+                    EndpointOp::Capacity => Some(Ok(endpoint.capacity())),
+                    EndpointOp::SetWatermarks { low, high } => endpoint
+                        .set_watermarks(low, high)
+                        .map(|result| result.map(|()| 0)),
+                };
+                result.unwrap_or_else(|| {
+                    self.restart_later(capability, args, 0);
+                    Ok(0)
+                })
+            }
+            Resource::Hierarchy(hierarchy) => {
+                let operation =
+                    HierarchyOp::from_args(args).map_err(|_| CapError::InvalidArgument)?;
+                match operation {
+                    HierarchyOp::Adopt { thread } => {
+                        self.resume_cursor(capability, args);
+                        let thread: KPtr<Thread> =
+                            self.resources.clone().get_resource_as(thread)?;
+                        Hierarchy::adopt(&hierarchy, thread)
+                            .map(|result| result.map(|()| 0))
+                            .unwrap_or_else(|| {
+                                self.restart_later(capability, args, 0);
+                                Ok(0)
+                            })
+                    }
+                    HierarchyOp::KillTree => {
+                        self.resume_cursor(capability, args);
+                        if self
+                            .hierarchy()
+                            .is_some_and(|own| own.is_within(&hierarchy))
+                        {
+                            return Err(CapError::InvalidArgument);
+                        }
+                        let own_l4 = self.l4_frame();
+                        let mut teardown = |thread: &Thread, budget: &mut usize| {
+                            thread.suspend();
+                            // Wait for the thread to leave its core.
+                            if thread.is_running() {
+                                return false;
+                            }
+                            // Helpers of the caller may share its address space.
+                            if thread.l4_frame() == own_l4 {
+                                return true;
+                            }
+                            // SAFETY: Every TLB is flushed before the thread
+                            // returns.
+                            unsafe { thread.clear_addrspace(budget) }
+                        };
+                        let mut budget = CLEAR_BUDGET;
+                        let done = hierarchy.kill(&mut budget, &mut teardown);
+                        if ipi::shootdown_all().is_err() || done != Some(true) {
+                            self.restart_later(capability, args, 0);
+                        }
+                        Ok(0)
+                    }
+                    HierarchyOp::TakeFaults => Ok(hierarchy.take_faults()),
+                }
+            }
+            Resource::System => {
+                let operation = SystemOp::from_args(args).map_err(|_| CapError::InvalidArgument)?;
+                match operation {
+                    SystemOp::GetRandom { buffer, len } => {
+                        self.resume_cursor(capability, args);
+                        let len = len.min(MAX_RANDOM_LEN);
+                        // SAFETY: We are handling a syscall from this thread.
+                        let buffer = unsafe { self.user_slice_mut(buffer, len)? };
+                        match entropy::fill(buffer) {
+                            Some(()) => Ok(len),
+                            None => {
+                                self.restart_later(capability, args, 0);
+                                Ok(0)
+                            }
+                        }
+                    }
+                    SystemOp::Identify { cap } => {
+                        let kind = self
+                            .resource(cap)
+                            .map_or(ResourceKind::Empty, |resource| resource.kind());
+                        Ok(u8::from(kind).into())
+                    }
                 }
             }
-            Resource::PageTable { table: _, flags: _ } => todo!(),
         }
     }
 }
+
+/// Number of page table entries removed by a single pass of a clear.
+///
+/// Larger trees are cleared over several passes so that interrupts aren't
+/// held off for too long.
+const CLEAR_BUDGET: usize = 512;
+
+/// Number of pages mapped by a single pass of a region map, for the same
+/// reason.
+const MAP_BUDGET: usize = 512;
+
+/// Mask with a bit set for every core in the system.
+fn present_cores() -> u64 {
+    u64::MAX >> (u64::BITS as usize - NUM_CORES)
+}
+
+/// Maps up to a table's worth of `pages` user frames read-only into the level
+/// 1 table `table`, starting with page `first` at entry 0, and returns the
+/// number of pages mapped. The frames of `what` are looked up with `frame`.
+///
+/// Nothing is mapped if one of the entries is in use.
+fn map_read_only(
+    table: &AnyPageTable,
+    first: usize,
+    pages: usize,
+    what: &str,
+    frame: impl Fn(usize) -> Option<RawFrame>,
+) -> Result<usize, CapError> {
+    if first >= pages {
+        return Err(CapError::InvalidArgument);
+    }
+    let count = (pages - first).min(512);
+    let offsets =
+        (0..count).map(|index| PageTableOffset::try_from(index).unwrap_or_else(|_| unreachable!()));
+    if offsets
+        .clone()
+        .any(|offset| table.get(offset).get().is_some())
+    {
+        return Err(CapError::ResourceInUse);
+    }
+    for (index, offset) in offsets.enumerate() {
+        let Some(frame) = frame(first + index) else {
+            kbail!(
+                CapError::Internal,
+                "{what} page {} of {pages} has no frame",
+                first + index
+            );
+        };
+        // The mapping keeps a reference, which is dropped when it's unmapped.
+        let frame = match frame.try_as_user() {
+            Ok(frame) => frame,
+            Err(e) => kbail!(
+                CapError::Internal,
+                "{what} {frame:?} isn't a user frame: {e:?}"
+            ),
+        };
+        // SAFETY: The pages are read-only to userspace and never hold kernel
+        // pointers.
+        unsafe {
+            table.map(
+                offset,
+                frame.into_raw(),
+                PageTableFlags::PRESENT
+                    | PageTableFlags::USER_ACCESSIBLE
+                    | PageTableFlags::NO_EXECUTE,
+            )
+        };
+    }
+    Ok(count)
+}
+
+/// Checks that `len` bytes at `addr` are within the user half of the address space.
+fn check_user_range(addr: usize, len: usize) -> Result<(), CapError> {
+    const USER_TOP: usize = 0x0000_8000_0000_0000;
+    match addr.checked_add(len) {
+        Some(end) if end <= USER_TOP && addr != 0 => Ok(()),
+        _ => Err(CapError::InvalidArgument),
+    }
+}
+
+/// Gets the entry mapping the 4 KiB page `page` in the address space of a
+/// level 4 table, along with the user frame behind it.
+fn user_page(table: &AnyPageTable, page: usize) -> Result<(&PageTableEntry, UserFrame), CapError> {
+    check_user_range(page, PAGE_SIZE)?;
+    let page = Page::try_from_start_address(
+        VirtAddr::try_new(page).map_err(|_| CapError::InvalidArgument)?,
+    )
+    .map_err(|_| CapError::InvalidArgument)?;
+    // SAFETY: Only level 4 tables are passed in, which are root tables.
+    let addrspace = unsafe { table.as_addrspace() };
+    let (entry, level) = addrspace.leaf(page).ok_or(CapError::InvalidArgument)?;
+    match entry.get() {
+        Some((frame, flags))
+            if level.is_bottom() && flags.contains(PageTableFlags::USER_ACCESSIBLE) =>
+        {
+            let frame = frame.try_as_user().map_err(|_| CapError::FrameNotUser)?;
+            Ok((entry, frame))
+        }
+        _ => Err(CapError::InvalidArgument),
+    }
+}
+
+/// Checks that the page right below `stack_pointer` is writable by userspace
+/// in the address space of a level 4 table.
+///
+/// Without it, a thread given a bad stack pointer, e.g. 0, would fault on its
+/// first push with nothing pointing at the cause.
+fn check_stack(table: &AnyPageTable, stack_pointer: usize) -> Result<(), CapError> {
+    let page = stack_pointer.wrapping_sub(1) & !(PAGE_SIZE - 1);
+    check_user_range(page, PAGE_SIZE).map_err(|_| CapError::BadStack)?;
+    // SAFETY: Only level 4 tables are passed in, which are root tables.
+    let addrspace = unsafe { table.as_addrspace() };
+    let writable =
+        PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE;
+    match addrspace.get(Page::from_start_address(VirtAddr::new(page))) {
+        Some((_, flags)) if flags.contains(writable) => Ok(()),
+        _ => Err(CapError::BadStack),
+    }
+}
+
+fn log_level(level: LevelFilter) -> log::LevelFilter {
+    match level {
+        LevelFilter::Off => log::LevelFilter::Off,
+        LevelFilter::Error => log::LevelFilter::Error,
+        LevelFilter::Warn => log::LevelFilter::Warn,
+        LevelFilter::Info => log::LevelFilter::Info,
+        LevelFilter::Debug => log::LevelFilter::Debug,
+        LevelFilter::Trace => log::LevelFilter::Trace,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use kapi::ops::cap_table::RawConstructArgs;
+    use kapi::ops::endpoint::{Message, MAX_ENDPOINT_PAGES, MESSAGES_PER_PAGE};
+
+    use super::*;
+    use crate::arch::exec::KernelStack;
+    use crate::caps::Region;
+
+    const TABLE_CAP: CapId = CapId::new(0);
+    const MEMORY_CAP: CapId = CapId::new(1);
+    /// Where [`untyped_region`] maps frames, at their physical address past
+    /// the start.
+    const WINDOW: usize = STANDARD.untyped.start;
+    /// User page where [`construct`] leaves the arguments it passes.
+    const ARGS: usize = 0x7000_0000;
+
+    /// Builds a thread whose capability table holds a capability to itself in
+    /// [`TABLE_CAP`] and a region covering all memory in [`MEMORY_CAP`].
+    fn thread(allocator: &mut BumpAllocator) -> (Thread, KPtr<RawCapEntry>) {
+        let resources = KPtr::new(
+            allocator.alloc_untyped_frame().unwrap(),
+            RawCapEntry::default(),
+        )
+        .unwrap();
+        let l4 = AnyPageTable::new_l4(allocator.alloc_untyped_frame().unwrap()).unwrap();
+        let kernel_stack =
+            KPtr::new(allocator.alloc_untyped_frame().unwrap(), KernelStack::new()).unwrap();
+        let thread = Thread::new(Regs::default(), l4, resources.clone(), kernel_stack);
+        insert(&resources, TABLE_CAP, Resource::CapEntry(resources.clone()));
+        let frames = (RawFrame::memory_limit() / PAGE_SIZE) as u32;
+        let memory = Region::new(
+            RawFrame::from_start_address(PhysAddr::new(0)).into(),
+            frames,
+        );
+        insert(&resources, MEMORY_CAP, Resource::Region(memory.unwrap()));
+        let flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        // SAFETY: The address space is never loaded.
+        unsafe {
+            thread
+                .addrspace()
+                .map_to(
+                    Page::from_start_address(VirtAddr::new(ARGS)),
+                    allocator.alloc_user_frame().unwrap().into_raw(),
+                    flags,
+                    flags,
+                    allocator,
+                )
+                .unwrap();
+        }
+        (thread, resources)
+    }
+
+    fn insert(resources: &KPtr<RawCapEntry>, cap: CapId, resource: Resource) {
+        resources
+            .clone()
+            .find(cap)
+            .unwrap()
+            .change(|slot| slot.resource = resource);
+    }
+
+    /// Maps a fresh untyped frame in an untyped memory window of the thread
+    /// and returns its address in the window.
+    fn untyped_region(thread: &Thread, allocator: &mut BumpAllocator) -> usize {
+        let frame = allocator.alloc_untyped_frame().unwrap();
+        let region = WINDOW + frame.base().as_u64() as usize;
+        let page = Page::from_start_address(VirtAddr::new(region));
+        // SAFETY: The address space is never loaded.
+        unsafe {
+            thread
+                .addrspace()
+                .map_to(
+                    page,
+                    frame,
+                    PageTableFlags::PRESENT,
+                    PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
+                    allocator,
+                )
+                .unwrap();
+        }
+        region
+    }
+
+    /// Leaves `kind` at [`ARGS`] in the thread's address space and returns
+    /// the syscall that constructs it from there.
+    fn construct(thread: &Thread, kind: ConstructArgs, region: usize, slot: usize) -> SyscallArgs {
+        let args = ARGS as *const RawConstructArgs;
+        let (frame, _) = thread
+            .addrspace()
+            .get(Page::from_start_address(VirtAddr::new(ARGS)))
+            .unwrap();
+        // SAFETY: The frame is only mapped at `ARGS`, which nothing runs on.
+        unsafe {
+            frame
+                .base()
+                .to_virtual()
+                .as_mut_ptr::<RawConstructArgs>()
+                .write(kind.into());
+        }
+        CapTableOp::<SLOT_COUNT>::Construct {
+            args,
+            region,
+            slot: slot.try_into().unwrap(),
+        }
+        .into_args()
+    }
+
+    #[test_case]
+    fn rejects_missing_capabilities() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, resources) = thread(&mut allocator);
+        let args = ThreadOp::GetAffinity.into_args();
+        assert_eq!(
+            thread.exercise_cap(CapId::new(7), args),
+            Err(CapError::NotFound)
+        );
+        // Past the root table with no child table linked.
+        assert_eq!(
+            thread.exercise_cap(CapId::new(SLOT_COUNT as u32 * 3), args),
+            Err(CapError::NotFound)
+        );
+        assert!(thread.resource(CapId::new(7)).unwrap().is_empty());
+
+        // Operations meant for a different resource or that don't exist.
+        insert(&resources, CapId::new(7), Resource::Logger);
+        assert_eq!(
+            thread.exercise_cap(CapId::new(7), args),
+            Err(CapError::InvalidArgument)
+        );
+        assert_eq!(
+            thread.exercise_cap(CapId::new(7), SyscallArgs::new(usize::MAX, 0, 0, 0, 0)),
+            Err(CapError::InvalidArgument)
+        );
+    }
+
+    #[test_case]
+    fn constructs_only_from_untyped_memory() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, _) = thread(&mut allocator);
+        let region = untyped_region(&thread, &mut allocator);
+        let table = ConstructArgs::PageTable { level: 3 };
+
+        for level in [0, 5] {
+            let kind = ConstructArgs::PageTable { level };
+            assert_eq!(
+                thread.exercise_cap(TABLE_CAP, construct(&thread, kind, region, 10)),
+                Err(CapError::InvalidArgument)
+            );
+        }
+        // Outside of the untyped region, not page aligned or not in a window.
+        let unmapped = WINDOW + allocator.alloc_untyped_frame().unwrap().base().as_u64() as usize;
+        for region in [usize::MAX & !0xFFF, region + 8, unmapped] {
+            assert_eq!(
+                thread.exercise_cap(TABLE_CAP, construct(&thread, table, region, 10)),
+                Err(CapError::InvalidArgument)
+            );
+        }
+
+        assert_eq!(
+            thread.exercise_cap(TABLE_CAP, construct(&thread, table, region, 10)),
+            Ok(0)
+        );
+        assert!(matches!(
+            thread.resource(CapId::new(10)),
+            Some(Resource::PageTable { flags, .. }) if flags.level() == 3
+        ));
+        // The frame is now referenced by the table.
+        assert_eq!(
+            thread.exercise_cap(TABLE_CAP, construct(&thread, table, region, 11)),
+            Err(CapError::InvalidArgument)
+        );
+        assert!(thread.resource(CapId::new(11)).unwrap().is_empty());
+    }
+
+    #[test_case]
+    fn constructs_threads_on_root_tables() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, resources) = thread(&mut allocator);
+        let l3 = KPtr::new(
+            allocator.alloc_untyped_frame().unwrap(),
+            AnyPageTable::new(),
+        )
+        .unwrap();
+        insert(
+            &resources,
+            CapId::new(10),
+            Resource::PageTable {
+                table: l3,
+                flags: PageCapFlags::new(3),
+            },
+        );
+        let l4 = AnyPageTable::new_l4(allocator.alloc_untyped_frame().unwrap()).unwrap();
+        insert(
+            &resources,
+            CapId::new(11),
+            Resource::PageTable {
+                table: l4.clone(),
+                flags: PageCapFlags::new(4),
+            },
+        );
+        // The stack is the page at 0x1000, which is only readable at first.
+        let stack = Page::from_start_address(VirtAddr::new(0x1000));
+        let read_only = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        // SAFETY: The address space is never loaded.
+        unsafe {
+            l4.as_addrspace()
+                .map_to(
+                    stack,
+                    allocator.alloc_user_frame().unwrap().into_raw(),
+                    read_only,
+                    read_only,
+                    &mut allocator,
+                )
+                .unwrap();
+        }
+        let kernel_stack = untyped_region(&thread, &mut allocator);
+        let kind = |page_table, stack_pointer| ConstructArgs::Thread {
+            entry: 0x1000,
+            stack_pointer,
+            cap_table: TABLE_CAP,
+            page_table: CapId::new(page_table),
+            kernel_stack,
+        };
+
+        let region = untyped_region(&thread, &mut allocator);
+        assert_eq!(
+            thread.exercise_cap(TABLE_CAP, construct(&thread, kind(10, 0x2000), region, 12)),
+            Err(CapError::InvalidArgument)
+        );
+        for stack_pointer in [0, 0x1000, 0x2000, 0x3000] {
+            assert_eq!(
+                thread.exercise_cap(
+                    TABLE_CAP,
+                    construct(&thread, kind(11, stack_pointer), region, 12)
+                ),
+                Err(CapError::BadStack)
+            );
+        }
+        // SAFETY: The address space is never loaded.
+        unsafe {
+            let (entry, _) = l4.as_addrspace().leaf(stack).unwrap();
+            entry.set_flags(read_only | PageTableFlags::WRITABLE);
+        }
+        assert_eq!(
+            thread.exercise_cap(TABLE_CAP, construct(&thread, kind(11, 0x2000), region, 12)),
+            Ok(0)
+        );
+        assert!(matches!(
+            thread.resource(CapId::new(12)),
+            Some(Resource::Thread(_))
+        ));
+
+        let child = CapId::new(12);
+        assert_eq!(
+            thread.exercise_cap(child, ThreadOp::SetAffinity { mask: 0 }.into_args()),
+            Err(CapError::InvalidArgument)
+        );
+        assert_eq!(
+            thread.exercise_cap(child, ThreadOp::SetAffinity { mask: 1 }.into_args()),
+            Ok(0)
+        );
+        assert_eq!(
+            thread.exercise_cap(child, ThreadOp::GetAffinity.into_args()),
+            Ok(1)
+        );
+        #[cfg(not(feature = "round-robin"))]
+        assert_eq!(
+            thread.exercise_cap(child, ThreadOp::Schedule.into_args()),
+            Err(CapError::InvalidOp)
+        );
+    }
+
+    #[test_case]
+    fn suspended_threads_cant_be_activated() {
+        let mut allocator = BumpAllocator::new();
+        let (supervisor, resources) = thread(&mut allocator);
+        let (worker, _) = thread(&mut allocator);
+        let worker = KPtr::new(allocator.alloc_untyped_frame().unwrap(), worker).unwrap();
+        insert(&resources, CapId::new(10), Resource::Thread(worker.clone()));
+        let op = |op: ThreadOp| supervisor.exercise_cap(CapId::new(10), op.into_args());
+        let ready = u8::from(ThreadState::Ready).into();
+        let suspended = u8::from(ThreadState::Suspended).into();
+
+        assert_eq!(op(ThreadOp::GetState), Ok(ready));
+        assert_eq!(op(ThreadOp::Suspend), Ok(ready));
+        assert_eq!(op(ThreadOp::Suspend), Ok(suspended));
+        assert!(worker.is_suspended());
+        assert_eq!(op(ThreadOp::Activate), Err(CapError::Suspended));
+        assert_eq!(op(ThreadOp::Resume), Ok(suspended));
+        assert_eq!(op(ThreadOp::GetState), Ok(ready));
+
+        // A running thread keeps its core until it gives it up.
+        worker.running.store(true, Ordering::Release);
+        assert_eq!(worker.suspend(), ThreadState::Running);
+        worker.running.store(false, Ordering::Release);
+        assert_eq!(worker.state(), ThreadState::Suspended);
+
+        // Faulted threads wait on their core until they're resumed.
+        let faulted = u8::from(ThreadState::Faulted).into();
+        assert_eq!(op(ThreadOp::Resume), Ok(suspended));
+        worker.running.store(true, Ordering::Release);
+        worker.fault();
+        assert_eq!(op(ThreadOp::GetState), Ok(faulted));
+        assert_eq!(op(ThreadOp::Suspend), Ok(faulted));
+        assert_eq!(op(ThreadOp::Resume), Ok(faulted));
+        assert_eq!(
+            op(ThreadOp::GetState),
+            Ok(u8::from(ThreadState::Running).into())
+        );
+        worker.running.store(false, Ordering::Release);
+    }
+
+    #[test_case]
+    fn yields_only_from_the_calling_thread() {
+        let mut allocator = BumpAllocator::new();
+        let (caller, resources) = thread(&mut allocator);
+        let caller = KPtr::new(allocator.alloc_untyped_frame().unwrap(), caller).unwrap();
+        let (target, _) = thread(&mut allocator);
+        let target = KPtr::new(allocator.alloc_untyped_frame().unwrap(), target).unwrap();
+        insert(&resources, CapId::new(10), Resource::Thread(caller.clone()));
+        insert(&resources, CapId::new(11), Resource::Thread(target.clone()));
+        let op = |cap, op: ThreadOp| caller.exercise_cap(CapId::new(cap), op.into_args());
+        let yield_to = |cap| ThreadOp::YieldTo {
+            thread: CapId::new(cap),
+        };
+
+        // Nothing else is queued to take the core.
+        assert_eq!(op(10, ThreadOp::Yield), Ok(0));
+        assert_eq!(op(11, ThreadOp::Yield), Err(CapError::InvalidArgument));
+        assert_eq!(op(11, yield_to(10)), Err(CapError::InvalidArgument));
+        assert_eq!(op(10, yield_to(0)), Err(CapError::InvalidArgument));
+
+        caller.running.store(true, Ordering::Release);
+        assert_eq!(op(10, yield_to(10)), Err(CapError::ResourceInUse));
+        caller.running.store(false, Ordering::Release);
+        target.suspend();
+        assert_eq!(op(10, yield_to(11)), Err(CapError::Suspended));
+    }
+
+    #[test_case]
+    fn links_only_capability_tables() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, resources) = thread(&mut allocator);
+        let region = untyped_region(&thread, &mut allocator);
+        assert_eq!(
+            thread.exercise_cap(
+                TABLE_CAP,
+                construct(&thread, ConstructArgs::CapTable, region, 10)
+            ),
+            Ok(0)
+        );
+        insert(&resources, CapId::new(11), Resource::Logger);
+
+        let link = |other_table_cap| {
+            CapTableOp::<SLOT_COUNT>::Link {
+                slot: 5.try_into().unwrap(),
+                other_table_cap: CapId::new(other_table_cap),
+            }
+            .into_args()
+        };
+        assert_eq!(
+            thread.exercise_cap(TABLE_CAP, link(11)),
+            Err(CapError::InvalidArgument)
+        );
+        assert_eq!(thread.exercise_cap(TABLE_CAP, link(10)), Ok(0));
+        let child = resources.clone().find(CapId::new(5)).unwrap().get().child;
+        assert!(child.is_some());
+
+        let unlink = CapTableOp::<SLOT_COUNT>::Unlink {
+            slot: 5.try_into().unwrap(),
+        };
+        assert_eq!(thread.exercise_cap(TABLE_CAP, unlink.into_args()), Ok(0));
+        let child = resources.clone().find(CapId::new(5)).unwrap().get().child;
+        assert!(child.is_none());
+
+        // Missing tables need frames that are actually untyped memory.
+        let unmapped = allocator.alloc_untyped_frame().unwrap().base().as_u64() as usize;
+        let far = CapId::new((SLOT_COUNT * SLOT_COUNT + 5) as u32);
+        let extend = |region, frames| {
+            CapTableOp::<SLOT_COUNT>::Extend {
+                cap: far,
+                region,
+                frames,
+            }
+            .into_args()
+        };
+        assert_eq!(
+            thread.exercise_cap(TABLE_CAP, extend(region, 0)),
+            Err(CapError::OutOfMemory)
+        );
+        assert_eq!(
+            thread.exercise_cap(TABLE_CAP, extend(unmapped, 1)),
+            Err(CapError::InvalidArgument)
+        );
+    }
+
+    #[test_case]
+    fn retypes_only_frames_in_held_regions() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, resources) = thread(&mut allocator);
+        let region = untyped_region(&thread, &mut allocator);
+        let frame = RawFrame::from_start_address(PhysAddr::new((region - WINDOW) as u64));
+        let child = CapId::new((SLOT_COUNT + 5) as u32);
+        let extend = CapTableOp::<SLOT_COUNT>::Extend {
+            cap: child,
+            region,
+            frames: 1,
+        }
+        .into_args();
+
+        // A region that ends right before the frame doesn't cover it.
+        let before = Region::new(
+            RawFrame::from_start_address(PhysAddr::new(0)).into(),
+            ((region - WINDOW) / PAGE_SIZE) as u32,
+        );
+        insert(&resources, MEMORY_CAP, Resource::Region(before.unwrap()));
+        assert_eq!(
+            thread.exercise_cap(TABLE_CAP, extend),
+            Err(CapError::FrameOutsideOfRegion)
+        );
+        let exact = Region::new(frame.into(), 1).unwrap();
+        insert(&resources, MEMORY_CAP, Resource::Region(exact));
+        assert_eq!(thread.exercise_cap(TABLE_CAP, extend), Ok(1));
+        assert!(resources.clone().find(child).is_ok());
+    }
+
+    #[test_case]
+    fn page_table_ops_stay_in_the_user_half() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, resources) = thread(&mut allocator);
+        let l4 = AnyPageTable::new_l4(allocator.alloc_untyped_frame().unwrap()).unwrap();
+        let page = 0x4020_1000;
+        // SAFETY: The address space is never loaded.
+        unsafe {
+            l4.as_addrspace()
+                .map_to(
+                    Page::from_start_address(VirtAddr::new(page)),
+                    allocator.alloc_user_frame().unwrap().into_raw(),
+                    PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
+                    PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
+                    &mut allocator,
+                )
+                .unwrap();
+        }
+        let l3 = KPtr::new(
+            allocator.alloc_untyped_frame().unwrap(),
+            AnyPageTable::new(),
+        )
+        .unwrap();
+        let (root, lower) = (CapId::new(10), CapId::new(11));
+        insert(
+            &resources,
+            root,
+            Resource::PageTable {
+                table: l4.clone(),
+                flags: PageCapFlags::new(4),
+            },
+        );
+        insert(
+            &resources,
+            lower,
+            Resource::PageTable {
+                table: l3,
+                flags: PageCapFlags::new(3),
+            },
+        );
+
+        // Never dereferenced since every eviction below fails.
+        let buffer = 0x1000 as *mut u8;
+        let evict = |page, token, buffer| {
+            PageTableOp::Evict {
+                page,
+                token,
+                buffer,
+            }
+            .into_args()
+        };
+        let kernel_page = 0xFFFF_8000_0000_0000;
+        for (cap, args) in [
+            (lower, evict(page, 1, buffer)),
+            (root, evict(kernel_page, 1, buffer)),
+            (root, evict(page + 8, 1, buffer)),
+            (root, evict(page, 1, kernel_page as *mut u8)),
+            (root, evict(page, 1, core::ptr::null_mut())),
+            (root, evict(page + PAGE_SIZE, 1, buffer)),
+            (root, evict(page, u64::MAX, buffer)),
+        ] {
+            assert_eq!(
+                thread.exercise_cap(cap, args),
+                Err(CapError::InvalidArgument)
+            );
+        }
+        // SAFETY: The address space is never loaded.
+        let addrspace = unsafe { l4.as_addrspace() };
+        assert!(addrspace
+            .get(Page::from_start_address(VirtAddr::new(page)))
+            .is_some());
+
+        let clear = PageTableOp::Clear { release: true }.into_args();
+        assert_eq!(thread.exercise_cap(root, clear), Ok(1));
+        assert!(!thread.restart_pending());
+        assert_eq!(thread.exercise_cap(root, clear), Ok(0));
+    }
+
+    #[test_case]
+    fn pins_stay_with_their_mapping() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, resources) = thread(&mut allocator);
+        let l4 = AnyPageTable::new_l4(allocator.alloc_untyped_frame().unwrap()).unwrap();
+        let page = 0x4020_1000;
+        let frame = allocator.alloc_user_frame().unwrap().into_raw();
+        // SAFETY: The address space is never loaded.
+        unsafe {
+            l4.as_addrspace()
+                .map_to(
+                    Page::from_start_address(VirtAddr::new(page)),
+                    frame,
+                    PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
+                    PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
+                    &mut allocator,
+                )
+                .unwrap();
+        }
+        let root = CapId::new(10);
+        insert(
+            &resources,
+            root,
+            Resource::PageTable {
+                table: l4,
+                flags: PageCapFlags::new(4),
+            },
+        );
+        let pin = PageTableOp::Pin { page }.into_args();
+        let unpin = PageTableOp::Unpin { page }.into_args();
+
+        let phys = frame.addr().as_u64() as usize;
+        assert_eq!(thread.exercise_cap(root, pin), Ok(phys));
+        let unmapped = PageTableOp::Pin {
+            page: page + PAGE_SIZE,
+        };
+        assert_eq!(
+            thread.exercise_cap(root, unmapped.into_args()),
+            Err(CapError::InvalidArgument)
+        );
+        let evict = PageTableOp::Evict {
+            page,
+            token: 1,
+            buffer: 0x1000 as *mut u8,
+        };
+        assert_eq!(
+            thread.exercise_cap(root, evict.into_args()),
+            Err(CapError::ResourceInUse)
+        );
+        assert_eq!(thread.exercise_cap(root, unpin), Ok(0));
+        assert_eq!(
+            thread.exercise_cap(root, unpin),
+            Err(CapError::InvalidArgument)
+        );
+
+        // Pins belong to the page they were taken through: they can't be
+        // taken twice, dropped through an unpinned page or unmapped.
+        assert_eq!(thread.exercise_cap(root, pin), Ok(phys));
+        assert_eq!(
+            thread.exercise_cap(root, pin),
+            Err(CapError::InvalidArgument)
+        );
+        let clear = PageTableOp::Clear { release: true }.into_args();
+        assert_eq!(
+            thread.exercise_cap(root, clear),
+            Err(CapError::ResourceInUse)
+        );
+        assert!(frame.try_as_user().unwrap().is_pinned());
+        assert_eq!(thread.exercise_cap(root, unpin), Ok(0));
+        assert_eq!(thread.exercise_cap(root, clear), Ok(1));
+        assert!(frame.try_as_untyped().is_ok());
+    }
+
+    #[test_case]
+    fn rejects_kernel_pointers() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, resources) = thread(&mut allocator);
+        insert(&resources, CapId::new(10), Resource::Logger);
+        let kernel = 0xFFFF_8000_0000_0000 as *const u8;
+        for (sink, filter) in [(kernel, 0x1000 as *const u8), (core::ptr::null(), kernel)] {
+            let args = LoggerOp::SetFilter {
+                sink,
+                sink_len: 6,
+                filter,
+                filter_len: 4,
+            }
+            .into_args();
+            assert_eq!(
+                thread.exercise_cap(CapId::new(10), args),
+                Err(CapError::InvalidArgument)
+            );
+        }
+        // Straddling the end of the user half.
+        let args = LoggerOp::SetFilter {
+            sink: 0x0000_7FFF_FFFF_FFFE as *const u8,
+            sink_len: 6,
+            filter: kernel,
+            filter_len: 4,
+        }
+        .into_args();
+        assert_eq!(
+            thread.exercise_cap(CapId::new(10), args),
+            Err(CapError::InvalidArgument)
+        );
+    }
+
+    #[test_case]
+    fn writes_only_user_text() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, resources) = thread(&mut allocator);
+        insert(&resources, CapId::new(10), Resource::Logger);
+        let text = b"written by a component\n";
+        let args = LoggerOp::Write {
+            buffer: text.as_ptr(),
+            len: text.len(),
+        }
+        .into_args();
+        // Kernel strings aren't the caller's.
+        assert_eq!(
+            thread.exercise_cap(CapId::new(10), args),
+            Err(CapError::InvalidArgument)
+        );
+        let args = LoggerOp::Write {
+            buffer: 0x1000 as *const u8,
+            len: 0,
+        }
+        .into_args();
+        assert_eq!(thread.exercise_cap(CapId::new(10), args), Ok(0));
+    }
+
+    #[test_case]
+    fn checks_user_memory_is_mapped() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, resources) = thread(&mut allocator);
+        insert(&resources, CapId::new(10), Resource::Logger);
+        insert(&resources, CapId::new(11), Resource::Clock);
+        let (read_only, kernel_only) = (0x4000_0000, 0x4000_1000);
+        for (page, flags) in [
+            (read_only, PageTableFlags::USER_ACCESSIBLE),
+            (kernel_only, PageTableFlags::WRITABLE),
+        ] {
+            let frame = allocator.alloc_user_frame().unwrap().into_raw();
+            // SAFETY: The address space is never loaded.
+            unsafe {
+                thread
+                    .addrspace()
+                    .map_to(
+                        Page::from_start_address(VirtAddr::new(page)),
+                        frame,
+                        PageTableFlags::PRESENT | flags,
+                        PageTableFlags::PRESENT
+                            | PageTableFlags::USER_ACCESSIBLE
+                            | PageTableFlags::WRITABLE,
+                        &mut allocator,
+                    )
+                    .unwrap();
+            }
+        }
+        // Nothing below is dereferenced since every call fails.
+        let write = |buffer: usize, len| LoggerOp::Write {
+            buffer: buffer as *const u8,
+            len,
+        };
+        for args in [
+            write(kernel_only, 1),
+            write(0x4000_2000, 1),
+            // Starts in the read-only page and ends in the kernel one.
+            write(read_only + PAGE_SIZE - 1, PAGE_SIZE + 1),
+        ] {
+            assert_eq!(
+                thread.exercise_cap(CapId::new(10), args.into_args()),
+                Err(CapError::InvalidArgument)
+            );
+        }
+        let calibration = ClockOp::GetCalibration {
+            buffer: read_only as *mut _,
+        };
+        assert_eq!(
+            thread.exercise_cap(CapId::new(11), calibration.into_args()),
+            Err(CapError::InvalidArgument)
+        );
+    }
+
+    #[test_case]
+    fn checks_ipi_targets() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, resources) = thread(&mut allocator);
+        insert(&resources, CapId::new(10), Resource::Ipi);
+        for (core, work) in [(NUM_CORES, 0), (0, kapi::ops::ipi::MAX_WORK)] {
+            assert_eq!(
+                thread.exercise_cap(CapId::new(10), IpiOp::Send { core, work }.into_args()),
+                Err(CapError::InvalidArgument)
+            );
+        }
+        assert!(!thread.restart_pending());
+        assert!(thread
+            .exercise_cap(CapId::new(10), IpiOp::TakePending.into_args())
+            .is_ok());
+    }
+
+    #[test_case]
+    fn perf_counters_stay_within_the_pmu() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, resources) = thread(&mut allocator);
+        insert(&resources, CapId::new(10), Resource::PerfCounter);
+        let counters = thread
+            .exercise_cap(CapId::new(10), PerfOp::Counters.into_args())
+            .unwrap();
+        assert_eq!(counters, Pmu::current().counters().into());
+        let past_the_end = counters.try_into().unwrap_or(u8::MAX);
+        for op in [
+            PerfOp::Read {
+                counter: past_the_end,
+            },
+            PerfOp::Stop {
+                counter: past_the_end,
+            },
+        ] {
+            assert_eq!(
+                thread.exercise_cap(CapId::new(10), op.into_args()),
+                Err(CapError::InvalidArgument)
+            );
+        }
+    }
+
+    #[test_case]
+    fn maps_diagnostics_into_leaf_tables() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, resources) = thread(&mut allocator);
+        let (diagnostics, l1, l4) = (CapId::new(10), CapId::new(11), CapId::new(12));
+        insert(&resources, diagnostics, Resource::Diagnostics);
+        let table = KPtr::new(
+            allocator.alloc_untyped_frame().unwrap(),
+            AnyPageTable::new(),
+        )
+        .unwrap();
+        insert(
+            &resources,
+            l1,
+            Resource::PageTable {
+                table: table.clone(),
+                flags: PageCapFlags::new(1),
+            },
+        );
+        insert(
+            &resources,
+            l4,
+            Resource::PageTable {
+                table: AnyPageTable::new_l4(allocator.alloc_untyped_frame().unwrap()).unwrap(),
+                flags: PageCapFlags::new(4),
+            },
+        );
+
+        let map = |table, index| DiagnosticsOp::Map { table, index }.into_args();
+        assert_eq!(
+            thread.exercise_cap(diagnostics, map(l4, 3)),
+            Err(CapError::InvalidArgument)
+        );
+        assert_eq!(
+            thread.exercise_cap(diagnostics, map(l1, 512)),
+            Err(CapError::InvalidArgument)
+        );
+        assert_eq!(thread.exercise_cap(diagnostics, map(l1, 3)), Ok(0));
+        let (frame, flags) = table.get(PageTableOffset::new(3).unwrap()).get().unwrap();
+        assert_eq!(Some(frame), diagnostics::frame());
+        assert!(!flags.contains(PageTableFlags::WRITABLE));
+        assert_eq!(
+            thread.exercise_cap(diagnostics, map(l1, 3)),
+            Err(CapError::ResourceInUse)
+        );
+
+        let generation = thread
+            .exercise_cap(diagnostics, DiagnosticsOp::Refresh.into_args())
+            .unwrap();
+        assert!(generation > 0 && generation % 2 == 0);
+    }
+
+    #[test_case]
+    fn transfers_region_ends() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, resources) = thread(&mut allocator);
+        let base = PhysAddr::new(16 * PAGE_SIZE as u64);
+        let region = Region::new(RawFrame::from_start_address(base).into(), 8).unwrap();
+        let cap = CapId::new(10);
+        insert(&resources, cap, Resource::Region(region));
+        insert(&resources, CapId::new(11), Resource::Logger);
+
+        assert_eq!(
+            thread.exercise_cap(cap, RegionOp::Base.into_args()),
+            Ok(base.as_u64() as usize)
+        );
+        let transfer = |offset, frames, table, slot| {
+            RegionOp::Transfer {
+                offset,
+                frames,
+                table: CapId::new(table),
+                slot: CapId::new(slot),
+            }
+            .into_args()
+        };
+        for (args, error) in [
+            (transfer(2, 2, 0, 20), CapError::InvalidArgument),
+            (transfer(6, 3, 0, 20), CapError::FrameOutsideOfRegion),
+            (transfer(0, 3, 11, 20), CapError::InvalidArgument),
+            (transfer(0, 3, 0, 11), CapError::ResourceInUse),
+            (
+                transfer(0, 3, 0, SLOT_COUNT as u32),
+                CapError::InvalidArgument,
+            ),
+        ] {
+            assert_eq!(thread.exercise_cap(cap, args), Err(error));
+        }
+        assert_eq!(
+            thread.exercise_cap(cap, RegionOp::Frames.into_args()),
+            Ok(8)
+        );
+
+        assert_eq!(thread.exercise_cap(cap, transfer(0, 3, 0, 20)), Ok(0));
+        assert_eq!(
+            thread.exercise_cap(cap, RegionOp::Frames.into_args()),
+            Ok(5)
+        );
+        assert_eq!(
+            thread.exercise_cap(CapId::new(20), RegionOp::Frames.into_args()),
+            Ok(3)
+        );
+        assert_eq!(
+            thread.exercise_cap(CapId::new(20), RegionOp::Base.into_args()),
+            Ok(base.as_u64() as usize)
+        );
+        // Taking the rest empties the source.
+        assert_eq!(thread.exercise_cap(cap, transfer(0, 5, 0, 21)), Ok(0));
+        assert_eq!(
+            thread.exercise_cap(cap, RegionOp::Frames.into_args()),
+            Err(CapError::NotFound)
+        );
+    }
+
+    #[test_case]
+    fn maps_region_windows() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, resources) = thread(&mut allocator);
+        let frame = allocator.alloc_untyped_frame().unwrap();
+        let cap = CapId::new(10);
+        insert(
+            &resources,
+            cap,
+            Resource::Region(Region::new(frame.into(), 1).unwrap()),
+        );
+        let base = WINDOW + 0x10_0000_0000;
+        let map = |base| RegionOp::Map { base }.into_args();
+        let table = ConstructArgs::CapTable;
+
+        // Nothing is mapped until the region is.
+        assert_eq!(
+            thread.exercise_cap(TABLE_CAP, construct(&thread, table, base, 11)),
+            Err(CapError::InvalidArgument)
+        );
+        for base in [base + 8, WINDOW - PAGE_SIZE, STANDARD.untyped.end] {
+            assert_eq!(
+                thread.exercise_cap(cap, map(base)),
+                Err(CapError::InvalidArgument)
+            );
+        }
+        assert_eq!(thread.exercise_cap(cap, map(base)), Ok(0));
+        assert_eq!(
+            thread.exercise_cap(cap, map(base)),
+            Err(CapError::ResourceInUse)
+        );
+        let (mapped, flags) = thread
+            .addrspace()
+            .get(Page::from_start_address(VirtAddr::new(base)))
+            .unwrap();
+        assert_eq!(mapped, frame);
+        assert!(!flags.contains(PageTableFlags::USER_ACCESSIBLE));
+
+        assert_eq!(
+            thread.exercise_cap(TABLE_CAP, construct(&thread, table, base + PAGE_SIZE, 11)),
+            Err(CapError::InvalidArgument)
+        );
+        assert_eq!(
+            thread.exercise_cap(TABLE_CAP, construct(&thread, table, base, 11)),
+            Ok(0)
+        );
+        assert!(matches!(
+            thread.resource(CapId::new(11)),
+            Some(Resource::CapEntry(_))
+        ));
+    }
+
+    #[test_case]
+    fn drops_only_resources_without_memory() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, resources) = thread(&mut allocator);
+        let region = Region::new(
+            RawFrame::from_start_address(PhysAddr::new(16 * PAGE_SIZE as u64)).into(),
+            8,
+        )
+        .unwrap();
+        let table = KPtr::new(
+            allocator.alloc_untyped_frame().unwrap(),
+            RawCapEntry::default(),
+        )
+        .unwrap();
+        let l1 = KPtr::new(
+            allocator.alloc_untyped_frame().unwrap(),
+            AnyPageTable::new(),
+        )
+        .unwrap();
+        insert(&resources, CapId::new(10), Resource::CapEntry(table));
+        insert(&resources, CapId::new(11), Resource::Region(region));
+        insert(
+            &resources,
+            CapId::new(12),
+            Resource::PageTable {
+                table: l1,
+                flags: PageCapFlags::new(1),
+            },
+        );
+        insert(&resources, CapId::new(13), Resource::Diagnostics);
+        let map = DiagnosticsOp::Map {
+            table: CapId::new(12),
+            index: 0,
+        };
+        assert_eq!(thread.exercise_cap(CapId::new(13), map.into_args()), Ok(0));
+
+        let drop = |slot: usize| {
+            CapTableOp::<SLOT_COUNT>::Drop {
+                slot: slot.try_into().unwrap(),
+            }
+            .into_args()
+        };
+        for (slot, error) in [
+            (11, CapError::ResourceInUse),
+            (12, CapError::ResourceInUse),
+            (20, CapError::NotFound),
+        ] {
+            assert_eq!(thread.exercise_cap(TABLE_CAP, drop(slot)), Err(error));
+        }
+        assert_eq!(thread.exercise_cap(TABLE_CAP, drop(10)), Ok(0));
+        assert_eq!(thread.exercise_cap(TABLE_CAP, drop(13)), Ok(0));
+        assert!(thread.resource(CapId::new(10)).unwrap().is_empty());
+        assert!(thread.resource(CapId::new(13)).unwrap().is_empty());
+        assert!(!thread.resource(CapId::new(11)).unwrap().is_empty());
+    }
+
+    #[test_case]
+    fn constructs_endpoints() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, _) = thread(&mut allocator);
+        let region = untyped_region(&thread, &mut allocator);
+        let buffer = untyped_region(&thread, &mut allocator);
+        let endpoint = |pages| ConstructArgs::Endpoint { buffer, pages };
+
+        for pages in [0, MAX_ENDPOINT_PAGES as u8 + 1] {
+            assert_eq!(
+                thread.exercise_cap(TABLE_CAP, construct(&thread, endpoint(pages), region, 10)),
+                Err(CapError::InvalidArgument)
+            );
+        }
+        assert_eq!(
+            thread.exercise_cap(TABLE_CAP, construct(&thread, endpoint(1), region, 10)),
+            Ok(0)
+        );
+        assert!(matches!(
+            thread.resource(CapId::new(10)),
+            Some(Resource::Endpoint(_))
+        ));
+
+        let cap = CapId::new(10);
+        assert_eq!(
+            thread.exercise_cap(cap, EndpointOp::Capacity.into_args()),
+            Ok(MESSAGES_PER_PAGE)
+        );
+        let send = EndpointOp::Send {
+            message: [1, 2, 3, 4],
+        };
+        assert!(thread.exercise_cap(cap, send.into_args()).is_ok());
+        // The message stays queued if it can't be written out.
+        let receive = EndpointOp::Receive {
+            buffer: 0x5000 as *mut Message,
+        };
+        assert_eq!(
+            thread.exercise_cap(cap, receive.into_args()),
+            Err(CapError::InvalidArgument)
+        );
+        assert_eq!(thread.exercise_cap(cap, EndpointOp::Len.into_args()), Ok(1));
+    }
+
+    #[test_case]
+    fn constructs_hierarchies() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, resources) = thread(&mut allocator);
+        let hierarchy = |parent: Option<u32>| ConstructArgs::Hierarchy {
+            parent: parent.map(CapId::new),
+        };
+        let root = CapId::new(10);
+        let child = CapId::new(11);
+        let region = untyped_region(&thread, &mut allocator);
+        assert_eq!(
+            thread.exercise_cap(TABLE_CAP, construct(&thread, hierarchy(None), region, 10)),
+            Ok(0)
+        );
+        let region = untyped_region(&thread, &mut allocator);
+        assert_eq!(
+            thread.exercise_cap(
+                TABLE_CAP,
+                construct(&thread, hierarchy(Some(0)), region, 11)
+            ),
+            Err(CapError::InvalidArgument)
+        );
+        assert_eq!(
+            thread.exercise_cap(
+                TABLE_CAP,
+                construct(&thread, hierarchy(Some(10)), region, 11)
+            ),
+            Ok(0)
+        );
+
+        let (member, _) = self::thread(&mut allocator);
+        let member = KPtr::new(allocator.alloc_untyped_frame().unwrap(), member).unwrap();
+        insert(&resources, CapId::new(12), Resource::Thread(member.clone()));
+        let adopt = HierarchyOp::Adopt {
+            thread: CapId::new(12),
+        };
+        assert_eq!(thread.exercise_cap(child, adopt.into_args()), Ok(0));
+        assert_eq!(
+            thread.exercise_cap(root, HierarchyOp::TakeFaults.into_args()),
+            Ok(0)
+        );
+
+        assert_eq!(
+            thread.exercise_cap(root, HierarchyOp::KillTree.into_args()),
+            Ok(0)
+        );
+        assert!(member.is_suspended());
+        // Nothing can join or nest in a killed hierarchy.
+        let region = untyped_region(&thread, &mut allocator);
+        assert_eq!(
+            thread.exercise_cap(
+                TABLE_CAP,
+                construct(&thread, hierarchy(Some(10)), region, 13)
+            ),
+            Err(CapError::InvalidArgument)
+        );
+    }
+
+    #[test_case]
+    fn constructs_dma_domains_from_the_iommu() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, resources) = thread(&mut allocator);
+        insert(&resources, CapId::new(10), Resource::Iommu);
+        let domain = |iommu| ConstructArgs::DmaDomain {
+            iommu: CapId::new(iommu),
+            requester: 0x10,
+        };
+        let region = untyped_region(&thread, &mut allocator);
+        assert_eq!(
+            thread.exercise_cap(TABLE_CAP, construct(&thread, domain(0), region, 11)),
+            Err(CapError::InvalidArgument)
+        );
+        // Tests run without an IOMMU unless QEMU is given one.
+        match thread.exercise_cap(TABLE_CAP, construct(&thread, domain(10), region, 11)) {
+            Ok(0) => assert!(matches!(
+                thread.resource(CapId::new(11)),
+                Some(Resource::DmaDomain(_))
+            )),
+            result => {
+                assert_eq!(result, Err(CapError::NotFound));
+                assert!(thread.resource(CapId::new(11)).unwrap().is_empty());
+            }
+        }
+    }
+
+    #[test_case]
+    fn evicts_only_into_other_frames() {
+        let mut allocator = BumpAllocator::new();
+        let (thread, resources) = thread(&mut allocator);
+        let l4 = AnyPageTable::new_l4(allocator.alloc_untyped_frame().unwrap()).unwrap();
+        let page = 0x4020_1000;
+        let buffer = 0x5000_0000;
+        let frame = allocator.alloc_user_frame().unwrap().into_raw();
+        let writable =
+            PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE;
+        // The caller reaches the frame it evicts through its buffer, as it
+        // would if it evicted its own page.
+        // SAFETY: The address space is never loaded.
+        let addrspace = unsafe { l4.as_addrspace() };
+        for (addrspace, address) in [(&addrspace, page), (&thread.addrspace(), buffer)] {
+            // SAFETY: Neither address space is ever loaded.
+            unsafe {
+                addrspace
+                    .map_to(
+                        Page::from_start_address(VirtAddr::new(address)),
+                        frame,
+                        writable,
+                        writable,
+                        &mut allocator,
+                    )
+                    .unwrap();
+            }
+        }
+        let root = CapId::new(10);
+        insert(
+            &resources,
+            root,
+            Resource::PageTable {
+                table: l4.clone(),
+                flags: PageCapFlags::new(4),
+            },
+        );
+
+        let evict = PageTableOp::Evict {
+            page,
+            token: 1,
+            buffer: buffer as *mut u8,
+        };
+        assert_eq!(
+            thread.exercise_cap(root, evict.into_args()),
+            Err(CapError::InvalidArgument)
+        );
+        assert_eq!(
+            addrspace
+                .get(Page::from_start_address(VirtAddr::new(page)))
+                .map(|(mapped, _)| mapped),
+            Some(frame)
+        );
+    }
+}
@@ -0,0 +1,380 @@
+//! A terminal drawn into a grid of VGA text cells.
+//!
+//! A cell is a code page 437 character in its low byte and an attribute in
+//! its high byte: the foreground color in the low nibble, with bit 3 making
+//! it bright, and the background color in the high one. [`Console`] writes
+//! text into [`Cells`] like a terminal would, wrapping long lines, scrolling
+//! at the bottom and expanding tabs to the next multiple of [`TAB_WIDTH`].
+//! ANSI SGR sequences change the colors, and other escape sequences are
+//! dropped. Characters the code page doesn't share with ASCII show up as one
+//! replacement glyph each, whether they're control characters or UTF-8.
+
+use core::fmt;
+
+/// Columns between tab stops.
+pub const TAB_WIDTH: usize = 8;
+
+/// Shown for characters the code page doesn't have in the same place as
+/// ASCII, a small square.
+pub const REPLACEMENT: u8 = 0xFE;
+
+/// Maximum number of parameters of an escape sequence, the rest are dropped.
+const MAX_PARAMS: usize = 4;
+
+/// VGA colors in the order of the ANSI ones: black, red, green, yellow,
+/// blue, magenta, cyan and white.
+const ANSI_TO_VGA: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+/// Where cells are drawn, in rows from the top left.
+pub trait Cells {
+    fn read(&self, index: usize) -> u16;
+    fn write(&mut self, index: usize, cell: u16);
+}
+
+impl Cells for &mut [u16] {
+    fn read(&self, index: usize) -> u16 {
+        self[index]
+    }
+
+    fn write(&mut self, index: usize, cell: u16) {
+        self[index] = cell;
+    }
+}
+
+/// Where an escape sequence being read is at.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// Right after the escape character.
+    Start,
+    /// In the parameters of a control sequence.
+    Csi,
+}
+
+/// Cells being written like a terminal.
+pub struct Console<C> {
+    cells: C,
+    width: usize,
+    height: usize,
+    row: usize,
+    col: usize,
+    /// Attribute text is drawn with.
+    attr: u8,
+    /// Attribute SGR resets go back to.
+    base: u8,
+    escape: Escape,
+    params: [u16; MAX_PARAMS],
+    /// Number of parameters read, including the one being read.
+    count: usize,
+    /// UTF-8 continuation bytes left to skip.
+    continuation: u8,
+}
+
+impl<C: Cells> Console<C> {
+    /// A console on `width` by `height` cells, drawing with `attr`.
+    ///
+    /// Doesn't touch the cells, see [`clear`](Self::clear).
+    pub const fn new(cells: C, width: usize, height: usize, attr: u8) -> Self {
+        Self {
+            cells,
+            width,
+            height,
+            row: 0,
+            col: 0,
+            attr,
+            base: attr,
+            escape: Escape::None,
+            params: [0; MAX_PARAMS],
+            count: 0,
+            continuation: 0,
+        }
+    }
+
+    /// Draws into `cells` from now on and returns the old ones. The cursor
+    /// stays where it was.
+    pub fn replace_cells(&mut self, cells: C) -> C {
+        core::mem::replace(&mut self.cells, cells)
+    }
+
+    /// The row and column the next character goes to.
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.col)
+    }
+
+    pub fn set_cursor(&mut self, row: usize, col: usize) {
+        self.row = row.min(self.height - 1);
+        self.col = col.min(self.width);
+    }
+
+    /// Draws with `attr` from now on, which SGR resets also go back to.
+    pub fn set_attr(&mut self, attr: u8) {
+        self.attr = attr;
+        self.base = attr;
+    }
+
+    /// Forgets any escape sequence or UTF-8 character that was cut short.
+    pub fn reset(&mut self) {
+        self.escape = Escape::None;
+        self.continuation = 0;
+    }
+
+    /// The cell at `row` and `col`.
+    pub fn cell(&self, row: usize, col: usize) -> u16 {
+        self.cells.read(row * self.width + col)
+    }
+
+    fn set(&mut self, row: usize, col: usize, byte: u8) {
+        let cell = (u16::from(self.attr) << 8) | u16::from(byte);
+        self.cells.write(row * self.width + col, cell);
+    }
+
+    /// Blanks every cell and moves the cursor to the top left.
+    pub fn clear(&mut self) {
+        for row in 0..self.height {
+            for col in 0..self.width {
+                self.set(row, col, b' ');
+            }
+        }
+        self.row = 0;
+        self.col = 0;
+    }
+
+    fn newline(&mut self) {
+        self.col = 0;
+        if self.row + 1 < self.height {
+            self.row += 1;
+            return;
+        }
+        for index in self.width..self.width * self.height {
+            let cell = self.cells.read(index);
+            self.cells.write(index - self.width, cell);
+        }
+        for col in 0..self.width {
+            self.set(self.height - 1, col, b' ');
+        }
+    }
+
+    fn draw(&mut self, byte: u8) {
+        if self.col == self.width {
+            self.newline();
+        }
+        self.set(self.row, self.col, byte);
+        self.col += 1;
+    }
+
+    /// Applies a select graphic rendition sequence.
+    fn sgr(&mut self) {
+        let params = &self.params[..self.count.max(1)];
+        for &param in params {
+            let attr = self.attr;
+            self.attr = match param {
+                0 => self.base,
+                1 => attr | 0x08,
+                22 => (attr & !0x08) | (self.base & 0x08),
+                30..=37 => (attr & 0xF8) | ANSI_TO_VGA[usize::from(param - 30)],
+                39 => (attr & 0xF0) | (self.base & 0x0F),
+                40..=47 => (attr & 0x0F) | (ANSI_TO_VGA[usize::from(param - 40)] << 4),
+                49 => (attr & 0x0F) | (self.base & 0xF0),
+                90..=97 => (attr & 0xF0) | 0x08 | ANSI_TO_VGA[usize::from(param - 90)],
+                _ => attr,
+            };
+        }
+    }
+
+    /// Reads a byte of an escape sequence, returning whether it was one.
+    fn escape(&mut self, byte: u8) -> bool {
+        match (self.escape, byte) {
+            (Escape::None, 0x1B) => self.escape = Escape::Start,
+            (Escape::None, _) => return false,
+            (Escape::Start, b'[') => {
+                self.escape = Escape::Csi;
+                self.params = [0; MAX_PARAMS];
+                self.count = 0;
+            }
+            (Escape::Start, _) => self.escape = Escape::None,
+            (Escape::Csi, b'0'..=b'9') => {
+                self.count = self.count.max(1);
+                if let Some(param) = self.params.get_mut(self.count - 1) {
+                    *param = param
+                        .saturating_mul(10)
+                        .saturating_add(u16::from(byte - b'0'));
+                }
+            }
+            (Escape::Csi, b';') => self.count = self.count.max(1) + 1,
+            (Escape::Csi, 0x40..=0x7E) => {
+                self.escape = Escape::None;
+                self.count = self.count.min(MAX_PARAMS);
+                if byte == b'm' {
+                    self.sgr();
+                }
+            }
+            (Escape::Csi, _) => {}
+        }
+        true
+    }
+
+    /// Writes a byte of text.
+    pub fn put(&mut self, byte: u8) {
+        if self.escape(byte) {
+            return;
+        }
+        if self.continuation > 0 && (0x80..=0xBF).contains(&byte) {
+            self.continuation -= 1;
+            return;
+        }
+        self.continuation = 0;
+        match byte {
+            b'\n' => self.newline(),
+            b'\r' => self.col = 0,
+            b'\t' => {
+                let stop = (self.col / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.col = stop.min(self.width);
+            }
+            b' '..=b'~' => self.draw(byte),
+            _ => {
+                self.continuation = match byte {
+                    0xC0..=0xDF => 1,
+                    0xE0..=0xEF => 2,
+                    0xF0..=0xF7 => 3,
+                    _ => 0,
+                };
+                self.draw(REPLACEMENT);
+            }
+        }
+    }
+}
+
+impl<C: Cells> fmt::Write for Console<C> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|byte| self.put(byte));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! The screens are compared with the files in `golden/console`. Running
+    //! the tests with `BLESS=1` rewrites them with what was drawn instead.
+
+    use core::fmt::Write as _;
+
+    use super::*;
+
+    const WIDTH: usize = 16;
+    const HEIGHT: usize = 6;
+    /// Light grey on black.
+    const NORMAL: u8 = 0x07;
+
+    /// Draws `text` on a blank screen and checks it against `golden/console/{name}.txt`.
+    fn check(name: &str, text: &str) {
+        let mut cells = [0; WIDTH * HEIGHT];
+        let mut console = Console::new(&mut cells[..], WIDTH, HEIGHT, NORMAL);
+        console.clear();
+        console.write_str(text).unwrap();
+        check_screen(name, &console);
+    }
+
+    /// The characters of the screen between bars, then its attributes in hex.
+    fn render(console: &Console<&mut [u16]>) -> String {
+        let mut out = String::new();
+        for row in 0..HEIGHT {
+            out.push('|');
+            for col in 0..WIDTH {
+                out.push(match console.cell(row, col) as u8 {
+                    byte @ b' '..=b'~' => byte.into(),
+                    REPLACEMENT => '■',
+                    _ => '?',
+                });
+            }
+            out.push_str("|\n");
+        }
+        out.push('\n');
+        for row in 0..HEIGHT {
+            for col in 0..WIDTH {
+                out.push_str(&format!("{:02X}", console.cell(row, col) >> 8));
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    fn check_screen(name: &str, console: &Console<&mut [u16]>) {
+        let path = format!("{}/golden/console/{name}.txt", env!("CARGO_MANIFEST_DIR"));
+        let screen = render(console);
+        if std::env::var_os("BLESS").is_some() {
+            std::fs::write(&path, &screen).unwrap();
+            return;
+        }
+        let golden = std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("Can't read {path} ({e}), run with BLESS=1 to create it"));
+        assert!(
+            screen == golden,
+            "{name} doesn't match {path}, run with BLESS=1 if that's expected\n\
+             drawn:\n{screen}\nexpected:\n{golden}"
+        );
+    }
+
+    #[test]
+    fn wraps_long_lines() {
+        // A newline right after a full row doesn't leave a blank one.
+        check(
+            "wrapping",
+            "exactly sixteen!\nno blank row before this\nshort",
+        );
+    }
+
+    #[test]
+    fn scrolls_at_the_bottom() {
+        // Colors scroll with the text.
+        check(
+            "scrolling",
+            "one\ntwo\n\x1b[31mthree\x1b[0m\nfour\nfive\nsix\nseven\neight",
+        );
+    }
+
+    #[test]
+    fn expands_tabs() {
+        // A tab at the end of a row wraps the next character.
+        check("tabs", "a\tb\n\tindented\n12345678\tx\nfifteen chars..\tz");
+    }
+
+    #[test]
+    fn applies_ansi_colors() {
+        check(
+            "colors",
+            "\x1b[31mred\x1b[0m grey\n\x1b[1;32mbright\x1b[22m dim\n\
+             \x1b[44;97mblue bg\x1b[49m fg\x1b[m\n\x1b[2Kcleared \x1b[5;31;1;4mfive",
+        );
+    }
+
+    #[test]
+    fn replaces_non_ascii() {
+        check(
+            "non_ascii",
+            "caf\u{e9} \u{2192} \u{1F600}!\nnul\0 del\x7f bell\x07",
+        );
+    }
+
+    #[test]
+    fn keeps_the_cursor_across_cells() {
+        let mut cells = [0; WIDTH * HEIGHT];
+        let mut other = [0; WIDTH * HEIGHT];
+        let mut console = Console::new(&mut cells[..], WIDTH, HEIGHT, NORMAL);
+        console.clear();
+        write!(console, "ab\x1b[3").unwrap();
+        let cells = console.replace_cells(&mut other[..]);
+        console.reset();
+        assert_eq!(console.cursor(), (0, 2));
+        // The cut short sequence doesn't eat the next character.
+        console.put(b'1');
+        console.set_cursor(HEIGHT, WIDTH + 1);
+        assert_eq!(console.cursor(), (HEIGHT - 1, WIDTH));
+        console.replace_cells(cells);
+        assert_eq!(
+            console.cell(0, 1),
+            (u16::from(NORMAL) << 8) | u16::from(b'b')
+        );
+        assert_eq!(console.cell(0, 2) as u8, b' ');
+        assert_eq!(other[2] as u8, b'1');
+    }
+}
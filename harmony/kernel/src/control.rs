@@ -0,0 +1,307 @@
+//! Test agent answering host commands on COM2.
+//!
+//! A test runner on the host drives the kernel through the second serial port
+//! with the protocol in [`kapi::control`]: it can run self-checks, arm fault
+//! injection sites and look at capabilities, and evaluate the responses
+//! itself. The port is polled from the timer interrupt, so commands are only
+//! handled once interrupts are enabled.
+
+use core::fmt;
+
+use kapi::control::{Command, ParseError, Reply, Status, MAX_COMMAND_LEN};
+use kapi::ops::cap_table::SLOT_COUNT;
+use kapi::raw::CapId;
+use sync::cell::AtomicRefCell;
+use uart_16550::SerialPort;
+use x86_64_impl::instructions::port::Port;
+
+use crate::component::Thread;
+use crate::retyping::RetypeTable;
+use crate::syscall::decode::resource_name;
+
+const COM2_BASE: u16 = 0x2F8;
+
+struct Agent {
+    port: Option<SerialPort>,
+    line: [u8; MAX_COMMAND_LEN],
+    len: usize,
+    /// Whether the line in progress went past `MAX_COMMAND_LEN`.
+    overflowed: bool,
+}
+
+static AGENT: AtomicRefCell<Agent> = AtomicRefCell::new(Agent {
+    port: None,
+    line: [0; MAX_COMMAND_LEN],
+    len: 0,
+    overflowed: false,
+});
+
+/// Numbers a self-check reports along with its outcome.
+#[derive(Default)]
+struct Results {
+    values: [(&'static str, u64); 4],
+    len: usize,
+}
+
+impl Results {
+    fn push(&mut self, key: &'static str, value: u64) {
+        if let Some(slot) = self.values.get_mut(self.len) {
+            *slot = (key, value);
+            self.len += 1;
+        }
+    }
+}
+
+/// A self-check the host can run by name.
+type Check = fn(&mut Results) -> Result<(), &'static str>;
+
+const CHECKS: &[(&str, Check)] = &[
+    ("stack", |results| {
+        let usage = crate::stack::stack_usage().ok_or("boot stack wasn't painted")?;
+        results.push("used", usage.used);
+        results.push("size", usage.size);
+        if usage.overflowed() {
+            return Err("boot stack overflowed");
+        }
+        Ok(())
+    }),
+    ("retype", |results| {
+        let stats = RetypeTable::stats().ok_or("retype table isn't initialized")?;
+        results.push("untyped", stats.untyped);
+        results.push("total", stats.total());
+        let run = RetypeTable::largest_untyped_run().ok_or("retype table isn't initialized")?;
+        results.push("largest_run", run as u64);
+        if stats.untyped > stats.total() {
+            return Err("more untyped frames than frames");
+        }
+        if RetypeTable::walk_stats() != Some(stats) {
+            return Err("usage counters don't match the table");
+        }
+        Ok(())
+    }),
+    ("diagnostics", |results| {
+        let generation = crate::diagnostics::refresh().ok_or("refresh failed")?;
+        results.push("generation", generation);
+        Ok(())
+    }),
+];
+
+/// The non-empty capabilities in the first node of a thread's table, as
+/// `<id>=<resource>` separated by spaces.
+struct Caps<'a>(&'a Thread);
+
+impl fmt::Display for Caps<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut separator = "";
+        for id in 0..SLOT_COUNT as u32 {
+            match self.0.resource(CapId::new(id)) {
+                Some(resource) if !resource.is_empty() => {
+                    write!(f, "{separator}{id}={}", resource_name(&resource))?;
+                    separator = " ";
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sets up COM2.
+pub fn init() {
+    // SAFETY: COM2 isn't used by anything else.
+    let mut port = unsafe { SerialPort::new(COM2_BASE) };
+    port.init();
+    if let Ok(mut agent) = AGENT.borrow_mut() {
+        agent.port = Some(port);
+    }
+    log::info!("Listening for test commands on COM2");
+}
+
+/// Handles the commands received since the last tick.
+pub fn tick() {
+    // Another core is already handling the port.
+    let Ok(mut agent) = AGENT.borrow_mut() else {
+        return;
+    };
+    let agent = &mut *agent;
+    let Some(port) = agent.port.as_mut() else {
+        return;
+    };
+    // SAFETY: Reading the line status and data registers only consumes the
+    // received byte.
+    let (mut status, mut data): (Port<u8>, Port<u8>) =
+        (Port::new(COM2_BASE + 5), Port::new(COM2_BASE));
+    while unsafe { status.read() } & 1 != 0 {
+        // SAFETY: See above.
+        let byte = unsafe { data.read() };
+        if byte != b'\n' && byte != b'\r' {
+            match agent.line.get_mut(agent.len) {
+                Some(slot) => {
+                    *slot = byte;
+                    agent.len += 1;
+                }
+                None => agent.overflowed = true,
+            }
+            continue;
+        }
+        let line = &agent.line[..agent.len];
+        let _ = if agent.overflowed {
+            too_long(&mut *port)
+        } else if line.is_empty() {
+            Ok(())
+        } else {
+            handle(line, &mut *port)
+        };
+        agent.len = 0;
+        agent.overflowed = false;
+    }
+}
+
+fn too_long(out: &mut dyn fmt::Write) -> fmt::Result {
+    let mut reply = Reply::new(out, Status::BadCommand)?;
+    reply.string("error", "command too long")?;
+    reply.finish()
+}
+
+/// Runs the command in `line` and writes the response to `out`.
+pub fn handle(line: &[u8], mut out: impl fmt::Write) -> fmt::Result {
+    let out: &mut dyn fmt::Write = &mut out;
+    let command = core::str::from_utf8(line)
+        .map_err(|_| ParseError::BadArgument)
+        .and_then(Command::parse);
+    let command = match command {
+        Ok(command) => command,
+        Err(e) => {
+            let mut reply = Reply::new(out, Status::BadCommand)?;
+            reply.string("error", format_args!("{e:?}"))?;
+            return reply.finish();
+        }
+    };
+    match command {
+        Command::Ping => {
+            let mut reply = Reply::new(out, Status::Ok)?;
+            reply.string("command", command.name())?;
+            reply.number("ns", crate::info::nanos_since_boot())?;
+            reply.finish()
+        }
+        Command::RunTest { name } => {
+            let Some((_, check)) = CHECKS.iter().find(|(check, _)| *check == name) else {
+                let mut reply = Reply::new(out, Status::Unsupported)?;
+                reply.string("command", command.name())?;
+                reply.string("test", name)?;
+                return reply.finish();
+            };
+            let mut results = Results::default();
+            let outcome = check(&mut results);
+            let status = match outcome {
+                Ok(()) => Status::Ok,
+                Err(_) => Status::Failed,
+            };
+            let mut reply = Reply::new(out, status)?;
+            reply.string("command", command.name())?;
+            reply.string("test", name)?;
+            if let Err(error) = outcome {
+                reply.string("error", error)?;
+            }
+            for &(key, value) in &results.values[..results.len] {
+                reply.number(key, value)?;
+            }
+            reply.finish()
+        }
+        Command::InjectFault { site, n } => inject_fault(out, site, n),
+        Command::DumpCaps => {
+            let Some(thread) = Thread::current() else {
+                let mut reply = Reply::new(out, Status::Failed)?;
+                reply.string("command", command.name())?;
+                reply.string("error", "no thread running")?;
+                return reply.finish();
+            };
+            let mut reply = Reply::new(out, Status::Ok)?;
+            reply.string("command", command.name())?;
+            reply.string("caps", Caps(&thread))?;
+            reply.finish()
+        }
+    }
+}
+
+#[cfg(feature = "fault-injection")]
+fn inject_fault(out: &mut dyn fmt::Write, name: &str, n: usize) -> fmt::Result {
+    let Some(site) = crate::fault::site(name) else {
+        let mut reply = Reply::new(out, Status::BadCommand)?;
+        reply.string("error", format_args!("unknown site {name}"))?;
+        return reply.finish();
+    };
+    crate::fault::fail_nth(site, n);
+    let mut reply = Reply::new(out, Status::Ok)?;
+    reply.string("command", "inject-fault")?;
+    reply.string("site", name)?;
+    reply.number("n", n as u64)?;
+    reply.finish()
+}
+
+#[cfg(not(feature = "fault-injection"))]
+fn inject_fault(out: &mut dyn fmt::Write, _name: &str, _n: usize) -> fmt::Result {
+    let mut reply = Reply::new(out, Status::Unsupported)?;
+    reply.string("command", "inject-fault")?;
+    reply.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use kapi::control::{Response, Value};
+
+    use super::*;
+
+    struct Buffer {
+        bytes: [u8; 256],
+        len: usize,
+    }
+
+    impl fmt::Write for Buffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            self.bytes
+                .get_mut(self.len..end)
+                .ok_or(fmt::Error)?
+                .copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    /// Handles `line` and returns the status and the value of `key`.
+    fn run(line: &str, key: &str, check: impl FnOnce(&str, Option<Value<'_>>)) {
+        let mut buffer = Buffer {
+            bytes: [0; 256],
+            len: 0,
+        };
+        handle(line.as_bytes(), &mut buffer).unwrap();
+        let response = core::str::from_utf8(&buffer.bytes[..buffer.len]).unwrap();
+        assert!(response.ends_with('\n'));
+        let response = Response::parse(response).unwrap();
+        check(response.status, response.get(key));
+    }
+
+    #[test_case]
+    fn answers_commands() {
+        run("ping", "command", |status, command| {
+            assert_eq!(status, "ok");
+            assert_eq!(command, Some(Value::String("ping")));
+        });
+        run("run-test stack", "size", |status, size| {
+            assert_eq!(status, "ok");
+            assert!(matches!(size, Some(Value::Number(size)) if size > 0));
+        });
+        run("run-test missing", "test", |status, test| {
+            assert_eq!(status, "unsupported");
+            assert_eq!(test, Some(Value::String("missing")));
+        });
+        run("ping twice", "error", |status, error| {
+            assert_eq!(status, "bad-command");
+            assert_eq!(error, Some(Value::String("TooManyArguments")));
+        });
+        run("dump-caps", "error", |status, _| {
+            assert_eq!(status, "failed")
+        });
+    }
+}
@@ -3,7 +3,13 @@ use core::mem::MaybeUninit;
 
 // FIXME: Make this an actual core-local api.
 
-const NUM_CORES: usize = 1;
+pub const NUM_CORES: usize = 1;
+
+/// Returns the index of the core we are running on.
+// FIXME: Read it from the core-local storage once we boot the other cores.
+pub fn current_core() -> usize {
+    0
+}
 
 #[repr(transparent)]
 pub struct CoreLocal<T> {
@@ -23,7 +29,14 @@ impl<T> CoreLocal<T> {
     }
 
     pub fn get(&self) -> &T {
-        &self.values[0]
+        &self.values[current_core()]
+    }
+
+    /// Returns the value belonging to `core`, if it exists.
+    ///
+    /// Only meaningful for values that can be shared with other cores.
+    pub fn get_for(&self, core: usize) -> Option<&T> {
+        self.values.get(core)
     }
 }
 
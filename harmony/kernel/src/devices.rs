@@ -0,0 +1,203 @@
+//! Inventory of the hardware discovered at boot.
+//!
+//! The kernel doesn't drive any of these devices. It only records where they
+//! are so that the boot component can hand them out to drivers. NUMA nodes
+//! are recorded along with them.
+
+use kapi::devices::{
+    pnp_id, BusPath, DeviceId, DeviceResource, InventoryError, InventoryWriter, INVENTORY_SIZE,
+};
+use sync::cell::AtomicOnceCell;
+
+use crate::arch::numa::{self, Affinity};
+use crate::arch::paging::RawFrame;
+use crate::arch::pci::{self, Bar};
+use crate::bump_allocator::BumpAllocator;
+use crate::kptr::KPtr;
+
+#[repr(C, align(4096))]
+struct InventoryPage([u8; INVENTORY_SIZE]);
+
+static INVENTORY: AtomicOnceCell<KPtr<InventoryPage>> = AtomicOnceCell::new();
+
+/// Legacy devices that are always present on a PC.
+const PLATFORM_DEVICES: &[(&[u8; 7], &[DeviceResource])] = &[
+    (
+        b"PNP0303",
+        &[
+            DeviceResource::Ports {
+                start: 0x60,
+                length: 1,
+            },
+            DeviceResource::Ports {
+                start: 0x64,
+                length: 1,
+            },
+            DeviceResource::Irq(1),
+        ],
+    ),
+    (
+        b"PNP0B00",
+        &[
+            DeviceResource::Ports {
+                start: 0x70,
+                length: 2,
+            },
+            DeviceResource::Irq(8),
+        ],
+    ),
+];
+
+/// Maximum number of resources recorded for a single PCI function.
+const MAX_PCI_RESOURCES: usize = 7;
+
+/// Maximum number of NUMA nodes listed.
+const MAX_NODES: usize = 8;
+
+/// Maximum number of processors, memory ranges and distances recorded for a
+/// single NUMA node.
+const MAX_NODE_RESOURCES: usize = 32;
+
+/// Scans the hardware and allocates the inventory page.
+///
+/// Must be called after the retype table has been initialized.
+pub fn init() {
+    let mut page = InventoryPage([0; INVENTORY_SIZE]);
+    let mut writer = InventoryWriter::new(&mut page.0).unwrap();
+
+    for (index, (name, resources)) in PLATFORM_DEVICES.iter().enumerate() {
+        writer
+            .add_device(
+                BusPath::Platform {
+                    index: index as u32,
+                },
+                DeviceId::Pnp(pnp_id(name)),
+                resources,
+            )
+            .unwrap();
+    }
+
+    if let Some(tables) = numa::tables() {
+        add_nodes(&mut writer, &tables);
+    }
+
+    let mut result = Ok(());
+    pci::enumerate(|func| {
+        if result.is_err() {
+            return;
+        }
+        let mut resources = [DeviceResource::Irq(0); MAX_PCI_RESOURCES];
+        let mut count = 0;
+        func.bars(|bar| {
+            resources[count] = match bar {
+                Bar::Memory {
+                    base,
+                    size,
+                    prefetchable,
+                } => DeviceResource::Mmio {
+                    start: base,
+                    length: size,
+                    prefetchable,
+                },
+                Bar::Io { base, size } => DeviceResource::Ports {
+                    start: base,
+                    length: size,
+                },
+            };
+            count += 1;
+        });
+        if let Some(line) = func.irq_line() {
+            resources[count] = DeviceResource::Irq(line);
+            count += 1;
+        }
+        let id = DeviceId::Pci {
+            vendor: func.vendor(),
+            device: func.device_id(),
+            class: func.class(),
+        };
+        log::debug!(
+            "PCI {:02x}:{:02x}.{} {:?}",
+            func.bus,
+            func.device,
+            func.function,
+            id
+        );
+        result = writer.add_device(
+            BusPath::Pci {
+                bus: func.bus,
+                device: func.device,
+                function: func.function,
+            },
+            id,
+            &resources[..count],
+        );
+    });
+    if let Err(InventoryError::NoSpace) = result {
+        log::warn!("Device inventory is full, some devices were left out");
+    } else {
+        result.unwrap();
+    }
+    log::info!("Device inventory uses {} bytes", writer.len());
+
+    let frame = BumpAllocator::new().alloc_untyped_frame().unwrap();
+    INVENTORY.set(KPtr::new(frame, page).unwrap()).unwrap();
+}
+
+/// Lists every node in the SRAT with its memory, processors and distances.
+fn add_nodes(writer: &mut InventoryWriter<'_>, tables: &numa::Tables) {
+    let mut nodes = [0; MAX_NODES];
+    let mut count = 0;
+    for affinity in numa::affinities(tables.srat) {
+        let node = affinity.node();
+        if nodes[..count].contains(&node) {
+            continue;
+        }
+        if count == MAX_NODES {
+            log::warn!("Too many NUMA nodes, node {node} was left out");
+            continue;
+        }
+        nodes[count] = node;
+        count += 1;
+    }
+    for &node in &nodes[..count] {
+        let mut resources = [DeviceResource::Irq(0); MAX_NODE_RESOURCES];
+        let mut len = 0;
+        let local = numa::affinities(tables.srat)
+            .filter(|affinity| affinity.node() == node)
+            .map(|affinity| match affinity {
+                Affinity::Cpu { apic_id, .. } => DeviceResource::Cpu { apic_id },
+                Affinity::Memory { start, length, .. } => DeviceResource::Memory { start, length },
+            });
+        let distances = nodes[..count].iter().filter_map(|&other| {
+            let distance = numa::distance(tables.slit?, node, other)?;
+            Some(DeviceResource::Distance {
+                node: other,
+                distance,
+            })
+        });
+        for resource in local.chain(distances) {
+            if len == MAX_NODE_RESOURCES {
+                log::warn!("NUMA node {node} has too many resources, some were left out");
+                break;
+            }
+            resources[len] = resource;
+            len += 1;
+        }
+        log::debug!("NUMA node {node}: {:?}", &resources[..len]);
+        if let Err(e) = writer.add_device(
+            BusPath::Node { id: node },
+            DeviceId::Node,
+            &resources[..len],
+        ) {
+            log::warn!("NUMA node {node} doesn't fit in the device inventory: {e:?}");
+        }
+    }
+}
+
+/// Returns the frame backing the inventory page, if initialized.
+///
+/// Like the info page, the inventory holds no kernel pointers so it can be
+/// mapped read-only into userspace.
+pub fn frame() -> Option<RawFrame> {
+    INVENTORY.get().map(|inventory| inventory.frame())
+}
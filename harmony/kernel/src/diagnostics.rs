@@ -0,0 +1,195 @@
+//! Kernel state exposed to monitoring components.
+//!
+//! Holders of a diagnostics capability can map the [`DiagnosticsPage`]
+//! read-only, ask the kernel to refresh it and look up kernel symbols. The
+//! page is only written on refresh so the kernel doesn't pay for it unless
+//! someone is watching.
+
+use core::cell::UnsafeCell;
+
+use goblin::elf::section_header::SHT_SYMTAB;
+use goblin::elf::sym::{st_type, STT_FUNC, STT_OBJECT};
+use goblin::elf64::header::{Header, SIZEOF_EHDR};
+use goblin::elf64::section_header::SectionHeader;
+use goblin::elf64::sym::Sym;
+use kapi::diagnostics::{
+    BootPhase, DiagnosticsPage, Symbol, MAX_BOOT_PHASES, PHASE_NAME_LEN, SYMBOL_NAME_LEN,
+};
+use sync::cell::{AtomicOnceCell, AtomicRefCell};
+
+use crate::arch::instructions;
+use crate::arch::paging::RawFrame;
+use crate::arch::sections::LINK_ADDRESS;
+use crate::boot::{self, BootProtocol as _};
+use crate::bump_allocator::BumpAllocator;
+use crate::kptr::KPtr;
+use crate::logging::RING_SINK;
+use crate::retyping::RetypeTable;
+
+/// The page shared with userspace.
+///
+/// Userspace only ever maps it read-only so the kernel can write to it while
+/// holding [`REFRESH`].
+#[repr(transparent)]
+struct SharedPage(UnsafeCell<DiagnosticsPage>);
+
+// SAFETY: Writes are serialized by `REFRESH`.
+unsafe impl Sync for SharedPage {}
+
+static PAGE: AtomicOnceCell<KPtr<SharedPage>> = AtomicOnceCell::new();
+
+/// Serializes refreshes of the page.
+static REFRESH: AtomicRefCell<()> = AtomicRefCell::new(());
+
+/// Boot phases with the TSC reading when they finished.
+///
+/// The TSC is converted to time on refresh since most phases finish before
+/// it's calibrated.
+static PHASES: AtomicRefCell<Phases> = AtomicRefCell::new(Phases {
+    phases: [("", 0); MAX_BOOT_PHASES],
+    count: 0,
+});
+
+struct Phases {
+    phases: [(&'static str, u64); MAX_BOOT_PHASES],
+    count: usize,
+}
+
+/// Records that the boot phase `name` just finished.
+///
+/// Phases past [`MAX_BOOT_PHASES`] are dropped.
+pub fn phase(name: &'static str) {
+    let tsc = instructions::rdtsc();
+    let Ok(mut phases) = PHASES.borrow_mut() else {
+        return;
+    };
+    let count = phases.count;
+    if let Some(slot) = phases.phases.get_mut(count) {
+        *slot = (name, tsc);
+        phases.count += 1;
+    }
+}
+
+/// Allocates the diagnostics page.
+///
+/// Must be called after the retype table has been initialized.
+pub fn init() {
+    let page = {
+        let frame = BumpAllocator::new().alloc_untyped_frame().unwrap();
+        KPtr::new(frame, SharedPage(UnsafeCell::new(DiagnosticsPage::new()))).unwrap()
+    };
+    PAGE.set(page).unwrap();
+    refresh();
+}
+
+/// Returns the frame backing the diagnostics page, if initialized.
+///
+/// Like the info page, it's typed as kernel memory but safe to map read-only
+/// into userspace since it doesn't hold any pointers.
+pub fn frame() -> Option<RawFrame> {
+    PAGE.get().map(|page| page.frame())
+}
+
+/// Updates the page with the current kernel state and returns its generation.
+///
+/// Returns `None` if the page isn't initialized or another core is
+/// refreshing it.
+pub fn refresh() -> Option<u64> {
+    let page = PAGE.get()?;
+    let _guard = REFRESH.borrow_mut().ok()?;
+    // SAFETY: We hold `REFRESH`.
+    let page = unsafe { &mut *page.0.get() };
+    page.generation = page.generation.wrapping_add(1);
+
+    page.refreshed_ns = crate::info::nanos_since_boot();
+    if let Some(stats) = RetypeTable::stats() {
+        page.retype = stats;
+    }
+    if let Some(run) = RetypeTable::largest_untyped_run() {
+        page.largest_untyped_run = run as u64;
+    }
+
+    let calibration = crate::info::calibration();
+    if let Ok(phases) = PHASES.borrow() {
+        for (out, &(name, tsc)) in page.phases.iter_mut().zip(&phases.phases[..phases.count]) {
+            let mut phase = BootPhase {
+                finished_ns: calibration.tsc_to_nanos(tsc).unwrap_or(0),
+                ..Default::default()
+            };
+            let len = name.len().min(PHASE_NAME_LEN);
+            phase.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+            *out = phase;
+        }
+        page.phase_count = phases.count as u64;
+    }
+
+    page.page_faults = crate::arch::interrupts::page_fault_stats();
+    page.log_len = RING_SINK.read(&mut page.log) as u64;
+    page.log_written = RING_SINK.written() as u64;
+    if let Some(usage) = crate::stack::stack_usage() {
+        page.boot_stack = usage;
+    }
+
+    page.generation = page.generation.wrapping_add(1);
+    Some(page.generation)
+}
+
+/// Finds the kernel symbol containing `address` in the kernel's symbol table.
+///
+/// Returns `None` if the bootloader didn't provide the kernel file or it
+/// doesn't have a symbol table.
+pub fn symbolize(address: u64) -> Option<Symbol> {
+    let file = boot::protocol().kernel_file()?;
+    // Symbols are relative to the link address.
+    let slide = boot::protocol()
+        .kernel_address()
+        .map(|kernel| kernel.virtual_base.wrapping_sub(LINK_ADDRESS))
+        .unwrap_or(0);
+    let address = address.wrapping_sub(slide);
+
+    let header = Header::from_bytes(file.get(..SIZEOF_EHDR)?.try_into().ok()?);
+    let sections: &[SectionHeader] = table(
+        file,
+        header.e_shoff,
+        usize::from(header.e_shnum) * core::mem::size_of::<SectionHeader>(),
+    )?;
+    let symtab = sections
+        .iter()
+        .find(|section| section.sh_type == SHT_SYMTAB)?;
+    let strtab = sections.get(usize::try_from(symtab.sh_link).ok()?)?;
+    let symbols: &[Sym] = table(file, symtab.sh_offset, symtab.sh_size.try_into().ok()?)?;
+    let strings = file.get(
+        usize::try_from(strtab.sh_offset).ok()?
+            ..usize::try_from(strtab.sh_offset + strtab.sh_size).ok()?,
+    )?;
+
+    let symbol = symbols.iter().find(|symbol| {
+        matches!(st_type(symbol.st_info), STT_FUNC | STT_OBJECT)
+            && symbol.st_value <= address
+            && address < symbol.st_value.saturating_add(symbol.st_size.max(1))
+    })?;
+    let name = strings.get(usize::try_from(symbol.st_name).ok()?..)?;
+    let name = &name[..name.iter().position(|&byte| byte == 0)?];
+    let len = name.len().min(SYMBOL_NAME_LEN);
+    let mut out = Symbol {
+        address: symbol.st_value.wrapping_add(slide),
+        size: symbol.st_size,
+        name_len: len as u64,
+        ..Default::default()
+    };
+    out.name[..len].copy_from_slice(&name[..len]);
+    Some(out)
+}
+
+/// Reinterprets `len` bytes at `offset` in the ELF file as a table of `T`.
+fn table<T>(file: &[u8], offset: u64, len: usize) -> Option<&[T]> {
+    let bytes = file.get(usize::try_from(offset).ok()?..)?.get(..len)?;
+    if bytes.as_ptr() as usize % core::mem::align_of::<T>() != 0 {
+        return None;
+    }
+    // SAFETY: The bytes are in bounds and aligned, and ELF tables are plain
+    // integers valid for any bit pattern.
+    Some(unsafe {
+        core::slice::from_raw_parts(bytes.as_ptr().cast(), len / core::mem::size_of::<T>())
+    })
+}
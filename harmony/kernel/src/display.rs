@@ -0,0 +1,162 @@
+//! The display the bootloader set up, shared read-only with userspace.
+//!
+//! The kernel fills a [`DisplayInfo`] page from the bootloader's description
+//! of the framebuffer and hands it over along with the frames of the font
+//! module, the same way as the initrd. The boot component holds the display
+//! capability and maps both into the display component, so that it draws
+//! with the mode and font the machine booted with instead of compiling its
+//! own in.
+
+use kapi::display::{ChannelInfo, DisplayInfo, FLAG_DIRECT_COLOR, FLAG_TEXT};
+use kapi::ops::display::FONT_MODULE;
+use sync::cell::AtomicOnceCell;
+
+use crate::arch::paging::{PhysAddr, PhysAddrExt as _, RawFrame, PAGE_SIZE};
+use crate::boot::{self, BootProtocol as _, Framebuffer};
+use crate::bump_allocator::BumpAllocator;
+use crate::initrd;
+
+static DISPLAY: AtomicOnceCell<Display> = AtomicOnceCell::new();
+
+/// The pages shared with the display component.
+#[derive(Debug, Copy, Clone)]
+pub struct Display {
+    info: RawFrame,
+    /// Where the font starts and its length in bytes.
+    font: Option<(PhysAddr, usize)>,
+}
+
+impl Display {
+    /// The info page and then the pages of the font.
+    pub fn pages(&self) -> usize {
+        1 + self.font.map_or(0, |(_, len)| len.div_ceil(PAGE_SIZE))
+    }
+
+    /// The frame holding page `index`.
+    pub fn frame(&self, index: usize) -> Option<RawFrame> {
+        if index == 0 {
+            return Some(self.info);
+        }
+        let (start, _) = self.font?;
+        (index < self.pages()).then(|| {
+            RawFrame::from_start_address(PhysAddr::new(
+                start.as_u64() + ((index - 1) * PAGE_SIZE) as u64,
+            ))
+        })
+    }
+}
+
+/// Returns the display, if the bootloader set one up.
+pub fn get() -> Option<&'static Display> {
+    DISPLAY.get()
+}
+
+/// Describes `framebuffer` and a font of `font_size` bytes.
+fn describe(framebuffer: &Framebuffer, font_size: usize) -> DisplayInfo {
+    let mut info = DisplayInfo {
+        address: framebuffer.address,
+        width: framebuffer.width,
+        height: framebuffer.height,
+        pitch: framebuffer.pitch,
+        bpp: framebuffer.bpp.into(),
+        font_size: font_size as u64,
+        ..DisplayInfo::new()
+    };
+    if framebuffer.text {
+        info.flags |= FLAG_TEXT;
+    }
+    if let Ok(format) = framebuffer.format {
+        let [red, green, blue] = format.channels().map(|channel| ChannelInfo {
+            shift: channel.shift,
+            size: channel.size,
+        });
+        info.flags |= FLAG_DIRECT_COLOR;
+        (info.red, info.green, info.blue) = (red, green, blue);
+    }
+    info
+}
+
+/// Fills in the info page and hands the frames of the font module over to
+/// userspace.
+///
+/// Must be called after the retype table has been initialized.
+pub fn init() {
+    let Some(framebuffer) = boot::protocol().framebuffer() else {
+        log::info!("No display");
+        return;
+    };
+    let font = initrd::hand_over(FONT_MODULE);
+    let info = describe(&framebuffer, font.map_or(0, |(_, len)| len));
+    let Some(frame) = BumpAllocator::new().alloc_kernel_frame() else {
+        log::error!("No memory for the display info page");
+        return;
+    };
+    let frame = frame.into_raw();
+    // SAFETY: The frame was just allocated and the info fills it.
+    unsafe {
+        frame
+            .addr()
+            .to_virtual()
+            .as_mut_ptr::<DisplayInfo>()
+            .write(info)
+    };
+    // The kernel's reference is never dropped, so it stays read-only to
+    // userspace and is never retyped.
+    let info = match frame.try_into_user_from_boot() {
+        Ok(user) => user.into_raw(),
+        Err(e) => {
+            log::error!("Couldn't hand the display info page over: {e:?}");
+            return;
+        }
+    };
+    let _ = DISPLAY.set(Display { info, font });
+    log::info!(
+        "Display is {}x{}, with a {} byte font",
+        framebuffer.width,
+        framebuffer.height,
+        font.map_or(0, |(_, len)| len)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use kernel::pixel::{PixelError, PixelFormat};
+
+    use super::*;
+
+    #[test_case]
+    fn describes_the_framebuffer_and_font() {
+        let mut framebuffer = Framebuffer {
+            address: 0xFD00_0000,
+            width: 1024,
+            height: 768,
+            pitch: 4096,
+            bpp: 32,
+            text: false,
+            format: Ok(PixelFormat::BGRX),
+        };
+        let info = describe(&framebuffer, 4096 + 32);
+        assert_eq!(info.flags, FLAG_DIRECT_COLOR);
+        assert_eq!(info.red, ChannelInfo { shift: 16, size: 8 });
+        assert_eq!(info.blue, ChannelInfo { shift: 0, size: 8 });
+        assert_eq!(info.offset(1, 1), Some(4100));
+        assert_eq!(info.font_size, 4128);
+
+        framebuffer.text = true;
+        framebuffer.format = Err(PixelError::Unsupported);
+        let info = describe(&framebuffer, 0);
+        assert_eq!(info.flags, FLAG_TEXT);
+        assert_eq!(info.red, ChannelInfo::default());
+
+        let display = Display {
+            info: RawFrame::from_start_address(PhysAddr::new(0x1000)),
+            font: Some((PhysAddr::new(0x20_0000), 4096 + 32)),
+        };
+        assert_eq!(display.pages(), 3);
+        assert_eq!(
+            display.frame(2).map(|frame| frame.addr()),
+            Some(PhysAddr::new(0x20_1000))
+        );
+        assert!(display.frame(3).is_none());
+    }
+}
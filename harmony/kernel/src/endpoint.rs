@@ -0,0 +1,194 @@
+//! Bounded message queues.
+//!
+//! An endpoint's queue lives in frames given by whoever builds it, so senders
+//! can never make the kernel allocate memory. Once the queue is full, sends
+//! fail with `WouldBlock` and senders have to back off. Crossing the
+//! watermarks is reported to the thread whose operation crossed them, which
+//! lets producers adapt before the queue fills up.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use kapi::ops::endpoint::{Message, Watermark, Watermarks, MAX_ENDPOINT_PAGES, MESSAGES_PER_PAGE};
+use kapi::raw::CapError;
+use sync::cell::AtomicRefCell;
+
+use crate::arch::paging::RawFrame;
+use crate::kptr::KPtr;
+
+/// A page of queued messages.
+#[repr(C, align(4096))]
+struct MessagePage([[AtomicUsize; 4]; MESSAGES_PER_PAGE]);
+
+impl MessagePage {
+    fn new() -> Self {
+        Self([const { [const { AtomicUsize::new(0) }; 4] }; MESSAGES_PER_PAGE])
+    }
+}
+
+#[derive(Debug)]
+struct Queue {
+    /// Index of the oldest message.
+    head: usize,
+    len: usize,
+    watermarks: Watermarks,
+}
+
+pub struct Endpoint {
+    queue: AtomicRefCell<Queue>,
+    pages: [Option<KPtr<MessagePage>>; MAX_ENDPOINT_PAGES],
+    capacity: usize,
+}
+
+impl Endpoint {
+    /// Builds an endpoint in `frame` whose queue is kept in `pages` frames
+    /// returned by `next_frame`.
+    pub fn construct<F>(
+        frame: RawFrame,
+        pages: usize,
+        mut next_frame: F,
+    ) -> Result<KPtr<Self>, CapError>
+    where
+        F: FnMut() -> Result<RawFrame, CapError>,
+    {
+        if pages == 0 || pages > MAX_ENDPOINT_PAGES {
+            return Err(CapError::InvalidArgument);
+        }
+        let capacity = pages * MESSAGES_PER_PAGE;
+        let mut endpoint = Self {
+            queue: AtomicRefCell::new(Queue {
+                head: 0,
+                len: 0,
+                watermarks: Watermarks::new(capacity),
+            }),
+            pages: [const { None }; MAX_ENDPOINT_PAGES],
+            capacity,
+        };
+        // Dropping the endpoint releases the pages built so far if this fails.
+        for page in endpoint.pages.iter_mut().take(pages) {
+            *page = Some(
+                KPtr::new(next_frame()?, MessagePage::new())
+                    .map_err(|_| CapError::InvalidArgument)?,
+            );
+        }
+        KPtr::new(frame, endpoint).map_err(|_| CapError::InvalidArgument)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of queued messages, or `None` if the queue is in use.
+    pub fn queued(&self) -> Option<usize> {
+        Some(self.queue.borrow().ok()?.len)
+    }
+
+    fn slot(&self, index: usize) -> &[AtomicUsize; 4] {
+        let index = index % self.capacity;
+        let page = self.pages[index / MESSAGES_PER_PAGE]
+            .as_ref()
+            .unwrap_or_else(|| unreachable!());
+        &page.0[index % MESSAGES_PER_PAGE]
+    }
+
+    /// Queues `message`.
+    ///
+    /// Returns `None` if the queue is in use by another core.
+    pub fn send(&self, message: Message) -> Option<Result<Watermark, CapError>> {
+        let mut queue = self.queue.borrow_mut().ok()?;
+        if queue.len == self.capacity {
+            return Some(Err(CapError::WouldBlock));
+        }
+        let slot = self.slot(queue.head + queue.len);
+        for (word, value) in slot.iter().zip(message) {
+            word.store(value, Ordering::Relaxed);
+        }
+        queue.len += 1;
+        let len = queue.len;
+        Some(Ok(queue.watermarks.sent(len)))
+    }
+
+    /// Removes the oldest message.
+    ///
+    /// Returns `None` if the queue is in use by another core.
+    pub fn receive(&self) -> Option<Result<(Message, Watermark), CapError>> {
+        let mut queue = self.queue.borrow_mut().ok()?;
+        if queue.len == 0 {
+            return Some(Err(CapError::WouldBlock));
+        }
+        let slot = self.slot(queue.head);
+        let message = core::array::from_fn(|word| slot[word].load(Ordering::Relaxed));
+        queue.head = (queue.head + 1) % self.capacity;
+        queue.len -= 1;
+        let len = queue.len;
+        Some(Ok((message, queue.watermarks.received(len))))
+    }
+
+    /// Moves the watermarks.
+    ///
+    /// Returns `None` if the queue is in use by another core.
+    pub fn set_watermarks(&self, low: usize, high: usize) -> Option<Result<(), CapError>> {
+        let mut queue = self.queue.borrow_mut().ok()?;
+        Some(
+            Watermarks::with_levels(low, high, self.capacity)
+                .map(|watermarks| queue.watermarks = watermarks)
+                .ok_or(CapError::InvalidArgument),
+        )
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        for page in self.pages.iter_mut().filter_map(Option::take) {
+            let _ = page.into_untyped();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bump_allocator::BumpAllocator;
+
+    fn endpoint(allocator: &mut BumpAllocator) -> KPtr<Endpoint> {
+        let frame = allocator.alloc_untyped_frame().unwrap();
+        let mut pages = (0..2).map(|_| allocator.alloc_untyped_frame().unwrap());
+        Endpoint::construct(frame, 2, || pages.next().ok_or(CapError::OutOfMemory)).unwrap()
+    }
+
+    #[test_case]
+    fn full_queues_push_back_on_senders() {
+        let mut allocator = BumpAllocator::new();
+        let endpoint = endpoint(&mut allocator);
+        let capacity = endpoint.capacity();
+        assert_eq!(capacity, 2 * MESSAGES_PER_PAGE);
+        for sent in 1..=capacity {
+            let crossed = endpoint.send([sent; 4]).unwrap().unwrap();
+            assert_eq!(crossed == Watermark::High, sent == capacity * 3 / 4);
+        }
+        assert_eq!(endpoint.send([0; 4]), Some(Err(CapError::WouldBlock)));
+
+        for received in 1..=capacity {
+            let (message, crossed) = endpoint.receive().unwrap().unwrap();
+            assert_eq!(message, [received; 4]);
+            assert_eq!(
+                crossed == Watermark::Low,
+                capacity - received == capacity / 4
+            );
+        }
+        assert_eq!(endpoint.receive(), Some(Err(CapError::WouldBlock)));
+        // The queue wraps around.
+        assert_eq!(endpoint.send([7; 4]), Some(Ok(Watermark::None)));
+        assert_eq!(endpoint.receive(), Some(Ok(([7; 4], Watermark::None))));
+    }
+
+    #[test_case]
+    fn endpoints_need_pages() {
+        let mut allocator = BumpAllocator::new();
+        let frame = allocator.alloc_untyped_frame().unwrap();
+        let too_many =
+            Endpoint::construct(frame, MAX_ENDPOINT_PAGES + 1, || Err(CapError::OutOfMemory));
+        assert_eq!(too_many.err(), Some(CapError::InvalidArgument));
+        let no_memory = Endpoint::construct(frame, 1, || Err(CapError::OutOfMemory));
+        assert_eq!(no_memory.err(), Some(CapError::OutOfMemory));
+    }
+}
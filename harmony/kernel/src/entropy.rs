@@ -0,0 +1,284 @@
+//! Random numbers for the kernel and for userspace.
+//!
+//! A ChaCha20 generator is seeded at boot from the best [`Source`] the
+//! processor has and reseeded from it every [`RESEED_INTERVAL`] requests.
+//! After every request the generator replaces its key with fresh output, so
+//! the state never holds what it gave out before.
+
+use core::hint::black_box;
+
+use sync::cell::AtomicRefCell;
+
+use crate::arch;
+use crate::arch::instructions::rdtsc;
+
+/// Number of requests served between reseeds.
+const RESEED_INTERVAL: usize = 64;
+
+/// Times the hardware sources are tried before giving up on a word.
+const HARDWARE_RETRIES: usize = 10;
+
+/// TSC samples folded into every word of jitter.
+const JITTER_SAMPLES: usize = 256;
+
+static GENERATOR: AtomicRefCell<Option<Generator>> = AtomicRefCell::new(None);
+
+/// Where the generator gets its seeds from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// The processor's entropy source.
+    Rdseed,
+    /// The processor's DRBG, which is reseeded from its entropy source.
+    Rdrand,
+    /// Variation in how long the same work takes, as measured by the TSC.
+    Jitter,
+}
+
+impl Source {
+    /// The best source available on this processor.
+    pub fn best() -> Self {
+        if arch::has_rdseed() {
+            Source::Rdseed
+        } else if arch::has_rdrand() {
+            Source::Rdrand
+        } else {
+            Source::Jitter
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::Rdseed => "rdseed",
+            Source::Rdrand => "rdrand",
+            Source::Jitter => "tsc jitter",
+        }
+    }
+
+    /// Reads a word, or returns `None` if the hardware kept running dry or
+    /// the processor doesn't have it.
+    pub fn read(self) -> Option<u64> {
+        match self {
+            // SAFETY: Checked that the processor supports it.
+            Source::Rdseed if arch::has_rdseed() => {
+                (0..HARDWARE_RETRIES).find_map(|_| unsafe { arch::instructions::rdseed() })
+            }
+            // SAFETY: Checked that the processor supports it.
+            Source::Rdrand if arch::has_rdrand() => {
+                (0..HARDWARE_RETRIES).find_map(|_| unsafe { arch::instructions::rdrand() })
+            }
+            Source::Rdseed | Source::Rdrand => None,
+            Source::Jitter => Some(jitter()),
+        }
+    }
+
+    /// Reads a seed, falling back to jitter if the hardware runs dry.
+    fn seed(self) -> [u8; 32] {
+        let mut seed = [0; 32];
+        for chunk in seed.chunks_mut(8) {
+            let word = self.read().unwrap_or_else(jitter);
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        seed
+    }
+}
+
+/// Gathers a word from the time it takes to run the same loop over and over.
+///
+/// Each sample only holds a bit or so of entropy in its low bits, so many of
+/// them are folded into a word.
+fn jitter() -> u64 {
+    let mut pool = 0u64;
+    for _ in 0..JITTER_SAMPLES {
+        let start = rdtsc();
+        let mut work = pool;
+        for i in 0..16 {
+            work = black_box(work.rotate_left(7) ^ i);
+        }
+        let elapsed = rdtsc().wrapping_sub(start);
+        pool = pool.rotate_left(5) ^ elapsed ^ (work & 1);
+    }
+    pool
+}
+
+/// The ChaCha20 block function of RFC 8439.
+fn chacha20_block(input: &[u32; 16]) -> [u32; 16] {
+    fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+        state[a] = state[a].wrapping_add(state[b]);
+        state[d] = (state[d] ^ state[a]).rotate_left(16);
+        state[c] = state[c].wrapping_add(state[d]);
+        state[b] = (state[b] ^ state[c]).rotate_left(12);
+        state[a] = state[a].wrapping_add(state[b]);
+        state[d] = (state[d] ^ state[a]).rotate_left(8);
+        state[c] = state[c].wrapping_add(state[d]);
+        state[b] = (state[b] ^ state[c]).rotate_left(7);
+    }
+
+    let mut state = *input;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+    for (word, input) in state.iter_mut().zip(input) {
+        *word = word.wrapping_add(*input);
+    }
+    state
+}
+
+/// A deterministic generator that runs ChaCha20 over a counter.
+pub struct Drbg {
+    key: [u32; 8],
+    counter: u64,
+}
+
+impl Drbg {
+    pub fn new(seed: [u8; 32]) -> Self {
+        let mut drbg = Self {
+            key: [0; 8],
+            counter: 0,
+        };
+        drbg.set_key(&seed);
+        drbg
+    }
+
+    fn set_key(&mut self, bytes: &[u8]) {
+        for (word, bytes) in self.key.iter_mut().zip(bytes.chunks_exact(4)) {
+            *word = u32::from_le_bytes(bytes.try_into().unwrap());
+        }
+    }
+
+    fn block(&mut self) -> [u8; 64] {
+        let mut input = [0; 16];
+        input[..4].copy_from_slice(&[0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574]);
+        input[4..12].copy_from_slice(&self.key);
+        input[12] = self.counter as u32;
+        input[13] = (self.counter >> 32) as u32;
+        self.counter = self.counter.wrapping_add(1);
+        let mut bytes = [0; 64];
+        for (bytes, word) in bytes.chunks_exact_mut(4).zip(chacha20_block(&input)) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Replaces the key with the next output, mixing `input` into it.
+    pub fn reseed(&mut self, input: &[u8; 32]) {
+        let mut key = self.block();
+        for (byte, input) in key.iter_mut().zip(input) {
+            *byte ^= input;
+        }
+        self.set_key(&key[..32]);
+    }
+
+    pub fn fill(&mut self, buffer: &mut [u8]) {
+        for chunk in buffer.chunks_mut(64) {
+            chunk.copy_from_slice(&self.block()[..chunk.len()]);
+        }
+        self.reseed(&[0; 32]);
+    }
+}
+
+struct Generator {
+    drbg: Drbg,
+    source: Source,
+    /// Requests served since the last reseed.
+    requests: usize,
+}
+
+/// Seeds the generator.
+pub fn init() {
+    let source = Source::best();
+    let generator = Generator {
+        drbg: Drbg::new(source.seed()),
+        source,
+        requests: 0,
+    };
+    *GENERATOR.borrow_mut().unwrap() = Some(generator);
+    log::info!("Seeded the random number generator from {}", source.name());
+}
+
+/// Fills `buffer` with random bytes.
+///
+/// Returns `None` if the generator is in use by another core.
+pub fn fill(buffer: &mut [u8]) -> Option<()> {
+    let mut generator = GENERATOR.borrow_mut().ok()?;
+    let generator = generator.as_mut().expect("The generator wasn't seeded");
+    generator.requests += 1;
+    if generator.requests == RESEED_INTERVAL {
+        let seed = generator.source.seed();
+        generator.drbg.reseed(&seed);
+        generator.requests = 0;
+    }
+    generator.drbg.fill(buffer);
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn chacha20_matches_the_rfc() {
+        // RFC 8439, section 2.3.2.
+        let mut input = [0; 16];
+        input[..4].copy_from_slice(&[0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574]);
+        for (i, word) in input[4..12].iter_mut().enumerate() {
+            let i = 4 * i as u32;
+            *word = u32::from_le_bytes([i as u8, i as u8 + 1, i as u8 + 2, i as u8 + 3]);
+        }
+        input[12..].copy_from_slice(&[1, 0x0900_0000, 0x4a00_0000, 0]);
+        assert_eq!(
+            chacha20_block(&input),
+            [
+                0xe4e7_f110,
+                0x1559_3bd1,
+                0x1fdd_0f50,
+                0xc471_20a3,
+                0xc7f4_d1c7,
+                0x0368_c033,
+                0x9aaa_2204,
+                0x4e6c_d4c3,
+                0x4664_82d2,
+                0x09aa_9f07,
+                0x05d7_c214,
+                0xa202_8bd9,
+                0xd19c_12b5,
+                0xb94e_16de,
+                0xe883_d0cb,
+                0x4e3c_50a2,
+            ]
+        );
+    }
+
+    #[test_case]
+    fn generators_forget_their_output() {
+        let mut first = Drbg::new([7; 32]);
+        let mut second = Drbg::new([7; 32]);
+        let (mut a, mut b) = ([0; 100], [0; 100]);
+        first.fill(&mut a);
+        second.fill(&mut b);
+        assert_eq!(a, b);
+        assert!(a.iter().any(|byte| *byte != 0));
+
+        // The key moved on, so the same request gives something else.
+        second.fill(&mut b);
+        assert_ne!(a, b);
+        first.reseed(&[1; 32]);
+        first.fill(&mut a);
+        assert_ne!(a, b);
+    }
+
+    #[test_case]
+    fn every_source_gives_words() {
+        assert!(Source::Jitter.read().is_some());
+        assert!(Source::best().read().is_some());
+        let mut buffer = [0; 64];
+        fill(&mut buffer).unwrap();
+        assert!(buffer.iter().any(|byte| *byte != 0));
+    }
+}
@@ -0,0 +1,131 @@
+//! Fault injection for the memory management paths.
+//!
+//! Each [`Site`] has a countdown. Once armed with [`fail_nth`], the `n`th call
+//! that goes through the site fails as if the system had run out of memory
+//! (or the frame was in the wrong state) and the countdown disarms itself.
+//! Schedules can be given on the command line as `fault.<site>=<n>`, e.g.
+//! `fault.retype=3`.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A place where failures can be injected.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Site {
+    /// Frame allocation in [`crate::bump_allocator::BumpAllocator`].
+    Alloc,
+    /// Retyping untyped frames into user or kernel frames.
+    Retype,
+    /// [`crate::kptr::KPtr::new`].
+    KPtr,
+}
+
+impl Site {
+    const ALL: [Site; 3] = [Site::Alloc, Site::Retype, Site::KPtr];
+
+    fn name(self) -> &'static str {
+        match self {
+            Site::Alloc => "alloc",
+            Site::Retype => "retype",
+            Site::KPtr => "kptr",
+        }
+    }
+}
+
+/// Finds the site called `name`.
+pub fn site(name: &str) -> Option<Site> {
+    Site::ALL.into_iter().find(|site| site.name() == name)
+}
+
+static SCHEDULE: [AtomicUsize; Site::ALL.len()] = [const { AtomicUsize::new(0) }; Site::ALL.len()];
+
+/// Makes the `n`th call through `site` fail. Zero disarms the site.
+pub fn fail_nth(site: Site, n: usize) {
+    SCHEDULE[site as usize].store(n, Ordering::Relaxed);
+}
+
+/// Counts a call through `site` and returns whether it should fail.
+pub fn should_fail(site: Site) -> bool {
+    let previous =
+        SCHEDULE[site as usize]
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+    if previous == Ok(1) {
+        log::warn!("Injecting a failure at {}", site.name());
+        true
+    } else {
+        false
+    }
+}
+
+/// Arms the sites requested in the command line.
+pub fn init() {
+    for option in crate::CMDLINE.split_ascii_whitespace() {
+        let Some((name, n)) = option
+            .strip_prefix("fault.")
+            .and_then(|option| option.split_once('='))
+        else {
+            continue;
+        };
+        let Some(site) = site(name) else {
+            log::warn!("Unknown fault injection site {name:?}");
+            continue;
+        };
+        match n.parse() {
+            Ok(n) => fail_nth(site, n),
+            Err(_) => log::warn!("Invalid fault injection schedule {n:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::context::Regs;
+    use crate::arch::paging::page_table::AnyPageTable;
+    use crate::arch::paging::RawFrame;
+    use crate::bump_allocator::BumpAllocator;
+    use crate::caps::RawCapEntry;
+    use crate::component::Thread;
+    use crate::kptr::KPtr;
+
+    /// Tries to build a thread with `site` failing on its `n`th call and
+    /// checks that neither of the thread's frames are leaked.
+    fn construct_rolls_back(site: Site, n: usize) {
+        let mut allocator = BumpAllocator::new();
+        let resources = KPtr::new(
+            allocator.alloc_untyped_frame().unwrap(),
+            RawCapEntry::default(),
+        )
+        .unwrap();
+        let l4_table = AnyPageTable::new_l4(allocator.alloc_untyped_frame().unwrap()).unwrap();
+        let frame = allocator.alloc_untyped_frame().unwrap();
+        let kernel_stack = allocator.alloc_untyped_frame().unwrap();
+
+        fail_nth(site, n);
+        let result = Thread::construct(frame, kernel_stack, Regs::default(), l4_table, resources);
+        fail_nth(site, 0);
+        assert!(result.is_err());
+        let is_untyped = |frame: RawFrame| frame.try_as_untyped().is_ok();
+        assert!(is_untyped(frame));
+        assert!(is_untyped(kernel_stack));
+    }
+
+    #[test_case]
+    fn fails_once() {
+        fail_nth(Site::Alloc, 3);
+        assert!(!should_fail(Site::Alloc));
+        assert!(!should_fail(Site::Alloc));
+        assert!(should_fail(Site::Alloc));
+        assert!(!should_fail(Site::Alloc));
+        assert!(!should_fail(Site::Retype));
+    }
+
+    #[test_case]
+    fn thread_stack_rolled_back() {
+        construct_rolls_back(Site::KPtr, 1);
+    }
+
+    #[test_case]
+    fn thread_frame_rolled_back() {
+        construct_rolls_back(Site::Retype, 2);
+    }
+}
@@ -0,0 +1,245 @@
+//! Groups of threads that are torn down together.
+//!
+//! A hierarchy owns its member threads and the hierarchies nested in it, while
+//! children and threads only keep weak pointers back up. That way, killing a
+//! hierarchy can reach the whole subtree and faults can be reported up to every
+//! ancestor, without the links keeping each other alive.
+//!
+//! Killing a hierarchy suspends its subtree right away: threads in a killed
+//! hierarchy count as suspended. The rest of the teardown is done in bounded
+//! passes, leaves first, and every member or child that's fully torn down is
+//! let go of, so that each pass picks up where the last one stopped.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use kapi::ops::hierarchy::{MAX_CHILDREN, MAX_DEPTH, MAX_MEMBERS};
+use kapi::raw::CapError;
+use sync::cell::AtomicRefCell;
+
+use crate::arch::paging::RawFrame;
+use crate::component::Thread;
+use crate::kptr::{KPtr, WeakKPtr};
+
+pub struct Hierarchy {
+    parent: Option<WeakKPtr<Hierarchy>>,
+    /// Number of ancestors.
+    depth: usize,
+    members: AtomicRefCell<[Option<KPtr<Thread>>; MAX_MEMBERS]>,
+    children: AtomicRefCell<[Option<KPtr<Hierarchy>>; MAX_CHILDREN]>,
+    killed: AtomicBool,
+    /// Faults raised in the subtree since they were last taken.
+    faults: AtomicUsize,
+}
+
+impl Hierarchy {
+    /// Builds a hierarchy in `frame`, nested in `parent` if given.
+    ///
+    /// Returns `None` if the parent's children are in use by another core.
+    pub fn construct(
+        frame: RawFrame,
+        parent: Option<KPtr<Hierarchy>>,
+    ) -> Option<Result<KPtr<Self>, CapError>> {
+        let depth = match parent {
+            Some(ref parent) if parent.is_killed() || parent.depth == MAX_DEPTH => {
+                return Some(Err(CapError::InvalidArgument))
+            }
+            Some(ref parent) => parent.depth + 1,
+            None => 0,
+        };
+        let hierarchy = Self {
+            parent: parent.as_ref().map(KPtr::downgrade),
+            depth,
+            members: AtomicRefCell::new([const { None }; MAX_MEMBERS]),
+            children: AtomicRefCell::new([const { None }; MAX_CHILDREN]),
+            killed: AtomicBool::new(false),
+            faults: AtomicUsize::new(0),
+        };
+        let Some(parent) = parent else {
+            return Some(KPtr::new(frame, hierarchy).map_err(|_| CapError::InvalidArgument));
+        };
+        let mut children = parent.children.borrow_mut().ok()?;
+        let Some(slot) = children.iter_mut().find(|child| child.is_none()) else {
+            return Some(Err(CapError::OutOfMemory));
+        };
+        let hierarchy = match KPtr::new(frame, hierarchy) {
+            Ok(hierarchy) => hierarchy,
+            Err(_) => return Some(Err(CapError::InvalidArgument)),
+        };
+        *slot = Some(hierarchy.clone());
+        Some(Ok(hierarchy))
+    }
+
+    /// Whether this hierarchy or one of its ancestors was killed.
+    pub fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::Acquire)
+            || self
+                .parent
+                .and_then(|parent| parent.upgrade())
+                .is_some_and(|parent| parent.is_killed())
+    }
+
+    /// Whether this is `ancestor` or is nested in it.
+    pub fn is_within(&self, ancestor: &Hierarchy) -> bool {
+        core::ptr::eq(self, ancestor)
+            || self
+                .parent
+                .and_then(|parent| parent.upgrade())
+                .is_some_and(|parent| parent.is_within(ancestor))
+    }
+
+    /// Makes `thread` a member of `this`.
+    ///
+    /// Returns `None` if the members are in use by another core.
+    pub fn adopt(this: &KPtr<Self>, thread: KPtr<Thread>) -> Option<Result<(), CapError>> {
+        let mut members = this.members.borrow_mut().ok()?;
+        if this.is_killed() {
+            return Some(Err(CapError::InvalidArgument));
+        }
+        let Some(slot) = members.iter_mut().find(|member| member.is_none()) else {
+            return Some(Err(CapError::OutOfMemory));
+        };
+        if !thread.join(KPtr::downgrade(this)) {
+            return Some(Err(CapError::ResourceInUse));
+        }
+        *slot = Some(thread);
+        Some(Ok(()))
+    }
+
+    /// Counts a fault in this hierarchy and every one above it.
+    pub fn report_fault(&self) {
+        self.faults.fetch_add(1, Ordering::AcqRel);
+        if let Some(parent) = self.parent.and_then(|parent| parent.upgrade()) {
+            parent.report_fault();
+        }
+    }
+
+    /// Returns the faults counted since the last call.
+    pub fn take_faults(&self) -> usize {
+        self.faults.swap(0, Ordering::AcqRel)
+    }
+
+    /// Kills the hierarchy and tears down as much of its subtree as `budget`
+    /// allows, calling `teardown` on every member.
+    ///
+    /// `teardown` returns whether the thread is fully torn down. Returns
+    /// whether the whole subtree is, or `None` if it's in use by another core.
+    pub fn kill<F>(&self, budget: &mut usize, teardown: &mut F) -> Option<bool>
+    where
+        F: FnMut(&Thread, &mut usize) -> bool,
+    {
+        self.killed.store(true, Ordering::Release);
+        let mut children = self.children.borrow_mut().ok()?;
+        for slot in children.iter_mut() {
+            let Some(child) = slot else {
+                continue;
+            };
+            if !child.kill(budget, teardown)? {
+                return Some(false);
+            }
+            *slot = None;
+        }
+        drop(children);
+        let mut members = self.members.borrow_mut().ok()?;
+        for slot in members.iter_mut() {
+            let Some(thread) = slot else {
+                continue;
+            };
+            if *budget == 0 || !teardown(thread, budget) {
+                return Some(false);
+            }
+            *slot = None;
+        }
+        Some(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::context::Regs;
+    use crate::arch::exec::KernelStack;
+    use crate::arch::paging::page_table::AnyPageTable;
+    use crate::bump_allocator::BumpAllocator;
+    use crate::caps::RawCapEntry;
+
+    fn thread(allocator: &mut BumpAllocator) -> KPtr<Thread> {
+        let mut frame = || allocator.alloc_untyped_frame().unwrap();
+        let l4 = AnyPageTable::new_l4(frame()).unwrap();
+        let resources = KPtr::new(frame(), RawCapEntry::default()).unwrap();
+        let stack = KPtr::new(frame(), KernelStack::new()).unwrap();
+        KPtr::new(frame(), Thread::new(Regs::default(), l4, resources, stack)).unwrap()
+    }
+
+    fn hierarchy(
+        allocator: &mut BumpAllocator,
+        parent: Option<&KPtr<Hierarchy>>,
+    ) -> KPtr<Hierarchy> {
+        let frame = allocator.alloc_untyped_frame().unwrap();
+        Hierarchy::construct(frame, parent.cloned())
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test_case]
+    fn killing_suspends_the_subtree() {
+        let mut allocator = BumpAllocator::new();
+        let root = hierarchy(&mut allocator, None);
+        let child = hierarchy(&mut allocator, Some(&root));
+        let sibling = hierarchy(&mut allocator, None);
+        let worker = thread(&mut allocator);
+        let bystander = thread(&mut allocator);
+        Hierarchy::adopt(&child, worker.clone()).unwrap().unwrap();
+        Hierarchy::adopt(&sibling, bystander.clone())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            Hierarchy::adopt(&root, worker.clone()),
+            Some(Err(CapError::ResourceInUse))
+        );
+
+        assert!(child.is_within(&root));
+        assert!(!sibling.is_within(&root));
+
+        // Nothing gets torn down without budget, but the subtree is suspended.
+        let mut budget = 0;
+        let mut torn_down = 0;
+        let mut teardown = |_: &Thread, _: &mut usize| {
+            torn_down += 1;
+            true
+        };
+        assert_eq!(root.kill(&mut budget, &mut teardown), Some(false));
+        assert!(worker.is_suspended());
+        assert!(!bystander.is_suspended());
+        assert_eq!(
+            Hierarchy::adopt(&child, thread(&mut allocator)),
+            Some(Err(CapError::InvalidArgument))
+        );
+
+        let mut budget = 1;
+        assert_eq!(root.kill(&mut budget, &mut teardown), Some(true));
+        assert_eq!(torn_down, 1);
+        assert!(root.children.borrow().unwrap().iter().all(Option::is_none));
+        assert!(child.members.borrow().unwrap().iter().all(Option::is_none));
+    }
+
+    #[test_case]
+    fn faults_reach_every_ancestor() {
+        let mut allocator = BumpAllocator::new();
+        let root = hierarchy(&mut allocator, None);
+        let mut leaf = root.clone();
+        for _ in 0..MAX_DEPTH {
+            leaf = hierarchy(&mut allocator, Some(&leaf));
+        }
+        let frame = allocator.alloc_untyped_frame().unwrap();
+        assert_eq!(
+            Hierarchy::construct(frame, Some(leaf.clone())).map(|h| h.err()),
+            Some(Some(CapError::InvalidArgument))
+        );
+
+        leaf.report_fault();
+        leaf.report_fault();
+        assert_eq!(root.take_faults(), 2);
+        assert_eq!(root.take_faults(), 0);
+        assert_eq!(leaf.take_faults(), 2);
+    }
+}
@@ -0,0 +1,88 @@
+//! The kernel info page shared read-only with every address space.
+
+use core::sync::atomic::Ordering;
+
+use kapi::info::{KernelInfo, FEATURE_INVARIANT_TSC, FEATURE_RDTSCP};
+use kapi::ops::clock::Calibration;
+use sync::cell::AtomicOnceCell;
+
+use crate::arch::paging::RawFrame;
+use crate::arch::timer::TICK_FREQUENCY;
+use crate::arch::{self, Arch as _, Current};
+use crate::bump_allocator::BumpAllocator;
+use crate::kptr::KPtr;
+
+static INFO: AtomicOnceCell<KPtr<KernelInfo>> = AtomicOnceCell::new();
+
+/// Allocates the info page and fills in the boot-time information.
+///
+/// Must be called after the retype table has been initialized.
+pub fn init() {
+    let boot_tsc = Current::counter();
+    let info = {
+        let frame = BumpAllocator::new().alloc_untyped_frame().unwrap();
+        KPtr::new(frame, KernelInfo::new()).unwrap()
+    };
+    info.boot_tsc.store(boot_tsc, Ordering::Relaxed);
+    info.tsc_frequency
+        .store(Current::counter_frequency(), Ordering::Relaxed);
+    info.tick_frequency.store(TICK_FREQUENCY, Ordering::Relaxed);
+    info.cpu_ids[0].store(Current::cpu_id(), Ordering::Relaxed);
+    info.cpu_count.store(1, Ordering::Relaxed);
+    if arch::set_core_index(0) {
+        info.features.fetch_or(FEATURE_RDTSCP, Ordering::Relaxed);
+    }
+    if arch::has_invariant_tsc() {
+        info.features
+            .fetch_or(FEATURE_INVARIANT_TSC, Ordering::Relaxed);
+    }
+    log::info!(
+        "TSC frequency calibrated at {} Hz",
+        info.tsc_frequency.load(Ordering::Relaxed)
+    );
+    INFO.set(info).unwrap();
+}
+
+/// Returns the frame backing the info page, if initialized.
+///
+/// The frame is typed as kernel memory but it's safe to map as read-only into
+/// userspace since the kernel never stores any pointers in it.
+pub fn frame() -> Option<RawFrame> {
+    INFO.get().map(|info| info.frame())
+}
+
+/// Returns the TSC calibration handed to clock capabilities.
+pub fn calibration() -> Calibration {
+    let Some(info) = INFO.get() else {
+        return Calibration::default();
+    };
+    Calibration {
+        tsc_frequency: info.tsc_frequency.load(Ordering::Relaxed),
+        boot_tsc: info.boot_tsc.load(Ordering::Relaxed),
+        stable_tsc: info.has_feature(FEATURE_INVARIANT_TSC),
+    }
+}
+
+/// Returns the nanoseconds elapsed since boot.
+///
+/// Uses the TSC when it's stable and counts scheduler ticks otherwise.
+pub fn nanos_since_boot() -> u64 {
+    let Some(info) = INFO.get() else {
+        return 0;
+    };
+    if let Some(nanos) = calibration().tsc_to_nanos(Current::counter()) {
+        return nanos;
+    }
+    let ticks = info.ticks.load(Ordering::Relaxed);
+    let frequency = info.tick_frequency.load(Ordering::Relaxed).max(1);
+    (u128::from(ticks) * 1_000_000_000 / u128::from(frequency))
+        .try_into()
+        .unwrap_or(u64::MAX)
+}
+
+/// Records a scheduler tick.
+pub fn tick() {
+    if let Some(info) = INFO.get() {
+        info.ticks.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,145 @@
+//! The initial ramdisk.
+//!
+//! The bootloader loads the archive as the boot module named
+//! [`INITRD_MODULE`]. Its frames are handed over to userspace as user frames
+//! that the kernel keeps a reference to, so they are never released or
+//! retyped. The boot component holds the initrd capability and maps the
+//! archive read-only into the components that need raw access to it, which
+//! saves copying it through IPC.
+
+use kapi::ops::initrd::INITRD_MODULE;
+use sync::cell::AtomicOnceCell;
+
+use crate::arch::paging::{PhysAddr, PhysAddrExt as _, RawFrame, VirtAddr, PAGE_SIZE};
+use crate::boot::{self, BootProtocol as _};
+
+static INITRD: AtomicOnceCell<Initrd> = AtomicOnceCell::new();
+
+/// Where the archive is in physical memory.
+#[derive(Debug, Copy, Clone)]
+pub struct Initrd {
+    start: PhysAddr,
+    len: usize,
+}
+
+impl Initrd {
+    /// Size of the archive in bytes.
+    pub fn size(&self) -> usize {
+        self.len
+    }
+
+    pub fn pages(&self) -> usize {
+        self.len.div_ceil(PAGE_SIZE)
+    }
+
+    /// The frame holding page `index` of the archive.
+    pub fn frame(&self, index: usize) -> Option<RawFrame> {
+        (index < self.pages()).then(|| {
+            RawFrame::from_start_address(PhysAddr::new(
+                self.start.as_u64() + (index * PAGE_SIZE) as u64,
+            ))
+        })
+    }
+}
+
+/// Returns the initrd, if the kernel was booted with one.
+pub fn get() -> Option<&'static Initrd> {
+    INITRD.get()
+}
+
+/// Hands the frames of the initrd module over to userspace.
+///
+/// Must be called after the retype table has been initialized.
+pub fn init() {
+    let Some((start, len)) = hand_over(INITRD_MODULE) else {
+        return;
+    };
+    let initrd = Initrd { start, len };
+    let _ = INITRD.set(initrd);
+    log::info!("Found a {} byte initrd at {start:?}", initrd.len);
+}
+
+/// Hands the frames of the boot module named `name` over to userspace as
+/// user frames the kernel keeps a reference to, and returns where the module
+/// starts and its length in bytes.
+///
+/// Must be called after the retype table has been initialized.
+pub fn hand_over(name: &[u8]) -> Option<(PhysAddr, usize)> {
+    let protocol = boot::protocol();
+    let module = (0..)
+        .map_while(|index| protocol.module(index))
+        .find(|module| module.name.ends_with(name));
+    let Some(module) = module else {
+        log::info!("No {} module", name.escape_ascii());
+        return None;
+    };
+    let address = VirtAddr::new(module.data.as_ptr() as usize);
+    // SAFETY: Modules are in the direct map.
+    let start = unsafe { PhysAddr::from_virtual(address) };
+    // Pages of the module can't be shared with anything else.
+    if start.as_u64() % PAGE_SIZE as u64 != 0 {
+        log::error!(
+            "The {} module at {start:?} isn't page aligned",
+            name.escape_ascii()
+        );
+        return None;
+    }
+    let len = module.data.len();
+    for index in 0..len.div_ceil(PAGE_SIZE) {
+        let frame = RawFrame::from_start_address(PhysAddr::new(
+            start.as_u64() + (index * PAGE_SIZE) as u64,
+        ));
+        match frame.try_into_user_from_boot() {
+            // The kernel's reference is never dropped.
+            Ok(frame) => {
+                frame.into_raw();
+            }
+            Err(e) => {
+                log::error!(
+                    "Couldn't hand {frame:?} of the {} module over: {e:?}",
+                    name.escape_ascii()
+                );
+                return None;
+            }
+        }
+    }
+    Some((start, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bump_allocator::BumpAllocator;
+
+    #[test_case]
+    fn boot_frames_keep_their_contents() {
+        let mut allocator = BumpAllocator::new();
+        let frame = allocator.alloc_kernel_frame().unwrap().into_raw();
+        // SAFETY: The frame was just allocated.
+        unsafe { *frame.addr().to_virtual().as_mut_ptr::<u64>() = 0xC0FFEE };
+
+        let user = frame.try_into_user_from_boot().unwrap();
+        assert_eq!(
+            // SAFETY: The frame is still held.
+            unsafe { *frame.addr().to_virtual().as_ptr::<u64>() },
+            0xC0FFEE
+        );
+        assert!(frame.try_into_user_from_boot().is_err());
+        assert_eq!(user.drop(), 1);
+        assert!(frame.try_into_untyped().is_ok());
+    }
+
+    #[test_case]
+    fn finds_the_frames_of_each_page() {
+        let initrd = Initrd {
+            start: PhysAddr::new(0x20_0000),
+            len: PAGE_SIZE + 1,
+        };
+        assert_eq!(initrd.pages(), 2);
+        assert_eq!(
+            initrd.frame(1).map(|frame| frame.addr()),
+            Some(PhysAddr::new(0x20_1000))
+        );
+        assert!(initrd.frame(2).is_none());
+    }
+}
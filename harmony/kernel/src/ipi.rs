@@ -0,0 +1,143 @@
+//! Inter-processor work requests.
+//!
+//! Each core has a lock-free queue of requests that other cores (or interrupt
+//! handlers) push to. The owning core drains it with [`handle_requests`],
+//! performing TLB shootdowns and accumulating userspace work items into a
+//! mask that the runtime collects with [`take_pending`].
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use kapi::ops::ipi::MAX_WORK;
+use kapi::trace::EventKind;
+use mpsc::Queue;
+use sync::cell::AtomicLazyCell;
+
+use crate::arch::paging::Page;
+use crate::arch::{Arch as _, Current};
+use crate::core_local::{self, CoreLocal, NUM_CORES};
+use crate::trace;
+
+/// Number of requests that can be queued on a core.
+const QUEUE_SIZE: usize = 64;
+
+#[derive(Debug, Copy, Clone)]
+pub enum Request {
+    /// Userspace work item (see [`kapi::ops::ipi`]).
+    Work(u8),
+    /// Invalidates the TLB entry for a page.
+    FlushPage(Page),
+    /// Invalidates every non-global TLB entry.
+    FlushAll,
+}
+
+struct CoreRequests {
+    queue: Queue<Request, QUEUE_SIZE>,
+    pending: AtomicU64,
+}
+
+static REQUESTS: AtomicLazyCell<CoreLocal<CoreRequests>> = AtomicLazyCell::new(|| {
+    CoreLocal::new_with(|_| CoreRequests {
+        queue: Queue::new(),
+        pending: AtomicU64::new(0),
+    })
+});
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IpiError {
+    NoSuchCore,
+    BadWork,
+    QueueFull,
+}
+
+/// Queues `request` on `core`.
+pub fn send_request(core: usize, request: Request) -> Result<(), IpiError> {
+    let requests = REQUESTS.get_for(core).ok_or(IpiError::NoSuchCore)?;
+    trace::event_current(EventKind::Ipi, core as u64);
+    requests
+        .queue
+        .push(request)
+        .map_err(|_| IpiError::QueueFull)?;
+    if core != core_local::current_core() {
+        // FIXME: Raise the IPI through the local APIC once we boot the other cores.
+        log::warn!("Can't interrupt core {core}, request will be handled on its next syscall");
+    }
+    Ok(())
+}
+
+/// Queues `work` on `core`.
+pub fn send(core: usize, work: u8) -> Result<(), IpiError> {
+    if work >= MAX_WORK {
+        return Err(IpiError::BadWork);
+    }
+    send_request(core, Request::Work(work))
+}
+
+/// Asks `core` to drop its TLB entry for `page`.
+pub fn shootdown(core: usize, page: Page) -> Result<(), IpiError> {
+    send_request(core, Request::FlushPage(page))
+}
+
+/// Flushes the TLB of the current core and asks every other core to do the same.
+pub fn shootdown_all() -> Result<(), IpiError> {
+    Current::flush_all();
+    let mut result = Ok(());
+    for core in (0..NUM_CORES).filter(|&core| core != core_local::current_core()) {
+        if let Err(e) = send_request(core, Request::FlushAll) {
+            result = Err(e);
+        }
+    }
+    result
+}
+
+/// Handles every request queued on the current core.
+pub fn handle_requests() {
+    let requests = REQUESTS.get().get();
+    // SAFETY: Only the owning core pops from its queue, and the kernel runs
+    // with interrupts disabled so this can't be re-entered.
+    while let Some(request) = unsafe { requests.queue.pop() } {
+        match request {
+            Request::Work(work) => {
+                requests.pending.fetch_or(1 << work, Ordering::Relaxed);
+            }
+            Request::FlushPage(page) => Current::flush_page(page.base().as_usize()),
+            Request::FlushAll => Current::flush_all(),
+        }
+    }
+}
+
+/// Returns and clears the work pending on the current core.
+pub fn take_pending() -> u64 {
+    handle_requests();
+    REQUESTS.get().get().pending.swap(0, Ordering::Acquire)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::paging::VirtAddr;
+
+    #[test_case]
+    fn queue_work() {
+        take_pending();
+        let core = core_local::current_core();
+        send(core, 0).unwrap();
+        send(core, 5).unwrap();
+        shootdown(core, Page::containing_address(VirtAddr::from_ptr(&core))).unwrap();
+        assert_eq!(take_pending(), 0b100001);
+        assert_eq!(take_pending(), 0);
+        assert_eq!(send(usize::MAX, 0), Err(IpiError::NoSuchCore));
+        assert_eq!(send(0, MAX_WORK), Err(IpiError::BadWork));
+    }
+
+    #[test_case]
+    fn full_queue() {
+        let core = core_local::current_core();
+        for _ in 0..QUEUE_SIZE {
+            send_request(core, Request::FlushAll).unwrap();
+        }
+        assert_eq!(send(core, 1), Err(IpiError::QueueFull));
+        handle_requests();
+        send(core, 1).unwrap();
+        assert_eq!(take_pending(), 0b10);
+    }
+}
@@ -0,0 +1,59 @@
+//! Failing syscalls instead of the kernel.
+//!
+//! A syscall that runs into something it can't handle, whether because of
+//! what the component passed in or because kernel state isn't what it
+//! expected, should fail the syscall rather than panic. [`kbail!`] returns an
+//! error from the enclosing function and [`kensure!`] does so if a condition
+//! doesn't hold. Both log the error with where it was raised and why, since
+//! the component only sees the error code.
+//!
+//! Plain validation of arguments, where the error code says it all, doesn't
+//! need them.
+
+/// Returns `Err(error)` from the enclosing function after logging it with a
+/// message, e.g. `kbail!(CapError::Internal, "no frame for page {page}")`.
+#[macro_export]
+macro_rules! kbail {
+    ($error:expr, $($arg:tt)+) => {{
+        let error = $error;
+        ::log::warn!(
+            "{:?} at {}:{}: {}",
+            error,
+            file!(),
+            line!(),
+            format_args!($($arg)+)
+        );
+        return Err(error);
+    }};
+}
+
+/// Returns `Err(error)` from the enclosing function, like [`kbail!`], unless
+/// `condition` holds.
+#[macro_export]
+macro_rules! kensure {
+    ($condition:expr, $error:expr, $($arg:tt)+) => {
+        if !$condition {
+            $crate::kbail!($error, $($arg)+);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use kapi::raw::CapError;
+
+    fn offset(len: usize, index: usize) -> Result<usize, CapError> {
+        kensure!(index < len, CapError::InvalidArgument, "{index} >= {len}");
+        let Some(offset) = index.checked_mul(8) else {
+            kbail!(CapError::Internal, "offset of {index} overflows");
+        };
+        Ok(offset)
+    }
+
+    #[test]
+    fn returns_the_error() {
+        assert_eq!(offset(4, 3), Ok(24));
+        assert_eq!(offset(4, 4), Err(CapError::InvalidArgument));
+        assert_eq!(offset(usize::MAX, usize::MAX / 2), Err(CapError::Internal));
+    }
+}
@@ -5,7 +5,7 @@ use core::ops::Deref;
 use core::ptr::NonNull;
 use core::sync::atomic::{fence, Ordering};
 
-use crate::arch::paging::{PhysAddr, RawFrame, VirtAddr, PAGE_SIZE};
+use crate::arch::paging::{PhysAddr, PhysAddrExt as _, RawFrame, VirtAddr, PAGE_SIZE};
 use crate::retyping::{KernelFrame, RetypeError};
 
 /// A "kernel" pointer to any page-aligned resource.
@@ -42,6 +42,12 @@ impl<T> KPtr<T> {
     };
 
     pub fn new(frame: RawFrame, value: T) -> Result<Self, RetypeError> {
+        #[cfg(feature = "fault-injection")]
+        if crate::fault::should_fail(crate::fault::Site::KPtr) {
+            return Err(RetypeError::InvalidFromState(
+                crate::retyping::State::Unavailable,
+            ));
+        }
         // SAFEYT: Frame is retyped into kernel so no other references to living
         // data exists.
         unsafe { Ok(Self::new_unchecked(frame.try_into_kernel()?, value)) }
@@ -95,10 +101,83 @@ impl<T> KPtr<T> {
         }
     }
 
+    /// Drops the reference and, if it was the last one, turns the frame back
+    /// into untyped memory.
+    ///
+    /// Returns the frame if it was released.
+    pub fn into_untyped(self) -> Option<RawFrame> {
+        let frame = self.frame();
+        drop(self.try_into_inner()?);
+        frame.try_into_untyped().ok()
+    }
+
     pub fn into_raw(self) -> RawFrame {
         let this = ManuallyDrop::new(self);
         this.frame()
     }
+
+    /// Creates a weak pointer to the same resource.
+    pub fn downgrade(this: &Self) -> WeakKPtr<T> {
+        WeakKPtr {
+            inner: this.inner,
+            epoch: this.frame().epoch().unwrap(),
+        }
+    }
+}
+
+/// A non-owning kernel pointer.
+///
+/// Weak pointers don't keep the resource alive. Instead, they remember the
+/// epoch of the frame when they were created and can only be upgraded while the
+/// frame is still alive in that same epoch. This makes it possible to hold
+/// references to a resource without preventing its frame from being retyped.
+pub struct WeakKPtr<T> {
+    inner: NonNull<T>,
+    epoch: u64,
+}
+
+impl<T> core::fmt::Debug for WeakKPtr<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("WeakKPtr")
+            .field("inner", &self.inner)
+            .field("epoch", &self.epoch)
+            .finish()
+    }
+}
+
+impl<T> Clone for WeakKPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T> Copy for WeakKPtr<T> {}
+
+unsafe impl<T: Send> Send for WeakKPtr<T> {}
+unsafe impl<T: Sync> Sync for WeakKPtr<T> {}
+
+impl<T> WeakKPtr<T> {
+    pub fn frame(&self) -> RawFrame {
+        // SAFETY: Pointer was created a physical address
+        RawFrame::from_start_address(unsafe {
+            PhysAddr::from_virtual(VirtAddr::from_ptr(self.inner.as_ptr()))
+        })
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Returns true if the frame has been retyped since this pointer was created.
+    pub fn is_stale(&self) -> bool {
+        self.frame().epoch().unwrap() != self.epoch
+    }
+
+    /// Attempts to get a strong pointer to the resource.
+    pub fn upgrade(&self) -> Option<KPtr<T>> {
+        let frame = self.frame().try_as_kernel_at(self.epoch).ok()?;
+        // SAFETY: The frame is alive at the same epoch so it still holds a `T`.
+        Some(unsafe { KPtr::from_frame_unchecked(frame) })
+    }
 }
 
 impl<T> AsRef<T> for KPtr<T> {
@@ -0,0 +1,104 @@
+//! Interrupt latency.
+//!
+//! Every hardware interrupt is timestamped when its handler enters the kernel
+//! and again when the kernel hands off, either by returning to whatever was
+//! interrupted or by dispatching a thread. The difference goes into a
+//! histogram per vector that the diagnostics capability exposes.
+//!
+//! FIXME: Also record the [`LatencyStage::Userspace`] stage once interrupts
+//! can be delivered to userspace drivers.
+
+use kapi::diagnostics::{InterruptLatency, LatencyStats};
+use sync::cell::AtomicRefCell;
+
+use crate::arch::interrupts::IRQ_VECTORS;
+use crate::core_local::{current_core, NUM_CORES};
+
+const VECTORS: usize = (IRQ_VECTORS.end - IRQ_VECTORS.start) as usize;
+
+/// Where an interrupt is when its latency is measured.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LatencyStage {
+    Handoff,
+    Userspace,
+}
+
+static STATS: [AtomicRefCell<InterruptLatency>; VECTORS] = [const {
+    AtomicRefCell::new(InterruptLatency {
+        handoff: LatencyStats::new(),
+        userspace: LatencyStats::new(),
+    })
+}; VECTORS];
+
+/// Vector and entry time of the interrupt each core is handling.
+static ENTERED: [AtomicRefCell<Option<(u8, u64)>>; NUM_CORES] =
+    [const { AtomicRefCell::new(None) }; NUM_CORES];
+
+/// Records that the core started handling interrupt `vector`.
+pub fn enter(vector: u8) {
+    if let Ok(mut entered) = ENTERED[current_core()].borrow_mut() {
+        *entered = Some((vector, crate::info::nanos_since_boot()));
+    }
+}
+
+/// Records that the kernel is done with the interrupt the core was handling.
+pub fn handoff() {
+    let Some((vector, entered)) = ENTERED[current_core()]
+        .borrow_mut()
+        .ok()
+        .and_then(|mut entered| entered.take())
+    else {
+        return;
+    };
+    let now = crate::info::nanos_since_boot();
+    record(vector, LatencyStage::Handoff, now.saturating_sub(entered));
+}
+
+/// Adds a sample of `ns` to the stats of `vector` at `stage`.
+pub fn record(vector: u8, stage: LatencyStage, ns: u64) {
+    let Some(stats) = index(vector).map(|index| &STATS[index]) else {
+        return;
+    };
+    // Another core is reading or recording, drop the sample.
+    let Ok(mut stats) = stats.borrow_mut() else {
+        return;
+    };
+    match stage {
+        LatencyStage::Handoff => stats.handoff.record(ns),
+        LatencyStage::Userspace => stats.userspace.record(ns),
+    }
+}
+
+/// Returns the latencies measured for `vector`, or `None` if it isn't a
+/// hardware interrupt or its stats are being written.
+pub fn stats(vector: u8) -> Option<InterruptLatency> {
+    let stats = STATS[index(vector)?].borrow().ok()?;
+    Some(*stats)
+}
+
+fn index(vector: u8) -> Option<usize> {
+    IRQ_VECTORS
+        .contains(&vector)
+        .then(|| usize::from(vector - IRQ_VECTORS.start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn records_per_vector() {
+        let vector = IRQ_VECTORS.end - 1;
+        let before = stats(vector).unwrap();
+        record(vector, LatencyStage::Handoff, 1_000);
+        record(vector, LatencyStage::Userspace, 5_000);
+        let after = stats(vector).unwrap();
+        assert_eq!(after.handoff.count, before.handoff.count + 1);
+        assert!(after.handoff.max_ns >= 1_000);
+        assert_eq!(after.userspace.count, before.userspace.count + 1);
+
+        assert!(stats(IRQ_VECTORS.start - 1).is_none());
+        record(0x80, LatencyStage::Handoff, 1);
+        assert!(stats(0x80).is_none());
+    }
+}
@@ -0,0 +1,20 @@
+//! The parts of the kernel that don't touch the hardware.
+//!
+//! The kernel binary builds on top of this library. Unlike the binary, the
+//! library also builds for the host, so its tests run without QEMU:
+//!
+//! ```text
+//! make host-test
+//! ```
+//!
+//! Code moves here when it only needs `core` and the other host-buildable
+//! crates of the workspace. Anything that reaches for the `arch` module, the
+//! physical memory offset or the boot protocol stays in the binary.
+#![cfg_attr(not(test), no_std)]
+
+pub mod console;
+pub mod kassert;
+pub mod page_fault;
+pub mod pixel;
+pub mod retype;
+pub mod timer_wheel;
@@ -0,0 +1,498 @@
+//! Kernel logging facade.
+//!
+//! Log records are dispatched to every registered [`Sink`] whose [`Filter`]
+//! allows them. Filters are written as comma-separated directives such as
+//! `paging=trace,others=info`, where `others` (or a bare level) sets the level
+//! for modules that don't match any other directive.
+//!
+//! Filters can be set at boot through the kernel command line with one
+//! `log.<sink>=<filter>` option per sink, e.g. `log.serial=paging=trace,info`.
+//!
+//! The serial sink prefixes records with the time since boot and the module
+//! that logged them and colors the level. `KERNEL_LOG_FORMAT` picks another
+//! [`Style`] at compile time, e.g. `logfmt` for a host test runner.
+
+use core::fmt::Write as _;
+
+use kapi::logfmt::{self, Line, Style};
+use log::{Level, LevelFilter, Metadata, Record};
+use sync::cell::{AtomicCell, AtomicOnceCell, AtomicRefCell};
+
+/// Maximum number of sinks that can be registered.
+const MAX_SINKS: usize = 4;
+/// Maximum number of module directives in a filter.
+const MAX_DIRECTIVES: usize = 8;
+/// Maximum length of the module name in a directive.
+const MAX_MODULE_LEN: usize = 32;
+
+/// A destination for log records.
+pub trait Sink: Sync {
+    /// Name used to address the sink in filters.
+    fn name(&self) -> &'static str;
+    fn log(&self, record: &Record);
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Directive {
+    module: [u8; MAX_MODULE_LEN],
+    len: usize,
+    level: LevelFilter,
+}
+
+impl Directive {
+    fn module(&self) -> &str {
+        // SAFETY: Constructed from a `&str` in `Filter::parse`.
+        unsafe { core::str::from_utf8_unchecked(&self.module[..self.len]) }
+    }
+
+    fn matches(&self, module_path: &str) -> bool {
+        let module = self.module();
+        module_path.starts_with(module) || module_path.split("::").any(|part| part == module)
+    }
+}
+
+/// Per-sink log level filter.
+#[derive(Debug, Copy, Clone)]
+pub struct Filter {
+    default: LevelFilter,
+    directives: [Option<Directive>; MAX_DIRECTIVES],
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FilterError {
+    UnknownLevel,
+    ModuleTooLong,
+    TooManyDirectives,
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, FilterError> {
+    match level {
+        "off" => Ok(LevelFilter::Off),
+        "trace" => Ok(LevelFilter::Trace),
+        "debug" => Ok(LevelFilter::Debug),
+        "info" => Ok(LevelFilter::Info),
+        "warn" => Ok(LevelFilter::Warn),
+        "error" => Ok(LevelFilter::Error),
+        _ => Err(FilterError::UnknownLevel),
+    }
+}
+
+impl Filter {
+    pub const fn new(default: LevelFilter) -> Self {
+        Self {
+            default,
+            directives: [None; MAX_DIRECTIVES],
+        }
+    }
+
+    pub fn parse(spec: &str) -> Result<Self, FilterError> {
+        let mut filter = Self::new(LevelFilter::Off);
+        let mut count = 0;
+        for directive in spec.split(',').filter(|d| !d.is_empty()) {
+            let (module, level) = match directive.split_once('=') {
+                Some((module, level)) => (module, parse_level(level)?),
+                None => ("others", parse_level(directive)?),
+            };
+            if module == "others" {
+                filter.default = level;
+                continue;
+            }
+            if module.len() > MAX_MODULE_LEN {
+                return Err(FilterError::ModuleTooLong);
+            }
+            let slot = filter
+                .directives
+                .get_mut(count)
+                .ok_or(FilterError::TooManyDirectives)?;
+            let mut name = [0; MAX_MODULE_LEN];
+            name[..module.len()].copy_from_slice(module.as_bytes());
+            *slot = Some(Directive {
+                module: name,
+                len: module.len(),
+                level,
+            });
+            count += 1;
+        }
+        Ok(filter)
+    }
+
+    /// Sets the level for modules that don't match any directive.
+    pub fn set_default(&mut self, level: LevelFilter) {
+        self.default = level;
+    }
+
+    /// Sets the level of `module`, replacing its directive if there's one.
+    pub fn set_module(&mut self, module: &str, level: LevelFilter) -> Result<(), FilterError> {
+        if module.len() > MAX_MODULE_LEN {
+            return Err(FilterError::ModuleTooLong);
+        }
+        let existing = self
+            .directives
+            .iter()
+            .position(|directive| directive.is_some_and(|directive| directive.module() == module));
+        let index = existing
+            .or_else(|| self.directives.iter().position(Option::is_none))
+            .ok_or(FilterError::TooManyDirectives)?;
+        let mut name = [0; MAX_MODULE_LEN];
+        name[..module.len()].copy_from_slice(module.as_bytes());
+        self.directives[index] = Some(Directive {
+            module: name,
+            len: module.len(),
+            level,
+        });
+        Ok(())
+    }
+
+    /// Returns the level enabled for `module_path`.
+    ///
+    /// The last directive that matches the module wins.
+    pub fn level_for(&self, module_path: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .flatten()
+            .filter(|directive| directive.matches(module_path))
+            .last()
+            .map(|directive| directive.level)
+            .unwrap_or(self.default)
+    }
+
+    /// Returns the most verbose level enabled by this filter.
+    pub fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .flatten()
+            .map(|directive| directive.level)
+            .fold(self.default, core::cmp::max)
+    }
+}
+
+struct Registered {
+    sink: &'static dyn Sink,
+    filter: AtomicCell<Filter>,
+}
+
+static SINKS: [AtomicOnceCell<Registered>; MAX_SINKS] = [
+    AtomicOnceCell::new(),
+    AtomicOnceCell::new(),
+    AtomicOnceCell::new(),
+    AtomicOnceCell::new(),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SinkError {
+    NoSpace,
+    NotFound,
+}
+
+/// Registers a new sink with the given filter.
+pub fn register(sink: &'static dyn Sink, filter: Filter) -> Result<(), SinkError> {
+    let registered = SINKS
+        .iter()
+        .find(|slot| {
+            slot.set(Registered {
+                sink,
+                filter: AtomicCell::new(filter),
+            })
+            .is_ok()
+        })
+        .ok_or(SinkError::NoSpace);
+    update_max_level();
+    registered.map(|_| ())
+}
+
+/// Changes the filter for the sink called `name`.
+pub fn set_filter(name: &str, filter: Filter) -> Result<(), SinkError> {
+    let registered = sinks()
+        .find(|registered| registered.sink.name() == name)
+        .ok_or(SinkError::NotFound)?;
+    registered.filter.set(filter);
+    update_max_level();
+    Ok(())
+}
+
+/// Returns the filter of the sink called `name`.
+pub fn filter(name: &str) -> Result<Filter, SinkError> {
+    sinks()
+        .find(|registered| registered.sink.name() == name)
+        .map(|registered| registered.filter.get())
+        .ok_or(SinkError::NotFound)
+}
+
+/// Logs `text` to every sink whatever their filters, to line up events
+/// outside of the kernel with its log.
+pub fn marker(text: &str) {
+    for registered in sinks() {
+        registered.sink.log(
+            &Record::builder()
+                .args(format_args!("{text}"))
+                .level(Level::Info)
+                .target("marker")
+                .module_path_static(Some("kernel::marker"))
+                .build(),
+        );
+    }
+}
+
+fn sinks() -> impl Iterator<Item = &'static Registered> {
+    SINKS.iter().filter_map(AtomicOnceCell::get)
+}
+
+fn update_max_level() {
+    let level = sinks()
+        .map(|registered| registered.filter.get().max_level())
+        .fold(LevelFilter::Off, core::cmp::max);
+    log::set_max_level(level);
+}
+
+/// Applies every `log.<sink>=<filter>` option in the command line for the
+/// sinks `wanted` picks.
+fn apply_cmdline(cmdline: &str, wanted: impl Fn(&str) -> bool) {
+    for option in cmdline.split_ascii_whitespace() {
+        let Some((sink, spec)) = option
+            .strip_prefix("log.")
+            .and_then(|option| option.split_once('='))
+        else {
+            continue;
+        };
+        if !wanted(sink) {
+            continue;
+        }
+        match Filter::parse(spec).map(|filter| set_filter(sink, filter)) {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => log::warn!("Couldn't apply log filter to {sink}: {e:?}"),
+            Err(e) => log::warn!("Invalid log filter {spec:?}: {e:?}"),
+        }
+    }
+}
+
+/// The default filter for the serial port.
+fn default_filter() -> Filter {
+    let level = option_env!("KERNEL_LOG_LEVEL").unwrap_or("info");
+    match parse_level(level) {
+        Ok(level) => Filter::new(level),
+        Err(_) => panic!("Unknown LOG LEVEL: {level}"),
+    }
+}
+
+/// The style of the serial output.
+fn serial_style() -> Style {
+    let format = option_env!("KERNEL_LOG_FORMAT").unwrap_or("color");
+    match Style::parse(format) {
+        Some(style) => style,
+        None => panic!("Unknown log format: {format}"),
+    }
+}
+
+/// Sets up the logger with the serial and in-memory sinks, and the VGA text
+/// buffer if there's no framebuffer. sprint! and log macros after this.
+pub fn init() {
+    log::set_logger(&LOGGER).expect("Couldn't set the kernel logger");
+    register(&SERIAL_SINK, default_filter()).unwrap();
+    register(&RING_SINK, Filter::new(LevelFilter::Info)).unwrap();
+    if let Some(vga) = crate::vga::init() {
+        register(vga, Filter::new(LevelFilter::Info)).unwrap();
+    }
+
+    apply_cmdline(&crate::CMDLINE, |sink| sink != "virtio");
+    crate::serial::init();
+    log::info!("Logging initialized");
+}
+
+/// Adds the virtio console sink, which needs memory for its queue and so
+/// comes up once the retype table is initialized. It logs the same as the
+/// serial port.
+#[cfg(feature = "virtio-console")]
+pub fn init_virtio() {
+    if let Some(sink) = crate::virtio_console::init() {
+        register(sink, default_filter()).unwrap();
+        apply_cmdline(&crate::CMDLINE, |sink| sink == "virtio");
+    }
+}
+
+/// The global logger.
+static LOGGER: Logger = Logger {};
+
+struct Logger;
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        sinks().any(|registered| {
+            metadata.level() <= registered.filter.get().level_for(metadata.target())
+        })
+    }
+
+    fn log(&self, record: &Record) {
+        let module = record.module_path().unwrap_or(record.target());
+        for registered in sinks() {
+            if record.level() <= registered.filter.get().level_for(module) {
+                registered.sink.log(record);
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Logs to COM1.
+pub struct SerialSink;
+
+pub static SERIAL_SINK: SerialSink = SerialSink;
+
+impl Sink for SerialSink {
+    fn name(&self) -> &'static str {
+        "serial"
+    }
+
+    fn log(&self, record: &Record) {
+        crate::sprintln!("{}", SerialLine(record));
+    }
+}
+
+/// Shows a record the way the serial port does, in the [`Style`] picked by
+/// `KERNEL_LOG_FORMAT`.
+pub struct SerialLine<'a>(pub &'a Record<'a>);
+
+impl core::fmt::Display for SerialLine<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let record = self.0;
+        let level = match record.level() {
+            Level::Error => logfmt::Level::Error,
+            Level::Warn => logfmt::Level::Warn,
+            Level::Info => logfmt::Level::Info,
+            Level::Debug => logfmt::Level::Debug,
+            Level::Trace => logfmt::Level::Trace,
+        };
+        let record = logfmt::Record {
+            nanos: Some(crate::info::nanos_since_boot()),
+            level,
+            component: "kernel",
+            module: record.module_path().unwrap_or(record.target()),
+            args: *record.args(),
+        };
+        let line = Line {
+            style: serial_style(),
+            record: &record,
+        };
+        write!(f, "{line}")
+    }
+}
+
+/// Size of the in-memory log ring in bytes.
+const LOG_RING_SIZE: usize = 4096;
+
+/// Keeps the most recent log output in memory.
+pub struct RingSink {
+    ring: AtomicRefCell<LogRing>,
+}
+
+pub static RING_SINK: RingSink = RingSink {
+    ring: AtomicRefCell::new(LogRing {
+        data: [0; LOG_RING_SIZE],
+        written: 0,
+    }),
+};
+
+struct LogRing {
+    data: [u8; LOG_RING_SIZE],
+    written: usize,
+}
+
+impl core::fmt::Write for LogRing {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for byte in s.bytes() {
+            self.data[self.written % LOG_RING_SIZE] = byte;
+            self.written = self.written.wrapping_add(1);
+        }
+        Ok(())
+    }
+}
+
+impl RingSink {
+    /// Total number of bytes ever logged to the ring.
+    pub fn written(&self) -> usize {
+        self.ring.borrow().map(|ring| ring.written).unwrap_or(0)
+    }
+
+    /// Copies the most recent log output into `buf`, returning the number of bytes copied.
+    pub fn read(&self, buf: &mut [u8]) -> usize {
+        let Ok(ring) = self.ring.borrow() else {
+            return 0;
+        };
+        let count = buf.len().min(ring.written).min(LOG_RING_SIZE);
+        let start = ring.written - count;
+        for (offset, out) in buf[..count].iter_mut().enumerate() {
+            *out = ring.data[(start + offset) % LOG_RING_SIZE];
+        }
+        count
+    }
+}
+
+impl Sink for RingSink {
+    fn name(&self) -> &'static str {
+        "ring"
+    }
+
+    fn log(&self, record: &Record) {
+        // Records are dropped if the ring is being read.
+        if let Ok(mut ring) = self.ring.borrow_mut() {
+            let _ = writeln!(ring, "{} - {}", record.level(), record.args());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn filter_directives() {
+        let filter = Filter::parse("paging=trace,others=warn").unwrap();
+        assert_eq!(
+            filter.level_for("kernel::arch::x86_64::paging::page_table"),
+            LevelFilter::Trace
+        );
+        assert_eq!(filter.level_for("kernel::caps"), LevelFilter::Warn);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+        assert_eq!(
+            Filter::parse("info").unwrap().level_for("kernel"),
+            LevelFilter::Info
+        );
+        assert_eq!(
+            Filter::parse("caps=loud").unwrap_err(),
+            FilterError::UnknownLevel
+        );
+    }
+
+    #[test_case]
+    fn markers_reach_every_sink() {
+        let written = RING_SINK.written();
+        marker("test marker 42");
+        let mut tail = [0; 64];
+        let len = RING_SINK.read(&mut tail);
+        assert!(RING_SINK.written() > written);
+        let tail = core::str::from_utf8(&tail[..len]).unwrap();
+        assert!(tail.ends_with("test marker 42\n"));
+    }
+
+    #[test_case]
+    fn changes_filters_in_place() {
+        let mut filter = Filter::parse("paging=trace,others=warn").unwrap();
+        filter.set_default(LevelFilter::Error);
+        filter.set_module("paging", LevelFilter::Debug).unwrap();
+        filter.set_module("sched", LevelFilter::Info).unwrap();
+        assert_eq!(filter.level_for("kernel::paging"), LevelFilter::Debug);
+        assert_eq!(filter.level_for("kernel::sched"), LevelFilter::Info);
+        assert_eq!(filter.level_for("kernel::caps"), LevelFilter::Error);
+        // Replacing a directive doesn't use up another one.
+        assert_eq!(filter.directives.iter().flatten().count(), 2);
+        for i in 0..MAX_DIRECTIVES - 2 {
+            filter
+                .set_module(["a", "b", "c", "d", "e", "f"][i], LevelFilter::Off)
+                .unwrap();
+        }
+        assert_eq!(
+            filter.set_module("full", LevelFilter::Off),
+            Err(FilterError::TooManyDirectives)
+        );
+    }
+}
@@ -9,64 +9,155 @@
 )]
 #![cfg_attr(target_arch = "x86_64", feature(abi_x86_interrupt))]
 
-use limine::memory_map::Entry;
-use limine::request::{HhdmRequest, MemoryMapRequest, StackSizeRequest};
-use limine::BaseRevision;
+#[cfg(target_arch = "x86_64")]
 use sync::cell::AtomicLazyCell;
 
-use crate::arch::interrupts;
+#[cfg(target_arch = "x86_64")]
 use crate::arch::paging::VirtAddr;
+use crate::arch::{Arch as _, Current};
+#[cfg(target_arch = "x86_64")]
+use crate::boot::BootProtocol as _;
+#[cfg(target_arch = "x86_64")]
 use crate::retyping::RetypeTable;
 
 pub mod arch;
+
+// Everything past the arch layer only builds for x86_64 so far (see `arch`).
+#[cfg(target_arch = "x86_64")]
+pub mod audit;
+#[cfg(target_arch = "x86_64")]
+pub mod boot;
+#[cfg(target_arch = "x86_64")]
 pub mod bump_allocator;
+#[cfg(target_arch = "x86_64")]
 pub mod caps;
+#[cfg(target_arch = "x86_64")]
 pub mod component;
+#[cfg(all(target_arch = "x86_64", feature = "control"))]
+pub mod control;
+#[cfg(target_arch = "x86_64")]
 pub mod core_local;
+#[cfg(target_arch = "x86_64")]
+pub mod devices;
+#[cfg(target_arch = "x86_64")]
+pub mod diagnostics;
+#[cfg(target_arch = "x86_64")]
+pub mod display;
+#[cfg(target_arch = "x86_64")]
+pub mod endpoint;
+#[cfg(target_arch = "x86_64")]
+pub mod entropy;
+#[cfg(all(target_arch = "x86_64", feature = "fault-injection"))]
+pub mod fault;
+#[cfg(target_arch = "x86_64")]
+pub mod hierarchy;
+#[cfg(target_arch = "x86_64")]
+pub mod info;
+#[cfg(target_arch = "x86_64")]
+pub mod initrd;
+#[cfg(target_arch = "x86_64")]
+pub mod ipi;
+#[cfg(target_arch = "x86_64")]
 pub mod kptr;
+#[cfg(target_arch = "x86_64")]
+pub mod latency;
+#[cfg(target_arch = "x86_64")]
+pub mod logging;
+#[cfg(target_arch = "x86_64")]
+pub mod measure;
+#[cfg(target_arch = "x86_64")]
+pub mod profile;
+#[cfg(target_arch = "x86_64")]
+pub mod reserve;
+#[cfg(target_arch = "x86_64")]
 pub mod retyping;
+#[cfg(all(target_arch = "x86_64", feature = "round-robin"))]
+pub mod sched;
+#[cfg(target_arch = "x86_64")]
+pub mod scrub;
+#[cfg(target_arch = "x86_64")]
+pub mod serial;
+#[cfg(target_arch = "x86_64")]
+pub mod stack;
+#[cfg(target_arch = "x86_64")]
 pub mod syscall;
+#[cfg(target_arch = "x86_64")]
+pub mod trace;
+#[cfg(target_arch = "x86_64")]
+pub mod user_frame;
+#[cfg(target_arch = "x86_64")]
+pub mod vga;
+#[cfg(all(target_arch = "x86_64", feature = "virtio-console"))]
+pub mod virtio_console;
 
-#[cfg(test)]
+#[cfg(all(target_arch = "x86_64", test, feature = "bench"))]
+mod bench;
+#[cfg(all(target_arch = "x86_64", test))]
 mod testing;
 
-mod serial;
-
-pub type MemoryMap = &'static mut [&'static mut Entry];
-
-pub const UNTYPED_MEMORY_OFFSET: usize = 0x0000_7000_0000_0000;
+#[cfg(target_arch = "x86_64")]
+pub use boot::MemoryMap;
 
+#[cfg(target_arch = "x86_64")]
 pub static PMO: AtomicLazyCell<VirtAddr> = AtomicLazyCell::new(|| {
-    #[used]
-    static HHDM: HhdmRequest = HhdmRequest::new();
-
-    let pmo = HHDM
-        .get_response()
-        .expect("Missing Higher-half direct mapping response from limine")
-        .offset();
+    let pmo = boot::protocol()
+        .hhdm_offset()
+        .expect("Missing higher-half direct mapping from the bootloader");
     // PMO must be on the higher half
     assert!(pmo >= 0xFFFF_8000_0000_0000);
     VirtAddr::new(pmo as usize)
 });
 
-#[cfg(not(test))]
+/// The command line the kernel was booted with.
+///
+/// Empty if the bootloader didn't provide one or it isn't valid UTF-8.
+#[cfg(target_arch = "x86_64")]
+pub static CMDLINE: AtomicLazyCell<&'static str> = AtomicLazyCell::new(|| {
+    boot::protocol()
+        .cmdline()
+        .and_then(|cmdline| match core::str::from_utf8(cmdline) {
+            Ok(cmdline) => Some(cmdline),
+            Err(_) => {
+                log::warn!("Kernel command line isn't valid UTF-8");
+                None
+            }
+        })
+        .unwrap_or("")
+});
+
+#[cfg(all(target_arch = "x86_64", not(test)))]
 #[no_mangle]
 extern "C" fn kmain() -> ! {
     use arch::bootup::Process;
-    use arch::exec::{ExecCtx, NoopSaver};
-    use arch::paging::RawFrame;
+    use arch::context::{ExecCtx, NoopSaver};
+    use arch::exec::KernelStack;
+    use arch::paging::policy::MappingPolicy;
+    use arch::paging::{PhysAddr, RawFrame, FRAME_SIZE};
     use bump_allocator::BumpAllocator;
-    use caps::RawCapEntry;
+    use caps::{CapEntryExtension as _, RawCapEntry, Region, Resource};
     use component::Thread;
+    use kapi::endowment::ResourceKind;
     use kptr::KPtr;
 
     init();
 
     let booter: ExecCtx = {
         let proc = include_bytes_aligned::include_bytes_aligned!(16, "../../../.build/booter");
+        measure::verify("booter", proc, env!("BOOTER_SHA256"))
+            .expect("Refusing to start the boot component");
         log::info!("Loading user process");
+        // The boot component doesn't generate code.
         let process =
-            Process::load(proc, 10, UNTYPED_MEMORY_OFFSET, RawFrame::memory_limit()).unwrap();
+            Process::load(proc, MappingPolicy::STRICT, kapi::layout::STANDARD, 10).unwrap();
+        // The boot component is endowed with every resource regardless of what
+        // it asks for, so the rest of its notes are only informative.
+        for interface in process.metadata.interfaces() {
+            log::info!("Boot component needs interface {:X?}", interface.0);
+        }
+        if let Some(heap) = process.metadata.heap_size {
+            log::info!("Boot component reserves {heap} bytes of heap");
+        }
+        diagnostics::phase("boot component loaded");
         process.into_exec()
     };
     let mut fallocator = BumpAllocator::new();
@@ -74,52 +165,153 @@ extern "C" fn kmain() -> ! {
         let frame = fallocator.alloc_untyped_frame().unwrap();
         KPtr::new(frame, RawCapEntry::default()).unwrap()
     };
+    for endowment in kapi::endowment::BOOT {
+        let resource = match endowment.kind {
+            ResourceKind::Logger => Resource::Logger,
+            ResourceKind::Ipi => Resource::Ipi,
+            ResourceKind::Clock => Resource::Clock,
+            ResourceKind::Diagnostics => Resource::Diagnostics,
+            ResourceKind::PerfCounter => Resource::PerfCounter,
+            ResourceKind::Iommu => Resource::Iommu,
+            ResourceKind::Initrd => Resource::Initrd,
+            ResourceKind::System => Resource::System,
+            ResourceKind::Display => Resource::Display,
+            // The table holds itself, so it's never freed, but neither is the
+            // boot component.
+            ResourceKind::CapTable => Resource::CapEntry(resources.clone()),
+            ResourceKind::Region => {
+                let frames = RawFrame::memory_limit() / FRAME_SIZE as usize;
+                let region = u32::try_from(frames)
+                    .ok()
+                    .and_then(|frames| {
+                        Region::new(
+                            RawFrame::from_start_address(PhysAddr::new(0)).into(),
+                            frames,
+                        )
+                    })
+                    .expect("Too much physical memory for a region");
+                Resource::Region(region)
+            }
+            kind => panic!("Can't endow the boot component with {kind:?}"),
+        };
+        resources
+            .clone()
+            .find(endowment.cap)
+            .unwrap()
+            .change(|slot| slot.resource = resource);
+    }
+    let kernel_stack = {
+        let frame = fallocator.alloc_untyped_frame().unwrap();
+        KPtr::new(frame, KernelStack::new()).unwrap()
+    };
     let thread = {
         let frame = fallocator.alloc_untyped_frame().unwrap();
-        KPtr::new(frame, Thread::new_with_ctx(booter, resources)).unwrap()
+        KPtr::new(frame, Thread::new_with_ctx(booter, resources, kernel_stack)).unwrap()
     };
 
     log::info!("Jumping to boot component");
     Thread::dispatch(thread, NoopSaver::new());
 }
 
-pub fn init() {
-    #[used]
-    static BASE_REVISION: BaseRevision = BaseRevision::with_revision(1);
-
-    #[used]
-    static mut MEMORY_MAP: MemoryMapRequest = MemoryMapRequest::new();
+/// Brings up the boot core, which is as far as the aarch64 and riscv64 ports
+/// go.
+#[cfg(all(any(target_arch = "aarch64", target_arch = "riscv64"), not(test)))]
+#[no_mangle]
+extern "C" fn kmain() -> ! {
+    Current::init();
+    log::info!("Harmony is up on core {}", Current::cpu_id());
+    log::info!(
+        "The rest of the kernel isn't ported yet, counting ticks at {} Hz",
+        Current::counter_frequency()
+    );
+    // SAFETY: The timer interrupt handler only touches its own state.
+    unsafe { Current::enable_interrupts() };
+    loop {
+        Current::wait_for_interrupt();
+    }
+}
 
-    #[used]
-    static STACK_SIZE: StackSizeRequest = StackSizeRequest::new().with_size(0x32000);
-    interrupts::disable();
+#[cfg(target_arch = "x86_64")]
+pub fn init() {
+    Current::disable_interrupts();
+    stack::paint();
+    // Lets the sync cells detect re-entrant initialization.
+    sync::context::set_context_id(core_local::current_core);
 
-    serial::init();
+    logging::init();
+    diagnostics::phase("logging");
+    #[cfg(feature = "fault-injection")]
+    fault::init();
     assert!(
-        BASE_REVISION.is_supported(),
-        "Limine revision not supported"
+        boot::protocol().is_supported(),
+        "{} boot protocol revision not supported",
+        boot::protocol().name()
     );
 
     arch::init();
-
-    STACK_SIZE.get_response().unwrap();
+    diagnostics::phase("arch");
+    arch::sections::log_kernel_address();
+    boot::log_info();
 
     log::info!(
-        "Got physical memory offset from limine at {:#X}",
+        "Got physical memory offset from the bootloader at {:#X}",
         PMO.as_usize()
     );
 
-    let memory_map = unsafe {
-        MEMORY_MAP
-            .get_response_mut()
-            .expect("Missing memory map from Limine")
-            .entries_mut()
-    };
-    RetypeTable::new(memory_map).unwrap().init().unwrap();
+    let memory_map = boot::protocol()
+        .memory_map()
+        .expect("Missing memory map from the bootloader");
+    RetypeTable::new(memory_map)
+        .unwrap_or_else(|e| panic!("Can't track memory with this memory map: {e:?}"))
+        .init()
+        .unwrap();
     log::info!("Initialized the retype table");
+    retyping::log_usage();
+    diagnostics::phase("retype table");
+    #[cfg(feature = "virtio-console")]
+    logging::init_virtio();
+    scrub::init();
+    reserve::init();
 
     component::init();
+    #[cfg(feature = "round-robin")]
+    sched::init();
     log::info!("Initialized component system");
+    diagnostics::phase("components");
+
+    info::init();
+    log::info!("Initialized the kernel info page");
+    diagnostics::phase("info page");
+
+    entropy::init();
+    diagnostics::phase("entropy");
+
+    devices::init();
+    log::info!("Initialized the device inventory");
+    diagnostics::phase("devices");
+
+    arch::iommu::init();
+    diagnostics::phase("iommu");
+
+    initrd::init();
+    display::init();
+
+    arch::dynlink::init();
+    diagnostics::phase("shared library");
+
+    diagnostics::init();
+    log::info!("Initialized the diagnostics page");
+
+    #[cfg(feature = "control")]
+    control::init();
+
+    arch::sections::protect_kernel();
+    arch::sections::check_wx();
+
+    stack::check();
+    if let Some(usage) = stack::stack_usage() {
+        log::info!("Boot stack used {} of {} bytes", usage.used, usage.size);
+    }
 }
 
 #[cfg(all(target_os = "none", not(test)))]
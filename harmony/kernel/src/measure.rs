@@ -0,0 +1,53 @@
+//! Integrity checks of the images the kernel starts.
+//!
+//! The kernel's build script hashes every component embedded in the kernel
+//! image. Before a component is loaded, its image is hashed again and compared
+//! with the digest recorded at build time so that corrupted or tampered images
+//! are never started.
+
+use sha256::Digest;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MeasureError {
+    /// The recorded digest isn't valid hex.
+    BadDigest,
+    Mismatch {
+        expected: Digest,
+        actual: Digest,
+    },
+}
+
+/// Hashes `image` and checks it against the `expected` hex digest.
+///
+/// Returns the measured digest. Mismatches are reported over the log.
+pub fn verify(name: &str, image: &[u8], expected: &str) -> Result<Digest, MeasureError> {
+    let expected = Digest::from_hex(expected).map_err(|_| MeasureError::BadDigest)?;
+    let actual = sha256::digest(image);
+    if actual != expected {
+        log::error!("Integrity check of {name} failed");
+        log::error!("  expected: {expected}");
+        log::error!("  actual:   {actual}");
+        return Err(MeasureError::Mismatch { expected, actual });
+    }
+    log::info!("Measured {name}: {actual}");
+    Ok(actual)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn rejects_tampered_images() {
+        const ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(
+            verify("abc", b"abc", ABC),
+            Ok(Digest::from_hex(ABC).unwrap())
+        );
+        assert!(matches!(
+            verify("abd", b"abd", ABC),
+            Err(MeasureError::Mismatch { .. })
+        ));
+        assert_eq!(verify("abc", b"abc", "abc"), Err(MeasureError::BadDigest));
+    }
+}
@@ -0,0 +1,210 @@
+//! Telling page faults apart.
+//!
+//! A page fault is one of three things: a bug in the kernel, a component
+//! touching memory it can't access, or a component touching a page that its
+//! pager swapped out. The first is fatal, the other two only concern the
+//! component. [`classify`] decides which from the error code the processor
+//! pushes and, for faults on missing user pages, the swap token left in the
+//! page's entry.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use kapi::diagnostics::PageFaultStats;
+
+/// The error code of a page fault.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct FaultCode(u64);
+
+impl FaultCode {
+    const PRESENT: u64 = 1 << 0;
+    const WRITE: u64 = 1 << 1;
+    const USER: u64 = 1 << 2;
+    const RESERVED: u64 = 1 << 3;
+    const FETCH: u64 = 1 << 4;
+    const PROTECTION_KEY: u64 = 1 << 5;
+    const SHADOW_STACK: u64 = 1 << 6;
+
+    pub const fn new(code: u64) -> Self {
+        Self(code)
+    }
+
+    /// Whether the page was mapped, i.e. the access broke its permissions.
+    pub fn present(self) -> bool {
+        self.0 & Self::PRESENT != 0
+    }
+
+    pub fn write(self) -> bool {
+        self.0 & Self::WRITE != 0
+    }
+
+    /// Whether the access was made from userspace.
+    pub fn user(self) -> bool {
+        self.0 & Self::USER != 0
+    }
+
+    /// Whether an entry on the way to the page had reserved bits set.
+    pub fn reserved(self) -> bool {
+        self.0 & Self::RESERVED != 0
+    }
+
+    /// Whether the access was an instruction fetch.
+    pub fn fetch(self) -> bool {
+        self.0 & Self::FETCH != 0
+    }
+}
+
+impl fmt::Debug for FaultCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self} ({:#X})", self.0)
+    }
+}
+
+/// Describes the access, e.g. "user write to a present page".
+impl fmt::Display for FaultCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = if self.user() { "user" } else { "kernel" };
+        let access = match (self.fetch(), self.write()) {
+            (true, _) => "instruction fetch from",
+            (false, true) => "write to",
+            (false, false) => "read from",
+        };
+        let page = if self.present() { "present" } else { "missing" };
+        write!(f, "{mode} {access} a {page} page")?;
+        let causes = [
+            (Self::RESERVED, "reserved bits set"),
+            (Self::PROTECTION_KEY, "protection key"),
+            (Self::SHADOW_STACK, "shadow stack"),
+        ];
+        for (bit, cause) in causes {
+            if self.0 & bit != 0 {
+                write!(f, ", {cause}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a page fault was caused by.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FaultClass {
+    /// The kernel accessed memory it shouldn't have.
+    Kernel,
+    /// Userspace accessed memory it can't.
+    User,
+    /// Userspace accessed a page that was swapped out and left `token` in
+    /// its entry.
+    Swapped { token: u64 },
+}
+
+/// Classifies a fault with error code `code`.
+///
+/// `swap_token` looks up the token left in the faulting page's entry. It's
+/// only called for userspace faults on missing pages.
+pub fn classify(code: FaultCode, swap_token: impl FnOnce() -> Option<u64>) -> FaultClass {
+    if !code.user() {
+        return FaultClass::Kernel;
+    }
+    if code.present() || code.reserved() {
+        return FaultClass::User;
+    }
+    match swap_token() {
+        Some(token) => FaultClass::Swapped { token },
+        None => FaultClass::User,
+    }
+}
+
+/// Number of faults of each class.
+#[derive(Debug)]
+pub struct FaultCounters {
+    kernel: AtomicU64,
+    user: AtomicU64,
+    swapped: AtomicU64,
+}
+
+impl Default for FaultCounters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FaultCounters {
+    pub const fn new() -> Self {
+        Self {
+            kernel: AtomicU64::new(0),
+            user: AtomicU64::new(0),
+            swapped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn count(&self, class: FaultClass) {
+        let counter = match class {
+            FaultClass::Kernel => &self.kernel,
+            FaultClass::User => &self.user,
+            FaultClass::Swapped { .. } => &self.swapped,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> PageFaultStats {
+        PageFaultStats {
+            kernel: self.kernel.load(Ordering::Relaxed),
+            user: self.user.load(Ordering::Relaxed),
+            swapped: self.swapped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_by_mode_and_swap_token() {
+        let no_token = || -> Option<u64> { panic!("looked up a token") };
+        assert_eq!(
+            classify(FaultCode::new(0b010), no_token),
+            FaultClass::Kernel
+        );
+        assert_eq!(classify(FaultCode::new(0b111), no_token), FaultClass::User);
+        assert_eq!(classify(FaultCode::new(0b1100), no_token), FaultClass::User);
+        assert_eq!(classify(FaultCode::new(0b100), || None), FaultClass::User);
+        assert_eq!(
+            classify(FaultCode::new(0b110), || Some(7)),
+            FaultClass::Swapped { token: 7 }
+        );
+
+        let counters = FaultCounters::new();
+        counters.count(FaultClass::User);
+        counters.count(FaultClass::Swapped { token: 7 });
+        counters.count(FaultClass::User);
+        assert_eq!(
+            counters.stats(),
+            PageFaultStats {
+                kernel: 0,
+                user: 2,
+                swapped: 1
+            }
+        );
+    }
+
+    #[test]
+    fn describes_the_access() {
+        assert_eq!(
+            FaultCode::new(0b000).to_string(),
+            "kernel read from a missing page"
+        );
+        assert_eq!(
+            FaultCode::new(0b111).to_string(),
+            "user write to a present page"
+        );
+        assert_eq!(
+            FaultCode::new(0b1_0101).to_string(),
+            "user instruction fetch from a present page"
+        );
+        assert_eq!(
+            format!("{:?}", FaultCode::new(0b110_1001)),
+            "kernel read from a present page, reserved bits set, protection key, shadow stack (0x69)"
+        );
+    }
+}
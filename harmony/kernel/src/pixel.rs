@@ -0,0 +1,204 @@
+//! Encoding colors into framebuffer pixels.
+//!
+//! Bootloaders describe a framebuffer's pixels as a bit field per color
+//! channel rather than naming one of a few byte orders, and UEFI GOP modes
+//! use all sorts of layouts: 32-bit BGRx and RGBx, 24-bit packed, 16-bit 565
+//! and so on. [`PixelFormat`] checks the fields once and then encodes colors
+//! with a shift per channel. Framebuffers without directly encoded colors,
+//! e.g. palette-indexed ones, are refused with [`PixelError::Unsupported`].
+
+/// A color channel's bit field in a pixel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Channel {
+    /// Position of the lowest bit.
+    pub shift: u8,
+    /// Number of bits, at most 8.
+    pub size: u8,
+}
+
+impl Channel {
+    pub const fn new(shift: u8, size: u8) -> Self {
+        Self { shift, size }
+    }
+
+    fn mask(self) -> u32 {
+        ((1u32 << self.size) - 1) << self.shift
+    }
+
+    /// Places the top bits of an 8-bit `value` in the field.
+    fn encode(self, value: u8) -> u32 {
+        u32::from(value >> (8 - self.size)) << self.shift
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PixelError {
+    /// The framebuffer doesn't encode colors directly, e.g. it's indexed or
+    /// can only be drawn to through firmware calls.
+    Unsupported,
+    /// Pixels that aren't 2, 3 or 4 bytes.
+    BadDepth(u16),
+    /// A channel is empty, wider than 8 bits, outside of the pixel or
+    /// overlaps another one.
+    BadChannel,
+}
+
+/// How colors are laid out in the pixels of a framebuffer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PixelFormat {
+    red: Channel,
+    green: Channel,
+    blue: Channel,
+    bytes: u8,
+}
+
+impl PixelFormat {
+    /// 32-bit pixels with blue in the lowest byte, the most common GOP mode.
+    pub const BGRX: PixelFormat = PixelFormat {
+        red: Channel::new(16, 8),
+        green: Channel::new(8, 8),
+        blue: Channel::new(0, 8),
+        bytes: 4,
+    };
+
+    /// Checks the channels of a pixel of `bpp` bits.
+    pub fn new(bpp: u16, red: Channel, green: Channel, blue: Channel) -> Result<Self, PixelError> {
+        let bytes = match bpp {
+            15 | 16 => 2,
+            24 => 3,
+            32 => 4,
+            _ => return Err(PixelError::BadDepth(bpp)),
+        };
+        let channels = [red, green, blue];
+        let fits = channels.iter().all(|channel| {
+            (1..=8).contains(&channel.size)
+                && u32::from(channel.shift) + u32::from(channel.size) <= bpp.into()
+        });
+        if !fits {
+            return Err(PixelError::BadChannel);
+        }
+        let overlap = red.mask() & green.mask() != 0
+            || red.mask() & blue.mask() != 0
+            || green.mask() & blue.mask() != 0;
+        if overlap {
+            return Err(PixelError::BadChannel);
+        }
+        Ok(Self {
+            red,
+            green,
+            blue,
+            bytes,
+        })
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bytes.into()
+    }
+
+    /// The red, green and blue channels.
+    pub fn channels(&self) -> [Channel; 3] {
+        [self.red, self.green, self.blue]
+    }
+
+    /// Encodes a color, dropping the low bits of channels narrower than 8
+    /// bits. Bits outside of the channels are zero.
+    pub fn encode(&self, red: u8, green: u8, blue: u8) -> u32 {
+        self.red.encode(red) | self.green.encode(green) | self.blue.encode(blue)
+    }
+
+    /// Writes a color to the first [`bytes_per_pixel`](Self::bytes_per_pixel)
+    /// bytes of `pixel`.
+    ///
+    /// # Panics
+    ///
+    /// If `pixel` is shorter than that.
+    pub fn write(&self, pixel: &mut [u8], red: u8, green: u8, blue: u8) {
+        let bytes = self.encode(red, green, blue).to_le_bytes();
+        pixel[..self.bytes_per_pixel()].copy_from_slice(&bytes[..self.bytes_per_pixel()]);
+    }
+
+    /// Offset of the pixel at `x`, `y` in a framebuffer whose rows are
+    /// `pitch` bytes apart, which may be more than the width of a row.
+    pub fn offset(&self, pitch: usize, x: usize, y: usize) -> Option<usize> {
+        y.checked_mul(pitch)?
+            .checked_add(x.checked_mul(self.bytes_per_pixel())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_common_layouts() {
+        let bgrx = PixelFormat::new(
+            32,
+            Channel::new(16, 8),
+            Channel::new(8, 8),
+            Channel::new(0, 8),
+        );
+        assert_eq!(bgrx, Ok(PixelFormat::BGRX));
+        assert_eq!(PixelFormat::BGRX.encode(0x12, 0x34, 0x56), 0x12_3456);
+
+        let rgbx = PixelFormat::new(
+            32,
+            Channel::new(0, 8),
+            Channel::new(8, 8),
+            Channel::new(16, 8),
+        )
+        .unwrap();
+        let mut pixel = [0xFF; 4];
+        rgbx.write(&mut pixel, 0x12, 0x34, 0x56);
+        assert_eq!(pixel, [0x12, 0x34, 0x56, 0]);
+
+        let rgb565 = PixelFormat::new(
+            16,
+            Channel::new(11, 5),
+            Channel::new(5, 6),
+            Channel::new(0, 5),
+        )
+        .unwrap();
+        assert_eq!(rgb565.encode(0xFF, 0xFF, 0xFF), 0xFFFF);
+        assert_eq!(rgb565.encode(0x08, 0x04, 0x08), 0x0821);
+        let mut pixel = [0xAA; 3];
+        rgb565.write(&mut pixel, 0xFF, 0, 0);
+        // The byte past the pixel is left alone.
+        assert_eq!(pixel, [0x00, 0xF8, 0xAA]);
+
+        let packed = PixelFormat::new(
+            24,
+            Channel::new(16, 8),
+            Channel::new(8, 8),
+            Channel::new(0, 8),
+        )
+        .unwrap();
+        assert_eq!(packed.bytes_per_pixel(), 3);
+        assert_eq!(packed.offset(3000, 2, 1), Some(3006));
+        assert_eq!(packed.offset(usize::MAX, 0, 2), None);
+    }
+
+    #[test]
+    fn refuses_bad_layouts() {
+        let byte = |shift| Channel::new(shift, 8);
+        assert_eq!(
+            PixelFormat::new(8, byte(0), byte(0), byte(0)),
+            Err(PixelError::BadDepth(8))
+        );
+        assert_eq!(
+            PixelFormat::new(32, byte(0), byte(4), byte(16)),
+            Err(PixelError::BadChannel)
+        );
+        assert_eq!(
+            PixelFormat::new(24, byte(0), byte(8), byte(24)),
+            Err(PixelError::BadChannel)
+        );
+        assert_eq!(
+            PixelFormat::new(32, byte(0), Channel::new(8, 0), byte(16)),
+            Err(PixelError::BadChannel)
+        );
+        assert_eq!(
+            PixelFormat::new(32, byte(0), Channel::new(8, 10), byte(24)),
+            Err(PixelError::BadChannel)
+        );
+    }
+}
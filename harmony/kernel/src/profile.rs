@@ -0,0 +1,225 @@
+//! Sampling profiler.
+//!
+//! While sampling is enabled, the timer interrupt records the call stack of
+//! whatever it interrupted every few ticks. The stack is walked through frame
+//! pointers, in the kernel or in the interrupted component, checking every
+//! frame against the page tables so that a component without frame pointers
+//! only cuts its stacks short. Identical stacks are counted together in a
+//! table per core, which the diagnostics capability drains.
+//!
+//! FIXME: Sample on performance counter overflows too once they can interrupt.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use kapi::profile::{StackSample, MAX_DEPTH};
+use sync::cell::AtomicRefCell;
+
+use crate::arch::context::IrqFrame;
+use crate::arch::paging::page_table::{AnyPageTable, PageTableFlags};
+use crate::arch::paging::policy::USER_END;
+use crate::arch::paging::{Page, VirtAddr};
+use crate::core_local::{current_core, NUM_CORES};
+
+/// Distinct stacks each core holds until they're drained.
+const STACKS: usize = 128;
+
+/// Timer ticks between samples, or 0 if sampling is off.
+static EVERY: AtomicU32 = AtomicU32::new(0);
+
+struct Profile {
+    stacks: [StackSample; STACKS],
+    len: usize,
+    /// Ticks left until the next sample.
+    countdown: u32,
+    /// Samples that didn't fit.
+    dropped: u64,
+}
+
+impl Profile {
+    const fn new() -> Self {
+        Self {
+            stacks: [StackSample {
+                count: 0,
+                core: 0,
+                user: false,
+                depth: 0,
+                frames: [0; MAX_DEPTH],
+            }; STACKS],
+            len: 0,
+            countdown: 0,
+            dropped: 0,
+        }
+    }
+
+    fn record(&mut self, sample: StackSample) {
+        let stacks = &mut self.stacks[..self.len];
+        if let Some(stack) = stacks
+            .iter_mut()
+            .find(|stack| stack.user == sample.user && stack.frames() == sample.frames())
+        {
+            stack.count += sample.count;
+        } else if self.len < STACKS {
+            self.stacks[self.len] = sample;
+            self.len += 1;
+        } else {
+            self.dropped += sample.count;
+        }
+    }
+
+    fn drain(&mut self, out: &mut [StackSample]) -> usize {
+        let count = out.len().min(self.len);
+        out[..count].copy_from_slice(&self.stacks[self.len - count..self.len]);
+        self.len -= count;
+        count
+    }
+}
+
+static PROFILES: [AtomicRefCell<Profile>; NUM_CORES] =
+    [const { AtomicRefCell::new(Profile::new()) }; NUM_CORES];
+
+/// Samples once every `every` timer ticks, or stops sampling if it's 0.
+pub fn start(every: u32) {
+    EVERY.store(every, Ordering::Relaxed);
+}
+
+/// Samples the interrupted code if it's time to.
+pub fn tick(frame: &IrqFrame) {
+    let every = EVERY.load(Ordering::Relaxed);
+    if every == 0 {
+        return;
+    }
+    let core = current_core();
+    let Ok(mut profile) = PROFILES[core].borrow_mut() else {
+        return;
+    };
+    if profile.countdown > 1 {
+        profile.countdown -= 1;
+        return;
+    }
+    profile.countdown = every;
+    let mut sample = StackSample {
+        count: 1,
+        core: core as u32,
+        user: frame.from_user(),
+        ..Default::default()
+    };
+    sample.depth = walk(frame.rip(), frame.rbp(), sample.user, &mut sample.frames);
+    profile.record(sample);
+}
+
+/// Moves up to `out.len()` stacks sampled on `core` into `out` and returns
+/// how many were moved, or `None` if there's no such core or its table is in
+/// use.
+pub fn drain(core: usize, out: &mut [StackSample]) -> Option<usize> {
+    let mut profile = PROFILES.get(core)?.borrow_mut().ok()?;
+    if profile.dropped > 0 {
+        log::warn!("Dropped {} samples on core {core}", profile.dropped);
+        profile.dropped = 0;
+    }
+    Some(profile.drain(out))
+}
+
+/// Records `rip` and the return addresses found by following the frame
+/// pointer `rbp` into `frames`, returning how many there are.
+fn walk(rip: u64, mut rbp: u64, user: bool, frames: &mut [u64; MAX_DEPTH]) -> u8 {
+    frames[0] = rip;
+    let mut depth = 1;
+    while depth < MAX_DEPTH {
+        // Each frame starts with the caller's frame pointer followed by the
+        // return address.
+        if rbp % 8 != 0 || !readable(rbp, user) || !readable(rbp + 8, user) {
+            break;
+        }
+        let record = rbp as *const u64;
+        // SAFETY: Both words are mapped and readable at this privilege level.
+        let (caller, ret) = unsafe { (record.read_volatile(), record.add(1).read_volatile()) };
+        if ret == 0 {
+            break;
+        }
+        frames[depth] = ret;
+        depth += 1;
+        // Callers' frames are further up the stack, anything else is a cycle
+        // or garbage.
+        if caller <= rbp {
+            break;
+        }
+        rbp = caller;
+    }
+    depth as u8
+}
+
+/// Whether the word at `addr` can be read from the half of the address space
+/// `user` selects.
+fn readable(addr: u64, user: bool) -> bool {
+    if addr == 0 || (addr < USER_END) != user {
+        return false;
+    }
+    let Some(addr) = usize::try_from(addr)
+        .ok()
+        .and_then(|addr| VirtAddr::try_new(addr).ok())
+    else {
+        return false;
+    };
+    let table = AnyPageTable::current();
+    // SAFETY: CR3 always holds a root-level page table.
+    let addrspace = unsafe { table.as_addrspace() };
+    let Some((entry, _level)) = addrspace.leaf(Page::containing_address(addr)) else {
+        return false;
+    };
+    let Some((_, flags)) = entry.get() else {
+        return false;
+    };
+    !user || flags.contains(PageTableFlags::USER_ACCESSIBLE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(user: bool, frames: &[u64]) -> StackSample {
+        let mut sample = StackSample {
+            count: 1,
+            user,
+            depth: frames.len() as u8,
+            ..Default::default()
+        };
+        sample.frames[..frames.len()].copy_from_slice(frames);
+        sample
+    }
+
+    #[test_case]
+    fn counts_identical_stacks_together() {
+        let mut profile = Profile::new();
+        profile.record(sample(false, &[1, 2]));
+        profile.record(sample(false, &[1, 2]));
+        profile.record(sample(true, &[1, 2]));
+        profile.record(sample(false, &[1]));
+        assert_eq!(profile.len, 3);
+        assert_eq!(profile.stacks[0].count, 2);
+
+        for frame in 0..STACKS as u64 {
+            profile.record(sample(false, &[frame + 10]));
+        }
+        assert_eq!(profile.dropped, 3);
+        let mut out = [StackSample::default(); STACKS + 1];
+        assert_eq!(profile.drain(&mut out), STACKS);
+        assert_eq!(profile.drain(&mut out), 0);
+    }
+
+    #[test_case]
+    fn walks_only_mapped_frames() {
+        let mut frames = [0; MAX_DEPTH];
+        assert_eq!(walk(0x1234, 0, false, &mut frames), 1);
+        assert_eq!(frames[0], 0x1234);
+        // The lower half isn't part of the kernel's stack, and nothing is
+        // mapped at the bottom of it for userspace.
+        assert_eq!(walk(0x1234, 0x1000, false, &mut frames), 1);
+        assert_eq!(walk(0x1234, 0x1000, true, &mut frames), 1);
+
+        let rbp: u64;
+        // SAFETY: Only reads the frame pointer.
+        unsafe { core::arch::asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack)) };
+        let depth = walk(0x1234, rbp, false, &mut frames);
+        assert!(depth > 1, "The kernel is built with frame pointers");
+    }
+}
@@ -0,0 +1,140 @@
+//! Frames set aside for allocations in interrupt context.
+//!
+//! General allocation scans the retype table and logs as it goes, so it can't
+//! be used from interrupt handlers. Instead, every core keeps a few kernel
+//! frames in reserve that handlers can take with [`alloc`]. Taking a frame
+//! never blocks: it fails if the reserve is empty or if the code that was
+//! interrupted is using it.
+//!
+//! Reserves are topped up with [`refill`] outside of interrupt context, when
+//! the kernel is initialized and after every syscall.
+
+use sync::cell::AtomicRefCell;
+
+use crate::arch::interrupts;
+use crate::bump_allocator::BumpAllocator;
+use crate::core_local::{self, NUM_CORES};
+use crate::retyping::KernelFrame;
+
+/// Number of frames kept in reserve on each core.
+pub const RESERVE_SIZE: usize = 8;
+
+static RESERVES: [AtomicRefCell<Reserve>; NUM_CORES] =
+    [const { AtomicRefCell::new(Reserve::new()) }; NUM_CORES];
+
+struct Reserve {
+    frames: [Option<KernelFrame>; RESERVE_SIZE],
+    len: usize,
+}
+
+impl Reserve {
+    const fn new() -> Self {
+        Self {
+            frames: [const { None }; RESERVE_SIZE],
+            len: 0,
+        }
+    }
+
+    fn pop(&mut self) -> Option<KernelFrame> {
+        let frame = self.frames.get_mut(self.len.checked_sub(1)?)?.take();
+        self.len -= 1;
+        frame
+    }
+
+    fn push(&mut self, frame: KernelFrame) -> Result<(), KernelFrame> {
+        match self.frames.get_mut(self.len) {
+            Some(slot) => {
+                *slot = Some(frame);
+                self.len += 1;
+                Ok(())
+            }
+            None => Err(frame),
+        }
+    }
+}
+
+/// Takes a kernel frame from the current core's reserve.
+///
+/// Returns `None` instead of waiting if the reserve is empty or in use.
+pub fn alloc() -> Option<KernelFrame> {
+    RESERVES[core_local::current_core()]
+        .borrow_mut()
+        .ok()?
+        .pop()
+}
+
+/// Puts a frame taken with [`alloc`] back in the current core's reserve.
+///
+/// If there's no room for it, the frame is turned back into untyped memory.
+pub fn free(frame: KernelFrame) {
+    let frame = match RESERVES[core_local::current_core()].borrow_mut() {
+        Ok(mut reserve) => match reserve.push(frame) {
+            Ok(()) => return,
+            Err(frame) => frame,
+        },
+        Err(_) => frame,
+    };
+    let raw = frame.frame();
+    drop(frame);
+    let _ = raw.try_into_untyped();
+}
+
+/// Tops up the current core's reserve.
+///
+/// Returns the number of frames added.
+pub fn refill() -> usize {
+    debug_assert!(
+        !interrupts::in_irq(),
+        "Reserves can't be refilled from interrupt context"
+    );
+    let Ok(mut reserve) = RESERVES[core_local::current_core()].borrow_mut() else {
+        return 0;
+    };
+    if reserve.len == RESERVE_SIZE {
+        return 0;
+    }
+    let mut allocator = BumpAllocator::new();
+    let mut added = 0;
+    while reserve.len < RESERVE_SIZE {
+        let Some(frame) = allocator.alloc_kernel_frame() else {
+            log::warn!("Out of memory refilling the interrupt reserve");
+            break;
+        };
+        // Can't fail, we checked there's room.
+        let _ = reserve.push(frame);
+        added += 1;
+    }
+    added
+}
+
+/// Fills the reserve of the boot core.
+pub fn init() {
+    let added = refill();
+    log::info!("Reserved {added} frames for interrupt context");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn fails_instead_of_blocking() {
+        refill();
+        let frame = alloc().unwrap();
+        {
+            let _interrupted = RESERVES[core_local::current_core()].borrow_mut().unwrap();
+            assert!(alloc().is_none());
+        }
+        free(frame);
+
+        let mut taken = [const { None }; RESERVE_SIZE];
+        for slot in taken.iter_mut() {
+            *slot = Some(alloc().unwrap());
+        }
+        assert!(alloc().is_none());
+        for frame in taken.into_iter().flatten() {
+            free(frame);
+        }
+        assert_eq!(refill(), 0);
+    }
+}
@@ -0,0 +1,660 @@
+//! The state machine behind the retype table.
+//!
+//! Every frame of physical memory has a [`RetypeEntry`] that tracks what it's
+//! used for. The entries don't know where the frames are, so this builds and
+//! is tested on the host along with the rest of the library.
+//!
+//! The table also keeps a [`Usage`] for every [`BLOCK_FRAMES`] entries, so
+//! that how much memory is in each state can be read without walking the
+//! whole table.
+
+use core::ops::Range;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use kapi::diagnostics::RetypeStats;
+
+#[derive(Debug)]
+pub enum AsTypeError {
+    NotExpectedState(State),
+    MaxRefs,
+    OutOfBounds,
+    StaleEpoch,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PinError {
+    /// The frame isn't the user frame it was when the reference was acquired.
+    StaleEpoch,
+    /// The frame has as many pins as the entry can count.
+    MaxPins,
+    NotPinned,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct MaxRefs;
+#[derive(Debug, Copy, Clone)]
+pub struct NoRefs;
+#[derive(Debug, Copy, Clone)]
+pub struct StaleEpoch;
+
+/// A single entry in the retype table.
+///
+/// Each entry packs the frame's epoch, its [`State`], its reference count and
+/// its DMA pins.
+/// The epoch is incremented every time the frame changes its type which allows
+/// references that don't hold a count, like weak kernel pointers, to
+/// detect that the frame they pointed to has been repurposed.
+///
+/// The epoch takes every bit above the state and never wraps: an entry at
+/// [`RetypeEntry::MAX_EPOCH`] refuses to be retyped again, retiring its frame
+/// in whatever state it's in rather than letting a stale reference match a
+/// later epoch.
+///
+/// Epochs don't let the kernel retype a frame that still has references:
+/// nothing maps a frame back to the page tables and cap slots that hold it,
+/// so a retype still needs the count to drop to zero.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct RetypeEntry(AtomicU64);
+
+#[derive(Debug)]
+struct Invalid;
+impl State {
+    const fn try_from(value: u8) -> Result<Self, Invalid> {
+        match value {
+            0 => Ok(State::Unavailable),
+            1 => Ok(State::Untyped),
+            2 => Ok(State::User),
+            3 => Ok(State::Kernel),
+            _ => Err(Invalid),
+        }
+    }
+}
+
+impl RetypeEntry {
+    const STATE_BITS: u32 = 2;
+    const COUNTER_BITS: u32 = 14;
+    const PIN_BITS: u32 = 3;
+    const PIN_SHIFT: u32 = Self::COUNTER_BITS;
+    /// Set on untyped frames that are known to be zeroed.
+    const CLEAN: u64 = 1 << (Self::PIN_SHIFT + Self::PIN_BITS);
+    const STATE_SHIFT: u32 = Self::PIN_SHIFT + Self::PIN_BITS + 1;
+    const EPOCH_SHIFT: u32 = Self::STATE_SHIFT + Self::STATE_BITS;
+    pub const MAX_REF_COUNT: u16 = (1 << Self::COUNTER_BITS) - 1;
+    pub const MAX_PINS: u8 = (1 << Self::PIN_BITS) - 1;
+    pub const MAX_EPOCH: u64 = (1 << (u64::BITS - Self::EPOCH_SHIFT)) - 1;
+
+    fn value_for(epoch: u64, state: State, counter: u16) -> u64 {
+        assert!(counter <= Self::MAX_REF_COUNT);
+
+        (epoch << Self::EPOCH_SHIFT)
+            + ((state as u8 as u64) << Self::STATE_SHIFT)
+            + u64::from(counter)
+    }
+
+    const fn value_into(value: u64) -> (State, u16) {
+        let counter = (value & ((1 << Self::COUNTER_BITS) - 1)) as u16;
+        let state = match State::try_from(
+            ((value >> Self::STATE_SHIFT) & ((1 << Self::STATE_BITS) - 1)) as u8,
+        ) {
+            Ok(state) => state,
+            Err(_e) => panic!("Invalid retype state"),
+        };
+        (state, counter)
+    }
+
+    const fn epoch_of(value: u64) -> u64 {
+        value >> Self::EPOCH_SHIFT
+    }
+
+    const fn pins_of(value: u64) -> u8 {
+        ((value >> Self::PIN_SHIFT) & ((1 << Self::PIN_BITS) - 1)) as u8
+    }
+
+    pub fn unavailable() -> Self {
+        Self(AtomicU64::new(Self::value_for(0, State::Unavailable, 0)))
+    }
+
+    pub fn untyped() -> Self {
+        Self(AtomicU64::new(Self::value_for(0, State::Untyped, 0)))
+    }
+
+    pub fn kernel(ref_count: u16) -> Self {
+        Self(AtomicU64::new(Self::value_for(0, State::Kernel, ref_count)))
+    }
+
+    pub fn increment(&self) -> Result<u16, MaxRefs> {
+        self.0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+                let (_, counter) = Self::value_into(value);
+                if counter == Self::MAX_REF_COUNT {
+                    None
+                } else {
+                    Some(value + 1)
+                }
+            })
+            .map(|entry| Self::value_into(entry).1)
+            .map_err(|_| MaxRefs)
+    }
+
+    pub fn decrement(&self) -> Result<u16, NoRefs> {
+        self.0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+                let (_, counter) = Self::value_into(value);
+                if counter == 0 {
+                    None
+                } else {
+                    Some(value - 1)
+                }
+            })
+            .map(|entry| Self::value_into(entry).1)
+            .map_err(|_| NoRefs)
+    }
+
+    /// Decrements the reference count only if the entry is still at `epoch`.
+    pub fn decrement_at(&self, epoch: u64) -> Result<u16, StaleEpoch> {
+        self.0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+                let (_, counter) = Self::value_into(value);
+                if Self::epoch_of(value) != epoch || counter == 0 {
+                    None
+                } else {
+                    Some(value - 1)
+                }
+            })
+            .map(|entry| Self::value_into(entry).1)
+            .map_err(|_| StaleEpoch)
+    }
+
+    pub fn get(&self) -> (State, u16) {
+        Self::value_into(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn epoch(&self) -> u64 {
+        Self::epoch_of(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.0.load(Ordering::Relaxed) & Self::CLEAN != 0
+    }
+
+    pub fn pins(&self) -> u8 {
+        Self::pins_of(self.0.load(Ordering::Relaxed))
+    }
+
+    /// Pins a user frame that's still at `epoch`, keeping it from being
+    /// retyped until it's unpinned.
+    ///
+    /// Returns the number of pins before this one.
+    pub fn pin_at(&self, epoch: u64) -> Result<u8, PinError> {
+        self.0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+                let (state, _) = Self::value_into(value);
+                if Self::epoch_of(value) == epoch
+                    && state == State::User
+                    && Self::pins_of(value) < Self::MAX_PINS
+                {
+                    Some(value + (1 << Self::PIN_SHIFT))
+                } else {
+                    None
+                }
+            })
+            .map(Self::pins_of)
+            .map_err(|value| {
+                if Self::epoch_of(value) != epoch || Self::value_into(value).0 != State::User {
+                    PinError::StaleEpoch
+                } else {
+                    PinError::MaxPins
+                }
+            })
+    }
+
+    /// Removes a pin added with [`RetypeEntry::pin_at`].
+    ///
+    /// Returns the number of pins before this one was removed.
+    pub fn unpin_at(&self, epoch: u64) -> Result<u8, PinError> {
+        self.0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+                if Self::epoch_of(value) == epoch && Self::pins_of(value) > 0 {
+                    Some(value - (1 << Self::PIN_SHIFT))
+                } else {
+                    None
+                }
+            })
+            .map(Self::pins_of)
+            .map_err(|value| {
+                if Self::epoch_of(value) != epoch {
+                    PinError::StaleEpoch
+                } else {
+                    PinError::NotPinned
+                }
+            })
+    }
+
+    pub fn get_as_and_increment(&self, wants: State) -> Result<u64, (State, u16)> {
+        self.0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+                let (state, count) = Self::value_into(value);
+                if wants == state && count < Self::MAX_REF_COUNT {
+                    Some(value + 1)
+                } else {
+                    None
+                }
+            })
+            .map(Self::epoch_of)
+            .map_err(Self::value_into)
+    }
+
+    /// Increments the reference count if the entry is at `epoch`, has the
+    /// `wants` state and is still referenced by someone else.
+    pub fn get_live_at_and_increment(&self, wants: State, epoch: u64) -> Result<(), AsTypeError> {
+        self.0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+                let (state, count) = Self::value_into(value);
+                if Self::epoch_of(value) == epoch
+                    && wants == state
+                    && count > 0
+                    && count < Self::MAX_REF_COUNT
+                {
+                    Some(value + 1)
+                } else {
+                    None
+                }
+            })
+            .map(|_| ())
+            .map_err(|value| {
+                let (state, count) = Self::value_into(value);
+                if Self::epoch_of(value) != epoch || count == 0 {
+                    AsTypeError::StaleEpoch
+                } else if state != wants {
+                    AsTypeError::NotExpectedState(state)
+                } else {
+                    AsTypeError::MaxRefs
+                }
+            })
+    }
+
+    /// Changes the state of the entry, advancing its epoch. Pinned entries
+    /// and entries at [`RetypeEntry::MAX_EPOCH`] keep their state.
+    ///
+    /// Returns the new epoch.
+    pub fn retype(
+        &self,
+        from_state: State,
+        to_state: State,
+        from_counter: u16,
+        to_counter: u16,
+    ) -> Result<u64, (State, u16)> {
+        self.retype_clean(from_state, to_state, from_counter, to_counter, false)
+            .map(|(epoch, _was_clean)| epoch)
+    }
+
+    /// Like [`RetypeEntry::retype`], but also marks the entry as `clean` and
+    /// reports whether it was clean before.
+    ///
+    /// Returns the new epoch and the previous clean bit.
+    pub fn retype_clean(
+        &self,
+        from_state: State,
+        to_state: State,
+        from_counter: u16,
+        to_counter: u16,
+        clean: bool,
+    ) -> Result<(u64, bool), (State, u16)> {
+        self.0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+                let (state, counter) = Self::value_into(value);
+                if state == from_state
+                    && counter == from_counter
+                    && Self::pins_of(value) == 0
+                    && Self::epoch_of(value) < Self::MAX_EPOCH
+                {
+                    let epoch = Self::epoch_of(value) + 1;
+                    let value = Self::value_for(epoch, to_state, to_counter);
+                    Some(if clean { value | Self::CLEAN } else { value })
+                } else {
+                    None
+                }
+            })
+            .map(|value| (Self::epoch_of(value) + 1, value & Self::CLEAN != 0))
+            .map_err(Self::value_into)
+    }
+
+    pub fn set(&mut self, state: State, value: u16) {
+        let epoch = Self::epoch_of(*self.0.get_mut());
+        let to = Self::value_for(epoch, state, value);
+        *self.0.get_mut() = to;
+    }
+}
+
+const _: () = assert!(RetypeEntry::MAX_PINS as usize == kapi::ops::page_table::MAX_PINS);
+const _: () = assert!(RetypeEntry::MAX_EPOCH == u64::MAX >> RetypeEntry::EPOCH_SHIFT);
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[repr(u8)]
+pub enum State {
+    Unavailable = 0,
+    Untyped = 1,
+    User = 2,
+    Kernel = 3,
+}
+
+/// Number of entries covered by each [`Usage`] of the retype table.
+pub const BLOCK_FRAMES: usize = 512;
+
+/// How many frames of a stretch of the retype table are in each [`State`].
+///
+/// The counters are updated right after the entries on every transition, so
+/// they can be briefly off while transitions are in flight.
+#[derive(Debug, Default)]
+pub struct Usage {
+    /// Indexed by [`State`], followed by the untyped frames known to be
+    /// zeroed.
+    frames: [AtomicU32; 5],
+}
+
+impl Usage {
+    const CLEAN: usize = 4;
+
+    pub const fn new() -> Self {
+        Self {
+            frames: [const { AtomicU32::new(0) }; 5],
+        }
+    }
+
+    /// Counts a frame that's in `state`.
+    pub fn add(&self, state: State, clean: bool) {
+        self.frames[state as usize].fetch_add(1, Ordering::Relaxed);
+        if state == State::Untyped && clean {
+            self.frames[Self::CLEAN].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn remove(&self, state: State, clean: bool) {
+        self.frames[state as usize].fetch_sub(1, Ordering::Relaxed);
+        if state == State::Untyped && clean {
+            self.frames[Self::CLEAN].fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Moves a frame from `from` to `to`. `was_clean` and `clean` are the
+    /// clean bits of its entry before and after.
+    pub fn record(&self, from: State, to: State, was_clean: bool, clean: bool) {
+        self.remove(from, was_clean);
+        self.add(to, clean);
+    }
+
+    fn get(&self, index: usize) -> u64 {
+        self.frames[index].load(Ordering::Relaxed).into()
+    }
+
+    pub fn stats(&self) -> RetypeStats {
+        RetypeStats {
+            untyped: self.get(State::Untyped as usize),
+            clean: self.get(Self::CLEAN),
+            user: self.get(State::User as usize),
+            kernel: self.get(State::Kernel as usize),
+            unavailable: self.get(State::Unavailable as usize),
+        }
+    }
+}
+
+/// Counts the frames in each state by reading every entry.
+pub fn walk(entries: &[RetypeEntry]) -> RetypeStats {
+    let mut stats = RetypeStats::default();
+    for entry in entries {
+        match entry.get().0 {
+            State::Unavailable => stats.unavailable += 1,
+            State::Untyped => {
+                stats.untyped += 1;
+                if entry.is_clean() {
+                    stats.clean += 1;
+                }
+            }
+            State::User => stats.user += 1,
+            State::Kernel => stats.kernel += 1,
+        }
+    }
+    stats
+}
+
+/// Counts the frames in each state among `range` of `entries`, with `blocks`
+/// holding the [`Usage`] of every [`BLOCK_FRAMES`] entries.
+///
+/// Blocks that are entirely in the range are read from their counters. Frames
+/// past the end of the table are counted as unavailable.
+pub fn usage_in(entries: &[RetypeEntry], blocks: &[Usage], range: Range<usize>) -> RetypeStats {
+    let mut stats = RetypeStats::default();
+    let end = range.end.min(entries.len());
+    let mut index = range.start.min(end);
+    while index < end {
+        let block_end = (index / BLOCK_FRAMES + 1) * BLOCK_FRAMES;
+        if index % BLOCK_FRAMES == 0 && block_end.min(entries.len()) <= end {
+            stats += blocks[index / BLOCK_FRAMES].stats();
+            index = block_end;
+        } else {
+            let next = block_end.min(end);
+            stats += walk(&entries[index..next]);
+            index = next;
+        }
+    }
+    stats.unavailable += range.end.saturating_sub(entries.len().max(range.start)) as u64;
+    stats
+}
+
+/// Length of the longest run of consecutive untyped entries, with `blocks`
+/// holding the [`Usage`] of every [`BLOCK_FRAMES`] entries.
+///
+/// Only the blocks that are partly untyped are walked.
+pub fn largest_untyped_run(entries: &[RetypeEntry], blocks: &[Usage]) -> usize {
+    let mut largest = 0;
+    let mut run = 0;
+    for (chunk, usage) in entries.chunks(BLOCK_FRAMES).zip(blocks) {
+        match usage.stats().untyped as usize {
+            0 => run = 0,
+            untyped if untyped == chunk.len() => run += untyped,
+            _ => {
+                for entry in chunk {
+                    if entry.get().0 == State::Untyped {
+                        run += 1;
+                    } else {
+                        largest = largest.max(run);
+                        run = 0;
+                    }
+                }
+            }
+        }
+        largest = largest.max(run);
+    }
+    largest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A table of `states` with its usage counted the way the kernel does.
+    fn table(states: &[State]) -> (Vec<RetypeEntry>, Vec<Usage>) {
+        let entries: Vec<_> = states
+            .iter()
+            .map(|&state| match state {
+                State::Unavailable => RetypeEntry::unavailable(),
+                State::Untyped => RetypeEntry::untyped(),
+                State::User => {
+                    let entry = RetypeEntry::untyped();
+                    entry.retype(State::Untyped, State::User, 0, 1).unwrap();
+                    entry
+                }
+                State::Kernel => RetypeEntry::kernel(1),
+            })
+            .collect();
+        let blocks: Vec<_> = (0..entries.len().div_ceil(BLOCK_FRAMES))
+            .map(|_| Usage::new())
+            .collect();
+        for (index, entry) in entries.iter().enumerate() {
+            blocks[index / BLOCK_FRAMES].add(entry.get().0, entry.is_clean());
+        }
+        (entries, blocks)
+    }
+
+    #[test]
+    fn retype_advances_epoch() {
+        let entry = RetypeEntry::untyped();
+        assert_eq!(entry.epoch(), 0);
+        assert_eq!(entry.retype(State::Untyped, State::User, 0, 1).unwrap(), 1);
+        assert_eq!(entry.get(), (State::User, 1));
+        assert!(entry.retype(State::User, State::Untyped, 0, 0).is_err());
+        assert_eq!(entry.epoch(), 1);
+    }
+
+    #[test]
+    fn retype_retires_entries_at_max_epoch() {
+        let entry = RetypeEntry(AtomicU64::new(RetypeEntry::value_for(
+            RetypeEntry::MAX_EPOCH - 1,
+            State::Untyped,
+            0,
+        )));
+        assert_eq!(
+            entry.retype(State::Untyped, State::User, 0, 0),
+            Ok(RetypeEntry::MAX_EPOCH)
+        );
+        assert_eq!(
+            entry.retype(State::User, State::Untyped, 0, 0),
+            Err((State::User, 0))
+        );
+        assert_eq!(entry.get(), (State::User, 0));
+        assert_eq!(entry.epoch(), RetypeEntry::MAX_EPOCH);
+    }
+
+    #[test]
+    fn stale_references_are_ignored() {
+        let entry = RetypeEntry::untyped();
+        let epoch = entry.retype(State::Untyped, State::User, 0, 1).unwrap();
+        entry.decrement_at(epoch).unwrap();
+        assert_eq!(entry.retype(State::User, State::Untyped, 0, 0), Ok(2));
+        assert!(entry.decrement_at(epoch).is_err());
+        assert!(matches!(
+            entry.get_live_at_and_increment(State::User, epoch),
+            Err(AsTypeError::StaleEpoch)
+        ));
+        assert_eq!(entry.get(), (State::Untyped, 0));
+    }
+
+    #[test]
+    fn pins_leave_room_for_references() {
+        assert_eq!(RetypeEntry::MAX_REF_COUNT, (1 << 14) - 1);
+        let entry = RetypeEntry::untyped();
+        let epoch = entry.retype(State::Untyped, State::User, 0, 1).unwrap();
+        for pins in 0..RetypeEntry::MAX_PINS {
+            assert_eq!(entry.pin_at(epoch), Ok(pins));
+        }
+        while entry.get().1 < RetypeEntry::MAX_REF_COUNT {
+            entry.increment().unwrap();
+        }
+        assert!(entry.increment().is_err());
+        assert_eq!(entry.pin_at(epoch), Err(PinError::MaxPins));
+        assert_eq!(entry.pins(), RetypeEntry::MAX_PINS);
+        assert_eq!(entry.get(), (State::User, RetypeEntry::MAX_REF_COUNT));
+        assert_eq!(entry.epoch(), epoch);
+    }
+
+    #[test]
+    fn pinned_frames_keep_their_type() {
+        let entry = RetypeEntry::untyped();
+        let epoch = entry.retype(State::Untyped, State::User, 0, 1).unwrap();
+        assert_eq!(entry.pin_at(epoch), Ok(0));
+        assert_eq!(entry.pin_at(epoch), Ok(1));
+        assert_eq!(entry.pins(), 2);
+        assert_eq!(entry.get(), (State::User, 1));
+        entry.decrement_at(epoch).unwrap();
+        assert!(entry.retype(State::User, State::Untyped, 0, 0).is_err());
+        assert_eq!(entry.epoch(), epoch);
+
+        assert_eq!(entry.unpin_at(epoch), Ok(2));
+        assert_eq!(entry.unpin_at(epoch), Ok(1));
+        assert_eq!(entry.unpin_at(epoch), Err(PinError::NotPinned));
+        assert_eq!(
+            entry.retype(State::User, State::Untyped, 0, 0),
+            Ok(epoch + 1)
+        );
+        assert!(!entry.is_clean());
+        assert_eq!(entry.pin_at(epoch), Err(PinError::StaleEpoch));
+    }
+
+    #[test]
+    fn pins_are_limited() {
+        let entry = RetypeEntry::untyped();
+        let epoch = entry.retype(State::Untyped, State::User, 0, 1).unwrap();
+        for pins in 0..RetypeEntry::MAX_PINS {
+            assert_eq!(entry.pin_at(epoch), Ok(pins));
+        }
+        assert_eq!(entry.pin_at(epoch), Err(PinError::MaxPins));
+        // The reference count is untouched.
+        assert_eq!(entry.get(), (State::User, 1));
+        assert_eq!(entry.increment().unwrap(), 1);
+    }
+
+    #[test]
+    fn usage_follows_transitions() {
+        let usage = Usage::new();
+        usage.add(State::Untyped, false);
+        usage.add(State::Kernel, false);
+        usage.record(State::Kernel, State::Untyped, false, true);
+        usage.record(State::Untyped, State::User, true, false);
+        let stats = usage.stats();
+        assert_eq!((stats.untyped, stats.clean, stats.user), (1, 0, 1));
+        assert_eq!(stats.kernel, 0);
+        usage.record(State::Untyped, State::Kernel, false, false);
+        usage.record(State::Kernel, State::Untyped, false, true);
+        assert_eq!(usage.stats().clean, 1);
+    }
+
+    #[test]
+    fn counts_ranges_from_blocks_and_entries() {
+        let mut states = Vec::from([State::Untyped; 3 * BLOCK_FRAMES + 10]);
+        states[5] = State::Kernel;
+        states[BLOCK_FRAMES + 1] = State::User;
+        states[3 * BLOCK_FRAMES + 2] = State::Unavailable;
+        let (entries, blocks) = table(&states);
+        assert_eq!(
+            usage_in(&entries, &blocks, 0..entries.len()),
+            walk(&entries)
+        );
+        for range in [
+            0..0,
+            3..BLOCK_FRAMES + 7,
+            BLOCK_FRAMES..3 * BLOCK_FRAMES,
+            2 * BLOCK_FRAMES - 1..3 * BLOCK_FRAMES + 5,
+        ] {
+            assert_eq!(
+                usage_in(&entries, &blocks, range.clone()),
+                walk(&entries[range])
+            );
+        }
+        // Frames the table doesn't have are unavailable.
+        let past = usage_in(&entries, &blocks, entries.len() - 1..entries.len() + 4);
+        assert_eq!((past.untyped, past.unavailable), (1, 4));
+        assert_eq!(
+            usage_in(&entries, &blocks, 1 << 40..(1 << 40) + 2).total(),
+            2
+        );
+    }
+
+    #[test]
+    fn finds_the_largest_untyped_run() {
+        let mut states = Vec::from([State::Untyped; 4 * BLOCK_FRAMES]);
+        let (entries, blocks) = table(&states);
+        assert_eq!(largest_untyped_run(&entries, &blocks), 4 * BLOCK_FRAMES);
+        states[10] = State::Kernel;
+        states[2 * BLOCK_FRAMES..3 * BLOCK_FRAMES].fill(State::Unavailable);
+        states[3 * BLOCK_FRAMES + 7] = State::User;
+        let (entries, blocks) = table(&states);
+        assert_eq!(
+            largest_untyped_run(&entries, &blocks),
+            2 * BLOCK_FRAMES - 11
+        );
+        let (entries, blocks) = table(&[State::Kernel, State::Untyped, State::Untyped]);
+        assert_eq!(largest_untyped_run(&entries, &blocks), 2);
+        assert_eq!(largest_untyped_run(&[], &[]), 0);
+    }
+}
@@ -1,41 +1,153 @@
 use core::mem::{ManuallyDrop, MaybeUninit};
-use core::sync::atomic::{AtomicU16, Ordering};
 
-use limine::memory_map::EntryType;
+use kapi::diagnostics::RetypeStats;
+use kernel::retype::{self, RetypeEntry, Usage, BLOCK_FRAMES};
+pub use kernel::retype::{AsTypeError, PinError, State};
 use sync::cell::AtomicOnceCell;
 
 use crate::arch::paging::page_table::AnyPageTable;
-use crate::arch::paging::{RawFrame, FRAME_SIZE, PAGE_SIZE};
+use crate::arch::paging::{PhysAddr, PhysAddrExt as _, RawFrame, FRAME_SIZE, PAGE_SIZE};
+use crate::boot::{MemoryKind, MemoryRegion};
 use crate::retyping::bump_alloc::BumpAllocator;
+use crate::scrub;
 use crate::MemoryMap;
 
 static RETYPE_TABLE: AtomicOnceCell<RetypeTable> = AtomicOnceCell::new();
 
 pub struct RetypeTable {
     retype_map: &'static mut [RetypeEntry],
+    /// Usage of every [`BLOCK_FRAMES`] entries of `retype_map`.
+    blocks: &'static [Usage],
+    /// Usage of the whole table.
+    usage: Usage,
+}
+
+/// Why a memory map can't back the retype table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MapError {
+    /// There's no memory the kernel knows about.
+    Empty,
+    /// A region isn't page aligned or runs past the end of the address space.
+    Unaligned(MemoryRegion),
+    /// The regions aren't sorted or a usable region overlaps another one.
+    Overlapping(MemoryRegion),
+    /// No usable region is large enough for the table.
+    NoRoom,
+}
+
+/// Where the retype table of a memory map goes.
+///
+/// Working this out doesn't touch the memory in the map, so any map can be
+/// tried.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Layout {
+    /// Frames the table tracks.
+    pub frames: usize,
+    /// First frame of the table itself.
+    pub table: PhysAddr,
+}
+
+impl Layout {
+    /// Checks that `memory_map` is sorted, aligned and doesn't claim usable
+    /// memory twice, and takes the frames for the table out of it.
+    ///
+    /// The table covers every region but the reserved ones past the last
+    /// region the kernel may use, so that holes in the physical address space
+    /// don't need entries.
+    pub fn new(memory_map: &mut [MemoryRegion]) -> Result<Self, MapError> {
+        let mut top = 0;
+        let mut end_of_all = 0;
+        let mut end_of_usable = 0;
+        let mut previous_base = 0;
+        for &region in memory_map.iter() {
+            let end = region
+                .base
+                .checked_add(region.length)
+                .ok_or(MapError::Unaligned(region))?;
+            if region.base % FRAME_SIZE != 0 || region.length % FRAME_SIZE != 0 {
+                return Err(MapError::Unaligned(region));
+            }
+            let overlaps_usable = region.base < end_of_usable;
+            let usable_overlaps = region.kind == MemoryKind::Usable && region.base < end_of_all;
+            if region.base < previous_base || overlaps_usable || usable_overlaps {
+                return Err(MapError::Overlapping(region));
+            }
+            previous_base = region.base;
+            end_of_all = end_of_all.max(end);
+            if region.kind == MemoryKind::Usable {
+                end_of_usable = end;
+            }
+            if region.kind != MemoryKind::Reserved {
+                top = top.max(end);
+            }
+        }
+        if top == 0 {
+            return Err(MapError::Empty);
+        }
+        let frames = usize::try_from(top / FRAME_SIZE).map_err(|_| MapError::NoRoom)?;
+        let table_frames = core::mem::size_of::<RetypeEntry>()
+            .checked_mul(frames)
+            .ok_or(MapError::NoRoom)?
+            .checked_add(Self::blocks_len(frames))
+            .ok_or(MapError::NoRoom)?
+            .div_ceil(PAGE_SIZE);
+        let table = BumpAllocator::new(memory_map)
+            .alloc_frames(table_frames)
+            .ok_or(MapError::NoRoom)?;
+        Ok(Self { frames, table })
+    }
+
+    /// Size of the usage counters that follow the entries of a table of
+    /// `frames` frames.
+    fn blocks_len(frames: usize) -> usize {
+        frames.div_ceil(BLOCK_FRAMES) * core::mem::size_of::<Usage>()
+    }
+}
+
+/// Logs how much memory is in each state.
+pub fn log_usage() {
+    let (Some(stats), Some(run)) = (RetypeTable::stats(), RetypeTable::largest_untyped_run())
+    else {
+        return;
+    };
+    let mib = |frames: u64| (frames * FRAME_SIZE) >> 20;
+    log::info!(
+        "Memory: {} MiB untyped ({} MiB clean), {} MiB user, {} MiB kernel, {} MiB unavailable",
+        mib(stats.untyped),
+        mib(stats.clean),
+        mib(stats.user),
+        mib(stats.kernel),
+        mib(stats.unavailable)
+    );
+    log::info!("Largest contiguous untyped run: {run} frames");
 }
 
 impl RetypeTable {
-    pub fn new(memory_map: MemoryMap) -> Option<Self> {
-        let physical_top = {
-            let last = memory_map.iter().last()?;
-            last.base + last.length
-        };
-        assert!(physical_top % FRAME_SIZE == 0);
-        let number_frames = (physical_top / FRAME_SIZE) as usize;
-        let mut allocator = BumpAllocator::new(memory_map);
-
-        let retype_map_frames = {
-            let retype_map_size = core::mem::size_of::<RetypeEntry>() * number_frames;
-            assert!(retype_map_size > 0);
-            (retype_map_size - 1) / PAGE_SIZE + 1
+    /// Builds the table for `memory_map`, taking the memory for it out of the
+    /// map.
+    pub fn new(memory_map: MemoryMap) -> Result<Self, MapError> {
+        let Layout { frames, table } = Layout::new(memory_map)?;
+        const _: () =
+            assert!(core::mem::align_of::<Usage>() <= core::mem::align_of::<RetypeEntry>());
+        let blocks = {
+            let entries: *mut RetypeEntry = table.to_virtual().as_mut_ptr();
+            // SAFETY: The counters are allocated right after the entries.
+            let start_addr = unsafe { entries.add(frames) }.cast::<MaybeUninit<Usage>>();
+            // SAFETY: Memory is allocated and off the memory map
+            let blocks: &mut [MaybeUninit<Usage>] = unsafe {
+                core::slice::from_raw_parts_mut(start_addr, frames.div_ceil(BLOCK_FRAMES))
+            };
+            for block in blocks.iter_mut() {
+                block.write(Usage::new());
+            }
+            // SAFETY: Initialized in earlier loop
+            let blocks: &[Usage] = unsafe { core::mem::transmute(blocks) };
+            blocks
         };
         let retype_map = {
-            let start_physical_address = allocator.alloc_frames(retype_map_frames)?;
-            let start_addr: *mut MaybeUninit<RetypeEntry> =
-                start_physical_address.to_virtual().as_mut_ptr();
+            let start_addr: *mut MaybeUninit<RetypeEntry> = table.to_virtual().as_mut_ptr();
             // SAFETY: Memory is allocated and off the memory map
-            unsafe { core::slice::from_raw_parts_mut(start_addr, number_frames) }
+            unsafe { core::slice::from_raw_parts_mut(start_addr, frames) }
         };
 
         for entry in retype_map.iter_mut() {
@@ -44,24 +156,56 @@ impl RetypeTable {
         // SAFETY: Initialized in earlier loop
         let retype_map: &mut [RetypeEntry] = unsafe { core::mem::transmute(retype_map) };
 
-        let memory_map = allocator.into_memory_map();
         for entry in memory_map.iter() {
-            assert!(entry.base % FRAME_SIZE == 0);
-            assert!(entry.length % FRAME_SIZE == 0);
             let start_idx = (entry.base / FRAME_SIZE) as usize;
             let count = (entry.length / FRAME_SIZE) as usize;
             for slot in retype_map.iter_mut().skip(start_idx).take(count) {
-                let retype_entry = match entry.entry_type {
-                    EntryType::USABLE => RetypeEntry::untyped(),
-                    EntryType::BOOTLOADER_RECLAIMABLE | EntryType::KERNEL_AND_MODULES => {
+                let retype_entry = match entry.kind {
+                    MemoryKind::Usable => RetypeEntry::untyped(),
+                    MemoryKind::BootloaderReclaimable | MemoryKind::KernelAndModules => {
                         RetypeEntry::kernel(1)
                     }
-                    _ => RetypeEntry::unavailable(),
+                    MemoryKind::Reserved => RetypeEntry::unavailable(),
                 };
                 *slot = retype_entry;
             }
         }
-        Some(Self { retype_map })
+        let usage = Usage::new();
+        for (index, entry) in retype_map.iter().enumerate() {
+            let (state, _) = entry.get();
+            blocks[index / BLOCK_FRAMES].add(state, entry.is_clean());
+            usage.add(state, entry.is_clean());
+        }
+        Ok(Self {
+            retype_map,
+            blocks,
+            usage,
+        })
+    }
+
+    /// Counts the frames in each state, or `None` before the table is initialized.
+    pub fn stats() -> Option<RetypeStats> {
+        Some(RETYPE_TABLE.get()?.usage.stats())
+    }
+
+    /// Counts the frames in each state by walking every entry instead of
+    /// reading the counters.
+    pub fn walk_stats() -> Option<RetypeStats> {
+        Some(retype::walk(RETYPE_TABLE.get()?.retype_map))
+    }
+
+    /// Counts the states of the `frames` frames starting at `first`.
+    pub fn region_stats(first: RawFrame, frames: usize) -> Option<RetypeStats> {
+        let table = RETYPE_TABLE.get()?;
+        let start = (first.addr().as_u64() / FRAME_SIZE) as usize;
+        let range = start..start.checked_add(frames)?;
+        Some(retype::usage_in(table.retype_map, table.blocks, range))
+    }
+
+    /// Length in frames of the longest run of contiguous untyped memory.
+    pub fn largest_untyped_run() -> Option<usize> {
+        let table = RETYPE_TABLE.get()?;
+        Some(retype::largest_untyped_run(table.retype_map, table.blocks))
     }
 
     pub fn init(self) -> Result<(), sync::cell::OnceError> {
@@ -92,31 +236,33 @@ impl From<OutOfBounds> for AsTypeError {
 pub enum RetypeError {
     InvalidFromState(State),
     RefsExist(u16),
+    /// The frame is pinned for DMA.
+    Pinned(u8),
     OutOfBounds,
 }
 
 #[derive(Debug)]
-pub enum AsTypeError {
-    NotExpectedState(State),
-    MaxRefs,
-    OutOfBounds,
+pub struct UserFrame {
+    frame: RawFrame,
+    epoch: u64,
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct MaxRefs;
-#[derive(Debug, Copy, Clone)]
-pub struct NoRefs;
-
-#[repr(transparent)]
-#[derive(Debug)]
-pub struct UserFrame(RawFrame);
-
 impl RawFrame {
     pub fn memory_limit() -> usize {
         let nframes = RETYPE_TABLE.get().unwrap().retype_map.len();
         nframes * FRAME_SIZE as usize
     }
 
+    /// Counts a transition of this frame's entry in the table's usage.
+    ///
+    /// `was_clean` and `clean` are the entry's clean bits before and after.
+    fn record(self, from: State, to: State, was_clean: bool, clean: bool) {
+        let table = RETYPE_TABLE.get().unwrap();
+        let index = (self.addr().as_u64() / FRAME_SIZE) as usize;
+        table.blocks[index / BLOCK_FRAMES].record(from, to, was_clean, clean);
+        table.usage.record(from, to, was_clean, clean);
+    }
+
     fn retype_entry(&self) -> Result<&'static RetypeEntry, OutOfBounds> {
         let index = (self.addr().as_u64() / FRAME_SIZE) as usize;
         RETYPE_TABLE
@@ -127,9 +273,15 @@ impl RawFrame {
             .ok_or(OutOfBounds)
     }
 
+    /// Returns the current epoch of the frame.
+    pub fn epoch(&self) -> Result<u64, OutOfBounds> {
+        Ok(self.retype_entry()?.epoch())
+    }
+
     pub fn try_as_user(self) -> Result<UserFrame, AsTypeError> {
         log::trace!("Turning {self:?} as user frame");
-        self.retype_entry()?
+        let epoch = self
+            .retype_entry()?
             .get_as_and_increment(State::User)
             .map_err(|(state, value)| {
                 if !matches!(state, State::User) {
@@ -139,7 +291,7 @@ impl RawFrame {
                     AsTypeError::MaxRefs
                 }
             })?;
-        Ok(UserFrame(self))
+        Ok(UserFrame { frame: self, epoch })
     }
 
     /// Unsafely turn a raw frame into a user frame.
@@ -148,9 +300,12 @@ impl RawFrame {
     ///
     /// The raw frame must be typed as user
     pub unsafe fn as_user_unchecked(self) -> UserFrame {
-        let frame = UserFrame(self);
-        frame.entry().increment().unwrap();
-        frame
+        let entry = unsafe { self.retype_entry().unwrap_unchecked() };
+        entry.increment().unwrap();
+        UserFrame {
+            frame: self,
+            epoch: entry.epoch(),
+        }
     }
 
     pub fn try_as_kernel(self) -> Result<KernelFrame, AsTypeError> {
@@ -168,6 +323,13 @@ impl RawFrame {
         Ok(KernelFrame(self))
     }
 
+    /// Gets the frame as a kernel frame if it's still alive at `epoch`.
+    pub fn try_as_kernel_at(self, epoch: u64) -> Result<KernelFrame, AsTypeError> {
+        self.retype_entry()?
+            .get_live_at_and_increment(State::Kernel, epoch)?;
+        Ok(KernelFrame(self))
+    }
+
     pub fn try_as_untyped(self) -> Result<RawFrame, AsTypeError> {
         log::trace!("Trying to get {self:?} as untyped");
         let (state, _count) = self.retype_entry()?.get();
@@ -188,34 +350,110 @@ impl RawFrame {
         frame
     }
 
-    pub fn try_into_user(self) -> Result<UserFrame, RetypeError> {
-        self.retype_entry()?
-            .retype(State::Untyped, State::User, 0, 1)
+    /// Retypes an untyped frame, zeroing it unless it was already clean.
+    ///
+    /// Returns the new epoch.
+    fn retype_zeroed(self, to: State) -> Result<u64, RetypeError> {
+        let (epoch, clean) = self
+            .retype_entry()?
+            .retype_clean(State::Untyped, to, 0, 1, false)
             .map_err(|(state, _count)| RetypeError::InvalidFromState(state))?;
-        Ok(UserFrame(self))
+        self.record(State::Untyped, to, clean, false);
+        if !clean {
+            // SAFETY: The frame was just retyped out of untyped memory so
+            // nothing else is using it.
+            unsafe { scrub::zero(self) };
+        }
+        Ok(epoch)
+    }
+
+    pub fn try_into_user(self) -> Result<UserFrame, RetypeError> {
+        #[cfg(feature = "fault-injection")]
+        if crate::fault::should_fail(crate::fault::Site::Retype) {
+            return Err(RetypeError::InvalidFromState(State::Unavailable));
+        }
+        let epoch = self.retype_zeroed(State::User)?;
+        Ok(UserFrame { frame: self, epoch })
     }
 
     pub fn try_into_kernel(self) -> Result<KernelFrame, RetypeError> {
-        self.retype_entry()?
-            .retype(State::Untyped, State::Kernel, 0, 1)
-            .map_err(|(state, _count)| RetypeError::InvalidFromState(state))?;
+        #[cfg(feature = "fault-injection")]
+        if crate::fault::should_fail(crate::fault::Site::Retype) {
+            return Err(RetypeError::InvalidFromState(State::Unavailable));
+        }
+        self.retype_zeroed(State::Kernel)?;
         Ok(KernelFrame(self))
     }
 
+    /// Hands a frame the bootloader loaded something into over to userspace,
+    /// keeping its contents.
+    ///
+    /// Such frames start out as kernel memory with a single reference, which
+    /// the returned user reference takes the place of.
+    pub fn try_into_user_from_boot(self) -> Result<UserFrame, RetypeError> {
+        let epoch = self
+            .retype_entry()?
+            .retype(State::Kernel, State::User, 1, 1)
+            .map_err(|(state, refs)| match state {
+                State::Kernel => RetypeError::RefsExist(refs),
+                state => RetypeError::InvalidFromState(state),
+            })?;
+        self.record(State::Kernel, State::User, false, false);
+        Ok(UserFrame { frame: self, epoch })
+    }
+
+    /// Zeroes an untyped frame ahead of time so that retyping it doesn't have to.
+    ///
+    /// Returns false if the frame isn't untyped or was already clean.
+    pub fn scrub(self) -> bool {
+        let Ok(entry) = self.retype_entry() else {
+            return false;
+        };
+        if entry.is_clean() {
+            return false;
+        }
+        // Hold the frame as kernel memory while it's zeroed so that nobody
+        // can retype it from under us.
+        let Ok((_, was_clean)) = entry.retype_clean(State::Untyped, State::Kernel, 0, 1, false)
+        else {
+            return false;
+        };
+        self.record(State::Untyped, State::Kernel, was_clean, false);
+        // SAFETY: The frame is held by us.
+        unsafe { scrub::zero(self) };
+        entry
+            .retype_clean(State::Kernel, State::Untyped, 1, 0, true)
+            .expect("Scrubbed frame was retyped");
+        self.record(State::Kernel, State::Untyped, false, true);
+        true
+    }
+
+    /// Whether the frame is untyped and known to be zeroed.
+    pub fn is_clean(self) -> bool {
+        self.retype_entry()
+            .is_ok_and(|entry| matches!(entry.get().0, State::Untyped) && entry.is_clean())
+    }
+
     fn try_into_untyped_from(self, from: State) -> Result<RawFrame, RetypeError> {
         assert!(matches!(from, State::User | State::Kernel));
         let entry = self.retype_entry()?;
 
         match entry.retype(from, State::Untyped, 0, 0) {
-            Ok(()) => Ok(self),
+            Ok(_epoch) => {
+                self.record(from, State::Untyped, false, false);
+                Ok(self)
+            }
             Err((State::Unavailable, refs)) => {
                 debug_assert_eq!(refs, 0);
                 Err(RetypeError::InvalidFromState(State::Unavailable))
             }
-            Err((s, refs)) if s == from => {
-                debug_assert_ne!(refs, 0);
-                Err(RetypeError::RefsExist(refs))
-            }
+            Err((s, refs)) if s == from => match entry.pins() {
+                0 => {
+                    debug_assert_ne!(refs, 0);
+                    Err(RetypeError::RefsExist(refs))
+                }
+                pins => Err(RetypeError::Pinned(pins)),
+            },
             Err((other_state, _refs)) => Err(RetypeError::InvalidFromState(other_state)),
         }
     }
@@ -227,29 +465,80 @@ impl RawFrame {
         self.try_into_untyped_from(State::Kernel)?;
         Ok(self)
     }
+
+    /// Drops a user reference that was leaked with [`UserFrame::into_raw`].
+    ///
+    /// Returns the number of references left, or `None` if the frame isn't a
+    /// user frame.
+    ///
+    /// # Safety
+    ///
+    /// The caller must own one of the frame's user references.
+    pub unsafe fn drop_user_ref(self) -> Option<u16> {
+        let entry = self.retype_entry().ok()?;
+        if !matches!(entry.get().0, State::User) {
+            return None;
+        }
+        entry.decrement().ok().map(|count| count - 1)
+    }
 }
 
 impl UserFrame {
     fn entry(&self) -> &'static RetypeEntry {
-        // SAFETY: Entry must exist if a KernelFrame exists.
-        unsafe { self.0.retype_entry().unwrap_unchecked() }
+        // SAFETY: Entry must exist if a UserFrame exists.
+        unsafe { self.frame.retype_entry().unwrap_unchecked() }
     }
 
     pub fn frame(&self) -> RawFrame {
-        self.0
+        self.frame
+    }
+
+    /// The epoch of the frame when this reference was acquired.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Returns false if the frame has been retyped since this reference was acquired.
+    pub fn is_valid(&self) -> bool {
+        self.entry().epoch() == self.epoch
     }
 
     pub fn into_raw(self) -> RawFrame {
-        ManuallyDrop::new(self).0
+        ManuallyDrop::new(self).frame
+    }
+
+    /// Pins the frame so that it can't be retyped while a device is accessing
+    /// it.
+    ///
+    /// Returns the number of pins the frame had before.
+    pub fn pin(&self) -> Result<u8, PinError> {
+        self.entry().pin_at(self.epoch)
+    }
+
+    /// Removes a pin added with [`UserFrame::pin`].
+    ///
+    /// Returns the number of pins the frame had before.
+    pub fn unpin(&self) -> Result<u8, PinError> {
+        self.entry().unpin_at(self.epoch)
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        self.is_valid() && self.entry().pins() > 0
     }
 
     pub fn try_clone(&self) -> Option<Self> {
-        self.0.retype_entry().unwrap().increment().ok()?;
-        Some(Self(self.frame()))
+        self.entry()
+            .get_live_at_and_increment(State::User, self.epoch)
+            .ok()?;
+        Some(Self {
+            frame: self.frame,
+            epoch: self.epoch,
+        })
     }
 
     pub fn drop(self) -> u16 {
-        self.entry().decrement().unwrap()
+        let this = ManuallyDrop::new(self);
+        this.entry().decrement_at(this.epoch).unwrap_or(0)
     }
 }
 
@@ -286,7 +575,8 @@ impl KernelFrame {
     }
 
     pub fn drop(self) -> u16 {
-        self.entry().decrement().unwrap()
+        let this = ManuallyDrop::new(self);
+        this.entry().decrement().unwrap()
     }
 }
 
@@ -300,152 +590,51 @@ impl Drop for KernelFrame {
 impl Drop for UserFrame {
     fn drop(&mut self) {
         log::trace!("Dropping {self:?}");
-        self.entry().decrement().unwrap();
-    }
-}
-
-#[repr(transparent)]
-#[derive(Debug)]
-struct RetypeEntry(AtomicU16);
-
-#[derive(Debug)]
-struct Invalid;
-impl State {
-    const fn try_from(value: u8) -> Result<Self, Invalid> {
-        match value {
-            0 => Ok(State::Unavailable),
-            1 => Ok(State::Untyped),
-            2 => Ok(State::User),
-            3 => Ok(State::Kernel),
-            _ => Err(Invalid),
+        if self.entry().decrement_at(self.epoch).is_err() {
+            log::trace!("{self:?} was retyped");
         }
     }
 }
 
-#[allow(unused)]
-impl RetypeEntry {
-    const STATE_BITS: u16 = 2;
-    const COUNTER_BITS: u16 = 16 - Self::STATE_BITS;
-    pub const MAX_REF_COUNT: u16 = (1 << Self::COUNTER_BITS) - 1;
-
-    fn value_for(state: State, counter: u16) -> u16 {
-        assert!(counter <= Self::MAX_REF_COUNT);
-
-        ((state as u8 as u16) << Self::COUNTER_BITS) + counter % Self::MAX_REF_COUNT
-    }
-
-    const fn value_into(value: u16) -> (State, u16) {
-        let counter = value & ((1 << Self::COUNTER_BITS) - 1);
-        let state = match State::try_from((value >> Self::COUNTER_BITS) as u8) {
-            Ok(state) => state,
-            Err(_e) => panic!("Invalid retype state"),
-        };
-        (state, counter)
-    }
-
-    pub fn unavailable() -> Self {
-        Self(AtomicU16::new(Self::value_for(State::Unavailable, 0)))
-    }
-
-    pub fn untyped() -> Self {
-        Self(AtomicU16::new(Self::value_for(State::Untyped, 0)))
-    }
-
-    pub fn kernel(ref_count: u16) -> Self {
-        Self(AtomicU16::new(Self::value_for(State::Kernel, ref_count)))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bump_allocator::BumpAllocator;
+
+    #[test_case]
+    fn usage_counters_follow_retypes() {
+        let before = RetypeTable::stats().unwrap();
+        assert_eq!(RetypeTable::walk_stats(), Some(before));
+        let frame = BumpAllocator::new().alloc_untyped_frame().unwrap();
+        let user = frame.try_into_user().unwrap();
+        let stats = RetypeTable::stats().unwrap();
+        assert_eq!(stats.user, before.user + 1);
+        assert_eq!(RetypeTable::walk_stats(), Some(stats));
+        let region = RetypeTable::region_stats(frame, 1).unwrap();
+        assert_eq!((region.user, region.total()), (1, 1));
+
+        drop(user);
+        frame.try_into_untyped().unwrap();
+        assert!(frame.scrub());
+        let stats = RetypeTable::stats().unwrap();
+        assert_eq!(stats.untyped, before.untyped);
+        assert_eq!(RetypeTable::walk_stats(), Some(stats));
+        assert!(RetypeTable::largest_untyped_run().unwrap() > 0);
     }
-
-    pub fn increment(&self) -> Result<u16, MaxRefs> {
-        self.0
-            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
-                let (_, counter) = Self::value_into(value);
-                if counter == Self::MAX_REF_COUNT {
-                    None
-                } else {
-                    Some(value + 1)
-                }
-            })
-            .map(|entry| Self::value_into(entry).1)
-            .map_err(|_| MaxRefs)
-    }
-
-    pub fn decrement(&self) -> Result<u16, NoRefs> {
-        self.0
-            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
-                let (_, counter) = Self::value_into(value);
-                if counter == 0 {
-                    None
-                } else {
-                    Some(value - 1)
-                }
-            })
-            .map(|entry| Self::value_into(entry).1)
-            .map_err(|_| NoRefs)
-    }
-
-    pub fn get(&self) -> (State, u16) {
-        Self::value_into(self.0.load(Ordering::Relaxed))
-    }
-
-    pub fn get_as_and_increment(&self, wants: State) -> Result<(), (State, u16)> {
-        self.0
-            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
-                let (state, count) = Self::value_into(value);
-                if wants == state && count < Self::MAX_REF_COUNT {
-                    Some(Self::value_for(state, count + 1))
-                } else {
-                    None
-                }
-            })
-            .map(|_| ())
-            .map_err(Self::value_into)
-    }
-
-    pub fn retype(
-        &self,
-        from_state: State,
-        to_state: State,
-        from_counter: u16,
-        to_counter: u16,
-    ) -> Result<(), (State, u16)> {
-        let from = Self::value_for(from_state, from_counter);
-        let to = Self::value_for(to_state, to_counter);
-        self.0
-            .compare_exchange(from, to, Ordering::Relaxed, Ordering::Relaxed)
-            .map_err(Self::value_into)?;
-
-        Ok(())
-    }
-
-    pub fn set(&mut self, state: State, value: u16) {
-        let to = Self::value_for(state, value);
-        *self.0.get_mut() = to;
-    }
-}
-
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
-#[repr(u8)]
-pub enum State {
-    Unavailable = 0,
-    Untyped = 1,
-    User = 2,
-    Kernel = 3,
 }
 
 mod bump_alloc {
-    use limine::memory_map::{Entry, EntryType};
-
     use crate::arch::paging::{PhysAddr, RawFrame, FRAME_SIZE};
-    use crate::MemoryMap;
+    use crate::boot::{MemoryKind, MemoryRegion};
 
-    pub struct BumpAllocator {
-        memory_map: MemoryMap,
+    pub struct BumpAllocator<'a> {
+        memory_map: &'a mut [MemoryRegion],
         index: usize,
     }
 
     #[allow(unused)]
-    impl BumpAllocator {
-        pub fn new(memory_map: MemoryMap) -> Self {
+    impl<'a> BumpAllocator<'a> {
+        pub fn new(memory_map: &'a mut [MemoryRegion]) -> Self {
             Self {
                 memory_map,
                 index: 0,
@@ -456,7 +645,7 @@ mod bump_alloc {
             let frame = loop {
                 let entry = self.memory_map.get_mut(self.index)?;
                 assert!(entry.length % FRAME_SIZE == 0);
-                if entry.entry_type == EntryType::USABLE && entry.length > 0 {
+                if entry.kind == MemoryKind::Usable && entry.length > 0 {
                     let start_address = entry.base;
                     entry.base += FRAME_SIZE;
                     entry.length -= FRAME_SIZE;
@@ -469,11 +658,11 @@ mod bump_alloc {
         }
 
         pub fn alloc_frames(&mut self, count: usize) -> Option<PhysAddr> {
-            let requested_length = count as u64 * FRAME_SIZE;
+            let requested_length = (count as u64).checked_mul(FRAME_SIZE)?;
             let start_address = loop {
                 let entry = self.memory_map.get_mut(self.index)?;
                 assert!(entry.length % FRAME_SIZE == 0);
-                if entry.entry_type == EntryType::USABLE && entry.length >= requested_length {
+                if entry.kind == MemoryKind::Usable && entry.length >= requested_length {
                     let start_address = entry.base;
                     entry.base += requested_length;
                     entry.length -= requested_length;
@@ -484,11 +673,11 @@ mod bump_alloc {
             Some(start_address)
         }
 
-        pub fn into_memory_map(self) -> &'static mut [&'static mut Entry] {
+        pub fn into_memory_map(self) -> &'a mut [MemoryRegion] {
             self.memory_map
         }
 
-        pub fn memory_map(&mut self) -> &mut [&'static mut Entry] {
+        pub fn memory_map(&mut self) -> &mut [MemoryRegion] {
             self.memory_map
         }
     }
@@ -499,26 +688,26 @@ mod bump_alloc {
 
         #[test_case]
         fn allocation_test() {
-            static mut TEST_MAP: [&mut Entry; 4] = [
-                &mut Entry {
+            static mut TEST_MAP: [MemoryRegion; 4] = [
+                MemoryRegion {
                     base: 0,
                     length: FRAME_SIZE * 2,
-                    entry_type: EntryType::USABLE,
+                    kind: MemoryKind::Usable,
                 },
-                &mut Entry {
+                MemoryRegion {
                     base: FRAME_SIZE * 4,
                     length: FRAME_SIZE,
-                    entry_type: EntryType::USABLE,
+                    kind: MemoryKind::Usable,
                 },
-                &mut Entry {
+                MemoryRegion {
                     base: FRAME_SIZE * 5,
                     length: FRAME_SIZE,
-                    entry_type: EntryType::RESERVED,
+                    kind: MemoryKind::Reserved,
                 },
-                &mut Entry {
+                MemoryRegion {
                     base: FRAME_SIZE * 6,
                     length: FRAME_SIZE,
-                    entry_type: EntryType::USABLE,
+                    kind: MemoryKind::Usable,
                 },
             ];
 
@@ -536,26 +725,26 @@ mod bump_alloc {
 
         #[test_case]
         fn multi_allocation_test() {
-            static mut TEST_MAP: [&mut Entry; 4] = [
-                &mut Entry {
+            static mut TEST_MAP: [MemoryRegion; 4] = [
+                MemoryRegion {
                     base: 0,
                     length: FRAME_SIZE * 2,
-                    entry_type: EntryType::USABLE,
+                    kind: MemoryKind::Usable,
                 },
-                &mut Entry {
+                MemoryRegion {
                     base: FRAME_SIZE * 4,
                     length: FRAME_SIZE,
-                    entry_type: EntryType::USABLE,
+                    kind: MemoryKind::Usable,
                 },
-                &mut Entry {
+                MemoryRegion {
                     base: FRAME_SIZE * 5,
                     length: FRAME_SIZE,
-                    entry_type: EntryType::RESERVED,
+                    kind: MemoryKind::Reserved,
                 },
-                &mut Entry {
+                MemoryRegion {
                     base: FRAME_SIZE * 6,
                     length: FRAME_SIZE * 2,
-                    entry_type: EntryType::USABLE,
+                    kind: MemoryKind::Usable,
                 },
             ];
 
@@ -0,0 +1,218 @@
+//! A round-robin scheduler for bring-up.
+//!
+//! Threads are added to a single run queue with `ThreadOp::Schedule`. Every
+//! time slice, the timer interrupt puts the thread running on the core at the
+//! back of the queue and dispatches the first queued thread that can run on
+//! it. Threads that aren't queued are only ever run through explicit
+//! activations, as usual. Suspended threads stay queued but are skipped, and
+//! a running thread that gets suspended gives up its core on the next tick if
+//! another thread can take it. Threads can also give up their core early with
+//! `ThreadOp::Yield`, or hand the rest of their slice to a specific thread with
+//! `ThreadOp::YieldTo`.
+//!
+//! This is a stopgap so that multi-threaded components can be developed before
+//! scheduling is handled by a userspace component. It's only built with the
+//! `round-robin` feature. The length of a slice can be set in timer ticks on
+//! the command line with `sched.slice=<ticks>`.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use sync::cell::AtomicRefCell;
+
+use crate::arch::context::IrqCtx;
+use crate::component::Thread;
+use crate::core_local::NUM_CORES;
+use crate::kptr::KPtr;
+
+/// Maximum number of queued threads.
+const CAPACITY: usize = 64;
+
+/// Default length of a time slice in timer ticks.
+const DEFAULT_SLICE: usize = 10;
+
+static QUEUE: AtomicRefCell<RunQueue<KPtr<Thread>, CAPACITY>> = AtomicRefCell::new(RunQueue::new());
+static SLICE: AtomicUsize = AtomicUsize::new(DEFAULT_SLICE);
+/// Ticks since the last switch on each core.
+static ELAPSED: [AtomicUsize; NUM_CORES] = [const { AtomicUsize::new(0) }; NUM_CORES];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SchedError {
+    QueueFull,
+    AlreadyQueued,
+}
+
+/// A fixed-size FIFO of threads.
+struct RunQueue<T, const N: usize> {
+    items: [Option<T>; N],
+    head: usize,
+    len: usize,
+}
+
+impl<T: PartialEq, const N: usize> RunQueue<T, N> {
+    const fn new() -> Self {
+        Self {
+            items: [const { None }; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, item: T) -> Result<(), SchedError> {
+        if self.items.iter().flatten().any(|queued| *queued == item) {
+            return Err(SchedError::AlreadyQueued);
+        }
+        if self.len == N {
+            return Err(SchedError::QueueFull);
+        }
+        self.items[(self.head + self.len) % N] = Some(item);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let item = self.items[self.head].take();
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        item
+    }
+
+    /// Removes the first item that satisfies `ready`.
+    ///
+    /// Items that are skipped are moved to the back in the same order.
+    fn pop_where<F: Fn(&T) -> bool>(&mut self, ready: F) -> Option<T> {
+        for _ in 0..self.len {
+            let item = self.pop()?;
+            if ready(&item) {
+                return Some(item);
+            }
+            // Can't fail, we just made room for it.
+            let _ = self.push(item);
+        }
+        None
+    }
+}
+
+/// Adds `thread` to the run queue.
+pub fn enqueue(thread: KPtr<Thread>) -> Result<(), SchedError> {
+    QUEUE.borrow_mut().unwrap().push(thread)
+}
+
+/// Whether a queued thread can be dispatched on this core.
+fn is_ready(thread: &KPtr<Thread>) -> bool {
+    thread.can_run_here() && !thread.is_running() && !thread.is_suspended()
+}
+
+/// Takes the first thread that's ready to run on this core out of the queue
+/// and puts `current` at the back.
+///
+/// Returns `None` if no thread is ready or another core is using the queue.
+fn switch_from(current: KPtr<Thread>) -> Option<KPtr<Thread>> {
+    let mut queue = QUEUE.borrow_mut().ok()?;
+    let next = queue.pop_where(is_ready)?;
+    // There's room since we just took a thread out.
+    let _ = queue.push(current);
+    Some(next)
+}
+
+/// Picks the thread that takes over the core when `current` yields it.
+///
+/// `current` is queued behind the other threads and the next thread starts a
+/// fresh slice. Returns `None` if no other thread is ready to run here.
+pub fn yield_now(current: KPtr<Thread>) -> Option<KPtr<Thread>> {
+    let next = switch_from(current)?;
+    ELAPSED[crate::core_local::current_core()].store(0, Ordering::Relaxed);
+    Some(next)
+}
+
+/// Queues `current` before it donates the rest of its slice to another thread,
+/// so that it gets a core back afterwards.
+///
+/// The slice keeps running, so the receiving thread is switched out when it
+/// would have expired for `current`.
+pub fn donate(current: KPtr<Thread>) {
+    // A thread that's already queued keeps its place. If the queue is full,
+    // the thread only runs again when it's activated.
+    let _ = enqueue(current);
+}
+
+/// Switches to the next queued thread if the current one used up its slice or
+/// was suspended.
+///
+/// Must be called from the timer interrupt once the interrupt was
+/// acknowledged, since it may not return.
+pub fn tick() {
+    let elapsed = &ELAPSED[crate::core_local::current_core()];
+    let expired = elapsed.fetch_add(1, Ordering::Relaxed) + 1 >= SLICE.load(Ordering::Relaxed);
+    // The kernel isn't preemptible.
+    // SAFETY: We are handling an interrupt.
+    if !unsafe { IrqCtx::from_user() } {
+        return;
+    }
+    let Some(current) = Thread::current() else {
+        return;
+    };
+    if !expired && !current.is_suspended() {
+        return;
+    }
+    let Some(next) = switch_from(current) else {
+        return;
+    };
+    elapsed.store(0, Ordering::Relaxed);
+    // SAFETY: We are handling an interrupt from userspace.
+    let ctx = unsafe { IrqCtx::current() };
+    Thread::dispatch(next, ctx);
+}
+
+/// Reads the time slice from the command line.
+pub fn init() {
+    for option in crate::CMDLINE.split_ascii_whitespace() {
+        let Some(slice) = option.strip_prefix("sched.slice=") else {
+            continue;
+        };
+        match slice.parse() {
+            Ok(slice) if slice > 0 => SLICE.store(slice, Ordering::Relaxed),
+            _ => log::warn!("Invalid time slice {slice:?}"),
+        }
+    }
+    log::info!(
+        "Round-robin scheduling every {} ticks",
+        SLICE.load(Ordering::Relaxed)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn rotates_in_order() {
+        let mut queue = RunQueue::<u32, 3>::new();
+        queue.push(1).unwrap();
+        queue.push(2).unwrap();
+        queue.push(3).unwrap();
+        assert_eq!(queue.push(4), Err(SchedError::QueueFull));
+        assert_eq!(queue.pop(), Some(1));
+        queue.push(1).unwrap();
+        assert_eq!(queue.push(2), Err(SchedError::AlreadyQueued));
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), Some(3));
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test_case]
+    fn skips_threads_that_cant_run() {
+        let mut queue = RunQueue::<u32, 4>::new();
+        for item in 1..=4 {
+            queue.push(item).unwrap();
+        }
+        assert_eq!(queue.pop_where(|&item| item % 2 == 0), Some(2));
+        assert_eq!(queue.pop_where(|&item| item > 10), None);
+        assert_eq!(queue.pop(), Some(3));
+        assert_eq!(queue.pop(), Some(4));
+        assert_eq!(queue.pop(), Some(1));
+    }
+}
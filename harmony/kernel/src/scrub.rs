@@ -0,0 +1,121 @@
+//! Zeroing of physical memory.
+//!
+//! Frames are zeroed when they're retyped out of untyped memory so that the
+//! new owner can't see what the previous one left behind. The scrubber zeroes
+//! free untyped frames ahead of time, a few at every timer tick, so that
+//! retyping a frame it already cleaned doesn't have to.
+//!
+//! The scrubber is off by default and can be enabled on the command line with
+//! `scrub=<frames per tick>`, e.g. `scrub=8`.
+//!
+//! Note that the owner of untyped memory can still write to a frame after it
+//! was scrubbed. That only hands its own data to whoever it gives the frame
+//! to, which it could do anyway.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::arch::paging::{PhysAddr, PhysAddrExt as _, RawFrame, FRAME_SIZE, PAGE_SIZE};
+
+/// Frames scrubbed at every tick.
+static BUDGET: AtomicUsize = AtomicUsize::new(0);
+/// Index of the next frame the scrubber looks at.
+static CURSOR: AtomicUsize = AtomicUsize::new(0);
+
+/// Fills the frame with zeroes.
+///
+/// # Safety
+///
+/// Nothing else may be using the frame.
+pub unsafe fn zero(frame: RawFrame) {
+    let ptr: *mut u8 = frame.addr().to_virtual().as_mut_ptr();
+    // SAFETY: The frame is mapped in the HHDM and unused.
+    unsafe { core::ptr::write_bytes(ptr, 0, PAGE_SIZE) };
+}
+
+/// Looks at up to `budget` frames and zeroes the untyped ones that aren't
+/// clean yet.
+///
+/// Returns the number of frames zeroed.
+pub fn scrub(budget: usize) -> usize {
+    let frames = RawFrame::memory_limit() / PAGE_SIZE;
+    if frames == 0 {
+        return 0;
+    }
+    let start = CURSOR.fetch_add(budget, Ordering::Relaxed);
+    (start..start + budget)
+        .map(|index| {
+            RawFrame::from_start_address(PhysAddr::new((index % frames) as u64 * FRAME_SIZE))
+        })
+        .filter(|frame| frame.scrub())
+        .count()
+}
+
+/// Runs the scrubber for a tick if it's enabled.
+pub fn tick() {
+    let budget = BUDGET.load(Ordering::Relaxed);
+    if budget > 0 {
+        scrub(budget);
+    }
+}
+
+/// Enables the scrubber if requested in the command line.
+pub fn init() {
+    for option in crate::CMDLINE.split_ascii_whitespace() {
+        let Some(budget) = option.strip_prefix("scrub=") else {
+            continue;
+        };
+        match budget.parse() {
+            Ok(budget) => {
+                BUDGET.store(budget, Ordering::Relaxed);
+                log::info!("Scrubbing {budget} frames per tick");
+            }
+            Err(_) => log::warn!("Invalid scrub budget {budget:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bump_allocator::BumpAllocator;
+
+    fn fill(frame: RawFrame, byte: u8) {
+        let ptr: *mut u8 = frame.addr().to_virtual().as_mut_ptr();
+        // SAFETY: The frame is owned by the test.
+        unsafe { core::ptr::write_bytes(ptr, byte, PAGE_SIZE) };
+    }
+
+    fn is_zeroed(frame: RawFrame) -> bool {
+        let ptr: *const u8 = frame.addr().to_virtual().as_ptr();
+        // SAFETY: The frame is owned by the test.
+        unsafe { core::slice::from_raw_parts(ptr, PAGE_SIZE) }
+            .iter()
+            .all(|&byte| byte == 0)
+    }
+
+    #[test_case]
+    fn zeroed_on_retype() {
+        let frame = BumpAllocator::new().alloc_user_frame().unwrap().into_raw();
+        fill(frame, 0xAA);
+        // SAFETY: The frame isn't mapped anywhere.
+        unsafe { frame.drop_user_ref() };
+        let frame = frame.try_into_untyped().unwrap();
+        assert!(!frame.is_clean());
+
+        let frame = frame.try_into_user().unwrap();
+        assert!(is_zeroed(frame.frame()));
+    }
+
+    #[test_case]
+    fn scrubbed_frames_are_clean() {
+        let frame = BumpAllocator::new().alloc_untyped_frame().unwrap();
+        fill(frame, 0xAA);
+        assert!(frame.scrub());
+        assert!(is_zeroed(frame));
+        assert!(frame.is_clean());
+        assert!(!frame.scrub());
+
+        let frame = frame.try_into_kernel().unwrap();
+        assert!(!frame.frame().is_clean());
+    }
+}
@@ -1,31 +1,190 @@
 //! Helpers to communicate with the serial port.
+//!
+//! Output goes to the 16550 UART on COM1 unless the command line asks for
+//! another backend with `serial.backend=<uart|debugcon>`. QEMU's and Bochs'
+//! debug console on port 0xE9 takes a byte per `out` without polling a status
+//! register, which makes it much faster for CI logs. Machines without one fall
+//! back to the UART. Input always comes from the UART.
+
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
 
-use log::{LevelFilter, Metadata, Record};
 use sync::cell::AtomicLazyCell;
 use uart_16550::SerialPort;
+use x86_64_impl::instructions::port::Port;
 
-/// Initializes serial port and logger. sprint! and log macros after this.
-pub(super) fn init() {
-    log::set_logger(&LOGGER)
-        .map(|()| log::set_max_level(*LOG_LEVEL))
-        .expect("Couldn't set the serial logger");
+const COM1_BASE: u16 = 0x3F8;
+/// Port of the debug console, which reads back as its own number.
+const DEBUGCON_PORT: u16 = 0xE9;
 
-    log::info!("Logging initialized");
+/// Where output goes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Backend {
+    Uart,
+    Debugcon,
 }
 
+static BACKEND: AtomicU8 = AtomicU8::new(Backend::Uart as u8);
+
 // TODO: Fix this to not use static mut
 static mut SERIAL: AtomicLazyCell<SerialPort> = AtomicLazyCell::new(|| {
     // SAFETY: Serial port address base is correct.
-    let mut serial_port = unsafe { SerialPort::new(0x3F8) };
+    let mut serial_port = unsafe { SerialPort::new(COM1_BASE) };
     serial_port.init();
     serial_port
 });
 
+/// Returns the backend output goes to.
+pub fn backend() -> Backend {
+    match BACKEND.load(Ordering::Relaxed) {
+        0 => Backend::Uart,
+        _ => Backend::Debugcon,
+    }
+}
+
+/// Whether the debug console is there.
+fn has_debugcon() -> bool {
+    // SAFETY: Reading port 0xE9 has no side effects, it's either the debug
+    // console or nothing.
+    unsafe { Port::<u8>::new(DEBUGCON_PORT).read() == DEBUGCON_PORT as u8 }
+}
+
+/// Picks the output backend from the command line.
+pub fn init() {
+    for option in crate::CMDLINE.split_ascii_whitespace() {
+        let Some(backend) = option.strip_prefix("serial.backend=") else {
+            continue;
+        };
+        match backend {
+            "uart" => BACKEND.store(Backend::Uart as u8, Ordering::Relaxed),
+            "debugcon" if has_debugcon() => {
+                BACKEND.store(Backend::Debugcon as u8, Ordering::Relaxed)
+            }
+            "debugcon" => log::warn!("No debug console at {DEBUGCON_PORT:#X}, staying on the UART"),
+            _ => log::warn!("Unknown serial backend {backend:?}"),
+        }
+    }
+}
+
+/// Writes to the debug console.
+struct Debugcon;
+
+impl core::fmt::Write for Debugcon {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let mut port = Port::<u8>::new(DEBUGCON_PORT);
+        for byte in s.bytes() {
+            // SAFETY: Only reached once `init` found the debug console.
+            unsafe { port.write(byte) };
+        }
+        Ok(())
+    }
+}
+
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
+    match backend() {
+        Backend::Uart => unsafe {
+            SERIAL.write_fmt(args).expect("Printing to serial failed");
+        },
+        Backend::Debugcon => Debugcon.write_fmt(args).unwrap(),
+    }
+    crate::vga::mirror(args);
+}
+
+/// Size of the receive buffer in bytes.
+const RX_BUFFER_SIZE: usize = 256;
+
+static RX_BUFFER: RxRing<RX_BUFFER_SIZE> = RxRing::new();
+
+/// Moves every byte pending in the UART into the receive buffer.
+///
+/// Called from the serial interrupt handler. Bytes are dropped if the buffer
+/// is full.
+pub(crate) fn receive_pending() {
+    // SAFETY: Reading the line status and data registers has no other side effects
+    // than consuming the received byte.
     unsafe {
-        SERIAL.write_fmt(args).expect("Printing to serial failed");
+        let mut line_status: Port<u8> = Port::new(COM1_BASE + 5);
+        let mut data: Port<u8> = Port::new(COM1_BASE);
+        while line_status.read() & 1 != 0 {
+            let byte = data.read();
+            if RX_BUFFER.push(byte).is_err() {
+                log::trace!("Serial receive buffer full, dropping {byte:#X}");
+            }
+        }
+    }
+}
+
+/// Reads a single received byte, if any.
+pub fn read_byte() -> Option<u8> {
+    RX_BUFFER.pop()
+}
+
+/// Reads a full line into `buf` without the line terminator.
+///
+/// Returns `None` if a complete line hasn't been received yet. Lines longer
+/// than `buf` are truncated.
+pub fn read_line(buf: &mut [u8]) -> Option<usize> {
+    RX_BUFFER.pop_line(buf)
+}
+
+/// A single-producer, single-consumer byte ring.
+struct RxRing<const N: usize> {
+    buffer: [AtomicU8; N],
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct Full;
+
+impl<const N: usize> RxRing<N> {
+    const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const EMPTY: AtomicU8 = AtomicU8::new(0);
+        Self {
+            buffer: [EMPTY; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, byte: u8) -> Result<(), Full> {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if head.wrapping_sub(tail) == N {
+            return Err(Full);
+        }
+        self.buffer[head % N].store(byte, Ordering::Relaxed);
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    fn pop(&self) -> Option<u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let byte = self.buffer[tail % N].load(Ordering::Relaxed);
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(byte)
+    }
+
+    fn pop_line(&self, buf: &mut [u8]) -> Option<usize> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        let length = (0..head.wrapping_sub(tail)).find(|offset| {
+            let byte = self.buffer[tail.wrapping_add(*offset) % N].load(Ordering::Relaxed);
+            byte == b'\n' || byte == b'\r'
+        })?;
+        for (offset, out) in (0..length).zip(buf.iter_mut()) {
+            *out = self.buffer[tail.wrapping_add(offset) % N].load(Ordering::Relaxed);
+        }
+        self.tail
+            .store(tail.wrapping_add(length + 1), Ordering::Release);
+        Some(length.min(buf.len()))
     }
 }
 
@@ -68,33 +227,24 @@ macro_rules! sdbg {
     };
 }
 
-/// The global logger.
-static LOGGER: Logger = Logger {};
-
-static LOG_LEVEL: AtomicLazyCell<LevelFilter> = AtomicLazyCell::new(|| {
-    let level = option_env!("KERNEL_LOG_LEVEL").unwrap_or("info");
-    match level {
-        "trace" => LevelFilter::Trace,
-        "debug" => LevelFilter::Debug,
-        "info" => LevelFilter::Info,
-        "warn" => LevelFilter::Warn,
-        "error" => LevelFilter::Error,
-        other => panic!("Unknown LOG LEVEL: {other}"),
-    }
-});
-
-struct Logger;
-
-impl log::Log for Logger {
-    fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= *LOG_LEVEL
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
-            crate::sprintln!("{} - {}", record.level(), record.args());
+    #[test_case]
+    fn rx_ring_lines() {
+        let ring: RxRing<8> = RxRing::new();
+        let mut line = [0; 8];
+        for byte in b"ab\ncd" {
+            ring.push(*byte).unwrap();
+        }
+        assert_eq!(ring.pop_line(&mut line), Some(2));
+        assert_eq!(&line[..2], b"ab");
+        assert_eq!(ring.pop_line(&mut line), None);
+        assert_eq!(ring.pop(), Some(b'c'));
+        for byte in b"efghijk" {
+            ring.push(*byte).unwrap();
         }
+        assert!(ring.push(b'l').is_err());
     }
-
-    fn flush(&self) {}
 }
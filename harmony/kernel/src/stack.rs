@@ -0,0 +1,84 @@
+//! Boot stack usage.
+//!
+//! The bootloader enters the kernel on a stack of a fixed size, and the kernel
+//! keeps running on it until it jumps to the boot component. The stack is
+//! painted as soon as the kernel starts (see [`kapi::stack`]) and checked once
+//! initialization is done, and then about once a second from the timer, to
+//! find how close it gets to overflowing.
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use kapi::stack::{self as paint, StackUsage, PAINT_MARGIN};
+
+use crate::arch::paging::PAGE_SIZE;
+use crate::arch::timer::TICK_FREQUENCY;
+use crate::boot::{self, BootProtocol as _};
+
+/// Address one past the top of the boot stack, or 0 if it wasn't painted.
+static TOP: AtomicUsize = AtomicUsize::new(0);
+static SIZE: AtomicUsize = AtomicUsize::new(0);
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Usage above which the boot stack is reported as too small.
+const WARN_PERCENT: u64 = 75;
+
+/// Paints the unused part of the boot stack.
+///
+/// Must be called with interrupts disabled and as close to the entry point as
+/// possible. The top of the stack is taken to be the end of the current page,
+/// which holds while the kernel is only a few calls deep.
+pub fn paint() {
+    let Some(size) = boot::protocol().stack_size() else {
+        return;
+    };
+    let rsp = paint::stack_pointer();
+    let top = rsp.next_multiple_of(PAGE_SIZE);
+    let bottom = top - size;
+    // SAFETY: Nothing lives below the stack pointer and interrupts are
+    // disabled, so nothing else will be pushed there while painting.
+    unsafe { paint::paint(bottom, rsp.saturating_sub(PAINT_MARGIN)) };
+    SIZE.store(size, Ordering::Relaxed);
+    TOP.store(top, Ordering::Release);
+}
+
+/// Returns the peak usage of the boot stack, if it was painted.
+pub fn stack_usage() -> Option<StackUsage> {
+    let top = TOP.load(Ordering::Acquire);
+    if top == 0 {
+        return None;
+    }
+    let bottom = top - SIZE.load(Ordering::Relaxed);
+    // SAFETY: The boot stack is kernel memory that is never freed.
+    Some(unsafe { paint::usage(bottom, top) })
+}
+
+/// Warns if the boot stack is close to full.
+///
+/// # Panics
+///
+/// If the stack overflowed.
+pub fn check() {
+    let Some(usage) = stack_usage() else {
+        return;
+    };
+    assert!(
+        !usage.overflowed(),
+        "The boot stack overflowed its {} bytes",
+        usage.size
+    );
+    if usage.percent() >= WARN_PERCENT {
+        log::warn!(
+            "Boot stack is {}% full ({} of {} bytes)",
+            usage.percent(),
+            usage.used,
+            usage.size
+        );
+    }
+}
+
+/// Checks the boot stack about once a second.
+pub fn tick() {
+    if TICKS.fetch_add(1, Ordering::Relaxed) % TICK_FREQUENCY == 0 {
+        check();
+    }
+}
@@ -1,7 +1,18 @@
 use kapi::raw::{CapError, CapId, SyscallArgs};
+use kapi::trace::EventKind;
+use kernel::kbail;
 
+use crate::arch::context::{RestartCtx, SyscallCtx};
 use crate::component::Thread;
 
+pub mod decode;
+#[cfg(any(test, feature = "trace-syscalls"))]
+pub mod trace;
+
+// `handle` takes the capability and the `SyscallArgs` that `raw_syscall`
+// passes in six registers.
+const _: () = assert!(core::mem::size_of::<SyscallArgs>() == 5 * core::mem::size_of::<usize>());
+
 pub extern "sysv64" fn handle(a: usize, b: usize, c: usize, d: usize, e: usize, f: usize) -> isize {
     let thread = Thread::current().unwrap();
 
@@ -10,8 +21,53 @@ pub extern "sysv64" fn handle(a: usize, b: usize, c: usize, d: usize, e: usize,
     };
     let capability = CapId::from(capability);
     let args = SyscallArgs::new(b, c, d, e, f);
-    match thread.exercise_cap(capability, args) {
-        Ok(result) => result.try_into().unwrap(),
-        Err(e) => e.to_errno(),
+    let id = crate::trace::thread_id(&thread);
+    crate::trace::event(EventKind::SyscallEnter, id, b as u64);
+    let logged = decode::log_call(&thread, capability, args);
+    // Arguments are checked against the operation's signature before the
+    // operation gets to see them.
+    let result = match kapi::validate::decode(a, args)
+        .and_then(|_| thread.exercise_cap(capability, args))
+        .and_then(return_value)
+    {
+        Ok(result) => result,
+        Err(e) => {
+            if logged {
+                decode::log_error(capability, args, e);
+            }
+            e.to_errno()
+        }
+    };
+    crate::trace::event(EventKind::SyscallExit, id, result as u64);
+    // Replace whatever interrupt handlers took while the thread ran.
+    crate::reserve::refill();
+    if thread.restart_pending() {
+        // The operation will resume when the thread re-executes the syscall.
+        // Going back to userspace first lets pending interrupts make progress.
+        // SAFETY: We are handling a syscall.
+        let ctx = unsafe { SyscallCtx::current() };
+        Thread::dispatch(thread, RestartCtx::new(ctx, [a, b, c, d, e, f]));
     }
+    #[cfg(any(test, feature = "trace-syscalls"))]
+    trace::record(trace::SyscallRecord {
+        thread: thread.frame().addr().as_u64(),
+        capability,
+        args,
+        result,
+    });
+    result
+}
+
+/// The value a successful operation returns to userspace.
+///
+/// Negative values are errors, so results that don't fit in an `isize` fail
+/// the syscall instead.
+fn return_value(result: usize) -> Result<isize, CapError> {
+    let Ok(value) = isize::try_from(result) else {
+        kbail!(
+            CapError::Internal,
+            "result {result:#x} would read as an error"
+        );
+    };
+    Ok(value)
 }
@@ -0,0 +1,241 @@
+//! Human readable syscall logging.
+//!
+//! Syscalls are logged at the debug level with the operation and resource
+//! names spelled out and every argument labeled, e.g.
+//!
+//! ```text
+//! thread 0x1F000: cap 0 (cap_table) cap_table.link(other_table=cap 3, slot=4)
+//! ```
+//!
+//! Components tend to issue the same syscall in a loop (polling, restarts), so
+//! a message identical to the previous one is suppressed and counted instead.
+//! The count is logged once a different syscall comes in.
+
+use core::fmt;
+
+use kapi::raw::{CapError, CapId, RawOperation, SyscallArgs};
+use kapi::validate::{signature, ArgKind, Signature};
+use sync::cell::AtomicRefCell;
+
+use crate::caps::Resource;
+use crate::component::Thread;
+use crate::kptr::KPtr;
+
+/// Name of the kind of resource held in a capability.
+pub fn resource_name(resource: &Resource) -> &'static str {
+    match resource {
+        Resource::Empty => "empty",
+        Resource::CapEntry(_) => "cap_table",
+        Resource::Thread(_) => "thread",
+        Resource::PageTable { .. } => "page_table",
+        Resource::Logger => "logger",
+        Resource::Ipi => "ipi",
+        Resource::Region(_) => "memory_region",
+        Resource::Clock => "clock",
+        Resource::Diagnostics => "diagnostics",
+        Resource::PerfCounter => "perf",
+        Resource::Iommu => "iommu",
+        Resource::DmaDomain(_) => "dma_domain",
+        Resource::Initrd => "initrd",
+        Resource::Endpoint(_) => "endpoint",
+        Resource::Hierarchy(_) => "hierarchy",
+        Resource::System => "system",
+        Resource::Display => "display",
+    }
+}
+
+/// Formats the operation and its arguments, e.g. `cap_table.unlink(slot=4)`.
+///
+/// Arguments are labeled from the operation's [`Signature`]. Operations with an
+/// unknown layout show all of their arguments in hex.
+pub struct Operation(pub SyscallArgs);
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (a, b, c, d) = self.0.args();
+        let values = [a, b, c, d];
+        let Ok(op) = RawOperation::try_from(self.0.op()) else {
+            return write!(
+                f,
+                "unknown op {}({a:#X}, {b:#X}, {c:#X}, {d:#X})",
+                self.0.op()
+            );
+        };
+        let Signature { name, args } = signature(op);
+        let labels = args.unwrap_or_default();
+        if labels.is_empty() && values.iter().any(|&value| value != 0) {
+            return write!(f, "{name}({a:#X}, {b:#X}, {c:#X}, {d:#X})");
+        }
+        write!(f, "{name}(")?;
+        for (i, (&(label, kind), value)) in labels.iter().zip(values).enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{label}=")?;
+            match kind {
+                ArgKind::Cap => write!(f, "cap {value}")?,
+                ArgKind::Slot | ArgKind::Count | ArgKind::Range { .. } => write!(f, "{value}")?,
+                ArgKind::Pointer { .. }
+                | ArgKind::Page
+                | ArgKind::Frame
+                | ArgKind::DeviceAddr
+                | ArgKind::Raw => write!(f, "{value:#X}")?,
+                ArgKind::Mask => write!(f, "{value:#b}")?,
+                ArgKind::Bool => write!(f, "{}", value != 0)?,
+            }
+        }
+        write!(f, ")")
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Call {
+    thread: u64,
+    capability: CapId,
+    args: SyscallArgs,
+}
+
+struct Limiter {
+    last: Option<Call>,
+    /// Number of times the last call was suppressed.
+    repeated: usize,
+}
+
+static LIMITER: AtomicRefCell<Limiter> = AtomicRefCell::new(Limiter {
+    last: None,
+    repeated: 0,
+});
+
+/// Records `call` and returns whether it should be logged.
+///
+/// Logs how many times the previous call was suppressed when a different one
+/// comes in.
+fn should_log(call: Call) -> bool {
+    // Another core is logging. Better to drop the message than to wait.
+    let Ok(mut limiter) = LIMITER.borrow_mut() else {
+        return false;
+    };
+    if limiter.last == Some(call) {
+        limiter.repeated += 1;
+        return false;
+    }
+    if limiter.repeated > 0 {
+        log::debug!("Previous syscall repeated {} more times", limiter.repeated);
+    }
+    limiter.last = Some(call);
+    limiter.repeated = 0;
+    true
+}
+
+/// Logs a syscall before it's executed.
+///
+/// Returns whether the call was logged so the result can be logged along with it.
+pub fn log_call(thread: &KPtr<Thread>, capability: CapId, args: SyscallArgs) -> bool {
+    if !log::log_enabled!(log::Level::Debug) {
+        return false;
+    }
+    let call = Call {
+        thread: thread.frame().addr().as_u64(),
+        capability,
+        args,
+    };
+    if !should_log(call) {
+        return false;
+    }
+    let resource = thread
+        .resource(capability)
+        .map_or("missing", |resource| resource_name(&resource));
+    log::debug!(
+        "thread {:#X}: cap {} ({resource}) {}",
+        call.thread,
+        u32::from(capability),
+        Operation(args)
+    );
+    true
+}
+
+/// Logs the error returned by a logged syscall.
+pub fn log_error(capability: CapId, args: SyscallArgs, error: CapError) {
+    log::debug!(
+        "cap {} {} failed: {error:?}",
+        u32::from(capability),
+        Operation(args)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt::Write as _;
+
+    use super::*;
+
+    struct Buffer {
+        bytes: [u8; 128],
+        len: usize,
+    }
+
+    impl Buffer {
+        fn format(value: impl fmt::Display) -> Self {
+            let mut buffer = Self {
+                bytes: [0; 128],
+                len: 0,
+            };
+            write!(buffer, "{value}").unwrap();
+            buffer
+        }
+
+        fn as_str(&self) -> &str {
+            core::str::from_utf8(&self.bytes[..self.len]).unwrap()
+        }
+    }
+
+    impl fmt::Write for Buffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            self.bytes
+                .get_mut(self.len..end)
+                .ok_or(fmt::Error)?
+                .copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    #[test_case]
+    fn labels_arguments() {
+        let link = SyscallArgs::new(RawOperation::CapTableLink.into(), 3, 4, 0, 0);
+        assert_eq!(
+            Buffer::format(Operation(link)).as_str(),
+            "cap_table.link(other_table=cap 3, slot=4)"
+        );
+        let extend = SyscallArgs::new(RawOperation::CapTableExtend.into(), 200, 0x5000, 2, 0);
+        assert_eq!(
+            Buffer::format(Operation(extend)).as_str(),
+            "cap_table.extend(cap=cap 200, region=0x5000, frames=2)"
+        );
+        let unknown = SyscallArgs::new(1000, 1, 0, 0, 0);
+        assert_eq!(
+            Buffer::format(Operation(unknown)).as_str(),
+            "unknown op 1000(0x1, 0x0, 0x0, 0x0)"
+        );
+    }
+
+    #[test_case]
+    fn suppresses_repeats() {
+        let call = Call {
+            thread: 0x1000,
+            capability: CapId::from(1),
+            args: SyscallArgs::new(RawOperation::IpiTakePending.into(), 0, 0, 0, 0),
+        };
+        let other = Call {
+            capability: CapId::from(2),
+            ..call
+        };
+        assert!(should_log(call));
+        assert!(!should_log(call));
+        assert!(!should_log(call));
+        assert_eq!(LIMITER.borrow().unwrap().repeated, 2);
+        assert!(should_log(other));
+        assert_eq!(LIMITER.borrow().unwrap().repeated, 0);
+    }
+}
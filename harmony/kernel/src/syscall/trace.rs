@@ -0,0 +1,199 @@
+//! Recording and replaying of syscall traces.
+//!
+//! When the `trace-syscalls` feature is enabled, every syscall that returns to
+//! the caller is recorded in a fixed-size buffer. The buffer can be dumped to
+//! the serial port and later fed back through [`replay`] to reproduce the same
+//! sequence of operations against a freshly constructed capability state.
+
+use kapi::raw::{CapError, CapId, RawOperation, SyscallArgs};
+use sync::cell::AtomicRefCell;
+
+use crate::component::Thread;
+
+/// Maximum number of recorded syscalls.
+const TRACE_CAPACITY: usize = 1024;
+
+static TRACE: AtomicRefCell<TraceBuffer> = AtomicRefCell::new(TraceBuffer::new());
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SyscallRecord {
+    /// Identifies the calling thread by the address of its frame.
+    pub thread: u64,
+    pub capability: CapId,
+    pub args: SyscallArgs,
+    pub result: isize,
+}
+
+struct TraceBuffer {
+    records: [Option<SyscallRecord>; TRACE_CAPACITY],
+    len: usize,
+    dropped: usize,
+}
+
+impl TraceBuffer {
+    const fn new() -> Self {
+        Self {
+            records: [None; TRACE_CAPACITY],
+            len: 0,
+            dropped: 0,
+        }
+    }
+}
+
+/// Appends a record to the trace buffer.
+///
+/// Records are dropped once the buffer is full.
+pub fn record(record: SyscallRecord) {
+    let Ok(mut trace) = TRACE.borrow_mut() else {
+        return;
+    };
+    if trace.len == TRACE_CAPACITY {
+        trace.dropped += 1;
+        return;
+    }
+    let index = trace.len;
+    trace.records[index] = Some(record);
+    trace.len += 1;
+}
+
+/// Clears the trace buffer.
+pub fn reset() {
+    let mut trace = TRACE.borrow_mut().unwrap();
+    trace.len = 0;
+    trace.dropped = 0;
+}
+
+/// Dumps the recorded trace to the serial port, one record per line.
+pub fn dump() {
+    let trace = TRACE.borrow().unwrap();
+    crate::sprintln!("syscall-trace: begin {} {}", trace.len, trace.dropped);
+    for (index, record) in trace.records[..trace.len].iter().flatten().enumerate() {
+        let (a, b, c, d) = record.args.args();
+        crate::sprintln!(
+            "syscall-trace: {} {:#X} {} {} {:#X} {:#X} {:#X} {:#X} {}",
+            index,
+            record.thread,
+            u32::from(record.capability),
+            record.args.op(),
+            a,
+            b,
+            c,
+            d,
+            record.result
+        );
+    }
+    crate::sprintln!("syscall-trace: end");
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ReplayMismatch {
+    pub index: usize,
+    pub expected: isize,
+    pub actual: isize,
+}
+
+/// Replays `records` on `thread`, checking that every syscall produces the
+/// recorded result.
+///
+/// `thread` must have been constructed with the same initial capability state
+/// as the recorded thread. Thread activations can't return to the caller and
+/// are skipped.
+pub fn replay(thread: &Thread, records: &[SyscallRecord]) -> Result<(), ReplayMismatch> {
+    for (index, record) in records.iter().enumerate() {
+        if let Ok(RawOperation::ThreadActivate) = RawOperation::try_from(record.args.op()) {
+            log::debug!("Skipping thread activation at {index}");
+            continue;
+        }
+        let actual = match thread.exercise_cap(record.capability, record.args) {
+            Ok(result) => result.try_into().unwrap(),
+            Err(e) => CapError::to_errno(e),
+        };
+        if actual != record.result {
+            return Err(ReplayMismatch {
+                index,
+                expected: record.result,
+                actual,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use kapi::raw::RawOperation;
+
+    use super::*;
+    use crate::arch::context::Regs;
+    use crate::arch::exec::KernelStack;
+    use crate::arch::paging::page_table::AnyPageTable;
+    use crate::bump_allocator::BumpAllocator;
+    use crate::caps::{CapEntryExtension as _, RawCapEntry, Resource};
+    use crate::kptr::KPtr;
+
+    fn thread_with_table() -> KPtr<Thread> {
+        let mut allocator = BumpAllocator::new();
+        let resources = KPtr::new(
+            allocator.alloc_untyped_frame().unwrap(),
+            RawCapEntry::default(),
+        )
+        .unwrap();
+        let table = KPtr::new(
+            allocator.alloc_untyped_frame().unwrap(),
+            RawCapEntry::default(),
+        )
+        .unwrap();
+        resources
+            .clone()
+            .find(CapId::from(0))
+            .unwrap()
+            .change(|slot| slot.resource = Resource::CapEntry(table));
+        let l4_table = AnyPageTable::new_l4(allocator.alloc_untyped_frame().unwrap()).unwrap();
+        let kernel_stack =
+            KPtr::new(allocator.alloc_untyped_frame().unwrap(), KernelStack::new()).unwrap();
+        KPtr::new(
+            allocator.alloc_untyped_frame().unwrap(),
+            Thread::new(Regs::default(), l4_table, resources, kernel_stack),
+        )
+        .unwrap()
+    }
+
+    #[test_case]
+    fn replays_recorded_results() {
+        let thread = thread_with_table();
+        let link = SyscallArgs::new(RawOperation::CapTableLink.into(), 0, 1, 0, 0);
+        let unlink = SyscallArgs::new(RawOperation::CapTableUnlink.into(), 1, 0, 0, 0);
+        let records = [
+            SyscallRecord {
+                thread: 0,
+                capability: CapId::from(0),
+                args: link,
+                result: 0,
+            },
+            SyscallRecord {
+                thread: 0,
+                capability: CapId::from(0),
+                args: unlink,
+                result: 0,
+            },
+            SyscallRecord {
+                thread: 0,
+                capability: CapId::from(5),
+                args: unlink,
+                result: CapError::NotFound.to_errno(),
+            },
+        ];
+        assert_eq!(replay(&thread, &records), Ok(()));
+
+        let mut bad = records;
+        bad[2].result = 0;
+        assert_eq!(
+            replay(&thread, &bad),
+            Err(ReplayMismatch {
+                index: 2,
+                expected: 0,
+                actual: CapError::NotFound.to_errno(),
+            })
+        );
+    }
+}
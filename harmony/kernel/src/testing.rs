@@ -6,6 +6,8 @@ unsafe extern "C" fn kmain() -> ! {
     crate::init();
     log::info!("IN TEST KERNEL");
     crate::test_main();
+    #[cfg(feature = "bench")]
+    crate::bench::run();
     exit_qemu(QemuExitCode::Success)
 }
 
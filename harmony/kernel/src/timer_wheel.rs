@@ -0,0 +1,341 @@
+//! A hierarchical timer wheel.
+//!
+//! Timers are kept in [`LEVELS`] wheels of [`SLOTS`] slots each. A timer due
+//! within [`SLOTS`] ticks sits in the slot of its tick on the first wheel, and
+//! later ones sit on the wheel whose slots are wide enough to reach them. Each
+//! time the first wheel wraps around, the next slot of the wheel above is
+//! cascaded: its timers move down to the wheel that now fits them. Inserting
+//! and cancelling a timer is O(1), and each tick only looks at the timers due
+//! then, plus the ones cascaded down. Timers further out than the last wheel
+//! reaches wait in its furthest slot and are placed again when it's cascaded.
+//!
+//! Timers live in a fixed table of `N` entries linked into their slots by
+//! index, since the kernel has no heap. A [`TimerId`] carries a generation
+//! so that cancelling a timer that already fired can't cancel the one that
+//! reused its entry.
+//!
+//! FIXME: Nothing uses the wheel yet. The timer interrupt doesn't drive one,
+//! and it isn't wired to thread wakeups or IPC timeouts, since threads can't
+//! sleep or wait on a timeout.
+
+/// Number of slots on each wheel.
+pub const SLOTS: usize = 64;
+/// Number of wheels.
+pub const LEVELS: usize = 4;
+
+const SLOT_BITS: u32 = SLOTS.trailing_zeros();
+/// Ticks reached by the wheels, a little over 16 million.
+const SPAN: u64 = 1 << (SLOT_BITS * LEVELS as u32);
+const NONE: u16 = u16::MAX;
+
+/// Names a timer in the wheel it was inserted into.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TimerId {
+    index: u16,
+    generation: u16,
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    expires: u64,
+    value: Option<T>,
+    prev: u16,
+    next: u16,
+    /// Slot the entry is linked into, as `level * SLOTS + slot`.
+    slot: u16,
+    generation: u16,
+}
+
+impl<T> Entry<T> {
+    const EMPTY: Self = Self {
+        expires: 0,
+        value: None,
+        prev: NONE,
+        next: NONE,
+        slot: NONE,
+        generation: 0,
+    };
+}
+
+/// Up to `N` timers carrying a `T` each.
+#[derive(Debug)]
+pub struct TimerWheel<T, const N: usize> {
+    entries: [Entry<T>; N],
+    /// First entry of each slot.
+    heads: [[u16; SLOTS]; LEVELS],
+    /// Number of timers on each wheel.
+    counts: [usize; LEVELS],
+    /// First unused entry, linked through `next`.
+    free: u16,
+    /// The next tick to process.
+    next_tick: u64,
+    len: usize,
+}
+
+impl<T, const N: usize> Default for TimerWheel<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> TimerWheel<T, N> {
+    /// Creates an empty wheel whose next tick is 0.
+    pub const fn new() -> Self {
+        assert!(N < NONE as usize, "Too many timers to index");
+        let mut entries = [const { Entry::EMPTY }; N];
+        let mut i = 0;
+        while i < N {
+            entries[i].next = if i + 1 < N { i as u16 + 1 } else { NONE };
+            i += 1;
+        }
+        Self {
+            entries,
+            heads: [[NONE; SLOTS]; LEVELS],
+            counts: [0; LEVELS],
+            free: if N > 0 { 0 } else { NONE },
+            next_tick: 0,
+            len: 0,
+        }
+    }
+
+    /// Number of pending timers.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds a timer that fires with `value` at tick `expires`, or on the next
+    /// tick if that's already past.
+    ///
+    /// Gives `value` back if the wheel is full.
+    pub fn insert(&mut self, expires: u64, value: T) -> Result<TimerId, T> {
+        if self.free == NONE {
+            return Err(value);
+        }
+        let index = self.free;
+        let entry = &mut self.entries[usize::from(index)];
+        self.free = entry.next;
+        entry.expires = expires;
+        entry.value = Some(value);
+        let generation = entry.generation;
+        self.link(index);
+        self.len += 1;
+        Ok(TimerId { index, generation })
+    }
+
+    /// Removes a timer that hasn't fired yet and returns its value.
+    pub fn cancel(&mut self, id: TimerId) -> Option<T> {
+        let entry = self.entries.get(usize::from(id.index))?;
+        if entry.generation != id.generation || entry.value.is_none() {
+            return None;
+        }
+        self.unlink(id.index);
+        Some(self.release(id.index))
+    }
+
+    /// Processes every tick up to and including `now`, calling `expire` with
+    /// the value of each timer that fires. Timers due on the same tick fire
+    /// in no particular order.
+    pub fn advance(&mut self, now: u64, mut expire: impl FnMut(T)) {
+        while self.next_tick <= now {
+            if self.is_empty() {
+                self.next_tick = now + 1;
+                return;
+            }
+            let tick = self.next_tick;
+            if self.counts[0] == 0 && tick % SLOTS as u64 != 0 {
+                // Nothing fires before the first wheel wraps around.
+                self.next_tick = (tick | (SLOTS as u64 - 1)) + 1;
+                self.next_tick = self.next_tick.min(now + 1);
+                continue;
+            }
+            for level in (1..LEVELS).rev() {
+                let shift = SLOT_BITS * level as u32;
+                if tick & ((1 << shift) - 1) == 0 {
+                    self.cascade(level, (tick >> shift) as usize % SLOTS);
+                }
+            }
+            let slot = tick as usize % SLOTS;
+            while self.heads[0][slot] != NONE {
+                let index = self.heads[0][slot];
+                self.unlink(index);
+                expire(self.release(index));
+            }
+            self.next_tick = tick + 1;
+        }
+    }
+
+    /// Moves the timers of a slot to where they belong from the current tick.
+    fn cascade(&mut self, level: usize, slot: usize) {
+        let mut index = core::mem::replace(&mut self.heads[level][slot], NONE);
+        while index != NONE {
+            let next = self.entries[usize::from(index)].next;
+            self.counts[level] -= 1;
+            self.link(index);
+            index = next;
+        }
+    }
+
+    /// Links an entry into the slot for its expiry, as seen from the next
+    /// tick to process.
+    fn link(&mut self, index: u16) {
+        let expires = self.entries[usize::from(index)].expires.max(self.next_tick);
+        let delta = expires - self.next_tick;
+        let (level, expires) =
+            match (0..LEVELS).find(|&level| delta >> (SLOT_BITS * (level as u32 + 1)) == 0) {
+                Some(level) => (level, expires),
+                None => (LEVELS - 1, self.next_tick + SPAN - 1),
+            };
+        let slot = (expires >> (SLOT_BITS * level as u32)) as usize % SLOTS;
+        let head = core::mem::replace(&mut self.heads[level][slot], index);
+        if head != NONE {
+            self.entries[usize::from(head)].prev = index;
+        }
+        let entry = &mut self.entries[usize::from(index)];
+        entry.prev = NONE;
+        entry.next = head;
+        entry.slot = (level * SLOTS + slot) as u16;
+        self.counts[level] += 1;
+    }
+
+    fn unlink(&mut self, index: u16) {
+        let entry = &self.entries[usize::from(index)];
+        let (prev, next, slot) = (entry.prev, entry.next, usize::from(entry.slot));
+        if prev == NONE {
+            self.heads[slot / SLOTS][slot % SLOTS] = next;
+        } else {
+            self.entries[usize::from(prev)].next = next;
+        }
+        if next != NONE {
+            self.entries[usize::from(next)].prev = prev;
+        }
+        self.counts[slot / SLOTS] -= 1;
+    }
+
+    /// Puts an unlinked entry back on the free list and returns its value.
+    fn release(&mut self, index: u16) -> T {
+        let entry = &mut self.entries[usize::from(index)];
+        let value = entry.value.take().expect("Released an unused timer");
+        entry.generation = entry.generation.wrapping_add(1);
+        entry.slot = NONE;
+        entry.prev = NONE;
+        entry.next = self.free;
+        self.free = index;
+        self.len -= 1;
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fired<const N: usize>(wheel: &mut TimerWheel<u64, N>, now: u64) -> Vec<u64> {
+        let mut fired = Vec::new();
+        wheel.advance(now, |value| fired.push(value));
+        fired.sort_unstable();
+        fired
+    }
+
+    #[test]
+    fn fires_on_the_tick_it_was_set_for() {
+        let mut wheel = TimerWheel::<u64, 16>::new();
+        wheel.advance(9, |_| unreachable!());
+        for expires in [10, 12, 12, 5] {
+            wheel.insert(expires, expires).unwrap();
+        }
+        assert_eq!(wheel.len(), 4);
+        // Past timers fire on the next tick.
+        assert_eq!(fired(&mut wheel, 10), [5, 10]);
+        assert_eq!(fired(&mut wheel, 11), []);
+        assert_eq!(fired(&mut wheel, 20), [12, 12]);
+        assert!(wheel.is_empty());
+    }
+
+    #[test]
+    fn cascades_every_level() {
+        let mut wheel = TimerWheel::<u64, 64>::new();
+        let start = 1000;
+        wheel.advance(start - 1, |_| unreachable!());
+        let deltas = [
+            1,
+            63,
+            64,
+            65,
+            SLOTS as u64 * 64 - 1,
+            SLOTS as u64 * 64,
+            SLOTS as u64 * 64 * 64 + 7,
+            SPAN - 1,
+            SPAN,
+            SPAN * 3 + 5,
+        ];
+        for delta in deltas {
+            wheel.insert(start + delta, start + delta).unwrap();
+        }
+        // Step to one tick before each timer, then onto it, so that it
+        // fires neither early nor late.
+        for delta in deltas {
+            let expires = start + delta;
+            assert_eq!(fired(&mut wheel, expires - 1), [], "{delta} fired early");
+            assert_eq!(fired(&mut wheel, expires), [expires], "{delta} fired late");
+        }
+        assert!(wheel.is_empty());
+    }
+
+    #[test]
+    fn cancels_only_pending_timers() {
+        let mut wheel = TimerWheel::<u64, 2>::new();
+        let first = wheel.insert(100, 1).unwrap();
+        let second = wheel.insert(5000, 2).unwrap();
+        assert_eq!(wheel.insert(3, 3), Err(3));
+        assert_eq!(wheel.cancel(first), Some(1));
+        assert_eq!(wheel.cancel(first), None);
+        // The entry is reused without `first` reaching the new timer.
+        let third = wheel.insert(100, 3).unwrap();
+        assert_eq!(wheel.cancel(first), None);
+        assert_eq!(fired(&mut wheel, 100), [3]);
+        assert_eq!(wheel.cancel(third), None);
+        assert_eq!(wheel.cancel(second), Some(2));
+        assert_eq!(fired(&mut wheel, 10_000), []);
+    }
+
+    #[test]
+    fn matches_a_sorted_list() {
+        // A simple generator so that the test is reproducible.
+        let mut seed = 0x2545_F491_4F6C_DD1Du64;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        let mut wheel = TimerWheel::<u64, 256>::new();
+        let mut pending = Vec::new();
+        let mut now = 0;
+        for _ in 0..2000 {
+            // Timers set for a tick that was already processed fire on the
+            // next one, so they're set at least a tick ahead.
+            let expires = now + 1 + random() % (1 << (random() % 26));
+            if let Ok(id) = wheel.insert(expires, expires) {
+                pending.push((expires, id));
+            }
+            if random() % 4 == 0 && !pending.is_empty() {
+                let (expires, id) = pending.swap_remove(random() as usize % pending.len());
+                assert_eq!(wheel.cancel(id), Some(expires));
+            }
+            now += random() % (1 << (random() % 20));
+            let mut due: Vec<_> = pending
+                .iter()
+                .map(|&(expires, _)| expires)
+                .filter(|&expires| expires <= now)
+                .collect();
+            due.sort_unstable();
+            pending.retain(|&(expires, _)| expires > now);
+            assert_eq!(fired(&mut wheel, now), due);
+            assert_eq!(wheel.len(), pending.len());
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Event tracing.
+//!
+//! Tracepoints in the scheduler, the syscall path, the IPI queue and the page
+//! fault handler record [`TraceRecord`]s into a ring per core. A full ring
+//! overwrites its oldest records, so a drained trace always ends with the most
+//! recent events. Records are only written in kernels built with the
+//! `trace-events` feature. Otherwise the tracepoints compile to nothing and
+//! draining always comes back empty.
+
+use kapi::trace::{EventKind, TraceRecord};
+use sync::cell::AtomicRefCell;
+
+use crate::component::Thread;
+use crate::core_local::{current_core, NUM_CORES};
+use crate::kptr::KPtr;
+
+/// Records each ring holds.
+const RING_CAPACITY: usize = 512;
+
+struct Ring {
+    records: [TraceRecord; RING_CAPACITY],
+    /// Index of the oldest record.
+    start: usize,
+    len: usize,
+    /// Records overwritten before they were drained.
+    lost: u64,
+}
+
+impl Ring {
+    const fn new() -> Self {
+        Self {
+            records: [TraceRecord {
+                nanos: 0,
+                kind: 0,
+                core: 0,
+                thread: 0,
+                arg: 0,
+            }; RING_CAPACITY],
+            start: 0,
+            len: 0,
+            lost: 0,
+        }
+    }
+
+    fn push(&mut self, record: TraceRecord) {
+        let end = (self.start + self.len) % RING_CAPACITY;
+        self.records[end] = record;
+        if self.len == RING_CAPACITY {
+            self.start = (self.start + 1) % RING_CAPACITY;
+            self.lost += 1;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    fn drain(&mut self, out: &mut [TraceRecord]) -> usize {
+        let count = out.len().min(self.len);
+        for (i, slot) in out[..count].iter_mut().enumerate() {
+            *slot = self.records[(self.start + i) % RING_CAPACITY];
+        }
+        self.start = (self.start + count) % RING_CAPACITY;
+        self.len -= count;
+        count
+    }
+}
+
+static RINGS: [AtomicRefCell<Ring>; NUM_CORES] =
+    [const { AtomicRefCell::new(Ring::new()) }; NUM_CORES];
+
+/// Identifies a thread in traces.
+pub fn thread_id(thread: &KPtr<Thread>) -> u64 {
+    thread.frame().addr().as_u64()
+}
+
+/// Records an event on the current core.
+#[inline]
+pub fn event(kind: EventKind, thread: u64, arg: u64) {
+    if !cfg!(feature = "trace-events") {
+        return;
+    }
+    let core = current_core();
+    // An interrupt fired while the ring was being written, drop its event.
+    let Ok(mut ring) = RINGS[core].borrow_mut() else {
+        return;
+    };
+    ring.push(TraceRecord {
+        nanos: crate::info::nanos_since_boot(),
+        kind: kind.into(),
+        core: core as u32,
+        thread,
+        arg,
+    });
+}
+
+/// Records an event for the thread running on the current core.
+pub fn event_current(kind: EventKind, arg: u64) {
+    if !cfg!(feature = "trace-events") {
+        return;
+    }
+    let thread = Thread::current().map_or(0, |thread| thread_id(&thread));
+    event(kind, thread, arg);
+}
+
+/// Moves the oldest records traced on `core` into `out` and returns how many
+/// were moved, or `None` if there's no such core or its ring is in use.
+pub fn drain(core: usize, out: &mut [TraceRecord]) -> Option<usize> {
+    let mut ring = RINGS.get(core)?.borrow_mut().ok()?;
+    if ring.lost > 0 {
+        log::warn!("Lost {} trace records on core {core}", ring.lost);
+        ring.lost = 0;
+    }
+    Some(ring.drain(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(arg: u64) -> TraceRecord {
+        TraceRecord {
+            kind: EventKind::Ipi.into(),
+            arg,
+            ..Default::default()
+        }
+    }
+
+    #[test_case]
+    fn rings_keep_the_newest_records() {
+        let mut ring = Ring::new();
+        for arg in 0..RING_CAPACITY as u64 + 2 {
+            ring.push(record(arg));
+        }
+        assert_eq!(ring.lost, 2);
+        let mut out = [TraceRecord::default(); 3];
+        assert_eq!(ring.drain(&mut out), 3);
+        assert_eq!(out.map(|record| record.arg), [2, 3, 4]);
+        assert_eq!(ring.len, RING_CAPACITY - 3);
+
+        let mut rest = [TraceRecord::default(); RING_CAPACITY];
+        assert_eq!(ring.drain(&mut rest), RING_CAPACITY - 3);
+        assert_eq!(rest[RING_CAPACITY - 4].arg, RING_CAPACITY as u64 + 1);
+        assert_eq!(ring.drain(&mut out), 0);
+    }
+}
@@ -0,0 +1,85 @@
+//! Kernel access to the contents of user frames.
+//!
+//! Syscalls that read the memory of a user frame borrow it through a
+//! [`UserFrameGuard`] instead of going through the direct map themselves. The
+//! guard checks that the frame is a live user frame and holds a reference to
+//! it while it's borrowed, so it can't be retyped and handed to someone else
+//! in the meantime. It also checks that the direct map actually covers the
+//! frame: the bootloader only maps the memory it reported, so a frame past
+//! that would fault in the kernel.
+
+use crate::arch::paging::page_table::AnyPageTable;
+use crate::arch::paging::{Page, PhysAddrExt as _, RawFrame, VirtAddr, PAGE_SIZE};
+use crate::retyping::{AsTypeError, UserFrame};
+
+#[derive(Debug)]
+pub enum GuardError {
+    /// The frame isn't a live user frame.
+    NotUser(AsTypeError),
+    /// The direct map doesn't cover the frame.
+    OutsideWindow,
+}
+
+/// A reference to a user frame whose contents the kernel can access.
+#[derive(Debug)]
+pub struct UserFrameGuard {
+    frame: UserFrame,
+    contents: VirtAddr,
+}
+
+impl UserFrameGuard {
+    /// Borrows `frame` if it's a user frame within the direct map.
+    pub fn new(frame: RawFrame) -> Result<Self, GuardError> {
+        let frame = frame.try_as_user().map_err(GuardError::NotUser)?;
+        let contents = locate(frame.frame()).ok_or(GuardError::OutsideWindow)?;
+        Ok(Self { frame, contents })
+    }
+
+    pub fn frame(&self) -> RawFrame {
+        self.frame.frame()
+    }
+
+    /// The contents of the frame.
+    ///
+    /// Userspace may write to the frame while it's read if it's mapped
+    /// writable anywhere, so callers shouldn't expect to read the same bytes
+    /// twice.
+    pub fn bytes(&self) -> &[u8] {
+        // SAFETY: The frame is mapped at `contents` and held by the guard.
+        unsafe { core::slice::from_raw_parts(self.contents.as_ptr(), PAGE_SIZE) }
+    }
+}
+
+/// Finds where the direct map has `frame`, if it does.
+fn locate(frame: RawFrame) -> Option<VirtAddr> {
+    let contents = frame.addr().to_virtual();
+    let table = AnyPageTable::current();
+    // SAFETY: CR3 always holds a root-level page table.
+    let addrspace = unsafe { table.as_addrspace() };
+    addrspace.leaf(Page::containing_address(contents))?;
+    Some(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bump_allocator::BumpAllocator;
+
+    #[test_case]
+    fn borrows_only_user_frames() {
+        let mut allocator = BumpAllocator::new();
+        let frame = allocator.alloc_untyped_frame().unwrap();
+        assert!(matches!(
+            UserFrameGuard::new(frame),
+            Err(GuardError::NotUser(_))
+        ));
+
+        let user = frame.try_into_user().unwrap();
+        let guard = UserFrameGuard::new(frame).unwrap();
+        assert_eq!(guard.frame(), frame);
+        assert!(guard.bytes().iter().all(|&byte| byte == 0));
+        drop(guard);
+        drop(user);
+        frame.try_into_untyped().unwrap();
+    }
+}
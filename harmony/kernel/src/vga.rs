@@ -0,0 +1,282 @@
+//! VGA text mode console.
+//!
+//! Machines booted without a framebuffer are usually left in the 80x25 text
+//! mode the firmware set up. When the bootloader doesn't hand out a pixel
+//! framebuffer, the kernel logs to the text buffer at `0xB8000` as one more
+//! [`Sink`] so that bring-up on old hardware still shows something on screen.
+//! Nothing switches video modes: if the firmware didn't leave text mode on,
+//! the writes go nowhere.
+//!
+//! With `vga.mirror=on` on the command line the screen shows the serial output
+//! instead of formatting records itself, so prints that don't go through the
+//! logger, e.g. test results and panics, show up too. Serial sees everything
+//! the screen does either way, since the screen only shows log records.
+//!
+//! The screen is drawn by [`Console`], whose rendering is checked on the host.
+//! Tests can [`capture`] the screen to check the rendered text.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use kernel::console::{Cells, Console};
+use log::{Level, Record};
+use sync::cell::AtomicRefCell;
+
+use crate::arch::paging::page_table::AnyPageTable;
+use crate::arch::paging::{Page, PhysAddr, PhysAddrExt as _};
+use crate::boot::{self, BootProtocol as _};
+use crate::logging::Sink;
+
+/// Physical address of the text buffer.
+const BUFFER: u64 = 0xB8000;
+const WIDTH: usize = 80;
+const HEIGHT: usize = 25;
+
+/// Light grey on black.
+const NORMAL: u8 = 0x07;
+/// Yellow on black.
+const WARNING: u8 = 0x0E;
+/// Light red on black.
+const ERROR: u8 = 0x0C;
+
+/// Whether the screen shows the serial output.
+static MIRROR: AtomicBool = AtomicBool::new(false);
+
+/// The text buffer at an address, which is 0 if there's no buffer.
+#[derive(Debug, Copy, Clone)]
+struct TextBuffer(usize);
+
+impl TextBuffer {
+    fn cell(self, index: usize) -> *mut u16 {
+        (self.0 as *mut u16).wrapping_add(index)
+    }
+}
+
+impl Cells for TextBuffer {
+    fn read(&self, index: usize) -> u16 {
+        if self.0 == 0 {
+            return 0;
+        }
+        // SAFETY: The buffer has `WIDTH * HEIGHT` cells.
+        unsafe { self.cell(index).read_volatile() }
+    }
+
+    fn write(&mut self, index: usize, cell: u16) {
+        if self.0 != 0 {
+            // SAFETY: The buffer has `WIDTH * HEIGHT` cells.
+            unsafe { self.cell(index).write_volatile(cell) }
+        }
+    }
+}
+
+type Screen = Console<TextBuffer>;
+
+/// Logs to the VGA text buffer.
+pub struct VgaSink {
+    screen: AtomicRefCell<Screen>,
+}
+
+static VGA_SINK: VgaSink = VgaSink {
+    screen: AtomicRefCell::new(Console::new(TextBuffer(0), WIDTH, HEIGHT, NORMAL)),
+};
+
+impl Sink for VgaSink {
+    fn name(&self) -> &'static str {
+        "vga"
+    }
+
+    fn log(&self, record: &Record) {
+        use fmt::Write as _;
+
+        if MIRROR.load(Ordering::Relaxed) {
+            return;
+        }
+        // Records are dropped if the screen is being written to.
+        if let Ok(mut screen) = self.screen.borrow_mut() {
+            screen.set_attr(match record.level() {
+                Level::Error => ERROR,
+                Level::Warn => WARNING,
+                _ => NORMAL,
+            });
+            let _ = writeln!(screen, "{} - {}", record.level(), record.args());
+        }
+    }
+}
+
+impl VgaSink {
+    /// Writes raw text to the screen, dropping it if the screen is being
+    /// written to.
+    fn print(&self, args: fmt::Arguments) {
+        use fmt::Write as _;
+
+        if let Ok(mut screen) = self.screen.borrow_mut() {
+            screen.set_attr(NORMAL);
+            let _ = screen.write_fmt(args);
+        }
+    }
+}
+
+/// Shows serial output on the screen if mirroring is on.
+pub(crate) fn mirror(args: fmt::Arguments) {
+    if MIRROR.load(Ordering::Relaxed) {
+        VGA_SINK.print(args);
+    }
+}
+
+/// Returns the text mode sink if the bootloader didn't provide a pixel
+/// framebuffer and the text buffer is in the direct map.
+pub fn init() -> Option<&'static VgaSink> {
+    if let Some(framebuffer) = boot::protocol().framebuffer() {
+        if !framebuffer.text {
+            return None;
+        }
+    }
+    let cells = PhysAddr::new(BUFFER).to_virtual();
+    let table = AnyPageTable::current();
+    // SAFETY: CR3 always holds a root-level page table.
+    let addrspace = unsafe { table.as_addrspace() };
+    if addrspace.leaf(Page::containing_address(cells)).is_none() {
+        log::warn!("No framebuffer and the VGA text buffer isn't mapped");
+        return None;
+    }
+    let mut screen = VGA_SINK.screen.borrow_mut().ok()?;
+    screen.replace_cells(TextBuffer(cells.as_usize()));
+    screen.clear();
+    drop(screen);
+    for option in crate::CMDLINE.split_ascii_whitespace() {
+        match option.strip_prefix("vga.mirror=") {
+            Some("on") => MIRROR.store(true, Ordering::Relaxed),
+            Some("off") => MIRROR.store(false, Ordering::Relaxed),
+            Some(mirror) => log::warn!("Unknown VGA mirror setting {mirror:?}"),
+            None => {}
+        }
+    }
+    log::info!("No framebuffer, logging to the VGA text buffer");
+    Some(&VGA_SINK)
+}
+
+/// Cells the screen is drawn into while captured.
+struct CaptureCells(UnsafeCell<[u16; WIDTH * HEIGHT]>);
+
+// SAFETY: Only accessed through the screen while `CAPTURING` is held.
+unsafe impl Sync for CaptureCells {}
+
+static CAPTURE_CELLS: CaptureCells = CaptureCells(UnsafeCell::new([0; WIDTH * HEIGHT]));
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+
+/// The screen drawn into memory instead of the text buffer, until dropped.
+pub struct Capture {
+    cells: TextBuffer,
+    cursor: (usize, usize),
+}
+
+/// Starts drawing the screen into memory, starting from a blank one.
+///
+/// Returns `None` if it's already captured or being written to.
+pub fn capture() -> Option<Capture> {
+    if CAPTURING.swap(true, Ordering::Acquire) {
+        return None;
+    }
+    let Ok(mut screen) = VGA_SINK.screen.borrow_mut() else {
+        CAPTURING.store(false, Ordering::Release);
+        return None;
+    };
+    let capture = Capture {
+        cells: screen.replace_cells(TextBuffer(CAPTURE_CELLS.0.get() as usize)),
+        cursor: screen.cursor(),
+    };
+    screen.clear();
+    Some(capture)
+}
+
+impl Capture {
+    /// Returns the characters in `row` of the screen, blanks included.
+    ///
+    /// # Panics
+    ///
+    /// If `row` is off the screen or the screen is being written to.
+    pub fn row(&self, row: usize) -> [u8; WIDTH] {
+        assert!(row < HEIGHT);
+        let screen = VGA_SINK.screen.borrow().unwrap();
+        core::array::from_fn(|col| screen.cell(row, col) as u8)
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        // The text buffer is left as it was before the capture.
+        let mut screen = VGA_SINK.screen.borrow_mut().unwrap();
+        screen.replace_cells(self.cells);
+        let (row, col) = self.cursor;
+        screen.set_cursor(row, col);
+        screen.reset();
+        drop(screen);
+        CAPTURING.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt::Write as _;
+
+    use kernel::console::REPLACEMENT;
+
+    use super::*;
+
+    fn text(cells: &[u16; WIDTH * HEIGHT], row: usize) -> [u8; WIDTH] {
+        core::array::from_fn(|col| cells[row * WIDTH + col] as u8)
+    }
+
+    #[test_case]
+    fn wraps_and_scrolls() {
+        let mut cells = [0; WIDTH * HEIGHT];
+        let mut screen: Screen = Console::new(
+            TextBuffer(cells.as_mut_ptr() as usize),
+            WIDTH,
+            HEIGHT,
+            NORMAL,
+        );
+        screen.clear();
+        screen.set_attr(ERROR);
+        writeln!(screen, "a").unwrap();
+        for _ in 0..WIDTH + 1 {
+            screen.put(b'b');
+        }
+        assert_eq!(cells[0], (u16::from(ERROR) << 8) | u16::from(b'a'));
+        assert!(text(&cells, 1).iter().all(|&byte| byte == b'b'));
+        assert_eq!(&text(&cells, 2)[..2], b"b ");
+
+        for _ in 0..HEIGHT - 2 {
+            screen.put(b'\n');
+        }
+        // The first row scrolled off and the last one is empty.
+        assert!(text(&cells, 0).iter().all(|&byte| byte == b'b'));
+        assert!(text(&cells, HEIGHT - 1).iter().all(|&byte| byte == b' '));
+        screen.put(0x80);
+        assert_eq!(text(&cells, HEIGHT - 1)[0], REPLACEMENT);
+    }
+
+    #[test_case]
+    fn captures_records_and_mirrored_output() {
+        let capture = capture().unwrap();
+        assert!(super::capture().is_none());
+        VGA_SINK.log(
+            &Record::builder()
+                .args(format_args!("disk {} gone", 2))
+                .level(Level::Warn)
+                .build(),
+        );
+        VGA_SINK.print(format_args!(
+            "[    1.000000] \x1b[32mINFO \x1b[0m kernel: ok\n"
+        ));
+        assert_eq!(capture.row(0).trim_ascii_end(), b"WARN - disk 2 gone");
+        assert_eq!(
+            capture.row(1).trim_ascii_end(),
+            b"[    1.000000] INFO  kernel: ok"
+        );
+        assert_eq!(capture.row(2).trim_ascii_end(), b"");
+        drop(capture);
+        assert!(super::capture().is_some());
+    }
+}
@@ -0,0 +1,401 @@
+//! Logging to a virtio console.
+//!
+//! QEMU's virtio console takes a whole buffer per notification instead of a
+//! byte per `out`, so bulk logs come out much faster than through the UART.
+//! The device is found on the PCI bus and set up through the virtio 1.x PCI
+//! transport: the common and notification structures are located with vendor
+//! specific capabilities and accessed through the direct map. Only the
+//! transmit queue of port 0 is used, with a single buffer that's handed to
+//! the device once per record and waited on, so the sink needs no interrupts.
+//!
+//! With QEMU, `-device virtio-serial-pci -device virtconsole,chardev=<id>`
+//! adds one, which `make emulate VIRTIO_CONSOLE=yes` does.
+//!
+//! DMA is blocked once the IOMMU turns translation on, unless the device has
+//! a domain. Records the device doesn't take in time are dropped and the
+//! sink goes quiet for good.
+
+use core::fmt;
+use core::sync::atomic::{fence, Ordering};
+
+use log::Record;
+use sync::cell::{AtomicOnceCell, AtomicRefCell};
+
+use crate::arch::paging::page_table::AnyPageTable;
+use crate::arch::paging::{Page, PhysAddr, PhysAddrExt as _, PAGE_SIZE};
+use crate::arch::pci::{self, Function};
+use crate::bump_allocator::BumpAllocator;
+use crate::logging::{SerialLine, Sink};
+
+const VENDOR: u16 = 0x1AF4;
+/// Device ids of the transitional and the modern console.
+const DEVICE_IDS: [u16; 2] = [0x1003, 0x1043];
+
+/// Id of the vendor specific PCI capabilities virtio describes itself with.
+const CAP_VENDOR: u8 = 0x09;
+const CFG_COMMON: u8 = 1;
+const CFG_NOTIFY: u8 = 2;
+
+// Registers of the common configuration structure.
+const DEVICE_FEATURE_SELECT: usize = 0x00;
+const DEVICE_FEATURE: usize = 0x04;
+const DRIVER_FEATURE_SELECT: usize = 0x08;
+const DRIVER_FEATURE: usize = 0x0C;
+const DEVICE_STATUS: usize = 0x14;
+const QUEUE_SELECT: usize = 0x16;
+const QUEUE_SIZE: usize = 0x18;
+const QUEUE_ENABLE: usize = 0x1C;
+const QUEUE_NOTIFY_OFF: usize = 0x1E;
+const QUEUE_DESC: usize = 0x20;
+const QUEUE_DRIVER: usize = 0x28;
+const QUEUE_DEVICE: usize = 0x30;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FEATURES_OK: u8 = 8;
+
+/// `VIRTIO_F_VERSION_1`, bit 32 of the features, so bit 0 of the second
+/// word.
+const FEATURE_VERSION_1: u32 = 1 << 0;
+
+/// The transmit queue of port 0.
+const TRANSMIT_QUEUE: u16 = 1;
+/// Largest queue the driver asks for. Only one descriptor is ever in flight.
+const MAX_QUEUE_SIZE: u16 = 16;
+
+// Offsets in the ring page, which holds the descriptors, the driver ring and
+// the device ring of a queue of up to `MAX_QUEUE_SIZE` entries.
+const DESCRIPTORS: usize = 0;
+const DRIVER_RING: usize = 0x100;
+const DEVICE_RING: usize = 0x200;
+
+/// Polls of the device ring before a record is given up on.
+const SPIN_LIMIT: usize = 1 << 20;
+
+/// Where the virtio structures of the device are in the direct map.
+#[derive(Debug, Copy, Clone)]
+struct Transport {
+    common: usize,
+    notify: usize,
+    notify_multiplier: u32,
+}
+
+impl Transport {
+    /// Finds the structures of `function` through its capabilities.
+    fn find(function: Function) -> Option<Self> {
+        let mut common = None;
+        let mut notify = None;
+        function.capabilities(|id, offset| {
+            if id != CAP_VENDOR {
+                return;
+            }
+            let header = function.read(offset);
+            let kind = (header >> 24) as u8;
+            let bar = function.read(offset + 4) as u8;
+            let start = u64::from(function.read(offset + 8));
+            let Some(base) = function.memory_bar(bar) else {
+                return;
+            };
+            match kind {
+                CFG_COMMON if common.is_none() => common = Some(base + start),
+                CFG_NOTIFY if notify.is_none() => {
+                    notify = Some((base + start, function.read(offset + 16)));
+                }
+                _ => {}
+            }
+        });
+        let (notify, notify_multiplier) = notify?;
+        Some(Self {
+            common: mapped(common?)?,
+            notify: mapped(notify)?,
+            notify_multiplier,
+        })
+    }
+
+    fn read8(&self, register: usize) -> u8 {
+        // SAFETY: `common` points to the common structure in the direct map.
+        unsafe { ((self.common + register) as *const u8).read_volatile() }
+    }
+
+    fn write8(&self, register: usize, value: u8) {
+        // SAFETY: `common` points to the common structure in the direct map.
+        unsafe { ((self.common + register) as *mut u8).write_volatile(value) }
+    }
+
+    fn read16(&self, register: usize) -> u16 {
+        // SAFETY: `common` points to the common structure in the direct map.
+        unsafe { ((self.common + register) as *const u16).read_volatile() }
+    }
+
+    fn write16(&self, register: usize, value: u16) {
+        // SAFETY: `common` points to the common structure in the direct map.
+        unsafe { ((self.common + register) as *mut u16).write_volatile(value) }
+    }
+
+    fn read32(&self, register: usize) -> u32 {
+        // SAFETY: `common` points to the common structure in the direct map.
+        unsafe { ((self.common + register) as *const u32).read_volatile() }
+    }
+
+    fn write32(&self, register: usize, value: u32) {
+        // SAFETY: `common` points to the common structure in the direct map.
+        unsafe { ((self.common + register) as *mut u32).write_volatile(value) }
+    }
+
+    /// Writes a 64-bit register as two halves, which the transport allows.
+    fn write64(&self, register: usize, value: u64) {
+        self.write32(register, value as u32);
+        self.write32(register + 4, (value >> 32) as u32);
+    }
+}
+
+/// The direct map address of the device memory at `address`, if the
+/// bootloader mapped it.
+fn mapped(address: u64) -> Option<usize> {
+    let virt = PhysAddr::new(address).to_virtual();
+    let table = AnyPageTable::current();
+    // SAFETY: CR3 always holds a root-level page table.
+    let addrspace = unsafe { table.as_addrspace() };
+    addrspace.leaf(Page::containing_address(virt))?;
+    Some(virt.as_usize())
+}
+
+/// The transmit queue and the buffer records are written to.
+struct Queue {
+    transport: Transport,
+    /// Notification register of the queue.
+    notify: usize,
+    size: u16,
+    /// The ring page in the direct map.
+    rings: usize,
+    buffer: usize,
+    buffer_address: u64,
+    /// Bytes written to the buffer since it was last sent.
+    len: usize,
+    /// Entries made available to the device.
+    available: u16,
+    /// Whether the device stopped taking buffers.
+    broken: bool,
+}
+
+impl Queue {
+    /// Resets the device, negotiates features and sets up the transmit
+    /// queue.
+    fn new(transport: Transport) -> Result<Self, &'static str> {
+        transport.write8(DEVICE_STATUS, 0);
+        while transport.read8(DEVICE_STATUS) != 0 {
+            core::hint::spin_loop();
+        }
+        transport.write8(DEVICE_STATUS, STATUS_ACKNOWLEDGE);
+        transport.write8(DEVICE_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        transport.write32(DEVICE_FEATURE_SELECT, 1);
+        if transport.read32(DEVICE_FEATURE) & FEATURE_VERSION_1 == 0 {
+            return Err("the device only has the legacy interface");
+        }
+        transport.write32(DRIVER_FEATURE_SELECT, 0);
+        transport.write32(DRIVER_FEATURE, 0);
+        transport.write32(DRIVER_FEATURE_SELECT, 1);
+        transport.write32(DRIVER_FEATURE, FEATURE_VERSION_1);
+        let status = STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK;
+        transport.write8(DEVICE_STATUS, status);
+        if transport.read8(DEVICE_STATUS) & STATUS_FEATURES_OK == 0 {
+            return Err("the device refused the features");
+        }
+
+        transport.write16(QUEUE_SELECT, TRANSMIT_QUEUE);
+        let size = queue_size(transport.read16(QUEUE_SIZE)).ok_or("no transmit queue")?;
+        transport.write16(QUEUE_SIZE, size);
+        let mut allocator = BumpAllocator::new();
+        let mut page = || {
+            let frame = allocator.alloc_kernel_frame()?.into_raw();
+            let virt = frame.addr().to_virtual();
+            // SAFETY: The frame was just allocated and nothing else uses it.
+            unsafe { virt.as_mut_ptr::<u8>().write_bytes(0, PAGE_SIZE) };
+            Some((frame.addr().as_u64(), virt.as_usize()))
+        };
+        let (rings_address, rings) = page().ok_or("out of memory")?;
+        let (buffer_address, buffer) = page().ok_or("out of memory")?;
+        transport.write64(QUEUE_DESC, rings_address + DESCRIPTORS as u64);
+        transport.write64(QUEUE_DRIVER, rings_address + DRIVER_RING as u64);
+        transport.write64(QUEUE_DEVICE, rings_address + DEVICE_RING as u64);
+        let notify_off = transport.read16(QUEUE_NOTIFY_OFF);
+        transport.write16(QUEUE_ENABLE, 1);
+        transport.write8(DEVICE_STATUS, status | STATUS_DRIVER_OK);
+
+        let notify =
+            transport.notify + usize::from(notify_off) * transport.notify_multiplier as usize;
+        Ok(Self {
+            transport,
+            notify,
+            size,
+            rings,
+            buffer,
+            buffer_address,
+            len: 0,
+            available: 0,
+            broken: false,
+        })
+    }
+
+    /// Hands the buffer to the device and waits for it to be taken.
+    fn flush(&mut self) {
+        if self.len == 0 || self.broken {
+            self.len = 0;
+            return;
+        }
+        // SAFETY: The ring page is laid out as described by the offsets,
+        // and only the device ring is written by the device.
+        unsafe {
+            let descriptor = (self.rings + DESCRIPTORS) as *mut u64;
+            descriptor.write_volatile(self.buffer_address);
+            // The length and no flags, since the buffer is read by the
+            // device.
+            descriptor.add(1).write_volatile(self.len as u64);
+
+            let driver = (self.rings + DRIVER_RING) as *mut u16;
+            let slot = usize::from(self.available % self.size);
+            driver.add(2 + slot).write_volatile(0);
+            self.available = self.available.wrapping_add(1);
+            fence(Ordering::Release);
+            driver.add(1).write_volatile(self.available);
+            fence(Ordering::SeqCst);
+            (self.notify as *mut u16).write_volatile(TRANSMIT_QUEUE);
+
+            let used = (self.rings + DEVICE_RING + 2) as *const u16;
+            let taken = (0..SPIN_LIMIT).any(|_| {
+                core::hint::spin_loop();
+                used.read_volatile() == self.available
+            });
+            if !taken {
+                self.broken = true;
+                // The device may still read the buffer, so it isn't reset.
+                self.transport.write8(DEVICE_STATUS, 0x80);
+            }
+        }
+        self.len = 0;
+    }
+}
+
+impl fmt::Write for Queue {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for chunk in chunks(s.as_bytes(), PAGE_SIZE - self.len, PAGE_SIZE) {
+            // SAFETY: The buffer page is ours and `chunks` keeps writes
+            // within it.
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    chunk.as_ptr(),
+                    (self.buffer + self.len) as *mut u8,
+                    chunk.len(),
+                )
+            };
+            self.len += chunk.len();
+            if self.len == PAGE_SIZE {
+                self.flush();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The queue size to ask for, given the largest one the device allows.
+///
+/// Split queues must be a power of two in size.
+fn queue_size(max: u16) -> Option<u16> {
+    let size = max.min(MAX_QUEUE_SIZE);
+    (size != 0).then(|| 1 << size.ilog2())
+}
+
+/// Splits `bytes` into a chunk of at most `first` bytes and then chunks of
+/// at most `rest` bytes.
+fn chunks(bytes: &[u8], first: usize, rest: usize) -> impl Iterator<Item = &[u8]> {
+    let (head, tail) = bytes.split_at(first.min(bytes.len()));
+    core::iter::once(head)
+        .filter(|head| !head.is_empty())
+        .chain(tail.chunks(rest))
+}
+
+/// Logs to the virtio console.
+pub struct VirtioSink {
+    queue: AtomicRefCell<Queue>,
+}
+
+impl Sink for VirtioSink {
+    fn name(&self) -> &'static str {
+        "virtio"
+    }
+
+    fn log(&self, record: &Record) {
+        use fmt::Write as _;
+
+        // Records are dropped if one is being sent, e.g. from an interrupt.
+        if let Ok(mut queue) = self.queue.borrow_mut() {
+            let _ = writeln!(queue, "{}", SerialLine(record));
+            queue.flush();
+        }
+    }
+}
+
+static SINK: AtomicOnceCell<VirtioSink> = AtomicOnceCell::new();
+
+/// Sets up the first virtio console on the PCI bus and returns its sink.
+///
+/// Must be called after the retype table has been initialized.
+pub fn init() -> Option<&'static VirtioSink> {
+    let mut found = None;
+    pci::enumerate(|function| {
+        if found.is_none()
+            && function.vendor() == VENDOR
+            && DEVICE_IDS.contains(&function.device_id())
+        {
+            found = Some(function);
+        }
+    });
+    let Some(function) = found else {
+        log::info!("No virtio console");
+        return None;
+    };
+    function.enable_bus_master();
+    let Some(transport) = Transport::find(function) else {
+        log::warn!("The virtio console at {function:?} has no mapped modern interface");
+        return None;
+    };
+    let queue = match Queue::new(transport) {
+        Ok(queue) => queue,
+        Err(e) => {
+            log::warn!("Can't use the virtio console at {function:?}: {e}");
+            return None;
+        }
+    };
+    log::info!("Found a virtio console at {function:?}");
+    SINK.set(VirtioSink {
+        queue: AtomicRefCell::new(queue),
+    })
+    .ok()?;
+    SINK.get()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn picks_a_power_of_two_queue() {
+        assert_eq!(queue_size(0), None);
+        assert_eq!(queue_size(1), Some(1));
+        assert_eq!(queue_size(12), Some(8));
+        assert_eq!(queue_size(256), Some(MAX_QUEUE_SIZE));
+        assert!(DEVICE_RING + 6 + 8 * usize::from(MAX_QUEUE_SIZE) <= PAGE_SIZE);
+        assert!(DRIVER_RING + 6 + 2 * usize::from(MAX_QUEUE_SIZE) <= DEVICE_RING);
+    }
+
+    #[test_case]
+    fn chunks_fill_the_buffer_first() {
+        let lengths = |first| chunks(&[0; 10], first, 4).map(<[u8]>::len);
+        assert!(lengths(3).eq([3, 4, 3]));
+        assert!(lengths(0).eq([4, 4, 2]));
+        assert!(lengths(12).eq([10]));
+        assert!(chunks(&[], 3, 4).next().is_none());
+    }
+}
@@ -0,0 +1,236 @@
+//! Bounded lock-free multi-producer single-consumer queue.
+//!
+//! The queue is a ring of slots tagged with sequence numbers (Vyukov's bounded
+//! queue). Producers claim a slot with a single compare-and-swap and never
+//! wait on each other, which makes pushing safe from interrupt handlers. It
+//! doesn't allocate, so it can live in a `static`.
+#![cfg_attr(not(test), no_std)]
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Pads and aligns a value to a cache line to avoid false sharing.
+#[repr(align(64))]
+struct CachePadded<T>(T);
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+struct Slot<T> {
+    /// Equal to the position the slot is ready to be written at, or that
+    /// position plus one once it holds a value.
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+pub struct Queue<T, const N: usize> {
+    slots: [CachePadded<Slot<T>>; N],
+    /// Next position to push to.
+    head: CachePadded<AtomicUsize>,
+    /// Next position to pop from. Only modified by the consumer.
+    tail: CachePadded<AtomicUsize>,
+}
+
+// SAFETY: Values are moved between threads through the queue, which requires
+// `T: Send`. Slots are only accessed by the thread that claimed them.
+unsafe impl<T: Send, const N: usize> Sync for Queue<T, N> {}
+unsafe impl<T: Send, const N: usize> Send for Queue<T, N> {}
+
+impl<T, const N: usize> Default for Queue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Queue<T, N> {
+    const VALID_CAPACITY: () = assert!(N.is_power_of_two(), "Capacity must be a power of two");
+
+    pub const fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID_CAPACITY;
+        let mut slots: [CachePadded<Slot<T>>; N] = [const {
+            CachePadded(Slot {
+                sequence: AtomicUsize::new(0),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+        }; N];
+        let mut i = 0;
+        while i < N {
+            slots[i].0.sequence = AtomicUsize::new(i);
+            i += 1;
+        }
+        Self {
+            slots,
+            head: CachePadded(AtomicUsize::new(0)),
+            tail: CachePadded(AtomicUsize::new(0)),
+        }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Pushes `value` into the queue, returning it back if the queue is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let mut position = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[position % N];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            match sequence.wrapping_sub(position) as isize {
+                0 => match self.head.compare_exchange_weak(
+                    position,
+                    position.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        // SAFETY: We claimed the slot and the consumer won't
+                        // read it until we publish the new sequence.
+                        unsafe { (*slot.value.get()).write(value) };
+                        slot.sequence
+                            .store(position.wrapping_add(1), Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => position = current,
+                },
+                // The slot still holds the value from the previous lap.
+                diff if diff < 0 => return Err(value),
+                // Another producer claimed the slot, catch up.
+                _ => position = self.head.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    /// Pops the oldest value in the queue.
+    ///
+    /// # Safety
+    ///
+    /// There must be a single consumer: this must not be called concurrently
+    /// from more than one thread.
+    pub unsafe fn pop(&self) -> Option<T> {
+        let position = self.tail.load(Ordering::Relaxed);
+        let slot = &self.slots[position % N];
+        let sequence = slot.sequence.load(Ordering::Acquire);
+        if sequence != position.wrapping_add(1) {
+            return None;
+        }
+        // SAFETY: The producer published the value and we are the only consumer.
+        let value = unsafe { (*slot.value.get()).assume_init_read() };
+        // Make the slot available to producers on the next lap.
+        slot.sequence
+            .store(position.wrapping_add(N), Ordering::Release);
+        self.tail.store(position.wrapping_add(1), Ordering::Relaxed);
+        Some(value)
+    }
+
+    /// Pops the oldest value in the queue.
+    pub fn pop_mut(&mut self) -> Option<T> {
+        // SAFETY: The mutable reference guarantees we are the only consumer.
+        unsafe { self.pop() }
+    }
+
+    /// Returns whether the queue was empty at the time of the call.
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed) == self.tail.load(Ordering::Relaxed)
+    }
+}
+
+impl<T, const N: usize> Drop for Queue<T, N> {
+    fn drop(&mut self) {
+        while self.pop_mut().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU64;
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn fifo() {
+        let mut queue = Queue::<u32, 4>::new();
+        assert_eq!(queue.pop_mut(), None);
+        for i in 0..4 {
+            queue.push(i).unwrap();
+        }
+        assert_eq!(queue.push(4), Err(4));
+        assert_eq!(queue.pop_mut(), Some(0));
+        queue.push(4).unwrap();
+        for i in 1..5 {
+            assert_eq!(queue.pop_mut(), Some(i));
+        }
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn drops_remaining_values() {
+        let value = Arc::new(());
+        let queue = Queue::<_, 8>::new();
+        for _ in 0..5 {
+            queue.push(value.clone()).unwrap();
+        }
+        drop(queue);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    /// Yields at pseudo-random points to shake out different interleavings.
+    fn maybe_yield(state: &mut u64) {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        if *state % 4 == 0 {
+            std::thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn concurrent_producers() {
+        const PRODUCERS: u64 = 8;
+        const ITEMS: u64 = 20_000;
+        let queue = Queue::<(u64, u64), 64>::new();
+        let seed = AtomicU64::new(0x9E37_79B9_7F4A_7C15);
+
+        std::thread::scope(|s| {
+            for producer in 0..PRODUCERS {
+                let queue = &queue;
+                let mut state = seed.fetch_add(producer * 0x1234_5678 + 1, Ordering::Relaxed);
+                s.spawn(move || {
+                    for item in 0..ITEMS {
+                        let mut value = (producer, item);
+                        while let Err(back) = queue.push(value) {
+                            value = back;
+                            std::thread::yield_now();
+                        }
+                        maybe_yield(&mut state);
+                    }
+                });
+            }
+
+            let mut next = [0; PRODUCERS as usize];
+            let mut state = 0xDEAD_BEEF;
+            let mut received = 0;
+            while received < PRODUCERS * ITEMS {
+                // SAFETY: This is the only consumer.
+                match unsafe { queue.pop() } {
+                    Some((producer, item)) => {
+                        // Values from each producer arrive in order.
+                        assert_eq!(next[producer as usize], item);
+                        next[producer as usize] += 1;
+                        received += 1;
+                    }
+                    None => maybe_yield(&mut state),
+                }
+            }
+        });
+        assert!(queue.is_empty());
+    }
+}
@@ -1,8 +1,15 @@
 //! A lock-free implementaiton of a Send/Sync Cell.
 
+use core::any;
 use core::cell::UnsafeCell;
 use core::sync::atomic::{AtomicBool, Ordering};
 
+use crate::lockdep;
+
+// SAFETY: Access to the value is serialized by the spin lock.
+unsafe impl<T: Send> Send for AtomicCell<T> {}
+unsafe impl<T: Send> Sync for AtomicCell<T> {}
+
 #[derive(Debug)]
 pub struct AtomicCell<T> {
     value: UnsafeCell<T>,
@@ -23,21 +30,27 @@ impl<T> AtomicCell<T> {
         }
     }
 
+    #[track_caller]
     pub fn set(&self, value: T) {
         self.spin_lock(|inner| {
             *inner = value;
         });
     }
 
+    #[track_caller]
     pub fn replace(&self, value: T) -> T {
         self.spin_lock(|inner| core::mem::replace(inner, value))
     }
 
     #[inline(always)]
+    #[track_caller]
     fn spin_lock<U, F: FnOnce(&mut T) -> U>(&self, fun: F) -> U {
+        let id = &self.locked as *const AtomicBool as usize;
+        lockdep::acquire(id, any::type_name::<T>());
         self.lock();
         let out = fun(unsafe { &mut *self.value.get() });
         self.unlock();
+        lockdep::release(id);
         out
     }
 
@@ -56,12 +69,14 @@ impl<T> AtomicCell<T> {
 }
 
 impl<T: Copy> AtomicCell<T> {
+    #[track_caller]
     pub fn get(&self) -> T {
         self.spin_lock(|inner| *inner)
     }
 }
 
 impl<T: Clone> AtomicCell<T> {
+    #[track_caller]
     pub fn get_cloned(&self) -> T {
         self.spin_lock(|inner| inner.clone())
     }
@@ -1,7 +1,7 @@
 use core::cell::Cell;
 use core::ops::{Deref, DerefMut};
 
-use super::AtomicOnceCell;
+use super::{AtomicOnceCell, OnceError};
 
 pub struct AtomicLazyCell<T, F = fn() -> T> {
     inner: AtomicOnceCell<T>,
@@ -22,27 +22,31 @@ impl<T, F: FnOnce() -> T> AtomicLazyCell<T, F> {
         }
     }
 
-    pub fn get(&self) -> &T {
-        let _ = self.inner.set_with(|| match self.fun.take() {
+    /// Returns the value, running the initializer if needed.
+    ///
+    /// Fails if the initializer panicked or if it (or an interrupt handler)
+    /// tries to access the cell while it's being initialized.
+    #[track_caller]
+    pub fn try_get(&self) -> Result<&T, OnceError> {
+        self.inner.get_or_init_with(|| match self.fun.take() {
             Some(fun) => fun(),
-            None => panic!("Lazy instance has previously been poisoned"),
-        });
-        loop {
-            if let Some(value) = self.inner.get() {
-                break value;
-            }
+            None => unreachable!("Lazy initializer ran twice"),
+        })
+    }
+
+    #[track_caller]
+    pub fn get(&self) -> &T {
+        match self.try_get() {
+            Ok(value) => value,
+            Err(e) => panic!("Lazy instance couldn't be initialized: {e:?}"),
         }
     }
 
     pub fn get_mut(&mut self) -> &mut T {
-        let _ = self.inner.set_with(|| match self.fun.take() {
-            Some(fun) => fun(),
-            None => panic!("Lazy instance has previously been poisoned"),
-        });
+        self.get();
         match self.inner.get_mut() {
             Some(value) => value,
-            // Still initializing
-            None => panic!("Lazy instance should have been initialized"),
+            None => unreachable!("Lazy instance should have been initialized"),
         }
     }
 }
@@ -86,4 +90,22 @@ mod tests {
             }
         });
     }
+
+    #[test]
+    fn detects_cycles() {
+        static CELL: AtomicLazyCell<u32> = AtomicLazyCell::new(|| {
+            assert_eq!(CELL.try_get(), Err(OnceError::Cycle));
+            7
+        });
+        crate::context::use_thread_ids();
+        assert_eq!(CELL.try_get(), Ok(&7));
+    }
+
+    #[test]
+    fn poisoned() {
+        let cell: AtomicLazyCell<u32> = AtomicLazyCell::new(|| panic!("Initializer failed"));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| cell.get()));
+        assert!(result.is_err());
+        assert_eq!(cell.try_get(), Err(OnceError::Poisoned));
+    }
 }
@@ -1,10 +1,20 @@
+use core::any;
 use core::cell::UnsafeCell;
 use core::mem::MaybeUninit;
-use core::sync::atomic::{fence, AtomicU8, Ordering};
+use core::sync::atomic::{fence, AtomicU8, AtomicUsize, Ordering};
+
+use crate::{context, lockdep};
+
+const UNINIT: u8 = 0;
+const INITIALIZING: u8 = 1;
+const INIT: u8 = 2;
+const POISONED: u8 = 3;
 
 pub struct AtomicOnceCell<T> {
     value: UnsafeCell<MaybeUninit<T>>,
     init: AtomicU8,
+    /// Context id (plus one) of the initializer, or 0 if unknown.
+    owner: AtomicUsize,
 }
 
 // Why do we need `T: Send`?
@@ -19,6 +29,12 @@ unsafe impl<T: Send> Send for AtomicOnceCell<T> {}
 pub enum OnceError {
     Initializing,
     AlreadyInit,
+    Uninit,
+    /// The initializer panicked.
+    Poisoned,
+    /// The cell was accessed from the context initializing it, e.g. the
+    /// initializer re-entered itself or was interrupted by code using the cell.
+    Cycle,
 }
 
 impl<T> Default for AtomicOnceCell<T> {
@@ -27,51 +43,112 @@ impl<T> Default for AtomicOnceCell<T> {
     }
 }
 
+/// Poisons the cell if dropped while initializing.
+struct PoisonGuard<'a>(&'a AtomicU8);
+
+impl Drop for PoisonGuard<'_> {
+    fn drop(&mut self) {
+        lockdep::release(self.0 as *const AtomicU8 as usize);
+        self.0.store(POISONED, Ordering::Release);
+    }
+}
+
 impl<T> AtomicOnceCell<T> {
     pub const fn new() -> Self {
         Self {
             value: UnsafeCell::new(MaybeUninit::uninit()),
-            init: AtomicU8::new(0),
+            init: AtomicU8::new(UNINIT),
+            owner: AtomicUsize::new(0),
         }
     }
 
+    #[track_caller]
     pub fn set_with<F>(&self, fun: F) -> Result<(), OnceError>
     where
         F: FnOnce() -> T,
     {
         match self
             .init
-            .compare_exchange(0, 1, Ordering::AcqRel, Ordering::Relaxed)
+            .compare_exchange(UNINIT, INITIALIZING, Ordering::AcqRel, Ordering::Acquire)
         {
-            // SAFETY: Okay to write while initializing because we only allow 1 reference to exist
-            Ok(_) => unsafe {
-                (*self.value.get()).write(fun());
-            },
-            Err(1) => return Err(OnceError::Initializing),
-            Err(2) => return Err(OnceError::AlreadyInit),
+            Ok(_) => {}
+            Err(INITIALIZING) => return Err(OnceError::Initializing),
+            Err(INIT) => return Err(OnceError::AlreadyInit),
+            Err(POISONED) => return Err(OnceError::Poisoned),
             Err(other) => panic!("Unknown initialization state: {other}"),
         }
-        self.init.store(2, Ordering::Release);
+        let owner = context::current().map_or(0, |id| id.wrapping_add(1));
+        self.owner.store(owner, Ordering::Relaxed);
+        let guard = PoisonGuard(&self.init);
+        // Others wait for the initializer, so it behaves like a lock.
+        lockdep::acquire(self.lock_id(), any::type_name::<T>());
+        // SAFETY: Okay to write while initializing because we only allow 1 reference to exist
+        unsafe {
+            (*self.value.get()).write(fun());
+        }
+        lockdep::release(self.lock_id());
+        core::mem::forget(guard);
+        self.init.store(INIT, Ordering::Release);
         Ok(())
     }
 
-    pub fn set(&self, value: T) -> Result<(), OnceError> {
-        self.set_with(|| value)
+    /// Returns the value, initializing it with `fun` if needed.
+    ///
+    /// Waits for the value if another context is initializing the cell.
+    #[track_caller]
+    pub fn get_or_init_with<F>(&self, fun: F) -> Result<&T, OnceError>
+    where
+        F: FnOnce() -> T,
+    {
+        match self.set_with(fun) {
+            Ok(()) | Err(OnceError::AlreadyInit) | Err(OnceError::Initializing) => {}
+            Err(e) => return Err(e),
+        }
+        if let Err(OnceError::Initializing) = self.try_get() {
+            lockdep::wait(self.lock_id(), any::type_name::<T>());
+        }
+        loop {
+            match self.try_get() {
+                Err(OnceError::Initializing) => core::hint::spin_loop(),
+                result => return result,
+            }
+        }
     }
 
-    pub fn get(&self) -> Option<&T> {
-        let is_init = self.init.load(Ordering::Acquire) == 2;
-        if is_init {
+    /// Returns the value or the reason it isn't available.
+    pub fn try_get(&self) -> Result<&T, OnceError> {
+        match self.init.load(Ordering::Acquire) {
             // SAFETY: The value has been initialized and from now on, we only provide
             // shared references
-            unsafe { Some(self.get_unchecked()) }
-        } else {
-            None
+            INIT => Ok(unsafe { self.get_unchecked() }),
+            UNINIT => Err(OnceError::Uninit),
+            INITIALIZING if self.is_initializing_here() => Err(OnceError::Cycle),
+            INITIALIZING => Err(OnceError::Initializing),
+            _ => Err(OnceError::Poisoned),
         }
     }
 
+    fn lock_id(&self) -> usize {
+        &self.init as *const AtomicU8 as usize
+    }
+
+    /// Whether the current context is the one running the initializer.
+    fn is_initializing_here(&self) -> bool {
+        context::current()
+            .is_some_and(|id| self.owner.load(Ordering::Relaxed) == id.wrapping_add(1))
+    }
+
+    #[track_caller]
+    pub fn set(&self, value: T) -> Result<(), OnceError> {
+        self.set_with(|| value)
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        self.try_get().ok()
+    }
+
     pub fn get_mut(&mut self) -> Option<&mut T> {
-        let is_init = self.init.load(Ordering::Acquire) == 2;
+        let is_init = self.init.load(Ordering::Acquire) == INIT;
         if is_init {
             // SAFETY: The value has been initialized and from now on, we only provide
             // shared references
@@ -111,6 +188,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::manual_range_contains)]
     fn multiple_init() {
         let cell = &AtomicOnceCell::new();
         std::thread::scope(|s| {
@@ -131,4 +209,38 @@ mod tests {
             }
         });
     }
+
+    #[test]
+    fn try_get_states() {
+        let cell = AtomicOnceCell::new();
+        assert_eq!(cell.try_get(), Err(OnceError::Uninit));
+        assert_eq!(cell.get_or_init_with(|| 3), Ok(&3));
+        assert_eq!(cell.get_or_init_with(|| 4), Ok(&3));
+        assert_eq!(cell.set(5), Err(OnceError::AlreadyInit));
+        assert_eq!(cell.try_get(), Ok(&3));
+    }
+
+    #[test]
+    fn poisoned_by_panicking_initializer() {
+        let cell = AtomicOnceCell::<u32>::new();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = cell.set_with(|| panic!("Initializer failed"));
+        }));
+        assert!(result.is_err());
+        assert_eq!(cell.try_get(), Err(OnceError::Poisoned));
+        assert_eq!(cell.get_or_init_with(|| 1), Err(OnceError::Poisoned));
+    }
+
+    #[test]
+    fn reentrant_initializer() {
+        context::use_thread_ids();
+        let cell = AtomicOnceCell::new();
+        // Behaves like an interrupt handler touching the cell while it's initialized.
+        let value = cell.get_or_init_with(|| {
+            assert_eq!(cell.try_get(), Err(OnceError::Cycle));
+            assert_eq!(cell.get_or_init_with(|| 2), Err(OnceError::Cycle));
+            1
+        });
+        assert_eq!(value, Ok(&1));
+    }
 }
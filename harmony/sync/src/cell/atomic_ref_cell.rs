@@ -1,10 +1,13 @@
 //! A lock-free implementaiton of a Send/Sync RefCell.
 
+use core::any;
 use core::cell::UnsafeCell;
 use core::ops::{Deref, DerefMut};
 use core::ptr::NonNull;
 use core::sync::atomic::{AtomicU8, Ordering};
 
+use crate::lockdep;
+
 unsafe impl<T: Send> Send for AtomicRefCell<T> {}
 // FIXME: Only doing exclusive borrows for now.
 // TODO: Add Send bound on T when non-exclusive borrows
@@ -40,8 +43,13 @@ impl State {
     }
 
     pub fn drop_borrow(&self) {
+        lockdep::release(self.lock_id());
         assert_eq!(self.0.swap(Self::FREE, Ordering::AcqRel), Self::BORROWED);
     }
+
+    fn lock_id(&self) -> usize {
+        self as *const Self as usize
+    }
 }
 
 #[derive(Debug)]
@@ -63,22 +71,28 @@ impl<T> AtomicRefCell<T> {
         }
     }
 
+    #[track_caller]
     pub fn borrow(&self) -> Result<Ref<T>, BorrowError> {
         self.state.try_borrow()?;
+        let borrow = BorrowRef(&self.state);
+        lockdep::acquire(self.state.lock_id(), any::type_name::<T>());
         unsafe {
             Ok(Ref {
                 value: NonNull::new_unchecked(self.data.get()),
-                borrow: BorrowRef(&self.state),
+                borrow,
             })
         }
     }
 
+    #[track_caller]
     pub fn borrow_mut(&self) -> Result<RefMut<T>, BorrowError> {
         self.state.try_borrow()?;
+        let borrow = BorrowRefMut(&self.state);
+        lockdep::acquire(self.state.lock_id(), any::type_name::<T>());
         unsafe {
             Ok(RefMut {
                 value: NonNull::new_unchecked(self.data.get()),
-                borrow: BorrowRefMut(&self.state),
+                borrow,
             })
         }
     }
@@ -160,6 +174,29 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn detects_inconsistent_order() {
+        static FIRST: AtomicRefCell<u8> = AtomicRefCell::new(0);
+        static SECOND: AtomicRefCell<u16> = AtomicRefCell::new(0);
+        crate::context::use_thread_ids();
+        {
+            let _first = FIRST.borrow_mut().unwrap();
+            let _second = SECOND.borrow_mut().unwrap();
+        }
+        let result = std::panic::catch_unwind(|| {
+            let _second = SECOND.borrow_mut().unwrap();
+            let _first = FIRST.borrow_mut().unwrap();
+        });
+        let message = result.unwrap_err();
+        let message = message.downcast_ref::<String>().unwrap();
+        assert!(
+            message.contains("u8") && message.contains("u16"),
+            "{message}"
+        );
+        // Both borrows were released while unwinding.
+        assert!(FIRST.borrow().is_ok() && SECOND.borrow().is_ok());
+    }
+
     #[test]
     fn threaded_mutability() {
         let cell = AtomicRefCell::new(0);
@@ -0,0 +1,36 @@
+//! Identification of the execution context running the code.
+//!
+//! The cells use this to tell a concurrent initialization apart from one that
+//! re-entered itself. Two pieces of code that report the same context id must
+//! never run concurrently, so one must be nested in the other (e.g. an
+//! interrupt handler on the same core).
+
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+static CONTEXT_ID: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Installs the function returning the id of the current context.
+pub fn set_context_id(fun: fn() -> usize) {
+    CONTEXT_ID.store(fun as *mut (), Ordering::Release);
+}
+
+/// Returns the id of the current context, if a context id function was installed.
+pub(crate) fn current() -> Option<usize> {
+    let fun = CONTEXT_ID.load(Ordering::Acquire);
+    if fun.is_null() {
+        return None;
+    }
+    // SAFETY: The pointer was created from a `fn() -> usize` in `set_context_id`.
+    let fun: fn() -> usize = unsafe { core::mem::transmute(fun) };
+    Some(fun())
+}
+
+/// Uses the address of a thread local as the context id so tests can detect re-entrance.
+#[cfg(test)]
+pub(crate) fn use_thread_ids() {
+    std::thread_local! {
+        static ID: u8 = const { 0 };
+    }
+    set_context_id(|| ID.with(|id| id as *const u8 as usize));
+}
@@ -0,0 +1,283 @@
+//! Lock ordering checks for debug builds.
+//!
+//! Every context (see [`crate::context`]) keeps a list of the locks it holds.
+//! Acquiring a lock while holding others records that those locks come before
+//! it. If a lock is ever acquired while holding one that, directly or through
+//! other locks, was recorded as coming after it, two contexts can deadlock by
+//! taking them in opposite orders, so we panic naming both locks and where
+//! they were taken.
+//!
+//! The registry has a fixed size and is updated under a try-lock, so checks
+//! are best effort: locks are skipped while another context is updating the
+//! registry, when the context id isn't known or once the tables fill up. In
+//! release builds every function here does nothing.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::context;
+
+/// Contexts that can hold locks at the same time.
+const MAX_CONTEXTS: usize = 16;
+/// Locks a single context can hold at the same time.
+const MAX_HELD: usize = 8;
+/// Distinct "held before" relations between locks.
+const MAX_EDGES: usize = 128;
+
+#[derive(Copy, Clone)]
+struct Acquired {
+    lock: usize,
+    name: &'static str,
+    site: &'static Location<'static>,
+}
+
+impl fmt::Display for Acquired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:#X}) at {}", self.name, self.lock, self.site)
+    }
+}
+
+/// `before` was held when `after` was acquired.
+#[derive(Copy, Clone)]
+struct Edge {
+    before: Acquired,
+    after: Acquired,
+}
+
+struct Held {
+    /// Context id plus one, or 0 if the entry is free.
+    owner: usize,
+    locks: [Option<Acquired>; MAX_HELD],
+}
+
+struct Registry {
+    contexts: [Held; MAX_CONTEXTS],
+    edges: [Option<Edge>; MAX_EDGES],
+}
+
+struct Global {
+    busy: AtomicBool,
+    registry: UnsafeCell<Registry>,
+}
+
+// SAFETY: The registry is only accessed while `busy` is held.
+unsafe impl Sync for Global {}
+
+static GLOBAL: Global = Global {
+    busy: AtomicBool::new(false),
+    registry: UnsafeCell::new(Registry {
+        contexts: [const {
+            Held {
+                owner: 0,
+                locks: [None; MAX_HELD],
+            }
+        }; MAX_CONTEXTS],
+        edges: [None; MAX_EDGES],
+    }),
+};
+
+/// Runs `fun` on the registry unless another context is using it.
+fn with_registry<U>(fun: impl FnOnce(&mut Registry) -> U) -> Option<U> {
+    GLOBAL
+        .busy
+        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .ok()?;
+    // SAFETY: We hold `busy`.
+    let out = fun(unsafe { &mut *GLOBAL.registry.get() });
+    GLOBAL.busy.store(false, Ordering::Release);
+    Some(out)
+}
+
+impl Registry {
+    fn held(&mut self, owner: usize) -> Option<&mut Held> {
+        let index = self
+            .contexts
+            .iter()
+            .position(|held| held.owner == owner)
+            .or_else(|| self.contexts.iter().position(|held| held.owner == 0))?;
+        let held = &mut self.contexts[index];
+        held.owner = owner;
+        Some(held)
+    }
+
+    /// Finds the relation showing that `from` comes before `to`, if any.
+    fn path(&self, from: usize, to: usize) -> Option<Edge> {
+        // Locks known to come after `from`, found breadth first.
+        let mut reached = [0; MAX_EDGES];
+        let mut count = 0;
+        let mut next = 0;
+        let mut current = from;
+        loop {
+            for edge in self.edges.iter().flatten() {
+                if edge.before.lock != current || reached[..count].contains(&edge.after.lock) {
+                    continue;
+                }
+                if edge.after.lock == to {
+                    return Some(*edge);
+                }
+                if count < MAX_EDGES {
+                    reached[count] = edge.after.lock;
+                    count += 1;
+                }
+            }
+            current = *reached[..count].get(next)?;
+            next += 1;
+        }
+    }
+
+    fn record(&mut self, before: Acquired, after: Acquired) {
+        let known = self
+            .edges
+            .iter()
+            .flatten()
+            .any(|edge| edge.before.lock == before.lock && edge.after.lock == after.lock);
+        if known {
+            return;
+        }
+        if let Some(free) = self.edges.iter_mut().find(|edge| edge.is_none()) {
+            *free = Some(Edge { before, after });
+        }
+    }
+}
+
+/// Records that the current context is acquiring `lock`.
+///
+/// Call it right before waiting for the lock, so that an ordering problem is
+/// reported instead of deadlocking. `lock` identifies the lock, usually by its
+/// address. Recorded orders are never forgotten, so a lock reusing the id of
+/// one that was dropped inherits its order.
+///
+/// # Panics
+///
+/// If `lock` was previously seen ordered before one of the locks held by the
+/// current context.
+#[track_caller]
+pub fn acquire(lock: usize, name: &'static str) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    let Some(id) = context::current() else {
+        return;
+    };
+    let acquiring = Acquired {
+        lock,
+        name,
+        site: Location::caller(),
+    };
+    let owner = id.wrapping_add(1);
+    let conflict = with_registry(|registry| {
+        let locks = match registry.contexts.iter().find(|held| held.owner == owner) {
+            Some(held) => held.locks,
+            None => [None; MAX_HELD],
+        };
+        let others = || {
+            locks
+                .iter()
+                .flatten()
+                .filter(|holding| holding.lock != lock)
+        };
+        for holding in others() {
+            if let Some(edge) = registry.path(lock, holding.lock) {
+                return Some((*holding, edge));
+            }
+        }
+        for holding in others() {
+            registry.record(*holding, acquiring);
+        }
+        let free = registry
+            .held(owner)
+            .and_then(|held| held.locks.iter_mut().find(|lock| lock.is_none()));
+        if let Some(free) = free {
+            *free = Some(acquiring);
+        }
+        None
+    })
+    .flatten();
+    if let Some((holding, edge)) = conflict {
+        panic!(
+            "Inconsistent lock order: acquiring {acquiring} while holding {holding}, but {} \
+             was held when acquiring {}",
+            edge.before, edge.after
+        );
+    }
+}
+
+/// Records that the current context released `lock`.
+///
+/// Locks don't need to be released in the order they were acquired.
+pub fn release(lock: usize) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    let Some(id) = context::current() else {
+        return;
+    };
+    with_registry(|registry| {
+        let owner = id.wrapping_add(1);
+        let Some(held) = registry
+            .contexts
+            .iter_mut()
+            .find(|held| held.owner == owner)
+        else {
+            return;
+        };
+        if let Some(entry) = held
+            .locks
+            .iter_mut()
+            .rfind(|entry| entry.is_some_and(|entry| entry.lock == lock))
+        {
+            *entry = None;
+        }
+        if held.locks.iter().all(Option::is_none) {
+            held.owner = 0;
+        }
+    });
+}
+
+/// Records waiting on `lock` without holding it afterwards.
+///
+/// Used for locks that are only held by whoever initializes something, when
+/// the current context waits for another one to finish.
+#[track_caller]
+pub fn wait(lock: usize, name: &'static str) {
+    acquire(lock, name);
+    release(lock);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(locks: &[usize]) {
+        for &lock in locks {
+            acquire(lock, "test lock");
+        }
+        for &lock in locks.iter().rev() {
+            release(lock);
+        }
+    }
+
+    #[test]
+    fn consistent_order() {
+        context::use_thread_ids();
+        order(&[0x100, 0x200, 0x300]);
+        order(&[0x100, 0x300]);
+        order(&[0x200, 0x300]);
+    }
+
+    #[test]
+    fn detects_inversions() {
+        context::use_thread_ids();
+        order(&[0x1100, 0x1200]);
+        order(&[0x1200, 0x1300]);
+        let result = std::panic::catch_unwind(|| order(&[0x1300, 0x1100]));
+        release(0x1300);
+        let message = result.unwrap_err();
+        let message = message.downcast_ref::<String>().unwrap();
+        assert!(message.contains("0x1100"), "{message}");
+        assert!(message.contains("0x1300"), "{message}");
+        assert!(message.contains(file!()), "{message}");
+    }
+}
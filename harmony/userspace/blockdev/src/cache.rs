@@ -0,0 +1,357 @@
+//! A write-back LRU cache of blocks.
+//!
+//! The cache doesn't allocate. Instead, the buffers holding the cached blocks
+//! are provided by the owner of the cache, usually frames retyped and mapped
+//! by the component running it.
+
+use crate::{Block, BlockDevice};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CacheError<E> {
+    Device(E),
+    OutOfRange,
+}
+
+impl<E> From<E> for CacheError<E> {
+    fn from(value: E) -> Self {
+        Self::Device(value)
+    }
+}
+
+/// Cache usage statistics.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Blocks loaded ahead of being requested.
+    pub prefetched: u64,
+    pub evictions: u64,
+    /// Dirty blocks written back to the device.
+    pub writebacks: u64,
+    /// Blocks currently dirty in the cache.
+    pub dirty: u64,
+}
+
+impl Stats {
+    /// Hit rate in parts per thousand.
+    pub fn hit_rate(&self) -> u64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0;
+        }
+        self.hits * 1000 / total
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+struct Entry {
+    block: Option<u64>,
+    dirty: bool,
+    last_used: u64,
+}
+
+pub struct BlockCache<'a, D: BlockDevice, const N: usize> {
+    device: D,
+    buffers: &'a mut [Block; N],
+    entries: [Entry; N],
+    clock: u64,
+    last_read: Option<u64>,
+    read_ahead: u64,
+    stats: Stats,
+}
+
+impl<'a, D: BlockDevice, const N: usize> BlockCache<'a, D, N> {
+    /// Number of blocks loaded ahead on sequential reads by default.
+    pub const DEFAULT_READ_AHEAD: u64 = 2;
+
+    pub fn new(device: D, buffers: &'a mut [Block; N]) -> Self {
+        Self {
+            device,
+            buffers,
+            entries: [Entry::default(); N],
+            clock: 0,
+            last_read: None,
+            read_ahead: Self::DEFAULT_READ_AHEAD,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Sets how many blocks are loaded ahead when sequential reads are detected.
+    pub fn set_read_ahead(&mut self, blocks: u64) {
+        self.read_ahead = blocks;
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    /// Flushes the cache and returns the underlying device.
+    pub fn into_device(mut self) -> Result<D, CacheError<D::Error>> {
+        self.flush()?;
+        Ok(self.device)
+    }
+
+    /// Returns the contents of `index`, loading it from the device if needed.
+    pub fn read(&mut self, index: u64) -> Result<&Block, CacheError<D::Error>> {
+        let sequential = self.last_read.is_some_and(|last| last + 1 == index);
+        self.last_read = Some(index);
+        let slot = self.lookup(index)?;
+        if sequential {
+            // Never prefetch enough blocks to evict the one being read.
+            let window = self.read_ahead.min(N as u64 - 1);
+            let end = (index + window).min(self.device.block_count() - 1);
+            for ahead in index + 1..=end {
+                if self.find(ahead).is_none() {
+                    self.load(ahead)?;
+                    self.stats.prefetched += 1;
+                }
+            }
+        }
+        Ok(&self.buffers[slot])
+    }
+
+    /// Replaces the contents of `index`.
+    ///
+    /// The block is only written to the device when evicted or flushed.
+    pub fn write(&mut self, index: u64, data: &Block) -> Result<(), CacheError<D::Error>> {
+        self.modify(index, |block| block.copy_from_slice(data))
+    }
+
+    /// Modifies the contents of `index` in place.
+    pub fn modify<F: FnOnce(&mut Block)>(
+        &mut self,
+        index: u64,
+        fun: F,
+    ) -> Result<(), CacheError<D::Error>> {
+        let slot = self.lookup(index)?;
+        fun(&mut self.buffers[slot]);
+        if !self.entries[slot].dirty {
+            self.entries[slot].dirty = true;
+            self.stats.dirty += 1;
+        }
+        Ok(())
+    }
+
+    /// Writes back every dirty block and issues a flush to the device.
+    ///
+    /// This acts as a barrier: once it returns, every write that happened
+    /// before is durable.
+    pub fn flush(&mut self) -> Result<(), CacheError<D::Error>> {
+        // Write back in block order to keep the device access sequential.
+        while let Some(slot) = (0..N)
+            .filter(|slot| self.entries[*slot].dirty)
+            .min_by_key(|slot| self.entries[*slot].block)
+        {
+            self.write_back(slot)?;
+        }
+        self.device.flush()?;
+        Ok(())
+    }
+
+    fn find(&self, index: u64) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.block == Some(index))
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Returns the slot holding `index`, loading it if needed.
+    fn lookup(&mut self, index: u64) -> Result<usize, CacheError<D::Error>> {
+        if index >= self.device.block_count() {
+            return Err(CacheError::OutOfRange);
+        }
+        let slot = match self.find(index) {
+            Some(slot) => {
+                self.stats.hits += 1;
+                slot
+            }
+            None => {
+                self.stats.misses += 1;
+                self.load(index)?
+            }
+        };
+        self.entries[slot].last_used = self.tick();
+        Ok(slot)
+    }
+
+    fn load(&mut self, index: u64) -> Result<usize, CacheError<D::Error>> {
+        let slot = self.evict()?;
+        self.device.read_block(index, &mut self.buffers[slot])?;
+        self.entries[slot] = Entry {
+            block: Some(index),
+            dirty: false,
+            last_used: self.tick(),
+        };
+        Ok(slot)
+    }
+
+    /// Frees the least recently used slot.
+    fn evict(&mut self) -> Result<usize, CacheError<D::Error>> {
+        let slot = (0..N)
+            .min_by_key(|slot| {
+                let entry = &self.entries[*slot];
+                (entry.block.is_some(), entry.last_used)
+            })
+            .expect("Block cache must have at least one buffer");
+        if self.entries[slot].block.is_some() {
+            self.write_back(slot)?;
+            self.stats.evictions += 1;
+        }
+        self.entries[slot].block = None;
+        Ok(slot)
+    }
+
+    fn write_back(&mut self, slot: usize) -> Result<(), CacheError<D::Error>> {
+        let entry = &mut self.entries[slot];
+        if let (Some(block), true) = (entry.block, entry.dirty) {
+            self.device.write_block(block, &self.buffers[slot])?;
+            entry.dirty = false;
+            self.stats.dirty -= 1;
+            self.stats.writebacks += 1;
+        }
+        Ok(())
+    }
+}
+
+impl<D: BlockDevice, const N: usize> BlockDevice for BlockCache<'_, D, N> {
+    type Error = CacheError<D::Error>;
+
+    fn block_count(&self) -> u64 {
+        self.device.block_count()
+    }
+
+    fn read_block(&mut self, index: u64, buf: &mut Block) -> Result<(), Self::Error> {
+        buf.copy_from_slice(self.read(index)?);
+        Ok(())
+    }
+
+    fn write_block(&mut self, index: u64, buf: &Block) -> Result<(), Self::Error> {
+        self.write(index, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        BlockCache::flush(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RamDisk, BLOCK_SIZE};
+
+    /// Records the order of operations performed on a ram disk.
+    struct Recorder<'a> {
+        disk: RamDisk<'a>,
+        log: Vec<(&'static str, u64)>,
+    }
+
+    impl BlockDevice for Recorder<'_> {
+        type Error = crate::OutOfRange;
+
+        fn block_count(&self) -> u64 {
+            self.disk.block_count()
+        }
+
+        fn read_block(&mut self, index: u64, buf: &mut Block) -> Result<(), Self::Error> {
+            self.log.push(("read", index));
+            self.disk.read_block(index, buf)
+        }
+
+        fn write_block(&mut self, index: u64, buf: &Block) -> Result<(), Self::Error> {
+            self.log.push(("write", index));
+            self.disk.write_block(index, buf)
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            self.log.push(("flush", 0));
+            Ok(())
+        }
+    }
+
+    fn disk(blocks: usize) -> Vec<Block> {
+        (0..blocks).map(|i| [i as u8; BLOCK_SIZE]).collect()
+    }
+
+    #[test]
+    fn hits_and_misses() {
+        let mut blocks = disk(8);
+        let mut buffers = [[0; BLOCK_SIZE]; 2];
+        let mut cache = BlockCache::new(RamDisk::new(&mut blocks), &mut buffers);
+        cache.set_read_ahead(0);
+        assert_eq!(cache.read(3).unwrap()[0], 3);
+        assert_eq!(cache.read(3).unwrap()[0], 3);
+        assert_eq!(cache.read(5).unwrap()[0], 5);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+        assert_eq!(stats.hit_rate(), 333);
+        assert_eq!(cache.read(8), Err(CacheError::OutOfRange));
+    }
+
+    #[test]
+    fn evicts_least_recently_used_and_writes_back() {
+        let mut blocks = disk(8);
+        let mut buffers = [[0; BLOCK_SIZE]; 2];
+        let recorder = Recorder {
+            disk: RamDisk::new(&mut blocks),
+            log: Vec::new(),
+        };
+        let mut cache = BlockCache::new(recorder, &mut buffers);
+        cache.set_read_ahead(0);
+        cache.write(1, &[0xAA; BLOCK_SIZE]).unwrap();
+        cache.read(4).unwrap();
+        cache.read(1).unwrap();
+        assert_eq!(cache.stats().dirty, 1);
+        // Block 4 is the least recently used one.
+        cache.read(6).unwrap();
+        assert_eq!(cache.stats().evictions, 1);
+        assert_eq!(cache.stats().writebacks, 0);
+        // Block 1 is now the least recently used and is dirty.
+        cache.read(7).unwrap();
+        assert_eq!(cache.stats().writebacks, 1);
+        assert_eq!(cache.stats().dirty, 0);
+        let recorder = cache.into_device().unwrap();
+        assert_eq!(recorder.disk.blocks()[1], [0xAA; BLOCK_SIZE]);
+    }
+
+    #[test]
+    fn flush_writes_dirty_blocks_in_order_before_flushing() {
+        let mut blocks = disk(8);
+        let mut buffers = [[0; BLOCK_SIZE]; 4];
+        let recorder = Recorder {
+            disk: RamDisk::new(&mut blocks),
+            log: Vec::new(),
+        };
+        let mut cache = BlockCache::new(recorder, &mut buffers);
+        cache.write(5, &[1; BLOCK_SIZE]).unwrap();
+        cache.write(2, &[2; BLOCK_SIZE]).unwrap();
+        cache.flush().unwrap();
+        let log = &cache.device().log;
+        assert_eq!(
+            &log[log.len() - 3..],
+            &[("write", 2), ("write", 5), ("flush", 0)]
+        );
+        assert_eq!(cache.stats().dirty, 0);
+    }
+
+    #[test]
+    fn sequential_reads_prefetch() {
+        let mut blocks = disk(8);
+        let mut buffers = [[0; BLOCK_SIZE]; 4];
+        let mut cache = BlockCache::new(RamDisk::new(&mut blocks), &mut buffers);
+        cache.read(0).unwrap();
+        cache.read(1).unwrap();
+        assert_eq!(cache.stats().prefetched, 2);
+        cache.read(2).unwrap();
+        cache.read(3).unwrap();
+        assert_eq!(cache.stats().misses, 2);
+        assert_eq!(cache.stats().hits, 2);
+    }
+}
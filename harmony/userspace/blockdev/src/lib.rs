@@ -0,0 +1,81 @@
+//! Block device interface shared by storage components.
+#![cfg_attr(not(test), no_std)]
+#![feature(slice_as_chunks)]
+
+pub mod cache;
+pub use cache::BlockCache;
+
+/// Size of a block in bytes.
+pub const BLOCK_SIZE: usize = 1024;
+
+pub type Block = [u8; BLOCK_SIZE];
+
+/// A device addressable in [`BLOCK_SIZE`] blocks.
+pub trait BlockDevice {
+    type Error: core::fmt::Debug;
+
+    /// Number of blocks in the device.
+    fn block_count(&self) -> u64;
+
+    fn read_block(&mut self, index: u64, buf: &mut Block) -> Result<(), Self::Error>;
+
+    fn write_block(&mut self, index: u64, buf: &Block) -> Result<(), Self::Error>;
+
+    /// Makes every write that completed before this call durable.
+    fn flush(&mut self) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OutOfRange;
+
+/// A block device backed by memory.
+#[derive(Debug)]
+pub struct RamDisk<'a> {
+    blocks: &'a mut [Block],
+}
+
+impl<'a> RamDisk<'a> {
+    pub fn new(blocks: &'a mut [Block]) -> Self {
+        Self { blocks }
+    }
+
+    /// Builds a ram disk out of raw bytes, ignoring any trailing partial block.
+    pub fn from_bytes(bytes: &'a mut [u8]) -> Self {
+        let (blocks, _rest) = bytes.as_chunks_mut::<BLOCK_SIZE>();
+        Self { blocks }
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        self.blocks
+    }
+}
+
+impl BlockDevice for RamDisk<'_> {
+    type Error = OutOfRange;
+
+    fn block_count(&self) -> u64 {
+        self.blocks.len() as u64
+    }
+
+    fn read_block(&mut self, index: u64, buf: &mut Block) -> Result<(), Self::Error> {
+        let block = usize::try_from(index)
+            .ok()
+            .and_then(|index| self.blocks.get(index))
+            .ok_or(OutOfRange)?;
+        buf.copy_from_slice(block);
+        Ok(())
+    }
+
+    fn write_block(&mut self, index: u64, buf: &Block) -> Result<(), Self::Error> {
+        let block = usize::try_from(index)
+            .ok()
+            .and_then(|index| self.blocks.get_mut(index))
+            .ok_or(OutOfRange)?;
+        block.copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
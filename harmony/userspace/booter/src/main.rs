@@ -1,8 +1,12 @@
 #![no_std]
 #![no_main]
 
+use librs::kapi::endowment::{self, BOOT};
+use librs::kapi::ops::system::BOOT_SYSTEM_CAP;
 use librs::kapi::raw::raw_syscall;
 
+librs::entry!(main, stack = 40 * 1024);
+
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
@@ -11,8 +15,10 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
     loop {}
 }
 
-#[no_mangle]
-extern "C" fn _start() -> ! {
+fn main() -> ! {
+    if let Err(mismatch) = endowment::verify(BOOT_SYSTEM_CAP, &BOOT) {
+        panic!("Unexpected boot endowment: {mismatch}");
+    }
     let _result = unsafe { raw_syscall(1, 2, 3, 4, 5, 6) };
     loop {}
 }
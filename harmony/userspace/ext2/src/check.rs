@@ -0,0 +1,100 @@
+//! Consistency checks run on mount.
+//!
+//! These cover the metadata the driver relies on when allocating: if the
+//! bitmaps and the free counts disagree we could hand out blocks that are in
+//! use. They are not a replacement for a full `e2fsck`.
+
+use blockdev::BlockDevice;
+
+use crate::layout::STATE_VALID;
+use crate::{Error, Ext2, ROOT_INODE};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CheckError {
+    /// The file system wasn't cleanly unmounted.
+    NotClean,
+    /// The superblock geometry is invalid.
+    BadGeometry,
+    /// A group's bitmaps or inode table are out of range or not marked as used.
+    BadGroupLayout { group: u32 },
+    /// The block bitmap disagrees with the group's free block count.
+    FreeBlocks { group: u32 },
+    /// The inode bitmap disagrees with the group's free inode count.
+    FreeInodes { group: u32 },
+    /// The superblock free counts don't match the sum of the groups.
+    SuperblockCounts,
+    /// The root inode isn't a directory.
+    BadRoot,
+}
+
+fn is_set(bitmap: &[u8], bit: u32) -> bool {
+    bitmap[bit as usize / 8] & (1 << (bit % 8)) != 0
+}
+
+fn count_clear(bitmap: &[u8], limit: u32) -> u32 {
+    (0..limit).filter(|bit| !is_set(bitmap, *bit)).count() as u32
+}
+
+impl<D: BlockDevice> Ext2<D> {
+    /// Checks the file system metadata for consistency.
+    pub fn check(&mut self) -> Result<(), Error<D::Error>> {
+        let inconsistent = |error| Err(Error::Inconsistent(error));
+        let sb = self.superblock;
+        if sb.state & STATE_VALID == 0 {
+            return inconsistent(CheckError::NotClean);
+        }
+        if sb.blocks_per_group == 0
+            || sb.blocks_per_group as usize > blockdev::BLOCK_SIZE * 8
+            || sb.inodes_per_group == 0
+            || sb.inodes_per_group as usize > blockdev::BLOCK_SIZE * 8
+            || sb.blocks_count <= sb.first_data_block
+            || u64::from(sb.blocks_count) > self.device.block_count()
+            || sb.inodes_count > sb.group_count() * sb.inodes_per_group
+            || sb.inode_size < crate::layout::GOOD_OLD_INODE_SIZE
+            || !sb.inode_size.is_power_of_two()
+            || usize::from(sb.inode_size) > blockdev::BLOCK_SIZE
+        {
+            return inconsistent(CheckError::BadGeometry);
+        }
+
+        let (mut free_blocks, mut free_inodes) = (0, 0);
+        let inode_table_blocks =
+            (sb.inodes_per_group * u32::from(sb.inode_size)).div_ceil(blockdev::BLOCK_SIZE as u32);
+        for group in 0..sb.group_count() {
+            let desc = self.group_desc(group)?;
+            let start = sb.first_data_block + group * sb.blocks_per_group;
+            let limit = self.blocks_in_group(group);
+            let blocks = self.read_block(desc.block_bitmap.min(sb.blocks_count - 1))?;
+            let in_group = |block: u32| block >= start && block - start < limit;
+            let metadata = [desc.block_bitmap, desc.inode_bitmap]
+                .into_iter()
+                .chain((0..inode_table_blocks).map(|i| desc.inode_table + i));
+            for block in metadata {
+                if !in_group(block) || !is_set(&blocks, block - start) {
+                    return inconsistent(CheckError::BadGroupLayout { group });
+                }
+            }
+            if count_clear(&blocks, limit) != u32::from(desc.free_blocks_count) {
+                return inconsistent(CheckError::FreeBlocks { group });
+            }
+            let inodes = self.read_block(desc.inode_bitmap)?;
+            if count_clear(&inodes, sb.inodes_per_group) != u32::from(desc.free_inodes_count) {
+                return inconsistent(CheckError::FreeInodes { group });
+            }
+            free_blocks += u32::from(desc.free_blocks_count);
+            free_inodes += u32::from(desc.free_inodes_count);
+        }
+        if free_blocks != sb.free_blocks_count || free_inodes != sb.free_inodes_count {
+            return inconsistent(CheckError::SuperblockCounts);
+        }
+
+        let root = self.read_inode(ROOT_INODE)?;
+        if !root.is_dir()
+            || root.links_count < 2
+            || !matches!(self.lookup(ROOT_INODE, "."), Ok(ROOT_INODE))
+        {
+            return inconsistent(CheckError::BadRoot);
+        }
+        Ok(())
+    }
+}
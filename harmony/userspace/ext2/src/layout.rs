@@ -0,0 +1,249 @@
+//! On-disk structures.
+//!
+//! Only the fields used by the driver are decoded. Updates are written back
+//! over the original bytes so that fields we don't understand are preserved.
+
+pub const SUPERBLOCK_MAGIC: u16 = 0xEF53;
+
+/// The file system was cleanly unmounted.
+pub const STATE_VALID: u16 = 1;
+
+pub const INCOMPAT_FILETYPE: u32 = 0x0002;
+pub const RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+pub const RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+
+/// Incompatible features supported by the driver.
+pub const SUPPORTED_INCOMPAT: u32 = INCOMPAT_FILETYPE;
+/// Read-only compatible features supported by the driver.
+pub const SUPPORTED_RO_COMPAT: u32 = RO_COMPAT_SPARSE_SUPER | RO_COMPAT_LARGE_FILE;
+
+pub const GROUP_DESC_SIZE: usize = 32;
+/// Inode size for revision 0 file systems.
+pub const GOOD_OLD_INODE_SIZE: u16 = 128;
+/// First non-reserved inode for revision 0 file systems.
+pub const GOOD_OLD_FIRST_INODE: u32 = 11;
+
+pub const DIRECT_BLOCKS: usize = 12;
+pub const INDIRECT_BLOCK: usize = 12;
+pub const DOUBLE_INDIRECT_BLOCK: usize = 13;
+
+pub const MODE_TYPE_MASK: u16 = 0xF000;
+pub const MODE_DIRECTORY: u16 = 0x4000;
+pub const MODE_REGULAR: u16 = 0x8000;
+
+/// Directory entry file types.
+pub const FT_REG_FILE: u8 = 1;
+pub const FT_DIR: u8 = 2;
+
+/// Size of a directory entry header before the name.
+pub const DIR_ENTRY_HEADER: usize = 8;
+
+pub fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+pub fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+pub fn write_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+pub fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Superblock {
+    pub inodes_count: u32,
+    pub blocks_count: u32,
+    pub free_blocks_count: u32,
+    pub free_inodes_count: u32,
+    pub first_data_block: u32,
+    pub log_block_size: u32,
+    pub blocks_per_group: u32,
+    pub inodes_per_group: u32,
+    pub magic: u16,
+    pub state: u16,
+    pub first_ino: u32,
+    pub inode_size: u16,
+    /// Size of the extra fields in large inodes that new inodes should reserve.
+    pub want_extra_isize: u16,
+    pub feature_incompat: u32,
+    pub feature_ro_compat: u32,
+}
+
+impl Superblock {
+    pub fn decode(bytes: &[u8]) -> Self {
+        let rev_level = read_u32(bytes, 76);
+        let (first_ino, inode_size) = if rev_level == 0 {
+            (GOOD_OLD_FIRST_INODE, GOOD_OLD_INODE_SIZE)
+        } else {
+            (read_u32(bytes, 84), read_u16(bytes, 88))
+        };
+        Self {
+            inodes_count: read_u32(bytes, 0),
+            blocks_count: read_u32(bytes, 4),
+            free_blocks_count: read_u32(bytes, 12),
+            free_inodes_count: read_u32(bytes, 16),
+            first_data_block: read_u32(bytes, 20),
+            log_block_size: read_u32(bytes, 24),
+            blocks_per_group: read_u32(bytes, 32),
+            inodes_per_group: read_u32(bytes, 40),
+            magic: read_u16(bytes, 56),
+            state: read_u16(bytes, 58),
+            first_ino,
+            inode_size,
+            want_extra_isize: if rev_level == 0 {
+                0
+            } else {
+                read_u16(bytes, 350)
+            },
+            feature_incompat: if rev_level == 0 {
+                0
+            } else {
+                read_u32(bytes, 96)
+            },
+            feature_ro_compat: if rev_level == 0 {
+                0
+            } else {
+                read_u32(bytes, 100)
+            },
+        }
+    }
+
+    /// Writes back the fields the driver modifies.
+    pub fn encode(&self, bytes: &mut [u8]) {
+        write_u32(bytes, 12, self.free_blocks_count);
+        write_u32(bytes, 16, self.free_inodes_count);
+        write_u16(bytes, 58, self.state);
+    }
+
+    pub fn group_count(&self) -> u32 {
+        (self.blocks_count - self.first_data_block).div_ceil(self.blocks_per_group)
+    }
+
+    pub fn has_filetype(&self) -> bool {
+        self.feature_incompat & INCOMPAT_FILETYPE != 0
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct GroupDesc {
+    pub block_bitmap: u32,
+    pub inode_bitmap: u32,
+    pub inode_table: u32,
+    pub free_blocks_count: u16,
+    pub free_inodes_count: u16,
+    pub used_dirs_count: u16,
+}
+
+impl GroupDesc {
+    pub fn decode(bytes: &[u8]) -> Self {
+        Self {
+            block_bitmap: read_u32(bytes, 0),
+            inode_bitmap: read_u32(bytes, 4),
+            inode_table: read_u32(bytes, 8),
+            free_blocks_count: read_u16(bytes, 12),
+            free_inodes_count: read_u16(bytes, 14),
+            used_dirs_count: read_u16(bytes, 16),
+        }
+    }
+
+    pub fn encode(&self, bytes: &mut [u8]) {
+        write_u16(bytes, 12, self.free_blocks_count);
+        write_u16(bytes, 14, self.free_inodes_count);
+        write_u16(bytes, 16, self.used_dirs_count);
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Inode {
+    pub mode: u16,
+    pub size: u64,
+    pub links_count: u16,
+    /// Number of 512-byte sectors allocated to the inode, including indirect blocks.
+    pub sectors: u32,
+    pub block: [u32; 15],
+}
+
+impl Inode {
+    pub fn decode(bytes: &[u8]) -> Self {
+        let mut block = [0; 15];
+        for (i, block) in block.iter_mut().enumerate() {
+            *block = read_u32(bytes, 40 + 4 * i);
+        }
+        let mode = read_u16(bytes, 0);
+        let mut size = u64::from(read_u32(bytes, 4));
+        // The high bits of the size share the directory ACL field.
+        if mode & MODE_TYPE_MASK == MODE_REGULAR {
+            size |= u64::from(read_u32(bytes, 108)) << 32;
+        }
+        Self {
+            mode,
+            size,
+            links_count: read_u16(bytes, 26),
+            sectors: read_u32(bytes, 28),
+            block,
+        }
+    }
+
+    pub fn encode(&self, bytes: &mut [u8]) {
+        write_u16(bytes, 0, self.mode);
+        write_u32(bytes, 4, self.size as u32);
+        write_u16(bytes, 26, self.links_count);
+        write_u32(bytes, 28, self.sectors);
+        for (i, block) in self.block.iter().enumerate() {
+            write_u32(bytes, 40 + 4 * i, *block);
+        }
+        if self.mode & MODE_TYPE_MASK == MODE_REGULAR {
+            write_u32(bytes, 108, (self.size >> 32) as u32);
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.mode & MODE_TYPE_MASK == MODE_DIRECTORY
+    }
+}
+
+/// A raw directory entry.
+#[derive(Debug, Copy, Clone)]
+pub struct DirEntry {
+    pub inode: u32,
+    pub rec_len: u16,
+    pub name_len: u8,
+    pub file_type: u8,
+}
+
+impl DirEntry {
+    pub fn decode(bytes: &[u8]) -> Self {
+        Self {
+            inode: read_u32(bytes, 0),
+            rec_len: read_u16(bytes, 4),
+            name_len: bytes[6],
+            file_type: bytes[7],
+        }
+    }
+
+    pub fn encode(&self, bytes: &mut [u8]) {
+        write_u32(bytes, 0, self.inode);
+        write_u16(bytes, 4, self.rec_len);
+        bytes[6] = self.name_len;
+        bytes[7] = self.file_type;
+    }
+
+    /// Space used by the entry, excluding any slack in `rec_len`.
+    pub fn used_len(&self) -> usize {
+        if self.inode == 0 {
+            0
+        } else {
+            record_len(self.name_len.into())
+        }
+    }
+}
+
+/// Minimum record length for an entry with a name of `name_len` bytes.
+pub fn record_len(name_len: usize) -> usize {
+    (DIR_ENTRY_HEADER + name_len).next_multiple_of(4)
+}
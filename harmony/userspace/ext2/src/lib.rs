@@ -0,0 +1,892 @@
+//! ext2 file system driver.
+//!
+//! The driver works on top of any [`BlockDevice`], usually a
+//! [`BlockCache`](blockdev::BlockCache) over the disk driver. It supports
+//! directory lookup, reading files, and creating and writing regular files and
+//! directories. Only 1 KiB blocks are supported, matching [`BLOCK_SIZE`].
+//!
+//! Files can also be read a page at a time with [`Ext2::read_page`] to back
+//! memory mappings.
+//!
+//! The file system is checked for consistency when mounted (see
+//! [`Ext2::check`]) and marked as not clean until it's unmounted.
+#![cfg_attr(not(test), no_std)]
+
+mod check;
+mod layout;
+
+pub use check::CheckError;
+
+use blockdev::{Block, BlockDevice, BLOCK_SIZE};
+use layout::{
+    read_u32, record_len, write_u16, write_u32, DirEntry, GroupDesc, Inode, Superblock,
+    DIRECT_BLOCKS, DIR_ENTRY_HEADER, DOUBLE_INDIRECT_BLOCK, FT_DIR, FT_REG_FILE, GROUP_DESC_SIZE,
+    INDIRECT_BLOCK, MODE_DIRECTORY, MODE_REGULAR, MODE_TYPE_MASK, STATE_VALID, SUPERBLOCK_MAGIC,
+    SUPPORTED_INCOMPAT, SUPPORTED_RO_COMPAT,
+};
+
+/// Inode number of the root directory.
+pub const ROOT_INODE: u32 = 2;
+
+/// Maximum length of a file name.
+pub const MAX_NAME_LEN: usize = 255;
+
+/// Size of the pages handed out by [`Ext2::read_page`].
+pub const PAGE_SIZE: usize = 4096;
+
+/// Block holding the superblock when using 1 KiB blocks.
+const SUPERBLOCK_BLOCK: u32 = 1;
+/// Block numbers stored in an indirect block.
+const POINTERS_PER_BLOCK: u64 = (BLOCK_SIZE / 4) as u64;
+/// Inode sector counts are in 512-byte units.
+const SECTORS_PER_BLOCK: u32 = (BLOCK_SIZE / 512) as u32;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error<E> {
+    Device(E),
+    BadMagic,
+    UnsupportedBlockSize,
+    UnsupportedFeature,
+    /// The file system uses features that can only be supported read-only.
+    ReadOnly,
+    /// The consistency check on mount failed.
+    Inconsistent(CheckError),
+    /// An on-disk structure points outside of the file system.
+    Corrupted,
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    InvalidName,
+    NameTooLong,
+    NoSpace,
+    FileTooLarge,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(value: E) -> Self {
+        Self::Device(value)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    /// Symbolic links, devices, etc. These can be looked up but not read.
+    Other,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    pub kind: FileType,
+    pub size: u64,
+    pub links: u16,
+}
+
+pub struct Ext2<D: BlockDevice> {
+    device: D,
+    superblock: Superblock,
+    read_only: bool,
+}
+
+impl<D: BlockDevice> Ext2<D> {
+    /// Mounts the file system in `device`, checking it for consistency.
+    pub fn mount(mut device: D) -> Result<Self, Error<D::Error>> {
+        let mut block = [0; BLOCK_SIZE];
+        device.read_block(SUPERBLOCK_BLOCK.into(), &mut block)?;
+        let superblock = Superblock::decode(&block);
+        if superblock.magic != SUPERBLOCK_MAGIC {
+            return Err(Error::BadMagic);
+        }
+        if superblock.log_block_size != 0 {
+            return Err(Error::UnsupportedBlockSize);
+        }
+        if superblock.feature_incompat & !SUPPORTED_INCOMPAT != 0 {
+            return Err(Error::UnsupportedFeature);
+        }
+        let mut fs = Self {
+            device,
+            superblock,
+            read_only: superblock.feature_ro_compat & !SUPPORTED_RO_COMPAT != 0,
+        };
+        fs.check()?;
+        if !fs.read_only {
+            fs.superblock.state &= !STATE_VALID;
+            fs.write_superblock()?;
+        }
+        Ok(fs)
+    }
+
+    /// Marks the file system as clean, flushes it, and returns the device.
+    pub fn unmount(mut self) -> Result<D, Error<D::Error>> {
+        if !self.read_only {
+            self.superblock.state |= STATE_VALID;
+            self.write_superblock()?;
+        }
+        self.device.flush()?;
+        Ok(self.device)
+    }
+
+    /// Flushes every write performed so far to the device.
+    pub fn flush(&mut self) -> Result<(), Error<D::Error>> {
+        self.device.flush()?;
+        Ok(())
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn free_blocks(&self) -> u32 {
+        self.superblock.free_blocks_count
+    }
+
+    pub fn free_inodes(&self) -> u32 {
+        self.superblock.free_inodes_count
+    }
+
+    pub fn metadata(&mut self, inode: u32) -> Result<Metadata, Error<D::Error>> {
+        let node = self.read_inode(inode)?;
+        let kind = match node.mode & MODE_TYPE_MASK {
+            MODE_REGULAR => FileType::Regular,
+            MODE_DIRECTORY => FileType::Directory,
+            _ => FileType::Other,
+        };
+        Ok(Metadata {
+            kind,
+            size: node.size,
+            links: node.links_count,
+        })
+    }
+
+    /// Finds `name` in the directory `dir`.
+    pub fn lookup(&mut self, dir: u32, name: &str) -> Result<u32, Error<D::Error>> {
+        if name.len() > MAX_NAME_LEN {
+            return Err(Error::NameTooLong);
+        }
+        let mut node = self.read_inode(dir)?;
+        if !node.is_dir() {
+            return Err(Error::NotADirectory);
+        }
+        self.find_entry(&mut node, |_, _, entry, entry_name| {
+            (entry.inode != 0 && entry_name == name.as_bytes()).then_some(entry.inode)
+        })?
+        .ok_or(Error::NotFound)
+    }
+
+    /// Resolves an absolute path starting at the root directory.
+    pub fn open(&mut self, path: &str) -> Result<u32, Error<D::Error>> {
+        path.split('/')
+            .filter(|component| !component.is_empty())
+            .try_fold(ROOT_INODE, |dir, name| self.lookup(dir, name))
+    }
+
+    /// Calls `fun` with the name and inode of every entry in `dir`.
+    pub fn read_dir<F: FnMut(&[u8], u32)>(
+        &mut self,
+        dir: u32,
+        mut fun: F,
+    ) -> Result<(), Error<D::Error>> {
+        let mut node = self.read_inode(dir)?;
+        if !node.is_dir() {
+            return Err(Error::NotADirectory);
+        }
+        self.find_entry(&mut node, |_, _, entry, name| {
+            if entry.inode != 0 {
+                fun(name, entry.inode);
+            }
+            None::<()>
+        })?;
+        Ok(())
+    }
+
+    /// Reads from `inode` starting at `offset`, returning the number of bytes read.
+    pub fn read(
+        &mut self,
+        inode: u32,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<usize, Error<D::Error>> {
+        let mut node = self.read_inode(inode)?;
+        if node.is_dir() {
+            return Err(Error::IsADirectory);
+        }
+        let len = buf
+            .len()
+            .min(usize::try_from(node.size.saturating_sub(offset)).unwrap_or(usize::MAX));
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let within = (position % BLOCK_SIZE as u64) as usize;
+            let count = (BLOCK_SIZE - within).min(len - done);
+            let out = &mut buf[done..done + count];
+            match self.map_block(&mut node, position / BLOCK_SIZE as u64, false)? {
+                Some(block) => out.copy_from_slice(&self.read_block(block)?[within..][..count]),
+                // Holes in sparse files read as zeroes.
+                None => out.fill(0),
+            }
+            done += count;
+        }
+        Ok(done)
+    }
+
+    /// Fills `page` with the `index`-th page of `inode` for mapping it into memory.
+    ///
+    /// Anything past the end of the file reads as zeroes. Returns the number of
+    /// bytes in the page backed by the file, which is 0 if the page is
+    /// entirely past the end of the file, and [`Error::FileTooLarge`] if the
+    /// page's offset doesn't fit in 64 bits.
+    pub fn read_page(
+        &mut self,
+        inode: u32,
+        index: u64,
+        page: &mut [u8; PAGE_SIZE],
+    ) -> Result<usize, Error<D::Error>> {
+        let offset = index
+            .checked_mul(PAGE_SIZE as u64)
+            .ok_or(Error::FileTooLarge)?;
+        let read = self.read(inode, offset, page)?;
+        page[read..].fill(0);
+        Ok(read)
+    }
+
+    /// Writes `data` to `inode` at `offset`, allocating blocks as needed.
+    ///
+    /// Returns the number of bytes written, which is only short of
+    /// `data.len()` if the file system ran out of space.
+    pub fn write(
+        &mut self,
+        inode: u32,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<usize, Error<D::Error>> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let mut node = self.read_inode(inode)?;
+        if node.is_dir() {
+            return Err(Error::IsADirectory);
+        }
+        let mut written = 0;
+        let result = self.write_blocks(&mut node, offset, data, &mut written);
+        node.size = node.size.max(offset + written as u64);
+        // Persist any progress even if we failed half way.
+        self.write_inode(inode, &node)?;
+        match result {
+            Err(e) if written == 0 => Err(e),
+            _ => Ok(written),
+        }
+    }
+
+    /// Creates an empty file or directory called `name` in `dir`.
+    pub fn create(&mut self, dir: u32, name: &str, kind: FileType) -> Result<u32, Error<D::Error>> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            return Err(Error::InvalidName);
+        }
+        let (mode, file_type) = match kind {
+            FileType::Regular => (MODE_REGULAR | 0o644, FT_REG_FILE),
+            FileType::Directory => (MODE_DIRECTORY | 0o755, FT_DIR),
+            FileType::Other => return Err(Error::InvalidName),
+        };
+        match self.lookup(dir, name) {
+            Ok(_) => return Err(Error::AlreadyExists),
+            Err(Error::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+        let mut parent = self.read_inode(dir)?;
+
+        let inode = self.alloc_inode(kind == FileType::Directory)?;
+        let mut node = Inode {
+            mode,
+            links_count: 1,
+            ..Default::default()
+        };
+        if kind == FileType::Directory {
+            // One link from the parent and another one from ".".
+            node.links_count = 2;
+            let block = self.map_block(&mut node, 0, true)?.unwrap();
+            let mut data = [0; BLOCK_SIZE];
+            let dot = record_len(1);
+            self.encode_entry(&mut data[..dot], inode, dot, ".", FT_DIR);
+            self.encode_entry(&mut data[dot..], dir, BLOCK_SIZE - dot, "..", FT_DIR);
+            self.write_block(block, &data)?;
+            node.size = BLOCK_SIZE as u64;
+            parent.links_count += 1;
+        }
+        self.init_inode(inode, &node)?;
+        self.add_entry(&mut parent, name, inode, file_type)?;
+        self.write_inode(dir, &parent)?;
+        Ok(inode)
+    }
+
+    fn read_block(&mut self, block: u32) -> Result<Block, Error<D::Error>> {
+        let mut data = [0; BLOCK_SIZE];
+        self.device.read_block(block.into(), &mut data)?;
+        Ok(data)
+    }
+
+    fn write_block(&mut self, block: u32, data: &Block) -> Result<(), Error<D::Error>> {
+        self.device.write_block(block.into(), data)?;
+        Ok(())
+    }
+
+    fn write_superblock(&mut self) -> Result<(), Error<D::Error>> {
+        let mut data = self.read_block(SUPERBLOCK_BLOCK)?;
+        self.superblock.encode(&mut data);
+        self.write_block(SUPERBLOCK_BLOCK, &data)
+    }
+
+    /// Returns the block and offset within it of the descriptor for `group`.
+    fn group_desc_location(&self, group: u32) -> (u32, usize) {
+        let offset = group as usize * GROUP_DESC_SIZE;
+        let block = self.superblock.first_data_block + 1 + (offset / BLOCK_SIZE) as u32;
+        (block, offset % BLOCK_SIZE)
+    }
+
+    fn group_desc(&mut self, group: u32) -> Result<GroupDesc, Error<D::Error>> {
+        let (block, offset) = self.group_desc_location(group);
+        Ok(GroupDesc::decode(&self.read_block(block)?[offset..]))
+    }
+
+    fn write_group_desc(&mut self, group: u32, desc: &GroupDesc) -> Result<(), Error<D::Error>> {
+        let (block, offset) = self.group_desc_location(group);
+        let mut data = self.read_block(block)?;
+        desc.encode(&mut data[offset..]);
+        self.write_block(block, &data)
+    }
+
+    /// Number of blocks in `group`. The last group may be smaller than the rest.
+    fn blocks_in_group(&self, group: u32) -> u32 {
+        let start = self.superblock.first_data_block + group * self.superblock.blocks_per_group;
+        self.superblock
+            .blocks_per_group
+            .min(self.superblock.blocks_count - start)
+    }
+
+    /// Returns the block and offset within it holding `inode`.
+    fn inode_location(&mut self, inode: u32) -> Result<(u32, usize), Error<D::Error>> {
+        if inode == 0 || inode > self.superblock.inodes_count {
+            return Err(Error::NotFound);
+        }
+        let group = (inode - 1) / self.superblock.inodes_per_group;
+        let index = (inode - 1) % self.superblock.inodes_per_group;
+        let table = self.group_desc(group)?.inode_table;
+        let offset = index as usize * usize::from(self.superblock.inode_size);
+        Ok((table + (offset / BLOCK_SIZE) as u32, offset % BLOCK_SIZE))
+    }
+
+    fn read_inode(&mut self, inode: u32) -> Result<Inode, Error<D::Error>> {
+        let (block, offset) = self.inode_location(inode)?;
+        Ok(Inode::decode(&self.read_block(block)?[offset..]))
+    }
+
+    fn write_inode(&mut self, inode: u32, node: &Inode) -> Result<(), Error<D::Error>> {
+        let (block, offset) = self.inode_location(inode)?;
+        let mut data = self.read_block(block)?;
+        node.encode(&mut data[offset..]);
+        self.write_block(block, &data)
+    }
+
+    /// Writes a newly allocated inode, clearing anything left in its slot.
+    fn init_inode(&mut self, inode: u32, node: &Inode) -> Result<(), Error<D::Error>> {
+        let (block, offset) = self.inode_location(inode)?;
+        let mut data = self.read_block(block)?;
+        let inode_size = usize::from(self.superblock.inode_size);
+        let slot = &mut data[offset..offset + inode_size];
+        slot.fill(0);
+        node.encode(slot);
+        if inode_size > usize::from(layout::GOOD_OLD_INODE_SIZE) {
+            write_u16(slot, 128, self.superblock.want_extra_isize);
+        }
+        self.write_block(block, &data)
+    }
+
+    /// Returns the block backing `file_block` in `node`.
+    ///
+    /// If `allocate` is set, missing data and indirect blocks are allocated
+    /// and accounted in `node`, which the caller must write back.
+    fn map_block(
+        &mut self,
+        node: &mut Inode,
+        file_block: u64,
+        allocate: bool,
+    ) -> Result<Option<u32>, Error<D::Error>> {
+        let direct = DIRECT_BLOCKS as u64;
+        let (slot, indices, depth) = if file_block < direct {
+            (file_block as usize, [0, 0], 0)
+        } else if file_block - direct < POINTERS_PER_BLOCK {
+            (INDIRECT_BLOCK, [file_block - direct, 0], 1)
+        } else if file_block - direct - POINTERS_PER_BLOCK < POINTERS_PER_BLOCK.pow(2) {
+            let index = file_block - direct - POINTERS_PER_BLOCK;
+            (
+                DOUBLE_INDIRECT_BLOCK,
+                [index / POINTERS_PER_BLOCK, index % POINTERS_PER_BLOCK],
+                2,
+            )
+        } else {
+            return Err(Error::FileTooLarge);
+        };
+
+        let mut pointer = node.block[slot];
+        if pointer == 0 {
+            if !allocate {
+                return Ok(None);
+            }
+            pointer = self.alloc_block()?;
+            node.block[slot] = pointer;
+            node.sectors += SECTORS_PER_BLOCK;
+        }
+        for index in &indices[..depth] {
+            if pointer >= self.superblock.blocks_count {
+                return Err(Error::Corrupted);
+            }
+            let offset = *index as usize * 4;
+            let mut table = self.read_block(pointer)?;
+            let mut next = read_u32(&table, offset);
+            if next == 0 {
+                if !allocate {
+                    return Ok(None);
+                }
+                next = self.alloc_block()?;
+                write_u32(&mut table, offset, next);
+                self.write_block(pointer, &table)?;
+                node.sectors += SECTORS_PER_BLOCK;
+            }
+            pointer = next;
+        }
+        if pointer >= self.superblock.blocks_count {
+            return Err(Error::Corrupted);
+        }
+        Ok(Some(pointer))
+    }
+
+    fn write_blocks(
+        &mut self,
+        node: &mut Inode,
+        offset: u64,
+        data: &[u8],
+        written: &mut usize,
+    ) -> Result<(), Error<D::Error>> {
+        while *written < data.len() {
+            let position = offset + *written as u64;
+            let within = (position % BLOCK_SIZE as u64) as usize;
+            let count = (BLOCK_SIZE - within).min(data.len() - *written);
+            let block = self
+                .map_block(node, position / BLOCK_SIZE as u64, true)?
+                .unwrap();
+            let mut contents = if count == BLOCK_SIZE {
+                [0; BLOCK_SIZE]
+            } else {
+                self.read_block(block)?
+            };
+            contents[within..][..count].copy_from_slice(&data[*written..][..count]);
+            self.write_block(block, &contents)?;
+            *written += count;
+        }
+        Ok(())
+    }
+
+    /// Finds and sets the first clear bit in a bitmap block, returning its index.
+    fn claim_bit(
+        &mut self,
+        bitmap: u32,
+        limit: u32,
+        skip: u32,
+    ) -> Result<Option<u32>, Error<D::Error>> {
+        let mut data = self.read_block(bitmap)?;
+        let Some(bit) = (skip..limit).find(|bit| data[*bit as usize / 8] & (1 << (bit % 8)) == 0)
+        else {
+            return Ok(None);
+        };
+        data[bit as usize / 8] |= 1 << (bit % 8);
+        self.write_block(bitmap, &data)?;
+        Ok(Some(bit))
+    }
+
+    /// Allocates a zeroed block.
+    fn alloc_block(&mut self) -> Result<u32, Error<D::Error>> {
+        for group in 0..self.superblock.group_count() {
+            let mut desc = self.group_desc(group)?;
+            if desc.free_blocks_count == 0 {
+                continue;
+            }
+            let limit = self.blocks_in_group(group);
+            let Some(bit) = self.claim_bit(desc.block_bitmap, limit, 0)? else {
+                continue;
+            };
+            desc.free_blocks_count -= 1;
+            self.write_group_desc(group, &desc)?;
+            self.superblock.free_blocks_count -= 1;
+            self.write_superblock()?;
+
+            let block =
+                self.superblock.first_data_block + group * self.superblock.blocks_per_group + bit;
+            self.write_block(block, &[0; BLOCK_SIZE])?;
+            return Ok(block);
+        }
+        Err(Error::NoSpace)
+    }
+
+    fn alloc_inode(&mut self, directory: bool) -> Result<u32, Error<D::Error>> {
+        let per_group = self.superblock.inodes_per_group;
+        for group in 0..self.superblock.group_count() {
+            let mut desc = self.group_desc(group)?;
+            if desc.free_inodes_count == 0 {
+                continue;
+            }
+            // Inodes before `first_ino` are reserved.
+            let skip = (self.superblock.first_ino - 1).saturating_sub(group * per_group);
+            let Some(bit) = self.claim_bit(desc.inode_bitmap, per_group, skip)? else {
+                continue;
+            };
+            desc.free_inodes_count -= 1;
+            if directory {
+                desc.used_dirs_count += 1;
+            }
+            self.write_group_desc(group, &desc)?;
+            self.superblock.free_inodes_count -= 1;
+            self.write_superblock()?;
+            return Ok(group * per_group + bit + 1);
+        }
+        Err(Error::NoSpace)
+    }
+
+    /// Walks the entries in directory `node`, stopping when `fun` returns a value.
+    ///
+    /// `fun` gets the block and offset of the entry, the entry and its name.
+    fn find_entry<T, F>(
+        &mut self,
+        node: &mut Inode,
+        mut fun: F,
+    ) -> Result<Option<T>, Error<D::Error>>
+    where
+        F: FnMut(u32, usize, &DirEntry, &[u8]) -> Option<T>,
+    {
+        for file_block in 0..node.size.div_ceil(BLOCK_SIZE as u64) {
+            let block = self
+                .map_block(node, file_block, false)?
+                .ok_or(Error::Corrupted)?;
+            let data = self.read_block(block)?;
+            let mut offset = 0;
+            while offset < BLOCK_SIZE {
+                if offset + DIR_ENTRY_HEADER > BLOCK_SIZE {
+                    return Err(Error::Corrupted);
+                }
+                let entry = DirEntry::decode(&data[offset..]);
+                let rec_len = usize::from(entry.rec_len);
+                // Unused entries have a name length too, which still has to
+                // fit in the record.
+                if rec_len < DIR_ENTRY_HEADER
+                    || rec_len % 4 != 0
+                    || offset + rec_len > BLOCK_SIZE
+                    || entry.used_len() > rec_len
+                    || DIR_ENTRY_HEADER + usize::from(entry.name_len) > rec_len
+                {
+                    return Err(Error::Corrupted);
+                }
+                let name = &data[offset + DIR_ENTRY_HEADER..][..usize::from(entry.name_len)];
+                if let Some(found) = fun(block, offset, &entry, name) {
+                    return Ok(Some(found));
+                }
+                offset += rec_len;
+            }
+        }
+        Ok(None)
+    }
+
+    fn encode_entry(&self, data: &mut [u8], inode: u32, rec_len: usize, name: &str, file_type: u8) {
+        DirEntry {
+            inode,
+            rec_len: rec_len as u16,
+            name_len: name.len() as u8,
+            file_type: if self.superblock.has_filetype() {
+                file_type
+            } else {
+                0
+            },
+        }
+        .encode(data);
+        data[DIR_ENTRY_HEADER..][..name.len()].copy_from_slice(name.as_bytes());
+    }
+
+    /// Links `inode` into directory `dir` as `name`.
+    fn add_entry(
+        &mut self,
+        dir: &mut Inode,
+        name: &str,
+        inode: u32,
+        file_type: u8,
+    ) -> Result<(), Error<D::Error>> {
+        if name.len() > MAX_NAME_LEN {
+            return Err(Error::NameTooLong);
+        }
+        let needed = record_len(name.len());
+        let free = self.find_entry(dir, |block, offset, entry, _| {
+            (usize::from(entry.rec_len) - entry.used_len() >= needed)
+                .then_some((block, offset, *entry))
+        })?;
+        if let Some((block, offset, entry)) = free {
+            let mut data = self.read_block(block)?;
+            let used = entry.used_len();
+            if used != 0 {
+                // Shrink the existing entry to make room for the new one.
+                layout::write_u16(&mut data[offset..], 4, used as u16);
+            }
+            let rec_len = usize::from(entry.rec_len) - used;
+            self.encode_entry(&mut data[offset + used..], inode, rec_len, name, file_type);
+            return self.write_block(block, &data);
+        }
+
+        // No space in the existing blocks, grow the directory.
+        let block = self
+            .map_block(dir, dir.size / BLOCK_SIZE as u64, true)?
+            .unwrap();
+        let mut data = [0; BLOCK_SIZE];
+        self.encode_entry(&mut data, inode, BLOCK_SIZE, name, file_type);
+        self.write_block(block, &data)?;
+        dir.size += BLOCK_SIZE as u64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockdev::RamDisk;
+
+    /// Built by `testdata/mkgolden.sh`.
+    const GOLDEN: &[u8] = include_bytes!("../testdata/golden.img");
+
+    fn golden() -> Vec<u8> {
+        GOLDEN.to_vec()
+    }
+
+    fn read_all(fs: &mut Ext2<RamDisk>, path: &str) -> Vec<u8> {
+        let inode = fs.open(path).unwrap();
+        let mut buf = vec![0; fs.metadata(inode).unwrap().size as usize];
+        assert_eq!(fs.read(inode, 0, &mut buf).unwrap(), buf.len());
+        buf
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn lookup_and_read() {
+        let mut image = golden();
+        let mut fs = Ext2::mount(RamDisk::from_bytes(&mut image)).unwrap();
+        assert_eq!(read_all(&mut fs, "/hello.txt"), b"Hello, Harmony!\n");
+        assert_eq!(
+            read_all(&mut fs, "/docs/readme.txt"),
+            b"ext2 golden image\n"
+        );
+        assert_eq!(
+            read_all(&mut fs, "docs/nested/../nested/deep.txt"),
+            b"deep\n"
+        );
+        assert_eq!(read_all(&mut fs, "/empty"), b"");
+
+        assert_eq!(fs.open("/missing"), Err(Error::NotFound));
+        assert_eq!(fs.open("/hello.txt/x"), Err(Error::NotADirectory));
+        let docs = fs.open("/docs").unwrap();
+        assert_eq!(fs.metadata(docs).unwrap().kind, FileType::Directory);
+        assert_eq!(fs.read(docs, 0, &mut [0; 4]), Err(Error::IsADirectory));
+    }
+
+    #[test]
+    fn read_through_indirect_blocks() {
+        let mut image = golden();
+        let mut fs = Ext2::mount(RamDisk::from_bytes(&mut image)).unwrap();
+        assert_eq!(read_all(&mut fs, "/big.bin"), pattern(20480));
+
+        let big = fs.open("/big.bin").unwrap();
+        let mut buf = [0; 100];
+        assert_eq!(fs.read(big, 12 * 1024 - 50, &mut buf).unwrap(), 100);
+        assert_eq!(&buf[..], &pattern(20480)[12 * 1024 - 50..][..100]);
+        assert_eq!(fs.read(big, 20480 - 10, &mut buf).unwrap(), 10);
+        assert_eq!(fs.read(big, 30000, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn read_pages() {
+        let mut image = golden();
+        let mut fs = Ext2::mount(RamDisk::from_bytes(&mut image)).unwrap();
+        let big = fs.open("/big.bin").unwrap();
+        let mut page = [0xAA; PAGE_SIZE];
+        assert_eq!(fs.read_page(big, 4, &mut page).unwrap(), PAGE_SIZE);
+        assert_eq!(&page[..], &pattern(20480)[4 * PAGE_SIZE..]);
+        assert_eq!(fs.read_page(big, 5, &mut page).unwrap(), 0);
+        assert_eq!(page, [0; PAGE_SIZE]);
+        assert_eq!(
+            fs.read_page(big, u64::MAX, &mut page),
+            Err(Error::FileTooLarge)
+        );
+
+        let hello = fs.open("/hello.txt").unwrap();
+        page.fill(0xAA);
+        assert_eq!(fs.read_page(hello, 0, &mut page).unwrap(), 16);
+        assert_eq!(&page[..16], b"Hello, Harmony!\n");
+        assert!(page[16..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn read_dir() {
+        let mut image = golden();
+        let mut fs = Ext2::mount(RamDisk::from_bytes(&mut image)).unwrap();
+        let mut names = Vec::new();
+        fs.read_dir(ROOT_INODE, |name, _| names.push(name.to_vec()))
+            .unwrap();
+        names.sort();
+        let expected: Vec<&[u8]> = vec![
+            b".",
+            b"..",
+            b"big.bin",
+            b"docs",
+            b"empty",
+            b"hello.txt",
+            b"lost+found",
+        ];
+        assert_eq!(names, expected);
+    }
+
+    #[test]
+    fn create_and_write() {
+        let mut image = golden();
+        let mut fs = Ext2::mount(RamDisk::from_bytes(&mut image)).unwrap();
+        let free_blocks = fs.free_blocks();
+        let docs = fs.open("/docs").unwrap();
+        let dir = fs.create(docs, "new", FileType::Directory).unwrap();
+        let file = fs.create(dir, "data.bin", FileType::Regular).unwrap();
+        assert_eq!(
+            fs.create(docs, "new", FileType::Regular),
+            Err(Error::AlreadyExists)
+        );
+        // Spans the direct blocks and an indirect block.
+        let data = pattern(30 * 1024 + 17);
+        assert_eq!(fs.write(file, 0, &data).unwrap(), data.len());
+        // Directory block, 31 data blocks and an indirect block.
+        assert_eq!(fs.free_blocks(), free_blocks - 33);
+
+        let hello = fs.open("/hello.txt").unwrap();
+        fs.write(hello, 7, b"ext2").unwrap();
+
+        // Remount to run the consistency checks over the changes.
+        let disk = fs.unmount().unwrap();
+        let mut fs = Ext2::mount(disk).unwrap();
+        assert_eq!(read_all(&mut fs, "/docs/new/data.bin"), data);
+        assert_eq!(read_all(&mut fs, "/hello.txt"), b"Hello, ext2ony!\n");
+        assert_eq!(fs.metadata(docs).unwrap().links, 4);
+        assert_eq!(fs.lookup(dir, ".."), Ok(docs));
+        fs.unmount().unwrap();
+    }
+
+    #[test]
+    fn grows_directories() {
+        let mut image = golden();
+        let mut fs = Ext2::mount(RamDisk::from_bytes(&mut image)).unwrap();
+        let dir = fs.create(ROOT_INODE, "many", FileType::Directory).unwrap();
+        let long = "x".repeat(200);
+        for i in 0..6 {
+            let name = format!("{long}{i}");
+            fs.create(dir, &name, FileType::Regular).unwrap();
+        }
+        assert_eq!(fs.metadata(dir).unwrap().size, 2 * BLOCK_SIZE as u64);
+        let disk = fs.unmount().unwrap();
+        let mut fs = Ext2::mount(disk).unwrap();
+        let dir = fs.open("/many").unwrap();
+        assert!(fs.lookup(dir, &format!("{long}5")).is_ok());
+    }
+
+    #[test]
+    fn partial_write_when_full() {
+        let mut image = golden();
+        let mut fs = Ext2::mount(RamDisk::from_bytes(&mut image)).unwrap();
+        let file = fs.create(ROOT_INODE, "huge", FileType::Regular).unwrap();
+        let data = pattern(200 * 1024);
+        let written = fs.write(file, 0, &data).unwrap();
+        assert!(written > 0 && written < data.len());
+        assert_eq!(fs.free_blocks(), 0);
+        assert_eq!(fs.write(file, written as u64, &data), Err(Error::NoSpace));
+        let disk = fs.unmount().unwrap();
+        let mut fs = Ext2::mount(disk).unwrap();
+        assert_eq!(read_all(&mut fs, "/huge"), data[..written]);
+    }
+
+    #[test]
+    fn rejects_inconsistent_images() {
+        let mut image = golden();
+        // Not cleanly unmounted.
+        image[1024 + 58] = 0;
+        assert_eq!(
+            Ext2::mount(RamDisk::from_bytes(&mut image)).err(),
+            Some(Error::Inconsistent(CheckError::NotClean))
+        );
+
+        let mut image = golden();
+        // Mark a free block as used in the block bitmap (block 3).
+        image[3 * 1024 + 8] = 0xFF;
+        assert_eq!(
+            Ext2::mount(RamDisk::from_bytes(&mut image)).err(),
+            Some(Error::Inconsistent(CheckError::FreeBlocks { group: 0 }))
+        );
+
+        let mut image = golden();
+        image[1024 + 56] = 0;
+        assert_eq!(
+            Ext2::mount(RamDisk::from_bytes(&mut image)).err(),
+            Some(Error::BadMagic)
+        );
+    }
+
+    #[test]
+    fn rejects_odd_inode_sizes() {
+        for size in [384u16, 2048] {
+            let mut image = golden();
+            image[1024 + 88..][..2].copy_from_slice(&size.to_le_bytes());
+            assert_eq!(
+                Ext2::mount(RamDisk::from_bytes(&mut image)).err(),
+                Some(Error::Inconsistent(CheckError::BadGeometry))
+            );
+        }
+    }
+
+    /// The offset of the directory block holding `name` and of its entry.
+    fn entry_of(image: &[u8], name: &[u8]) -> (usize, usize) {
+        let at = image
+            .windows(name.len())
+            .position(|window| window == name)
+            .unwrap();
+        (at / BLOCK_SIZE * BLOCK_SIZE, at - DIR_ENTRY_HEADER)
+    }
+
+    #[test]
+    fn rejects_corrupt_directory_entries() {
+        // An unused entry whose name runs past its record.
+        let mut image = golden();
+        let (_, entry) = entry_of(&image, b"empty");
+        image[entry..][..4].fill(0);
+        image[entry + 6] = 255;
+        let mut fs = Ext2::mount(RamDisk::from_bytes(&mut image)).unwrap();
+        assert_eq!(fs.open("/hello.txt"), Err(Error::Corrupted));
+
+        // A last entry that leaves less than a header at the end of the block.
+        let mut image = golden();
+        let (block, mut entry) = entry_of(&image, b"hello.txt");
+        loop {
+            let rec_len = usize::from(layout::read_u16(&image[entry..], 4));
+            if entry + rec_len == block + BLOCK_SIZE {
+                break;
+            }
+            entry += rec_len;
+        }
+        let rec_len = (block + BLOCK_SIZE - 4 - entry) as u16;
+        image[entry + 4..][..2].copy_from_slice(&rec_len.to_le_bytes());
+        let mut fs = Ext2::mount(RamDisk::from_bytes(&mut image)).unwrap();
+        assert_eq!(fs.open("/missing"), Err(Error::Corrupted));
+    }
+}
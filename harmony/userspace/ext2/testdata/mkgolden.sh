@@ -0,0 +1,20 @@
+#!/bin/sh
+# Regenerates golden.img, the ext2 image used by the unit tests.
+set -e
+cd "$(dirname "$0")"
+root=$(mktemp -d)
+trap 'rm -rf "$root"' EXIT
+
+printf 'Hello, Harmony!\n' > "$root/hello.txt"
+mkdir -p "$root/docs/nested"
+printf 'ext2 golden image\n' > "$root/docs/readme.txt"
+printf 'deep\n' > "$root/docs/nested/deep.txt"
+: > "$root/empty"
+# 20 KiB spans the direct blocks and into the single indirect block.
+python3 -c 'import sys; sys.stdout.buffer.write(bytes(i % 251 for i in range(20480)))' > "$root/big.bin"
+
+rm -f golden.img
+E2FSPROGS_FAKE_TIME=1700000000 mke2fs -q -F -t ext2 -b 1024 -N 32 -m 0 \
+    -O none,filetype,sparse_super \
+    -U 6861726d-6f6e-7900-0000-000000000001 -E hash_seed=6861726d-6f6e-7900-0000-000000000002 \
+    -L golden -d "$root" golden.img 128
@@ -0,0 +1,66 @@
+//! The client side: sends requests and times the round trips.
+
+use core::hint::spin_loop;
+
+use librs::kapi::diagnostics::LatencyStats;
+use librs::kapi::userspace::time::Instant;
+
+use crate::ring::Ring;
+use crate::server::Server;
+use crate::{MAX_PAYLOAD, RING_SIZE};
+
+pub struct Client<'a> {
+    requests: &'a Ring<RING_SIZE>,
+    replies: &'a Ring<RING_SIZE>,
+    reply: [u8; MAX_PAYLOAD],
+}
+
+impl<'a> Client<'a> {
+    pub fn new(requests: &'a Ring<RING_SIZE>, replies: &'a Ring<RING_SIZE>) -> Self {
+        Self {
+            requests,
+            replies,
+            reply: [0; MAX_PAYLOAD],
+        }
+    }
+
+    /// Sends `payload` and waits for the echo, letting `server` run while
+    /// waiting.
+    ///
+    /// FIXME: Poll in a loop of its own once the server can run in another
+    /// component.
+    pub fn call(&mut self, payload: &[u8], server: &mut Server) {
+        while !self.requests.push(payload) {
+            server.poll();
+        }
+        loop {
+            if let Some(len) = self.replies.pop(&mut self.reply) {
+                assert_eq!(&self.reply[..len], payload, "Bad echo");
+                return;
+            }
+            if server.poll() == 0 {
+                spin_loop();
+            }
+        }
+    }
+
+    /// Times `iterations` calls with `len` bytes after a few untimed ones.
+    pub fn measure(&mut self, server: &mut Server, len: usize, iterations: usize) -> LatencyStats {
+        let mut payload = [0; MAX_PAYLOAD];
+        payload
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, byte)| *byte = i as u8);
+        let payload = &payload[..len];
+        for _ in 0..iterations / 10 {
+            self.call(payload, server);
+        }
+        let mut stats = LatencyStats::new();
+        for _ in 0..iterations {
+            let start = Instant::now();
+            self.call(payload, server);
+            stats.record(start.elapsed().as_nanos() as u64);
+        }
+        stats
+    }
+}
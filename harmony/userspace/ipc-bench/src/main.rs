@@ -0,0 +1,190 @@
+//! Single-component IPC baseline.
+//!
+//! This isn't an IPC benchmark yet. Components can't start other components,
+//! so everything below runs on one thread of one component and no row pays
+//! for a context switch or an address space switch. The rows are the floor a
+//! real IPC path would be measured against. Once done it prints a table of
+//! percentiles per row, in nanoseconds:
+//!
+//! - `syscall`: a clock read, the least any transport through the kernel
+//!   costs.
+//! - `memcpy/<bytes>`: a request copied into a ring in memory, copied back
+//!   out and echoed through a second ring by the same thread. It measures the
+//!   copies, not the ring as a transport between components.
+//! - `endpoint`: a message sent to an endpoint and received back by the same
+//!   thread, so two syscalls without a wakeup.
+//!
+//! FIXME: Measure IPC between two components once a component can start
+//! another. Boot it in place of the booter with
+//! `make emulate BOOT_COMPONENT=ipc-bench`.
+#![no_std]
+#![no_main]
+
+mod client;
+mod ring;
+mod server;
+
+use librs::kapi::addr::PAGE_SIZE;
+use librs::kapi::diagnostics::LatencyStats;
+use librs::kapi::layout::STANDARD;
+use librs::kapi::ops::cap_table::{
+    CapTableOp, ConstructArgs, RawConstructArgs, BOOT_CAP_TABLE_CAP, SLOT_COUNT,
+};
+use librs::kapi::ops::clock::{ClockOp, BOOT_CLOCK_CAP};
+use librs::kapi::ops::endpoint::{EndpointOp, Message};
+use librs::kapi::ops::region::{RegionOp, BOOT_REGION_CAP};
+use librs::kapi::ops::SyscallOp as _;
+use librs::kapi::raw::{CapError, CapId};
+use librs::kapi::userspace::time::{self, Instant};
+use librs::println;
+
+use crate::client::Client;
+use crate::ring::Ring;
+use crate::server::Server;
+
+/// Bytes of each ring.
+pub const RING_SIZE: usize = 16 * 1024;
+/// Largest request the benchmark sends.
+pub const MAX_PAYLOAD: usize = 4096;
+const PAYLOADS: [usize; 4] = [8, 64, 512, MAX_PAYLOAD];
+const ITERATIONS: usize = 1000;
+/// Slot of the endpoint in the component's table.
+const ENDPOINT_CAP: CapId = CapId::new(11);
+
+static REQUESTS: Ring<RING_SIZE> = Ring::new();
+static REPLIES: Ring<RING_SIZE> = Ring::new();
+
+librs::entry!(main, stack = 64 * 1024);
+
+#[cfg(not(test))]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    println!("{}", info);
+    loop {}
+}
+
+fn print_header() {
+    println!(
+        "{:16}{:>10}{:>10}{:>10}{:>10}{:>10}{:>10}{:>10}",
+        "name", "samples", "min", "avg", "p50", "p90", "p99", "max"
+    );
+}
+
+fn print_row(name: core::fmt::Arguments, stats: &LatencyStats) {
+    let [p50, p90, p99] = [50, 90, 99].map(|percent| stats.percentile_ns(percent).unwrap_or(0));
+    println!(
+        "{:16}{:>10}{:>10}{:>10}{:>10}{:>10}{:>10}{:>10}",
+        // Padding only applies to strings.
+        Padded(name),
+        stats.count,
+        stats.min_ns,
+        stats.average_ns().unwrap_or(0),
+        p50,
+        p90,
+        p99,
+        stats.max_ns
+    );
+}
+
+/// Pads formatted arguments to the width of the field.
+struct Padded<'a>(core::fmt::Arguments<'a>);
+
+impl core::fmt::Display for Padded<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut counter = Counter(0);
+        core::fmt::write(&mut counter, self.0)?;
+        f.write_fmt(self.0)?;
+        let width = f.width().unwrap_or(0);
+        (counter.0..width).try_for_each(|_| f.write_str(" "))
+    }
+}
+
+struct Counter(usize);
+
+impl core::fmt::Write for Counter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.0 += s.chars().count();
+        Ok(())
+    }
+}
+
+/// Builds a one page endpoint in [`ENDPOINT_CAP`] out of two free frames of
+/// the boot region, which covers all of physical memory.
+///
+/// The whole region is mapped as a window and frames are tried from the top
+/// down, since the ones already in use can't be retyped.
+fn construct_endpoint() -> Result<CapId, CapError> {
+    let window = STANDARD.untyped.start;
+    // SAFETY: Windows aren't accessible to the component.
+    let frames = unsafe {
+        RegionOp::Map { base: window }.syscall(BOOT_REGION_CAP)?;
+        RegionOp::Frames.syscall(BOOT_REGION_CAP)?
+    };
+    for frame in (1..frames).rev() {
+        let args = RawConstructArgs::from(ConstructArgs::Endpoint {
+            buffer: window + (frame - 1) * PAGE_SIZE,
+            pages: 1,
+        });
+        let construct = CapTableOp::<SLOT_COUNT>::Construct {
+            args: &args,
+            region: window + frame * PAGE_SIZE,
+            slot: usize::from(ENDPOINT_CAP).try_into().unwrap(),
+        };
+        // SAFETY: Nothing else uses the slot.
+        match unsafe { construct.syscall(BOOT_CAP_TABLE_CAP) } {
+            Ok(()) => return Ok(ENDPOINT_CAP),
+            Err(CapError::InvalidArgument) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(CapError::OutOfMemory)
+}
+
+fn main() -> ! {
+    time::init(BOOT_CLOCK_CAP).expect("No clock");
+    println!("ipc-bench: {ITERATIONS} single-thread round trips per row, in nanoseconds");
+    print_header();
+
+    let mut stats = LatencyStats::new();
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        // SAFETY: Reading the clock doesn't touch any memory.
+        unsafe { ClockOp::GetTimeNs.syscall(BOOT_CLOCK_CAP) }.expect("Clock read failed");
+        stats.record(start.elapsed().as_nanos() as u64);
+    }
+    print_row(format_args!("syscall"), &stats);
+
+    let mut client = Client::new(&REQUESTS, &REPLIES);
+    let mut server = Server::new(&REQUESTS, &REPLIES);
+    for len in PAYLOADS {
+        let stats = client.measure(&mut server, len, ITERATIONS);
+        print_row(format_args!("memcpy/{len}"), &stats);
+    }
+    match construct_endpoint() {
+        Ok(endpoint) => {
+            let send = EndpointOp::Send {
+                message: [1, 2, 3, 4],
+            };
+            let mut message: Message = [0; 4];
+            let receive = EndpointOp::Receive {
+                buffer: &mut message,
+            };
+            let mut stats = LatencyStats::new();
+            for _ in 0..ITERATIONS {
+                let start = Instant::now();
+                // SAFETY: The buffer outlives the receive.
+                unsafe {
+                    send.syscall(endpoint).expect("Send failed");
+                    receive.syscall(endpoint).expect("Receive failed");
+                }
+                stats.record(start.elapsed().as_nanos() as u64);
+            }
+            assert_eq!(message, [1, 2, 3, 4], "Bad echo");
+            print_row(format_args!("endpoint"), &stats);
+        }
+        Err(e) => println!("endpoint: skipped, can't construct one: {e:?}"),
+    }
+    loop {
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,99 @@
+//! A single-producer, single-consumer ring of messages in shared memory.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Bytes of the length before every message.
+const HEADER: usize = 4;
+
+pub struct Ring<const N: usize> {
+    /// Bytes ever read.
+    head: AtomicUsize,
+    /// Bytes ever written.
+    tail: AtomicUsize,
+    data: UnsafeCell<[u8; N]>,
+}
+
+// SAFETY: The producer only writes the free bytes, from `tail` up to
+// `head + N`, and the consumer only reads the ones from `head` to `tail`.
+unsafe impl<const N: usize> Sync for Ring<N> {}
+
+impl<const N: usize> Ring<N> {
+    pub const fn new() -> Self {
+        Self {
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            data: UnsafeCell::new([0; N]),
+        }
+    }
+
+    /// Queues `message`, returning whether there was room for it.
+    ///
+    /// Must only be called by the producer.
+    pub fn push(&self, message: &[u8]) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        let Ok(len) = u32::try_from(message.len()) else {
+            return false;
+        };
+        let size = HEADER + message.len();
+        if N - tail.wrapping_sub(head) < size {
+            return false;
+        }
+        self.copy_in(tail, &len.to_le_bytes());
+        self.copy_in(tail.wrapping_add(HEADER), message);
+        self.tail.store(tail.wrapping_add(size), Ordering::Release);
+        true
+    }
+
+    /// Takes the oldest message into `buf` and returns its length, cutting
+    /// it short if `buf` is too small.
+    ///
+    /// Must only be called by the consumer.
+    pub fn pop(&self, buf: &mut [u8]) -> Option<usize> {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let mut len = [0; HEADER];
+        self.copy_out(head, &mut len);
+        let len = u32::from_le_bytes(len) as usize;
+        let copied = len.min(buf.len());
+        self.copy_out(head.wrapping_add(HEADER), &mut buf[..copied]);
+        self.head
+            .store(head.wrapping_add(HEADER + len), Ordering::Release);
+        Some(copied)
+    }
+
+    /// Splits `len` bytes at position `at` into the parts before and after
+    /// the end of the buffer.
+    fn split(at: usize, len: usize) -> (usize, usize) {
+        let start = at % N;
+        (start, len.min(N - start))
+    }
+
+    fn copy_in(&self, at: usize, bytes: &[u8]) {
+        let data = self.data.get().cast::<u8>();
+        let (start, first) = Self::split(at, bytes.len());
+        // SAFETY: The bytes are free and in the buffer, and only the producer
+        // writes them.
+        unsafe {
+            data.add(start)
+                .copy_from_nonoverlapping(bytes.as_ptr(), first);
+            data.copy_from_nonoverlapping(bytes[first..].as_ptr(), bytes.len() - first);
+        }
+    }
+
+    fn copy_out(&self, at: usize, bytes: &mut [u8]) {
+        let data = self.data.get().cast::<u8>();
+        let (start, first) = Self::split(at, bytes.len());
+        // SAFETY: The bytes were written and are in the buffer, and only the
+        // consumer reads them.
+        unsafe {
+            data.add(start)
+                .copy_to_nonoverlapping(bytes.as_mut_ptr(), first);
+            data.copy_to_nonoverlapping(bytes[first..].as_mut_ptr(), bytes.len() - first);
+        }
+    }
+}
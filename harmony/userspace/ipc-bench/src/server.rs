@@ -0,0 +1,32 @@
+//! The server side: echoes every request back.
+
+use crate::ring::Ring;
+use crate::{MAX_PAYLOAD, RING_SIZE};
+
+pub struct Server<'a> {
+    requests: &'a Ring<RING_SIZE>,
+    replies: &'a Ring<RING_SIZE>,
+    buffer: [u8; MAX_PAYLOAD],
+}
+
+impl<'a> Server<'a> {
+    pub fn new(requests: &'a Ring<RING_SIZE>, replies: &'a Ring<RING_SIZE>) -> Self {
+        Self {
+            requests,
+            replies,
+            buffer: [0; MAX_PAYLOAD],
+        }
+    }
+
+    /// Answers the pending requests, returning how many there were.
+    pub fn poll(&mut self) -> usize {
+        let mut served = 0;
+        while let Some(len) = self.requests.pop(&mut self.buffer) {
+            while !self.replies.push(&self.buffer[..len]) {
+                core::hint::spin_loop();
+            }
+            served += 1;
+        }
+        served
+    }
+}
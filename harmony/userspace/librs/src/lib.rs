@@ -3,9 +3,26 @@
 pub use kapi;
 
 pub mod serial {
+    //! Output to the kernel's serial console through a logger capability.
+    //!
+    //! The kernel sends it to whichever backend it was booted with, the UART
+    //! or QEMU's debug console.
+
     use core::fmt::Write;
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    use kapi::ops::logger::{LoggerOp, BOOT_LOGGER_CAP, MAX_WRITE_LEN};
+    use kapi::ops::SyscallOp as _;
+    use kapi::raw::CapId;
+
+    /// The logger capability, or `u32::MAX` for [`BOOT_LOGGER_CAP`].
+    static LOGGER: AtomicU32 = AtomicU32::new(u32::MAX);
 
-    use kapi::raw::raw_syscall;
+    /// Writes through the logger capability in `cap` from now on instead of
+    /// [`BOOT_LOGGER_CAP`].
+    pub fn set_logger(cap: CapId) {
+        LOGGER.store(cap.into(), Ordering::Relaxed);
+    }
 
     #[macro_export]
     macro_rules! print {
@@ -23,9 +40,26 @@ pub mod serial {
     }};
 }
 
-    fn write(msg: &str) {
-        let msg = msg.as_bytes();
-        unsafe { raw_syscall(usize::MAX, 0, msg.as_ptr() as usize, msg.len(), 0, 0) };
+    pub(crate) fn write(mut msg: &str) {
+        let logger = match LOGGER.load(Ordering::Relaxed) {
+            u32::MAX => BOOT_LOGGER_CAP,
+            cap => CapId::new(cap),
+        };
+        while !msg.is_empty() {
+            let mut len = msg.len().min(MAX_WRITE_LEN);
+            while !msg.is_char_boundary(len) {
+                len -= 1;
+            }
+            let (chunk, rest) = msg.split_at(len);
+            let op = LoggerOp::Write {
+                buffer: chunk.as_ptr(),
+                len: chunk.len(),
+            };
+            // SAFETY: The kernel only reads the chunk. There's nowhere to
+            // report the output being lost to.
+            let _ = unsafe { op.syscall(logger) };
+            msg = rest;
+        }
     }
 
     struct DebugOut;
@@ -40,4 +74,375 @@ pub mod serial {
             Ok(())
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        extern crate std;
+
+        use std::vec::Vec;
+
+        use kapi::raw::RawOperation;
+        use kapi::testing::{MockKernel, MockResource};
+
+        use super::*;
+
+        #[test]
+        fn splits_long_writes_on_char_boundaries() {
+            let mut kernel = MockKernel::new();
+            kernel
+                .insert(BOOT_LOGGER_CAP, MockResource::Logger)
+                .unwrap();
+            let kernel = kernel.install();
+
+            // A two byte character straddles the end of the first chunk.
+            let mut text = [b'a'; MAX_WRITE_LEN + 1];
+            text[MAX_WRITE_LEN - 1..].copy_from_slice("é".as_bytes());
+            write(core::str::from_utf8(&text).unwrap());
+            kernel.with(|kernel| {
+                assert_eq!(kernel.ops(), [RawOperation::LoggerWrite; 2]);
+                let lens: Vec<_> = kernel
+                    .invocations()
+                    .iter()
+                    .map(|call| call.args.args().1)
+                    .collect();
+                assert_eq!(lens, [MAX_WRITE_LEN - 1, 2]);
+            });
+        }
+    }
+}
+
+pub mod logger {
+    //! A logger that writes records to the serial port through the kernel,
+    //! formatted like the kernel's own.
+    //!
+    //! Records carry the time since boot once `kapi::userspace::time` is
+    //! initialized. `LOG_FORMAT` picks the [`Style`] at compile time.
+
+    use core::fmt::Write;
+    use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+    use kapi::logfmt::{self, Line, Style};
+    use kapi::userspace::time::{self, Instant};
+    use log::{Level, LevelFilter, Metadata, Record, SetLoggerError};
+
+    /// Records are written in pieces of up to this many bytes.
+    const BUFFER_SIZE: usize = 256;
+
+    static COMPONENT: AtomicPtr<u8> = AtomicPtr::new(core::ptr::null_mut());
+    static COMPONENT_LEN: AtomicUsize = AtomicUsize::new(0);
+    static LOGGER: Logger = Logger;
+
+    /// Logs records up to `level` under the name `component`.
+    ///
+    /// Fails if a logger was already set.
+    pub fn init(component: &'static str, level: LevelFilter) -> Result<(), SetLoggerError> {
+        log::set_logger(&LOGGER)?;
+        COMPONENT_LEN.store(component.len(), Ordering::Relaxed);
+        COMPONENT.store(component.as_ptr().cast_mut(), Ordering::Release);
+        log::set_max_level(level);
+        Ok(())
+    }
+
+    fn component() -> &'static str {
+        let ptr = COMPONENT.load(Ordering::Acquire);
+        if ptr.is_null() {
+            return "?";
+        }
+        let len = COMPONENT_LEN.load(Ordering::Relaxed);
+        // SAFETY: Stored from a `&'static str` in `init`.
+        unsafe { core::str::from_utf8_unchecked(core::slice::from_raw_parts(ptr, len)) }
+    }
+
+    fn style() -> Style {
+        let format = option_env!("LOG_FORMAT").unwrap_or("color");
+        match Style::parse(format) {
+            Some(style) => style,
+            None => panic!("Unknown log format: {format}"),
+        }
+    }
+
+    /// Collects a record so that it reaches the kernel in as few writes as
+    /// possible and isn't split up by other threads' output.
+    struct LineBuffer {
+        bytes: [u8; BUFFER_SIZE],
+        len: usize,
+    }
+
+    impl LineBuffer {
+        fn flush(&mut self) {
+            // Only whole `str`s are ever pushed.
+            let text = core::str::from_utf8(&self.bytes[..self.len]).unwrap_or_default();
+            super::serial::write(text);
+            self.len = 0;
+        }
+    }
+
+    impl Write for LineBuffer {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            if self.len + s.len() > BUFFER_SIZE {
+                self.flush();
+            }
+            if s.len() > BUFFER_SIZE {
+                super::serial::write(s);
+                return Ok(());
+            }
+            self.bytes[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+            Ok(())
+        }
+    }
+
+    struct Logger;
+
+    impl log::Log for Logger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= log::max_level()
+        }
+
+        fn log(&self, record: &Record) {
+            if !self.enabled(record.metadata()) {
+                return;
+            }
+            let level = match record.level() {
+                Level::Error => logfmt::Level::Error,
+                Level::Warn => logfmt::Level::Warn,
+                Level::Info => logfmt::Level::Info,
+                Level::Debug => logfmt::Level::Debug,
+                Level::Trace => logfmt::Level::Trace,
+            };
+            let record = logfmt::Record {
+                nanos: time::is_initialized().then(|| Instant::now().as_nanos()),
+                level,
+                component: component(),
+                module: record.module_path().unwrap_or(record.target()),
+                args: *record.args(),
+            };
+            let mut buffer = LineBuffer {
+                bytes: [0; BUFFER_SIZE],
+                len: 0,
+            };
+            let line = Line {
+                style: style(),
+                record: &record,
+            };
+            let _ = writeln!(buffer, "{line}");
+            buffer.flush();
+        }
+
+        fn flush(&self) {}
+    }
+}
+
+pub mod stack {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use kapi::stack::{self as paint, StackUsage, PAINT_MARGIN};
+    use kapi::userspace::vmm::PAGE_SIZE;
+
+    /// Address one past the top of the stack, or 0 if it wasn't painted.
+    static TOP: AtomicUsize = AtomicUsize::new(0);
+    static SIZE: AtomicUsize = AtomicUsize::new(0);
+
+    /// Defines the component's entry point.
+    ///
+    /// Declares the size of the initial stack to the loader, paints the stack
+    /// so that [`stack::usage`](crate::stack::usage) can report its peak usage
+    /// and then calls `main`.
+    ///
+    /// ```ignore
+    /// librs::entry!(main, stack = 64 * 1024);
+    ///
+    /// fn main() -> ! {
+    ///     loop {}
+    /// }
+    /// ```
+    #[macro_export]
+    macro_rules! entry {
+        ($main:path, stack = $size:expr) => {
+            #[used]
+            #[link_section = ".note.harmony"]
+            static __STACK_SIZE: $crate::kapi::component::Note<8> =
+                $crate::kapi::component::stack_size($size);
+
+            #[no_mangle]
+            extern "C" fn _start() -> ! {
+                // SAFETY: We just entered the component at the top of its stack.
+                unsafe { $crate::stack::init($size) };
+                $main()
+            }
+        };
+    }
+
+    /// Paints the part of the stack below the current frame.
+    ///
+    /// # Safety
+    ///
+    /// Must be called once, from the entry point, on a stack of at least
+    /// `size` bytes whose top is page aligned.
+    #[doc(hidden)]
+    pub unsafe fn init(size: usize) {
+        let rsp = paint::stack_pointer();
+        let top = rsp.next_multiple_of(PAGE_SIZE);
+        let bottom = top.saturating_sub(size);
+        // SAFETY: Precondition. Nothing lives below the stack pointer.
+        unsafe { paint::paint(bottom, rsp.saturating_sub(PAINT_MARGIN)) };
+        SIZE.store(size, Ordering::Relaxed);
+        TOP.store(top, Ordering::Release);
+    }
+
+    /// Returns the peak usage of the initial stack, for the component to
+    /// report to its supervisor.
+    ///
+    /// `None` unless the component was started with [`entry!`](crate::entry).
+    pub fn usage() -> Option<StackUsage> {
+        let top = TOP.load(Ordering::Acquire);
+        if top == 0 {
+            return None;
+        }
+        let bottom = top - SIZE.load(Ordering::Relaxed);
+        // SAFETY: The stack stays mapped while the component runs.
+        Some(unsafe { paint::usage(bottom, top) })
+    }
+}
+
+pub mod caps {
+    use kapi::ops::cap_table::{CapTableOp, SLOT_COUNT};
+    use kapi::ops::SyscallOp as _;
+    use kapi::raw::{CapError, CapId};
+
+    /// Hands out slots in the component's own capability table.
+    ///
+    /// The table is a trie of page-sized nodes. Slots past the first node are
+    /// made addressable by asking the kernel to link new nodes, which are built
+    /// in untyped pages provided by `next_region`.
+    pub struct SelfCapabilityManager<F> {
+        /// Capability to the component's own table.
+        table: CapId,
+        next: u32,
+        next_region: F,
+    }
+
+    impl<F: FnMut() -> Option<usize>> SelfCapabilityManager<F> {
+        /// Creates a manager that hands out slots starting at `first`.
+        pub fn new(table: CapId, first: u32, next_region: F) -> Self {
+            Self {
+                table,
+                next: first,
+                next_region,
+            }
+        }
+
+        pub fn allocate_capability(&mut self) -> Result<CapId, CapError> {
+            let cap = CapId::new(self.next);
+            if self.next as usize >= SLOT_COUNT {
+                self.extend(cap)?;
+            }
+            self.next += 1;
+            Ok(cap)
+        }
+
+        /// Links the nodes needed to address `cap`, one page at a time.
+        fn extend(&mut self, cap: CapId) -> Result<(), CapError> {
+            let mut region = 0;
+            loop {
+                let op = CapTableOp::<SLOT_COUNT>::Extend {
+                    cap,
+                    region,
+                    frames: usize::from(region != 0),
+                };
+                // SAFETY: Extending the table doesn't change any existing slot.
+                match unsafe { op.syscall(self.table) } {
+                    Ok(()) => return Ok(()),
+                    Err(CapError::OutOfMemory) => {
+                        region = (self.next_region)().ok_or(CapError::OutOfMemory)?;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use kapi::raw::RawOperation;
+        use kapi::testing::{MockKernel, MockResource};
+
+        use super::*;
+
+        #[test]
+        fn extends_the_table_past_the_first_node() {
+            let mut kernel = MockKernel::new();
+            let root = kernel.root();
+            kernel
+                .insert(CapId::new(0), MockResource::CapTable(root))
+                .unwrap();
+            kernel.add_untyped(0x1000, 2);
+            let kernel = kernel.install();
+
+            let mut regions = [0x1000, 0x2000].into_iter();
+            let mut caps = SelfCapabilityManager::new(CapId::new(0), 1, || regions.next());
+            for id in 1..SLOT_COUNT as u32 {
+                assert_eq!(caps.allocate_capability(), Ok(CapId::new(id)));
+            }
+            kernel.with(|kernel| assert!(kernel.invocations().is_empty()));
+
+            let cap = caps.allocate_capability().unwrap();
+            kernel.with(|kernel| {
+                // The first attempt has no memory and asks for a region.
+                assert_eq!(
+                    kernel.ops(),
+                    [RawOperation::CapTableExtend, RawOperation::CapTableExtend]
+                );
+                assert_eq!(kernel.untyped_left(), 1);
+                kernel.insert(cap, MockResource::Logger).unwrap();
+            });
+            // Ids are indexed by their low digits first, so the next id hangs
+            // off a different slot of the root table.
+            caps.allocate_capability().unwrap();
+            assert_eq!(caps.allocate_capability(), Err(CapError::OutOfMemory));
+            kernel.with(|kernel| assert_eq!(kernel.untyped_left(), 0));
+        }
+    }
+}
+
+pub mod info {
+    use core::arch::x86_64::{__rdtscp, _rdtsc};
+    use core::sync::atomic::Ordering;
+    use core::time::Duration;
+
+    use kapi::info::{KernelInfo, FEATURE_RDTSCP, INFO_PAGE_ADDRESS};
+
+    /// Returns the kernel info page mapped into this address space.
+    pub fn kernel_info() -> &'static KernelInfo {
+        // SAFETY: The kernel maps the info page in every address space it builds.
+        unsafe { &*(INFO_PAGE_ADDRESS as *const KernelInfo) }
+    }
+
+    /// Returns the monotonic time elapsed since boot.
+    pub fn now() -> Duration {
+        let info = kernel_info();
+        // SAFETY: rdtsc has no side effects.
+        let tsc = unsafe { _rdtsc() };
+        match info.tsc_to_nanos(tsc) {
+            Some(nanos) => Duration::from_nanos(nanos),
+            None => {
+                let ticks = info.ticks.load(Ordering::Relaxed);
+                let frequency = info.tick_frequency.load(Ordering::Relaxed).max(1);
+                Duration::from_nanos(ticks * 1_000_000_000 / frequency)
+            }
+        }
+    }
+
+    /// Returns the index of the core this thread is executing on.
+    pub fn cpu_id() -> u32 {
+        let info = kernel_info();
+        if !info.has_feature(FEATURE_RDTSCP) {
+            // The kernel only runs a single core without rdtscp.
+            return 0;
+        }
+        let mut index = 0;
+        // SAFETY: The kernel checked that rdtscp is supported.
+        unsafe { __rdtscp(&mut index) };
+        index
+    }
 }
@@ -0,0 +1,83 @@
+//! Ring of previously entered lines.
+
+/// The last `ENTRIES` lines of up to `LEN` bytes, newest first.
+#[derive(Debug, Clone)]
+pub struct History<const LEN: usize, const ENTRIES: usize> {
+    lines: [[u8; LEN]; ENTRIES],
+    lens: [usize; ENTRIES],
+    /// Slot the next line goes into.
+    next: usize,
+    count: usize,
+}
+
+impl<const LEN: usize, const ENTRIES: usize> Default for History<LEN, ENTRIES> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const LEN: usize, const ENTRIES: usize> History<LEN, ENTRIES> {
+    pub const fn new() -> Self {
+        Self {
+            lines: [[0; LEN]; ENTRIES],
+            lens: [0; ENTRIES],
+            next: 0,
+            count: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Records `line`, overwriting the oldest line once the ring is full.
+    ///
+    /// Blank lines and repeats of the newest line aren't recorded. Lines
+    /// longer than `LEN` are truncated.
+    pub fn push(&mut self, line: &[u8]) {
+        if ENTRIES == 0 || line.iter().all(u8::is_ascii_whitespace) || self.get(0) == Some(line) {
+            return;
+        }
+        let len = line.len().min(LEN);
+        self.lines[self.next][..len].copy_from_slice(&line[..len]);
+        self.lens[self.next] = len;
+        self.next = (self.next + 1) % ENTRIES;
+        self.count = (self.count + 1).min(ENTRIES);
+    }
+
+    /// The line entered `age` lines ago, 0 being the newest.
+    pub fn get(&self, age: usize) -> Option<&[u8]> {
+        if age >= self.count {
+            return None;
+        }
+        let slot = (self.next + ENTRIES - 1 - age) % ENTRIES;
+        Some(&self.lines[slot][..self.lens[slot]])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_newest_lines() {
+        let mut history = History::<4, 3>::new();
+        assert_eq!(history.get(0), None);
+        for line in [&b"a"[..], b"bb", b"bb", b"  ", b"ccccc", b"d"] {
+            history.push(line);
+        }
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.get(0), Some(&b"d"[..]));
+        assert_eq!(history.get(1), Some(&b"cccc"[..]));
+        assert_eq!(history.get(2), Some(&b"bb"[..]));
+        assert_eq!(history.get(3), None);
+
+        let mut empty = History::<4, 0>::new();
+        empty.push(b"a");
+        assert!(empty.is_empty());
+    }
+}
@@ -0,0 +1,183 @@
+//! Decoding of terminal input into keys.
+
+/// An editing command read from the terminal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Key {
+    /// A printable ASCII character.
+    Char(u8),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    /// Recalls the previous line in the history.
+    Up,
+    /// Recalls the next line in the history.
+    Down,
+    Tab,
+    /// Deletes from the cursor to the end of the line (`Ctrl-K`).
+    KillLine,
+    /// Deletes from the start of the line to the cursor (`Ctrl-U`).
+    KillToStart,
+    /// Deletes the word before the cursor (`Ctrl-W`).
+    KillWord,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Ground,
+    /// After a carriage return, so that the line feed of a CRLF isn't a
+    /// second enter.
+    Return,
+    Escape,
+    /// Inside a control sequence, with the numeric parameter read so far.
+    Csi(u8),
+}
+
+/// Turns the bytes a VT100 compatible terminal sends into [`Key`]s.
+///
+/// Besides the arrow, home, end and delete keys, the emacs style control keys
+/// that readline users expect are understood. Anything else is dropped.
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    state: State,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Ground,
+        }
+    }
+
+    /// Feeds the next byte of input, returning the key it completes.
+    pub fn feed(&mut self, byte: u8) -> Option<Key> {
+        let state = core::mem::replace(&mut self.state, State::Ground);
+        match state {
+            State::Ground | State::Return => self.ground(state, byte),
+            State::Escape => {
+                match byte {
+                    b'[' | b'O' => self.state = State::Csi(0),
+                    // An escape on its own, start over.
+                    0x1B => self.state = State::Escape,
+                    _ => {}
+                }
+                None
+            }
+            State::Csi(param) => match byte {
+                b'0'..=b'9' => {
+                    self.state = State::Csi(param.saturating_mul(10).saturating_add(byte - b'0'));
+                    None
+                }
+                b'A' => Some(Key::Up),
+                b'B' => Some(Key::Down),
+                b'C' => Some(Key::Right),
+                b'D' => Some(Key::Left),
+                b'H' => Some(Key::Home),
+                b'F' => Some(Key::End),
+                b'~' => match param {
+                    1 | 7 => Some(Key::Home),
+                    3 => Some(Key::Delete),
+                    4 | 8 => Some(Key::End),
+                    _ => None,
+                },
+                _ => None,
+            },
+        }
+    }
+
+    fn ground(&mut self, state: State, byte: u8) -> Option<Key> {
+        match byte {
+            b'\n' if state == State::Return => None,
+            b'\r' => {
+                self.state = State::Return;
+                Some(Key::Enter)
+            }
+            b'\n' => Some(Key::Enter),
+            0x1B => {
+                self.state = State::Escape;
+                None
+            }
+            0x01 => Some(Key::Home),
+            0x02 => Some(Key::Left),
+            0x04 => Some(Key::Delete),
+            0x05 => Some(Key::End),
+            0x06 => Some(Key::Right),
+            0x08 | 0x7F => Some(Key::Backspace),
+            b'\t' => Some(Key::Tab),
+            0x0B => Some(Key::KillLine),
+            0x0E => Some(Key::Down),
+            0x10 => Some(Key::Up),
+            0x15 => Some(Key::KillToStart),
+            0x17 => Some(Key::KillWord),
+            b' '..=b'~' => Some(Key::Char(byte)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(input: &[u8]) -> Vec<Key> {
+        let mut decoder = Decoder::new();
+        input
+            .iter()
+            .filter_map(|&byte| decoder.feed(byte))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_escape_sequences() {
+        assert_eq!(
+            decode(b"a\x1b[A\x1b[B\x1b[C\x1b[D\x1bOH\x1b[F\x1b[3~\x1b[1~\x1b[4~"),
+            [
+                Key::Char(b'a'),
+                Key::Up,
+                Key::Down,
+                Key::Right,
+                Key::Left,
+                Key::Home,
+                Key::End,
+                Key::Delete,
+                Key::Home,
+                Key::End,
+            ]
+        );
+        // Unknown sequences are dropped without eating what follows.
+        assert_eq!(
+            decode(b"\x1b[15~x\x1bzy"),
+            [Key::Char(b'x'), Key::Char(b'y')]
+        );
+    }
+
+    #[test]
+    fn decodes_control_keys() {
+        assert_eq!(
+            decode(b"\x01\x05\x0b\x15\x17\x7f\x08\t\x10\x0e"),
+            [
+                Key::Home,
+                Key::End,
+                Key::KillLine,
+                Key::KillToStart,
+                Key::KillWord,
+                Key::Backspace,
+                Key::Backspace,
+                Key::Tab,
+                Key::Up,
+                Key::Down,
+            ]
+        );
+        assert_eq!(decode(b"\r\n\n\r"), [Key::Enter; 3]);
+        assert_eq!(decode("é\x00".as_bytes()), []);
+    }
+}
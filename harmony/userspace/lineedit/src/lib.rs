@@ -0,0 +1,407 @@
+//! Line editing for interactive consoles.
+//!
+//! [`Editor`] keeps the line being typed and turns [`Key`]s into the VT100
+//! escape sequences that update the terminal, so that a shell only has to
+//! feed it the bytes the console reads (through a [`Decoder`]) and run the
+//! lines it hands back. Everything lives in fixed-size buffers so it works
+//! without an allocator. Only printable ASCII is editable.
+#![cfg_attr(not(test), no_std)]
+
+use core::cmp::Ordering;
+use core::fmt::{self, Write};
+
+pub mod history;
+pub mod keys;
+
+pub use history::History;
+pub use keys::{Decoder, Key};
+
+/// Suggests how to finish the word being typed when tab is pressed.
+pub trait Completer {
+    /// Returns the text to insert at the cursor to complete `line`, which
+    /// holds everything before the cursor.
+    fn complete(&mut self, line: &str) -> Option<&str>;
+}
+
+/// Completes nothing.
+impl Completer for () {
+    fn complete(&mut self, _line: &str) -> Option<&str> {
+        None
+    }
+}
+
+/// What the editor did with a key.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// The line is still being edited.
+    Editing,
+    /// Enter was pressed. The line can be read with [`Editor::line`] until the
+    /// next key is handled, which starts a new one.
+    Submitted,
+    /// The key couldn't be applied, e.g. the line is full or the cursor is
+    /// already at the start. Shells usually ring the bell.
+    Refused,
+}
+
+/// A line of up to `LEN` bytes with a history of `HISTORY` lines.
+#[derive(Debug, Clone)]
+pub struct Editor<const LEN: usize, const HISTORY: usize> {
+    line: [u8; LEN],
+    len: usize,
+    cursor: usize,
+    history: History<LEN, HISTORY>,
+    /// Age of the history entry being shown, if any.
+    recalled: Option<usize>,
+    /// The line that was being typed before browsing the history.
+    draft: [u8; LEN],
+    draft_len: usize,
+    submitted: bool,
+}
+
+impl<const LEN: usize, const HISTORY: usize> Default for Editor<LEN, HISTORY> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const LEN: usize, const HISTORY: usize> Editor<LEN, HISTORY> {
+    pub const fn new() -> Self {
+        Self {
+            line: [0; LEN],
+            len: 0,
+            cursor: 0,
+            history: History::new(),
+            recalled: None,
+            draft: [0; LEN],
+            draft_len: 0,
+            submitted: false,
+        }
+    }
+
+    pub fn line(&self) -> &str {
+        // Only printable ASCII is ever inserted.
+        core::str::from_utf8(&self.line[..self.len]).unwrap()
+    }
+
+    /// Position of the cursor in bytes from the start of the line.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn history(&self) -> &History<LEN, HISTORY> {
+        &self.history
+    }
+
+    /// Applies `key` and writes what changed on the screen to `out`.
+    ///
+    /// The screen is updated relative to the cursor, so whatever was printed
+    /// before the line (the prompt) is left alone.
+    pub fn handle(
+        &mut self,
+        key: Key,
+        completer: &mut impl Completer,
+        out: &mut impl Write,
+    ) -> Result<Status, fmt::Error> {
+        if core::mem::take(&mut self.submitted) {
+            self.len = 0;
+            self.cursor = 0;
+        }
+        let applied = match key {
+            Key::Char(byte) => self.insert(&[byte], out)?,
+            Key::Enter => {
+                self.move_to(self.len, out)?;
+                out.write_str("\r\n")?;
+                self.history.push(&self.line[..self.len]);
+                self.recalled = None;
+                self.submitted = true;
+                return Ok(Status::Submitted);
+            }
+            Key::Backspace => self.cursor > 0 && self.delete(self.cursor - 1, self.cursor, out)?,
+            Key::Delete => {
+                self.cursor < self.len && self.delete(self.cursor, self.cursor + 1, out)?
+            }
+            Key::Left => self.cursor > 0 && self.move_to(self.cursor - 1, out)?,
+            Key::Right => self.cursor < self.len && self.move_to(self.cursor + 1, out)?,
+            Key::Home => self.move_to(0, out)?,
+            Key::End => self.move_to(self.len, out)?,
+            Key::KillLine => self.cursor < self.len && self.delete(self.cursor, self.len, out)?,
+            Key::KillToStart => self.cursor > 0 && self.delete(0, self.cursor, out)?,
+            Key::KillWord => {
+                let before = &self.line[..self.cursor];
+                let end = before.iter().rposition(|b| *b != b' ').map_or(0, |i| i + 1);
+                let start = before[..end]
+                    .iter()
+                    .rposition(|b| *b == b' ')
+                    .map_or(0, |i| i + 1);
+                self.cursor > 0 && self.delete(start, self.cursor, out)?
+            }
+            Key::Up => self.recall(self.recalled.map_or(Some(0), |age| age.checked_add(1)), out)?,
+            Key::Down => match self.recalled {
+                Some(age) => self.recall(age.checked_sub(1), out)?,
+                None => false,
+            },
+            Key::Tab => {
+                let prefix = core::str::from_utf8(&self.line[..self.cursor]).unwrap();
+                match completer.complete(prefix) {
+                    Some(text) => self.insert(text.as_bytes(), out)?,
+                    None => false,
+                }
+            }
+        };
+        Ok(if applied {
+            Status::Editing
+        } else {
+            Status::Refused
+        })
+    }
+
+    /// Inserts `text` at the cursor, or nothing if it doesn't all fit.
+    fn insert(&mut self, text: &[u8], out: &mut impl Write) -> Result<bool, fmt::Error> {
+        if text.is_empty()
+            || self.len + text.len() > LEN
+            || !text.iter().all(|b| (b' '..=b'~').contains(b))
+        {
+            return Ok(false);
+        }
+        let at = self.cursor;
+        self.line.copy_within(at..self.len, at + text.len());
+        self.line[at..at + text.len()].copy_from_slice(text);
+        self.len += text.len();
+        self.redraw(at, at + text.len(), out)?;
+        Ok(true)
+    }
+
+    fn delete(
+        &mut self,
+        start: usize,
+        end: usize,
+        out: &mut impl Write,
+    ) -> Result<bool, fmt::Error> {
+        self.move_to(start, out)?;
+        self.line.copy_within(end..self.len, start);
+        self.len -= end - start;
+        self.redraw(start, start, out)?;
+        Ok(true)
+    }
+
+    /// Shows the history entry of `age`, or the draft if `None`.
+    fn recall(&mut self, age: Option<usize>, out: &mut impl Write) -> Result<bool, fmt::Error> {
+        if self.recalled.is_none() {
+            self.draft[..self.len].copy_from_slice(&self.line[..self.len]);
+            self.draft_len = self.len;
+        }
+        let text = match age {
+            Some(age) => match self.history.get(age) {
+                Some(text) => text,
+                None => return Ok(false),
+            },
+            None => &self.draft[..self.draft_len],
+        };
+        // Copy out of the history before touching the line.
+        let mut line = [0; LEN];
+        line[..text.len()].copy_from_slice(text);
+        let len = text.len();
+        self.recalled = age;
+        self.move_to(0, out)?;
+        self.line = line;
+        self.len = len;
+        self.redraw(0, len, out)?;
+        Ok(true)
+    }
+
+    /// Moves the cursor to `position` on the screen.
+    fn move_to(&mut self, position: usize, out: &mut impl Write) -> Result<bool, fmt::Error> {
+        match position.cmp(&self.cursor) {
+            Ordering::Less => write!(out, "\x1b[{}D", self.cursor - position)?,
+            Ordering::Greater => write!(out, "\x1b[{}C", position - self.cursor)?,
+            Ordering::Equal => {}
+        }
+        self.cursor = position;
+        Ok(true)
+    }
+
+    /// Prints the line from `from`, where the cursor is, to the end, clears
+    /// what's left of the old line and leaves the cursor at `cursor`.
+    fn redraw(
+        &mut self,
+        from: usize,
+        cursor: usize,
+        out: &mut impl Write,
+    ) -> Result<(), fmt::Error> {
+        debug_assert_eq!(self.cursor, from);
+        // Only printable ASCII is ever inserted.
+        out.write_str(core::str::from_utf8(&self.line[from..self.len]).unwrap())?;
+        out.write_str("\x1b[K")?;
+        self.cursor = self.len;
+        self.move_to(cursor, out)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A single terminal line that understands what the editor writes.
+    #[derive(Default)]
+    struct Screen {
+        cells: Vec<u8>,
+        column: usize,
+        escape: Option<String>,
+        lines: Vec<String>,
+    }
+
+    impl Write for Screen {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            for byte in s.bytes() {
+                if let Some(escape) = &mut self.escape {
+                    escape.push(byte as char);
+                    if !byte.is_ascii_alphabetic() {
+                        continue;
+                    }
+                    let escape = self.escape.take().unwrap();
+                    let count = escape[1..escape.len() - 1].parse().unwrap_or(1);
+                    match escape.as_bytes()[escape.len() - 1] {
+                        b'D' => self.column -= count,
+                        b'C' => self.column += count,
+                        b'K' => self.cells.truncate(self.column),
+                        other => panic!("unexpected sequence {}", other as char),
+                    }
+                    continue;
+                }
+                match byte {
+                    0x1B => self.escape = Some(String::new()),
+                    b'\r' => self.column = 0,
+                    b'\n' => {
+                        self.lines
+                            .push(String::from_utf8(self.cells.clone()).unwrap());
+                        self.cells.clear();
+                    }
+                    _ => {
+                        if self.column == self.cells.len() {
+                            self.cells.push(byte);
+                        } else {
+                            self.cells[self.column] = byte;
+                        }
+                        self.column += 1;
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl Screen {
+        fn text(&self) -> &str {
+            core::str::from_utf8(&self.cells).unwrap()
+        }
+    }
+
+    struct Commands;
+
+    impl Completer for Commands {
+        fn complete(&mut self, line: &str) -> Option<&str> {
+            "help".strip_prefix(line).filter(|rest| !rest.is_empty())
+        }
+    }
+
+    fn type_keys<const LEN: usize, const HISTORY: usize>(
+        editor: &mut Editor<LEN, HISTORY>,
+        screen: &mut Screen,
+        input: &[u8],
+    ) -> Vec<Status> {
+        let mut decoder = Decoder::new();
+        input
+            .iter()
+            .filter_map(|&byte| decoder.feed(byte))
+            .map(|key| editor.handle(key, &mut Commands, screen).unwrap())
+            .collect()
+    }
+
+    /// Checks that the screen shows the line with the cursor where the editor
+    /// thinks it is.
+    fn assert_shows<const LEN: usize, const HISTORY: usize>(
+        editor: &Editor<LEN, HISTORY>,
+        screen: &Screen,
+        line: &str,
+        cursor: usize,
+    ) {
+        assert_eq!(editor.line(), line);
+        assert_eq!(screen.text(), line);
+        assert_eq!(editor.cursor(), cursor);
+        assert_eq!(screen.column, cursor);
+    }
+
+    #[test]
+    fn edits_in_the_middle_of_the_line() {
+        let mut editor = Editor::<32, 4>::new();
+        let mut screen = Screen::default();
+        type_keys(
+            &mut editor,
+            &mut screen,
+            b"helo world\x1b[D\x1b[D\x1b[D\x1b[D\x1b[D\x1b[D\x1b[Dl",
+        );
+        assert_shows(&editor, &screen, "hello world", 4);
+        type_keys(&mut editor, &mut screen, b"\x1b[3~\x7f\x05!");
+        assert_shows(&editor, &screen, "hel world!", 10);
+        type_keys(&mut editor, &mut screen, b"\x01\x06\x06\x0b");
+        assert_shows(&editor, &screen, "he", 2);
+        type_keys(&mut editor, &mut screen, b"\x05 there  \x17");
+        assert_shows(&editor, &screen, "he ", 3);
+        type_keys(&mut editor, &mut screen, b"\x02\x15");
+        assert_shows(&editor, &screen, " ", 0);
+    }
+
+    #[test]
+    fn refuses_what_doesnt_fit() {
+        let mut editor = Editor::<4, 4>::new();
+        let mut screen = Screen::default();
+        let statuses = type_keys(&mut editor, &mut screen, b"\x7fabcde\x1b[C");
+        assert_eq!(statuses[0], Status::Refused);
+        assert_eq!(&statuses[1..5], [Status::Editing; 4]);
+        assert_eq!(&statuses[5..], [Status::Refused; 2]);
+        assert_shows(&editor, &screen, "abcd", 4);
+    }
+
+    #[test]
+    fn recalls_history() {
+        let mut editor = Editor::<32, 4>::new();
+        let mut screen = Screen::default();
+        let statuses = type_keys(&mut editor, &mut screen, b"first\r\nsecond\r");
+        assert_eq!(statuses.last(), Some(&Status::Submitted));
+        assert_eq!(editor.line(), "second");
+        assert_eq!(screen.lines, ["first", "second"]);
+
+        type_keys(&mut editor, &mut screen, b"dra");
+        assert_shows(&editor, &screen, "dra", 3);
+        type_keys(&mut editor, &mut screen, b"\x1b[A");
+        assert_shows(&editor, &screen, "second", 6);
+        let statuses = type_keys(&mut editor, &mut screen, b"\x1b[A\x1b[A");
+        assert_eq!(statuses, [Status::Editing, Status::Refused]);
+        assert_shows(&editor, &screen, "first", 5);
+        type_keys(&mut editor, &mut screen, b"\x1b[B\x1b[B");
+        assert_shows(&editor, &screen, "dra", 3);
+        assert_eq!(
+            type_keys(&mut editor, &mut screen, b"\x1b[B"),
+            [Status::Refused]
+        );
+
+        // Recalled lines can be edited and entered like any other.
+        type_keys(&mut editor, &mut screen, b"\x15\x10\x10!\r");
+        assert_eq!(editor.line(), "first!");
+        assert_eq!(editor.history().get(0), Some(&b"first!"[..]));
+        assert_eq!(editor.history().len(), 3);
+    }
+
+    #[test]
+    fn completes_at_the_cursor() {
+        let mut editor = Editor::<32, 4>::new();
+        let mut screen = Screen::default();
+        type_keys(&mut editor, &mut screen, b"he\t");
+        assert_shows(&editor, &screen, "help", 4);
+        assert_eq!(
+            type_keys(&mut editor, &mut screen, b"\t"),
+            [Status::Refused]
+        );
+    }
+}
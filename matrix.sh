@@ -0,0 +1,86 @@
+#!/bin/sh
+# Boots the test image across a grid of QEMU configurations.
+#
+# Usage: matrix.sh <iso> <disk> <log dir> [qemu args...]
+#
+# Every combination of an image kind in IMAGES (`iso` or `disk`), a CPU count
+# in CPUS and a memory size in MEMORY is a cell. Each cell boots its image
+# once and saves the serial output to <log dir>/<image>-<cpus>cpu-<memory>.log.
+# A cell passes if the tests exit QEMU with the success code, and fails if
+# they exit with anything else or run for longer than TIMEOUT seconds. The
+# results and timings are printed as a table, which is also saved to
+# <log dir>/summary.txt, and the script exits with 1 if any cell failed.
+set -u
+
+iso=$1
+disk=$2
+logs=$3
+shift 3
+
+IMAGES=${IMAGES:-iso disk}
+CPUS=${CPUS:-1 4}
+MEMORY=${MEMORY:-128M 1G}
+TIMEOUT=${TIMEOUT:-300}
+
+# The code QEMU exits with when the tests pass (see `QemuExitCode`).
+SUCCESS=33
+# The code `timeout` exits with when it kills QEMU.
+TIMED_OUT=124
+
+millis() {
+    date +%s%3N
+}
+
+row() {
+    printf '%-5s %5s %7s %-9s %8s\n' "$@"
+}
+
+mkdir -p "$logs"
+summary="$logs/summary.txt"
+row IMAGE CPUS MEMORY RESULT SECONDS > "$summary"
+failed=0
+
+for image in $IMAGES; do
+    case $image in
+        iso) drive="-cdrom $iso" ;;
+        disk) drive="-drive file=$disk,format=raw" ;;
+        *)
+            echo "Unknown image kind: $image" >&2
+            exit 2
+            ;;
+    esac
+    for cpus in $CPUS; do
+        for memory in $MEMORY; do
+            log="$logs/$image-${cpus}cpu-$memory.log"
+            echo "Booting the $image image with $cpus CPUs and $memory of memory"
+            start=$(millis)
+            # $drive is split into QEMU's arguments on purpose.
+            # shellcheck disable=SC2086
+            timeout "$TIMEOUT" qemu-system-x86_64 $drive \
+                -bios /usr/share/ovmf/OVMF.fd \
+                -smp "$cpus" \
+                -m "$memory" \
+                -serial "file:$log" \
+                -device isa-debug-exit,iobase=0xf4,iosize=0x04 \
+                -display none \
+                "$@" < /dev/null
+            status=$?
+            elapsed=$(($(millis) - start))
+            case $status in
+                "$SUCCESS") result=pass ;;
+                "$TIMED_OUT") result=timeout ;;
+                *) result="fail:$status" ;;
+            esac
+            [ "$result" = pass ] || failed=1
+            seconds=$(printf '%d.%03d' $((elapsed / 1000)) $((elapsed % 1000)))
+            row "$image" "$cpus" "$memory" "$result" "$seconds" >> "$summary"
+        done
+    done
+done
+
+echo
+cat "$summary"
+if [ "$failed" -ne 0 ]; then
+    echo "Some configurations failed, see the logs in $logs"
+    exit 1
+fi
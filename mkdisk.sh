@@ -0,0 +1,64 @@
+#!/bin/sh
+# Builds a GPT disk image that boots Harmony through Limine from BIOS or UEFI.
+#
+# Usage: mkdisk.sh <image> <kernel> <limine.cfg> <limine dir> [root dir]
+#
+# The first partition is a FAT32 EFI system partition holding Limine, its
+# config and the kernel. If a root directory is given, a second partition is
+# formatted as ext2 and populated from it. Nothing is mounted, so this doesn't
+# need root or loop devices. Partitions are aligned to 1 MiB and the sizes can
+# be changed with ESP_MIB and ROOT_MIB.
+set -e
+
+image=$1
+kernel=$2
+config=$3
+limine=$4
+root=$5
+
+ESP_MIB=${ESP_MIB:-64}
+ROOT_MIB=${ROOT_MIB:-64}
+
+# GPT partition types.
+ESP_TYPE=C12A7328-F81F-11D2-BA4B-00A0C93EC93B
+LINUX_TYPE=0FC63DAF-8483-4772-8E79-3D69D8477DE4
+
+sectors() {
+    echo $(($1 * 2048))
+}
+
+esp_start=1
+root_start=$((esp_start + ESP_MIB))
+end=$root_start
+if [ -n "$root" ]; then
+    end=$((root_start + ROOT_MIB))
+fi
+
+rm -f "$image"
+# The last MiB keeps the backup GPT.
+truncate -s "$((end + 1))M" "$image"
+{
+    echo "label: gpt"
+    echo "start=$(sectors $esp_start), size=$(sectors "$ESP_MIB"), type=$ESP_TYPE, name=ESP"
+    if [ -n "$root" ]; then
+        echo "start=$(sectors $root_start), size=$(sectors "$ROOT_MIB"), type=$LINUX_TYPE, name=root"
+    fi
+} | sfdisk --quiet "$image"
+
+# FAT32 needs at least 65525 clusters, so use a sector per cluster.
+esp="$image@@$((esp_start * 1048576))"
+mformat -i "$esp" -F -c 1 -T "$(sectors "$ESP_MIB")" -v HARMONY ::
+mmd -i "$esp" ::/EFI ::/EFI/BOOT ::/boot ::/boot/limine
+mcopy -i "$esp" "$limine/BOOTX64.EFI" "$limine/BOOTIA32.EFI" ::/EFI/BOOT/
+mcopy -i "$esp" "$limine/limine-bios.sys" ::/boot/limine/
+mcopy -i "$esp" "$config" ::/boot/limine/limine.cfg
+mcopy -i "$esp" "$kernel" ::/boot/kernel
+
+# The ext2 driver only supports 1 KiB blocks and a few features.
+if [ -n "$root" ]; then
+    mke2fs -q -F -t ext2 -b 1024 -m 0 -O none,filetype,sparse_super \
+        -L root -d "$root" -E offset=$((root_start * 1048576)) \
+        "$image" $((ROOT_MIB * 1024))
+fi
+
+"$limine/limine" bios-install "$image"